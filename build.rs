@@ -0,0 +1,35 @@
+//! Records what `si::version` reports about the build: the target triple and,
+//! in a git checkout with git installed, `git describe` of the sources
+
+use std::process::Command;
+
+fn main() {
+    println!(
+        "cargo:rustc-env=SI_TARGET={}",
+        std::env::var("TARGET").unwrap_or_default()
+    );
+
+    let describe = Command::new("git")
+        .args(["describe", "--always", "--dirty", "--tags"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok());
+    if let Some(describe) = describe {
+        println!("cargo:rustc-env=SI_GIT_DESCRIBE={}", describe.trim());
+    }
+    // A checkout changes HEAD, a commit the branch HEAD points to, and staging
+    // or editing files the index
+    let mut watched = vec![".git/HEAD".to_string(), ".git/index".to_string()];
+    if let Ok(head) = std::fs::read_to_string(".git/HEAD") {
+        if let Some(branch) = head.trim().strip_prefix("ref: ") {
+            watched.push(format!(".git/{branch}"));
+        }
+    }
+    for path in watched {
+        if std::path::Path::new(&path).exists() {
+            println!("cargo:rerun-if-changed={path}");
+        }
+    }
+    println!("cargo:rerun-if-changed=build.rs");
+}
@@ -0,0 +1,155 @@
+//! Reading the input to pick settings the user left to si
+//!
+//! [`suggest_strength`] backs `si image generate --strength auto`. A hue shift
+//! moves a pixel's color by an amount proportional to its chroma, so the same
+//! strength that turns a vivid red shirt convincingly blue barely tints a dark
+//! navy one. The suggestion estimates how far the garment would visibly move at
+//! full strength and picks the strength that moves it by [`TARGET_CHANGE`].
+
+use image::{GrayImage, Rgb, RgbImage};
+use palette::{FromColor, Hsl, Srgb};
+
+use crate::planner::TransformPlan;
+
+/// Lowest strength [`suggest_strength`] proposes
+pub const MIN_SUGGESTED_STRENGTH: f32 = 0.2;
+/// Highest strength [`suggest_strength`] proposes; above it the garment's own
+/// shading starts to wash out
+pub const MAX_SUGGESTED_STRENGTH: f32 = 0.85;
+/// Visible change, in HSL units, the suggested strength aims for
+pub const TARGET_CHANGE: f32 = 0.15;
+
+/// A strength for applying `target` to the garment `mask` covers in `image`,
+/// between [`MIN_SUGGESTED_STRENGTH`] and [`MAX_SUGGESTED_STRENGTH`]
+///
+/// The masked pixels are averaged, weighted by the mask, into their chroma,
+/// saturation and lightness. The change `target` makes at full strength is
+/// then estimated as the sum of:
+///
+/// - the hue shift, as a fraction of the largest possible (180°), times the
+///   chroma, since a hue shift moves gray pixels nowhere;
+/// - how far the saturation multiplier is from 1, times the saturation;
+/// - how far the lightness multiplier is from 1, times the lightness.
+///
+/// The strength is [`TARGET_CHANGE`] divided by that estimate, clamped. Dark and
+/// muted garments get high strengths, vivid ones low strengths. With an empty
+/// mask the whole image is measured. The result only depends on the pixels, so
+/// the same input always gets the same suggestion.
+pub fn suggest_strength(image: &RgbImage, mask: &GrayImage, target: &TransformPlan) -> f32 {
+    let mut masked = Averages::default();
+    for (x, y, pixel) in image.enumerate_pixels() {
+        let weight = mask
+            .get_pixel_checked(x, y)
+            .map_or(0.0, |m| f32::from(m.0[0]) / 255.0);
+        if weight > 0.0 {
+            masked.add(pixel, weight);
+        }
+    }
+    let averages = if masked.weight > 0.0 {
+        masked
+    } else {
+        let mut whole = Averages::default();
+        for pixel in image.pixels() {
+            whole.add(pixel, 1.0);
+        }
+        whole
+    };
+    let Some((chroma, saturation, lightness)) = averages.means() else {
+        return MAX_SUGGESTED_STRENGTH;
+    };
+
+    let color = &target.color;
+    let hue_shift = color.hue_shift.rem_euclid(360.0);
+    let hue_distance = hue_shift.min(360.0 - hue_shift) / 180.0;
+    let change = hue_distance * chroma
+        + (color.saturation_mult - 1.0).abs() * saturation
+        + (color.lightness_mult - 1.0).abs() * lightness;
+    if change <= f32::EPSILON {
+        return MAX_SUGGESTED_STRENGTH;
+    }
+    (TARGET_CHANGE / change).clamp(MIN_SUGGESTED_STRENGTH, MAX_SUGGESTED_STRENGTH)
+}
+
+/// Weighted sums of HSL chroma, saturation and lightness
+#[derive(Debug, Default)]
+struct Averages {
+    weight: f32,
+    chroma: f32,
+    saturation: f32,
+    lightness: f32,
+}
+
+impl Averages {
+    fn add(&mut self, pixel: &Rgb<u8>, weight: f32) {
+        let [r, g, b] = pixel.0.map(|c| f32::from(c) / 255.0);
+        let hsl = Hsl::from_color(Srgb::new(r, g, b));
+        self.weight += weight;
+        self.chroma += weight * hsl.saturation * (1.0 - (2.0 * hsl.lightness - 1.0).abs());
+        self.saturation += weight * hsl.saturation;
+        self.lightness += weight * hsl.lightness;
+    }
+
+    /// Mean chroma, saturation and lightness, if anything was added
+    fn means(&self) -> Option<(f32, f32, f32)> {
+        (self.weight > 0.0).then(|| {
+            (
+                self.chroma / self.weight,
+                self.saturation / self.weight,
+                self.lightness / self.weight,
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::planner::{KeywordPlanner, TransformPlanner};
+    use image::Luma;
+
+    /// A `color` garment on a white background, the mask covering the garment
+    fn garment(color: [u8; 3]) -> (RgbImage, GrayImage) {
+        let inside = |x: u32, y: u32| (16..48).contains(&x) && (8..56).contains(&y);
+        let image = RgbImage::from_fn(64, 64, |x, y| {
+            if inside(x, y) {
+                Rgb(color)
+            } else {
+                Rgb([255, 255, 255])
+            }
+        });
+        let mask = GrayImage::from_fn(64, 64, |x, y| Luma([if inside(x, y) { 255 } else { 0 }]));
+        (image, mask)
+    }
+
+    #[test]
+    fn test_darker_and_duller_garments_get_more_strength() -> anyhow::Result<()> {
+        let blue = KeywordPlanner::default().plan("blue shirt")?;
+        let suggest = |color| {
+            let (image, mask) = garment(color);
+            suggest_strength(&image, &mask, &blue)
+        };
+        let dark = suggest([40, 30, 25]);
+        let mid = suggest([150, 110, 90]);
+        let vivid = suggest([230, 30, 30]);
+        assert!(dark > mid && mid > vivid, "{dark} {mid} {vivid}");
+        for strength in [dark, mid, vivid] {
+            assert!((MIN_SUGGESTED_STRENGTH..=MAX_SUGGESTED_STRENGTH).contains(&strength));
+        }
+        assert_eq!(suggest([150, 110, 90]), mid);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mask_decides_what_is_measured() {
+        let blue = KeywordPlanner::default().plan("blue shirt").unwrap();
+        let (image, mask) = garment([230, 30, 30]);
+        let masked = suggest_strength(&image, &mask, &blue);
+        // Unmasked, the white background dilutes the red
+        let empty = GrayImage::new(64, 64);
+        assert!(suggest_strength(&image, &empty, &blue) > masked);
+        assert_eq!(
+            suggest_strength(&image, &mask, &TransformPlan::default()),
+            MAX_SUGGESTED_STRENGTH
+        );
+    }
+}
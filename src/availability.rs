@@ -0,0 +1,196 @@
+//! Telling missing model files apart from files on storage that isn't mounted
+//!
+//! A file that doesn't exist is *missing* when the storage it lives on is
+//! clearly there: its directory still exists, or it sits under the models
+//! directory or the HF cache while that root exists. Otherwise the whole tree
+//! above it is gone, as when an external drive is unplugged, and the file is
+//! *unavailable* rather than lost.
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use log::debug;
+use serde::Serialize;
+
+use crate::{
+    index_paths,
+    models::{ModelInfo, ModelManager},
+};
+
+/// Whether a model's files can be read right now
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Availability {
+    /// Every file is present
+    Available,
+    /// Some file is gone from storage that is present
+    Missing,
+    /// Some file is on storage that isn't there, e.g. an unmounted drive
+    Unavailable,
+}
+
+impl fmt::Display for Availability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Self::Available => "available",
+            Self::Missing => "missing",
+            Self::Unavailable => "unavailable",
+        })
+    }
+}
+
+/// The deepest ancestor of `path`, or `path` itself, that exists
+pub fn longest_existing_ancestor(path: &Path) -> Option<&Path> {
+    path.ancestors().find(|p| p.exists())
+}
+
+impl ModelManager {
+    /// Indexed model ids grouped by [`Availability`], each group sorted
+    pub fn classify_availability(&self) -> Result<BTreeMap<Availability, Vec<String>>> {
+        let mut groups: BTreeMap<Availability, Vec<String>> = BTreeMap::new();
+        for model in self.list_models()? {
+            groups
+                .entry(self.model_availability(&model))
+                .or_default()
+                .push(model.model_id);
+        }
+        for ids in groups.values_mut() {
+            ids.sort();
+        }
+        Ok(groups)
+    }
+
+    /// [`Availability::Unavailable`] if any absent file is on absent storage,
+    /// otherwise [`Availability::Missing`] if any file is absent
+    pub fn model_availability(&self, model: &ModelInfo) -> Availability {
+        let roots = self.storage_roots();
+        model
+            .files
+            .iter()
+            .map(|file| file_availability(&file.path, &roots))
+            .max()
+            .unwrap_or(Availability::Available)
+    }
+
+    /// Roots whose existence vouches for the storage of everything below them
+    fn storage_roots(&self) -> Vec<PathBuf> {
+        [self.models_dir().to_path_buf(), self.hf_cache_dir()]
+            .into_iter()
+            .filter(|root| root.is_dir())
+            .collect()
+    }
+}
+
+fn file_availability(path: &Path, roots: &[PathBuf]) -> Availability {
+    if path.exists() {
+        return Availability::Available;
+    }
+    let parent_exists = path.parent().is_some_and(Path::is_dir);
+    if parent_exists || roots.iter().any(|root| index_paths::is_within(path, root)) {
+        return Availability::Missing;
+    }
+    if let Some(ancestor) = longest_existing_ancestor(path) {
+        debug!(
+            "{} is unavailable; nearest existing ancestor is {}",
+            path.display(),
+            ancestor.display()
+        );
+    }
+    Availability::Unavailable
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MockSource, ModelManagerBuilder};
+    use std::fs;
+    use tempfile::tempdir;
+
+    fn index_entry(model_id: &str, paths: &[&Path]) -> serde_json::Value {
+        let files: Vec<_> = paths
+            .iter()
+            .map(|p| serde_json::json!({"size": 1, "path": p}))
+            .collect();
+        serde_json::json!({"model_id": model_id, "files": files})
+    }
+
+    #[test]
+    fn test_classify_availability() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let root = temp_dir.path();
+        let drive = tempdir()?;
+        let on_drive = drive.path().join("models/big.safetensors");
+        fs::create_dir_all(on_drive.parent().unwrap())?;
+        fs::write(&on_drive, "w")?;
+        // Pull the drive
+        let drive_path = drive.path().to_path_buf();
+        drop(drive);
+        assert!(!drive_path.exists());
+        assert_eq!(longest_existing_ancestor(&on_drive), drive_path.parent());
+
+        let present = root.join("present.bin");
+        fs::write(&present, "w")?;
+        let deleted = root.join("deleted.bin");
+        let cache_file = root.join("cache/models--org--gone/blobs/abc");
+        fs::create_dir_all(root.join("cache"))?;
+
+        let models_dir = root.join("models");
+        fs::create_dir_all(&models_dir)?;
+        let index = serde_json::json!({"models": [
+            index_entry("org/present", &[&present]),
+            index_entry("org/deleted", &[&present, &deleted]),
+            index_entry("org/gone", &[&cache_file]),
+            index_entry("local/big", &[&on_drive]),
+            index_entry("org/empty", &[]),
+        ]});
+        fs::write(models_dir.join("model_index.json"), index.to_string())?;
+        let manager = ModelManagerBuilder::new()
+            .with_models_dir(models_dir)
+            .with_source(Box::new(MockSource::new(
+                root.join("fixtures"),
+                root.join("cache"),
+            )))
+            .build()?;
+
+        let groups = manager.classify_availability()?;
+        assert_eq!(
+            groups[&Availability::Available],
+            ["org/empty", "org/present"]
+        );
+        assert_eq!(groups[&Availability::Missing], ["org/deleted", "org/gone"]);
+        assert_eq!(groups[&Availability::Unavailable], ["local/big"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sync_spares_models_on_unavailable_volume() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let root = temp_dir.path();
+        // HF cache on a drive that has since been unplugged
+        let drive = tempdir()?;
+        let cache = drive.path().join("hf");
+        let blob = cache.join("models--org--model/blobs/abc");
+        drop(drive);
+
+        let models_dir = root.join("models");
+        fs::create_dir_all(&models_dir)?;
+        let index = serde_json::json!({"models": [index_entry("org/model", &[&blob])]});
+        fs::write(models_dir.join("model_index.json"), index.to_string())?;
+        let manager = ModelManagerBuilder::new()
+            .with_models_dir(models_dir)
+            .with_source(Box::new(MockSource::new(root.join("fixtures"), &cache)))
+            .build()?;
+
+        let result = manager.sync_models(true).await?;
+        assert_eq!(result.discrepancies_count(), 0);
+        assert_eq!(
+            result.to_string(),
+            "On unavailable volume (1):\n  org/model"
+        );
+        Ok(())
+    }
+}
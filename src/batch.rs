@@ -0,0 +1,205 @@
+//! Selecting the input images of a batch run
+//!
+//! [`InputSelector`] enumerates a directory, honoring gitignore-style patterns
+//! from an optional [`IGNORE_FILENAME`] in that directory plus any extra
+//! excludes, and keeps only files whose extension names an image format.
+
+use std::{
+    ffi::OsStr,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use image::ImageFormat;
+use log::debug;
+
+/// Ignore file read from the root of a batch input directory
+pub const IGNORE_FILENAME: &str = ".siignore";
+
+/// Chooses which files under a directory a batch processes
+///
+/// Excludes given to [`new`](Self::new) are applied after the ignore file, so an
+/// exclude wins over a `!pattern` re-include in the file.
+#[derive(Debug)]
+pub struct InputSelector {
+    root: PathBuf,
+    ignore: Gitignore,
+    recursive: bool,
+}
+
+/// The files an [`InputSelector`] picked
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Selection {
+    /// Images to process, sorted by path
+    pub images: Vec<PathBuf>,
+    /// Files left out because they matched an ignore pattern or aren't images;
+    /// an excluded directory counts once
+    pub skipped: usize,
+}
+
+impl InputSelector {
+    /// A selector for `root`, reading `root/.siignore` if it exists and then
+    /// adding `excludes`
+    pub fn new(root: &Path, excludes: &[String]) -> Result<Self> {
+        let mut builder = GitignoreBuilder::new(root);
+        let ignore_file = root.join(IGNORE_FILENAME);
+        if ignore_file.is_file() {
+            if let Some(e) = builder.add(&ignore_file) {
+                return Err(e).with_context(|| format!("Invalid {}", ignore_file.display()));
+            }
+        }
+        for pattern in excludes {
+            builder
+                .add_line(None, pattern)
+                .with_context(|| format!("Invalid exclude pattern `{pattern}`"))?;
+        }
+        let ignore = builder
+            .build()
+            .with_context(|| format!("Invalid ignore patterns for {}", root.display()))?;
+
+        Ok(Self {
+            root: root.to_path_buf(),
+            ignore,
+            recursive: false,
+        })
+    }
+
+    /// Also descend into subdirectories
+    pub fn with_recursive(mut self, recursive: bool) -> Self {
+        self.recursive = recursive;
+        self
+    }
+
+    /// Whether `path`, a file or directory under the root, matches the patterns
+    pub fn is_excluded(&self, path: &Path, is_dir: bool) -> bool {
+        self.ignore.matched(path, is_dir).is_ignore()
+    }
+
+    /// Walk the root directory and pick the images to process
+    pub fn select(&self) -> Result<Selection> {
+        let mut selection = Selection::default();
+        self.walk(&self.root, &mut selection)?;
+        selection.images.sort();
+        Ok(selection)
+    }
+
+    fn walk(&self, dir: &Path, selection: &mut Selection) -> Result<()> {
+        let entries = fs::read_dir(dir)
+            .with_context(|| format!("Failed to read directory {}", dir.display()))?;
+        for entry in entries {
+            let path = entry
+                .with_context(|| format!("Failed to read directory {}", dir.display()))?
+                .path();
+            let is_dir = path.is_dir();
+            if is_dir && !self.recursive {
+                continue;
+            }
+            if dir == self.root && path.file_name() == Some(OsStr::new(IGNORE_FILENAME)) {
+                continue;
+            }
+            if self.is_excluded(&path, is_dir) {
+                debug!("Excluded {}", path.display());
+                selection.skipped += 1;
+            } else if is_dir {
+                self.walk(&path, selection)?;
+            } else if ImageFormat::from_path(&path).is_ok() {
+                selection.images.push(path);
+            } else {
+                debug!("Not an image: {}", path.display());
+                selection.skipped += 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn touch(root: &Path, files: &[&str]) -> Result<()> {
+        for file in files {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap())?;
+            fs::write(path, "")?;
+        }
+        Ok(())
+    }
+
+    fn names(root: &Path, selection: &Selection) -> Vec<String> {
+        selection
+            .images
+            .iter()
+            .map(|p| {
+                p.strip_prefix(root)
+                    .unwrap()
+                    .to_string_lossy()
+                    .replace('\\', "/")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_skips_non_images_and_subdirectories() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let root = temp_dir.path();
+        touch(root, &["a.jpg", "b.PNG", "notes.txt", "sub/c.png"])?;
+
+        let selection = InputSelector::new(root, &[])?.select()?;
+        assert_eq!(names(root, &selection), ["a.jpg", "b.PNG"]);
+        assert_eq!(selection.skipped, 1);
+
+        let selection = InputSelector::new(root, &[])?
+            .with_recursive(true)
+            .select()?;
+        assert_eq!(names(root, &selection), ["a.jpg", "b.PNG", "sub/c.png"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_ignore_file_patterns() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let root = temp_dir.path();
+        touch(
+            root,
+            &["a.jpg", "draft_a.jpg", "raw/b.png", "keep/draft_c.png"],
+        )?;
+        fs::write(
+            root.join(IGNORE_FILENAME),
+            "# comment\ndraft_*\n!keep/draft_*\nraw/\n",
+        )?;
+
+        let selection = InputSelector::new(root, &[])?
+            .with_recursive(true)
+            .select()?;
+        assert_eq!(names(root, &selection), ["a.jpg", "keep/draft_c.png"]);
+        // draft_a.jpg and the raw directory
+        assert_eq!(selection.skipped, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_cli_excludes_take_precedence_over_ignore_file() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let root = temp_dir.path();
+        touch(root, &["a.jpg", "b.jpg", "keep.jpg"])?;
+        fs::write(root.join(IGNORE_FILENAME), "*.jpg\n!keep.jpg\n!b.jpg\n")?;
+
+        let selection = InputSelector::new(root, &[])?.select()?;
+        assert_eq!(names(root, &selection), ["b.jpg", "keep.jpg"]);
+
+        // An exclude overrides the file's re-include...
+        let selection = InputSelector::new(root, &["keep.jpg".to_string()])?.select()?;
+        assert_eq!(names(root, &selection), ["b.jpg"]);
+        // ...and excludes combine with the file's patterns
+        let selection = InputSelector::new(root, &["b.*".to_string()])?.select()?;
+        assert_eq!(names(root, &selection), ["keep.jpg"]);
+        assert_eq!(selection.skipped, 2);
+
+        assert!(InputSelector::new(root, &["[z-a]".to_string()]).is_err());
+        Ok(())
+    }
+}
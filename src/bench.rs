@@ -0,0 +1,365 @@
+//! Timing the image pipeline on a synthetic input
+//!
+//! [`run`] renders the same generated photo through a [`BenchPipeline`] a number
+//! of times and reports the spread of wall times, the throughput and, where the
+//! platform reports it, the peak resident memory. The pipeline is a trait so the
+//! harness can be tested with a trivial one; [`TryOnPipeline`] is the one
+//! `si image bench` measures.
+
+use std::{
+    fmt,
+    path::PathBuf,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Result, bail};
+use image::{DynamicImage, Rgb, RgbImage};
+use serde::{Serialize, Serializer};
+
+use crate::{
+    format::format_size,
+    tryon::{TryOnRequest, VirtualTryOn},
+};
+
+/// Width and height of the input unless [`BenchConfig::with_size`] says otherwise
+pub const DEFAULT_SIZE: u32 = 512;
+/// Timed runs unless [`BenchConfig::with_iterations`] says otherwise
+pub const DEFAULT_ITERATIONS: u32 = 5;
+/// What [`TryOnPipeline`] asks for; a color and a fabric so every stage runs
+const BENCH_PROMPT: &str = "red denim shirt";
+
+/// The implementation being measured
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// The built-in segmentation and color transformation
+    #[default]
+    Heuristic,
+    /// Diffusion through candle; not part of this build yet
+    Candle,
+}
+
+impl FromStr for Backend {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "heuristic" => Ok(Self::Heuristic),
+            "candle" => Ok(Self::Candle),
+            other => bail!("Unknown backend `{other}`, expected heuristic or candle"),
+        }
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Heuristic => "heuristic",
+            Self::Candle => "candle",
+        })
+    }
+}
+
+/// Something [`run`] can time
+pub trait BenchPipeline: Send + Sync {
+    /// Process `input` the way a generation would, returning the result
+    fn render(&self, input: &DynamicImage) -> Result<DynamicImage>;
+
+    /// Where the work runs, e.g. CPU or a GPU
+    fn device(&self) -> String;
+}
+
+/// The try-on pipeline minus reading the input and writing the result
+pub struct TryOnPipeline {
+    tryon: VirtualTryOn,
+    request: TryOnRequest,
+}
+
+impl TryOnPipeline {
+    pub fn new(tryon: VirtualTryOn) -> Self {
+        Self {
+            tryon,
+            request: TryOnRequest {
+                input_image_path: PathBuf::from("bench.png"),
+                clothing_description: BENCH_PROMPT.to_string(),
+                negative_prompt: None,
+                output_path: PathBuf::from("bench-out.png"),
+                model_name: None,
+                strength: None,
+                color_strength: None,
+                style_strength: None,
+                reference_image: None,
+                crop: None,
+                mask: None,
+                transform_plan: None,
+                comparison: None,
+                remote_input: None,
+                seed: None,
+            },
+        }
+    }
+}
+
+impl BenchPipeline for TryOnPipeline {
+    fn render(&self, input: &DynamicImage) -> Result<DynamicImage> {
+        self.tryon.render_image(input.clone(), &self.request)
+    }
+
+    fn device(&self) -> String {
+        self.tryon.device_info()
+    }
+}
+
+/// What [`run`] measures and how often
+pub struct BenchConfig {
+    pub backend: Backend,
+    /// Width and height of the square input
+    pub size: u32,
+    pub iterations: u32,
+    pub pipeline: Arc<dyn BenchPipeline>,
+}
+
+impl BenchConfig {
+    pub fn new(pipeline: Arc<dyn BenchPipeline>) -> Self {
+        Self {
+            backend: Backend::default(),
+            size: DEFAULT_SIZE,
+            iterations: DEFAULT_ITERATIONS,
+            pipeline,
+        }
+    }
+
+    /// Record `backend` as the one `pipeline` implements
+    pub fn with_backend(mut self, backend: Backend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    pub fn with_size(mut self, size: u32) -> Self {
+        self.size = size;
+        self
+    }
+
+    pub fn with_iterations(mut self, iterations: u32) -> Self {
+        self.iterations = iterations;
+        self
+    }
+}
+
+/// The outcome of a [`run`]
+#[derive(Debug, Clone, Serialize)]
+pub struct BenchReport {
+    /// The si version measured, to compare releases
+    pub version: String,
+    pub backend: Backend,
+    pub device: String,
+    pub size: u32,
+    pub iterations: u32,
+    #[serde(rename = "min_ms", serialize_with = "fractional_millis")]
+    pub min: Duration,
+    #[serde(rename = "median_ms", serialize_with = "fractional_millis")]
+    pub median: Duration,
+    #[serde(rename = "max_ms", serialize_with = "fractional_millis")]
+    pub max: Duration,
+    /// Input pixels processed per second at the median time
+    pub pixels_per_sec: f64,
+    /// The process's resident memory high-water mark, where the platform
+    /// reports one
+    ///
+    /// It covers the whole process, so it's best read against
+    /// [`frame_bytes`](Self::frame_bytes): the pipeline holds a few frames at
+    /// once (the input, its mask and the result), while writing a PNG or a grid
+    /// adds only a band of rows on top, as both are streamed to the file.
+    pub peak_rss_bytes: Option<u64>,
+    /// Bytes one RGB frame of the input's size takes in memory
+    pub frame_bytes: u64,
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "si {} {} backend on {}: {}x{}, {} iterations",
+            self.version, self.backend, self.device, self.size, self.size, self.iterations
+        )?;
+        writeln!(
+            f,
+            "  min {:.2?}, median {:.2?}, max {:.2?}",
+            self.min, self.median, self.max
+        )?;
+        write!(f, "  {:.2} Mpixels/s", self.pixels_per_sec / 1e6)?;
+        if let Some(rss) = self.peak_rss_bytes {
+            write!(
+                f,
+                "\n  peak RSS {} ({:.1} frames of {})",
+                format_size(rss),
+                rss as f64 / self.frame_bytes.max(1) as f64,
+                format_size(self.frame_bytes)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn fractional_millis<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64() * 1000.0)
+}
+
+/// Render [`synthetic_input`] once to warm up, then `iterations` more times
+/// while timing each
+pub fn run(config: BenchConfig) -> Result<BenchReport> {
+    if config.size == 0 {
+        bail!("The benchmark input needs a size above 0");
+    }
+    if config.iterations == 0 {
+        bail!("The benchmark needs at least one iteration");
+    }
+    let input = DynamicImage::ImageRgb8(synthetic_input(config.size));
+    let output = config.pipeline.render(&input)?;
+    if (output.width(), output.height()) != (input.width(), input.height()) {
+        bail!(
+            "The pipeline turned a {}x{} input into {}x{}",
+            input.width(),
+            input.height(),
+            output.width(),
+            output.height()
+        );
+    }
+
+    let mut times = Vec::with_capacity(config.iterations as usize);
+    for _ in 0..config.iterations {
+        let start = Instant::now();
+        config.pipeline.render(&input)?;
+        times.push(start.elapsed());
+    }
+    times.sort();
+    let middle = times.len() / 2;
+    let median = if times.len() % 2 == 0 {
+        (times[middle - 1] + times[middle]) / 2
+    } else {
+        times[middle]
+    };
+    let pixels = u64::from(config.size) * u64::from(config.size);
+    Ok(BenchReport {
+        version: crate::version::VERSION.to_string(),
+        backend: config.backend,
+        device: config.pipeline.device(),
+        size: config.size,
+        iterations: config.iterations,
+        min: times[0],
+        median,
+        max: times[times.len() - 1],
+        pixels_per_sec: pixels as f64 / median.as_secs_f64().max(1e-9),
+        peak_rss_bytes: peak_rss(),
+        frame_bytes: pixels * 3,
+    })
+}
+
+/// A `size`x`size` stand-in for a photo: a light backdrop, a head, and a shaded
+/// shirt across the middle where the heuristic segmenter finds clothing
+pub fn synthetic_input(size: u32) -> RgbImage {
+    RgbImage::from_fn(size, size, |x, y| {
+        let (fx, fy) = (
+            (x as f32 + 0.5) / size as f32,
+            (y as f32 + 0.5) / size as f32,
+        );
+        let (dx, dy) = ((fx - 0.5) / 0.12, (fy - 0.2) / 0.12);
+        if dx * dx + dy * dy <= 1.0 {
+            Rgb([224, 180, 150])
+        } else if (0.35..0.95).contains(&fy) && (0.3..0.7).contains(&fx) {
+            let shade = 1.0 - (fy - 0.35) * 0.5;
+            Rgb([
+                (90.0 * shade) as u8,
+                (110.0 * shade) as u8,
+                (140.0 * shade) as u8,
+            ])
+        } else {
+            Rgb([245, 245, 240])
+        }
+    })
+}
+
+/// The resident memory high-water mark from `/proc/self/status`
+#[cfg(target_os = "linux")]
+fn peak_rss() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    let kib: u64 = line
+        .trim_start_matches("VmHWM:")
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()?;
+    Some(kib * 1024)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn peak_rss() -> Option<u64> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    /// Returns its input after sleeping a little longer on every call
+    struct Slowing {
+        calls: AtomicU32,
+    }
+
+    impl BenchPipeline for Slowing {
+        fn render(&self, input: &DynamicImage) -> Result<DynamicImage> {
+            let call = self.calls.fetch_add(1, Ordering::SeqCst);
+            std::thread::sleep(Duration::from_millis(u64::from(call)));
+            Ok(input.clone())
+        }
+
+        fn device(&self) -> String {
+            "test".to_string()
+        }
+    }
+
+    #[test]
+    fn test_run_reports_the_spread() -> Result<()> {
+        let pipeline = Arc::new(Slowing {
+            calls: AtomicU32::new(0),
+        });
+        let report = run(BenchConfig::new(pipeline.clone())
+            .with_size(16)
+            .with_iterations(3))?;
+
+        // One warm-up run on top of the timed ones
+        assert_eq!(pipeline.calls.load(Ordering::SeqCst), 4);
+        assert_eq!((report.size, report.iterations), (16, 3));
+        assert_eq!(report.device, "test");
+        assert!(report.min <= report.median && report.median <= report.max);
+        assert!(report.min >= Duration::from_millis(1));
+        assert!(report.pixels_per_sec > 0.0);
+        assert_eq!(report.frame_bytes, 16 * 16 * 3);
+
+        let json = serde_json::to_value(&report)?;
+        assert_eq!(json["backend"], "heuristic");
+        assert!(json["median_ms"].as_f64().unwrap() >= 1.0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_rejects_a_pipeline_that_changes_the_size() {
+        struct Shrinking;
+        impl BenchPipeline for Shrinking {
+            fn render(&self, input: &DynamicImage) -> Result<DynamicImage> {
+                Ok(input.thumbnail(4, 4))
+            }
+            fn device(&self) -> String {
+                "test".to_string()
+            }
+        }
+        let config = BenchConfig::new(Arc::new(Shrinking)).with_size(16);
+        assert!(run(config).is_err());
+        assert!(run(BenchConfig::new(Arc::new(Shrinking)).with_iterations(0)).is_err());
+    }
+}
@@ -0,0 +1,170 @@
+//! Cooperative cancellation for long-running operations
+//!
+//! Downloads check their token between files and while a file is in flight;
+//! image processing checks it every few rows. A cancelled operation cleans up
+//! what it wrote and returns [`SiError::Cancelled`].
+//!
+//! A [`Watchdog`] trips a token once a time limit passes, which is how a
+//! try-on gets a timeout: its stages already stop at the next check.
+
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+        mpsc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use anyhow::Result;
+
+use crate::error::SiError;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// A cloneable flag shared between the caller and a running operation
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+    /// Tokens whose cancellation also cancels this one
+    parents: Vec<Arc<AtomicBool>>,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+            || self.parents.iter().any(|p| p.load(Ordering::SeqCst))
+    }
+
+    /// A token that is cancelled along with this one, but can also be cancelled
+    /// without affecting it
+    pub fn child(&self) -> Self {
+        let mut parents = self.parents.clone();
+        parents.push(self.cancelled.clone());
+        Self {
+            cancelled: Arc::default(),
+            parents,
+        }
+    }
+
+    /// Return [`SiError::Cancelled`] if the token has been tripped
+    pub fn check(&self) -> Result<()> {
+        if self.is_cancelled() {
+            return Err(SiError::Cancelled.into());
+        }
+        Ok(())
+    }
+
+    /// Resolve once the token is tripped
+    pub async fn cancelled(&self) {
+        while !self.is_cancelled() {
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+}
+
+/// Cancels a token once `limit` has passed, unless dropped first
+///
+/// The waiting happens on a thread of its own, so the limit holds while the
+/// guarded work blocks the caller's thread.
+#[derive(Debug)]
+pub struct Watchdog {
+    fired: Arc<AtomicBool>,
+    /// Dropped to stop the thread early
+    done: Option<mpsc::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Watchdog {
+    pub fn start(token: CancelToken, limit: Duration) -> Self {
+        let fired = Arc::new(AtomicBool::new(false));
+        let (done, stopped) = mpsc::channel::<()>();
+        let thread = {
+            let fired = fired.clone();
+            thread::spawn(move || {
+                if let Err(mpsc::RecvTimeoutError::Timeout) = stopped.recv_timeout(limit) {
+                    fired.store(true, Ordering::SeqCst);
+                    token.cancel();
+                }
+            })
+        };
+        Self {
+            fired,
+            done: Some(done),
+            thread: Some(thread),
+        }
+    }
+
+    /// Whether the limit passed and the token was cancelled
+    pub fn fired(&self) -> bool {
+        self.fired.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        self.done.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_cancel_is_shared_between_clones() {
+        let token = CancelToken::new();
+        let clone = token.clone();
+        assert!(clone.check().is_ok());
+
+        token.cancel();
+        assert!(clone.is_cancelled());
+        let err = clone.check().unwrap_err();
+        assert!(SiError::Cancelled.matches(&err));
+
+        // Resolves immediately once cancelled
+        clone.cancelled().await;
+    }
+
+    #[test]
+    fn test_child_follows_parent_but_not_back() {
+        let parent = CancelToken::new();
+        let child = parent.child();
+        let grandchild = child.child();
+        grandchild.cancel();
+        assert!(!child.is_cancelled() && !parent.is_cancelled());
+
+        let sibling = parent.child().child();
+        parent.cancel();
+        assert!(child.is_cancelled() && sibling.is_cancelled());
+    }
+
+    #[test]
+    fn test_watchdog_cancels_after_limit_only() {
+        let token = CancelToken::new();
+        let watchdog = Watchdog::start(token.clone(), Duration::from_millis(30));
+        assert!(!token.is_cancelled());
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(watchdog.fired() && token.is_cancelled());
+
+        // Dropping it in time stops it, without waiting out the limit
+        let token = CancelToken::new();
+        let started = std::time::Instant::now();
+        let watchdog = Watchdog::start(token.clone(), Duration::from_secs(60));
+        drop(watchdog);
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(!token.is_cancelled());
+    }
+}
@@ -0,0 +1,313 @@
+//! Color transfer from a reference garment photo
+//!
+//! Instead of deriving a hue shift from the prompt, [`ColorReference`] takes the
+//! dominant color of a reference image (k-means over a downsampled copy) and
+//! matches the mean and standard deviation of each L*a*b* channel of the masked
+//! region to it.
+
+use std::path::Path;
+
+use anyhow::{Context, Result, bail};
+use image::{DynamicImage, GrayImage, Rgb, RgbImage, imageops::FilterType};
+use log::debug;
+use palette::{FromColor, Lab, Srgb};
+
+use crate::cancel::CancelToken;
+
+/// References smaller than this in either dimension are rejected
+pub const MIN_REFERENCE_SIZE: u32 = 32;
+
+/// Longest side of the copy that k-means runs on
+const SAMPLE_SIZE: u32 = 64;
+const CLUSTERS: usize = 3;
+const KMEANS_ITERATIONS: usize = 10;
+
+/// Mask values below this (after scaling by strength) are left untouched, as in
+/// the description-driven transform
+const MIN_MASK_STRENGTH: f32 = 0.1;
+
+/// How many pixel rows are processed between cancellation checks
+const CANCEL_CHECK_ROWS: u32 = 64;
+
+/// Mean and standard deviation of the L*, a* and b* channels of a set of pixels
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LabStats {
+    pub mean: [f32; 3],
+    pub std: [f32; 3],
+}
+
+impl LabStats {
+    fn from_samples(samples: &[[f32; 3]]) -> Option<Self> {
+        if samples.is_empty() {
+            return None;
+        }
+        let n = samples.len() as f32;
+        let mean = [0, 1, 2].map(|c| samples.iter().map(|s| s[c]).sum::<f32>() / n);
+        let std = [0, 1, 2].map(|c| {
+            let variance = samples
+                .iter()
+                .map(|s| (s[c] - mean[c]).powi(2))
+                .sum::<f32>()
+                / n;
+            variance.sqrt()
+        });
+        Some(Self { mean, std })
+    }
+}
+
+/// The dominant garment color of a reference image
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ColorReference {
+    stats: LabStats,
+}
+
+impl ColorReference {
+    /// Load and analyze the reference image at `path`
+    pub fn load(path: &Path) -> Result<Self> {
+        let image = image::open(path)
+            .with_context(|| format!("Failed to load reference image {}", path.display()))?;
+        Self::from_image(&image)
+            .with_context(|| format!("Unusable reference image {}", path.display()))
+    }
+
+    /// Extract the dominant color of `image`
+    ///
+    /// Grayscale references are accepted; they carry no chroma, so the garment is
+    /// desaturated towards the reference's lightness.
+    pub fn from_image(image: &DynamicImage) -> Result<Self> {
+        let (width, height) = (image.width(), image.height());
+        if width < MIN_REFERENCE_SIZE || height < MIN_REFERENCE_SIZE {
+            bail!(
+                "Reference image is {width}x{height}, it must be at least \
+                 {MIN_REFERENCE_SIZE}x{MIN_REFERENCE_SIZE} pixels"
+            );
+        }
+
+        let sample = image
+            .resize(SAMPLE_SIZE, SAMPLE_SIZE, FilterType::Triangle)
+            .to_rgb8();
+        let pixels: Vec<[f32; 3]> = sample.pixels().map(to_lab).collect();
+        let cluster = dominant_cluster(&pixels);
+        let stats = LabStats::from_samples(&cluster).context("Reference image has no pixels")?;
+        debug!("Reference color stats: {stats:?}");
+        Ok(Self { stats })
+    }
+
+    pub fn stats(&self) -> LabStats {
+        self.stats
+    }
+
+    /// Recolor the pixels of `image` selected by `mask` so their L*a*b* statistics
+    /// match the reference, blended in by `strength`
+    pub fn transfer(
+        &self,
+        image: &RgbImage,
+        mask: &GrayImage,
+        strength: f32,
+        cancel: &CancelToken,
+    ) -> Result<RgbImage> {
+        let mut result = image.clone();
+        let masked: Vec<[f32; 3]> = image
+            .enumerate_pixels()
+            .filter(|(x, y, _)| mask.get_pixel(*x, *y).0[0] > 127)
+            .map(|(_, _, p)| to_lab(p))
+            .collect();
+        let Some(source) = LabStats::from_samples(&masked) else {
+            debug!("Empty clothing mask, skipping color transfer");
+            return Ok(result);
+        };
+
+        for (x, y, pixel) in image.enumerate_pixels() {
+            if x == 0 && y % CANCEL_CHECK_ROWS == 0 {
+                cancel.check()?;
+            }
+            let mask_strength = (mask.get_pixel(x, y).0[0] as f32 / 255.0) * strength;
+            if mask_strength <= MIN_MASK_STRENGTH {
+                continue;
+            }
+
+            let lab = to_lab(pixel);
+            let transferred = [0, 1, 2].map(|c| {
+                let scale = if source.std[c] > f32::EPSILON {
+                    self.stats.std[c] / source.std[c]
+                } else {
+                    1.0
+                };
+                let matched = (lab[c] - source.mean[c]) * scale + self.stats.mean[c];
+                lab[c] + (matched - lab[c]) * mask_strength
+            });
+            result.put_pixel(x, y, from_lab(transferred));
+        }
+
+        debug!("Applied reference color transfer");
+        Ok(result)
+    }
+}
+
+fn to_lab(pixel: &Rgb<u8>) -> [f32; 3] {
+    let [r, g, b] = pixel.0;
+    let rgb = Srgb::new(r, g, b).into_format::<f32>();
+    let lab: Lab = Lab::from_color(rgb);
+    [lab.l, lab.a, lab.b]
+}
+
+fn from_lab([l, a, b]: [f32; 3]) -> Rgb<u8> {
+    let rgb = Srgb::from_color(Lab::new(l.clamp(0.0, 100.0), a, b));
+    let rgb: Srgb<u8> = rgb.into_format();
+    Rgb([rgb.red, rgb.green, rgb.blue])
+}
+
+/// Whether a color is a near-white backdrop, as in most product photos
+fn is_backdrop(lab: &[f32; 3]) -> bool {
+    lab[0] > 92.0 && lab[1].hypot(lab[2]) < 6.0
+}
+
+/// Members of the largest k-means cluster, skipping a white backdrop unless it's
+/// all there is
+fn dominant_cluster(pixels: &[[f32; 3]]) -> Vec<[f32; 3]> {
+    let clusters = kmeans(pixels, CLUSTERS, KMEANS_ITERATIONS);
+    let mut ranked: Vec<_> = clusters.into_iter().filter(|c| !c.is_empty()).collect();
+    ranked.sort_by_key(|c| std::cmp::Reverse(c.len()));
+
+    let centroid = |c: &[[f32; 3]]| LabStats::from_samples(c).map(|s| s.mean);
+    let garment = ranked
+        .iter()
+        .position(|c| centroid(c).is_some_and(|m| !is_backdrop(&m)))
+        .unwrap_or(0);
+    ranked.into_iter().nth(garment).unwrap_or_default()
+}
+
+/// Plain k-means with centroids seeded from evenly spaced lightness quantiles, so
+/// results are deterministic
+fn kmeans(pixels: &[[f32; 3]], k: usize, iterations: usize) -> Vec<Vec<[f32; 3]>> {
+    if pixels.is_empty() {
+        return Vec::new();
+    }
+    let mut by_lightness = pixels.to_vec();
+    by_lightness.sort_by(|a, b| a[0].total_cmp(&b[0]));
+    let mut centroids: Vec<[f32; 3]> = (0..k)
+        .map(|i| by_lightness[(i * 2 + 1) * (pixels.len() - 1) / (k * 2)])
+        .collect();
+
+    let mut clusters = vec![Vec::new(); k];
+    for _ in 0..iterations {
+        clusters = vec![Vec::new(); k];
+        for p in pixels {
+            let nearest = (0..k)
+                .min_by(|&a, &b| distance(p, &centroids[a]).total_cmp(&distance(p, &centroids[b])))
+                .unwrap_or(0);
+            clusters[nearest].push(*p);
+        }
+        for (centroid, members) in centroids.iter_mut().zip(&clusters) {
+            if let Some(stats) = LabStats::from_samples(members) {
+                *centroid = stats.mean;
+            }
+        }
+    }
+    clusters
+}
+
+fn distance(a: &[f32; 3], b: &[f32; 3]) -> f32 {
+    (0..3).map(|c| (a[c] - b[c]).powi(2)).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+    use palette::Hsl;
+
+    fn hue(pixel: &Rgb<u8>) -> f32 {
+        let [r, g, b] = pixel.0;
+        let hsl = Hsl::from_color(Srgb::new(r, g, b).into_format::<f32>());
+        hsl.hue.into_positive_degrees()
+    }
+
+    fn hue_distance(a: f32, b: f32) -> f32 {
+        let d = (a - b).abs() % 360.0;
+        d.min(360.0 - d)
+    }
+
+    #[test]
+    fn test_transfer_moves_masked_hue_towards_reference() -> Result<()> {
+        let blue = Rgb([40, 60, 200]);
+        let reference = ColorReference::from_image(&DynamicImage::ImageRgb8(
+            RgbImage::from_pixel(64, 64, blue),
+        ))?;
+
+        // Red garment in the left half, untouched background on the right
+        let red = Rgb([200, 40, 40]);
+        let image = RgbImage::from_pixel(20, 10, red);
+        let mask = GrayImage::from_fn(20, 10, |x, _| Luma([if x < 10 { 255 } else { 0 }]));
+
+        let result = reference.transfer(&image, &mask, 1.0, &CancelToken::new())?;
+
+        let before = hue_distance(hue(&red), hue(&blue));
+        let after = hue_distance(hue(result.get_pixel(2, 2)), hue(&blue));
+        assert!(
+            after < before / 4.0,
+            "hue moved from {before} to only {after}"
+        );
+        assert_eq!(result.get_pixel(15, 5), &red);
+        Ok(())
+    }
+
+    #[test]
+    fn test_transfer_strength_scales_the_change() -> Result<()> {
+        let reference = ColorReference::from_image(&DynamicImage::ImageRgb8(
+            RgbImage::from_pixel(40, 40, Rgb([30, 160, 60])),
+        ))?;
+        let red = Rgb([200, 40, 40]);
+        let image = RgbImage::from_pixel(8, 8, red);
+        let mask = GrayImage::from_pixel(8, 8, Luma([255]));
+        let green_hue = hue(&Rgb([30, 160, 60]));
+
+        let half = reference.transfer(&image, &mask, 0.5, &CancelToken::new())?;
+        let full = reference.transfer(&image, &mask, 1.0, &CancelToken::new())?;
+        assert!(
+            hue_distance(hue(full.get_pixel(0, 0)), green_hue)
+                < hue_distance(hue(half.get_pixel(0, 0)), green_hue)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_dominant_color_ignores_white_backdrop() -> Result<()> {
+        // A small blue garment on a mostly white product shot
+        let image = RgbImage::from_fn(64, 64, |x, y| {
+            if (24..40).contains(&x) && (16..48).contains(&y) {
+                Rgb([40, 60, 200])
+            } else {
+                Rgb([255, 255, 255])
+            }
+        });
+        let reference = ColorReference::from_image(&DynamicImage::ImageRgb8(image))?;
+        let blue = to_lab(&Rgb([40, 60, 200]));
+        assert!(distance(&reference.stats().mean, &blue) < 25.0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_grayscale_reference_desaturates() -> Result<()> {
+        let gray = DynamicImage::ImageLuma8(GrayImage::from_pixel(48, 48, Luma([90])));
+        let reference = ColorReference::from_image(&gray)?;
+        let stats = reference.stats();
+        assert!(stats.mean[1].abs() < 1.0 && stats.mean[2].abs() < 1.0);
+
+        let image = RgbImage::from_pixel(4, 4, Rgb([200, 40, 40]));
+        let mask = GrayImage::from_pixel(4, 4, Luma([255]));
+        let [r, g, b] = reference
+            .transfer(&image, &mask, 1.0, &CancelToken::new())?
+            .get_pixel(0, 0)
+            .0;
+        assert!(r.abs_diff(g) <= 3 && g.abs_diff(b) <= 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_tiny_reference_is_rejected() {
+        let tiny = DynamicImage::ImageRgb8(RgbImage::from_pixel(31, 64, Rgb([40, 60, 200])));
+        let err = ColorReference::from_image(&tiny).unwrap_err();
+        assert!(err.to_string().contains("at least 32x32"));
+    }
+}
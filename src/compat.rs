@@ -0,0 +1,237 @@
+//! Whether a model can do what a command asks of it
+//!
+//! Any model in the index can be named with `--model`, including ones that
+//! can't make images at all, like the CLIP encoder
+//! `openai/clip-vit-base-patch32`. [`classify`] looks at what is known about a
+//! model, in order: its diffusers pipeline, the task it declared on the Hub,
+//! the architecture in a transformers `config.json`, and whether it has any
+//! weights. Models none of these settle are [`Compatibility::Unknown`].
+
+use std::{fmt, fs};
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{
+    diffusers_config::DiffusersConfig,
+    models::{ModelFile, ModelInfo},
+};
+
+/// Hub pipeline tags of models that generate images
+const IMAGE_GENERATION_TAGS: &[&str] = &[
+    "text-to-image",
+    "image-to-image",
+    "unconditional-image-generation",
+];
+/// Pipeline components that decode latents into pixels
+const IMAGE_DECODERS: &[&str] = &["vae", "vqvae", "movq"];
+/// The transformers config at the root of a repo
+const TRANSFORMERS_CONFIG: &str = "config.json";
+
+/// What a command needs a model for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Task {
+    /// Making an image from a prompt
+    ImageGeneration,
+    /// Changing an input image, as `image generate` does
+    ImageToImage,
+}
+
+impl fmt::Display for Task {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::ImageGeneration => "image generation",
+            Self::ImageToImage => "image-to-image",
+        })
+    }
+}
+
+/// Whether a model suits a [`Task`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "status", content = "reason", rename_all = "snake_case")]
+pub enum Compatibility {
+    Compatible,
+    /// The model can't do the task, and why
+    Incompatible(String),
+    /// Nothing known about the model settles it
+    Unknown,
+}
+
+impl Compatibility {
+    /// The short form shown in a `model list` column
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Compatible => "yes",
+            Self::Incompatible(_) => "no",
+            Self::Unknown => "?",
+        }
+    }
+}
+
+impl fmt::Display for Compatibility {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Compatible => f.write_str("compatible"),
+            Self::Incompatible(reason) => write!(f, "incompatible: {reason}"),
+            Self::Unknown => f.write_str("unknown"),
+        }
+    }
+}
+
+/// Whether `model`, whose diffusers layout is `config`, suits `task`
+pub fn classify(model: &ModelInfo, config: &DiffusersConfig, task: Task) -> Compatibility {
+    if let DiffusersConfig::Pipeline(pipeline) = config {
+        let has_decoder = pipeline
+            .index
+            .components()
+            .iter()
+            .any(|c| c.is_present() && IMAGE_DECODERS.contains(&c.name.as_str()));
+        return if has_decoder {
+            Compatibility::Compatible
+        } else {
+            Compatibility::Unknown
+        };
+    }
+    if let Some(tag) = &model.pipeline_tag {
+        return if IMAGE_GENERATION_TAGS.contains(&tag.as_str()) {
+            Compatibility::Compatible
+        } else {
+            Compatibility::Incompatible(format!("it is a {tag} model, not made for {task}"))
+        };
+    }
+    if let Some(architecture) = model
+        .files
+        .iter()
+        .find(|f| f.name() == Some(TRANSFORMERS_CONFIG))
+        .and_then(transformers_architecture)
+    {
+        return Compatibility::Incompatible(format!(
+            "it is a transformers {architecture}, not a diffusion pipeline"
+        ));
+    }
+    if !model.files.is_empty() && !model.files.iter().any(|f| f.weight_format().is_weights()) {
+        return Compatibility::Incompatible(
+            "it has no weights, only configuration and tokenizer files".to_string(),
+        );
+    }
+    Compatibility::Unknown
+}
+
+/// The first of the `architectures` a transformers config lists, if `file` is
+/// one
+fn transformers_architecture(file: &ModelFile) -> Option<String> {
+    let config: Value = serde_json::from_str(&fs::read_to_string(&file.path).ok()?).ok()?;
+    config["architectures"].get(0)?.as_str().map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diffusers_config;
+    use anyhow::Result;
+    use std::path::{Path, PathBuf};
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/compat")
+            .join(name)
+    }
+
+    /// The fixture repo `name` as the index would hold it, with its config
+    fn indexed(name: &str) -> Result<(ModelInfo, DiffusersConfig)> {
+        let root = fixture(name);
+        let mut names = Vec::new();
+        collect(&root, &root, &mut names)?;
+        let files = names
+            .iter()
+            .map(|name| ModelFile::from_cached(&root.join(name), name))
+            .collect::<std::io::Result<_>>()?;
+        let model = ModelInfo::new(format!("fixtures/{name}"), files);
+        Ok((model, diffusers_config::read(&root)?))
+    }
+
+    fn collect(root: &Path, dir: &Path, names: &mut Vec<String>) -> Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                collect(root, &path, names)?;
+            } else {
+                let name = path.strip_prefix(root)?.to_string_lossy();
+                names.push(name.replace('\\', "/"));
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_stable_diffusion_is_compatible() -> Result<()> {
+        let (model, config) = indexed("stable-diffusion")?;
+        for task in [Task::ImageGeneration, Task::ImageToImage] {
+            assert_eq!(classify(&model, &config, task), Compatibility::Compatible);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_clip_is_incompatible() -> Result<()> {
+        let (mut model, config) = indexed("clip-vit")?;
+        // Indexed before pipeline tags were recorded, the config gives it away
+        let Compatibility::Incompatible(reason) = classify(&model, &config, Task::ImageToImage)
+        else {
+            panic!("CLIP classified as compatible");
+        };
+        assert!(reason.contains("CLIPModel"), "{reason}");
+
+        model.pipeline_tag = Some("zero-shot-image-classification".to_string());
+        assert_eq!(
+            classify(&model, &config, Task::ImageGeneration),
+            Compatibility::Incompatible(
+                "it is a zero-shot-image-classification model, not made for image generation"
+                    .to_string()
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_tokenizer_only_repo_is_incompatible() -> Result<()> {
+        let (model, config) = indexed("tokenizer-only")?;
+        assert_eq!(
+            classify(&model, &config, Task::ImageToImage),
+            Compatibility::Incompatible(
+                "it has no weights, only configuration and tokenizer files".to_string()
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_unknown_without_evidence() {
+        let config = DiffusersConfig::NotDiffusersLayout;
+        let empty = ModelInfo::new("org/empty", vec![]);
+        assert_eq!(
+            classify(&empty, &config, Task::ImageToImage),
+            Compatibility::Unknown
+        );
+
+        // A bare checkpoint may well be Stable Diffusion, but nothing says so
+        let mut checkpoint = ModelInfo::new("org/single-file", vec![]);
+        checkpoint.files.push(ModelFile {
+            size: 1,
+            path: PathBuf::from("/models/v1-5.safetensors"),
+            sha256: None,
+            rfilename: Some("v1-5.safetensors".to_string()),
+            format: None,
+        });
+        assert_eq!(
+            classify(&checkpoint, &config, Task::ImageToImage),
+            Compatibility::Unknown
+        );
+        checkpoint.pipeline_tag = Some("text-to-image".to_string());
+        assert_eq!(
+            classify(&checkpoint, &config, Task::ImageToImage),
+            Compatibility::Compatible
+        );
+    }
+}
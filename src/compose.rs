@@ -0,0 +1,570 @@
+//! Compositing helpers for combining several generated images into one
+
+use std::{fmt, str::FromStr};
+
+use anyhow::{Result, bail};
+use image::{DynamicImage, GenericImage, Rgb, RgbImage, imageops};
+use serde::{Deserialize, Serialize};
+
+/// Space in pixels around and between grid cells
+pub const GRID_PADDING: u32 = 4;
+const GLYPH_WIDTH: u32 = 5;
+const GLYPH_HEIGHT: u32 = 7;
+const GLYPH_SCALE: u32 = 2;
+const GLYPH_ADVANCE: u32 = (GLYPH_WIDTH + 1) * GLYPH_SCALE;
+/// Height of the label band drawn under each cell
+pub const LABEL_HEIGHT: u32 = GLYPH_HEIGHT * GLYPH_SCALE + 2 * GRID_PADDING;
+
+/// Width of the line between the two halves of a [`side_by_side`] comparison
+pub const DIVIDER_WIDTH: u32 = 2;
+
+const BACKGROUND: Rgb<u8> = Rgb([255, 255, 255]);
+const TEXT_COLOR: Rgb<u8> = Rgb([0, 0, 0]);
+const DIVIDER_COLOR: Rgb<u8> = Rgb([128, 128, 128]);
+
+/// How [`side_by_side`] arranges its two images
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ComparisonLayout {
+    /// Next to each other, the first on the left
+    #[default]
+    Horizontal,
+    /// Stacked, the first on top
+    Vertical,
+}
+
+impl FromStr for ComparisonLayout {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "horizontal" => Ok(Self::Horizontal),
+            "vertical" => Ok(Self::Vertical),
+            other => bail!("Unknown comparison layout `{other}`, expected horizontal or vertical"),
+        }
+    }
+}
+
+impl fmt::Display for ComparisonLayout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Horizontal => "horizontal",
+            Self::Vertical => "vertical",
+        })
+    }
+}
+
+/// Arrange `images` in a grid with `cols` columns, optionally labeling each cell
+///
+/// Cells are sized to the largest image; smaller images are centered in their cell.
+/// The whole grid is drawn at once; [`GridLayout::rows`] draws the same grid a
+/// row of cells at a time.
+pub fn make_grid(
+    images: &[(String, DynamicImage)],
+    cols: usize,
+    label: bool,
+) -> Result<DynamicImage> {
+    let layout = GridLayout::fit(images, cols, label)?;
+    let (width, height) = layout.dimensions();
+    let mut canvas = RgbImage::from_pixel(width, height, BACKGROUND);
+
+    for (i, (text, image)) in images.iter().enumerate() {
+        let col = i as u32 % layout.cols;
+        let row = i as u32 / layout.cols;
+        let cell_y = GRID_PADDING + row * (layout.row_height() + GRID_PADDING);
+        layout.draw_cell(&mut canvas, col, cell_y, text, image)?;
+    }
+
+    Ok(DynamicImage::ImageRgb8(canvas))
+}
+
+/// Where the cells of a grid go, settled before any image is drawn
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GridLayout {
+    /// Cells the grid holds
+    pub count: usize,
+    pub cols: u32,
+    pub rows: u32,
+    pub cell_width: u32,
+    pub cell_height: u32,
+    /// Whether a label band goes under each cell
+    pub label: bool,
+}
+
+impl GridLayout {
+    /// A grid of `count` cells of `cell_width`x`cell_height`, `cols` to a row
+    /// or all in one when there are fewer
+    pub fn new(
+        count: usize,
+        cols: usize,
+        (cell_width, cell_height): (u32, u32),
+        label: bool,
+    ) -> Result<Self> {
+        if count == 0 {
+            bail!("Cannot build a grid from zero images");
+        }
+        if cols == 0 {
+            bail!("Grid must have at least one column");
+        }
+        let cols = cols.min(count) as u32;
+        Ok(Self {
+            count,
+            cols,
+            rows: count.div_ceil(cols as usize) as u32,
+            cell_width,
+            cell_height,
+            label,
+        })
+    }
+
+    /// The layout [`make_grid`] gives `images`: cells as large as the largest
+    pub fn fit(images: &[(String, DynamicImage)], cols: usize, label: bool) -> Result<Self> {
+        let cell_width = images.iter().map(|(_, i)| i.width()).max().unwrap_or(0);
+        let cell_height = images.iter().map(|(_, i)| i.height()).max().unwrap_or(0);
+        Self::new(images.len(), cols, (cell_width, cell_height), label)
+    }
+
+    /// Width and height of the whole grid
+    pub fn dimensions(&self) -> (u32, u32) {
+        grid_dimensions(self.cols, self.rows, self.cell_width, self.row_height())
+    }
+
+    /// Draw the grid a row of cells at a time, taking the images for each row
+    /// from `images` only as it's drawn
+    ///
+    /// Each band is the padding above a row and the row, plus the padding below
+    /// for the last, so stacked they make the image [`make_grid`] draws. Only
+    /// one row of images and one band are held at once, and handed to
+    /// [`save_bands`](crate::imageio::save_bands) a PNG grid of any size is
+    /// never in memory as a whole.
+    pub fn rows<I>(self, images: I) -> GridRows<I::IntoIter>
+    where
+        I: IntoIterator<Item = Result<(String, DynamicImage)>>,
+    {
+        GridRows {
+            layout: self,
+            images: images.into_iter(),
+            row: 0,
+        }
+    }
+
+    /// Height of a row of cells with their labels
+    fn row_height(&self) -> u32 {
+        self.cell_height + if self.label { LABEL_HEIGHT } else { 0 }
+    }
+
+    /// Center `image` in the cell of column `col` whose top is at `cell_y`,
+    /// with its label under it
+    fn draw_cell(
+        &self,
+        canvas: &mut RgbImage,
+        col: u32,
+        cell_y: u32,
+        text: &str,
+        image: &DynamicImage,
+    ) -> Result<()> {
+        if image.width() > self.cell_width || image.height() > self.cell_height {
+            bail!(
+                "A {}x{} image doesn't fit a {}x{} grid cell",
+                image.width(),
+                image.height(),
+                self.cell_width,
+                self.cell_height
+            );
+        }
+        let cell_x = GRID_PADDING + col * (self.cell_width + GRID_PADDING);
+        let x = cell_x + (self.cell_width - image.width()) / 2;
+        let y = cell_y + (self.cell_height - image.height()) / 2;
+        canvas.copy_from(&image.to_rgb8(), x, y)?;
+
+        if self.label {
+            draw_text(
+                canvas,
+                &fit_label(text, self.cell_width),
+                cell_x,
+                cell_y + self.cell_height + GRID_PADDING,
+            );
+        }
+        Ok(())
+    }
+}
+
+/// The bands of a grid from [`GridLayout::rows`], top to bottom
+pub struct GridRows<I> {
+    layout: GridLayout,
+    images: I,
+    row: u32,
+}
+
+impl<I: Iterator<Item = Result<(String, DynamicImage)>>> GridRows<I> {
+    fn band(&mut self, row: u32) -> Result<RgbImage> {
+        let layout = self.layout;
+        let last = row + 1 == layout.rows;
+        let height = GRID_PADDING + layout.row_height() + if last { GRID_PADDING } else { 0 };
+        let mut band = RgbImage::from_pixel(layout.dimensions().0, height, BACKGROUND);
+
+        let first = row as usize * layout.cols as usize;
+        for i in first..layout.count.min(first + layout.cols as usize) {
+            let Some(next) = self.images.next() else {
+                bail!("Only {i} images for a grid of {}", layout.count);
+            };
+            let (text, image) = next?;
+            layout.draw_cell(
+                &mut band,
+                i as u32 % layout.cols,
+                GRID_PADDING,
+                &text,
+                &image,
+            )?;
+        }
+        if last && self.images.next().is_some() {
+            bail!("More than {} images for a grid of {0}", layout.count);
+        }
+        Ok(band)
+    }
+}
+
+impl<I: Iterator<Item = Result<(String, DynamicImage)>>> Iterator for GridRows<I> {
+    type Item = Result<RgbImage>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.row >= self.layout.rows {
+            return None;
+        }
+        self.row += 1;
+        let band = self.band(self.row - 1);
+        if band.is_err() {
+            // Nothing after a failed row lines up
+            self.row = self.layout.rows;
+        }
+        Some(band)
+    }
+}
+
+/// Put `a` and `b` next to each other (or one above the other) with a thin
+/// divider between them, optionally labeling each with the matching entry of
+/// `labels`
+///
+/// Both halves are sized to the larger image; the smaller one is letterboxed in
+/// its half rather than stretched.
+pub fn side_by_side(
+    a: &DynamicImage,
+    b: &DynamicImage,
+    layout: ComparisonLayout,
+    labels: Option<[&str; 2]>,
+) -> DynamicImage {
+    let cell_width = a.width().max(b.width());
+    let cell_height = a.height().max(b.height());
+    let label_height = if labels.is_some() { LABEL_HEIGHT } else { 0 };
+    let half_height = cell_height + label_height;
+
+    let (width, height) = match layout {
+        ComparisonLayout::Horizontal => (2 * cell_width + DIVIDER_WIDTH, half_height),
+        ComparisonLayout::Vertical => (cell_width, 2 * half_height + DIVIDER_WIDTH),
+    };
+    let mut canvas = RgbImage::from_pixel(width, height, BACKGROUND);
+
+    for (i, image) in [a, b].into_iter().enumerate() {
+        let offset = i as u32;
+        let (cell_x, cell_y) = match layout {
+            ComparisonLayout::Horizontal => (offset * (cell_width + DIVIDER_WIDTH), 0),
+            ComparisonLayout::Vertical => (0, offset * (half_height + DIVIDER_WIDTH)),
+        };
+        let x = cell_x + (cell_width - image.width()) / 2;
+        let y = cell_y + (cell_height - image.height()) / 2;
+        imageops::overlay(&mut canvas, &image.to_rgb8(), x.into(), y.into());
+
+        if let Some(labels) = labels {
+            draw_text(
+                &mut canvas,
+                &fit_label(labels[i], cell_width),
+                cell_x + GRID_PADDING,
+                cell_y + cell_height + GRID_PADDING,
+            );
+        }
+    }
+
+    let divider = match layout {
+        ComparisonLayout::Horizontal => (cell_width, 0, DIVIDER_WIDTH, height),
+        ComparisonLayout::Vertical => (0, half_height, width, DIVIDER_WIDTH),
+    };
+    let (divider_x, divider_y, divider_width, divider_height) = divider;
+    for y in divider_y..divider_y + divider_height {
+        for x in divider_x..divider_x + divider_width {
+            canvas.put_pixel(x, y, DIVIDER_COLOR);
+        }
+    }
+
+    DynamicImage::ImageRgb8(canvas)
+}
+
+/// Total grid size for `cols` x `rows` cells of the given size, including padding
+pub fn grid_dimensions(cols: u32, rows: u32, cell_width: u32, cell_height: u32) -> (u32, u32) {
+    (
+        cols * cell_width + (cols + 1) * GRID_PADDING,
+        rows * cell_height + (rows + 1) * GRID_PADDING,
+    )
+}
+
+/// A roughly square column count for `n` images
+pub fn default_columns(n: usize) -> usize {
+    (n as f64).sqrt().ceil().max(1.0) as usize
+}
+
+/// Truncate `text` with an ellipsis so it fits in `width` pixels
+fn fit_label(text: &str, width: u32) -> String {
+    let max_chars = (width / GLYPH_ADVANCE) as usize;
+    if text.chars().count() <= max_chars {
+        return text.to_string();
+    }
+    if max_chars <= 3 {
+        return ".".repeat(max_chars);
+    }
+    let mut truncated: String = text.chars().take(max_chars - 3).collect();
+    truncated.push_str("...");
+    truncated
+}
+
+/// Draw `text` with the embedded bitmap font, clipping at the canvas edge
+pub fn draw_text(canvas: &mut RgbImage, text: &str, x: u32, y: u32) {
+    for (i, c) in text.chars().enumerate() {
+        let glyph = glyph(c);
+        let origin_x = x + i as u32 * GLYPH_ADVANCE;
+        for (row, bits) in glyph.iter().enumerate() {
+            for col in 0..GLYPH_WIDTH {
+                if bits & (1 << (GLYPH_WIDTH - 1 - col)) == 0 {
+                    continue;
+                }
+                for dy in 0..GLYPH_SCALE {
+                    for dx in 0..GLYPH_SCALE {
+                        let px = origin_x + col * GLYPH_SCALE + dx;
+                        let py = y + row as u32 * GLYPH_SCALE + dy;
+                        if px < canvas.width() && py < canvas.height() {
+                            canvas.put_pixel(px, py, TEXT_COLOR);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// 5x7 bitmap glyphs; each row's low five bits are pixels, most significant on the left
+fn glyph(c: char) -> [u8; 7] {
+    match c.to_ascii_uppercase() {
+        'A' => [0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'B' => [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E],
+        'C' => [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E],
+        'D' => [0x1E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x1E],
+        'E' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F],
+        'F' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10],
+        'G' => [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F],
+        'H' => [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'I' => [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        'J' => [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C],
+        'K' => [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F],
+        'M' => [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11],
+        'N' => [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+        'O' => [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'P' => [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10],
+        'Q' => [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D],
+        'R' => [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11],
+        'S' => [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E],
+        'T' => [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04],
+        'W' => [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A],
+        'X' => [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11],
+        'Y' => [0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04],
+        'Z' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F],
+        '0' => [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],
+        '1' => [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        '2' => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],
+        '3' => [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],
+        '4' => [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],
+        '5' => [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],
+        '6' => [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],
+        '7' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],
+        '9' => [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],
+        ' ' => [0x00; 7],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C],
+        ',' => [0x00, 0x00, 0x00, 0x00, 0x0C, 0x04, 0x08],
+        ':' => [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00],
+        '-' => [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00],
+        '_' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1F],
+        '/' => [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00],
+        '(' => [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02],
+        ')' => [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08],
+        '!' => [0x04, 0x04, 0x04, 0x04, 0x04, 0x00, 0x04],
+        '?' => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04],
+        '\'' => [0x04, 0x04, 0x08, 0x00, 0x00, 0x00, 0x00],
+        // Anything else renders as a hollow box so missing glyphs are visible
+        _ => [0x1F, 0x11, 0x11, 0x11, 0x11, 0x11, 0x1F],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solid(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_pixel(width, height, Rgb([200, 30, 30])))
+    }
+
+    #[test]
+    fn test_grid_dimensions_three_images_two_columns() -> Result<()> {
+        let images: Vec<_> = ["red", "blue", "green"]
+            .iter()
+            .map(|p| (p.to_string(), solid(64, 48)))
+            .collect();
+
+        let grid = make_grid(&images, 2, false)?;
+        assert_eq!(grid.width(), 2 * 64 + 3 * GRID_PADDING);
+        assert_eq!(grid.height(), 2 * 48 + 3 * GRID_PADDING);
+
+        let labeled = make_grid(&images, 2, true)?;
+        assert_eq!(labeled.width(), grid.width());
+        assert_eq!(labeled.height(), 2 * (48 + LABEL_HEIGHT) + 3 * GRID_PADDING);
+        Ok(())
+    }
+
+    #[test]
+    fn test_grid_uses_largest_cell() -> Result<()> {
+        let images = vec![
+            ("small".to_string(), solid(10, 10)),
+            ("large".to_string(), solid(30, 20)),
+        ];
+
+        let grid = make_grid(&images, 5, false)?;
+        assert_eq!((grid.width(), grid.height()), grid_dimensions(2, 1, 30, 20));
+        Ok(())
+    }
+
+    #[test]
+    fn test_long_labels_do_not_panic() -> Result<()> {
+        let long_prompt = "an extremely long prompt ".repeat(40) + "with ünïcödé ✨";
+        let images = vec![(long_prompt, solid(8, 8))];
+
+        let grid = make_grid(&images, 1, true)?;
+        assert_eq!(grid.width(), 8 + 2 * GRID_PADDING);
+        Ok(())
+    }
+
+    /// A tile that differs from every other tile and pixel to pixel
+    fn tile(i: u32, size: u32) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(size, size, |x, y| {
+            Rgb([(x + i * 16) as u8, (y * 3) as u8, (x ^ y ^ i) as u8])
+        }))
+    }
+
+    #[test]
+    fn test_lazy_grid_matches_the_eager_one() -> Result<()> {
+        const TILES: u32 = 16;
+        const SIZE: u32 = 1024;
+        let eager_images: Vec<_> = (0..TILES)
+            .map(|i| (format!("tile {i}"), tile(i, SIZE)))
+            .collect();
+        let eager = make_grid(&eager_images, 4, true)?.to_rgb8();
+        drop(eager_images);
+
+        let layout = GridLayout::new(TILES as usize, 4, (SIZE, SIZE), true)?;
+        let (width, height) = layout.dimensions();
+        assert_eq!((width, height), eager.dimensions());
+        let made = std::cell::Cell::new(0);
+        let images = (0..TILES).map(|i| {
+            made.set(made.get() + 1);
+            Ok((format!("tile {i}"), tile(i, SIZE)))
+        });
+
+        let mut lazy = RgbImage::new(width, height);
+        let mut y = 0;
+        for (row, band) in layout.rows(images).enumerate() {
+            // Only the row being drawn has been made
+            assert_eq!(made.get(), 4 * (row + 1));
+            let band = band?;
+            lazy.copy_from(&band, 0, y)?;
+            y += band.height();
+        }
+        assert_eq!(y, height);
+        assert!(lazy == eager);
+        Ok(())
+    }
+
+    #[test]
+    fn test_lazy_grid_needs_its_images() -> Result<()> {
+        let layout = GridLayout::new(3, 2, (8, 8), false)?;
+        let images = |n: usize| (0..n).map(|_| Ok((String::new(), solid(8, 8))));
+
+        assert!(layout.rows(images(2)).any(|band| band.is_err()));
+        assert!(layout.rows(images(4)).any(|band| band.is_err()));
+        assert_eq!(layout.rows(images(3)).filter(Result::is_ok).count(), 2);
+
+        let too_big = std::iter::once(Ok((String::new(), solid(9, 8))));
+        let mut bands = GridLayout::new(1, 1, (8, 8), false)?.rows(too_big);
+        assert!(bands.next().unwrap().is_err());
+        assert!(bands.next().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_fit_label() {
+        assert_eq!(fit_label("red", 200), "red");
+        assert_eq!(
+            fit_label("red dress with sleeves", 7 * GLYPH_ADVANCE),
+            "red ..."
+        );
+        assert_eq!(fit_label("red dress", GLYPH_ADVANCE), ".");
+    }
+
+    #[test]
+    fn test_invalid_grid_requests() {
+        assert!(make_grid(&[], 2, true).is_err());
+        assert!(make_grid(&[("a".to_string(), solid(4, 4))], 0, true).is_err());
+    }
+
+    #[test]
+    fn test_side_by_side_equal_sizes() {
+        let (a, b) = (solid(40, 30), solid(40, 30));
+
+        let horizontal = side_by_side(&a, &b, ComparisonLayout::Horizontal, None);
+        assert_eq!(horizontal.width(), 2 * 40 + DIVIDER_WIDTH);
+        assert_eq!(horizontal.height(), 30);
+
+        let vertical = side_by_side(&a, &b, ComparisonLayout::Vertical, Some(["in", "out"]));
+        assert_eq!(vertical.width(), 40);
+        assert_eq!(vertical.height(), 2 * (30 + LABEL_HEIGHT) + DIVIDER_WIDTH);
+    }
+
+    #[test]
+    fn test_side_by_side_letterboxes_the_smaller_image() {
+        let (a, b) = (solid(40, 30), solid(20, 10));
+
+        let composite = side_by_side(&a, &b, ComparisonLayout::Horizontal, None).to_rgb8();
+        assert_eq!(composite.dimensions(), (2 * 40 + DIVIDER_WIDTH, 30));
+        let right = 40 + DIVIDER_WIDTH;
+        // The smaller image keeps its size and sits centered on the background
+        assert_eq!(*composite.get_pixel(right, 0), BACKGROUND);
+        assert_eq!(*composite.get_pixel(right + 10, 10), Rgb([200, 30, 30]));
+        assert_eq!(*composite.get_pixel(right + 10, 9), BACKGROUND);
+        assert_eq!(*composite.get_pixel(40, 0), DIVIDER_COLOR);
+    }
+
+    #[test]
+    fn test_comparison_layout_round_trip() -> Result<()> {
+        for layout in [ComparisonLayout::Horizontal, ComparisonLayout::Vertical] {
+            assert_eq!(layout.to_string().parse::<ComparisonLayout>()?, layout);
+        }
+        assert!("diagonal".parse::<ComparisonLayout>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_default_columns() {
+        assert_eq!(default_columns(1), 1);
+        assert_eq!(default_columns(3), 2);
+        assert_eq!(default_columns(9), 3);
+        assert_eq!(default_columns(10), 4);
+    }
+}
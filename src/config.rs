@@ -0,0 +1,977 @@
+//! User configuration stored as TOML in the si config directory
+//!
+//! Generation settings (default model, strength, ...) can be set at the top level
+//! and overridden by named profiles stored under `[profiles.<name>]`. The profile
+//! in effect is chosen by the `--profile` flag, then the `SI_PROFILE` environment
+//! variable, then the `active_profile` key.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{Context, Result, bail};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    download_lock::DEFAULT_LOCK_TTL,
+    eviction::CachePolicy,
+    format::parse_size,
+    hooks::{FailurePolicy, Hook, HookEvent, Hooks},
+    index_paths::PathPolicy,
+    logging, paths,
+    rate_limit::RateLimit,
+    segment::CoverageBounds,
+    source::{self, HF_ENDPOINT_ENV},
+    template::OutputTemplate,
+};
+
+const CONFIG_FILENAME: &str = "config.toml";
+pub const PROFILE_ENV_VAR: &str = "SI_PROFILE";
+
+/// Keys understood by [`Config::get`] and [`Config::set`]
+pub const KNOWN_KEYS: &[&str] = &[
+    "output_template",
+    "history_enabled",
+    "metrics_enabled",
+    "thumbnails_enabled",
+    "active_profile",
+    "hf_endpoint",
+    "hf_endpoint_fallbacks",
+    "index_path_policy",
+    "mask_coverage_min",
+    "mask_coverage_max",
+    "download_lock_ttl_secs",
+    "download_rate_limit",
+    "cache_max_bytes",
+    "cache_max_age_days",
+    "models_dir",
+    "default_model",
+    "strength",
+    "steps",
+    "max_size",
+    "output_format",
+];
+
+/// Generation settings that can be set at the top level or per profile
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Profile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strength: Option<f64>,
+    /// Inference steps, reserved for diffusion backends
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub steps: Option<u32>,
+    /// Maximum input edge length in pixels
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_size: Option<u32>,
+    /// Output file extension used when the output path is generated
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_format: Option<String>,
+}
+
+impl Profile {
+    /// The value of a profile key; `None` when `key` isn't a profile key
+    fn get(&self, key: &str) -> Option<Option<String>> {
+        Some(match key {
+            "default_model" => self.default_model.clone(),
+            "strength" => self.strength.map(|v| v.to_string()),
+            "steps" => self.steps.map(|v| v.to_string()),
+            "max_size" => self.max_size.map(|v| v.to_string()),
+            "output_format" => self.output_format.clone(),
+            _ => return None,
+        })
+    }
+
+    /// Set a profile key, returning `false` when `key` isn't a profile key
+    fn set(&mut self, key: &str, value: &str) -> Result<bool> {
+        match key {
+            "default_model" => self.default_model = Some(value.to_string()),
+            "strength" => self.strength = Some(parse_fraction(key, value)?),
+            "steps" => self.steps = Some(parse_number(key, value)?),
+            "max_size" => self.max_size = Some(parse_number(key, value)?),
+            "output_format" => {
+                self.output_format = Some(value.trim_start_matches('.').to_lowercase())
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Fill unset fields from `fallback`
+    fn or(&self, fallback: &Profile) -> Profile {
+        Profile {
+            default_model: self
+                .default_model
+                .clone()
+                .or_else(|| fallback.default_model.clone()),
+            strength: self.strength.or(fallback.strength),
+            steps: self.steps.or(fallback.steps),
+            max_size: self.max_size.or(fallback.max_size),
+            output_format: self
+                .output_format
+                .clone()
+                .or_else(|| fallback.output_format.clone()),
+        }
+    }
+}
+
+/// Settings after layering the selected profile over the top-level config
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct EffectiveConfig {
+    /// Name of the profile that was applied, if any
+    pub profile: Option<String>,
+    #[serde(flatten)]
+    pub settings: Profile,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Config {
+    /// Template used to name generated images when no output path is given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_template: Option<String>,
+    /// Whether generations are appended to the history file (default: true)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub history_enabled: Option<bool>,
+    /// Whether generations and downloads are counted in the local metrics file
+    /// read by `si stats` (default: true); nothing is ever sent anywhere
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metrics_enabled: Option<bool>,
+    /// Whether a model's first generation is kept as its preview thumbnail (default: true)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thumbnails_enabled: Option<bool>,
+    /// Profile applied when neither `--profile` nor `SI_PROFILE` is given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub active_profile: Option<String>,
+    /// Hub to download models from, e.g. an internal mirror; `HF_ENDPOINT` wins
+    /// over it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hf_endpoint: Option<String>,
+    /// Endpoints retried in order when a request to the primary one fails
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hf_endpoint_fallbacks: Vec<String>,
+    /// What loading the model index does with file paths outside the models
+    /// directory and the Hub cache: warn (default), filter or strict
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index_path_policy: Option<PathPolicy>,
+    /// Fraction of the image below which a clothing mask is reported as
+    /// suspiciously small (default: 0.05)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mask_coverage_min: Option<f32>,
+    /// Fraction of the image above which a clothing mask is reported as
+    /// suspiciously large (default: 0.8)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mask_coverage_max: Option<f32>,
+    /// Seconds a model download lock may go unrefreshed before another download
+    /// breaks it (default: 7200)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub download_lock_ttl_secs: Option<u64>,
+    /// Cap on the download rate, such as `5MB/s`; `--limit-rate` wins over it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub download_rate_limit: Option<String>,
+    /// Size the Hub cache may grow to before `si model evict` removes the least
+    /// recently used models
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_max_bytes: Option<u64>,
+    /// Days a downloaded model may go unused before `si model evict` removes it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_max_age_days: Option<u64>,
+    /// Directory holding the model index; `SI_DATA_DIR` and `--models-dir` win
+    /// over it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub models_dir: Option<PathBuf>,
+    /// Top-level generation settings that profiles fall back to
+    #[serde(flatten)]
+    pub defaults: Profile,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, Profile>,
+    /// Commands run before and after generation
+    #[serde(default, skip_serializing_if = "Hooks::is_empty")]
+    pub hooks: Hooks,
+    /// Log level per target, e.g. `"si::models" = "debug"`; `RUST_LOG` and
+    /// `--debug` take precedence
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub log_levels: BTreeMap<String, String>,
+    /// Keys not known to this version of si, preserved on save
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, toml::Value>,
+}
+
+impl Config {
+    pub fn default_path() -> Result<PathBuf> {
+        Ok(paths::config_dir()?.join(CONFIG_FILENAME))
+    }
+
+    /// Load the config at `path`, returning defaults when the file doesn't exist
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            debug!(
+                "Config file not found at {}, using defaults",
+                path.display()
+            );
+            return Ok(Self::default());
+        }
+
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config from {}", path.display()))?;
+        toml::from_str(&text)
+            .with_context(|| format!("Failed to parse config from {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory {}", parent.display()))?;
+        }
+
+        let text = toml::to_string_pretty(self).context("Failed to serialize config")?;
+        fs::write(path, text)
+            .with_context(|| format!("Failed to write config to {}", path.display()))
+    }
+
+    /// Get a key; profile settings are addressed as `profiles.<name>.<key>`,
+    /// hooks as `hooks.<event>[.<key>]` and log levels as `log_levels.<target>`
+    pub fn get(&self, key: &str) -> Option<String> {
+        if let Some(target) = key.strip_prefix("log_levels.") {
+            return self.log_levels.get(target).cloned();
+        }
+        if let Some((name, profile_key)) = split_profile_key(key) {
+            return self.profiles.get(name)?.get(profile_key).flatten();
+        }
+        if let Some((event, hook_key)) = split_hook_key(key) {
+            let hook = self.hooks.get(event.ok()?)?;
+            return match hook_key {
+                "command" => Some(hook.command.clone()),
+                "on_failure" => Some(hook.on_failure.to_string()),
+                "timeout_secs" => hook.timeout_secs.map(|t| t.to_string()),
+                _ => None,
+            };
+        }
+        if let Some(value) = self.defaults.get(key) {
+            return value;
+        }
+
+        match key {
+            "output_template" => self.output_template.clone(),
+            "history_enabled" => self.history_enabled.map(|b| b.to_string()),
+            "metrics_enabled" => self.metrics_enabled.map(|b| b.to_string()),
+            "thumbnails_enabled" => self.thumbnails_enabled.map(|b| b.to_string()),
+            "active_profile" => self.active_profile.clone(),
+            "hf_endpoint" => self.hf_endpoint.clone(),
+            "hf_endpoint_fallbacks" => (!self.hf_endpoint_fallbacks.is_empty())
+                .then(|| self.hf_endpoint_fallbacks.join(",")),
+            "index_path_policy" => self.index_path_policy.map(|p| p.to_string()),
+            "mask_coverage_min" => self.mask_coverage_min.map(|v| v.to_string()),
+            "mask_coverage_max" => self.mask_coverage_max.map(|v| v.to_string()),
+            "download_lock_ttl_secs" => self.download_lock_ttl_secs.map(|v| v.to_string()),
+            "download_rate_limit" => self.download_rate_limit.clone(),
+            "cache_max_bytes" => self.cache_max_bytes.map(|v| v.to_string()),
+            "cache_max_age_days" => self.cache_max_age_days.map(|v| v.to_string()),
+            "models_dir" => self.models_dir.as_ref().map(|d| d.display().to_string()),
+            other => self.extra.get(other).map(|v| match v {
+                toml::Value::String(s) => s.clone(),
+                v => v.to_string(),
+            }),
+        }
+    }
+
+    /// Set a key, validating the value for keys si understands
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        if let Some(target) = key.strip_prefix("log_levels.") {
+            logging::parse_level(value).with_context(|| format!("Invalid value for `{key}`"))?;
+            self.log_levels
+                .insert(target.to_string(), value.trim().to_lowercase());
+            return Ok(());
+        }
+        if let Some((name, profile_key)) = split_profile_key(key) {
+            let profile = self.profiles.entry(name.to_string()).or_default();
+            if !profile.set(profile_key, value)? {
+                bail!("`{profile_key}` is not a profile setting");
+            }
+            return Ok(());
+        }
+        if let Some((event, hook_key)) = split_hook_key(key) {
+            let event = event?;
+            let slot = self.hooks.get_mut(event);
+            if hook_key == "command" {
+                slot.get_or_insert_with(Hook::default).command = value.to_string();
+                return Ok(());
+            }
+            let Some(hook) = slot else {
+                bail!("Set `hooks.{event}` before its other settings");
+            };
+            match hook_key {
+                "on_failure" => {
+                    hook.on_failure = value
+                        .parse::<FailurePolicy>()
+                        .with_context(|| format!("Invalid value for `{key}`"))?;
+                }
+                "timeout_secs" => hook.timeout_secs = Some(parse_number(key, value)?.into()),
+                other => bail!("`{other}` is not a hook setting"),
+            }
+            return Ok(());
+        }
+        if self.defaults.set(key, value)? {
+            return Ok(());
+        }
+
+        match key {
+            "output_template" => {
+                value
+                    .parse::<OutputTemplate>()
+                    .with_context(|| format!("Invalid value for `{key}`"))?;
+                self.output_template = Some(value.to_string());
+            }
+            "history_enabled" => {
+                self.history_enabled = Some(parse_bool(key, value)?);
+            }
+            "metrics_enabled" => {
+                self.metrics_enabled = Some(parse_bool(key, value)?);
+            }
+            "thumbnails_enabled" => {
+                self.thumbnails_enabled = Some(parse_bool(key, value)?);
+            }
+            "active_profile" => {
+                if !self.profiles.contains_key(value) {
+                    bail!("Unknown profile `{value}`");
+                }
+                self.active_profile = Some(value.to_string());
+            }
+            "hf_endpoint" => {
+                source::validate_endpoint(value)
+                    .with_context(|| format!("Invalid value for `{key}`"))?;
+                self.hf_endpoint = Some(value.to_string());
+            }
+            "hf_endpoint_fallbacks" => {
+                let endpoints: Vec<String> = value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|e| !e.is_empty())
+                    .map(str::to_string)
+                    .collect();
+                for endpoint in &endpoints {
+                    source::validate_endpoint(endpoint)
+                        .with_context(|| format!("Invalid value for `{key}`"))?;
+                }
+                self.hf_endpoint_fallbacks = endpoints;
+            }
+            "index_path_policy" => {
+                let policy = value
+                    .parse::<PathPolicy>()
+                    .with_context(|| format!("Invalid value for `{key}`"))?;
+                self.index_path_policy = Some(policy);
+            }
+            "mask_coverage_min" => {
+                self.mask_coverage_min = Some(parse_fraction(key, value)? as f32);
+            }
+            "mask_coverage_max" => {
+                self.mask_coverage_max = Some(parse_fraction(key, value)? as f32);
+            }
+            "download_lock_ttl_secs" => {
+                self.download_lock_ttl_secs = Some(parse_number(key, value)?.into());
+            }
+            "download_rate_limit" => {
+                value
+                    .parse::<RateLimit>()
+                    .with_context(|| format!("Invalid value for `{key}`"))?;
+                self.download_rate_limit = Some(value.trim().to_string());
+            }
+            "cache_max_bytes" => {
+                let bytes =
+                    parse_size(value).with_context(|| format!("Invalid value for `{key}`"))?;
+                self.cache_max_bytes = Some(bytes);
+            }
+            "cache_max_age_days" => {
+                self.cache_max_age_days = Some(parse_number(key, value)?.into());
+            }
+            "models_dir" => {
+                if value.trim().is_empty() {
+                    bail!("`{key}` can't be empty");
+                }
+                self.models_dir = Some(PathBuf::from(value));
+            }
+            other => {
+                warn!("`{other}` is not a known configuration key");
+                self.extra
+                    .insert(other.to_string(), toml::Value::String(value.to_string()));
+            }
+        }
+        Ok(())
+    }
+
+    /// The configured output template, or the built-in default
+    pub fn output_template(&self) -> Result<OutputTemplate> {
+        match &self.output_template {
+            Some(template) => template
+                .parse()
+                .context("Invalid `output_template` in config"),
+            None => Ok(OutputTemplate::default()),
+        }
+    }
+
+    pub fn history_enabled(&self) -> bool {
+        self.history_enabled.unwrap_or(true)
+    }
+
+    pub fn metrics_enabled(&self) -> bool {
+        self.metrics_enabled.unwrap_or(true)
+    }
+
+    pub fn thumbnails_enabled(&self) -> bool {
+        self.thumbnails_enabled.unwrap_or(true)
+    }
+
+    /// The configured clothing mask coverage bounds, each falling back to its
+    /// default
+    pub fn mask_coverage_bounds(&self) -> CoverageBounds {
+        let defaults = CoverageBounds::default();
+        CoverageBounds {
+            min: self.mask_coverage_min.unwrap_or(defaults.min),
+            max: self.mask_coverage_max.unwrap_or(defaults.max),
+        }
+    }
+
+    /// How long a model download lock may go unrefreshed before it is broken
+    pub fn download_lock_ttl(&self) -> Duration {
+        self.download_lock_ttl_secs
+            .map_or(DEFAULT_LOCK_TTL, Duration::from_secs)
+    }
+
+    /// The configured download rate limit, if any
+    pub fn download_rate_limit(&self) -> Result<Option<RateLimit>> {
+        self.download_rate_limit
+            .as_deref()
+            .map(|limit| {
+                limit
+                    .parse()
+                    .context("Invalid `download_rate_limit` in config")
+            })
+            .transpose()
+    }
+
+    /// The configured limits on the Hub cache
+    pub fn cache_policy(&self) -> CachePolicy {
+        CachePolicy {
+            max_bytes: self.cache_max_bytes,
+            max_age_days: self.cache_max_age_days,
+        }
+    }
+
+    /// The Hub endpoint to use: `HF_ENDPOINT`, then `hf_endpoint`; `None` means
+    /// the public Hub
+    pub fn hf_endpoint(&self) -> Option<String> {
+        let env = std::env::var(HF_ENDPOINT_ENV).ok();
+        self.hf_endpoint_with_env(env.as_deref())
+    }
+
+    fn hf_endpoint_with_env(&self, env: Option<&str>) -> Option<String> {
+        env.filter(|e| !e.is_empty())
+            .map(str::to_string)
+            .or_else(|| self.hf_endpoint.clone())
+    }
+
+    /// The configured `models_dir`, unless `SI_DATA_DIR` moves the data
+    /// directory; `None` means the models directory under the data directory
+    pub fn models_dir(&self) -> Option<PathBuf> {
+        let env = std::env::var_os(paths::DATA_DIR_ENV);
+        self.models_dir_with_env(env.as_deref())
+    }
+
+    fn models_dir_with_env(&self, env: Option<&std::ffi::OsStr>) -> Option<PathBuf> {
+        match env {
+            Some(dir) if !dir.is_empty() => None,
+            _ => self.models_dir.clone(),
+        }
+    }
+
+    /// Pick the profile name by precedence: `flag`, then `SI_PROFILE`, then `active_profile`
+    pub fn resolve_profile_name(&self, flag: Option<&str>) -> Option<String> {
+        let env = std::env::var(PROFILE_ENV_VAR).ok();
+        self.resolve_profile_name_with_env(flag, env.as_deref())
+    }
+
+    fn resolve_profile_name_with_env(
+        &self,
+        flag: Option<&str>,
+        env: Option<&str>,
+    ) -> Option<String> {
+        flag.or(env.filter(|e| !e.is_empty()))
+            .map(str::to_string)
+            .or_else(|| self.active_profile.clone())
+    }
+
+    /// Layer the named profile over the top-level settings
+    pub fn effective(&self, profile: Option<&str>) -> Result<EffectiveConfig> {
+        let Some(name) = profile else {
+            return Ok(EffectiveConfig {
+                profile: None,
+                settings: self.defaults.clone(),
+            });
+        };
+
+        let selected = self.profiles.get(name).with_context(|| {
+            let known: Vec<_> = self.profiles.keys().map(String::as_str).collect();
+            format!(
+                "Unknown profile `{name}` (known profiles: {})",
+                if known.is_empty() {
+                    "none".to_string()
+                } else {
+                    known.join(", ")
+                }
+            )
+        })?;
+
+        Ok(EffectiveConfig {
+            profile: Some(name.to_string()),
+            settings: selected.or(&self.defaults),
+        })
+    }
+
+    pub fn create_profile(&mut self, name: &str, profile: Profile) -> Result<()> {
+        if name.is_empty() || name.contains('.') {
+            bail!("Invalid profile name `{name}`");
+        }
+        if self.profiles.contains_key(name) {
+            bail!("Profile `{name}` already exists");
+        }
+        self.profiles.insert(name.to_string(), profile);
+        Ok(())
+    }
+
+    pub fn delete_profile(&mut self, name: &str) -> Result<()> {
+        if self.profiles.remove(name).is_none() {
+            bail!("Unknown profile `{name}`");
+        }
+        if self.active_profile.as_deref() == Some(name) {
+            self.active_profile = None;
+        }
+        Ok(())
+    }
+}
+
+fn split_profile_key(key: &str) -> Option<(&str, &str)> {
+    key.strip_prefix("profiles.")?.split_once('.')
+}
+
+/// Split `hooks.<event>` and `hooks.<event>.<key>`; a bare event addresses its command
+fn split_hook_key(key: &str) -> Option<(Result<HookEvent>, &str)> {
+    let rest = key.strip_prefix("hooks.")?;
+    let (event, hook_key) = rest.split_once('.').unwrap_or((rest, "command"));
+    Some((event.parse(), hook_key))
+}
+
+fn parse_number(key: &str, value: &str) -> Result<u32> {
+    value.parse().with_context(|| {
+        format!("Invalid value for `{key}`: expected a whole number, got `{value}`")
+    })
+}
+
+fn parse_fraction(key: &str, value: &str) -> Result<f64> {
+    let fraction: f64 = value
+        .parse()
+        .with_context(|| format!("Invalid value for `{key}`: expected a number, got `{value}`"))?;
+    if !(0.0..=1.0).contains(&fraction) {
+        bail!("Invalid value for `{key}`: must be between 0.0 and 1.0, got {fraction}");
+    }
+    Ok(fraction)
+}
+
+fn parse_bool(key: &str, value: &str) -> Result<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Ok(true),
+        "false" | "no" | "off" | "0" => Ok(false),
+        _ => bail!("Invalid value for `{key}`: expected true or false, got `{value}`"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_load_missing_file_returns_defaults() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let config = Config::load(&temp_dir.path().join("config.toml"))?;
+        assert_eq!(config, Config::default());
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_save_and_load_round_trip() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("nested/config.toml");
+
+        let mut config = Config::default();
+        config.set("output_template", "{stem}/{seed}.{ext}")?;
+        config.set("custom_key", "value")?;
+        config.save(&path)?;
+
+        let loaded = Config::load(&path)?;
+        assert_eq!(
+            loaded.get("output_template").as_deref(),
+            Some("{stem}/{seed}.{ext}")
+        );
+        assert_eq!(loaded.get("custom_key").as_deref(), Some("value"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_rejects_invalid_output_template() {
+        let mut config = Config::default();
+        assert!(config.set("output_template", "{unknown}.png").is_err());
+        assert_eq!(config.output_template, None);
+    }
+
+    #[test]
+    fn test_history_enabled_defaults_on() -> Result<()> {
+        let mut config = Config::default();
+        assert!(config.history_enabled());
+
+        config.set("history_enabled", "off")?;
+        assert!(!config.history_enabled());
+        assert!(config.set("history_enabled", "maybe").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_hook_keys_round_trip() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("config.toml");
+
+        let mut config = Config::default();
+        assert!(
+            config
+                .set("hooks.post_generate.on_failure", "warn")
+                .is_err()
+        );
+        config.set("hooks.post_generate", "upscale {output}")?;
+        config.set("hooks.post_generate.on_failure", "warn")?;
+        config.set("hooks.post_generate.timeout_secs", "30")?;
+        assert!(
+            config
+                .set("hooks.post_generate.on_failure", "ignore")
+                .is_err()
+        );
+        assert!(config.set("hooks.on_save", "true").is_err());
+        config.save(&path)?;
+
+        let loaded = Config::load(&path)?;
+        assert_eq!(
+            loaded.hooks.post_generate,
+            Some(Hook {
+                command: "upscale {output}".to_string(),
+                on_failure: FailurePolicy::Warn,
+                timeout_secs: Some(30),
+            })
+        );
+        assert_eq!(loaded.hooks.pre_generate, None);
+        assert_eq!(
+            loaded.get("hooks.post_generate").as_deref(),
+            Some("upscale {output}")
+        );
+        assert_eq!(
+            loaded.get("hooks.post_generate.on_failure").as_deref(),
+            Some("warn")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_thumbnails_enabled_defaults_on() -> Result<()> {
+        let mut config = Config::default();
+        assert!(config.thumbnails_enabled());
+
+        config.set("thumbnails_enabled", "false")?;
+        assert!(!config.thumbnails_enabled());
+        assert_eq!(config.get("thumbnails_enabled").as_deref(), Some("false"));
+        Ok(())
+    }
+
+    fn config_with_profiles() -> Config {
+        let mut config = Config::default();
+        config.set("default_model", "org/base-model").unwrap();
+        config.set("strength", "0.5").unwrap();
+        config.set("output_format", "png").unwrap();
+        config.set("profiles.quality.strength", "0.8").unwrap();
+        config.set("profiles.quality.steps", "50").unwrap();
+        config
+            .set("profiles.fast.default_model", "org/tiny-model")
+            .unwrap();
+        config
+    }
+
+    #[test]
+    fn test_effective_profile_falls_back_to_top_level() -> Result<()> {
+        let config = config_with_profiles();
+
+        let quality = config.effective(Some("quality"))?;
+        assert_eq!(quality.profile.as_deref(), Some("quality"));
+        assert_eq!(quality.settings.strength, Some(0.8));
+        assert_eq!(quality.settings.steps, Some(50));
+        assert_eq!(
+            quality.settings.default_model.as_deref(),
+            Some("org/base-model")
+        );
+        assert_eq!(quality.settings.output_format.as_deref(), Some("png"));
+
+        let fast = config.effective(Some("fast"))?;
+        assert_eq!(
+            fast.settings.default_model.as_deref(),
+            Some("org/tiny-model")
+        );
+        assert_eq!(fast.settings.strength, Some(0.5));
+
+        let none = config.effective(None)?;
+        assert_eq!(none.settings, config.defaults);
+        Ok(())
+    }
+
+    #[test]
+    fn test_effective_unknown_profile_errors() {
+        let config = config_with_profiles();
+        let err = config.effective(Some("nope")).unwrap_err();
+        assert!(err.to_string().contains("nope"));
+        assert!(err.to_string().contains("quality"));
+    }
+
+    #[test]
+    fn test_profile_name_precedence() -> Result<()> {
+        let mut config = config_with_profiles();
+        config.set("active_profile", "fast")?;
+
+        assert_eq!(
+            config.resolve_profile_name_with_env(Some("quality"), Some("other")),
+            Some("quality".to_string())
+        );
+        assert_eq!(
+            config.resolve_profile_name_with_env(None, Some("other")),
+            Some("other".to_string())
+        );
+        assert_eq!(
+            config.resolve_profile_name_with_env(None, None),
+            Some("fast".to_string())
+        );
+        assert!(config.set("active_profile", "missing").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_profiles_round_trip_through_toml() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("config.toml");
+        let config = config_with_profiles();
+        config.save(&path)?;
+
+        let text = fs::read_to_string(&path)?;
+        assert!(text.contains("[profiles.quality]"));
+        assert_eq!(Config::load(&path)?, config);
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_and_delete_profile() -> Result<()> {
+        let mut config = config_with_profiles();
+        config.create_profile("draft", Profile::default())?;
+        assert!(config.create_profile("draft", Profile::default()).is_err());
+
+        config.set("active_profile", "draft")?;
+        config.delete_profile("draft")?;
+        assert_eq!(config.active_profile, None);
+        assert!(config.delete_profile("draft").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_profile_values_rejected() {
+        let mut config = Config::default();
+        assert!(config.set("strength", "1.5").is_err());
+        assert!(config.set("profiles.quality.steps", "many").is_err());
+        assert!(config.set("profiles.quality.unknown", "1").is_err());
+    }
+
+    #[test]
+    fn test_hf_endpoint_keys() -> Result<()> {
+        let mut config = Config::default();
+        assert_eq!(config.hf_endpoint_with_env(None), None);
+
+        config.set("hf_endpoint", "https://mirror.internal")?;
+        config.set(
+            "hf_endpoint_fallbacks",
+            "https://backup.internal, http://10.0.0.5:8080",
+        )?;
+        assert_eq!(
+            config.hf_endpoint_fallbacks,
+            ["https://backup.internal", "http://10.0.0.5:8080"]
+        );
+        assert_eq!(
+            config.get("hf_endpoint_fallbacks").as_deref(),
+            Some("https://backup.internal,http://10.0.0.5:8080")
+        );
+
+        // HF_ENDPOINT beats the config, an empty one doesn't
+        assert_eq!(
+            config.hf_endpoint_with_env(None).as_deref(),
+            Some("https://mirror.internal")
+        );
+        assert_eq!(
+            config.hf_endpoint_with_env(Some("https://env")).as_deref(),
+            Some("https://env")
+        );
+        assert_eq!(
+            config.hf_endpoint_with_env(Some("")).as_deref(),
+            Some("https://mirror.internal")
+        );
+
+        assert!(config.set("hf_endpoint", "mirror.internal").is_err());
+        assert!(config.set("hf_endpoint", "ftp://mirror").is_err());
+        assert!(
+            config
+                .set("hf_endpoint_fallbacks", "https://ok,file:///tmp")
+                .is_err()
+        );
+        assert_eq!(
+            config.hf_endpoint.as_deref(),
+            Some("https://mirror.internal")
+        );
+        assert_eq!(config.hf_endpoint_fallbacks.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mask_coverage_keys() -> Result<()> {
+        let mut config = Config::default();
+        assert_eq!(config.mask_coverage_bounds(), CoverageBounds::default());
+        config.set("mask_coverage_max", "0.6")?;
+        assert_eq!(config.mask_coverage_bounds().max, 0.6);
+        assert_eq!(config.mask_coverage_bounds().min, 0.05);
+        assert_eq!(config.get("mask_coverage_max").as_deref(), Some("0.6"));
+        assert!(config.set("mask_coverage_min", "5%").is_err());
+        assert!(config.set("mask_coverage_min", "1.5").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_download_lock_ttl_key() -> Result<()> {
+        let mut config = Config::default();
+        assert_eq!(config.download_lock_ttl(), DEFAULT_LOCK_TTL);
+        config.set("download_lock_ttl_secs", "600")?;
+        assert_eq!(config.download_lock_ttl(), Duration::from_secs(600));
+        assert_eq!(config.get("download_lock_ttl_secs").as_deref(), Some("600"));
+        assert!(config.set("download_lock_ttl_secs", "10m").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_cache_policy_keys() -> Result<()> {
+        let mut config = Config::default();
+        assert_eq!(config.cache_policy(), CachePolicy::default());
+        config.set("cache_max_bytes", "50GB")?;
+        config.set("cache_max_age_days", "30")?;
+        assert_eq!(
+            config.cache_policy(),
+            CachePolicy {
+                max_bytes: Some(50_000_000_000),
+                max_age_days: Some(30),
+            }
+        );
+        assert_eq!(
+            config.get("cache_max_bytes").as_deref(),
+            Some("50000000000")
+        );
+        assert!(config.set("cache_max_bytes", "lots").is_err());
+        assert!(config.set("cache_max_age_days", "-1").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_download_rate_limit_key() -> Result<()> {
+        let mut config = Config::default();
+        assert_eq!(config.download_rate_limit()?, None);
+        config.set("download_rate_limit", "5MB/s")?;
+        assert_eq!(
+            config.download_rate_limit()?,
+            Some(RateLimit::new(5_000_000)?)
+        );
+        assert_eq!(config.get("download_rate_limit").as_deref(), Some("5MB/s"));
+        assert!(config.set("download_rate_limit", "fast").is_err());
+        assert!(config.set("download_rate_limit", "0/s").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_models_dir_key() -> Result<()> {
+        let mut config = Config::default();
+        assert_eq!(config.models_dir_with_env(None), None);
+        config.set("models_dir", "/srv/models")?;
+        assert_eq!(config.get("models_dir").as_deref(), Some("/srv/models"));
+        assert_eq!(
+            config.models_dir_with_env(None),
+            Some(PathBuf::from("/srv/models"))
+        );
+        // SI_DATA_DIR beats the config, an empty one doesn't
+        assert_eq!(config.models_dir_with_env(Some("/data".as_ref())), None);
+        assert_eq!(
+            config.models_dir_with_env(Some("".as_ref())),
+            Some(PathBuf::from("/srv/models"))
+        );
+        assert!(config.set("models_dir", " ").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_index_path_policy_key() -> Result<()> {
+        let mut config = Config::default();
+        assert_eq!(config.get("index_path_policy"), None);
+        config.set("index_path_policy", "strict")?;
+        assert_eq!(config.index_path_policy, Some(PathPolicy::Strict));
+        assert_eq!(config.get("index_path_policy").as_deref(), Some("strict"));
+        assert!(config.set("index_path_policy", "lenient").is_err());
+        let text = toml::to_string(&config)?;
+        assert!(text.contains(r#"index_path_policy = "strict""#), "{text}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_log_levels_round_trip() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("config.toml");
+        fs::write(
+            &path,
+            "[log_levels]\n\"si::models\" = \"debug\"\n\"si::tryon\" = \"warn\"\n",
+        )?;
+
+        let mut config = Config::load(&path)?;
+        assert_eq!(config.get("log_levels.si::tryon").as_deref(), Some("warn"));
+        config.set("log_levels.si::tryon::mask", "TRACE")?;
+        assert!(config.set("log_levels.si", "loud").is_err());
+        config.save(&path)?;
+
+        let loaded = Config::load(&path)?;
+        assert_eq!(
+            loaded.log_levels,
+            BTreeMap::from([
+                ("si::models".to_string(), "debug".to_string()),
+                ("si::tryon".to_string(), "warn".to_string()),
+                ("si::tryon::mask".to_string(), "trace".to_string()),
+            ])
+        );
+        assert!(loaded.extra.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_template_in_file_is_reported() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("config.toml");
+        fs::write(&path, "output_template = \"{nope}\"\n")?;
+
+        let config = Config::load(&path)?;
+        assert!(config.output_template().is_err());
+        Ok(())
+    }
+}
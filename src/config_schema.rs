@@ -0,0 +1,756 @@
+//! The keys `config.toml` understands, and strict validation against them
+//!
+//! [`SCHEMA`] lists every key of [`Config`](crate::Config) with its type,
+//! default and description; `si config schema` prints it and
+//! `si config validate` checks a config file against it. Keys under a table of
+//! user-chosen names are written with a placeholder, as in
+//! `profiles.<name>.strength`.
+//!
+//! Unlike [`Config::load`](crate::Config::load), which keeps unknown keys and
+//! stops at the first bad value, [`validate`] reports every unknown key, value of
+//! the wrong type and inconsistent setting, each with the line it is on.
+
+use std::{collections::HashMap, fmt, path::Path};
+
+use anyhow::{Result, bail};
+use serde::{Serialize, de::DeserializeOwned};
+use toml::{Table, Value};
+
+use crate::{
+    hooks::{FailurePolicy, HookEvent},
+    index_paths::PathPolicy,
+    logging,
+    rate_limit::RateLimit,
+    source,
+    template::{DEFAULT_OUTPUT_TEMPLATE, OutputTemplate},
+};
+
+/// One key of the config file
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct ConfigKey {
+    /// Dotted path of the key, with `<name>`, `<event>` or `<target>` standing
+    /// for names the user picks
+    pub key: &'static str,
+    /// Type of the value, e.g. `boolean` or `number (0.0 to 1.0)`
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    /// The value in effect when the key isn't set, if there is one
+    pub default: Option<&'static str>,
+    pub description: &'static str,
+    /// Whether the key can also be set per profile, as `profiles.<name>.<key>`
+    pub profile: bool,
+    /// Type-check and validate a value for the key
+    #[serde(skip)]
+    check: fn(&Value) -> Result<()>,
+}
+
+/// Every key of the config file
+pub const SCHEMA: &[ConfigKey] = &[
+    ConfigKey {
+        key: "output_template",
+        kind: "string",
+        default: Some(DEFAULT_OUTPUT_TEMPLATE),
+        description: "Template used to name generated images when no output path is given",
+        profile: false,
+        check: |v| typed::<String>(v)?.parse::<OutputTemplate>().map(drop),
+    },
+    ConfigKey {
+        key: "history_enabled",
+        kind: "boolean",
+        default: Some("true"),
+        description: "Whether generations are appended to the history file",
+        profile: false,
+        check: |v| typed::<bool>(v).map(drop),
+    },
+    ConfigKey {
+        key: "metrics_enabled",
+        kind: "boolean",
+        default: Some("true"),
+        description: "Whether generations and downloads are counted in the local metrics file \
+                      read by `si stats`; nothing is ever sent anywhere",
+        profile: false,
+        check: |v| typed::<bool>(v).map(drop),
+    },
+    ConfigKey {
+        key: "thumbnails_enabled",
+        kind: "boolean",
+        default: Some("true"),
+        description: "Whether a model's first generation is kept as its preview thumbnail",
+        profile: false,
+        check: |v| typed::<bool>(v).map(drop),
+    },
+    ConfigKey {
+        key: "active_profile",
+        kind: "string",
+        default: None,
+        description: "Profile applied when neither `--profile` nor `SI_PROFILE` is given",
+        profile: false,
+        check: |v| typed::<String>(v).map(drop),
+    },
+    ConfigKey {
+        key: "hf_endpoint",
+        kind: "string",
+        default: Some(source::DEFAULT_HF_ENDPOINT),
+        description: "Hub to download models from, e.g. an internal mirror; `HF_ENDPOINT` wins \
+                      over it",
+        profile: false,
+        check: |v| source::validate_endpoint(&typed::<String>(v)?),
+    },
+    ConfigKey {
+        key: "hf_endpoint_fallbacks",
+        kind: "list of strings",
+        default: None,
+        description: "Endpoints retried in order when a request to the primary one fails",
+        profile: false,
+        check: |v| {
+            typed::<Vec<String>>(v)?
+                .iter()
+                .try_for_each(|endpoint| source::validate_endpoint(endpoint))
+        },
+    },
+    ConfigKey {
+        key: "index_path_policy",
+        kind: "string",
+        default: Some("warn"),
+        description: "What loading the model index does with file paths outside the models \
+                      directory and the Hub cache: warn, filter or strict",
+        profile: false,
+        check: |v| typed::<String>(v)?.parse::<PathPolicy>().map(drop),
+    },
+    ConfigKey {
+        key: "mask_coverage_min",
+        kind: "number (0.0 to 1.0)",
+        default: Some("0.05"),
+        description: "Fraction of the image below which a clothing mask is reported as \
+                      suspiciously small",
+        profile: false,
+        check: fraction,
+    },
+    ConfigKey {
+        key: "mask_coverage_max",
+        kind: "number (0.0 to 1.0)",
+        default: Some("0.8"),
+        description: "Fraction of the image above which a clothing mask is reported as \
+                      suspiciously large",
+        profile: false,
+        check: fraction,
+    },
+    ConfigKey {
+        key: "download_lock_ttl_secs",
+        kind: "integer",
+        default: Some("7200"),
+        description: "Seconds a model download lock may go unrefreshed before another \
+                      download breaks it",
+        profile: false,
+        check: |v| typed::<u64>(v).map(drop),
+    },
+    ConfigKey {
+        key: "download_rate_limit",
+        kind: "string",
+        default: None,
+        description: "Cap on the download rate, such as `5MB/s`; `--limit-rate` wins over it",
+        profile: false,
+        check: |v| typed::<String>(v)?.parse::<RateLimit>().map(drop),
+    },
+    ConfigKey {
+        key: "cache_max_bytes",
+        kind: "integer",
+        default: None,
+        description: "Size in bytes the Hub cache may grow to before `si model evict` removes \
+                      the least recently used models; `si config set` also takes `50GB`",
+        profile: false,
+        check: |v| typed::<u64>(v).map(drop),
+    },
+    ConfigKey {
+        key: "cache_max_age_days",
+        kind: "integer",
+        default: None,
+        description: "Days a downloaded model may go unused before `si model evict` removes it",
+        profile: false,
+        check: |v| typed::<u64>(v).map(drop),
+    },
+    ConfigKey {
+        key: "models_dir",
+        kind: "path",
+        default: None,
+        description: "Directory holding the model index; `SI_DATA_DIR` and `--models-dir` win \
+                      over it",
+        profile: false,
+        check: |v| match typed::<String>(v)?.trim() {
+            "" => bail!("can't be empty"),
+            _ => Ok(()),
+        },
+    },
+    ConfigKey {
+        key: "default_model",
+        kind: "string",
+        default: None,
+        description: "Model used when `--model` isn't given",
+        profile: true,
+        check: |v| typed::<String>(v).map(drop),
+    },
+    ConfigKey {
+        key: "strength",
+        kind: "number (0.0 to 1.0)",
+        default: None,
+        description: "Transformation strength",
+        profile: true,
+        check: fraction,
+    },
+    ConfigKey {
+        key: "steps",
+        kind: "integer",
+        default: None,
+        description: "Inference steps, reserved for diffusion backends",
+        profile: true,
+        check: |v| typed::<u32>(v).map(drop),
+    },
+    ConfigKey {
+        key: "max_size",
+        kind: "integer",
+        default: None,
+        description: "Maximum input edge length in pixels",
+        profile: true,
+        check: |v| typed::<u32>(v).map(drop),
+    },
+    ConfigKey {
+        key: "output_format",
+        kind: "string",
+        default: None,
+        description: "Output file extension used when the output path is generated",
+        profile: true,
+        check: |v| typed::<String>(v).map(drop),
+    },
+    ConfigKey {
+        key: "profiles.<name>",
+        kind: "table",
+        default: None,
+        description: "Named profile holding any of the profile keys, which fall back to the \
+                      top-level ones",
+        profile: false,
+        check: |v| typed::<Table>(v).map(drop),
+    },
+    ConfigKey {
+        key: "hooks.<event>.command",
+        kind: "string",
+        default: None,
+        description: "Command run through the shell at the event, pre_generate or \
+                      post_generate, with {input}, {output}, {prompt} and {model} replaced",
+        profile: false,
+        check: |v| match typed::<String>(v)?.trim() {
+            "" => bail!("can't be empty"),
+            _ => Ok(()),
+        },
+    },
+    ConfigKey {
+        key: "hooks.<event>.on_failure",
+        kind: "string",
+        default: Some("abort"),
+        description: "What a failing hook does to the generation: abort or warn",
+        profile: false,
+        check: |v| typed::<FailurePolicy>(v).map(drop),
+    },
+    ConfigKey {
+        key: "hooks.<event>.timeout_secs",
+        kind: "integer",
+        default: Some("60"),
+        description: "Seconds a hook may run before it counts as failed",
+        profile: false,
+        check: |v| typed::<u64>(v).map(drop),
+    },
+    ConfigKey {
+        key: "log_levels.<target>",
+        kind: "string",
+        default: None,
+        description: "Log level for a target such as `si::models`; `RUST_LOG` and `--debug` \
+                      take precedence",
+        profile: false,
+        check: |v| logging::parse_level(&typed::<String>(v)?).map(drop),
+    },
+];
+
+/// The schema entry for `key`, written as in [`ConfigKey::key`]
+pub fn find(key: &str) -> Option<&'static ConfigKey> {
+    SCHEMA.iter().find(|entry| entry.key == key)
+}
+
+/// `value` deserialized as a `T`
+fn typed<T: DeserializeOwned>(value: &Value) -> Result<T> {
+    T::deserialize(value.clone()).map_err(|err| anyhow::anyhow!("{}", err.message()))
+}
+
+fn fraction(value: &Value) -> Result<()> {
+    let fraction = typed::<f64>(value)?;
+    if !(0.0..=1.0).contains(&fraction) {
+        bail!("must be between 0.0 and 1.0, got {fraction}");
+    }
+    Ok(())
+}
+
+/// Something wrong with a config file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ConfigProblem {
+    /// Line of the file the key is on, counting from 1
+    pub line: Option<usize>,
+    /// Dotted path of the key; empty when the file doesn't parse at all
+    pub key: String,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(line) = self.line {
+            write!(f, "line {line}: ")?;
+        }
+        if !self.key.is_empty() {
+            write!(f, "`{}`: ", self.key)?;
+        }
+        f.write_str(&self.message)
+    }
+}
+
+/// Check the text of a config file against [`SCHEMA`], returning every problem
+/// found, in file order
+///
+/// Besides the type and value of each key, this checks that `active_profile`
+/// names a profile, that the mask coverage bounds are in order and that
+/// `models_dir` is an existing directory.
+pub fn validate(text: &str) -> Vec<ConfigProblem> {
+    let mut problems = Problems {
+        lines: key_lines(text),
+        found: Vec::new(),
+    };
+    let table = match toml::from_str::<Table>(text) {
+        Ok(table) => table,
+        Err(err) => {
+            let line = err
+                .span()
+                .map(|span| text[..span.start].matches('\n').count() + 1);
+            let message = err.message().trim().to_string();
+            return vec![ConfigProblem {
+                line,
+                key: String::new(),
+                message,
+            }];
+        }
+    };
+
+    for (key, value) in &table {
+        match key.as_str() {
+            "profiles" => check_profiles(value, &mut problems),
+            "hooks" => check_hooks(value, &mut problems),
+            "log_levels" => {
+                for (target, level) in problems.table(key, value) {
+                    problems.check(
+                        &format!("log_levels.{target}"),
+                        "log_levels.<target>",
+                        level,
+                    );
+                }
+            }
+            _ => match find(key) {
+                Some(_) => problems.check(key, key, value),
+                None => problems.unknown(key, SCHEMA.iter().filter(|e| !e.key.contains('<'))),
+            },
+        }
+    }
+    check_consistency(&table, &mut problems);
+
+    let mut found = problems.found;
+    found.sort_by_key(|problem| problem.line.unwrap_or(usize::MAX));
+    found
+}
+
+fn check_profiles(profiles: &Value, problems: &mut Problems) {
+    for (name, profile) in problems.table("profiles", profiles) {
+        let key = format!("profiles.{name}");
+        if name.is_empty() || name.contains('.') {
+            problems.report(&key, format!("invalid profile name `{name}`"));
+        }
+        for (setting, value) in problems.table(&key, profile) {
+            let setting_key = format!("{key}.{setting}");
+            match find(setting).filter(|entry| entry.profile) {
+                Some(entry) => problems.check(&setting_key, entry.key, value),
+                None => problems.unknown(&setting_key, SCHEMA.iter().filter(|e| e.profile)),
+            }
+        }
+    }
+}
+
+fn check_hooks(hooks: &Value, problems: &mut Problems) {
+    for (event, hook) in problems.table("hooks", hooks) {
+        let key = format!("hooks.{event}");
+        if let Err(err) = event.parse::<HookEvent>() {
+            problems.report(&key, err.to_string());
+            continue;
+        }
+        let settings = problems.table(&key, hook);
+        if !settings.is_empty() && !settings.iter().any(|(name, _)| *name == "command") {
+            problems.report(&key, "missing `command`".to_string());
+        }
+        for (name, value) in settings {
+            let schema_key = format!("hooks.<event>.{name}");
+            let setting_key = format!("{key}.{name}");
+            match find(&schema_key) {
+                Some(entry) => problems.check(&setting_key, entry.key, value),
+                None => problems.unknown(
+                    &setting_key,
+                    SCHEMA.iter().filter(|e| e.key.starts_with("hooks.")),
+                ),
+            }
+        }
+    }
+}
+
+/// Checks between keys, each made only when the keys involved are well typed
+fn check_consistency(table: &Table, problems: &mut Problems) {
+    if let Some(Value::String(active)) = table.get("active_profile") {
+        let known = table
+            .get("profiles")
+            .and_then(Value::as_table)
+            .is_some_and(|profiles| profiles.contains_key(active));
+        if !known {
+            problems.report("active_profile", format!("unknown profile `{active}`"));
+        }
+    }
+
+    let bound = |key| table.get(key).and_then(|v| typed::<f64>(v).ok());
+    if let (Some(min), Some(max)) = (bound("mask_coverage_min"), bound("mask_coverage_max")) {
+        if min > max {
+            problems.report(
+                "mask_coverage_min",
+                format!("is above `mask_coverage_max` ({min} > {max})"),
+            );
+        }
+    }
+
+    if let Some(Value::String(dir)) = table.get("models_dir") {
+        if !dir.trim().is_empty() && !Path::new(dir).is_dir() {
+            problems.report("models_dir", format!("{dir} is not an existing directory"));
+        }
+    }
+}
+
+/// Problems found so far, and where in the file each key is
+struct Problems {
+    lines: HashMap<String, usize>,
+    found: Vec<ConfigProblem>,
+}
+
+impl Problems {
+    fn report(&mut self, key: &str, message: String) {
+        self.found.push(ConfigProblem {
+            line: self.line_of(key),
+            key: key.to_string(),
+            message,
+        });
+    }
+
+    /// Check `value` of `key` against the schema entry `schema_key`
+    fn check(&mut self, key: &str, schema_key: &str, value: &Value) {
+        let entry = find(schema_key).expect("checked keys are in the schema");
+        if let Err(err) = (entry.check)(value) {
+            self.report(key, format!("{err} (expected {})", entry.kind));
+        }
+    }
+
+    /// Report `key` as unknown, suggesting the closest of `candidates`
+    fn unknown<'a>(&mut self, key: &str, candidates: impl Iterator<Item = &'a ConfigKey>) {
+        let name = key.rsplit('.').next().unwrap_or(key);
+        let suggestion = candidates
+            .map(|entry| entry.key.rsplit('.').next().unwrap_or(entry.key))
+            .map(|candidate| (edit_distance(name, candidate), candidate))
+            .filter(|(distance, _)| *distance <= 2)
+            .min();
+        let message = match suggestion {
+            Some((_, candidate)) => format!("unknown key, did you mean `{candidate}`?"),
+            None => "unknown key".to_string(),
+        };
+        self.report(key, message);
+    }
+
+    /// The entries of the table `value`, reporting it when it isn't one
+    fn table<'a>(&mut self, key: &str, value: &'a Value) -> Vec<(&'a str, &'a Value)> {
+        match value {
+            Value::Table(table) => table.iter().map(|(k, v)| (k.as_str(), v)).collect(),
+            other => {
+                self.report(key, format!("expected a table, got a {}", other.type_str()));
+                Vec::new()
+            }
+        }
+    }
+
+    /// The line `key` is on, or else the nearest table holding it
+    fn line_of(&self, key: &str) -> Option<usize> {
+        let mut key = key;
+        loop {
+            if let Some(line) = self.lines.get(key) {
+                return Some(*line);
+            }
+            key = key.rsplit_once('.')?.0;
+        }
+    }
+}
+
+/// The line each key and table header of `text` is first on, by dotted path
+///
+/// A plain line scan rather than a parse, good enough for pointing at keys; the
+/// text is parsed properly by [`validate`].
+fn key_lines(text: &str) -> HashMap<String, usize> {
+    let mut lines = HashMap::new();
+    let mut table = String::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        let key = if let Some(header) = line.strip_prefix('[') {
+            let header = header.trim_start_matches('[');
+            let header = header.split(']').next().unwrap_or_default();
+            table = dotted(header);
+            table.clone()
+        } else {
+            match line.split_once('=') {
+                Some((key, _)) if !line.starts_with('#') => match table.as_str() {
+                    "" => dotted(key),
+                    table => format!("{table}.{}", dotted(key)),
+                },
+                _ => continue,
+            }
+        };
+        lines.entry(key).or_insert(index + 1);
+    }
+    lines
+}
+
+/// A TOML key such as `profiles . "fast"` as `profiles.fast`
+fn dotted(key: &str) -> String {
+    key.split('.')
+        .map(|part| part.trim().trim_matches(['"', '\'']))
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// The Levenshtein distance between `a` and `b`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substituted = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substituted.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::{Config, KNOWN_KEYS, Profile},
+        hooks::{Hook, Hooks},
+    };
+    use tempfile::tempdir;
+
+    /// The field names of a struct, failing to compile when the list is missing
+    /// one or names one it doesn't have
+    macro_rules! fields {
+        ($ty:ident { $($field:ident),* $(,)? }) => {{
+            let _ = |value: $ty| {
+                let $ty { $($field: _),* } = value;
+            };
+            [$(stringify!($field)),*]
+        }};
+    }
+
+    #[test]
+    fn test_schema_covers_every_config_field() {
+        let config = fields!(Config {
+            output_template,
+            history_enabled,
+            metrics_enabled,
+            thumbnails_enabled,
+            active_profile,
+            hf_endpoint,
+            hf_endpoint_fallbacks,
+            index_path_policy,
+            mask_coverage_min,
+            mask_coverage_max,
+            download_lock_ttl_secs,
+            download_rate_limit,
+            cache_max_bytes,
+            cache_max_age_days,
+            models_dir,
+            defaults,
+            profiles,
+            hooks,
+            log_levels,
+            extra,
+        });
+        // `defaults` is flattened into the top level; `extra` holds unknown keys
+        for field in config.iter().filter(|f| !["defaults", "extra"].contains(f)) {
+            assert!(
+                SCHEMA
+                    .iter()
+                    .any(|e| e.key.split('.').next() == Some(field)),
+                "{field}"
+            );
+        }
+
+        let profile = fields!(Profile {
+            default_model,
+            strength,
+            steps,
+            max_size,
+            output_format,
+        });
+        let profile_keys: Vec<_> = SCHEMA.iter().filter(|e| e.profile).map(|e| e.key).collect();
+        assert_eq!(profile_keys, profile);
+
+        let hooks = fields!(Hooks {
+            pre_generate,
+            post_generate
+        });
+        for event in hooks {
+            assert!(event.parse::<HookEvent>().is_ok(), "{event}");
+        }
+        for field in fields!(Hook {
+            command,
+            on_failure,
+            timeout_secs
+        }) {
+            assert!(find(&format!("hooks.<event>.{field}")).is_some(), "{field}");
+        }
+
+        for key in KNOWN_KEYS {
+            assert!(find(key).is_some(), "{key}");
+        }
+    }
+
+    #[test]
+    fn test_schema_defaults_pass_their_own_checks() {
+        for entry in SCHEMA {
+            let Some(default) = entry.default else {
+                continue;
+            };
+            let value = match entry.kind {
+                "boolean" => Value::Boolean(default.parse().unwrap()),
+                "integer" => Value::Integer(default.parse().unwrap()),
+                kind if kind.starts_with("number") => Value::Float(default.parse().unwrap()),
+                _ => Value::String(default.to_string()),
+            };
+            assert!((entry.check)(&value).is_ok(), "{}", entry.key);
+        }
+    }
+
+    #[test]
+    fn test_validate_accepts_a_good_config() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let text = format!(
+            r#"
+output_template = "{{stem}}/{{seed}}.{{ext}}"
+history_enabled = false
+strength = 1
+active_profile = "quality"
+models_dir = "{}"
+hf_endpoint_fallbacks = ["https://mirror.example.com"]
+mask_coverage_min = 0.1
+
+[profiles.quality]
+strength = 0.8
+steps = 50
+
+[hooks.post_generate]
+command = "upscale {{output}}"
+on_failure = "warn"
+
+[log_levels]
+"si::models" = "debug"
+"#,
+            temp_dir.path().display().to_string().replace('\\', "/")
+        );
+        assert_eq!(validate(&text), []);
+        toml::from_str::<Config>(&text)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_reports_every_problem() {
+        let text = r#"
+strenght = 0.5
+history_enabled = "yes"
+output_template = "{unknown}.png"
+mask_coverage_min = 0.9
+mask_coverage_max = 0.2
+download_rate_limit = "fast"
+active_profile = "missing"
+models_dir = "/definitely/not/a/dir"
+
+[profiles.fast]
+steps = -3
+colour = "red"
+
+[hooks.on_save]
+command = "true"
+
+[hooks.post_generate]
+on_failure = "retry"
+
+[log_levels]
+"si::models" = "loud"
+"#;
+        let problems = validate(text);
+        let found: Vec<_> = problems.iter().map(|p| (p.line, p.key.as_str())).collect();
+        assert_eq!(
+            found,
+            [
+                (Some(2), "strenght"),
+                (Some(3), "history_enabled"),
+                (Some(4), "output_template"),
+                (Some(5), "mask_coverage_min"),
+                (Some(7), "download_rate_limit"),
+                (Some(8), "active_profile"),
+                (Some(9), "models_dir"),
+                (Some(12), "profiles.fast.steps"),
+                (Some(13), "profiles.fast.colour"),
+                (Some(15), "hooks.on_save"),
+                (Some(18), "hooks.post_generate"),
+                (Some(19), "hooks.post_generate.on_failure"),
+                (Some(22), "log_levels.si::models"),
+            ]
+        );
+        assert_eq!(
+            problems[0].to_string(),
+            "line 2: `strenght`: unknown key, did you mean `strength`?"
+        );
+        assert!(
+            problems[1].message.contains("expected boolean"),
+            "{}",
+            problems[1]
+        );
+        assert!(
+            problems[5].message.contains("unknown profile"),
+            "{}",
+            problems[5]
+        );
+    }
+
+    #[test]
+    fn test_validate_reports_syntax_errors_with_their_line() {
+        let problems = validate("strength = 0.5\nsteps = = 3\n");
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].line, Some(2));
+        assert_eq!(problems[0].key, "");
+    }
+
+    #[test]
+    fn test_key_lines() {
+        let lines = key_lines("a = 1\n# b = 2\n[profiles . \"fast\"]\nsteps = 3\n[[x]]\ny.z = 1\n");
+        assert_eq!(lines.get("a"), Some(&1));
+        assert_eq!(lines.get("b"), None);
+        assert_eq!(lines.get("profiles.fast"), Some(&3));
+        assert_eq!(lines.get("profiles.fast.steps"), Some(&4));
+        assert_eq!(lines.get("x.y.z"), Some(&6));
+    }
+}
@@ -0,0 +1,310 @@
+//! Standalone image format conversion
+//!
+//! Mostly useful for preparing try-on inputs: shrinking huge photos and turning
+//! formats the pipeline can't read into ones it can.
+
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{BufWriter, Read},
+    path::Path,
+    str::FromStr,
+};
+
+use anyhow::{Context, Result, anyhow, bail};
+use image::{ImageFormat, imageops::FilterType};
+use log::debug;
+use serde::Serialize;
+
+use crate::formats;
+
+/// Target size for [`convert`]: `WxH`, or `Wx`/`xH` to scale by one side and keep
+/// the aspect ratio
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Resize {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+}
+
+impl Resize {
+    /// The size an image of `width`x`height` is resized to
+    pub fn dimensions(&self, width: u32, height: u32) -> (u32, u32) {
+        let scaled = |side: u32, target: u32, of: u32| {
+            ((f64::from(side) * f64::from(target) / f64::from(of)).round() as u32).max(1)
+        };
+        match (self.width, self.height) {
+            (Some(w), Some(h)) => (w, h),
+            (Some(w), None) => (w, scaled(height, w, width)),
+            (None, Some(h)) => (scaled(width, h, height), h),
+            (None, None) => (width, height),
+        }
+    }
+}
+
+impl FromStr for Resize {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (width, height) = s
+            .split_once('x')
+            .with_context(|| format!("Invalid size `{s}`, expected WxH, Wx or xH"))?;
+        let side = |value: &str| -> Result<Option<u32>> {
+            if value.is_empty() {
+                return Ok(None);
+            }
+            match value.parse::<u32>() {
+                Ok(0) | Err(_) => bail!("Invalid size `{s}`, sides must be positive integers"),
+                Ok(n) => Ok(Some(n)),
+            }
+        };
+        let resize = Self {
+            width: side(width)?,
+            height: side(height)?,
+        };
+        if resize.width.is_none() && resize.height.is_none() {
+            bail!("Invalid size `{s}`, give at least one side");
+        }
+        Ok(resize)
+    }
+}
+
+impl fmt::Display for Resize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(width) = self.width {
+            write!(f, "{width}")?;
+        }
+        f.write_str("x")?;
+        if let Some(height) = self.height {
+            write!(f, "{height}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ConvertOptions {
+    /// Quality, 1-100; only valid for JPEG, WebP and AVIF output
+    pub quality: Option<u8>,
+    pub resize: Option<Resize>,
+    /// Output format, instead of the one implied by the output extension
+    pub format: Option<ImageFormat>,
+    /// Drop EXIF and other metadata. The encoders never copy metadata over, so
+    /// this only makes the behaviour explicit.
+    pub strip_metadata: bool,
+}
+
+/// What [`convert`] did
+#[derive(Debug, Clone, Serialize)]
+pub struct ConvertReport {
+    #[serde(serialize_with = "formats::serialize_format")]
+    pub input_format: ImageFormat,
+    #[serde(serialize_with = "formats::serialize_format")]
+    pub output_format: ImageFormat,
+    pub input_dimensions: (u32, u32),
+    pub output_dimensions: (u32, u32),
+    pub input_bytes: u64,
+    pub output_bytes: u64,
+}
+
+/// Decode `input`, optionally resize it, and encode it to `output`
+pub fn convert(input: &Path, output: &Path, options: &ConvertOptions) -> Result<ConvertReport> {
+    let input_format = detect_format(input)?;
+    let output_format = match options.format {
+        Some(format) => format,
+        None => ImageFormat::from_path(output).map_err(|_| {
+            anyhow!(
+                "Can't tell the output format from {}; pass --format",
+                output.display()
+            )
+        })?,
+    };
+    formats::ensure_writable(output_format)?;
+    if options.quality.is_some() && !formats::supports_quality(output_format) {
+        bail!("Quality only applies to JPEG, WebP and AVIF output, not {output_format:?}");
+    }
+
+    let input_bytes = fs::metadata(input)
+        .with_context(|| format!("Failed to read {}", input.display()))?
+        .len();
+    let mut reader = image::io::Reader::open(input)
+        .with_context(|| format!("Failed to open {}", input.display()))?;
+    reader.set_format(input_format);
+    let mut img = reader.decode().map_err(|e| match e {
+        image::ImageError::Unsupported(_) => {
+            anyhow!("Unsupported input format {input_format:?}: {e}")
+        }
+        e => anyhow!(e).context(format!("Failed to decode {}", input.display())),
+    })?;
+    let input_dimensions = (img.width(), img.height());
+
+    if let Some(resize) = options.resize {
+        let (width, height) = resize.dimensions(img.width(), img.height());
+        debug!(
+            "Resizing {}x{} to {width}x{height}",
+            img.width(),
+            img.height()
+        );
+        img = img.resize_exact(width, height, FilterType::Lanczos3);
+    }
+    if let Some(parent) = output.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory {}", parent.display()))?;
+    }
+    let file =
+        File::create(output).with_context(|| format!("Failed to create {}", output.display()))?;
+    let mut writer = BufWriter::new(file);
+    formats::write_image(&img, &mut writer, output_format, options.quality)
+        .with_context(|| format!("Failed to write {}", output.display()))?;
+    drop(writer);
+
+    Ok(ConvertReport {
+        input_format,
+        output_format,
+        input_dimensions,
+        output_dimensions: (img.width(), img.height()),
+        input_bytes,
+        output_bytes: fs::metadata(output)?.len(),
+    })
+}
+
+/// The format of `path` according to its leading bytes
+fn detect_format(path: &Path) -> Result<ImageFormat> {
+    let mut header = Vec::with_capacity(32);
+    File::open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?
+        .take(32)
+        .read_to_end(&mut header)?;
+
+    image::guess_format(&header).map_err(|_| match heif_brand(&header) {
+        Some(brand) => anyhow!("Unsupported input format {brand} in {}", path.display()),
+        None => anyhow!("Unsupported input format in {}", path.display()),
+    })
+}
+
+/// Name of the HEIF-family format announced by an ISO media `ftyp` box
+fn heif_brand(header: &[u8]) -> Option<&'static str> {
+    if header.get(4..8) != Some(&b"ftyp"[..]) {
+        return None;
+    }
+    match header.get(8..12)? {
+        b"heic" | b"heix" | b"heim" | b"heis" | b"hevc" | b"hevx" => Some("HEIC"),
+        b"mif1" | b"msf1" => Some("HEIF"),
+        b"avif" | b"avis" => Some("AVIF"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+    use tempfile::tempdir;
+
+    /// A noisy image, so JPEG quality makes a visible difference in size
+    fn noisy(width: u32, height: u32) -> RgbImage {
+        RgbImage::from_fn(width, height, |x, y| {
+            let v = (x * 31 + y * 17) ^ (x * y);
+            Rgb([v as u8, (v >> 3) as u8, (v * 7) as u8])
+        })
+    }
+
+    #[test]
+    fn test_png_to_jpeg_with_quality() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let input = temp_dir.path().join("in.png");
+        noisy(128, 96).save(&input)?;
+
+        let low = temp_dir.path().join("low.jpg");
+        let report = convert(
+            &input,
+            &low,
+            &ConvertOptions {
+                quality: Some(20),
+                ..Default::default()
+            },
+        )?;
+        assert_eq!(report.input_format, ImageFormat::Png);
+        assert_eq!(report.output_format, ImageFormat::Jpeg);
+        assert_eq!(report.output_dimensions, (128, 96));
+        assert_eq!(report.output_bytes, fs::metadata(&low)?.len());
+        assert_eq!(detect_format(&low)?, ImageFormat::Jpeg);
+
+        let high = temp_dir.path().join("high.jpg");
+        let high_report = convert(
+            &input,
+            &high,
+            &ConvertOptions {
+                quality: Some(95),
+                ..Default::default()
+            },
+        )?;
+        assert!(high_report.output_bytes > report.output_bytes);
+
+        let err = convert(
+            &input,
+            &temp_dir.path().join("out.png"),
+            &ConvertOptions {
+                quality: Some(80),
+                ..Default::default()
+            },
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("only applies to JPEG"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_resize_math() -> Result<()> {
+        assert_eq!(
+            "1024x".parse::<Resize>()?.dimensions(2048, 1536),
+            (1024, 768)
+        );
+        assert_eq!("x300".parse::<Resize>()?.dimensions(400, 600), (200, 300));
+        assert_eq!("64x64".parse::<Resize>()?.dimensions(400, 600), (64, 64));
+        assert_eq!("1x".parse::<Resize>()?.dimensions(1000, 10), (1, 1));
+        assert_eq!("1024x".parse::<Resize>()?.to_string(), "1024x");
+        for bad in ["x", "1024", "0x10", "ax10", "10x-1"] {
+            assert!(bad.parse::<Resize>().is_err(), "{bad}");
+        }
+
+        let temp_dir = tempdir()?;
+        let input = temp_dir.path().join("in.png");
+        noisy(300, 200).save(&input)?;
+        // No known extension, so the format must be given
+        let output = temp_dir.path().join("small.out");
+        let report = convert(
+            &input,
+            &output,
+            &ConvertOptions {
+                resize: Some("150x".parse()?),
+                format: Some(ImageFormat::Png),
+                ..Default::default()
+            },
+        )?;
+        assert_eq!(report.output_dimensions, (150, 100));
+        let written = image::io::Reader::open(&output)?.with_guessed_format()?;
+        assert_eq!(written.format(), Some(ImageFormat::Png));
+        assert_eq!(written.into_dimensions()?, (150, 100));
+        Ok(())
+    }
+
+    #[test]
+    fn test_unsupported_input_format() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let heic = temp_dir.path().join("photo.heic");
+        let mut bytes = b"\0\0\0\x18ftypheic\0\0\0\0mif1heic".to_vec();
+        bytes.resize(64, 0);
+        fs::write(&heic, bytes)?;
+
+        let output = temp_dir.path().join("photo.jpg");
+        let err = convert(&heic, &output, &ConvertOptions::default()).unwrap_err();
+        assert!(err.to_string().contains("Unsupported input format HEIC"));
+        assert!(!output.exists());
+
+        let text = temp_dir.path().join("notes.png");
+        fs::write(&text, "not an image")?;
+        let err = convert(&text, &output, &ConvertOptions::default()).unwrap_err();
+        assert!(err.to_string().contains("Unsupported input format"));
+        Ok(())
+    }
+}
@@ -0,0 +1,317 @@
+//! What a sync changes in the model index, model by model
+//!
+//! [`IndexDiff::between`] compares the index before and after a sync and lists
+//! each model that was added, had files added or removed, or is indexed but gone
+//! from the cache. Its `Display` is a compact `+`/`~`/`-` report, noting what
+//! to keep in mind about entries from sources such as sync:
+//!
+//! ```text
+//! + org/new (3 files, 1.2 GB; reconstructed by sync, sizes may be incomplete)
+//! ~ org/updated (2 -> 3 files, 600 MB -> 1.2 GB)
+//!     + unet/model.safetensors
+//! - org/gone (2 files, 40 MB would be lost, missing locally)
+//! ```
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt,
+};
+
+use serde::Serialize;
+
+use crate::{
+    format::format_size,
+    models::{IndexSource, ModelInfo},
+};
+
+/// How one model's index entry changed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ModelChange {
+    /// Found in the cache and added to the index
+    Added,
+    /// Files were added to or removed from the model's entry
+    Updated,
+    /// Indexed but gone from the cache; the entry is kept, this shows what's lost
+    MissingLocally,
+}
+
+/// File count and total size of an index entry
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct EntrySummary {
+    pub files: usize,
+    pub size: u64,
+}
+
+impl From<&ModelInfo> for EntrySummary {
+    fn from(model: &ModelInfo) -> Self {
+        Self {
+            files: model.file_count(),
+            size: model.total_size(),
+        }
+    }
+}
+
+impl fmt::Display for EntrySummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} files, {}", self.files, format_size(self.size))
+    }
+}
+
+/// One model's entry before and after the sync
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ModelDiff {
+    pub model_id: String,
+    pub change: ModelChange,
+    /// `None` for added models
+    pub before: Option<EntrySummary>,
+    /// `None` for models missing locally
+    pub after: Option<EntrySummary>,
+    /// Names of files that are new in the entry, sorted
+    pub files_added: Vec<String>,
+    /// Names of files that left the entry, or would be lost, sorted
+    pub files_removed: Vec<String>,
+    /// What wrote the entry, as of after the sync
+    pub source: IndexSource,
+}
+
+/// Changes to the model index, sorted by model id
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct IndexDiff {
+    pub models: Vec<ModelDiff>,
+}
+
+impl IndexDiff {
+    /// Compare two index snapshots, treating `missing_locally` as entries that
+    /// are still indexed but whose files are gone
+    pub fn between(before: &[ModelInfo], after: &[ModelInfo], missing_locally: &[String]) -> Self {
+        let before_by_id: HashMap<&str, &ModelInfo> =
+            before.iter().map(|m| (m.model_id.as_str(), m)).collect();
+        let mut models = Vec::new();
+
+        for model in after {
+            let Some(old) = before_by_id.get(model.model_id.as_str()) else {
+                models.push(ModelDiff {
+                    model_id: model.model_id.clone(),
+                    change: ModelChange::Added,
+                    before: None,
+                    after: Some(model.into()),
+                    files_added: file_names(model).into_iter().collect(),
+                    files_removed: Vec::new(),
+                    source: model.source.clone(),
+                });
+                continue;
+            };
+            let (old_names, new_names) = (file_names(old), file_names(model));
+            let (old_summary, new_summary) = (EntrySummary::from(*old), EntrySummary::from(model));
+            if old_names == new_names && old_summary == new_summary {
+                continue;
+            }
+            models.push(ModelDiff {
+                model_id: model.model_id.clone(),
+                change: ModelChange::Updated,
+                before: Some(old_summary),
+                after: Some(new_summary),
+                files_added: new_names.difference(&old_names).cloned().collect(),
+                files_removed: old_names.difference(&new_names).cloned().collect(),
+                source: model.source.clone(),
+            });
+        }
+
+        for model_id in missing_locally {
+            let Some(old) = before_by_id.get(model_id.as_str()) else {
+                continue;
+            };
+            models.push(ModelDiff {
+                model_id: model_id.clone(),
+                change: ModelChange::MissingLocally,
+                before: Some((*old).into()),
+                after: None,
+                files_added: Vec::new(),
+                files_removed: file_names(old).into_iter().collect(),
+                source: old.source.clone(),
+            });
+        }
+
+        models.sort_by(|a, b| a.model_id.cmp(&b.model_id));
+        Self { models }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.models.is_empty()
+    }
+}
+
+fn file_names(model: &ModelInfo) -> BTreeSet<String> {
+    model.files.iter().map(|file| file.to_string()).collect()
+}
+
+/// One line per model; updated models also list their added and removed files
+impl fmt::Display for IndexDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return f.write_str("No index changes.");
+        }
+        for (i, model) in self.models.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            let id = &model.model_id;
+            let before = model.before.unwrap_or_default();
+            let after = model.after.unwrap_or_default();
+            let caveat = model
+                .source
+                .caveat()
+                .map(|caveat| format!("; {caveat}"))
+                .unwrap_or_default();
+            match model.change {
+                ModelChange::Added => write!(f, "+ {id} ({after}{caveat})")?,
+                ModelChange::Updated => {
+                    write!(
+                        f,
+                        "~ {id} ({} -> {} files, {} -> {}{caveat})",
+                        before.files,
+                        after.files,
+                        format_size(before.size),
+                        format_size(after.size)
+                    )?;
+                    for name in &model.files_added {
+                        write!(f, "\n    + {name}")?;
+                    }
+                    for name in &model.files_removed {
+                        write!(f, "\n    - {name}")?;
+                    }
+                }
+                ModelChange::MissingLocally => write!(
+                    f,
+                    "- {id} ({} files, {} would be lost, missing locally)",
+                    before.files,
+                    format_size(before.size)
+                )?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MockSource, ModelManagerBuilder, models::ModelFile};
+    use anyhow::Result;
+    use std::{fs, path::Path};
+    use tempfile::tempdir;
+
+    fn snapshot_dir(cache: &Path, model_id: &str) -> std::path::PathBuf {
+        let repo = cache.join(format!("models--{}", model_id.replace('/', "--")));
+        fs::read_dir(repo.join("snapshots"))
+            .unwrap()
+            .flatten()
+            .next()
+            .unwrap()
+            .path()
+    }
+
+    #[tokio::test]
+    async fn test_sync_diff_add_update_and_missing() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let root = temp_dir.path();
+        let (fixtures, cache) = (root.join("fixtures"), root.join("cache"));
+        for (model_id, files) in [
+            ("org/same", &["model.bin"][..]),
+            ("org/changed", &["model.bin", "old.bin"][..]),
+            ("org/gone", &["model.bin"][..]),
+        ] {
+            for file in files {
+                fs::create_dir_all(fixtures.join(model_id))?;
+                fs::write(fixtures.join(model_id).join(file), "1234")?;
+            }
+        }
+        let manager = ModelManagerBuilder::new()
+            .with_models_dir(root.join("models"))
+            .with_source(Box::new(MockSource::new(&fixtures, &cache)))
+            .build()?;
+        for model_id in ["org/same", "org/changed", "org/gone"] {
+            manager.download_model(model_id).await?;
+        }
+
+        // A model only in the cache, one that gained and lost a file, one deleted
+        let new_snapshot = cache.join("models--org--new/snapshots/abc");
+        fs::create_dir_all(&new_snapshot)?;
+        fs::write(new_snapshot.join("config.json"), "{}")?;
+        fs::create_dir_all(cache.join("models--org--new/refs"))?;
+        fs::write(cache.join("models--org--new/refs/main"), "abc")?;
+        let changed = snapshot_dir(&cache, "org/changed");
+        fs::remove_file(changed.join("old.bin"))?;
+        fs::create_dir_all(changed.join("unet"))?;
+        fs::write(changed.join("unet/model.safetensors"), "12345678")?;
+        fs::remove_dir_all(cache.join("models--org--gone"))?;
+
+        let result = manager.sync_models(true).await?;
+        let diff = result.diff();
+        assert_eq!(
+            diff.to_string(),
+            "~ org/changed (2 -> 2 files, 8 B -> 12 B)\n    \
+             + unet/model.safetensors\n    \
+             - old.bin\n\
+             - org/gone (1 files, 4 B would be lost, missing locally)\n\
+             + org/new (1 files, 2 B; reconstructed by sync, sizes may be incomplete)"
+        );
+        let changes: Vec<_> = diff
+            .models
+            .iter()
+            .map(|m| (m.model_id.as_str(), m.change))
+            .collect();
+        assert_eq!(
+            changes,
+            [
+                ("org/changed", ModelChange::Updated),
+                ("org/gone", ModelChange::MissingLocally),
+                ("org/new", ModelChange::Added),
+            ]
+        );
+        assert_eq!(
+            diff.models[1].before,
+            Some(EntrySummary { files: 1, size: 4 })
+        );
+        assert_eq!(diff.models[1].after, None);
+        assert_eq!(
+            diff.models[0].source,
+            IndexSource::Download {
+                revision: Some(crate::source::MOCK_REVISION.to_string())
+            }
+        );
+        assert_eq!(diff.models[2].source, IndexSource::Sync);
+        // A dry run leaves the index alone
+        let indexed = manager.get_model("org/changed")?.unwrap();
+        assert!(indexed.files.iter().any(|f| f.to_string() == "old.bin"));
+
+        let result = manager.sync_models(false).await?;
+        assert_eq!(result.diff(), diff);
+        let json = serde_json::to_value(&result)?;
+        assert_eq!(json["diff"]["models"][0]["change"], "updated");
+        let indexed = manager.get_model("org/changed")?.unwrap();
+        let names: Vec<_> = indexed.files.iter().map(ModelFile::to_string).collect();
+        assert_eq!(names, ["model.bin", "unet/model.safetensors"]);
+        assert!(
+            indexed.files[0].sha256.is_some(),
+            "kept file keeps its hash"
+        );
+
+        // Nothing left to change but the model that's still missing
+        let result = manager.sync_models(false).await?;
+        assert_eq!(
+            result.diff().to_string(),
+            "- org/gone (1 files, 4 B would be lost, missing locally)"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_empty_diff() {
+        assert_eq!(IndexDiff::default().to_string(), "No index changes.");
+        let model = ModelInfo::new("org/a", vec![]);
+        let same = std::slice::from_ref(&model);
+        assert!(IndexDiff::between(same, same, &[]).is_empty());
+    }
+}
@@ -0,0 +1,295 @@
+//! Reading the configuration of a model stored in the diffusers layout
+//!
+//! A diffusers repository has a top-level `model_index.json` naming the
+//! pipeline class and, for each component, the library and class that load the
+//! subdirectory of the same name. The scheduler has no weights, only its
+//! config, which says how the model was trained to be sampled. Repos holding a
+//! single checkpoint file have neither, which [`read`] reports as
+//! [`DiffusersConfig::NotDiffusersLayout`] rather than as an error.
+
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::{Map, Value};
+
+/// The pipeline description at the top of a diffusers repo
+pub const MODEL_INDEX: &str = "model_index.json";
+
+/// Where the scheduler config is looked for, in order; diffusers writes the
+/// first, some conversions the second
+const SCHEDULER_CONFIGS: [&str; 2] = ["scheduler/scheduler_config.json", "scheduler/config.json"];
+
+/// The parsed `model_index.json`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PipelineIndex {
+    /// e.g. `StableDiffusionPipeline`
+    #[serde(rename = "_class_name")]
+    pub class_name: String,
+    #[serde(
+        rename = "_diffusers_version",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub diffusers_version: Option<String>,
+    /// The components and any pipeline flags such as `requires_safety_checker`
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl PipelineIndex {
+    /// The `"name": [library, class]` entries, sorted by name
+    pub fn components(&self) -> Vec<Component> {
+        let mut components: Vec<_> = self
+            .extra
+            .iter()
+            .filter_map(|(name, value)| match value.as_array()?.as_slice() {
+                [library, class_name] => Some(Component {
+                    name: name.clone(),
+                    library: library.as_str().map(str::to_string),
+                    class_name: class_name.as_str().map(str::to_string),
+                }),
+                _ => None,
+            })
+            .collect();
+        components.sort_by(|a, b| a.name.cmp(&b.name));
+        components
+    }
+}
+
+/// One component of a pipeline, e.g. `unet` loaded by diffusers'
+/// `UNet2DConditionModel`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Component {
+    pub name: String,
+    /// None, like `class_name`, for an optional component the repo leaves out
+    pub library: Option<String>,
+    pub class_name: Option<String>,
+}
+
+impl Component {
+    /// Whether the repo ships this component
+    pub fn is_present(&self) -> bool {
+        self.class_name.is_some()
+    }
+}
+
+/// The parsed scheduler config; only the fields si looks at are typed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SchedulerConfig {
+    /// e.g. `PNDMScheduler`
+    #[serde(rename = "_class_name")]
+    pub class_name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub num_train_timesteps: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub beta_start: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub beta_end: Option<f64>,
+    /// e.g. `scaled_linear`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub beta_schedule: Option<String>,
+    /// `epsilon` (the diffusers default), `v_prediction` or `sample`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prediction_type: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// What [`read`] found in a diffusers repo
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PipelineConfig {
+    /// The directory holding `model_index.json`
+    pub root: PathBuf,
+    pub index: PipelineIndex,
+    /// None when the repo has no scheduler config
+    pub scheduler: Option<SchedulerConfig>,
+}
+
+/// The configuration of a model, or the lack of one
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "layout", rename_all = "snake_case")]
+pub enum DiffusersConfig {
+    Pipeline(Box<PipelineConfig>),
+    /// No `model_index.json`, e.g. a single `.safetensors` checkpoint
+    NotDiffusersLayout,
+}
+
+impl fmt::Display for DiffusersConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let config = match self {
+            Self::Pipeline(config) => config,
+            Self::NotDiffusersLayout => {
+                return f.write_str("Not a diffusers pipeline (no model_index.json)");
+            }
+        };
+        write!(f, "Pipeline:  {}", config.index.class_name)?;
+        if let Some(version) = &config.index.diffusers_version {
+            write!(f, " (diffusers {version})")?;
+        }
+        let components = config.index.components();
+        let name_width = components.iter().map(|c| c.name.len()).max().unwrap_or(0);
+        for component in &components {
+            write!(f, "\n  {:name_width$}  ", component.name)?;
+            match (&component.library, &component.class_name) {
+                (Some(library), Some(class_name)) => write!(f, "{library}.{class_name}")?,
+                (None, Some(class_name)) => f.write_str(class_name)?,
+                _ => f.write_str("(not included)")?,
+            }
+        }
+        match &config.scheduler {
+            Some(scheduler) => {
+                write!(f, "\nScheduler: {}", scheduler.class_name)?;
+                let mut details = Vec::new();
+                if let Some(steps) = scheduler.num_train_timesteps {
+                    details.push(format!("{steps} training steps"));
+                }
+                if let (Some(start), Some(end)) = (scheduler.beta_start, scheduler.beta_end) {
+                    let schedule = scheduler.beta_schedule.as_deref().unwrap_or("linear");
+                    details.push(format!("{schedule} betas {start}-{end}"));
+                }
+                if let Some(prediction) = &scheduler.prediction_type {
+                    details.push(format!("{prediction} prediction"));
+                }
+                if !details.is_empty() {
+                    write!(f, " ({})", details.join(", "))?;
+                }
+            }
+            None => f.write_str("\nScheduler: no config found")?,
+        }
+        Ok(())
+    }
+}
+
+/// Read the pipeline and scheduler config of the diffusers repo at `root`
+pub fn read(root: &Path) -> Result<DiffusersConfig> {
+    let index_path = root.join(MODEL_INDEX);
+    if !index_path.is_file() {
+        return Ok(DiffusersConfig::NotDiffusersLayout);
+    }
+    let index = read_json(&index_path)?;
+    let scheduler = SCHEDULER_CONFIGS
+        .iter()
+        .map(|name| root.join(name))
+        .find(|path| path.is_file())
+        .map(|path| read_json(&path))
+        .transpose()?;
+    Ok(DiffusersConfig::Pipeline(Box::new(PipelineConfig {
+        root: root.to_path_buf(),
+        index,
+        scheduler,
+    })))
+}
+
+fn read_json<T: DeserializeOwned>(path: &Path) -> Result<T> {
+    let text =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    serde_json::from_str(&text).with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/diffusers")
+            .join(name)
+    }
+
+    fn pipeline(config: DiffusersConfig) -> PipelineConfig {
+        match config {
+            DiffusersConfig::Pipeline(config) => *config,
+            DiffusersConfig::NotDiffusersLayout => panic!("expected a diffusers layout"),
+        }
+    }
+
+    fn component_names(config: &PipelineConfig) -> Vec<String> {
+        config
+            .index
+            .components()
+            .into_iter()
+            .filter(Component::is_present)
+            .map(|c| c.name)
+            .collect()
+    }
+
+    #[test]
+    fn test_reads_sd15_layout() -> Result<()> {
+        let config = pipeline(read(&fixture("sd15"))?);
+        assert_eq!(config.index.class_name, "StableDiffusionPipeline");
+        assert_eq!(config.index.diffusers_version.as_deref(), Some("0.6.0"));
+        assert_eq!(
+            component_names(&config),
+            [
+                "feature_extractor",
+                "safety_checker",
+                "scheduler",
+                "text_encoder",
+                "tokenizer",
+                "unet",
+                "vae"
+            ]
+        );
+
+        let scheduler = config.scheduler.expect("scheduler config");
+        assert_eq!(scheduler.class_name, "PNDMScheduler");
+        assert_eq!(scheduler.num_train_timesteps, Some(1000));
+        assert_eq!(scheduler.beta_schedule.as_deref(), Some("scaled_linear"));
+        assert_eq!(scheduler.prediction_type, None);
+        assert_eq!(scheduler.extra["skip_prk_steps"], Value::Bool(true));
+        Ok(())
+    }
+
+    #[test]
+    fn test_reads_sdxl_layout() -> Result<()> {
+        let config = pipeline(read(&fixture("sdxl"))?);
+        assert_eq!(config.index.class_name, "StableDiffusionXLPipeline");
+        let names = component_names(&config);
+        assert!(names.iter().any(|n| n == "text_encoder_2"));
+        assert!(!names.iter().any(|n| n == "image_encoder"));
+        // Flags aren't components
+        assert!(!names.iter().any(|n| n == "force_zeros_for_empty_prompt"));
+        assert_eq!(
+            config.index.extra["force_zeros_for_empty_prompt"],
+            Value::Bool(true)
+        );
+
+        let scheduler = config.scheduler.as_ref().expect("scheduler config");
+        assert_eq!(scheduler.class_name, "EulerDiscreteScheduler");
+        assert_eq!(scheduler.prediction_type.as_deref(), Some("epsilon"));
+
+        let text = DiffusersConfig::Pipeline(Box::new(config)).to_string();
+        assert!(text.contains("StableDiffusionXLPipeline (diffusers 0.19.0.dev0)"));
+        assert!(text.contains("transformers.CLIPTextModelWithProjection"));
+        assert!(text.contains("image_encoder"));
+        assert!(text.contains("(not included)"));
+        assert!(text.contains("EulerDiscreteScheduler (1000 training steps"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_single_checkpoint_is_not_an_error() -> Result<()> {
+        let temp_dir = tempdir()?;
+        fs::write(temp_dir.path().join("model.safetensors"), b"")?;
+        assert_eq!(read(temp_dir.path())?, DiffusersConfig::NotDiffusersLayout);
+
+        // A pipeline without a scheduler config still reads
+        fs::write(
+            temp_dir.path().join(MODEL_INDEX),
+            r#"{"_class_name": "FluxPipeline"}"#,
+        )?;
+        let config = pipeline(read(temp_dir.path())?);
+        assert_eq!(config.scheduler, None);
+        assert!(config.index.components().is_empty());
+
+        fs::write(temp_dir.path().join(MODEL_INDEX), "{")?;
+        let err = read(temp_dir.path()).unwrap_err();
+        assert!(format!("{err:#}").contains("Failed to parse"));
+        Ok(())
+    }
+}
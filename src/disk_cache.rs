@@ -0,0 +1,311 @@
+//! Files computed from other files, kept between runs
+//!
+//! A [`DiskCache`] stores one file per key in its own directory, named by the
+//! SHA-256 of the key serialized as JSON, so any change to the key (a different
+//! input, option or algorithm version) is a miss rather than a stale hit. Reads
+//! touch the file's modification time and writes evict the least recently used
+//! files once the directory outgrows its size cap.
+
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{self, Write},
+    marker::PhantomData,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::SystemTime,
+};
+
+use anyhow::{Context, Result, bail};
+use log::{debug, warn};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::paths;
+
+/// The caches si keeps under [`paths::cache_dir`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheKind {
+    /// Clothing masks, keyed by input image and mask options
+    Masks,
+    /// SHA-256 of the files of models used from local paths, keyed by path,
+    /// size and modification time
+    Hashes,
+}
+
+impl CacheKind {
+    /// The directory holding this cache
+    pub fn dir(self) -> Result<PathBuf> {
+        Ok(paths::cache_dir()?.join(self.to_string()))
+    }
+}
+
+impl FromStr for CacheKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "masks" => Ok(Self::Masks),
+            "hashes" => Ok(Self::Hashes),
+            other => bail!("Unknown cache `{other}`, expected masks or hashes"),
+        }
+    }
+}
+
+impl fmt::Display for CacheKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Masks => "masks",
+            Self::Hashes => "hashes",
+        })
+    }
+}
+
+/// Number and total size of the files in a cache
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct CacheUsage {
+    pub entries: usize,
+    pub bytes: u64,
+}
+
+/// A directory of files addressed by keys of type `K`
+#[derive(Debug, Clone)]
+pub struct DiskCache<K> {
+    dir: PathBuf,
+    extension: &'static str,
+    max_bytes: Option<u64>,
+    key: PhantomData<fn(&K)>,
+}
+
+impl<K: Serialize> DiskCache<K> {
+    /// A cache in `dir` whose files end in `.{extension}`; the directory is
+    /// created on the first write
+    pub fn new(dir: impl Into<PathBuf>, extension: &'static str) -> Self {
+        Self {
+            dir: dir.into(),
+            extension,
+            max_bytes: None,
+            key: PhantomData,
+        }
+    }
+
+    /// Evict the least recently used files once the cache outgrows `max_bytes`
+    pub fn with_max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The file `key` is stored in, whether or not it exists
+    pub fn path(&self, key: &K) -> Result<PathBuf> {
+        let json = serde_json::to_vec(key).context("Failed to serialize the cache key")?;
+        let digest = hex(&Sha256::digest(&json));
+        Ok(self.dir.join(format!("{digest}.{}", self.extension)))
+    }
+
+    /// The contents stored for `key`, marking them as recently used
+    pub fn get(&self, key: &K) -> Result<Option<Vec<u8>>> {
+        let path = self.path(key)?;
+        let bytes = match fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read {}", path.display()));
+            }
+        };
+        // Only matters for eviction order, so failing to touch is fine
+        if let Err(e) = File::options()
+            .append(true)
+            .open(&path)
+            .and_then(|file| file.set_modified(SystemTime::now()))
+        {
+            debug!("Failed to touch {}: {e}", path.display());
+        }
+        Ok(Some(bytes))
+    }
+
+    /// Store `bytes` for `key`, then evict old entries if over the size cap
+    ///
+    /// Written to a temporary file and renamed into place, so concurrent readers
+    /// never see a partial entry.
+    pub fn put(&self, key: &K, bytes: &[u8]) -> Result<()> {
+        let path = self.path(key)?;
+        fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Failed to create {}", self.dir.display()))?;
+        let partial =
+            path.with_extension(format!("{}.{}.partial", self.extension, std::process::id()));
+        File::create(&partial)
+            .and_then(|mut file| file.write_all(bytes))
+            .and_then(|()| fs::rename(&partial, &path))
+            .inspect_err(|_| {
+                let _ = fs::remove_file(&partial);
+            })
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        if let Some(max_bytes) = self.max_bytes {
+            self.evict(max_bytes, &path)?;
+        }
+        Ok(())
+    }
+
+    /// Remove every entry
+    pub fn clear(&self) -> Result<CacheUsage> {
+        let mut removed = CacheUsage::default();
+        for (path, bytes, _) in self.entries()? {
+            fs::remove_file(&path)
+                .with_context(|| format!("Failed to remove {}", path.display()))?;
+            removed.entries += 1;
+            removed.bytes += bytes;
+        }
+        Ok(removed)
+    }
+
+    pub fn usage(&self) -> Result<CacheUsage> {
+        let entries = self.entries()?;
+        Ok(CacheUsage {
+            entries: entries.len(),
+            bytes: entries.iter().map(|(_, bytes, _)| bytes).sum(),
+        })
+    }
+
+    /// Remove the least recently used entries other than `keep` until the cache
+    /// fits in `max_bytes`
+    fn evict(&self, max_bytes: u64, keep: &Path) -> Result<()> {
+        let mut entries = self.entries()?;
+        let mut total: u64 = entries.iter().map(|(_, bytes, _)| bytes).sum();
+        entries.sort_by_key(|(_, _, modified)| *modified);
+        for (path, bytes, _) in entries {
+            if total <= max_bytes {
+                break;
+            }
+            if path == keep {
+                continue;
+            }
+            match fs::remove_file(&path) {
+                Ok(()) => {
+                    debug!("Evicted {} from the cache", path.display());
+                    total -= bytes;
+                }
+                Err(e) => warn!("Failed to evict {}: {e}", path.display()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Path, size and modification time of every entry
+    fn entries(&self) -> Result<Vec<(PathBuf, u64, SystemTime)>> {
+        let read_dir = match fs::read_dir(&self.dir) {
+            Ok(read_dir) => read_dir,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to list {}", self.dir.display()));
+            }
+        };
+        let mut entries = Vec::new();
+        for entry in read_dir {
+            let path = entry?.path();
+            if path.extension().is_none_or(|e| e != self.extension) {
+                continue;
+            }
+            // Another process may have evicted it since the listing
+            let Ok(metadata) = fs::metadata(&path) else {
+                continue;
+            };
+            let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            entries.push((path, metadata.len(), modified));
+        }
+        Ok(entries)
+    }
+}
+
+/// `bytes` as lowercase hex
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tempfile::tempdir;
+
+    #[derive(Serialize)]
+    struct Key<'a> {
+        input: &'a str,
+        version: u32,
+    }
+
+    fn key(input: &str) -> Key<'_> {
+        Key { input, version: 1 }
+    }
+
+    #[test]
+    fn test_get_put_clear() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let cache = DiskCache::new(temp_dir.path().join("cache"), "bin");
+        assert_eq!(cache.get(&key("a"))?, None);
+        assert_eq!(cache.usage()?, CacheUsage::default());
+
+        cache.put(&key("a"), b"first")?;
+        assert_eq!(cache.get(&key("a"))?.as_deref(), Some(&b"first"[..]));
+        // Any field of the key tells entries apart
+        assert_eq!(
+            cache.get(&Key {
+                input: "a",
+                version: 2
+            })?,
+            None
+        );
+        cache.put(&key("a"), b"second")?;
+        assert_eq!(cache.get(&key("a"))?.as_deref(), Some(&b"second"[..]));
+        cache.put(&key("b"), b"other")?;
+
+        assert_eq!(
+            cache.clear()?,
+            CacheUsage {
+                entries: 2,
+                bytes: 11
+            }
+        );
+        assert_eq!(cache.get(&key("a"))?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_size_cap_evicts_least_recently_used() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let cache = DiskCache::new(temp_dir.path(), "bin").with_max_bytes(25);
+        let backdate = |name: &'static str, secs: u64| -> Result<()> {
+            let file = File::options().append(true).open(cache.path(&key(name))?)?;
+            file.set_modified(SystemTime::now() - Duration::from_secs(secs))?;
+            Ok(())
+        };
+        cache.put(&key("old"), &[0; 10])?;
+        backdate("old", 300)?;
+        cache.put(&key("used"), &[0; 10])?;
+        backdate("used", 200)?;
+        // Reading marks it as recently used
+        cache.get(&key("used"))?;
+
+        cache.put(&key("new"), &[0; 10])?;
+        assert_eq!(cache.get(&key("old"))?, None);
+        assert!(cache.get(&key("used"))?.is_some());
+        assert!(cache.get(&key("new"))?.is_some());
+        assert_eq!(cache.usage()?.bytes, 20);
+
+        // An entry bigger than the cap is still kept until the next write
+        cache.put(&key("huge"), &[0; 40])?;
+        assert_eq!(cache.usage()?.entries, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_cache_kind_round_trips() -> Result<()> {
+        assert_eq!("masks".parse::<CacheKind>()?, CacheKind::Masks);
+        assert_eq!(CacheKind::Masks.to_string(), "masks");
+        assert!("thumbnails".parse::<CacheKind>().is_err());
+        Ok(())
+    }
+}
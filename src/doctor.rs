@@ -0,0 +1,309 @@
+//! Checks of the directories and credentials si depends on
+//!
+//! [`run_checks`] looks at the models directory, the Hugging Face cache and the
+//! Hub token, [`check_index`] at the model index, [`check_weights`] at the
+//! formats of the indexed weights and [`check_download_queue`] at downloads
+//! queued for later. Nothing here changes
+//! anything: a missing directory is reported as one that will be created, and a
+//! failed check is for the caller to print.
+
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+
+use crate::{ModelManager, paths};
+
+/// Environment variable the Hub client reads a token from
+pub const HF_TOKEN_ENV: &str = "HF_TOKEN";
+
+/// How a [`Check`] went
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Ok,
+    /// Works, but something will be missing, e.g. gated models
+    Warn,
+    /// si can't work this way
+    Fail,
+}
+
+impl fmt::Display for CheckStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Ok => "ok",
+            Self::Warn => "warn",
+            Self::Fail => "FAIL",
+        })
+    }
+}
+
+/// The outcome of one check
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    pub detail: String,
+}
+
+impl Check {
+    fn new(name: &'static str, status: CheckStatus, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status,
+            detail: detail.into(),
+        }
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}] {}: {}", self.status, self.name, self.detail)
+    }
+}
+
+/// Check the models directory, the Hub cache and the Hub token
+pub fn run_checks(models_dir: &Path, hf_cache_dir: &Path) -> Vec<Check> {
+    let token = std::env::var(HF_TOKEN_ENV).ok();
+    vec![
+        check_dir("models directory", models_dir),
+        check_dir("Hugging Face cache", hf_cache_dir),
+        check_token(token.as_deref(), &paths::hf_token_path()),
+    ]
+}
+
+/// Whether `dir` can hold si's files: writable if it exists, else creatable
+pub fn check_dir(name: &'static str, dir: &Path) -> Check {
+    if !dir.exists() {
+        return match existing_ancestor(dir) {
+            Some(ancestor) if ancestor.is_dir() => Check::new(
+                name,
+                CheckStatus::Ok,
+                format!("{} (created when needed)", dir.display()),
+            ),
+            _ => Check::new(
+                name,
+                CheckStatus::Fail,
+                format!("{} can't be created", dir.display()),
+            ),
+        };
+    }
+    if !dir.is_dir() {
+        return Check::new(
+            name,
+            CheckStatus::Fail,
+            format!("{} is not a directory", dir.display()),
+        );
+    }
+    let probe = dir.join(".si-write-check");
+    match fs::write(&probe, b"") {
+        Ok(()) => {
+            let _ = fs::remove_file(&probe);
+            Check::new(name, CheckStatus::Ok, dir.display().to_string())
+        }
+        Err(e) => Check::new(
+            name,
+            CheckStatus::Fail,
+            format!("{} is not writable: {e}", dir.display()),
+        ),
+    }
+}
+
+fn existing_ancestor(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .skip(1)
+        .find(|a| a.as_os_str().is_empty() || a.exists())
+        .map(|a| {
+            if a.as_os_str().is_empty() {
+                PathBuf::from(".")
+            } else {
+                a.to_path_buf()
+            }
+        })
+}
+
+/// Whether a Hub token is available from `env` (`HF_TOKEN`) or `token_path`
+pub fn check_token(env: Option<&str>, token_path: &Path) -> Check {
+    const NAME: &str = "Hugging Face token";
+    if env.is_some_and(|t| !t.trim().is_empty()) {
+        return Check::new(NAME, CheckStatus::Ok, format!("from {HF_TOKEN_ENV}"));
+    }
+    match fs::read_to_string(token_path) {
+        Ok(token) if !token.trim().is_empty() => Check::new(
+            NAME,
+            CheckStatus::Ok,
+            format!("from {}", token_path.display()),
+        ),
+        _ => Check::new(
+            NAME,
+            CheckStatus::Warn,
+            format!(
+                "none found; public models download fine, gated ones need \
+                 `huggingface-cli login` or {HF_TOKEN_ENV}"
+            ),
+        ),
+    }
+}
+
+/// Whether the model index parses and is free of the duplicates
+/// `si model repair` cleans up
+pub fn check_index(manager: &ModelManager) -> Check {
+    const NAME: &str = "model index";
+    match manager.repair_index_with(true) {
+        Ok(report) if report.is_clean() => Check::new(NAME, CheckStatus::Ok, "no duplicates"),
+        Ok(report) => Check::new(
+            NAME,
+            CheckStatus::Warn,
+            format!("{}; run `si model repair` to clean it up", report.summary()),
+        ),
+        Err(e) => Check::new(NAME, CheckStatus::Fail, format!("{e:#}")),
+    }
+}
+
+/// Whether any indexed model has only pickle weights, which generation refuses
+pub fn check_weights(manager: &ModelManager) -> Check {
+    const NAME: &str = "model weights";
+    match manager.conversion_candidates() {
+        Ok(models) if models.is_empty() => {
+            Check::new(NAME, CheckStatus::Ok, "no pickle-only models")
+        }
+        Ok(models) => {
+            let ids: Vec<&str> = models.iter().map(|m| m.model_id.as_str()).collect();
+            Check::new(
+                NAME,
+                CheckStatus::Warn,
+                format!(
+                    "only pickle weights in {}; convert them to .safetensors to generate with them",
+                    ids.join(", ")
+                ),
+            )
+        }
+        Err(e) => Check::new(NAME, CheckStatus::Fail, format!("{e:#}")),
+    }
+}
+
+/// Whether downloads queued with `si model download --queue` are waiting for
+/// `si model queue run`
+pub fn check_download_queue(manager: &ModelManager) -> Check {
+    const NAME: &str = "download queue";
+    match manager.download_queue() {
+        Ok(queue) => match queue.pending() {
+            0 => Check::new(NAME, CheckStatus::Ok, "nothing waiting"),
+            pending => Check::new(
+                NAME,
+                CheckStatus::Warn,
+                format!(
+                    "{pending} queued download{} waiting; run `si model queue run` when online",
+                    if pending == 1 { "" } else { "s" }
+                ),
+            ),
+        },
+        Err(e) => Check::new(NAME, CheckStatus::Fail, format!("{e:#}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_check_dir() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let check = check_dir("models directory", temp_dir.path());
+        assert_eq!(check.status, CheckStatus::Ok);
+        assert!(!temp_dir.path().join(".si-write-check").exists());
+
+        let missing = temp_dir.path().join("a/b");
+        let check = check_dir("models directory", &missing);
+        assert_eq!(check.status, CheckStatus::Ok);
+        assert!(check.detail.contains("created when needed"));
+        assert!(!missing.exists());
+
+        let file = temp_dir.path().join("file");
+        fs::write(&file, "")?;
+        assert_eq!(check_dir("x", &file).status, CheckStatus::Fail);
+        assert_eq!(check_dir("x", &file.join("sub")).status, CheckStatus::Fail);
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_index_suggests_repair() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let models_dir = temp_dir.path().join("models");
+        let manager = crate::ModelManagerBuilder::new()
+            .with_models_dir(models_dir.clone())
+            .with_source(Box::new(crate::MockSource::new(
+                temp_dir.path().join("fixtures"),
+                temp_dir.path().join("cache"),
+            )))
+            .build()?;
+        assert_eq!(check_index(&manager).status, CheckStatus::Ok);
+
+        fs::create_dir_all(&models_dir)?;
+        fs::write(
+            models_dir.join("model_index.json"),
+            r#"{"models": [{"model_id": "org/a", "files": []}, {"model_id": "ORG/A", "files": []}]}"#,
+        )?;
+        let check = check_index(&manager);
+        assert_eq!(check.status, CheckStatus::Warn);
+        assert_eq!(
+            check.detail,
+            "1 duplicate entry; run `si model repair` to clean it up"
+        );
+
+        fs::write(models_dir.join("model_index.json"), "{")?;
+        assert_eq!(check_index(&manager).status, CheckStatus::Fail);
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_download_queue_counts_waiting_entries() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let manager = crate::ModelManagerBuilder::new()
+            .with_models_dir(temp_dir.path().join("models"))
+            .with_source(Box::new(crate::MockSource::new(
+                temp_dir.path().join("fixtures"),
+                temp_dir.path().join("cache"),
+            )))
+            .build()?;
+        assert_eq!(check_download_queue(&manager).status, CheckStatus::Ok);
+
+        let mut queue = manager.download_queue()?;
+        queue.push("org/one".parse()?);
+        queue.push("org/two".parse()?);
+        queue.save()?;
+        let check = check_download_queue(&manager);
+        assert_eq!(check.status, CheckStatus::Warn);
+        assert_eq!(
+            check.detail,
+            "2 queued downloads waiting; run `si model queue run` when online"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_token_sources() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let token_path = temp_dir.path().join("token");
+
+        let check = check_token(None, &token_path);
+        assert_eq!(check.status, CheckStatus::Warn);
+        assert_eq!(
+            check_token(Some(" "), &token_path).status,
+            CheckStatus::Warn
+        );
+        assert_eq!(
+            check_token(Some("hf_abc"), &token_path).detail,
+            "from HF_TOKEN"
+        );
+
+        fs::write(&token_path, "hf_abc\n")?;
+        let check = check_token(None, &token_path);
+        assert_eq!(check.status, CheckStatus::Ok);
+        assert!(check.detail.contains("token"));
+        Ok(())
+    }
+}
@@ -0,0 +1,442 @@
+//! Lock files that keep concurrent downloads of one model apart
+//!
+//! Two `si model download` processes, or two tasks in the server, fetching the
+//! same model would both write its index entry and could read each other's
+//! half-written snapshot. A download therefore holds `locks/<model>.lock` under
+//! the models directory, created exclusively and holding the owner's PID. The
+//! owner refreshes the file's modification time as it goes; a lock whose process
+//! has exited, or that hasn't been refreshed within the TTL, is stale and is
+//! broken by the next caller.
+//!
+//! Several waiters can find the same lock stale. Breaking one moves it aside
+//! under a unique name first and deletes it only if it is still the stale
+//! owner's, so a waiter that looked late can't delete the lock another waiter
+//! just took. A held lock is released the same way, in case it was broken and
+//! taken over in the meantime.
+
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{cancel::CancelToken, error::SiError, logging::DOWNLOAD_TARGET};
+
+const LOCKS_DIR: &str = "locks";
+/// How long a lock may go unrefreshed before it is considered abandoned
+pub const DEFAULT_LOCK_TTL: Duration = Duration::from_secs(2 * 60 * 60);
+/// How often a waiting download checks whether the lock was released
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+/// How often a held lock is refreshed at most, as progress is reported far more
+/// often
+const REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// What a download does when another one of the same model holds the lock
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LockWait {
+    /// Wait for the other download to finish
+    #[default]
+    Wait,
+    /// Fail with [`SiError::AlreadyInProgress`]
+    FailFast,
+}
+
+/// Contents of a lock file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct LockOwner {
+    pid: u32,
+    acquired_at: DateTime<Utc>,
+}
+
+/// A held download lock, released when dropped
+#[derive(Debug)]
+pub struct DownloadLock {
+    path: PathBuf,
+    /// What this download wrote to the lock file, to tell it from a lock taken
+    /// over by another download
+    owner: LockOwner,
+    /// Whether another download held the lock when this one asked for it
+    waited: bool,
+    /// When the lock file was last refreshed
+    refreshed: Mutex<Instant>,
+}
+
+impl DownloadLock {
+    /// The lock file for `model_id` under `models_dir`
+    pub fn path_for(models_dir: &Path, model_id: &str) -> PathBuf {
+        let name: String = model_id
+            .replace('/', "--")
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        models_dir.join(LOCKS_DIR).join(format!("{name}.lock"))
+    }
+
+    /// Take the lock for `model_id`, breaking it if stale and otherwise waiting
+    /// for it or failing as `wait` says
+    pub async fn acquire(
+        models_dir: &Path,
+        model_id: &str,
+        wait: LockWait,
+        ttl: Duration,
+        cancel: &CancelToken,
+    ) -> Result<Self> {
+        let path = Self::path_for(models_dir, model_id);
+        let mut waited = false;
+        loop {
+            match try_create(&path)? {
+                Ok(owner) => {
+                    debug!(target: DOWNLOAD_TARGET, "Locked {}", path.display());
+                    return Ok(Self {
+                        path,
+                        owner,
+                        waited,
+                        refreshed: Mutex::new(Instant::now()),
+                    });
+                }
+                Err(owner) if is_stale(&path, owner.as_ref(), ttl) => {
+                    // Only if it's still the lock found stale, and still stale
+                    let broken = remove_if(&path, |aside, current| {
+                        current == owner.as_ref() && is_stale(aside, current, ttl)
+                    })?;
+                    if broken {
+                        warn!(
+                            "Broke the stale download lock of {model_id} ({})",
+                            describe(owner.as_ref())
+                        );
+                    }
+                }
+                Err(owner) => {
+                    if wait == LockWait::FailFast {
+                        return Err(SiError::AlreadyInProgress {
+                            model_id: model_id.to_string(),
+                            pid: owner.map(|o| o.pid),
+                        }
+                        .into());
+                    }
+                    if !waited {
+                        info!(
+                            target: DOWNLOAD_TARGET,
+                            "Waiting for another download of {model_id} ({}) to finish",
+                            describe(owner.as_ref())
+                        );
+                        waited = true;
+                    }
+                    tokio::select! {
+                        _ = tokio::time::sleep(POLL_INTERVAL) => {}
+                        _ = cancel.cancelled() => return Err(SiError::Cancelled.into()),
+                    }
+                }
+            }
+        }
+    }
+
+    /// Whether another download held the lock first, in which case it may have
+    /// already fetched the model
+    pub fn waited(&self) -> bool {
+        self.waited
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Mark the lock as still in use, so a long download isn't taken for an
+    /// abandoned one; cheap enough to call on every bit of progress, as the file
+    /// is only touched every [`REFRESH_INTERVAL`]
+    pub fn refresh(&self) {
+        {
+            let mut refreshed = self.refreshed.lock().expect("refresh lock");
+            if refreshed.elapsed() < REFRESH_INTERVAL {
+                return;
+            }
+            *refreshed = Instant::now();
+        }
+        if let Err(e) = File::options()
+            .append(true)
+            .open(&self.path)
+            .and_then(|file| file.set_modified(SystemTime::now()))
+        {
+            warn!("Failed to refresh {}: {e}", self.path.display());
+        }
+    }
+}
+
+impl Drop for DownloadLock {
+    fn drop(&mut self) {
+        match remove_if(&self.path, |_, current| current == Some(&self.owner)) {
+            Ok(true) => debug!(target: DOWNLOAD_TARGET, "Unlocked {}", self.path.display()),
+            Ok(false) => warn!(
+                "{} was broken as stale while this download held it",
+                self.path.display()
+            ),
+            Err(e) => warn!("Failed to remove {}: {e:#}", self.path.display()),
+        }
+    }
+}
+
+/// Remove the lock file at `path` if `expected` holds for it, given where it
+/// was moved aside and its owner, putting it back otherwise; returns whether
+/// it was removed
+///
+/// Moving the file aside first means the check and the removal see the same
+/// lock, even when another process replaces the one at `path` in between.
+fn remove_if(
+    path: &Path,
+    expected: impl FnOnce(&Path, Option<&LockOwner>) -> bool,
+) -> Result<bool> {
+    static ASIDE: AtomicU64 = AtomicU64::new(0);
+    let mut aside = path.as_os_str().to_owned();
+    aside.push(format!(
+        ".{}-{}.breaking",
+        std::process::id(),
+        ASIDE.fetch_add(1, Ordering::Relaxed)
+    ));
+    let aside = PathBuf::from(aside);
+    match fs::rename(path, &aside) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e).with_context(|| format!("Failed to move {}", path.display())),
+    }
+    if expected(&aside, read_owner(&aside).as_ref()) {
+        fs::remove_file(&aside).with_context(|| format!("Failed to remove {}", aside.display()))?;
+        return Ok(true);
+    }
+    // Not the lock we meant: put it back, unless a new one has been taken since
+    if let Err(e) = fs::hard_link(&aside, path) {
+        warn!(
+            "Failed to restore the download lock {}: {e}",
+            path.display()
+        );
+    }
+    fs::remove_file(&aside).with_context(|| format!("Failed to remove {}", aside.display()))?;
+    Ok(false)
+}
+
+/// The owner recorded in the lock file at `path`, if it can be read
+fn read_owner(path: &Path) -> Option<LockOwner> {
+    fs::read(path)
+        .ok()
+        .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+}
+
+/// Create the lock file at `path`, returning the owner written to it, or
+/// return its current owner when it exists; that owner is `None` when the file
+/// can't be read or is still being written
+fn try_create(path: &Path) -> Result<Result<LockOwner, Option<LockOwner>>> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    let mut file = match File::options().write(true).create_new(true).open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => return Ok(Err(read_owner(path))),
+        Err(e) => return Err(e).with_context(|| format!("Failed to create {}", path.display())),
+    };
+    let owner = LockOwner {
+        pid: std::process::id(),
+        acquired_at: Utc::now(),
+    };
+    serde_json::to_writer(&mut file, &owner)
+        .map_err(io::Error::from)
+        .and_then(|()| file.flush())
+        .inspect_err(|_| {
+            let _ = fs::remove_file(path);
+        })
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(Ok(owner))
+}
+
+/// Whether the lock at `path` was abandoned: its process is gone, or it hasn't
+/// been refreshed within `ttl`
+fn is_stale(path: &Path, owner: Option<&LockOwner>, ttl: Duration) -> bool {
+    if owner.is_some_and(|owner| !process_alive(owner.pid)) {
+        return true;
+    }
+    let Ok(modified) = fs::metadata(path).and_then(|m| m.modified()) else {
+        // Released since we looked
+        return false;
+    };
+    modified.elapsed().is_ok_and(|age| age > ttl)
+}
+
+/// Whether a process with `pid` is running; assumed to be when that can't be
+/// checked on this platform
+fn process_alive(pid: u32) -> bool {
+    if cfg!(target_os = "linux") {
+        Path::new("/proc").join(pid.to_string()).exists()
+    } else {
+        true
+    }
+}
+
+fn describe(owner: Option<&LockOwner>) -> String {
+    match owner {
+        Some(owner) => format!(
+            "process {} since {}",
+            owner.pid,
+            owner.acquired_at.format("%Y-%m-%d %H:%M:%S UTC")
+        ),
+        None => "unknown owner".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_lock_path_is_sanitized() {
+        let path = DownloadLock::path_for(Path::new("/models"), "org/some model:v1");
+        assert_eq!(
+            path,
+            Path::new("/models/locks/org--some_model_v1.lock").to_path_buf()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_held_lock_fails_fast_and_is_released_on_drop() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let cancel = CancelToken::new();
+        let acquire = |wait| {
+            DownloadLock::acquire(
+                temp_dir.path(),
+                "org/model",
+                wait,
+                DEFAULT_LOCK_TTL,
+                &cancel,
+            )
+        };
+
+        let lock = acquire(LockWait::Wait).await?;
+        assert!(!lock.waited());
+        let err = acquire(LockWait::FailFast).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<SiError>(),
+            Some(SiError::AlreadyInProgress { pid: Some(pid), .. }) if *pid == std::process::id()
+        ));
+
+        let path = lock.path().to_path_buf();
+        drop(lock);
+        assert!(!path.exists());
+        acquire(LockWait::FailFast).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_stale_locks_are_broken() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let cancel = CancelToken::new();
+        let path = DownloadLock::path_for(temp_dir.path(), "org/model");
+        fs::create_dir_all(path.parent().unwrap())?;
+
+        // Not refreshed within the TTL
+        fs::write(&path, "")?;
+        File::options()
+            .append(true)
+            .open(&path)?
+            .set_modified(SystemTime::now() - Duration::from_secs(120))?;
+        let lock = DownloadLock::acquire(
+            temp_dir.path(),
+            "org/model",
+            LockWait::FailFast,
+            Duration::from_secs(60),
+            &cancel,
+        )
+        .await?;
+        drop(lock);
+
+        // Held by a process that has exited
+        if cfg!(target_os = "linux") {
+            let owner = LockOwner {
+                pid: u32::MAX,
+                acquired_at: Utc::now(),
+            };
+            fs::write(&path, serde_json::to_vec(&owner)?)?;
+            DownloadLock::acquire(
+                temp_dir.path(),
+                "org/model",
+                LockWait::FailFast,
+                DEFAULT_LOCK_TTL,
+                &cancel,
+            )
+            .await?;
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_lock_taken_over_is_left_alone() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let lock = DownloadLock::acquire(
+            temp_dir.path(),
+            "org/model",
+            LockWait::FailFast,
+            DEFAULT_LOCK_TTL,
+            &CancelToken::new(),
+        )
+        .await?;
+        let path = lock.path().to_path_buf();
+
+        // Broken as stale and taken by another download meanwhile
+        let other = LockOwner {
+            pid: std::process::id(),
+            acquired_at: Utc::now() + chrono::Duration::seconds(1),
+        };
+        fs::write(&path, serde_json::to_vec(&other)?)?;
+        drop(lock);
+        assert_eq!(read_owner(&path), Some(other.clone()));
+
+        // A waiter that found an earlier owner stale doesn't break it either
+        let stale = LockOwner {
+            pid: u32::MAX,
+            acquired_at: Utc::now(),
+        };
+        assert!(!remove_if(&path, |_, current| current == Some(&stale))?);
+        assert_eq!(read_owner(&path), Some(other));
+        assert_eq!(fs::read_dir(path.parent().unwrap())?.count(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_waiting_is_cancellable() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let cancel = CancelToken::new();
+        let _held = DownloadLock::acquire(
+            temp_dir.path(),
+            "org/model",
+            LockWait::Wait,
+            DEFAULT_LOCK_TTL,
+            &cancel,
+        )
+        .await?;
+
+        let waiting = DownloadLock::acquire(
+            temp_dir.path(),
+            "org/model",
+            LockWait::Wait,
+            DEFAULT_LOCK_TTL,
+            &cancel,
+        );
+        cancel.cancel();
+        let err = waiting.await.unwrap_err();
+        assert!(SiError::Cancelled.matches(&err));
+        Ok(())
+    }
+}
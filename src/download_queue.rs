@@ -0,0 +1,397 @@
+//! Downloads queued for later, for machines that are often offline
+//!
+//! `si model download --queue` appends a [`ModelSpec`] to
+//! `download_queue.json` in the models directory instead of fetching it, and
+//! `si model queue run` later hands the entries still to do to
+//! [`ModelManager::download_many`]. Each entry keeps its [`QueueState`], how
+//! often it was tried and the last error, so a flaky model doesn't get lost
+//! among the ones that went through.
+
+use std::{
+    fmt,
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    manifest::{BulkDownloadOptions, BulkDownloadReport, BulkOutcome, ModelSpec},
+    models::{ModelManager, ensure_dir},
+};
+
+/// File in the models directory holding the queue
+pub const DOWNLOAD_QUEUE_FILENAME: &str = "download_queue.json";
+
+/// Where a queued download stands
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QueueState {
+    /// Not tried yet
+    Pending,
+    /// Tried and failed; the next run tries again
+    Failed,
+    /// Downloaded, or found already present
+    Done,
+}
+
+impl fmt::Display for QueueState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Self::Pending => "pending",
+            Self::Failed => "failed",
+            Self::Done => "done",
+        })
+    }
+}
+
+/// One queued download
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QueueEntry {
+    /// Stable number `si model queue remove` takes
+    pub id: u64,
+    pub spec: ModelSpec,
+    pub state: QueueState,
+    /// Downloads tried, not counting runs that found the model present
+    #[serde(default)]
+    pub attempts: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    pub queued_at: DateTime<Utc>,
+}
+
+impl QueueEntry {
+    /// Whether [`DownloadQueue::run`] picks the entry up
+    pub fn is_runnable(&self) -> bool {
+        self.state != QueueState::Done
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct QueueFile {
+    /// Id of the next entry; ids aren't reused, so a stale `queue remove`
+    /// can't hit another entry
+    #[serde(default = "first_id")]
+    next_id: u64,
+    entries: Vec<QueueEntry>,
+}
+
+fn first_id() -> u64 {
+    1
+}
+
+/// The download queue of a models directory, loaded into memory
+///
+/// Changes are only kept once [`save`](Self::save)d.
+#[derive(Debug)]
+pub struct DownloadQueue {
+    path: PathBuf,
+    next_id: u64,
+    entries: Vec<QueueEntry>,
+}
+
+impl DownloadQueue {
+    /// Load the queue at `path`; a missing file is an empty queue
+    pub fn load(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let file: QueueFile = match fs::read_to_string(&path) {
+            Ok(text) => serde_json::from_str(&text)
+                .with_context(|| format!("Invalid download queue {}", path.display()))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => QueueFile {
+                next_id: first_id(),
+                entries: Vec::new(),
+            },
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("Failed to read download queue {}", path.display()));
+            }
+        };
+        let last_id = file.entries.iter().map(|e| e.id).max().unwrap_or(0);
+        Ok(Self {
+            path,
+            next_id: file.next_id.max(last_id + 1),
+            entries: file.entries,
+        })
+    }
+
+    /// Write the queue to a temp file and rename it into place, as the model
+    /// index is saved
+    pub fn save(&self) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            ensure_dir(dir)?;
+        }
+        let tmp_path = self.path.with_extension("json.tmp");
+        let file = File::create(&tmp_path).with_context(|| {
+            format!("Failed to create download queue at {}", tmp_path.display())
+        })?;
+        serde_json::to_writer_pretty(
+            file,
+            &QueueFile {
+                next_id: self.next_id,
+                entries: self.entries.clone(),
+            },
+        )
+        .with_context(|| format!("Failed to write download queue to {}", tmp_path.display()))?;
+        fs::rename(&tmp_path, &self.path)
+            .with_context(|| format!("Failed to replace download queue {}", self.path.display()))?;
+        debug!(
+            "Saved {} queued downloads to {}",
+            self.entries.len(),
+            self.path.display()
+        );
+        Ok(())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn entries(&self) -> &[QueueEntry] {
+        &self.entries
+    }
+
+    /// Entries not done yet
+    pub fn pending(&self) -> usize {
+        self.entries.iter().filter(|e| e.is_runnable()).count()
+    }
+
+    /// Queue `spec`, unless it is queued and not done already; returns the
+    /// entry and whether it is new
+    pub fn push(&mut self, spec: ModelSpec) -> (&QueueEntry, bool) {
+        if let Some(i) = self
+            .entries
+            .iter()
+            .position(|e| e.spec == spec && e.is_runnable())
+        {
+            return (&self.entries[i], false);
+        }
+        self.entries.push(QueueEntry {
+            id: self.next_id,
+            spec,
+            state: QueueState::Pending,
+            attempts: 0,
+            last_error: None,
+            queued_at: Utc::now(),
+        });
+        self.next_id += 1;
+        (self.entries.last().expect("just pushed"), true)
+    }
+
+    /// Remove the entry numbered `id`
+    pub fn remove(&mut self, id: u64) -> Option<QueueEntry> {
+        let i = self.entries.iter().position(|e| e.id == id)?;
+        Some(self.entries.remove(i))
+    }
+
+    /// Remove the entries that are done, returning how many there were
+    pub fn remove_done(&mut self) -> usize {
+        let before = self.entries.len();
+        self.entries.retain(QueueEntry::is_runnable);
+        before - self.entries.len()
+    }
+
+    /// Download up to `max` entries that aren't done, in queue order, and
+    /// record how each went
+    ///
+    /// Entries whose model is already indexed are marked done without a
+    /// download. Every runnable entry is tried whatever happens to the others,
+    /// so `options.continue_on_error` is ignored. The queue is saved before
+    /// returning; the report covers only the entries tried.
+    pub async fn run(
+        &mut self,
+        manager: &ModelManager,
+        max: Option<usize>,
+        options: &BulkDownloadOptions,
+    ) -> Result<BulkDownloadReport> {
+        let runnable: Vec<usize> = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(_, e)| e.is_runnable())
+            .map(|(i, _)| i)
+            .take(max.unwrap_or(usize::MAX))
+            .collect();
+        let specs: Vec<ModelSpec> = runnable
+            .iter()
+            .map(|&i| self.entries[i].spec.clone())
+            .collect();
+        let options = BulkDownloadOptions {
+            continue_on_error: true,
+            ..options.clone()
+        };
+        let report = manager.download_many(&specs, &options).await?;
+
+        for (&i, (_, outcome)) in runnable.iter().zip(&report.results) {
+            let entry = &mut self.entries[i];
+            match outcome {
+                BulkOutcome::Downloaded(_) => {
+                    entry.attempts += 1;
+                    entry.state = QueueState::Done;
+                    entry.last_error = None;
+                }
+                BulkOutcome::AlreadyPresent => {
+                    entry.state = QueueState::Done;
+                    entry.last_error = None;
+                }
+                BulkOutcome::Failed(e) => {
+                    entry.attempts += 1;
+                    entry.state = QueueState::Failed;
+                    entry.last_error = Some(e.clone());
+                }
+                BulkOutcome::NotAttempted => {}
+            }
+        }
+        self.save()?;
+        Ok(report)
+    }
+}
+
+impl ModelManager {
+    /// Where `si model download --queue` queues downloads
+    pub fn download_queue_path(&self) -> PathBuf {
+        self.models_dir().join(DOWNLOAD_QUEUE_FILENAME)
+    }
+
+    /// The download queue of this models directory
+    pub fn download_queue(&self) -> Result<DownloadQueue> {
+        DownloadQueue::load(self.download_queue_path())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MockSource, ModelManagerBuilder};
+    use std::sync::Arc;
+    use tempfile::tempdir;
+
+    fn mock_setup(root: &Path, repos: &[&str]) -> Result<(ModelManager, Arc<MockSource>)> {
+        for repo in repos {
+            let dir = root.join("fixtures").join(repo);
+            fs::create_dir_all(&dir)?;
+            fs::write(dir.join("model.bin"), "weights")?;
+        }
+        let source = Arc::new(MockSource::new(root.join("fixtures"), root.join("cache")));
+        let manager = ModelManagerBuilder::new()
+            .with_models_dir(root.join("models"))
+            .with_source(Box::new(source.clone()))
+            .build()?;
+        Ok((manager, source))
+    }
+
+    #[test]
+    fn test_queue_persists_pushes_and_removals() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("models").join(DOWNLOAD_QUEUE_FILENAME);
+        let mut queue = DownloadQueue::load(&path)?;
+        assert!(queue.entries().is_empty());
+
+        assert_eq!(queue.push("org/one".parse()?).0.id, 1);
+        assert_eq!(queue.push("org/two@v2".parse()?).0.id, 2);
+        let (entry, added) = queue.push("org/one".parse()?);
+        assert_eq!((entry.id, added), (1, false));
+        queue.save()?;
+
+        let mut queue = DownloadQueue::load(&path)?;
+        assert_eq!(queue.pending(), 2);
+        assert_eq!(
+            queue.entries()[1].spec,
+            ModelSpec::new("org/two".parse()?).with_revision("v2")
+        );
+        assert_eq!(queue.entries()[1].state, QueueState::Pending);
+        assert_eq!(
+            queue.remove(1).map(|e| e.spec.id.to_string()),
+            Some("org/one".to_string())
+        );
+        assert!(queue.remove(1).is_none());
+        assert!(queue.remove(2).is_some());
+        queue.save()?;
+
+        // Ids aren't reused, even the last one's
+        let mut queue = DownloadQueue::load(&path)?;
+        assert_eq!(queue.push("org/three".parse()?).0.id, 3);
+        let ids: Vec<u64> = queue.entries().iter().map(|e| e.id).collect();
+        assert_eq!(ids, [3]);
+        assert!(!path.with_extension("json.tmp").exists());
+
+        fs::write(&path, "{")?;
+        assert!(DownloadQueue::load(&path).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_run_skips_present_models_and_records_failures() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let (manager, source) = mock_setup(temp_dir.path(), &["org/one", "org/two"])?;
+        manager.download_model("org/one").await?;
+
+        let mut queue = manager.download_queue()?;
+        for spec in ["org/one", "org/missing", "org/two"] {
+            queue.push(spec.parse()?);
+        }
+        queue.save()?;
+        let report = queue
+            .run(&manager, None, &BulkDownloadOptions::default())
+            .await?;
+        assert_eq!(
+            (
+                report.succeeded(),
+                report.already_present(),
+                report.failed()
+            ),
+            (1, 1, 1)
+        );
+        assert_eq!(
+            source.downloads(),
+            ["org/one/model.bin", "org/two/model.bin"]
+        );
+
+        let queue = manager.download_queue()?;
+        let states: Vec<(QueueState, u32)> = queue
+            .entries()
+            .iter()
+            .map(|e| (e.state, e.attempts))
+            .collect();
+        assert_eq!(
+            states,
+            [
+                (QueueState::Done, 0),
+                (QueueState::Failed, 1),
+                (QueueState::Done, 1)
+            ]
+        );
+        assert!(queue.entries()[1].last_error.is_some());
+
+        // Only the failed entry is left to run, and it fails again
+        let mut queue = manager.download_queue()?;
+        let report = queue
+            .run(&manager, Some(5), &BulkDownloadOptions::default())
+            .await?;
+        assert_eq!(report.results.len(), 1);
+        assert_eq!(queue.entries()[1].attempts, 2);
+        assert_eq!(queue.pending(), 1);
+        assert_eq!(queue.remove_done(), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_run_takes_at_most_max_entries() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let (manager, _source) = mock_setup(temp_dir.path(), &["org/one", "org/two"])?;
+        let mut queue = manager.download_queue()?;
+        queue.push("org/one".parse()?);
+        queue.push("org/two".parse()?);
+
+        let report = queue
+            .run(&manager, Some(1), &BulkDownloadOptions::default())
+            .await?;
+        assert_eq!(report.succeeded(), 1);
+        assert_eq!(queue.entries()[1].state, QueueState::Pending);
+        assert_eq!(manager.download_queue()?.pending(), 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,181 @@
+//! Errors callers may want to tell apart from other failures
+//!
+//! Most of si reports errors through `anyhow`; the variants here are returned
+//! inside an `anyhow::Error` and can be recovered with `downcast_ref::<SiError>()`.
+
+use std::{fmt, time::Duration};
+
+use crate::format::{format_duration, format_size};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SiError {
+    /// The operation was stopped through a [`CancelToken`](crate::CancelToken)
+    Cancelled,
+    /// A try-on ran longer than its time limit and was stopped
+    Timeout { limit: Duration },
+    /// An input image is narrower or shorter than the configured minimum
+    ImageTooSmall {
+        width: u32,
+        height: u32,
+        min_width: u32,
+        min_height: u32,
+    },
+    /// An input image has more pixels than the configured maximum
+    TooManyPixels {
+        width: u32,
+        height: u32,
+        max_pixels: u64,
+    },
+    /// Decoding and processing an input image would need more memory than allowed
+    ImageTooLarge {
+        width: u32,
+        height: u32,
+        estimated_bytes: u64,
+        max_bytes: u64,
+    },
+    /// Another download of the model holds its lock and the caller chose not to wait
+    AlreadyInProgress {
+        model_id: String,
+        /// Process holding the lock, when the lock file names one
+        pid: Option<u32>,
+    },
+    /// A request needed the Hugging Face Hub, but si was built without the `hub`
+    /// feature
+    HubDisabled,
+    /// A request needed the network while si runs offline
+    Offline,
+    /// The model's weights are all pickles, which si doesn't load
+    PickleOnly { model_id: String },
+    /// The model can't generate images, as
+    /// [`ModelManager::check_compatibility`](crate::ModelManager::check_compatibility)
+    /// found
+    IncompatibleModel { model_id: String, reason: String },
+    /// A model repository has nothing to download besides its README, license
+    /// and Git metadata, either at all or once the download's filters applied
+    EmptyModelRepo {
+        model_id: String,
+        /// Every file in the repository, metadata included
+        total_siblings: usize,
+        /// Model files the filters left out
+        filtered_out: usize,
+    },
+    /// Generation needs a model that isn't in the index, or whose files are gone
+    ModelNotDownloaded {
+        model_id: String,
+        /// What downloading it would transfer, when the source could tell
+        estimated_size: Option<u64>,
+    },
+}
+
+impl fmt::Display for SiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Cancelled => f.write_str("Operation cancelled"),
+            Self::Timeout { limit } => {
+                write!(f, "Timed out after {}", format_duration(*limit))
+            }
+            Self::ImageTooSmall {
+                width,
+                height,
+                min_width,
+                min_height,
+            } => write!(
+                f,
+                "Input image is {width}x{height}, smaller than the {min_width}x{min_height} minimum; \
+                 use a larger photo"
+            ),
+            Self::TooManyPixels {
+                width,
+                height,
+                max_pixels,
+            } => write!(
+                f,
+                "Input image is {width}x{height} ({:.1} MP), over the {:.1} MP limit; \
+                 downscale it first",
+                megapixels(u64::from(*width) * u64::from(*height)),
+                megapixels(*max_pixels)
+            ),
+            Self::ImageTooLarge {
+                width,
+                height,
+                estimated_bytes,
+                max_bytes,
+            } => write!(
+                f,
+                "Input image is {width}x{height} and would need about {} to process, over the {} \
+                 limit; downscale it or convert it to 8-bit",
+                format_size(*estimated_bytes),
+                format_size(*max_bytes)
+            ),
+            Self::AlreadyInProgress { model_id, pid } => {
+                write!(f, "A download of {model_id} is already in progress")?;
+                if let Some(pid) = pid {
+                    write!(f, " (process {pid})")?;
+                }
+                f.write_str("; wait for it to finish or try again without --no-wait")
+            }
+            Self::HubDisabled => f.write_str(
+                "si was built without the `hub` feature and can't reach the Hugging Face Hub",
+            ),
+            Self::Offline => f.write_str(
+                "si is running offline (--offline or HF_HUB_OFFLINE) and won't use the network",
+            ),
+            Self::PickleOnly { model_id } => write!(
+                f,
+                "{model_id} only has pickle weights (.ckpt, .bin, .pt), which can run code when \
+                 loaded, so si won't use them; convert them to .safetensors and import the result"
+            ),
+            Self::IncompatibleModel { model_id, reason } => write!(
+                f,
+                "{model_id} can't be used to generate images: {reason}; pick an image generation \
+                 model such as runwayml/stable-diffusion-v1-5, or pass --force to try it anyway"
+            ),
+            Self::EmptyModelRepo {
+                model_id,
+                total_siblings,
+                filtered_out: 0,
+            } => write!(
+                f,
+                "{model_id} has no model files to download ({total_siblings} files, all README, \
+                 license or Git metadata); the repository may be empty or gated"
+            ),
+            Self::EmptyModelRepo {
+                model_id,
+                filtered_out,
+                ..
+            } => write!(
+                f,
+                "The download filters excluded all {filtered_out} model files of {model_id}; \
+                 widen --include or --variant, or pass --include '*' to fetch everything"
+            ),
+            Self::ModelNotDownloaded {
+                model_id,
+                estimated_size,
+            } => {
+                write!(f, "Model {model_id} isn't downloaded")?;
+                if let Some(size) = estimated_size {
+                    write!(f, " (about {})", format_size(*size))?;
+                }
+                write!(
+                    f,
+                    "; run `si model download {model_id}` first, or pass --download-missing to \
+                     download it now"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for SiError {}
+
+fn megapixels(pixels: u64) -> f64 {
+    pixels as f64 / 1_000_000.0
+}
+
+impl SiError {
+    /// Whether `err` is, or was caused by, `self`
+    pub fn matches(&self, err: &anyhow::Error) -> bool {
+        err.chain()
+            .any(|cause| cause.downcast_ref::<SiError>() == Some(self))
+    }
+}
@@ -0,0 +1,149 @@
+//! Estimating the time left for a long step from its progress updates
+//!
+//! [`EtaEstimator`] keeps an exponential moving average of the throughput
+//! between updates, so one slow or fast interval nudges the estimate instead of
+//! replacing it. Times are passed in as durations since the step started, which
+//! keeps the estimator free of clocks and easy to test.
+
+use std::time::Duration;
+
+/// Weight of the newest throughput sample when none is given
+pub const DEFAULT_SMOOTHING: f64 = 0.3;
+
+#[derive(Debug, Clone)]
+pub struct EtaEstimator {
+    total: u64,
+    smoothing: f64,
+    /// Smoothed units per second
+    rate: Option<f64>,
+    /// Units done and time of the previous update
+    last: Option<(u64, Duration)>,
+}
+
+impl EtaEstimator {
+    /// An estimator for a step of `total` units (rows, bytes, ...)
+    pub fn new(total: u64) -> Self {
+        Self {
+            total,
+            smoothing: DEFAULT_SMOOTHING,
+            rate: None,
+            last: None,
+        }
+    }
+
+    /// Weight of each new sample, between 0 (ignore new samples) and 1 (use only
+    /// the latest)
+    pub fn with_smoothing(mut self, smoothing: f64) -> Self {
+        self.smoothing = smoothing.clamp(f64::EPSILON, 1.0);
+        self
+    }
+
+    /// Record that `done` units were finished `elapsed` after the step started
+    ///
+    /// Progress going backwards starts the estimate over.
+    pub fn update(&mut self, done: u64, elapsed: Duration) {
+        let Some((last_done, last_elapsed)) = self.last else {
+            // The step started at zero, so the first update is already a sample
+            self.last = Some((0, Duration::ZERO));
+            return self.update(done, elapsed);
+        };
+        if done < last_done || elapsed < last_elapsed {
+            self.rate = None;
+            self.last = Some((done, elapsed));
+            return;
+        }
+        let interval = (elapsed - last_elapsed).as_secs_f64();
+        if interval <= 0.0 {
+            return;
+        }
+        let sample = (done - last_done) as f64 / interval;
+        self.rate = Some(match self.rate {
+            Some(rate) => rate + self.smoothing * (sample - rate),
+            None => sample,
+        });
+        self.last = Some((done, elapsed));
+    }
+
+    /// Smoothed throughput in units per second, once there is a sample
+    pub fn rate(&self) -> Option<f64> {
+        self.rate
+    }
+
+    /// Share of the step done, from 0 to 1
+    pub fn fraction(&self) -> f64 {
+        let done = self.last.map_or(0, |(done, _)| done);
+        if self.total == 0 {
+            return 1.0;
+        }
+        (done as f64 / self.total as f64).min(1.0)
+    }
+
+    /// Time left at the smoothed rate; `None` until the rate is known and positive
+    pub fn eta(&self) -> Option<Duration> {
+        let done = self.last.map_or(0, |(done, _)| done);
+        if done >= self.total {
+            return Some(Duration::ZERO);
+        }
+        let rate = self.rate.filter(|rate| *rate > 0.0)?;
+        Some(Duration::from_secs_f64((self.total - done) as f64 / rate))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_eta_converges_after_rate_change() {
+        // 1000 rows: the first 100 at 50 rows/s, the rest at a steady 100 rows/s
+        let mut eta = EtaEstimator::new(1000);
+        let mut elapsed = Duration::from_secs(2);
+        eta.update(100, elapsed);
+        assert_eq!(eta.rate(), Some(50.0));
+
+        let mut previous_eta = eta.eta().unwrap();
+        let mut previous_error = f64::MAX;
+        for done in (200..1000).step_by(100) {
+            elapsed += Duration::from_secs(1);
+            eta.update(done, elapsed);
+            let estimate = eta.eta().unwrap();
+            let actual = (1000 - done) as f64 / 100.0;
+            let error = (estimate.as_secs_f64() - actual).abs();
+            assert!(estimate <= previous_eta, "ETA rose at {done}");
+            assert!(error < previous_error, "ETA error grew at {done}");
+            (previous_eta, previous_error) = (estimate, error);
+        }
+        assert!(previous_error < 0.2, "{previous_error}");
+        assert!((eta.rate().unwrap() - 100.0).abs() < 10.0);
+
+        eta.update(1000, elapsed + Duration::from_secs(1));
+        assert_eq!(eta.eta(), Some(Duration::ZERO));
+        assert_eq!(eta.fraction(), 1.0);
+    }
+
+    #[test]
+    fn test_eta_unknown_until_progress_and_resets_backwards() {
+        let mut eta = EtaEstimator::new(10);
+        assert_eq!(eta.eta(), None);
+        eta.update(0, Duration::from_secs(1));
+        assert_eq!(eta.eta(), None);
+        eta.update(5, Duration::from_secs(2));
+        let remaining = eta.eta().unwrap().as_secs_f64();
+        // 0 rows/s then 5 rows/s, smoothed to 1.5 rows/s for the last 5 rows
+        assert!((remaining - 5.0 / 1.5).abs() < 1e-6, "{remaining}");
+        assert_eq!(eta.fraction(), 0.5);
+
+        // A new pass starting over
+        eta.update(1, Duration::from_secs(3));
+        assert_eq!(eta.rate(), None);
+        assert_eq!(eta.eta(), None);
+    }
+
+    #[test]
+    fn test_smoothing_one_follows_latest_sample() {
+        let mut eta = EtaEstimator::new(100).with_smoothing(1.0);
+        eta.update(10, Duration::from_secs(1));
+        eta.update(40, Duration::from_secs(2));
+        assert_eq!(eta.rate(), Some(30.0));
+    }
+}
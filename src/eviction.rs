@@ -0,0 +1,414 @@
+//! Keeping the Hub cache within a size and age budget
+//!
+//! With `cache_max_bytes` or `cache_max_age_days` set, `si model evict` deletes
+//! downloaded models that haven't been used for longer than the age limit, then
+//! the least recently used of the rest until the cache fits in the size budget.
+//! When a model was last used comes from the generation history; models the
+//! history never saw are dated by their cache directory. Pinned models and
+//! models indexed in place are never evicted, and nothing outside the cache
+//! root is touched. An evicted model is deleted as `si model delete` would,
+//! index entry included.
+
+use std::{collections::BTreeMap, fmt, fs};
+
+use anyhow::Result;
+use chrono::{DateTime, Days, Utc};
+use log::{debug, warn};
+use serde::Serialize;
+
+use crate::{
+    format::format_size,
+    index_paths,
+    models::{ModelManager, ModelOrigin, disk_usage},
+};
+
+/// Limits on the Hub cache; both unset means no eviction
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct CachePolicy {
+    /// Bytes the whole cache may take
+    pub max_bytes: Option<u64>,
+    /// Days a model may go unused
+    pub max_age_days: Option<u64>,
+}
+
+impl CachePolicy {
+    pub fn is_set(&self) -> bool {
+        self.max_bytes.is_some() || self.max_age_days.is_some()
+    }
+}
+
+/// Why a model was evicted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EvictionReason {
+    /// Unused for longer than `max_age_days`
+    Age,
+    /// Least recently used while the cache was over `max_bytes`
+    Size,
+}
+
+impl fmt::Display for EvictionReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Age => "unused too long",
+            Self::Size => "over size budget",
+        })
+    }
+}
+
+/// One model [`ModelManager::enforce_cache_policy`] evicted
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct EvictedModel {
+    pub model_id: String,
+    /// Bytes its cache directory took
+    pub bytes: u64,
+    pub last_used: Option<DateTime<Utc>>,
+    pub reason: EvictionReason,
+}
+
+/// Outcome of [`ModelManager::enforce_cache_policy`]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct EvictionReport {
+    pub policy: CachePolicy,
+    /// Bytes in the cache before eviction
+    pub cache_bytes: u64,
+    /// Bytes left after eviction, or that would be left after a dry run
+    pub remaining_bytes: u64,
+    /// Evicted models (or, in a dry run, those that would be), least recently
+    /// used first
+    pub evicted: Vec<EvictedModel>,
+    /// Pinned models in the cache, which were left alone
+    pub skipped_pinned: Vec<String>,
+}
+
+impl EvictionReport {
+    pub fn reclaimed_bytes(&self) -> u64 {
+        self.evicted.iter().map(|m| m.bytes).sum()
+    }
+
+    /// Whether the cache is still bigger than `max_bytes`, as it is when only
+    /// pinned models and files si didn't download are left
+    pub fn over_budget(&self) -> bool {
+        self.policy
+            .max_bytes
+            .is_some_and(|max| self.remaining_bytes > max)
+    }
+}
+
+/// A downloaded model whose cache directory eviction could delete
+#[derive(Debug)]
+struct Candidate {
+    model_id: String,
+    bytes: u64,
+    last_used: Option<DateTime<Utc>>,
+    pinned: bool,
+}
+
+impl ModelManager {
+    /// The limits set with
+    /// [`ModelManagerBuilder::with_cache_policy`](crate::ModelManagerBuilder::with_cache_policy)
+    pub fn cache_policy(&self) -> CachePolicy {
+        self.cache_policy
+    }
+
+    /// Evict downloaded models from the Hub cache, least recently used first,
+    /// until it keeps to the [`cache_policy`](Self::cache_policy)
+    ///
+    /// Models unused for longer than `max_age_days` are evicted whatever the
+    /// cache size; then, while the cache is over `max_bytes`, so are the least
+    /// recently used of the rest. Pinned models are skipped and listed in the
+    /// report. With `dry_run` nothing is deleted.
+    pub fn enforce_cache_policy(&self, dry_run: bool) -> Result<EvictionReport> {
+        self.enforce_cache_policy_at(dry_run, Utc::now())
+    }
+
+    pub(crate) fn enforce_cache_policy_at(
+        &self,
+        dry_run: bool,
+        now: DateTime<Utc>,
+    ) -> Result<EvictionReport> {
+        let policy = self.cache_policy;
+        let cache_bytes = self.cache_bytes()?;
+        let mut report = EvictionReport {
+            policy,
+            cache_bytes,
+            remaining_bytes: cache_bytes,
+            ..EvictionReport::default()
+        };
+        let cutoff = policy
+            .max_age_days
+            .and_then(|days| now.checked_sub_days(Days::new(days)));
+
+        let mut candidates = self.eviction_candidates()?;
+        candidates.sort_by(|a, b| {
+            a.last_used
+                .cmp(&b.last_used)
+                .then_with(|| a.model_id.cmp(&b.model_id))
+        });
+        for candidate in candidates {
+            if candidate.pinned {
+                report.skipped_pinned.push(candidate.model_id);
+                continue;
+            }
+            let reason = if cutoff.is_some_and(|cutoff| candidate.last_used < Some(cutoff)) {
+                EvictionReason::Age
+            } else if policy
+                .max_bytes
+                .is_some_and(|max| report.remaining_bytes > max)
+            {
+                EvictionReason::Size
+            } else {
+                continue;
+            };
+            if !dry_run {
+                debug!("Evicting {} ({reason})", candidate.model_id);
+                if let Err(e) = self.delete_model(&candidate.model_id) {
+                    warn!("Failed to evict {}: {e:#}", candidate.model_id);
+                    continue;
+                }
+            }
+            report.remaining_bytes = report.remaining_bytes.saturating_sub(candidate.bytes);
+            report.evicted.push(EvictedModel {
+                model_id: candidate.model_id,
+                bytes: candidate.bytes,
+                last_used: candidate.last_used,
+                reason,
+            });
+        }
+        Ok(report)
+    }
+
+    /// Bytes the Hub cache takes, counting files si didn't download
+    pub fn cache_bytes(&self) -> Result<u64> {
+        let root = self.hf_cache_dir();
+        if root.is_dir() {
+            disk_usage(&root)
+        } else {
+            Ok(0)
+        }
+    }
+
+    /// Warn when the Hub cache has grown over `max_bytes`; called after
+    /// downloads, which never evict anything themselves
+    pub(crate) fn warn_if_over_cache_budget(&self) {
+        let Some(max) = self.cache_policy.max_bytes else {
+            return;
+        };
+        match self.cache_bytes() {
+            Ok(bytes) if bytes > max => warn!(
+                "The Hub cache takes {}, over its {} budget; run `si model evict` to free space",
+                format_size(bytes),
+                format_size(max)
+            ),
+            Ok(_) => {}
+            Err(e) => debug!("Failed to measure the Hub cache: {e:#}"),
+        }
+    }
+
+    /// Every downloaded model with a directory in the Hub cache, variants
+    /// merged, with when it was last used
+    fn eviction_candidates(&self) -> Result<Vec<Candidate>> {
+        let last_used: BTreeMap<_, _> = match &self.usage_history {
+            Some(history) => self
+                .usage_stats(history)?
+                .into_iter()
+                .filter_map(|usage| Some((usage.model_id, usage.last_used?)))
+                .collect(),
+            None => BTreeMap::new(),
+        };
+        let root = self.hf_cache_dir();
+        let mut candidates: BTreeMap<String, Candidate> = BTreeMap::new();
+        for model in self.list_models()? {
+            if model.origin != ModelOrigin::HuggingFace {
+                continue;
+            }
+            if let Some(candidate) = candidates.get_mut(&model.model_id) {
+                candidate.pinned |= model.pinned;
+                continue;
+            }
+            let repo_dir = self.repo_cache_dir(&model.model_id);
+            if !repo_dir.is_dir() || !index_paths::is_within(&repo_dir, &root) {
+                continue;
+            }
+            let used = last_used.get(&model.model_id).copied().or_else(|| {
+                let modified = fs::metadata(&repo_dir).and_then(|m| m.modified()).ok()?;
+                Some(DateTime::<Utc>::from(modified))
+            });
+            candidates.insert(
+                model.model_id.clone(),
+                Candidate {
+                    bytes: disk_usage(&repo_dir)?,
+                    last_used: used,
+                    pinned: model.pinned,
+                    model_id: model.model_id,
+                },
+            );
+        }
+        Ok(candidates.into_values().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{History, ModelInfo, ModelManagerBuilder};
+    use std::path::Path;
+    use tempfile::tempdir;
+
+    fn history_line(model: &str, timestamp: &str) -> String {
+        serde_json::json!({
+            "timestamp": timestamp,
+            "prompt": "red shirt",
+            "model": model,
+            "input": "in.png",
+            "output": "out.png",
+            "strength": null,
+            "duration_ms": 100,
+            "success": true,
+        })
+        .to_string()
+    }
+
+    /// A manager over a synthetic cache holding `models` as (id, bytes, last
+    /// used) under `policy`
+    fn cache(
+        root: &Path,
+        models: &[(&str, usize, &str)],
+        policy: CachePolicy,
+    ) -> Result<ModelManager> {
+        let cache_dir = root.join("hub");
+        let history_path = root.join("history.jsonl");
+        let lines: Vec<_> = models
+            .iter()
+            .map(|(model_id, _, used)| history_line(model_id, used))
+            .collect();
+        fs::write(&history_path, lines.join("\n"))?;
+        let manager = ModelManagerBuilder::new()
+            .with_models_dir(root.join("models"))
+            .with_hf_cache_dir(cache_dir.clone())
+            .with_cache_policy(policy)
+            .with_usage_history(History::new(history_path))
+            .build()?;
+        for (model_id, bytes, _) in models {
+            let blobs = manager.repo_cache_dir(model_id).join("blobs");
+            fs::create_dir_all(&blobs)?;
+            fs::write(blobs.join("weights"), vec![0; *bytes])?;
+            manager
+                .model_index()
+                .add_model(ModelInfo::new(*model_id, vec![]))?;
+        }
+        // Not a model si downloaded, so never evicted but counted
+        fs::create_dir_all(cache_dir.join("datasets--org--data"))?;
+        fs::write(cache_dir.join("datasets--org--data").join("rows"), [0; 50])?;
+        Ok(manager)
+    }
+
+    fn ids(report: &EvictionReport) -> Vec<&str> {
+        report.evicted.iter().map(|m| m.model_id.as_str()).collect()
+    }
+
+    fn now() -> DateTime<Utc> {
+        "2026-03-01T00:00:00Z".parse().unwrap()
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used_until_under_budget() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let policy = CachePolicy {
+            max_bytes: Some(700),
+            max_age_days: None,
+        };
+        let manager = cache(
+            temp_dir.path(),
+            &[
+                ("org/recent", 300, "2026-02-20T00:00:00Z"),
+                ("org/oldest", 200, "2026-01-01T00:00:00Z"),
+                ("org/pinned", 400, "2025-12-01T00:00:00Z"),
+                ("org/older", 100, "2026-01-15T00:00:00Z"),
+                ("org/newer", 250, "2026-02-01T00:00:00Z"),
+            ],
+            policy,
+        )?;
+        manager.set_pinned("org/pinned", true)?;
+        assert_eq!(manager.cache_bytes()?, 1300);
+
+        // 1300 - 200 - 100 = 1000, still over; - 250 = 750, still over; - 300
+        let dry = manager.enforce_cache_policy_at(true, now())?;
+        assert_eq!(
+            ids(&dry),
+            ["org/oldest", "org/older", "org/newer", "org/recent"]
+        );
+        assert!(dry.evicted.iter().all(|m| m.reason == EvictionReason::Size));
+        assert_eq!(dry.reclaimed_bytes(), 850);
+        assert_eq!(dry.remaining_bytes, 450);
+        assert!(!dry.over_budget());
+        assert_eq!(dry.skipped_pinned, ["org/pinned"]);
+        assert_eq!(manager.list_models()?.len(), 5);
+
+        let report = manager.enforce_cache_policy_at(false, now())?;
+        assert_eq!(report, dry);
+        assert_eq!(manager.cache_bytes()?, 450);
+        let left: Vec<_> = manager
+            .list_models()?
+            .into_iter()
+            .map(|m| m.model_id)
+            .collect();
+        assert_eq!(left, ["org/pinned"]);
+        assert!(manager.repo_cache_dir("org/pinned").exists());
+        assert!(!manager.repo_cache_dir("org/oldest").exists());
+        assert!(temp_dir.path().join("hub/datasets--org--data").exists());
+
+        // Under budget now, so nothing more goes
+        assert!(
+            manager
+                .enforce_cache_policy_at(false, now())?
+                .evicted
+                .is_empty()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_evicts_by_age_before_size() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let policy = CachePolicy {
+            max_bytes: Some(1000),
+            max_age_days: Some(30),
+        };
+        let manager = cache(
+            temp_dir.path(),
+            &[
+                ("org/stale", 10, "2026-01-10T00:00:00Z"),
+                ("org/big", 900, "2026-02-10T00:00:00Z"),
+                ("org/fresh", 300, "2026-02-25T00:00:00Z"),
+            ],
+            policy,
+        )?;
+
+        let report = manager.enforce_cache_policy_at(true, now())?;
+        assert_eq!(ids(&report), ["org/stale", "org/big"]);
+        assert_eq!(report.evicted[0].reason, EvictionReason::Age);
+        assert_eq!(report.evicted[1].reason, EvictionReason::Size);
+        assert_eq!(report.cache_bytes, 1260);
+        assert_eq!(report.remaining_bytes, 350);
+
+        // Only the age limit: a big cache is fine
+        let manager = ModelManagerBuilder::new()
+            .with_models_dir(temp_dir.path().join("models"))
+            .with_hf_cache_dir(temp_dir.path().join("hub"))
+            .with_cache_policy(CachePolicy {
+                max_bytes: None,
+                max_age_days: Some(30),
+            })
+            .build()?;
+        // Without the history, models are as new as their cache directories
+        let report = manager.enforce_cache_policy_at(true, now())?;
+        assert!(report.evicted.is_empty(), "{report:?}");
+        let later = Utc::now() + chrono::Duration::days(31);
+        let report = manager.enforce_cache_policy_at(true, later)?;
+        let mut evicted = ids(&report);
+        evicted.sort_unstable();
+        assert_eq!(evicted, ["org/big", "org/fresh", "org/stale"]);
+        Ok(())
+    }
+}
@@ -0,0 +1,214 @@
+//! Sizes and durations as people read them
+//!
+//! Everything si prints goes through [`format_size`] and [`format_duration`], so
+//! a size looks the same in `model list` as in a download summary. `si --raw`
+//! switches both, process-wide, to plain numbers for scripts: bytes and whole
+//! milliseconds.
+
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use anyhow::{Context, bail};
+use humansize::DECIMAL;
+
+static RAW: AtomicBool = AtomicBool::new(false);
+
+/// How numbers are rendered
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Style {
+    /// `1.2 GB`, `1m 23s`
+    #[default]
+    Human,
+    /// `1200000000`, `83000`
+    Raw,
+}
+
+/// Render all later output in `style`
+pub fn set_style(style: Style) {
+    RAW.store(style == Style::Raw, Ordering::Relaxed);
+}
+
+/// The style set with [`set_style`], [`Style::Human`] by default
+pub fn style() -> Style {
+    if RAW.load(Ordering::Relaxed) {
+        Style::Raw
+    } else {
+        Style::Human
+    }
+}
+
+/// `bytes` in the configured style, e.g. `1.2 GB`
+pub fn format_size(bytes: u64) -> String {
+    format_size_as(bytes, style())
+}
+
+/// `bytes` in `style`: decimal units, or the plain byte count
+pub fn format_size_as(bytes: u64, style: Style) -> String {
+    match style {
+        Style::Human => humansize::format_size(bytes, DECIMAL),
+        Style::Raw => bytes.to_string(),
+    }
+}
+
+/// Parse a byte count such as `1024`, `500MB`, `1.5GB` or `2GiB`
+pub fn parse_size(value: &str) -> anyhow::Result<u64> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number
+        .parse()
+        .with_context(|| format!("Invalid size `{value}`"))?;
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "kb" => 1_000,
+        "mb" => 1_000_000,
+        "gb" => 1_000_000_000,
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        other => bail!("Unknown size unit `{other}` in `{value}`"),
+    };
+    Ok((number * multiplier as f64) as u64)
+}
+
+/// Parse a duration such as `90` or `90s` (seconds), `2m` or `1h`; it must not
+/// be zero
+pub fn parse_duration(value: &str) -> anyhow::Result<Duration> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number
+        .parse()
+        .with_context(|| format!("Invalid duration `{value}`"))?;
+    let seconds = match unit.trim() {
+        "" | "s" => number,
+        "m" => number * 60.0,
+        "h" => number * 3600.0,
+        other => bail!("Unknown duration unit `{other}` in `{value}`, expected s, m or h"),
+    };
+    let duration = Duration::try_from_secs_f64(seconds)
+        .with_context(|| format!("Duration `{value}` is out of range"))?;
+    if duration.is_zero() {
+        bail!("Invalid duration `{value}`: must be above zero");
+    }
+    Ok(duration)
+}
+
+/// `duration` in the configured style, e.g. `450ms` or `1m 23s`
+pub fn format_duration(duration: Duration) -> String {
+    format_duration_as(duration, style())
+}
+
+/// `duration` in `style`: milliseconds under a second, tenths of a second under
+/// a minute, then minutes and seconds, then hours and minutes; or the plain
+/// millisecond count
+pub fn format_duration_as(duration: Duration, style: Style) -> String {
+    let millis = duration.as_millis();
+    if style == Style::Raw {
+        return millis.to_string();
+    }
+    let secs = duration.as_secs();
+    match secs {
+        0 => format!("{millis}ms"),
+        1..60 => format!("{secs}.{}s", duration.subsec_millis() / 100),
+        60..3600 => format!("{}m {}s", secs / 60, secs % 60),
+        _ => format!("{}h {}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+/// Serde support for a [`Duration`] stored as whole milliseconds, e.g.
+/// `#[serde(with = "si::format::millis")]`
+pub mod millis {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(duration.as_millis() as u64)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        u64::deserialize(deserializer).map(Duration::from_millis)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_duration() {
+        let human = |ms| format_duration_as(Duration::from_millis(ms), Style::Human);
+        assert_eq!(human(0), "0ms");
+        assert_eq!(human(450), "450ms");
+        assert_eq!(human(999), "999ms");
+        assert_eq!(human(1_000), "1.0s");
+        assert_eq!(human(12_345), "12.3s");
+        assert_eq!(human(59_999), "59.9s");
+        assert_eq!(human(83_000), "1m 23s");
+        assert_eq!(human(3_599_999), "59m 59s");
+        assert_eq!(human(3_600_000), "1h 0m");
+        assert_eq!(human(3_723_000), "1h 2m");
+        assert_eq!(human(90_000_000), "25h 0m");
+
+        let raw = |ms| format_duration_as(Duration::from_millis(ms), Style::Raw);
+        assert_eq!(raw(0), "0");
+        assert_eq!(raw(83_000), "83000");
+        assert_eq!(raw(90_000_000), "90000000");
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size_as(0, Style::Human), "0 B");
+        assert_eq!(format_size_as(999, Style::Human), "999 B");
+        assert_eq!(format_size_as(1_500, Style::Human), "1.50 kB");
+        assert_eq!(format_size_as(1_200_000_000, Style::Human), "1.20 GB");
+        assert_eq!(format_size_as(0, Style::Raw), "0");
+        assert_eq!(format_size_as(1_200_000_000, Style::Raw), "1200000000");
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("1024").unwrap(), 1024);
+        assert_eq!(parse_size("500MB").unwrap(), 500_000_000);
+        assert_eq!(parse_size("1.5gb").unwrap(), 1_500_000_000);
+        assert_eq!(parse_size("2 GiB").unwrap(), 2 << 30);
+        assert!(parse_size("12 parsecs").is_err());
+        assert!(parse_size("MB").is_err());
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("90s").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration(" 2m ").unwrap(), Duration::from_secs(120));
+        assert_eq!(parse_duration("1.5m").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("1h").unwrap(), Duration::from_secs(3600));
+        assert_eq!(parse_duration("0.5").unwrap(), Duration::from_millis(500));
+        for invalid in ["", "s", "0", "0s", "-5s", "2d", "90 seconds", "1e999"] {
+            assert!(parse_duration(invalid).is_err(), "{invalid:?}");
+        }
+    }
+
+    #[test]
+    fn test_millis_round_trip() -> anyhow::Result<()> {
+        #[derive(serde::Serialize, serde::Deserialize)]
+        struct Timed {
+            #[serde(with = "millis")]
+            took: Duration,
+        }
+        let json = serde_json::to_string(&Timed {
+            took: Duration::from_millis(1_234),
+        })?;
+        assert_eq!(json, r#"{"took":1234}"#);
+        let timed: Timed = serde_json::from_str(&json)?;
+        assert_eq!(timed.took, Duration::from_millis(1_234));
+        Ok(())
+    }
+}
@@ -0,0 +1,246 @@
+//! Image formats this build can read and write
+//!
+//! Decoding and most encoders come with the `image` crate's defaults. WebP and
+//! AVIF output are opt-in cargo features, `webp` (libwebp, needs a C compiler)
+//! and `avif` (the pure-Rust ravif encoder), so [`encoders`] is what a user's
+//! binary actually supports and [`ensure_writable`] names the feature to enable
+//! for anything missing.
+
+use std::{
+    borrow::Cow,
+    io::{Seek, Write},
+};
+
+use anyhow::{Context, Result, bail};
+use image::{ColorType, DynamicImage, ImageFormat, codecs::jpeg::JpegEncoder};
+
+/// JPEG quality used when none is given
+pub const DEFAULT_JPEG_QUALITY: u8 = 90;
+/// WebP quality used when none is given; 100 means lossless
+pub const DEFAULT_WEBP_QUALITY: u8 = 80;
+/// AVIF quality used when none is given
+pub const DEFAULT_AVIF_QUALITY: u8 = 70;
+/// ravif speed, 1 (slowest, smallest) to 10; 6 is its own default
+#[cfg(feature = "avif")]
+const AVIF_SPEED: u8 = 6;
+
+/// Formats whose encoder is behind one of si's cargo features, with that feature
+const FEATURE_GATED: &[(ImageFormat, &str)] =
+    &[(ImageFormat::WebP, "webp"), (ImageFormat::Avif, "avif")];
+
+/// The format named by a file extension such as `png`, `jpg` or `webp`
+pub fn parse_format(name: &str) -> Result<ImageFormat> {
+    ImageFormat::from_extension(name.trim_start_matches('.'))
+        .with_context(|| format!("Unknown image format `{name}`"))
+}
+
+/// The usual file extension for `format`
+pub fn extension(format: ImageFormat) -> &'static str {
+    format.extensions_str().first().copied().unwrap_or("img")
+}
+
+/// Serialize `format` as its [`extension`]
+pub(crate) fn serialize_format<S: serde::Serializer>(
+    format: &ImageFormat,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_str(extension(*format))
+}
+
+/// The cargo feature that compiles in the encoder for `format`, if it's optional
+pub fn cargo_feature(format: ImageFormat) -> Option<&'static str> {
+    FEATURE_GATED
+        .iter()
+        .find(|(gated, _)| *gated == format)
+        .map(|(_, feature)| *feature)
+}
+
+/// Whether encoding `format` takes a quality
+pub fn supports_quality(format: ImageFormat) -> bool {
+    matches!(
+        format,
+        ImageFormat::Jpeg | ImageFormat::WebP | ImageFormat::Avif
+    )
+}
+
+/// Formats this build can decode
+pub fn decoders() -> Vec<ImageFormat> {
+    ImageFormat::all()
+        .filter(ImageFormat::reading_enabled)
+        .collect()
+}
+
+/// Formats this build can encode
+pub fn encoders() -> Vec<ImageFormat> {
+    ImageFormat::all()
+        .filter(ImageFormat::writing_enabled)
+        .collect()
+}
+
+/// Optional encoders this build lacks, with the cargo feature for each
+pub fn missing_encoders() -> Vec<(ImageFormat, &'static str)> {
+    FEATURE_GATED
+        .iter()
+        .copied()
+        .filter(|(format, _)| !format.writing_enabled())
+        .collect()
+}
+
+/// Fail unless this build can encode `format`, naming the cargo feature that
+/// would add it
+pub fn ensure_writable(format: ImageFormat) -> Result<()> {
+    if format.writing_enabled() {
+        return Ok(());
+    }
+    match cargo_feature(format) {
+        Some(feature) => bail!(
+            "{format:?} output isn't compiled into this build; rebuild si with \
+             `--features {feature}` (see `si image formats`)"
+        ),
+        None => bail!("Unsupported output format {format:?} (see `si image formats`)"),
+    }
+}
+
+/// Encode `img` as `format` into `writer`
+///
+/// `quality` (1-100) applies to JPEG, WebP (100 is lossless) and AVIF and
+/// defaults per format; for any other format it's an error. Images are
+/// converted to the color types the encoder takes.
+pub fn write_image<W: Write + Seek>(
+    img: &DynamicImage,
+    writer: &mut W,
+    format: ImageFormat,
+    quality: Option<u8>,
+) -> Result<()> {
+    ensure_writable(format)?;
+    if quality.is_some() && !supports_quality(format) {
+        bail!("Quality only applies to JPEG, WebP and AVIF output, not {format:?}");
+    }
+    let written = match format {
+        ImageFormat::Jpeg => {
+            // JPEG has no alpha channel or 16-bit samples
+            let img = match img.color() {
+                ColorType::L8 | ColorType::Rgb8 => Cow::Borrowed(img),
+                _ => Cow::Owned(DynamicImage::ImageRgb8(img.to_rgb8())),
+            };
+            let quality = quality.unwrap_or(DEFAULT_JPEG_QUALITY);
+            img.write_with_encoder(JpegEncoder::new_with_quality(writer, quality))
+        }
+        #[cfg(feature = "webp")]
+        ImageFormat::WebP => {
+            use image::codecs::webp::{WebPEncoder, WebPQuality};
+            let quality = match quality.unwrap_or(DEFAULT_WEBP_QUALITY) {
+                100 => WebPQuality::lossless(),
+                quality => WebPQuality::lossy(quality),
+            };
+            eight_bit(img).write_with_encoder(WebPEncoder::new_with_quality(writer, quality))
+        }
+        #[cfg(feature = "avif")]
+        ImageFormat::Avif => {
+            use image::codecs::avif::AvifEncoder;
+            let quality = quality.unwrap_or(DEFAULT_AVIF_QUALITY);
+            eight_bit(img).write_with_encoder(AvifEncoder::new_with_speed_quality(
+                writer, AVIF_SPEED, quality,
+            ))
+        }
+        // ICO decoders, `image`'s included, only read back RGBA icons
+        ImageFormat::Ico => DynamicImage::ImageRgba8(img.to_rgba8()).write_to(writer, format),
+        // Farbfeld is 16-bit RGBA only
+        ImageFormat::Farbfeld => {
+            DynamicImage::ImageRgba16(img.to_rgba16()).write_to(writer, format)
+        }
+        // OpenEXR takes float samples only
+        ImageFormat::OpenExr => {
+            DynamicImage::ImageRgba32F(img.to_rgba32f()).write_to(writer, format)
+        }
+        format => img.write_to(writer, format),
+    };
+    written.with_context(|| format!("Failed to encode the image as {format:?}"))
+}
+
+/// `img` as 8-bit RGB, or RGBA when it has an alpha channel
+#[cfg(any(feature = "webp", feature = "avif"))]
+fn eight_bit(img: &DynamicImage) -> Cow<'_, DynamicImage> {
+    match img.color() {
+        ColorType::Rgb8 | ColorType::Rgba8 => Cow::Borrowed(img),
+        color if color.has_alpha() => Cow::Owned(DynamicImage::ImageRgba8(img.to_rgba8())),
+        _ => Cow::Owned(DynamicImage::ImageRgb8(img.to_rgb8())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::gradient;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_every_encoder_round_trips() -> Result<()> {
+        let img = DynamicImage::ImageRgb8(gradient(33, 17));
+        for format in encoders() {
+            let mut encoded = Cursor::new(Vec::new());
+            write_image(&img, &mut encoded, format, None)
+                .with_context(|| format!("Encoding {format:?}"))?;
+            let bytes = encoded.into_inner();
+            if !format.reading_enabled() {
+                // AVIF decoding needs dav1d, which si doesn't pull in
+                assert_eq!(image::guess_format(&bytes)?, format);
+                continue;
+            }
+            let decoded = image::load_from_memory_with_format(&bytes, format)
+                .with_context(|| format!("Decoding {format:?}"))?;
+            assert_eq!((decoded.width(), decoded.height()), (33, 17), "{format:?}");
+        }
+        assert!(encoders().contains(&ImageFormat::Png));
+        assert!(decoders().contains(&ImageFormat::WebP));
+        Ok(())
+    }
+
+    #[test]
+    fn test_quality_applies_to_lossy_formats_only() -> Result<()> {
+        let img = DynamicImage::ImageRgb8(gradient(64, 64));
+        let encode = |format, quality| -> Result<usize> {
+            let mut encoded = Cursor::new(Vec::new());
+            write_image(&img, &mut encoded, format, quality)?;
+            Ok(encoded.into_inner().len())
+        };
+        assert!(encode(ImageFormat::Jpeg, Some(10))? < encode(ImageFormat::Jpeg, Some(95))?);
+        #[cfg(feature = "webp")]
+        assert!(encode(ImageFormat::WebP, Some(10))? < encode(ImageFormat::WebP, Some(100))?);
+        #[cfg(feature = "avif")]
+        assert!(encode(ImageFormat::Avif, Some(10))? < encode(ImageFormat::Avif, Some(95))?);
+
+        let err = encode(ImageFormat::Png, Some(80)).unwrap_err();
+        assert!(err.to_string().contains("only applies to JPEG"), "{err:#}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_missing_encoder_names_the_feature() {
+        for (format, feature) in FEATURE_GATED {
+            let enabled = encoders().contains(format);
+            let result = ensure_writable(*format);
+            let missing = missing_encoders().iter().any(|(gated, _)| gated == format);
+            assert_eq!(missing, !enabled, "{format:?}");
+            assert_eq!(result.is_ok(), enabled, "{format:?}");
+            if let Err(err) = result {
+                assert!(
+                    err.to_string().contains(&format!("--features {feature}")),
+                    "{err:#}"
+                );
+            }
+        }
+        let err = ensure_writable(ImageFormat::Dds).unwrap_err();
+        assert!(err.to_string().contains("Unsupported output format"));
+    }
+
+    #[test]
+    fn test_parse_format() -> Result<()> {
+        assert_eq!(parse_format("webp")?, ImageFormat::WebP);
+        assert_eq!(parse_format(".JPG")?, ImageFormat::Jpeg);
+        assert_eq!(parse_format("avif")?, ImageFormat::Avif);
+        assert!(parse_format("heic").is_err());
+        assert_eq!(extension(ImageFormat::Jpeg), "jpg");
+        Ok(())
+    }
+}
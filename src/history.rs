@@ -0,0 +1,439 @@
+//! Append-only history of image generations, stored as JSON lines
+//!
+//! Each generation appends one line to `history.jsonl` in the project data dir.
+//! Once the file grows past [`DEFAULT_MAX_HISTORY_BYTES`] it is rotated to
+//! `history.jsonl.1` (replacing any previous rotation), so at most two files
+//! are kept on disk. Generations may run concurrently, in one process or
+//! several, so [`History::record`] holds `history.jsonl.lock` while it picks the
+//! next id and appends.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use fs4::fs_std::FileExt;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+#[cfg(feature = "image-pipeline")]
+use crate::{Crop, TryOnRequest};
+use crate::{ParsedPrompt, Region, RemoteInput, mask::RegionMask, paths};
+
+const HISTORY_FILENAME: &str = "history.jsonl";
+pub const DEFAULT_MAX_HISTORY_BYTES: u64 = 5 * 1024 * 1024;
+/// How much of the end of a file is read at first when looking for its last record
+const TAIL_BYTES: u64 = 16 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// Sequential id assigned by [`History::record`]
+    #[serde(default)]
+    pub id: u64,
+    pub timestamp: DateTime<Utc>,
+    pub prompt: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub negative_prompt: Option<String>,
+    /// `prompt` and `negative_prompt` as the pipeline understood them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parsed_prompt: Option<ParsedPrompt>,
+    pub model: String,
+    /// Content hash of a model used from a local path
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model_hash: Option<String>,
+    pub input: PathBuf,
+    pub output: PathBuf,
+    pub strength: Option<f64>,
+    /// Color strength that was applied, whether given or from `strength`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color_strength: Option<f64>,
+    /// Style strength that was applied, whether given or from `strength`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub style_strength: Option<f64>,
+    /// Reference garment image used for color transfer
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reference: Option<PathBuf>,
+    /// The URL `input` was downloaded from, with the hash of what it served
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote_input: Option<RemoteInput>,
+    /// Region of the input that was processed, when it was cropped
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crop: Option<Region>,
+    /// Polygon the transformation was limited to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mask: Option<RegionMask>,
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// Shared by entries produced together, e.g. the passes of a strength ramp
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    pub duration_ms: u64,
+    pub success: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[cfg(feature = "image-pipeline")]
+impl HistoryEntry {
+    /// Rebuild the request that produced this entry, optionally writing elsewhere
+    pub fn to_request(&self, output_override: Option<PathBuf>) -> TryOnRequest {
+        TryOnRequest {
+            input_image_path: self.input.clone(),
+            clothing_description: self.prompt.clone(),
+            negative_prompt: self.negative_prompt.clone(),
+            output_path: output_override.unwrap_or_else(|| self.output.clone()),
+            model_name: Some(self.model.clone()),
+            strength: self.strength,
+            color_strength: self.color_strength,
+            style_strength: self.style_strength,
+            reference_image: self.reference.clone(),
+            crop: self.crop.map(|region| Crop::Region(region.into())),
+            mask: self.mask.clone(),
+            transform_plan: None,
+            comparison: None,
+            remote_input: self.remote_input.clone(),
+            seed: self.seed,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct History {
+    path: PathBuf,
+    max_bytes: u64,
+}
+
+impl History {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            max_bytes: DEFAULT_MAX_HISTORY_BYTES,
+        }
+    }
+
+    pub fn default_path() -> Result<PathBuf> {
+        Ok(paths::data_dir()
+            .context("History directory is not set")?
+            .join(HISTORY_FILENAME))
+    }
+
+    pub fn with_max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    #[cfg(test)]
+    fn rotated_path(&self) -> PathBuf {
+        rotated_path(&self.path)
+    }
+
+    /// Append an entry, assigning it the next id
+    pub fn record(&self, mut entry: HistoryEntry) -> Result<HistoryEntry> {
+        let _lock = self.lock()?;
+        entry.id = self.last_id()?.map_or(1, |id| id + 1);
+        append_json_line(&self.path, self.max_bytes, &entry)?;
+        debug!("Recorded history entry {}", entry.id);
+        Ok(entry)
+    }
+
+    pub fn get(&self, id: u64) -> Result<Option<HistoryEntry>> {
+        Ok(self.entries()?.into_iter().find(|e| e.id == id))
+    }
+
+    /// Entries newest first, optionally limited to the `limit` most recent
+    pub fn list(&self, limit: Option<usize>) -> Result<Vec<HistoryEntry>> {
+        let mut entries = self.entries()?;
+        entries.reverse();
+        if let Some(limit) = limit {
+            entries.truncate(limit);
+        }
+        Ok(entries)
+    }
+
+    /// All entries, oldest first, across the rotated and current files
+    pub fn entries(&self) -> Result<Vec<HistoryEntry>> {
+        read_json_lines(&self.path)
+    }
+
+    /// The id of the newest entry, from the end of the current file or, right
+    /// after a rotation, the rotated one
+    fn last_id(&self) -> Result<Option<u64>> {
+        for path in [self.path.clone(), rotated_path(&self.path)] {
+            if let Some(entry) = last_record::<HistoryEntry>(&path)? {
+                return Ok(Some(entry.id));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Take the history's lock file exclusively, waiting for other recorders;
+    /// released when the returned file is closed
+    fn lock(&self) -> Result<File> {
+        let mut name = self.path.as_os_str().to_owned();
+        name.push(".lock");
+        let path = PathBuf::from(name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory {}", parent.display()))?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        file.lock_exclusive()
+            .with_context(|| format!("Failed to lock {}", path.display()))?;
+        Ok(file)
+    }
+}
+
+/// Where a JSON lines file at `path` is moved once it outgrows its size limit
+fn rotated_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".1");
+    PathBuf::from(name)
+}
+
+/// Append `value` as one line to the JSON lines file at `path`, first rotating
+/// the file if it has reached `max_bytes`
+pub(crate) fn append_json_line<T: Serialize>(path: &Path, max_bytes: u64, value: &T) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory {}", parent.display()))?;
+    }
+    let size = fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    if size >= max_bytes {
+        debug!("Rotating {}", path.display());
+        fs::rename(path, rotated_path(path))
+            .with_context(|| format!("Failed to rotate {}", path.display()))?;
+    }
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    let line = serde_json::to_string(value).context("Failed to serialize record")?;
+    writeln!(file, "{line}").with_context(|| format!("Failed to write to {}", path.display()))
+}
+
+/// Every record of the JSON lines file at `path`, oldest first, across the
+/// rotated and current files; malformed lines are skipped with a warning
+pub(crate) fn read_json_lines<T: DeserializeOwned>(path: &Path) -> Result<Vec<T>> {
+    let mut records = read_entries(&rotated_path(path))?;
+    records.extend(read_entries(path)?);
+    Ok(records)
+}
+
+/// The last well-formed record of the JSON lines file at `path`, reading back
+/// from its end rather than parsing the whole file
+fn last_record<T: DeserializeOwned>(path: &Path) -> Result<Option<T>> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(_) => return Ok(None),
+    };
+    let len = file
+        .metadata()
+        .with_context(|| format!("Failed to read {}", path.display()))?
+        .len();
+    let mut window = TAIL_BYTES;
+    loop {
+        let start = len.saturating_sub(window);
+        let mut tail = Vec::new();
+        file.seek(SeekFrom::Start(start))
+            .and_then(|_| file.read_to_end(&mut tail))
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let mut lines = tail.split(|&b| b == b'\n');
+        if start > 0 {
+            // The window may begin mid-line
+            lines.next();
+        }
+        let last = lines
+            .rev()
+            .filter(|line| !line.trim_ascii().is_empty())
+            .find_map(|line| serde_json::from_slice(line).ok());
+        if last.is_some() || start == 0 {
+            return Ok(last);
+        }
+        window *= 2;
+    }
+}
+
+fn read_entries<T: DeserializeOwned>(path: &Path) -> Result<Vec<T>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(_) => return Ok(vec![]),
+    };
+
+    let mut entries = Vec::new();
+    for (n, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.with_context(|| format!("Failed to read {}", path.display()))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(&line) {
+            Ok(entry) => entries.push(entry),
+            Err(e) => warn!(
+                "Skipping malformed line {} in {}: {e}",
+                n + 1,
+                path.display()
+            ),
+        }
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn entry(prompt: &str) -> HistoryEntry {
+        HistoryEntry {
+            id: 0,
+            timestamp: Utc::now(),
+            prompt: prompt.to_string(),
+            negative_prompt: None,
+            parsed_prompt: None,
+            model: "org/model".to_string(),
+            model_hash: None,
+            input: PathBuf::from("/photos/in.jpg"),
+            output: PathBuf::from("/photos/out.png"),
+            strength: Some(0.5),
+            color_strength: None,
+            style_strength: None,
+            reference: None,
+            remote_input: None,
+            crop: None,
+            mask: None,
+            seed: None,
+            group: None,
+            duration_ms: 120,
+            success: true,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn test_record_assigns_sequential_ids() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let history = History::new(temp_dir.path().join("history.jsonl"));
+
+        let first = history.record(entry("red dress"))?;
+        let second = history.record(entry("blue dress"))?;
+
+        assert_eq!(first.id, 1);
+        assert_eq!(second.id, 2);
+        assert_eq!(history.get(2)?.unwrap().prompt, "blue dress");
+        assert_eq!(history.get(3)?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_concurrent_records_get_distinct_ids() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let history = History::new(temp_dir.path().join("history.jsonl"));
+
+        let mut ids: Vec<u64> = std::thread::scope(|scope| {
+            let threads: Vec<_> = (0..4)
+                .map(|_| {
+                    scope.spawn(|| {
+                        (0..10)
+                            .map(|_| history.record(entry("red dress")).map(|e| e.id))
+                            .collect::<Result<Vec<_>>>()
+                    })
+                })
+                .collect();
+            threads
+                .into_iter()
+                .map(|thread| thread.join().expect("recording thread"))
+                .collect::<Result<Vec<_>>>()
+        })?
+        .concat();
+
+        ids.sort_unstable();
+        assert_eq!(ids, (1..=40).collect::<Vec<_>>());
+        assert_eq!(history.entries()?.len(), 40);
+        Ok(())
+    }
+
+    #[test]
+    fn test_last_id_reads_past_a_long_tail() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("history.jsonl");
+        let history = History::new(path.clone());
+        history.record(entry("red dress"))?;
+        history.record(entry(&"x".repeat(3 * TAIL_BYTES as usize)))?;
+        fs::write(&path, format!("{}not json\n", fs::read_to_string(&path)?))?;
+
+        assert_eq!(history.record(entry("blue dress"))?.id, 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_list_with_limit_is_newest_first() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let history = History::new(temp_dir.path().join("history.jsonl"));
+        for prompt in ["one", "two", "three", "four"] {
+            history.record(entry(prompt))?;
+        }
+
+        let recent = history.list(Some(2))?;
+        let prompts: Vec<_> = recent.iter().map(|e| e.prompt.as_str()).collect();
+        assert_eq!(prompts, ["four", "three"]);
+        assert_eq!(history.list(None)?.len(), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rotation_keeps_ids_and_lookups() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let history = History::new(temp_dir.path().join("history.jsonl")).with_max_bytes(1);
+
+        history.record(entry("one"))?;
+        history.record(entry("two"))?;
+        let third = history.record(entry("three"))?;
+
+        assert_eq!(third.id, 3);
+        assert!(history.rotated_path().exists());
+        // Only one rotation is kept, so the oldest entry is gone
+        assert_eq!(history.get(1)?, None);
+        assert_eq!(history.get(2)?.unwrap().prompt, "two");
+        Ok(())
+    }
+
+    #[test]
+    fn test_malformed_lines_are_skipped() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("history.jsonl");
+        let history = History::new(path.clone());
+        history.record(entry("ok"))?;
+        fs::write(&path, format!("{}not json\n", fs::read_to_string(&path)?))?;
+
+        assert_eq!(history.list(None)?.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "image-pipeline")]
+    fn test_to_request_overrides_output() {
+        let entry = entry("red dress");
+        let request = entry.to_request(Some(PathBuf::from("/tmp/new.png")));
+
+        assert_eq!(request.clothing_description, "red dress");
+        assert_eq!(request.model_name.as_deref(), Some("org/model"));
+        assert_eq!(request.output_path, PathBuf::from("/tmp/new.png"));
+        assert_eq!(
+            entry.to_request(None).output_path,
+            PathBuf::from("/photos/out.png")
+        );
+    }
+}
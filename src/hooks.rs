@@ -0,0 +1,373 @@
+//! External commands run around generation
+//!
+//! Hooks are configured under `[hooks]` in `config.toml`:
+//!
+//! ```toml
+//! [hooks.post_generate]
+//! command = "upscale --in {output} --out {output}"
+//! on_failure = "warn"
+//! timeout_secs = 120
+//! ```
+//!
+//! Commands are run through the platform shell. Placeholders are replaced with
+//! shell-quoted values: `{input}`, `{output}`, `{prompt}` and `{model}`.
+
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    process::Stdio,
+    str::FromStr,
+    time::Duration,
+};
+
+use anyhow::{Context, Result, bail};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+
+/// How long a hook may run when `timeout_secs` isn't set
+pub const DEFAULT_HOOK_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Points in the generation pipeline where hooks run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookEvent {
+    /// Before the input image is processed
+    PreGenerate,
+    /// After an output image has been written
+    PostGenerate,
+}
+
+impl HookEvent {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::PreGenerate => "pre_generate",
+            Self::PostGenerate => "post_generate",
+        }
+    }
+}
+
+impl fmt::Display for HookEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for HookEvent {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "pre_generate" => Ok(Self::PreGenerate),
+            "post_generate" => Ok(Self::PostGenerate),
+            other => bail!("Unknown hook `{other}`, expected pre_generate or post_generate"),
+        }
+    }
+}
+
+/// What to do when a hook fails or times out
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FailurePolicy {
+    /// Fail the generation
+    #[default]
+    Abort,
+    /// Log a warning and carry on
+    Warn,
+}
+
+impl FromStr for FailurePolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "abort" => Ok(Self::Abort),
+            "warn" => Ok(Self::Warn),
+            other => bail!("Unknown failure policy `{other}`, expected abort or warn"),
+        }
+    }
+}
+
+impl fmt::Display for FailurePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Abort => "abort",
+            Self::Warn => "warn",
+        })
+    }
+}
+
+/// One configured hook command
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Hook {
+    /// Command template, run through the shell
+    pub command: String,
+    #[serde(default)]
+    pub on_failure: FailurePolicy,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_secs: Option<u64>,
+}
+
+impl Hook {
+    pub fn new(command: impl Into<String>) -> Self {
+        Self {
+            command: command.into(),
+            ..Default::default()
+        }
+    }
+
+    fn timeout(&self) -> Duration {
+        self.timeout_secs
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_HOOK_TIMEOUT)
+    }
+}
+
+/// The `[hooks]` table of the config
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Hooks {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pre_generate: Option<Hook>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post_generate: Option<Hook>,
+}
+
+impl Hooks {
+    pub fn is_empty(&self) -> bool {
+        self.pre_generate.is_none() && self.post_generate.is_none()
+    }
+
+    pub fn get(&self, event: HookEvent) -> Option<&Hook> {
+        match event {
+            HookEvent::PreGenerate => self.pre_generate.as_ref(),
+            HookEvent::PostGenerate => self.post_generate.as_ref(),
+        }
+    }
+
+    pub fn get_mut(&mut self, event: HookEvent) -> &mut Option<Hook> {
+        match event {
+            HookEvent::PreGenerate => &mut self.pre_generate,
+            HookEvent::PostGenerate => &mut self.post_generate,
+        }
+    }
+}
+
+/// Values substituted into hook command templates
+#[derive(Debug, Clone, Default)]
+pub struct HookContext {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    pub prompt: String,
+    pub model: String,
+}
+
+impl HookContext {
+    pub fn new(input: &Path, output: &Path, prompt: &str, model: &str) -> Self {
+        Self {
+            input: input.to_path_buf(),
+            output: output.to_path_buf(),
+            prompt: prompt.to_string(),
+            model: model.to_string(),
+        }
+    }
+
+    /// Replace placeholders in `template` with shell-quoted values
+    fn render(&self, template: &str) -> String {
+        template
+            .replace("{input}", &shell_quote(&self.input.to_string_lossy()))
+            .replace("{output}", &shell_quote(&self.output.to_string_lossy()))
+            .replace("{prompt}", &shell_quote(&self.prompt))
+            .replace("{model}", &shell_quote(&self.model))
+    }
+}
+
+/// Runs the configured hooks, or nothing when disabled
+#[derive(Debug, Clone, Default)]
+pub struct HookRunner {
+    hooks: Hooks,
+}
+
+impl HookRunner {
+    pub fn new(hooks: Hooks) -> Self {
+        Self { hooks }
+    }
+
+    /// A runner that never runs anything, for `--no-hooks`
+    pub fn disabled() -> Self {
+        Self::default()
+    }
+
+    /// Run the hook for `event`, if one is configured
+    ///
+    /// Failures (non-zero exit, spawn errors, timeouts) are returned as errors
+    /// under the `abort` policy and logged under `warn`.
+    pub async fn run(&self, event: HookEvent, ctx: &HookContext) -> Result<()> {
+        let Some(hook) = self.hooks.get(event) else {
+            return Ok(());
+        };
+
+        match run_hook(event, hook, ctx).await {
+            Ok(()) => Ok(()),
+            Err(e) if hook.on_failure == FailurePolicy::Warn => {
+                warn!("{e:#}");
+                Ok(())
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+async fn run_hook(event: HookEvent, hook: &Hook, ctx: &HookContext) -> Result<()> {
+    let command = ctx.render(&hook.command);
+    debug!("Running {event} hook: {command}");
+
+    let child = shell_command(&command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("Failed to start {event} hook `{command}`"))?;
+
+    let output = match tokio::time::timeout(hook.timeout(), child.wait_with_output()).await {
+        Ok(output) => output.with_context(|| format!("Failed to run {event} hook"))?,
+        Err(_) => bail!(
+            "{event} hook timed out after {}s",
+            hook.timeout().as_secs_f64()
+        ),
+    };
+
+    debug!(
+        "{event} hook stdout: {}",
+        String::from_utf8_lossy(&output.stdout).trim_end()
+    );
+    debug!(
+        "{event} hook stderr: {}",
+        String::from_utf8_lossy(&output.stderr).trim_end()
+    );
+    if !output.status.success() {
+        bail!("{event} hook failed ({}): {command}", output.status);
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn shell_command(command: &str) -> Command {
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c").arg(command);
+    cmd
+}
+
+#[cfg(windows)]
+fn shell_command(command: &str) -> Command {
+    let mut cmd = Command::new("cmd");
+    cmd.arg("/C").arg(command);
+    cmd
+}
+
+#[cfg(unix)]
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+#[cfg(windows)]
+fn shell_quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('"', "\"\""))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    fn ctx(dir: &Path) -> HookContext {
+        HookContext::new(
+            &dir.join("in put.png"),
+            &dir.join("out.png"),
+            "a 'red' dress",
+            "org/model",
+        )
+    }
+
+    #[tokio::test]
+    async fn test_post_hook_touches_marker() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let script = temp_dir.path().join("touch.sh");
+        fs::write(
+            &script,
+            "#!/bin/sh\nprintf '%s|%s' \"$1\" \"$2\" > \"$3.marker\"\n",
+        )?;
+
+        let runner = HookRunner::new(Hooks {
+            post_generate: Some(Hook::new(format!(
+                "sh {} {{prompt}} {{model}} {{output}}",
+                script.display()
+            ))),
+            ..Default::default()
+        });
+        runner
+            .run(HookEvent::PostGenerate, &ctx(temp_dir.path()))
+            .await?;
+
+        let marker = temp_dir.path().join("out.png.marker");
+        assert_eq!(fs::read_to_string(marker)?, "a 'red' dress|org/model");
+
+        // Nothing is configured for pre_generate
+        runner
+            .run(HookEvent::PreGenerate, &ctx(temp_dir.path()))
+            .await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_failing_hook_policies() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let script = temp_dir.path().join("fail.sh");
+        fs::write(&script, "#!/bin/sh\necho broken >&2\nexit 3\n")?;
+        let failing = Hook::new(format!("sh {}", script.display()));
+
+        let abort = HookRunner::new(Hooks {
+            pre_generate: Some(failing.clone()),
+            ..Default::default()
+        });
+        let err = abort
+            .run(HookEvent::PreGenerate, &ctx(temp_dir.path()))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("pre_generate hook failed"));
+
+        let warn = HookRunner::new(Hooks {
+            pre_generate: Some(Hook {
+                on_failure: FailurePolicy::Warn,
+                ..failing
+            }),
+            ..Default::default()
+        });
+        warn.run(HookEvent::PreGenerate, &ctx(temp_dir.path()))
+            .await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_hook_timeout() {
+        let runner = HookRunner::new(Hooks {
+            post_generate: Some(Hook {
+                timeout_secs: Some(0),
+                ..Hook::new("sleep 5")
+            }),
+            ..Default::default()
+        });
+        let err = runner
+            .run(HookEvent::PostGenerate, &HookContext::default())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("timed out"));
+    }
+
+    #[tokio::test]
+    async fn test_disabled_runner_runs_nothing() -> Result<()> {
+        HookRunner::disabled()
+            .run(HookEvent::PostGenerate, &HookContext::default())
+            .await
+    }
+}
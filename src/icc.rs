@@ -0,0 +1,154 @@
+//! ICC color profiles in PNG and JPEG files
+//!
+//! The decoders hand a file's profile over through
+//! [`ImageDecoder::icc_profile`], but the encoders can't write one, so [`embed`]
+//! splices it into the encoded bytes instead: an `iCCP` chunk after a PNG's
+//! header chunk, `APP2` segments after a JPEG's start (and JFIF) marker. The
+//! pixels are left alone; only the tag travels.
+
+use std::io::{Cursor, Write};
+
+use anyhow::{Context, Result, bail};
+use flate2::{Compression, Crc, write::ZlibEncoder};
+use image::{
+    ImageDecoder, ImageFormat,
+    codecs::{jpeg::JpegDecoder, png::PngDecoder},
+};
+
+/// Name the `iCCP` chunk gives the profile; readers ignore it
+const PNG_PROFILE_NAME: &[u8] = b"ICC Profile";
+/// Signature opening every JPEG `APP2` segment that holds part of a profile
+const JPEG_SIGNATURE: &[u8] = b"ICC_PROFILE\0";
+/// Profile bytes one `APP2` segment holds: the 65535-byte segment maximum minus
+/// the length field, the signature and the sequence number and count
+const JPEG_CHUNK_LEN: usize = 65535 - 2 - JPEG_SIGNATURE.len() - 2;
+
+/// Whether [`embed`] can put a profile into `format`
+pub fn can_embed(format: ImageFormat) -> bool {
+    matches!(format, ImageFormat::Png | ImageFormat::Jpeg)
+}
+
+/// The profile of an encoded image, if its format is one that carries one and
+/// it has one
+pub fn read(bytes: &[u8], format: ImageFormat) -> Option<Vec<u8>> {
+    let profile = match format {
+        ImageFormat::Png => PngDecoder::new(Cursor::new(bytes)).ok()?.icc_profile(),
+        ImageFormat::Jpeg => JpegDecoder::new(Cursor::new(bytes)).ok()?.icc_profile(),
+        _ => None,
+    };
+    profile.filter(|profile| !profile.is_empty())
+}
+
+/// `encoded`, a PNG or JPEG as the encoders write it, with `profile` added
+pub fn embed(encoded: &[u8], format: ImageFormat, profile: &[u8]) -> Result<Vec<u8>> {
+    match format {
+        ImageFormat::Png => embed_png(encoded, profile),
+        ImageFormat::Jpeg => embed_jpeg(encoded, profile),
+        other => bail!("{other:?} images can't carry an ICC profile"),
+    }
+}
+
+fn embed_png(png: &[u8], profile: &[u8]) -> Result<Vec<u8>> {
+    // The signature, then IHDR: length, type, 13 bytes of data and the CRC
+    const IHDR_END: usize = 8 + 4 + 4 + 13 + 4;
+    if png.len() < IHDR_END || &png[12..16] != b"IHDR" {
+        bail!("Not a PNG starting with its header chunk");
+    }
+    let data = png_chunk(profile)?;
+    let len = u32::try_from(data.len()).context("ICC profile is too large for a PNG")?;
+
+    let mut crc = Crc::new();
+    crc.update(b"iCCP");
+    crc.update(&data);
+    let mut out = Vec::with_capacity(png.len() + data.len() + 12);
+    out.extend(&png[..IHDR_END]);
+    out.extend(len.to_be_bytes());
+    out.extend(b"iCCP");
+    out.extend(&data);
+    out.extend(crc.sum().to_be_bytes());
+    out.extend(&png[IHDR_END..]);
+    Ok(out)
+}
+
+/// The data of a PNG `iCCP` chunk holding `profile`, for encoders that write
+/// their own chunks
+pub fn png_chunk(profile: &[u8]) -> Result<Vec<u8>> {
+    let mut data = PNG_PROFILE_NAME.to_vec();
+    // The name's terminator and compression method 0, zlib
+    data.extend([0, 0]);
+    let mut encoder = ZlibEncoder::new(data, Compression::default());
+    encoder.write_all(profile)?;
+    Ok(encoder.finish()?)
+}
+
+fn embed_jpeg(jpeg: &[u8], profile: &[u8]) -> Result<Vec<u8>> {
+    if !jpeg.starts_with(&[0xFF, 0xD8]) {
+        bail!("Not a JPEG");
+    }
+    // Readers expect a JFIF APP0 to come first
+    let mut at = 2;
+    if jpeg.get(2..4) == Some(&[0xFF, 0xE0]) {
+        let len = jpeg
+            .get(4..6)
+            .map(|len| u16::from_be_bytes([len[0], len[1]]))
+            .context("Truncated JPEG header")?;
+        at = 4 + usize::from(len);
+    }
+    let chunks: Vec<_> = profile.chunks(JPEG_CHUNK_LEN).collect();
+    let count = u8::try_from(chunks.len()).context("ICC profile is too large for a JPEG")?;
+
+    let mut out = Vec::with_capacity(jpeg.len() + profile.len() + 18 * chunks.len());
+    out.extend(&jpeg[..at]);
+    for (i, chunk) in chunks.iter().enumerate() {
+        out.extend([0xFF, 0xE2]);
+        out.extend(((chunk.len() + JPEG_SIGNATURE.len() + 4) as u16).to_be_bytes());
+        out.extend(JPEG_SIGNATURE);
+        out.extend([i as u8 + 1, count]);
+        out.extend(*chunk);
+    }
+    out.extend(&jpeg[at..]);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    /// Not a valid profile, but nothing between reading and embedding looks
+    /// inside one
+    fn dummy_profile(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 31 % 251) as u8).collect()
+    }
+
+    fn encoded(format: ImageFormat) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        RgbImage::from_pixel(8, 8, Rgb([10, 200, 30]))
+            .write_to(&mut Cursor::new(&mut bytes), format)?;
+        Ok(bytes)
+    }
+
+    #[test]
+    fn test_embedded_profiles_read_back() -> Result<()> {
+        // The large one spans three JPEG segments
+        for profile in [dummy_profile(560), dummy_profile(150_000)] {
+            for format in [ImageFormat::Png, ImageFormat::Jpeg] {
+                let plain = encoded(format)?;
+                assert_eq!(read(&plain, format), None);
+                let tagged = embed(&plain, format, &profile)?;
+                assert_eq!(read(&tagged, format).as_ref(), Some(&profile), "{format:?}");
+                let image = image::load_from_memory_with_format(&tagged, format)?;
+                assert_eq!((image.width(), image.height()), (8, 8));
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_formats_without_profiles() -> Result<()> {
+        assert!(!can_embed(ImageFormat::Gif));
+        assert!(embed(&encoded(ImageFormat::Bmp)?, ImageFormat::Bmp, b"icc").is_err());
+        assert!(embed(b"not a png", ImageFormat::Png, b"icc").is_err());
+        Ok(())
+    }
+}
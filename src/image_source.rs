@@ -0,0 +1,436 @@
+//! Input images given as a local path or an HTTP(S) URL
+//!
+//! `si image generate --input` takes either, as an [`ImageSource`]. A URL is
+//! fetched up front with [`fetch`] and saved under [`inputs_dir`], named by the
+//! hash of its content, so the rest of the pipeline reads it like any other
+//! file and the [`RemoteInput`] on the request records where it came from.
+
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+#[cfg(all(feature = "hub", feature = "image-pipeline"))]
+use anyhow::{Context, anyhow};
+use anyhow::{Result, bail};
+#[cfg(all(feature = "hub", feature = "image-pipeline"))]
+use reqwest::{StatusCode, header::CONTENT_TYPE, redirect::Policy};
+use serde::{Deserialize, Serialize};
+#[cfg(all(feature = "hub", feature = "image-pipeline"))]
+use sha2::{Digest, Sha256};
+
+use crate::paths;
+#[cfg(all(feature = "hub", feature = "image-pipeline"))]
+use crate::{disk_cache::hex, format::format_size, formats};
+
+/// Largest image [`fetch`] downloads by default
+pub const DEFAULT_MAX_BYTES: u64 = 50_000_000;
+/// How long [`fetch`] waits for a whole download by default
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+/// Redirects [`fetch`] follows by default before giving up
+pub const DEFAULT_MAX_REDIRECTS: usize = 5;
+
+/// Where an input image comes from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImageSource {
+    /// A local file, or `-` for stdin
+    Path(PathBuf),
+    /// An `http://` or `https://` URL
+    Url(String),
+}
+
+impl ImageSource {
+    /// The local path, for a source that isn't a URL
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Self::Path(path) => Some(path),
+            Self::Url(_) => None,
+        }
+    }
+}
+
+impl From<PathBuf> for ImageSource {
+    fn from(path: PathBuf) -> Self {
+        Self::Path(path)
+    }
+}
+
+impl FromStr for ImageSource {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let Some((scheme, rest)) = s.split_once("://") else {
+            return Ok(Self::Path(PathBuf::from(s)));
+        };
+        // Anything else with a scheme is a typo or a protocol si can't fetch,
+        // not a file name
+        if !scheme.eq_ignore_ascii_case("http") && !scheme.eq_ignore_ascii_case("https") {
+            bail!("Unsupported URL `{s}`: only http:// and https:// inputs can be fetched");
+        }
+        let host = rest.split(['/', '?', '#']).next().unwrap_or_default();
+        if host.is_empty() || s.contains(char::is_whitespace) {
+            bail!("Invalid URL `{s}`");
+        }
+        Ok(Self::Url(s.to_string()))
+    }
+}
+
+impl fmt::Display for ImageSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Path(path) => write!(f, "{}", path.display()),
+            Self::Url(url) => f.write_str(url),
+        }
+    }
+}
+
+/// The URL an input image was downloaded from and the SHA-256 of what it
+/// returned
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemoteInput {
+    pub url: String,
+    pub sha256: String,
+}
+
+/// Limits on [`fetch`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FetchOptions {
+    /// Largest response accepted, in bytes
+    pub max_bytes: u64,
+    /// Time allowed for the whole request, body included
+    pub timeout: Duration,
+    /// Redirects followed before failing
+    pub max_redirects: usize,
+}
+
+impl Default for FetchOptions {
+    fn default() -> Self {
+        Self {
+            max_bytes: DEFAULT_MAX_BYTES,
+            timeout: DEFAULT_TIMEOUT,
+            max_redirects: DEFAULT_MAX_REDIRECTS,
+        }
+    }
+}
+
+/// Where fetched inputs are saved: `inputs` under [`paths::cache_dir`]
+pub fn inputs_dir() -> Result<PathBuf> {
+    Ok(paths::cache_dir()?.join("inputs"))
+}
+
+/// Download the image at `url` into `dir`, returning where it was saved
+///
+/// The response must be a 200 whose content type, when it names one, is an
+/// image; either way the body has to look like an image format si decodes.
+/// It's saved as `<dir>/<hash prefix>/<last URL segment>`, so fetching the same
+/// image again reuses the file and output names still follow the URL.
+#[cfg(all(feature = "hub", feature = "image-pipeline"))]
+pub async fn fetch(
+    url: &str,
+    dir: &Path,
+    options: &FetchOptions,
+) -> Result<(PathBuf, RemoteInput)> {
+    let client = reqwest::Client::builder()
+        .redirect(Policy::limited(options.max_redirects))
+        .timeout(options.timeout)
+        .user_agent(concat!("si/", env!("CARGO_PKG_VERSION")))
+        .build()
+        .context("Failed to create the HTTP client")?;
+    let mut response = client.get(url).send().await.map_err(|e| {
+        let reason = if e.is_redirect() {
+            format!("more than {} redirects", options.max_redirects)
+        } else if e.is_timeout() {
+            format!("no response within {:?}", options.timeout)
+        } else {
+            format!("{e}")
+        };
+        anyhow!("Failed to fetch {url}: {reason}")
+    })?;
+
+    let status = response.status();
+    if status != StatusCode::OK {
+        bail!("Failed to fetch {url}: the server answered {status}");
+    }
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| {
+            value
+                .split(';')
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .to_ascii_lowercase()
+        });
+    // Servers often label images as generic binary data; the bytes decide then
+    if let Some(content_type) = content_type
+        .as_deref()
+        .filter(|ct| !ct.starts_with("image/") && *ct != "application/octet-stream")
+    {
+        bail!("{url} is not an image: the server sent {content_type}");
+    }
+    let too_large = || {
+        anyhow!(
+            "{url} is larger than the {} limit for input images",
+            format_size(options.max_bytes)
+        )
+    };
+    if response
+        .content_length()
+        .is_some_and(|length| length > options.max_bytes)
+    {
+        return Err(too_large());
+    }
+
+    let mut bytes = Vec::new();
+    while let Some(chunk) = response.chunk().await.map_err(|e| {
+        if e.is_timeout() {
+            anyhow!(
+                "Failed to fetch {url}: not done within {:?}",
+                options.timeout
+            )
+        } else {
+            anyhow!("Failed to fetch {url}: {e}")
+        }
+    })? {
+        if (bytes.len() + chunk.len()) as u64 > options.max_bytes {
+            return Err(too_large());
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    let format = image::guess_format(&bytes)
+        .map_err(|_| anyhow!("{url} is not an image in a format si can read"))?;
+
+    let sha256 = hex(&Sha256::digest(&bytes));
+    let path = dir
+        .join(&sha256[..16])
+        .join(file_name(url, formats::extension(format)));
+    if !path.exists() {
+        let parent = path.parent().unwrap_or(dir);
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+        let partial = path.with_extension(format!("{}.partial", std::process::id()));
+        std::fs::write(&partial, &bytes)
+            .and_then(|()| std::fs::rename(&partial, &path))
+            .inspect_err(|_| {
+                let _ = std::fs::remove_file(&partial);
+            })
+            .with_context(|| format!("Failed to save {url} to {}", path.display()))?;
+    }
+    Ok((
+        path,
+        RemoteInput {
+            url: url.to_string(),
+            sha256,
+        },
+    ))
+}
+
+/// A safe file name for what `url` serves: its last path segment, or `image`,
+/// with `extension` added when it has none
+#[cfg(all(feature = "hub", feature = "image-pipeline"))]
+fn file_name(url: &str, extension: &str) -> String {
+    let path = url
+        .split_once("://")
+        .map_or(url, |(_, rest)| rest)
+        .split(['?', '#'])
+        .next()
+        .unwrap_or_default();
+    let segment = path
+        .split_once('/')
+        .map_or("", |(_, path)| path.rsplit('/').next().unwrap_or_default());
+    let name: String = segment
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let name = name.trim_start_matches('.');
+    match name {
+        "" => format!("image.{extension}"),
+        name if Path::new(name).extension().is_none() => format!("{name}.{extension}"),
+        name => name.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_image_source() -> Result<()> {
+        assert_eq!(
+            "photo.jpg".parse::<ImageSource>()?,
+            ImageSource::Path("photo.jpg".into())
+        );
+        assert_eq!("-".parse::<ImageSource>()?, ImageSource::Path("-".into()));
+        assert_eq!(
+            "https://example.com/dress.jpg".parse::<ImageSource>()?,
+            ImageSource::Url("https://example.com/dress.jpg".into())
+        );
+        assert_eq!("HTTP://example.com".parse::<ImageSource>()?.path(), None);
+        assert!(
+            "ftp://example.com/dress.jpg"
+                .parse::<ImageSource>()
+                .is_err()
+        );
+        assert!("https:///dress.jpg".parse::<ImageSource>().is_err());
+        Ok(())
+    }
+
+    #[cfg(all(feature = "hub", feature = "image-pipeline"))]
+    #[test]
+    fn test_file_name_follows_the_url() {
+        assert_eq!(
+            file_name("https://example.com/shop/dress.jpg", "png"),
+            "dress.jpg"
+        );
+        assert_eq!(file_name("https://example.com/img?id=3", "png"), "img.png");
+        assert_eq!(file_name("https://example.com/", "jpg"), "image.jpg");
+        assert_eq!(file_name("https://example.com", "jpg"), "image.jpg");
+        assert_eq!(
+            file_name("https://example.com/a%20b/..%2Fetc.png#x", "png"),
+            "_2Fetc.png"
+        );
+    }
+
+    #[cfg(all(feature = "hub", feature = "server"))]
+    mod fetch {
+        use super::*;
+        use crate::test_support::gradient;
+        use axum::{
+            Router,
+            http::{StatusCode, header},
+            response::{IntoResponse, Redirect},
+            routing::get,
+        };
+        use image::{DynamicImage, ImageFormat};
+        use std::io::Cursor;
+        use tempfile::tempdir;
+
+        fn png() -> Vec<u8> {
+            let mut png = Cursor::new(Vec::new());
+            DynamicImage::ImageRgb8(gradient(8, 8))
+                .write_to(&mut png, ImageFormat::Png)
+                .expect("encode png");
+            png.into_inner()
+        }
+
+        /// A server on a free local port; returns its base URL
+        async fn serve() -> Result<String> {
+            let app = Router::new()
+                .route(
+                    "/dress.png",
+                    get(|| async { ([(header::CONTENT_TYPE, "image/png")], png()) }),
+                )
+                .route(
+                    "/untyped",
+                    get(|| async { ([(header::CONTENT_TYPE, "application/octet-stream")], png()) }),
+                )
+                .route(
+                    "/page.html",
+                    get(|| async {
+                        (
+                            [(header::CONTENT_TYPE, "text/html; charset=utf-8")],
+                            "<html><body>Not a dress</body></html>",
+                        )
+                    }),
+                )
+                .route(
+                    "/fake.png",
+                    get(|| async { ([(header::CONTENT_TYPE, "image/png")], "plain text") }),
+                )
+                .route(
+                    "/missing.png",
+                    get(|| async { StatusCode::NOT_FOUND.into_response() }),
+                )
+                .route("/loop", get(|| async { Redirect::temporary("/loop") }))
+                .route(
+                    "/moved",
+                    get(|| async { Redirect::permanent("/dress.png") }),
+                );
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+            let address = listener.local_addr()?;
+            tokio::spawn(async move { axum::serve(listener, app).await });
+            Ok(format!("http://{address}"))
+        }
+
+        #[tokio::test]
+        async fn test_fetch_saves_the_image_by_hash() -> Result<()> {
+            let base = serve().await?;
+            let dir = tempdir()?;
+            let options = FetchOptions::default();
+
+            let url = format!("{base}/dress.png");
+            let (path, remote) = fetch(&url, dir.path(), &options).await?;
+            assert_eq!(std::fs::read(&path)?, png());
+            assert_eq!(path.file_name().unwrap(), "dress.png");
+            assert_eq!(remote.url, url);
+            assert_eq!(remote.sha256.len(), 64);
+            assert!(path.starts_with(dir.path().join(&remote.sha256[..16])));
+
+            // Redirects are followed; the same bytes land in the same directory
+            let (moved, moved_remote) =
+                fetch(&format!("{base}/moved"), dir.path(), &options).await?;
+            assert_eq!(moved_remote.sha256, remote.sha256);
+            assert_eq!(moved.parent(), path.parent());
+            assert_eq!(moved.file_name().unwrap(), "moved.png");
+
+            let (untyped, _) = fetch(&format!("{base}/untyped"), dir.path(), &options).await?;
+            assert_eq!(std::fs::read(untyped)?, png());
+            Ok(())
+        }
+
+        #[tokio::test]
+        async fn test_fetch_rejects_what_isnt_an_image() -> Result<()> {
+            let base = serve().await?;
+            let dir = tempdir()?;
+            let options = FetchOptions::default();
+            let error = |path: &'static str| {
+                let url = format!("{base}{path}");
+                let dir = dir.path().to_path_buf();
+                async move {
+                    fetch(&url, &dir, &options)
+                        .await
+                        .expect_err(path)
+                        .to_string()
+                }
+            };
+
+            let html = error("/page.html").await;
+            assert!(
+                html.contains("is not an image: the server sent text/html"),
+                "{html}"
+            );
+            let fake = error("/fake.png").await;
+            assert!(
+                fake.contains("not an image in a format si can read"),
+                "{fake}"
+            );
+            let missing = error("/missing.png").await;
+            assert!(missing.contains("answered 404 Not Found"), "{missing}");
+            let looped = error("/loop").await;
+            assert!(looped.contains("more than 5 redirects"), "{looped}");
+
+            let small = FetchOptions {
+                max_bytes: 16,
+                ..options
+            };
+            let url = format!("{base}/dress.png");
+            let large = fetch(&url, dir.path(), &small)
+                .await
+                .unwrap_err()
+                .to_string();
+            assert!(large.contains("larger than the 16 B limit"), "{large}");
+            assert_eq!(std::fs::read_dir(dir.path())?.count(), 0);
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,845 @@
+//! Reading and writing image files
+//!
+//! [`load`] detects the format from the file's leading bytes rather than its
+//! extension, so a PNG named `.jpg` still loads, and turns JPEGs upright by
+//! their EXIF orientation. [`save`] picks the format from the extension,
+//! creates missing parent directories and writes to a temporary file that is
+//! renamed into place, so a failed or interrupted save never leaves a
+//! half-written image. [`VirtualTryOn`](crate::VirtualTryOn) goes through both.
+//! [`save_bands`] writes an image that arrives in horizontal bands, such as a
+//! large grid, without ever holding all of it.
+//!
+//! An input's ICC profile comes back in [`LoadedImage::icc_profile`] and is
+//! written out again when it is passed in [`OutputOptions::icc_profile`] and the
+//! output format can carry it; see [`crate::icc`]. Generation parameters in
+//! [`OutputOptions::parameters`] are embedded the same way; see
+//! [`crate::metadata`].
+//!
+//! [`load_lenient`] salvages truncated files: a JPEG that ends early is decoded
+//! as far as its data goes, and whatever a decoder can't reach is filled with
+//! [`FILL_COLOR`]. What was recovered comes back as [`LoadWarning`]s.
+
+use std::{
+    borrow::Cow,
+    fmt,
+    fs::{self, File},
+    io::{BufWriter, Cursor, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+use image::{
+    ColorType, DynamicImage, GenericImage, ImageBuffer, ImageDecoder, ImageFormat, RgbImage,
+    codecs::{jpeg::JpegDecoder, png::PngDecoder},
+};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    formats, icc,
+    metadata::{self, ReproInfo},
+};
+
+/// Bytes of a file's header [`sniff_format`] reads
+pub const SNIFF_LEN: usize = 64;
+
+/// EXIF tag holding the orientation
+const ORIENTATION_TAG: u16 = 0x0112;
+/// Marker every complete JPEG ends with
+const JPEG_END: [u8; 2] = [0xFF, 0xD9];
+/// What [`load_lenient`] paints where a damaged image has no pixels
+pub const FILL_COLOR: [u8; 3] = [128, 128, 128];
+/// Rows of an image [`save`] hands the PNG encoder at a time
+const PNG_BAND_ROWS: usize = 64;
+
+/// An image as [`load`] found it
+#[derive(Debug, Clone)]
+pub struct LoadedImage {
+    /// The pixels, upright when `orientation_applied`
+    pub image: DynamicImage,
+    /// Format detected from the file's header
+    pub format: ImageFormat,
+    /// Color type as decoded, before any conversion
+    pub color_type: ColorType,
+    /// Whether an EXIF orientation rotated or flipped the pixels
+    pub orientation_applied: bool,
+    /// The embedded ICC color profile, for PNG and JPEG files with one
+    pub icc_profile: Option<Vec<u8>>,
+}
+
+/// Something [`load_lenient`] had to work around
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum LoadWarning {
+    /// The JPEG ended before its end marker; the rows after the last complete
+    /// data are gray
+    Truncated,
+    /// The decoder stopped at `error`; the pixels it didn't reach are filled
+    PartialDecode { error: String },
+}
+
+impl fmt::Display for LoadWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Truncated => f.write_str("the file is truncated; the missing rows are gray"),
+            Self::PartialDecode { error } => write!(
+                f,
+                "decoding stopped early ({error}); the rest is filled with gray"
+            ),
+        }
+    }
+}
+
+/// How [`save`] encodes an image
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OutputOptions {
+    /// Format to write; `None` takes it from the path's extension
+    pub format: Option<ImageFormat>,
+    /// Quality (1-100) for JPEG, WebP and AVIF; ignored by formats that take
+    /// none, and each format's default when `None`
+    pub quality: Option<u8>,
+    /// ICC profile to embed; dropped with a warning for formats that can't
+    /// carry one
+    pub icc_profile: Option<Vec<u8>>,
+    /// Generation parameters to embed; left out of formats that can't carry
+    /// them
+    pub parameters: Option<ReproInfo>,
+}
+
+/// What [`save`] wrote
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SaveReport {
+    pub path: PathBuf,
+    #[serde(serialize_with = "formats::serialize_format")]
+    pub format: ImageFormat,
+    pub bytes_written: u64,
+    /// Whether the ICC profile of the options was embedded
+    pub icc_embedded: bool,
+}
+
+/// The format of the image at `path`, from its first [`SNIFF_LEN`] bytes
+pub fn sniff_format(path: &Path) -> Result<ImageFormat> {
+    let file =
+        File::open(path).with_context(|| format!("Failed to open image {}", path.display()))?;
+    let mut header = Vec::with_capacity(SNIFF_LEN);
+    file.take(SNIFF_LEN as u64)
+        .read_to_end(&mut header)
+        .with_context(|| format!("Failed to read image {}", path.display()))?;
+    sniff(&header, &path.display().to_string())
+}
+
+fn sniff(header: &[u8], name: &str) -> Result<ImageFormat> {
+    image::guess_format(header).map_err(|e| anyhow::anyhow!("Invalid image format in {name}: {e}"))
+}
+
+/// Decode the image at `path`, whatever its extension says
+pub fn load(path: &Path) -> Result<LoadedImage> {
+    debug!("Loading image from: {}", path.display());
+    let bytes =
+        fs::read(path).with_context(|| format!("Failed to read image {}", path.display()))?;
+    let format = sniff(
+        &bytes[..bytes.len().min(SNIFF_LEN)],
+        &path.display().to_string(),
+    )?;
+    let image = image::load_from_memory_with_format(&bytes, format)
+        .with_context(|| format!("Failed to load image from {}", path.display()))?;
+    let color_type = image.color();
+
+    let orientation = match format {
+        ImageFormat::Jpeg => jpeg_orientation(&bytes).unwrap_or(1),
+        _ => 1,
+    };
+    let (image, orientation_applied) = orient(image, orientation);
+    Ok(LoadedImage {
+        image,
+        format,
+        color_type,
+        orientation_applied,
+        icc_profile: icc::read(&bytes, format),
+    })
+}
+
+/// Decode the image at `path`, recovering what it can of a truncated or
+/// corrupt file
+///
+/// An intact file loads as with [`load`], without warnings. Files whose header
+/// is unreadable still fail.
+pub fn load_lenient(path: &Path) -> Result<(LoadedImage, Vec<LoadWarning>)> {
+    let error = match load(path) {
+        Ok(loaded) => return Ok((loaded, Vec::new())),
+        Err(e) => e,
+    };
+    debug!("Recovering {}: {error:#}", path.display());
+    let bytes =
+        fs::read(path).with_context(|| format!("Failed to read image {}", path.display()))?;
+    let format = sniff(
+        &bytes[..bytes.len().min(SNIFF_LEN)],
+        &path.display().to_string(),
+    )?;
+    let orientation = match format {
+        ImageFormat::Jpeg => jpeg_orientation(&bytes).unwrap_or(1),
+        _ => 1,
+    };
+    let recovered = |image: DynamicImage| {
+        let color_type = image.color();
+        let (image, orientation_applied) = orient(image, orientation);
+        LoadedImage {
+            image,
+            format,
+            color_type,
+            orientation_applied,
+            icc_profile: icc::read(&bytes, format),
+        }
+    };
+
+    // The JPEG decoder pads missing scan data once it sees the end marker
+    if format == ImageFormat::Jpeg && !bytes.ends_with(&JPEG_END) {
+        let mut patched = bytes.clone();
+        patched.extend(JPEG_END);
+        if let Ok(image) = image::load_from_memory_with_format(&patched, format) {
+            return Ok((recovered(image), vec![LoadWarning::Truncated]));
+        }
+    }
+
+    let (image, stopped) = decode_partial(&bytes, format)
+        .with_context(|| format!("{error:#}"))
+        .with_context(|| format!("Can't recover anything from {}", path.display()))?;
+    Ok((
+        recovered(image),
+        vec![LoadWarning::PartialDecode { error: stopped }],
+    ))
+}
+
+/// Decode as much of `bytes` as the decoder gets through, returning the image
+/// and the error that stopped it
+fn decode_partial(bytes: &[u8], format: ImageFormat) -> Result<(DynamicImage, String)> {
+    match format {
+        ImageFormat::Png => decode_into_fill(PngDecoder::new(Cursor::new(bytes))?),
+        ImageFormat::Jpeg => decode_into_fill(JpegDecoder::new(Cursor::new(bytes))?),
+        other => bail!("Partial recovery isn't supported for {other:?} images"),
+    }
+}
+
+fn decode_into_fill<'a>(decoder: impl ImageDecoder<'a>) -> Result<(DynamicImage, String)> {
+    let (width, height) = decoder.dimensions();
+    let color_type = decoder.color_type();
+    let fill: &[u8] = match color_type {
+        ColorType::L8 => &FILL_COLOR[..1],
+        ColorType::La8 => &[FILL_COLOR[0], 255],
+        ColorType::Rgb8 => &FILL_COLOR,
+        ColorType::Rgba8 => &[FILL_COLOR[0], FILL_COLOR[1], FILL_COLOR[2], 255],
+        other => bail!("Partial recovery isn't supported for {other:?} pixels"),
+    };
+    let size = usize::try_from(decoder.total_bytes()).context("Image is too large")?;
+    let mut buf: Vec<u8> = fill.iter().copied().cycle().take(size).collect();
+    let stopped = match decoder.read_image(&mut buf) {
+        Ok(()) => bail!("The image decoded without errors the second time"),
+        Err(e) => e.to_string(),
+    };
+    if buf.chunks(fill.len()).all(|pixel| pixel == fill) {
+        bail!("No pixels could be decoded: {stopped}");
+    }
+
+    let image = match color_type {
+        ColorType::L8 => ImageBuffer::from_raw(width, height, buf).map(DynamicImage::ImageLuma8),
+        ColorType::La8 => ImageBuffer::from_raw(width, height, buf).map(DynamicImage::ImageLumaA8),
+        ColorType::Rgb8 => ImageBuffer::from_raw(width, height, buf).map(DynamicImage::ImageRgb8),
+        _ => ImageBuffer::from_raw(width, height, buf).map(DynamicImage::ImageRgba8),
+    };
+    Ok((
+        image.context("Decoded pixels don't fit the image")?,
+        stopped,
+    ))
+}
+
+/// Encode `image` to `path`, creating its parent directory
+///
+/// Nothing is left at `path` when encoding or writing fails. An ICC profile
+/// the format can't carry is left out with a warning; see
+/// [`SaveReport::icc_embedded`]. PNGs are streamed to the file a few rows at a
+/// time rather than encoded in memory first.
+pub fn save(image: &DynamicImage, path: &Path, options: &OutputOptions) -> Result<SaveReport> {
+    save_with(path, options, |writer, format, embeds, quality| {
+        encode(image, writer, format, embeds, quality)
+    })
+}
+
+/// Encode an RGB image handed over as horizontal bands, top to bottom, to
+/// `path`, as [`save`] does
+///
+/// A PNG never exists in memory as a whole: each band is encoded and dropped
+/// before the next is asked for, so a grid built by
+/// [`GridLayout::rows`](crate::compose::GridLayout::rows) costs one band of
+/// memory however large it gets. Other formats need the whole image and
+/// assemble the bands first.
+pub fn save_bands<I>(
+    (width, height): (u32, u32),
+    bands: I,
+    path: &Path,
+    options: &OutputOptions,
+) -> Result<SaveReport>
+where
+    I: IntoIterator<Item = Result<RgbImage>>,
+{
+    save_with(path, options, |writer, format, embeds, quality| {
+        let mut bands = bands.into_iter().map(|band| {
+            let band = band?;
+            if band.width() != width {
+                bail!(
+                    "A {}-pixel band in a {width}-pixel wide image",
+                    band.width()
+                );
+            }
+            Ok(band)
+        });
+        if format == ImageFormat::Png {
+            let bands = bands.map(|band| band.map(|band| Cow::Owned(band.into_raw())));
+            return write_png(writer, (width, height), ColorType::Rgb8, embeds, bands);
+        }
+        let mut canvas = RgbImage::new(width, height);
+        let mut y = 0;
+        for band in &mut bands {
+            let band = band?;
+            canvas.copy_from(&band, 0, y)?;
+            y += band.height();
+        }
+        if y != height {
+            bail!("The bands cover {y} of {height} rows");
+        }
+        encode(
+            &DynamicImage::ImageRgb8(canvas),
+            writer,
+            format,
+            embeds,
+            quality,
+        )
+    })
+}
+
+/// What [`save`] puts into an output besides its pixels
+#[derive(Clone, Copy)]
+struct Embeds<'a> {
+    profile: Option<&'a [u8]>,
+    parameters: Option<&'a ReproInfo>,
+}
+
+/// Save to `path` what `write` encodes, given the format, what to embed and
+/// the quality
+fn save_with(
+    path: &Path,
+    options: &OutputOptions,
+    write: impl FnOnce(&mut BufWriter<File>, ImageFormat, Embeds, Option<u8>) -> Result<()>,
+) -> Result<SaveReport> {
+    debug!("Saving image to: {}", path.display());
+    let format = match options.format {
+        Some(format) => format,
+        None => ImageFormat::from_path(path)
+            .with_context(|| format!("Can't tell the output format from {}", path.display()))?,
+    };
+    formats::ensure_writable(format)?;
+
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory {}", parent.display()))?;
+    }
+
+    let quality = options
+        .quality
+        .filter(|_| formats::supports_quality(format));
+    let profile = options.icc_profile.as_deref().filter(|_| {
+        let embeddable = icc::can_embed(format);
+        if !embeddable {
+            warn!(
+                "{} is {format:?}, which can't carry the input's ICC profile; colors may shift",
+                path.display()
+            );
+        }
+        embeddable
+    });
+    let parameters = options
+        .parameters
+        .as_ref()
+        .filter(|_| metadata::can_embed(format));
+    let embeds = Embeds {
+        profile,
+        parameters,
+    };
+    let tmp_path = partial_path(path);
+    let saved = File::create(&tmp_path)
+        .map_err(anyhow::Error::from)
+        .and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            write(&mut writer, format, embeds, quality)?;
+            writer.flush()?;
+            Ok(())
+        })
+        .and_then(|()| Ok(fs::rename(&tmp_path, path)?))
+        .with_context(|| format!("Failed to save image to {}", path.display()));
+    if let Err(e) = saved {
+        // Don't leave a half-written image behind
+        let _ = fs::remove_file(&tmp_path);
+        return Err(e);
+    }
+
+    let bytes_written = fs::metadata(path)
+        .with_context(|| format!("Failed to read back {}", path.display()))?
+        .len();
+    Ok(SaveReport {
+        path: path.to_path_buf(),
+        format,
+        bytes_written,
+        icc_embedded: profile.is_some(),
+    })
+}
+
+/// Hidden sibling of `path` that an image is written to before it is renamed
+/// into place; the process id keeps two processes saving the same file apart
+fn partial_path(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{name}.{}.partial", std::process::id()))
+}
+
+/// Encode `image` as `format` into `writer`, with what `embeds` holds
+fn encode(
+    image: &DynamicImage,
+    writer: &mut BufWriter<File>,
+    format: ImageFormat,
+    embeds: Embeds,
+    quality: Option<u8>,
+) -> Result<()> {
+    if format == ImageFormat::Png && png_color(image.color()).is_some() {
+        let row_len = image.width() as usize * usize::from(image.color().bytes_per_pixel());
+        let bands = image
+            .as_bytes()
+            .chunks((row_len * PNG_BAND_ROWS).max(1))
+            .map(|band| Ok(Cow::Borrowed(band)));
+        let size = (image.width(), image.height());
+        return write_png(writer, size, image.color(), embeds, bands);
+    }
+    if embeds.profile.is_none() && embeds.parameters.is_none() {
+        return formats::write_image(image, writer, format, quality);
+    }
+    let mut encoded = Cursor::new(Vec::new());
+    formats::write_image(image, &mut encoded, format, quality)?;
+    let mut encoded = encoded.into_inner();
+    if let Some(profile) = embeds.profile {
+        encoded = icc::embed(&encoded, format, profile)?;
+    }
+    if let Some(parameters) = embeds.parameters {
+        encoded = metadata::embed(&encoded, format, parameters)?;
+    }
+    writer.write_all(&encoded)?;
+    Ok(())
+}
+
+/// How PNG stores pixels of `color` unchanged, for the color types it has
+fn png_color(color: ColorType) -> Option<(png::ColorType, png::BitDepth)> {
+    use png::{BitDepth, ColorType as Png};
+    Some(match color {
+        ColorType::L8 => (Png::Grayscale, BitDepth::Eight),
+        ColorType::La8 => (Png::GrayscaleAlpha, BitDepth::Eight),
+        ColorType::Rgb8 => (Png::Rgb, BitDepth::Eight),
+        ColorType::Rgba8 => (Png::Rgba, BitDepth::Eight),
+        ColorType::L16 => (Png::Grayscale, BitDepth::Sixteen),
+        ColorType::La16 => (Png::GrayscaleAlpha, BitDepth::Sixteen),
+        ColorType::Rgb16 => (Png::Rgb, BitDepth::Sixteen),
+        ColorType::Rgba16 => (Png::Rgba, BitDepth::Sixteen),
+        _ => return None,
+    })
+}
+
+/// Write a PNG of `color` pixels whose samples, in native byte order, `bands`
+/// hands over a number of whole rows at a time
+///
+/// The encoder filters and compresses each band as it arrives, with the same
+/// settings as `image`'s PNG encoder. The profile of `embeds` goes in as an
+/// `iCCP` chunk and its parameters as a text chunk, up front rather than being
+/// spliced into the finished file.
+fn write_png<'a>(
+    writer: impl Write,
+    (width, height): (u32, u32),
+    color: ColorType,
+    embeds: Embeds,
+    bands: impl IntoIterator<Item = Result<Cow<'a, [u8]>>>,
+) -> Result<()> {
+    let (png_color, depth) =
+        png_color(color).with_context(|| format!("PNG can't store {color:?} pixels"))?;
+    let mut encoder = png::Encoder::new(writer, width, height);
+    encoder.set_color(png_color);
+    encoder.set_depth(depth);
+    encoder.set_compression(png::Compression::Default);
+    encoder.set_filter(png::FilterType::Sub);
+    encoder.set_adaptive_filter(png::AdaptiveFilterType::Adaptive);
+    let mut writer = encoder.write_header()?;
+    if let Some(profile) = embeds.profile {
+        writer.write_chunk(png::chunk::iCCP, &icc::png_chunk(profile)?)?;
+    }
+    if let Some(parameters) = embeds.parameters {
+        let (kind, data) = metadata::png_chunk(parameters);
+        writer.write_chunk(kind, &data)?;
+    }
+
+    let expected = u64::from(width) * u64::from(height) * u64::from(color.bytes_per_pixel());
+    let mut written = 0;
+    let mut stream = writer.stream_writer()?;
+    for band in bands {
+        let mut band = band?;
+        if depth == png::BitDepth::Sixteen {
+            // PNG samples are big-endian
+            for sample in band.to_mut().chunks_exact_mut(2) {
+                let value = u16::from_ne_bytes([sample[0], sample[1]]);
+                sample.copy_from_slice(&value.to_be_bytes());
+            }
+        }
+        written += band.len() as u64;
+        if written > expected {
+            bail!("More pixel data than a {width}x{height} {color:?} image holds");
+        }
+        stream.write_all(&band)?;
+    }
+    if written != expected {
+        bail!("{written} of the {expected} bytes of a {width}x{height} {color:?} image");
+    }
+    stream.finish()?;
+    Ok(())
+}
+
+/// Turn `image` upright for an EXIF `orientation` (1-8), and whether that
+/// changed anything
+fn orient(image: DynamicImage, orientation: u16) -> (DynamicImage, bool) {
+    let image = match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        // Transpose
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        // Transverse
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => return (image, false),
+    };
+    (image, true)
+}
+
+/// The orientation in a JPEG's EXIF segment, if it has one
+fn jpeg_orientation(bytes: &[u8]) -> Option<u16> {
+    tiff_orientation(jpeg_exif(bytes)?)
+}
+
+/// The TIFF structure of a JPEG's EXIF segment, if it has one
+pub(crate) fn jpeg_exif(bytes: &[u8]) -> Option<&[u8]> {
+    if bytes.get(..2)? != [0xFF, 0xD8] {
+        return None;
+    }
+    let mut pos = 2;
+    loop {
+        if *bytes.get(pos)? != 0xFF {
+            return None;
+        }
+        let marker = *bytes.get(pos + 1)?;
+        // Start of scan: the metadata segments are over
+        if marker == 0xDA {
+            return None;
+        }
+        let len = read_u16(bytes, pos + 2, true)? as usize;
+        let segment = bytes.get(pos + 4..pos + 2 + len)?;
+        if marker == 0xE1 {
+            if let Some(tiff) = segment.strip_prefix(b"Exif\0\0") {
+                return Some(tiff);
+            }
+        }
+        pos += 2 + len;
+    }
+}
+
+/// The orientation tag of IFD0 in a TIFF header
+fn tiff_orientation(tiff: &[u8]) -> Option<u16> {
+    let big_endian = match tiff.get(..2)? {
+        b"MM" => true,
+        b"II" => false,
+        _ => return None,
+    };
+    let ifd = read_u32(tiff, 4, big_endian)? as usize;
+    let count = read_u16(tiff, ifd, big_endian)? as usize;
+    (0..count)
+        .map(|i| ifd + 2 + i * 12)
+        .find(|&entry| read_u16(tiff, entry, big_endian) == Some(ORIENTATION_TAG))
+        .and_then(|entry| read_u16(tiff, entry + 8, big_endian))
+}
+
+pub(crate) fn read_u16(bytes: &[u8], at: usize, big_endian: bool) -> Option<u16> {
+    let raw = bytes.get(at..at + 2)?.try_into().ok()?;
+    Some(if big_endian {
+        u16::from_be_bytes(raw)
+    } else {
+        u16::from_le_bytes(raw)
+    })
+}
+
+pub(crate) fn read_u32(bytes: &[u8], at: usize, big_endian: bool) -> Option<u32> {
+    let raw = bytes.get(at..at + 4)?.try_into().ok()?;
+    Some(if big_endian {
+        u32::from_be_bytes(raw)
+    } else {
+        u32::from_le_bytes(raw)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::gradient;
+    use image::Rgb;
+    use tempfile::tempdir;
+
+    /// A JPEG of `image` carrying an EXIF segment with `orientation`
+    fn jpeg_with_orientation(image: &RgbImage, orientation: u16) -> Result<Vec<u8>> {
+        let mut jpeg = Vec::new();
+        image.write_to(&mut std::io::Cursor::new(&mut jpeg), ImageFormat::Jpeg)?;
+
+        let mut tiff = b"MM\0\x2a\0\0\0\x08\0\x01".to_vec();
+        tiff.extend(ORIENTATION_TAG.to_be_bytes());
+        tiff.extend([0, 3, 0, 0, 0, 1]);
+        tiff.extend(orientation.to_be_bytes());
+        tiff.extend([0, 0, 0, 0, 0, 0]);
+        let mut segment = b"Exif\0\0".to_vec();
+        segment.extend(tiff);
+
+        let mut out = jpeg[..2].to_vec();
+        out.extend([0xFF, 0xE1]);
+        out.extend((segment.len() as u16 + 2).to_be_bytes());
+        out.extend(segment);
+        out.extend(&jpeg[2..]);
+        Ok(out)
+    }
+
+    #[test]
+    fn test_sniffing_ignores_the_extension() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let disguised = temp_dir.path().join("photo.jpg");
+        RgbImage::from_pixel(3, 2, Rgb([10, 20, 30]))
+            .save_with_format(&disguised, ImageFormat::Png)?;
+
+        assert_eq!(sniff_format(&disguised)?, ImageFormat::Png);
+        let loaded = load(&disguised)?;
+        assert_eq!(loaded.format, ImageFormat::Png);
+        assert_eq!(loaded.color_type, ColorType::Rgb8);
+        assert_eq!((loaded.image.width(), loaded.image.height()), (3, 2));
+        assert!(!loaded.orientation_applied);
+
+        let garbage = temp_dir.path().join("garbage.png");
+        fs::write(&garbage, "not an image")?;
+        let err = sniff_format(&garbage).unwrap_err();
+        assert!(format!("{err:#}").contains("Invalid image format"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_applies_exif_orientation() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("rotated.jpg");
+        let image = RgbImage::from_pixel(16, 8, Rgb([200, 100, 50]));
+
+        fs::write(&path, jpeg_with_orientation(&image, 6)?)?;
+        let loaded = load(&path)?;
+        assert!(loaded.orientation_applied);
+        assert_eq!((loaded.image.width(), loaded.image.height()), (8, 16));
+
+        fs::write(&path, jpeg_with_orientation(&image, 1)?)?;
+        let loaded = load(&path)?;
+        assert!(!loaded.orientation_applied);
+        assert_eq!((loaded.image.width(), loaded.image.height()), (16, 8));
+        Ok(())
+    }
+
+    #[test]
+    fn test_save_reports_bytes_written() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let image = DynamicImage::ImageRgb8(RgbImage::from_pixel(32, 32, Rgb([1, 2, 3])));
+
+        let path = temp_dir.path().join("nested/dir/out.png");
+        let report = save(&image, &path, &OutputOptions::default())?;
+        assert_eq!(report.format, ImageFormat::Png);
+        assert_eq!(report.bytes_written, fs::metadata(&path)?.len());
+        assert!(report.bytes_written > 0);
+
+        // Quality is ignored for PNG and changes the size of a JPEG
+        let options = |quality| OutputOptions {
+            quality: Some(quality),
+            ..Default::default()
+        };
+        save(&image, &path, &options(10))?;
+        let low = save(&image, &temp_dir.path().join("low.jpg"), &options(5))?;
+        let high = save(&image, &temp_dir.path().join("high.jpg"), &options(100))?;
+        assert_eq!(low.format, ImageFormat::Jpeg);
+        assert!(low.bytes_written < high.bytes_written);
+
+        let forced = OutputOptions {
+            format: Some(ImageFormat::Png),
+            ..Default::default()
+        };
+        let report = save(&image, &temp_dir.path().join("out.bin"), &forced)?;
+        assert_eq!(sniff_format(&report.path)?, ImageFormat::Png);
+        Ok(())
+    }
+
+    /// A noisy JPEG big enough that its scan data dwarfs the headers
+    fn noisy_jpeg(width: u32, height: u32) -> Result<Vec<u8>> {
+        let image = RgbImage::from_fn(width, height, |x, y| {
+            let v = (x * 7 + y * 13) ^ (x * y);
+            Rgb([v as u8, (v >> 2) as u8, (x + y) as u8])
+        });
+        let mut jpeg = Vec::new();
+        image.write_to(&mut std::io::Cursor::new(&mut jpeg), ImageFormat::Jpeg)?;
+        Ok(jpeg)
+    }
+
+    #[test]
+    fn test_lenient_load_recovers_truncated_jpegs() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let jpeg = noisy_jpeg(96, 64)?;
+        let path = temp_dir.path().join("cut.jpg");
+
+        fs::write(&path, &jpeg)?;
+        let (loaded, warnings) = load_lenient(&path)?;
+        assert!(warnings.is_empty());
+        assert_eq!((loaded.image.width(), loaded.image.height()), (96, 64));
+
+        for fraction in [0.5, 0.75, 0.95] {
+            let cut = (jpeg.len() as f64 * fraction) as usize;
+            fs::write(&path, &jpeg[..cut])?;
+            assert!(load(&path).is_err(), "cut at {cut} loads normally");
+            let (loaded, warnings) = load_lenient(&path)?;
+            let size = (loaded.image.width(), loaded.image.height());
+            assert_eq!(size, (96, 64), "cut at {cut}");
+            assert_eq!(warnings, [LoadWarning::Truncated], "cut at {cut}");
+        }
+
+        // Nothing is left to recover without the headers
+        fs::write(&path, &jpeg[..20])?;
+        let err = load_lenient(&path).unwrap_err();
+        assert!(format!("{err:#}").contains("Can't recover"), "{err:#}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_lenient_load_fills_the_rest_of_a_truncated_png() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("cut.png");
+        let image = RgbImage::from_fn(64, 64, |x, y| Rgb([(x * 4) as u8, (y * 4) as u8, 7]));
+        let mut png = Vec::new();
+        image.write_to(&mut std::io::Cursor::new(&mut png), ImageFormat::Png)?;
+
+        fs::write(&path, &png[..png.len() * 2 / 3])?;
+        let (recovered, warnings) = load_lenient(&path)?;
+        let recovered = recovered.image;
+        assert_eq!((recovered.width(), recovered.height()), (64, 64));
+        assert!(
+            matches!(warnings.as_slice(), [LoadWarning::PartialDecode { .. }]),
+            "{warnings:?}"
+        );
+        let recovered = recovered.to_rgb8();
+        assert_eq!(recovered.get_pixel(5, 0), image.get_pixel(5, 0));
+        assert_eq!(recovered.get_pixel(5, 63), &Rgb(FILL_COLOR));
+        Ok(())
+    }
+
+    #[test]
+    fn test_icc_profile_survives_a_round_trip() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let image = DynamicImage::ImageRgb8(RgbImage::from_pixel(8, 8, Rgb([40, 90, 200])));
+        let profile: Vec<u8> = (0..=255).collect();
+        let options = OutputOptions {
+            icc_profile: Some(profile.clone()),
+            ..Default::default()
+        };
+
+        for name in ["tagged.png", "tagged.jpg"] {
+            let report = save(&image, &temp_dir.path().join(name), &options)?;
+            assert!(report.icc_embedded);
+            assert_eq!(load(&report.path)?.icc_profile, Some(profile.clone()));
+        }
+        let report = save(&image, &temp_dir.path().join("untagged.bmp"), &options)?;
+        assert!(!report.icc_embedded);
+
+        let plain = save(
+            &image,
+            &temp_dir.path().join("plain.png"),
+            &OutputOptions::default(),
+        )?;
+        assert!(!plain.icc_embedded);
+        assert_eq!(load(&plain.path)?.icc_profile, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_streamed_pngs_decode_to_the_same_pixels() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let rgb = gradient(100, 150);
+        let profile: Vec<u8> = (0..=255).collect();
+        let tagged = OutputOptions {
+            icc_profile: Some(profile.clone()),
+            ..Default::default()
+        };
+
+        // Bands of uneven heights, the last one short
+        let bands = [0, 7, 71, 150].windows(2).map(|rows| {
+            Ok(image::imageops::crop_imm(&rgb, 0, rows[0], 100, rows[1] - rows[0]).to_image())
+        });
+        let path = temp_dir.path().join("bands.png");
+        let report = save_bands((100, 150), bands, &path, &tagged)?;
+        assert!(report.icc_embedded);
+        let loaded = load(&path)?;
+        assert_eq!(loaded.icc_profile, Some(profile));
+        assert!(loaded.image.to_rgb8() == rgb);
+
+        // Other formats assemble the bands first
+        let path = temp_dir.path().join("bands.bmp");
+        save_bands((100, 150), [Ok(rgb.clone())], &path, &tagged)?;
+        assert!(load(&path)?.image.to_rgb8() == rgb);
+
+        // 16-bit samples are stored big-endian, as PNG wants them
+        let deep = DynamicImage::ImageRgba16(image::ImageBuffer::from_fn(70, 90, |x, y| {
+            image::Rgba([x as u16 * 900, y as u16 * 700, 0x1234, u16::MAX])
+        }));
+        let path = temp_dir.path().join("deep.png");
+        save(&deep, &path, &OutputOptions::default())?;
+        let loaded = load(&path)?;
+        assert_eq!(loaded.color_type, ColorType::Rgba16);
+        assert!(loaded.image == deep);
+
+        // A failed save leaves the previous image alone
+        let missing = save_bands((100, 150), [Ok(gradient(100, 10))], &path, &tagged);
+        assert!(missing.is_err());
+        assert!(load(&path)?.image == deep);
+        Ok(())
+    }
+
+    #[test]
+    fn test_failed_save_leaves_nothing_behind() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("out.unknownext");
+        let image = DynamicImage::ImageRgb8(RgbImage::new(4, 4));
+        assert!(save(&image, &path, &OutputOptions::default()).is_err());
+        assert!(!path.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_failed_save_keeps_the_previous_image() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("out.png");
+        let options = OutputOptions::default();
+        let image = DynamicImage::ImageRgb8(gradient(100, 150));
+        save(&image, &path, &options)?;
+        let before = fs::read(&path)?;
+
+        let missing = save_bands((100, 150), [Ok(gradient(100, 10))], &path, &options);
+        assert!(missing.is_err());
+        assert_eq!(fs::read(&path)?, before);
+        let files = fs::read_dir(temp_dir.path())?.count();
+        assert_eq!(files, 1, "partial file left behind");
+        Ok(())
+    }
+}
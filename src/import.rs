@@ -0,0 +1,787 @@
+//! Registering models that other tools already downloaded
+//!
+//! [`ModelManager::import_external`] finds single-file checkpoints
+//! (`.safetensors`, `.ckpt`) and diffusers folders (a directory with a
+//! `model_index.json`) and adds them to the index as
+//! [`ModelOrigin::External`] models, either pointing at the files where they
+//! are or at copies in the models directory.
+//!
+//! [`ModelManager::open_local`] scans a model the same way without indexing it,
+//! for `--model ./my-finetune`: a model argument starting with `./`, `../`, `/`
+//! or `~` is a path (see [`is_local_model`]), anything else a model id.
+//! [`ModelManager::register_local`] indexes such a model after all. Hashing a
+//! local model's files for provenance reads all of them, so with a
+//! [`HashCache`] each file's hash is kept until the file changes.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use anyhow::{Context, Result, bail};
+use directories::BaseDirs;
+use log::{debug, warn};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    compat::{self, Compatibility, Task},
+    diffusers_config::{self, DiffusersConfig},
+    disk_cache::{CacheKind, DiskCache},
+    index_paths,
+    journal::IndexTransaction,
+    models::{self, IndexSource, ModelFile, ModelInfo, ModelManager, ModelOrigin},
+    weights::WeightFormat,
+};
+
+/// Namespace of the ids derived for imported models
+pub const EXTERNAL_NAMESPACE: &str = "local";
+/// Directory under the models dir that `copy` imports go to
+const EXTERNAL_DIR: &str = "external";
+/// Marker file of a diffusers pipeline folder
+const DIFFUSERS_INDEX: &str = "model_index.json";
+const CHECKPOINT_EXTENSIONS: &[&str] = &["safetensors", "ckpt"];
+/// Starts of a model argument that make it a path rather than a model id
+const LOCAL_PREFIXES: [&str; 4] = ["./", "../", "/", "~"];
+/// Size the file hash cache is trimmed to
+pub const HASH_CACHE_MAX_BYTES: u64 = 4 * 1024 * 1024;
+
+/// How a found model is laid out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExternalLayout {
+    /// One `.safetensors` or `.ckpt` file
+    Checkpoint,
+    /// A diffusers folder; every file under it belongs to the model
+    Diffusers,
+}
+
+/// A model found by [`ModelManager::find_external`]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ExternalModel {
+    pub path: PathBuf,
+    pub layout: ExternalLayout,
+    /// Id derived from the file or folder name
+    pub model_id: String,
+}
+
+/// A model used from where it is, without being indexed, found by
+/// [`ModelManager::open_local`]
+#[derive(Debug, Clone, Serialize)]
+pub struct LocalModel {
+    /// The model's folder or checkpoint, resolved
+    pub path: PathBuf,
+    pub layout: ExternalLayout,
+    /// What the model would be indexed as, under an id derived from its name
+    pub info: ModelInfo,
+}
+
+impl LocalModel {
+    /// Whether the model suits `task`, judged as [`compat::classify`] judges
+    /// indexed models
+    pub fn compatibility(&self, task: Task) -> Result<Compatibility> {
+        let config = match self.layout {
+            ExternalLayout::Diffusers => diffusers_config::read(&self.path)?,
+            ExternalLayout::Checkpoint => DiffusersConfig::NotDiffusersLayout,
+        };
+        Ok(compat::classify(&self.info, &config, task))
+    }
+
+    /// Hex SHA-256 over the model's file names and contents, in name order, so
+    /// an output can be traced to the exact weights that made it
+    ///
+    /// Every file not in `cache` is read, which takes a while for a large model.
+    pub fn content_hash(&self, cache: Option<&HashCache>) -> Result<String> {
+        let mut hasher = Sha256::new();
+        for file in &self.info.files {
+            let sha256 = match cache {
+                Some(cache) => cache.sha256(&file.path)?,
+                None => models::sha256_file(&file.path, &|_| {})?,
+            };
+            hasher.update(file.rfilename.as_deref().unwrap_or_default());
+            hasher.update([0]);
+            hasher.update(sha256);
+            hasher.update([b'\n']);
+        }
+        Ok(format!("{:x}", hasher.finalize()))
+    }
+}
+
+/// A file as it was when hashed; rewriting it changes its size or modification
+/// time, so its old hash is never used again
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FileHashKey {
+    pub path: PathBuf,
+    pub size: u64,
+    pub modified: SystemTime,
+}
+
+pub type HashCache = DiskCache<FileHashKey>;
+
+impl DiskCache<FileHashKey> {
+    /// The file hash cache in the si cache directory
+    pub fn hashes() -> Result<Self> {
+        Ok(Self::new(CacheKind::Hashes.dir()?, "sha256").with_max_bytes(HASH_CACHE_MAX_BYTES))
+    }
+
+    /// Hex SHA-256 of the file at `path`, read from the cache while the file is
+    /// unchanged; an unreadable entry counts as a miss
+    pub fn sha256(&self, path: &Path) -> Result<String> {
+        let metadata =
+            fs::metadata(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let key = FileHashKey {
+            path: path.to_path_buf(),
+            size: metadata.len(),
+            modified: metadata.modified()?,
+        };
+        match self.get(&key).map(|hash| hash.map(String::from_utf8)) {
+            Ok(Some(Ok(hash))) => {
+                debug!("Using the cached hash of {}", path.display());
+                return Ok(hash);
+            }
+            Ok(_) => {}
+            Err(e) => warn!("Ignoring the cached hash of {}: {e:#}", path.display()),
+        }
+        let hash = models::sha256_file(path, &|_| {})?;
+        if let Err(e) = self.put(&key, hash.as_bytes()) {
+            warn!("Failed to cache the hash of {}: {e:#}", path.display());
+        }
+        Ok(hash)
+    }
+}
+
+/// Whether the model argument `model` is a path rather than a model id: it
+/// starts with `./`, `../`, `/` or `~`, or is absolute on this platform
+pub fn is_local_model(model: &str) -> bool {
+    LOCAL_PREFIXES
+        .iter()
+        .any(|prefix| model.starts_with(prefix))
+        || Path::new(model).is_absolute()
+}
+
+/// `model` with a leading `~` expanded to the home directory
+fn expand_home(model: &str) -> Result<PathBuf> {
+    let Some(rest) = model.strip_prefix('~') else {
+        return Ok(PathBuf::from(model));
+    };
+    if !(rest.is_empty() || rest.starts_with(['/', '\\'])) {
+        bail!("Can't find `{model}`: only `~/` is expanded, to your home directory");
+    }
+    let home = BaseDirs::new()
+        .context("Home directory is not set")?
+        .home_dir()
+        .to_path_buf();
+    Ok(home.join(rest.trim_start_matches(['/', '\\'])))
+}
+
+/// Outcome of [`ModelManager::import_external`]
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ImportReport {
+    /// Models added to the index
+    pub imported: Vec<ModelInfo>,
+    /// Models found but not imported, with the reason
+    pub skipped: Vec<(ExternalModel, String)>,
+}
+
+impl ModelManager {
+    /// Import every model found in `dir`, descending into subdirectories when
+    /// `recursive` is set
+    ///
+    /// Models are indexed where they are unless `copy` is set, in which case their
+    /// files are copied into the models directory first. Models whose derived id is
+    /// already indexed are skipped. The models are indexed together in one write,
+    /// so when one fails to import none is.
+    pub fn import_external(&self, dir: &Path, recursive: bool, copy: bool) -> Result<ImportReport> {
+        let found = self.find_external(dir, recursive)?;
+        self.model_index().transaction(|txn| {
+            let mut report = ImportReport::default();
+            for found in found {
+                if txn.contains(&found.model_id)? {
+                    report
+                        .skipped
+                        .push((found, "already in the index".to_string()));
+                    continue;
+                }
+                match self.import_found(&found, copy, txn) {
+                    Ok(model) => report.imported.push(model),
+                    Err(e) => {
+                        if copy {
+                            // Nothing gets indexed, so the copies made so far
+                            // would only take up space
+                            self.remove_copies(&report.imported);
+                        }
+                        return Err(e);
+                    }
+                }
+            }
+            Ok(report)
+        })
+    }
+
+    /// Import the single model at `path` under `model_id` instead of the derived id
+    ///
+    /// `path` is a checkpoint file, a diffusers folder, or a directory holding
+    /// exactly one of those.
+    pub fn import_external_as(&self, path: &Path, model_id: &str, copy: bool) -> Result<ModelInfo> {
+        let mut found = self.find_external(path, false)?;
+        if found.len() != 1 {
+            bail!(
+                "Expected one model at {} to import as `{model_id}`, found {}",
+                path.display(),
+                found.len()
+            );
+        }
+        if self.get_model(model_id)?.is_some() {
+            bail!("Model {model_id} is already in the index");
+        }
+        let mut found = found.remove(0);
+        found.model_id = model_id.to_string();
+        self.model_index()
+            .transaction(|txn| self.import_found(&found, copy, txn))
+    }
+
+    /// Recognizable models at `path`, sorted by path
+    ///
+    /// `path` itself may be a checkpoint or a diffusers folder. Folders inside a
+    /// diffusers folder are never searched.
+    pub fn find_external(&self, path: &Path, recursive: bool) -> Result<Vec<ExternalModel>> {
+        let mut found = Vec::new();
+        if path.is_file() {
+            if let Some(model) = external_model(path) {
+                found.push(model);
+            }
+        } else if path.join(DIFFUSERS_INDEX).is_file() {
+            found.extend(external_model(path));
+        } else {
+            find_in_dir(path, recursive, &mut found)?;
+        }
+        if found.is_empty() && !path.exists() {
+            bail!("{} does not exist", path.display());
+        }
+        found.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(found)
+    }
+
+    /// The model at the path `model`, a diffusers folder or checkpoint file or
+    /// a directory holding exactly one, scanned as an import would but not
+    /// indexed
+    ///
+    /// The Hub isn't asked about it; `model` must be a path by
+    /// [`is_local_model`].
+    pub fn open_local(&self, model: &str) -> Result<LocalModel> {
+        if !is_local_model(model) {
+            bail!("`{model}` isn't a path; start it with `./` to use a local model");
+        }
+        let path = expand_home(model)?;
+        let mut found = self.find_external(&path, false)?;
+        if found.len() != 1 {
+            bail!(
+                "Expected one model at {}, a diffusers folder (with a {DIFFUSERS_INDEX}) or a \
+                 .safetensors or .ckpt file, found {}",
+                path.display(),
+                found.len()
+            );
+        }
+        let found = found.remove(0);
+        let root = index_paths::canonicalize(&found.path)
+            .with_context(|| format!("Failed to resolve {}", found.path.display()))?;
+        let info = external_info(&found, &root, IndexSource::External)?;
+        debug!(
+            "Using {} as {} without indexing it",
+            root.display(),
+            info.model_id
+        );
+        Ok(LocalModel {
+            path: root,
+            layout: found.layout,
+            info,
+        })
+    }
+
+    /// Index `local` where it is, returning `None` when it already is
+    ///
+    /// A different model indexed under the same derived id is an error.
+    pub fn register_local(&self, local: &LocalModel) -> Result<Option<ModelInfo>> {
+        let model_id = &local.info.model_id;
+        if let Some(indexed) = self.get_model(model_id)? {
+            let paths = |model: &ModelInfo| -> Vec<PathBuf> {
+                model.files.iter().map(|f| f.path.clone()).collect()
+            };
+            if paths(&indexed) == paths(&local.info) {
+                return Ok(None);
+            }
+            bail!(
+                "Model {model_id} is already in the index with other files; import {} under \
+                 another id with `si model import --as`",
+                local.path.display()
+            );
+        }
+        self.model_index().transaction(|txn| {
+            txn.add(local.info.clone())
+                .with_context(|| format!("Failed to add model '{model_id}' to index"))?;
+            Ok(Some(local.info.clone()))
+        })
+    }
+
+    fn import_found(
+        &self,
+        found: &ExternalModel,
+        copy: bool,
+        txn: &IndexTransaction,
+    ) -> Result<ModelInfo> {
+        debug!("Importing {} as {}", found.path.display(), found.model_id);
+        let root = if copy {
+            let target = self.external_copy_dir(&found.model_id);
+            copy_model(&found.path, &target)?
+        } else {
+            index_paths::canonicalize(&found.path)
+                .with_context(|| format!("Failed to resolve {}", found.path.display()))?
+        };
+        let source = if copy {
+            IndexSource::Import
+        } else {
+            IndexSource::External
+        };
+        let model = external_info(found, &root, source)?;
+        txn.add(model.clone())
+            .with_context(|| format!("Failed to add model '{}' to index", model.model_id))?;
+        Ok(model)
+    }
+
+    fn remove_copies(&self, models: &[ModelInfo]) {
+        for model in models {
+            if let Err(e) = self.remove_model_files(model) {
+                warn!("Failed to remove the copy of {}: {e:#}", model.model_id);
+            }
+        }
+    }
+
+    /// Where `copy` imports of `model_id` are stored
+    pub(crate) fn external_copy_dir(&self, model_id: &str) -> PathBuf {
+        self.models_dir()
+            .join(EXTERNAL_DIR)
+            .join(model_id.replace('/', "--"))
+    }
+}
+
+/// The model at `path` if it is a checkpoint file or a diffusers folder
+fn external_model(path: &Path) -> Option<ExternalModel> {
+    let layout = if path.is_dir() {
+        path.join(DIFFUSERS_INDEX)
+            .is_file()
+            .then_some(ExternalLayout::Diffusers)?
+    } else {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        CHECKPOINT_EXTENSIONS
+            .contains(&extension.as_str())
+            .then_some(ExternalLayout::Checkpoint)?
+    };
+    let name = match layout {
+        ExternalLayout::Checkpoint => path.file_stem()?,
+        ExternalLayout::Diffusers => path.file_name()?,
+    };
+    Some(ExternalModel {
+        path: path.to_path_buf(),
+        layout,
+        model_id: derive_model_id(&name.to_string_lossy()),
+    })
+}
+
+/// What `found`, whose files are under `root`, is indexed as
+fn external_info(found: &ExternalModel, root: &Path, source: IndexSource) -> Result<ModelInfo> {
+    let files = match found.layout {
+        ExternalLayout::Checkpoint => vec![external_file(root, root)?],
+        ExternalLayout::Diffusers => {
+            let mut paths = Vec::new();
+            collect_files(root, &mut paths)?;
+            paths.sort();
+            paths
+                .iter()
+                .map(|path| external_file(root, path))
+                .collect::<Result<_>>()?
+        }
+    };
+    let mut model = ModelInfo::new(&found.model_id, files);
+    model.origin = ModelOrigin::External;
+    model.source = source;
+    Ok(model)
+}
+
+/// `local/<name>`, with whitespace and path separators in `name` replaced by `-`
+pub fn derive_model_id(name: &str) -> String {
+    let name: String = name
+        .trim()
+        .chars()
+        .map(|c| {
+            if c.is_whitespace() || c == '/' || c == '\\' || c == '@' {
+                '-'
+            } else {
+                c
+            }
+        })
+        .collect();
+    format!("{EXTERNAL_NAMESPACE}/{name}")
+}
+
+fn find_in_dir(dir: &Path, recursive: bool, found: &mut Vec<ExternalModel>) -> Result<()> {
+    let entries =
+        fs::read_dir(dir).with_context(|| format!("Failed to read directory {}", dir.display()))?;
+    for entry in entries {
+        let path = entry
+            .with_context(|| format!("Failed to read directory {}", dir.display()))?
+            .path();
+        if let Some(model) = external_model(&path) {
+            found.push(model);
+        } else if recursive && path.is_dir() {
+            find_in_dir(&path, recursive, found)?;
+        }
+    }
+    Ok(())
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    let entries =
+        fs::read_dir(dir).with_context(|| format!("Failed to read directory {}", dir.display()))?;
+    for entry in entries {
+        let path = entry
+            .with_context(|| format!("Failed to read directory {}", dir.display()))?
+            .path();
+        if path.is_dir() {
+            collect_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Copy the file or folder at `source` to `target`, returning the copy's path
+fn copy_model(source: &Path, target_dir: &Path) -> Result<PathBuf> {
+    if source.is_dir() {
+        let mut files = Vec::new();
+        collect_files(source, &mut files)?;
+        for file in files {
+            let relative = file.strip_prefix(source).expect("collected under source");
+            let target = target_dir.join(relative);
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)
+                    .with_context(|| format!("Failed to create directory {}", parent.display()))?;
+            }
+            fs::copy(&file, &target)
+                .with_context(|| format!("Failed to copy {}", file.display()))?;
+        }
+        return Ok(target_dir.to_path_buf());
+    }
+
+    fs::create_dir_all(target_dir)
+        .with_context(|| format!("Failed to create directory {}", target_dir.display()))?;
+    let target = target_dir.join(source.file_name().context("Model file has no name")?);
+    fs::copy(source, &target).with_context(|| format!("Failed to copy {}", source.display()))?;
+    Ok(target)
+}
+
+/// Describe `path`, named relative to the model `root` (a checkpoint is its own root)
+fn external_file(root: &Path, path: &Path) -> Result<ModelFile> {
+    let rfilename = match path.strip_prefix(root) {
+        Ok(relative) if !relative.as_os_str().is_empty() => relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/"),
+        _ => path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned(),
+    };
+    let size = fs::metadata(path)
+        .with_context(|| format!("Couldn't get file size for `{}`", path.display()))?
+        .len();
+    // Other tools' files are named by hand often enough that the name alone
+    // isn't trusted
+    let format = WeightFormat::detect(path, &rfilename)
+        .with_context(|| format!("Failed to read `{}`", path.display()))?;
+    Ok(ModelFile {
+        size,
+        path: path.to_path_buf(),
+        sha256: None,
+        rfilename: Some(rfilename),
+        format: Some(format),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ModelManagerBuilder;
+    use tempfile::tempdir;
+
+    fn touch(root: &Path, files: &[(&str, &str)]) -> Result<()> {
+        for (file, contents) in files {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap())?;
+            fs::write(path, contents)?;
+        }
+        Ok(())
+    }
+
+    /// A stable-diffusion-webui style model tree
+    fn webui(root: &Path) -> Result<PathBuf> {
+        let models = root.join("webui/models");
+        touch(
+            &models,
+            &[
+                ("Stable-diffusion/v1-5 pruned.safetensors", "weights"),
+                ("Stable-diffusion/old.ckpt", "ckpt"),
+                ("Stable-diffusion/notes.txt", "not a model"),
+                ("Lora/style/detail.safetensors", "lora"),
+                ("diffusers/sd-turbo/model_index.json", "{}"),
+                ("diffusers/sd-turbo/unet/config.json", "{}"),
+                ("diffusers/sd-turbo/unet/model.safetensors", "unet"),
+            ],
+        )?;
+        Ok(models)
+    }
+
+    fn manager(root: &Path) -> Result<ModelManager> {
+        ModelManagerBuilder::new()
+            .with_models_dir(root.join("models"))
+            .with_source(Box::new(crate::MockSource::new(
+                root.join("fixtures"),
+                root.join("cache"),
+            )))
+            .build()
+    }
+
+    #[test]
+    fn test_import_webui_directory_in_place() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let models = webui(temp_dir.path())?;
+        let manager = manager(temp_dir.path())?;
+
+        let shallow = manager.find_external(&models.join("Stable-diffusion"), false)?;
+        let ids: Vec<_> = shallow.iter().map(|m| m.model_id.as_str()).collect();
+        assert_eq!(ids, ["local/old", "local/v1-5-pruned"]);
+
+        let report = manager.import_external(&models, true, false)?;
+        let mut ids: Vec<_> = report
+            .imported
+            .iter()
+            .map(|m| m.model_id.as_str())
+            .collect();
+        ids.sort();
+        assert_eq!(
+            ids,
+            [
+                "local/detail",
+                "local/old",
+                "local/sd-turbo",
+                "local/v1-5-pruned"
+            ]
+        );
+        assert!(report.skipped.is_empty());
+
+        let turbo = manager.get_model("local/sd-turbo")?.unwrap();
+        assert_eq!(turbo.origin, ModelOrigin::External);
+        assert_eq!(turbo.source, IndexSource::External);
+        let names: Vec<_> = turbo.files.iter().filter_map(ModelFile::name).collect();
+        assert_eq!(
+            names,
+            [
+                "model_index.json",
+                "unet/config.json",
+                "unet/model.safetensors"
+            ]
+        );
+        // Indexed in place, nothing copied
+        assert!(
+            turbo.files[0]
+                .path
+                .starts_with(index_paths::canonicalize(&models)?)
+        );
+        assert!(!manager.external_copy_dir("local/sd-turbo").exists());
+
+        let checkpoint = manager.get_model("local/v1-5-pruned")?.unwrap();
+        assert_eq!(checkpoint.total_size(), 7);
+        assert_eq!(checkpoint.files[0].name(), Some("v1-5 pruned.safetensors"));
+
+        // A second import finds the same models already indexed
+        let again = manager.import_external(&models, true, false)?;
+        assert!(again.imported.is_empty());
+        assert_eq!(again.skipped.len(), 4);
+        assert_eq!(manager.list_models()?.len(), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_classifies_weight_formats() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let models = webui(temp_dir.path())?;
+        // A pickle in PyTorch's zip container, named as if it were safe
+        touch(
+            &models,
+            &[(
+                "Stable-diffusion/renamed.safetensors",
+                "PK\u{3}\u{4}data.pkl",
+            )],
+        )?;
+        let manager = manager(temp_dir.path())?;
+        manager.import_external(&models, true, false)?;
+
+        let format = |id: &str| -> Result<Vec<Option<WeightFormat>>> {
+            let model = manager.get_model(id)?.unwrap();
+            Ok(model.files.iter().map(|f| f.format).collect())
+        };
+        assert_eq!(format("local/old")?, [Some(WeightFormat::Pickle)]);
+        assert_eq!(format("local/renamed")?, [Some(WeightFormat::Pickle)]);
+        assert_eq!(
+            format("local/v1-5-pruned")?,
+            [Some(WeightFormat::Safetensors)]
+        );
+        assert_eq!(
+            format("local/sd-turbo")?,
+            [
+                Some(WeightFormat::Unknown),
+                Some(WeightFormat::Unknown),
+                Some(WeightFormat::Safetensors)
+            ]
+        );
+
+        let mut candidates: Vec<_> = manager
+            .conversion_candidates()?
+            .into_iter()
+            .map(|m| m.model_id)
+            .collect();
+        candidates.sort();
+        assert_eq!(candidates, ["local/old", "local/renamed"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_model_dirs_of_a_scattered_import() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let models = webui(temp_dir.path())?;
+        let manager = manager(temp_dir.path())?;
+        let turbo = index_paths::canonicalize(&models.join("diffusers/sd-turbo"))?;
+        manager.import_external_as(&turbo, "me/turbo", false)?;
+
+        assert_eq!(
+            manager.model_dirs("me/turbo")?,
+            [turbo.clone(), turbo.join("unet")]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_copy_and_override_id() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let models = webui(temp_dir.path())?;
+        let manager = manager(temp_dir.path())?;
+
+        let model =
+            manager.import_external_as(&models.join("diffusers/sd-turbo"), "me/turbo", true)?;
+        assert_eq!(model.model_id, "me/turbo");
+        assert_eq!(model.source, IndexSource::Import);
+        let copy_dir = manager.external_copy_dir("me/turbo");
+        assert!(copy_dir.join("unet/model.safetensors").is_file());
+        assert!(model.files.iter().all(|f| f.path.starts_with(&copy_dir)));
+
+        // The Stable-diffusion folder holds two checkpoints
+        assert!(
+            manager
+                .import_external_as(&models.join("Stable-diffusion"), "me/one", false)
+                .is_err()
+        );
+        assert!(
+            manager
+                .import_external_as(&models.join("diffusers/sd-turbo"), "me/turbo", false)
+                .is_err()
+        );
+        assert!(
+            manager
+                .import_external(&temp_dir.path().join("missing"), false, false)
+                .is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_local_paths_are_told_from_model_ids() {
+        for path in [
+            "./my-finetune",
+            "../models/sd",
+            "/models/sd",
+            "~/models/sd",
+            "~",
+        ] {
+            assert!(is_local_model(path), "{path}");
+        }
+        for id in [
+            "org/model",
+            "gpt2",
+            "my-finetune",
+            ".hidden/model",
+            "org/~model",
+        ] {
+            assert!(!is_local_model(id), "{id}");
+        }
+        assert!(expand_home("~/models/sd").unwrap().ends_with("models/sd"));
+        assert!(expand_home("~someone/sd").is_err());
+    }
+
+    #[test]
+    fn test_open_local_leaves_the_index_alone() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let models = webui(temp_dir.path())?;
+        let manager = manager(temp_dir.path())?;
+        let turbo = models.join("diffusers/sd-turbo");
+        let spec = turbo.to_str().unwrap();
+
+        let local = manager.open_local(spec)?;
+        assert_eq!(local.info.model_id, "local/sd-turbo");
+        assert_eq!(local.layout, ExternalLayout::Diffusers);
+        assert_eq!(local.info.files.len(), 3);
+        assert!(manager.list_models()?.is_empty());
+        let hash = local.content_hash(None)?;
+        assert_eq!(hash.len(), 64);
+        assert_eq!(manager.open_local(spec)?.content_hash(None)?, hash);
+
+        // Cached hashes are reused only while the files are unchanged
+        let cache = HashCache::new(temp_dir.path().join("hashes"), "sha256");
+        assert_eq!(local.content_hash(Some(&cache))?, hash);
+        assert_eq!(cache.usage()?.entries, 3);
+        assert_eq!(local.content_hash(Some(&cache))?, hash);
+        assert_eq!(cache.usage()?.entries, 3);
+
+        // The hash follows the contents
+        fs::write(turbo.join("unet/model.safetensors"), "retrained")?;
+        let retrained = manager.open_local(spec)?;
+        assert_ne!(retrained.content_hash(None)?, hash);
+        assert_eq!(
+            retrained.content_hash(Some(&cache))?,
+            retrained.content_hash(None)?
+        );
+
+        assert!(manager.open_local("org/model").is_err());
+        let two = manager.open_local(models.join("Stable-diffusion").to_str().unwrap());
+        assert!(two.unwrap_err().to_string().contains("found 2"));
+        assert!(
+            manager
+                .open_local(temp_dir.path().join("missing").to_str().unwrap())
+                .is_err()
+        );
+
+        let registered = manager.register_local(&local)?.expect("newly registered");
+        let indexed = manager.get_model("local/sd-turbo")?.expect("indexed");
+        assert_eq!(indexed.files.len(), registered.files.len());
+        assert_eq!(indexed.source, IndexSource::External);
+        assert!(manager.register_local(&local)?.is_none());
+        // Another folder by the same name isn't taken for the registered one
+        let other = temp_dir.path().join("elsewhere/sd-turbo");
+        touch(&other, &[("model_index.json", "{}")])?;
+        let other = manager.open_local(other.to_str().unwrap())?;
+        assert!(manager.register_local(&other).is_err());
+        Ok(())
+    }
+}
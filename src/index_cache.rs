@@ -0,0 +1,185 @@
+//! In-memory copy of the parsed model index
+//!
+//! Listing models is the most common operation, and the server does it on every
+//! request, so [`ModelManager`] keeps the last parsed index along with the
+//! modification time and size of `model_index.json` it was read from. A read
+//! whose file still has the same stamp is served from memory; any other writer
+//! changes the stamp and causes a fresh parse. si's own saves replace the cached
+//! copy right away.
+
+use std::{
+    fs::Metadata,
+    sync::{
+        Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::SystemTime,
+};
+
+use log::debug;
+
+use crate::{ModelManager, logging::INDEX_TARGET, models::ModelIndexData};
+
+/// What identifies one version of the index file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct FileStamp {
+    modified: Option<SystemTime>,
+    len: u64,
+}
+
+impl From<&Metadata> for FileStamp {
+    fn from(metadata: &Metadata) -> Self {
+        Self {
+            modified: metadata.modified().ok(),
+            len: metadata.len(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Cached {
+    stamp: FileStamp,
+    index: ModelIndexData,
+}
+
+/// The parsed index shared by every [`ModelIndex`](crate::models::ModelIndex)
+/// handle of one manager
+#[derive(Debug, Default)]
+pub(crate) struct IndexCache {
+    cached: Mutex<Option<Cached>>,
+    parses: AtomicUsize,
+    saves: AtomicUsize,
+}
+
+impl IndexCache {
+    /// The cached index, if it was read from the file version `stamp`
+    pub fn get(&self, stamp: FileStamp) -> Option<ModelIndexData> {
+        let cached = self.cached.lock().expect("index cache lock");
+        let hit = cached
+            .as_ref()
+            .filter(|c| c.stamp == stamp)
+            .map(|c| c.index.clone());
+        if hit.is_none() && cached.is_some() {
+            debug!(target: INDEX_TARGET, "Model index changed on disk, reparsing");
+        }
+        hit
+    }
+
+    /// Remember `index` as the contents of the file version `stamp`
+    pub fn store(&self, stamp: FileStamp, index: &ModelIndexData) {
+        *self.cached.lock().expect("index cache lock") = Some(Cached {
+            stamp,
+            index: index.clone(),
+        });
+    }
+
+    /// Count one parse of the index file
+    pub fn record_parse(&self) {
+        self.parses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// How often the index file has been parsed
+    #[cfg(test)]
+    pub fn parses(&self) -> usize {
+        self.parses.load(Ordering::Relaxed)
+    }
+
+    /// Count one write of the index file
+    pub fn record_save(&self) {
+        self.saves.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// How often the index file has been written
+    #[cfg(test)]
+    pub fn saves(&self) -> usize {
+        self.saves.load(Ordering::Relaxed)
+    }
+
+    pub fn clear(&self) {
+        *self.cached.lock().expect("index cache lock") = None;
+    }
+}
+
+impl ModelManager {
+    /// Forget the cached model index so the next read parses the file again
+    ///
+    /// Changes to `model_index.json` are noticed on their own by its modification
+    /// time and size; this is for writers that preserve both.
+    pub fn invalidate_cache(&self) {
+        debug!(target: INDEX_TARGET, "Model index cache invalidated");
+        self.index_cache.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ModelManagerBuilder, models::ModelInfo};
+    use anyhow::Result;
+    use std::{fs, sync::Arc, thread};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_repeated_lists_parse_once() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let models_dir = temp_dir.path().join("models");
+        fs::create_dir_all(&models_dir)?;
+        let index_path = models_dir.join("model_index.json");
+        fs::write(
+            &index_path,
+            r#"{"models": [{"model_id": "org/a", "files": []}]}"#,
+        )?;
+        let manager = ModelManagerBuilder::new()
+            .with_models_dir(models_dir)
+            .build()?;
+
+        for _ in 0..5 {
+            assert_eq!(manager.list_models()?.len(), 1);
+        }
+        assert_eq!(manager.index_cache.parses(), 1);
+
+        // Our own writes update the cache instead of forcing a reparse
+        manager
+            .model_index()
+            .add_model(ModelInfo::new("org/b", vec![]))?;
+        assert_eq!(manager.list_models()?.len(), 2);
+        assert_eq!(manager.index_cache.parses(), 1);
+
+        // Another writer is noticed
+        fs::write(&index_path, r#"{"models": []}"#)?;
+        assert!(manager.list_models()?.is_empty());
+        assert_eq!(manager.index_cache.parses(), 2);
+
+        manager.invalidate_cache();
+        assert!(manager.list_models()?.is_empty());
+        assert_eq!(manager.index_cache.parses(), 3);
+
+        // A deleted index reads as empty rather than stale
+        fs::remove_file(&index_path)?;
+        assert!(manager.list_models()?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_cache_is_shared_across_threads() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let manager = Arc::new(
+            ModelManagerBuilder::new()
+                .with_models_dir(temp_dir.path().join("models"))
+                .build()?,
+        );
+        manager
+            .model_index()
+            .add_model(ModelInfo::new("org/a", vec![]))?;
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let manager = Arc::clone(&manager);
+                thread::spawn(move || manager.list_models().map(|m| m.len()))
+            })
+            .collect();
+        for handle in handles {
+            assert_eq!(handle.join().expect("lister panicked")?, 1);
+        }
+        assert_eq!(manager.index_cache.parses(), 0);
+        Ok(())
+    }
+}
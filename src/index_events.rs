@@ -0,0 +1,298 @@
+//! Notifications of changes to the model index
+//!
+//! [`ModelManager::subscribe`] hands out a receiver of [`IndexEvent`]s, sent
+//! after each save of `model_index.json` by this process has reached the disk,
+//! one per model whose entries it changed. A manager built with
+//! [`watch_external`](crate::ModelManagerBuilder::watch_external) also watches
+//! the file and sends [`IndexEvent::Reloaded`] when another process, such as
+//! the CLI, replaces it; what changed is then for the subscriber to read.
+//!
+//! Events go through a bounded broadcast channel: a subscriber that falls more
+//! than [`EVENT_CAPACITY`] events behind gets
+//! [`RecvError::Lagged`](tokio::sync::broadcast::error::RecvError::Lagged) and
+//! should list the models afresh.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{Context, Result};
+use log::{debug, warn};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use crate::{ModelManager, index_cache::FileStamp, logging::INDEX_TARGET, models::ModelInfo};
+
+/// Events a subscriber may fall behind by before it starts missing them
+pub const EVENT_CAPACITY: usize = 256;
+
+/// A change to the model index
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", content = "model_id", rename_all = "snake_case")]
+pub enum IndexEvent {
+    /// A model that wasn't indexed now is
+    ModelAdded(String),
+    /// A model is no longer indexed, in any variant
+    ModelRemoved(String),
+    /// A model's entries changed: files, pin, revisions or variants
+    ModelUpdated(String),
+    /// Another process replaced the index file
+    Reloaded,
+}
+
+/// Receiving end of [`ModelManager::subscribe`]
+pub type IndexEventReceiver = broadcast::Receiver<IndexEvent>;
+
+/// The sending side shared by every [`ModelIndex`](crate::models::ModelIndex)
+/// handle of one manager, and its file watcher
+#[derive(Debug, Clone)]
+pub(crate) struct IndexEvents {
+    sender: broadcast::Sender<IndexEvent>,
+    /// The index file as last written or seen, so the watcher can tell this
+    /// process's saves and repeated notifications from new changes
+    stamp: Arc<Mutex<Option<FileStamp>>>,
+}
+
+impl Default for IndexEvents {
+    fn default() -> Self {
+        Self {
+            sender: broadcast::channel(EVENT_CAPACITY).0,
+            stamp: Arc::default(),
+        }
+    }
+}
+
+impl IndexEvents {
+    pub fn subscribe(&self) -> IndexEventReceiver {
+        self.sender.subscribe()
+    }
+
+    /// Whether anyone listens, so saves can skip working out the changes
+    pub fn has_subscribers(&self) -> bool {
+        self.sender.receiver_count() > 0
+    }
+
+    /// Note that this process is about to rename `new_file` over the index, so
+    /// the watcher doesn't take the save for another process's
+    pub fn expect(&self, new_file: &Path) {
+        self.remember(new_file);
+    }
+
+    /// Send the changes a save made to the index, from `before` to `after`
+    pub fn saved(&self, before: &[ModelInfo], after: &[ModelInfo]) {
+        for event in changes(before, after) {
+            debug!(target: INDEX_TARGET, "Index event: {event:?}");
+            // Nobody listening is fine
+            let _ = self.sender.send(event);
+        }
+    }
+
+    /// Note the current stamp of `path`, returning whether it differs from the
+    /// last one noted; renaming a file keeps its stamp
+    fn remember(&self, path: &Path) -> bool {
+        let stamp = fs::metadata(path).ok().map(|m| FileStamp::from(&m));
+        let mut last = self.stamp.lock().expect("index event stamp lock");
+        let changed = *last != stamp;
+        *last = stamp;
+        changed
+    }
+
+    /// Watch the directory holding `index_path` and send
+    /// [`IndexEvent::Reloaded`] whenever the file changes other than by a save
+    /// announced with [`expect`](Self::expect)
+    ///
+    /// The directory is watched rather than the file because saves replace the
+    /// file by renaming a new one over it.
+    pub fn watch(&self, index_path: &Path) -> Result<RecommendedWatcher> {
+        let dir = index_path
+            .parent()
+            .context("Model index path has no parent directory")?;
+        self.remember(index_path);
+        let events = self.clone();
+        let path = index_path.to_path_buf();
+        let mut watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+            let event = match res {
+                Ok(event) => event,
+                Err(e) => {
+                    warn!("Watching the model index failed: {e}");
+                    return;
+                }
+            };
+            let touches_index = event
+                .paths
+                .iter()
+                .any(|p| p.file_name() == path.file_name());
+            if matches!(event.kind, EventKind::Access(_)) || !touches_index {
+                return;
+            }
+            if events.remember(&path) {
+                debug!(target: INDEX_TARGET, "Model index changed on disk");
+                let _ = events.sender.send(IndexEvent::Reloaded);
+            }
+        })
+        .context("Failed to start watching the model index")?;
+        watcher
+            .watch(dir, RecursiveMode::NonRecursive)
+            .with_context(|| format!("Failed to watch {}", dir.display()))?;
+        Ok(watcher)
+    }
+}
+
+/// One event per model whose entries differ between `before` and `after`, in
+/// model id order
+fn changes(before: &[ModelInfo], after: &[ModelInfo]) -> Vec<IndexEvent> {
+    let (before, after) = (by_id(before), by_id(after));
+    let mut events = Vec::new();
+    for (model_id, entries) in &after {
+        match before.get(model_id) {
+            None => events.push(IndexEvent::ModelAdded(model_id.to_string())),
+            Some(old) if old != entries => {
+                events.push(IndexEvent::ModelUpdated(model_id.to_string()));
+            }
+            Some(_) => {}
+        }
+    }
+    for model_id in before.keys().filter(|id| !after.contains_key(*id)) {
+        events.push(IndexEvent::ModelRemoved(model_id.to_string()));
+    }
+    events.sort_by(|a, b| event_model(a).cmp(event_model(b)));
+    events
+}
+
+/// Each model's entries as JSON, so they can be compared
+fn by_id(models: &[ModelInfo]) -> BTreeMap<&str, Vec<serde_json::Value>> {
+    let mut entries: BTreeMap<&str, Vec<serde_json::Value>> = BTreeMap::new();
+    for model in models {
+        entries
+            .entry(model.model_id.as_str())
+            .or_default()
+            .push(serde_json::to_value(model).unwrap_or_default());
+    }
+    entries
+}
+
+fn event_model(event: &IndexEvent) -> &str {
+    match event {
+        IndexEvent::ModelAdded(id)
+        | IndexEvent::ModelRemoved(id)
+        | IndexEvent::ModelUpdated(id) => id,
+        IndexEvent::Reloaded => "",
+    }
+}
+
+impl ModelManager {
+    /// Receive an [`IndexEvent`] for every change this manager saves to the
+    /// index from now on, and for changes by other processes when it was built
+    /// with [`watch_external`](crate::ModelManagerBuilder::watch_external)
+    pub fn subscribe(&self) -> IndexEventReceiver {
+        self.index_events.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ModelManagerBuilder, models::MODEL_INDEX_FILENAME};
+    use std::time::Duration;
+    use tempfile::tempdir;
+    use tokio::{sync::broadcast::error::TryRecvError, time::timeout};
+
+    fn drain(receiver: &mut IndexEventReceiver) -> Vec<IndexEvent> {
+        let mut events = Vec::new();
+        while let Ok(event) = receiver.try_recv() {
+            events.push(event);
+        }
+        events
+    }
+
+    #[test]
+    fn test_events_follow_changes_made_through_the_manager() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let manager = ModelManagerBuilder::new()
+            .with_models_dir(temp_dir.path().join("models"))
+            .build()?;
+        let index = manager.model_index();
+        // Saves before anyone subscribes aren't replayed
+        index.add_model(ModelInfo::new("org/early", vec![]))?;
+
+        let mut receiver = manager.subscribe();
+        index.add_model(ModelInfo::new("org/first", vec![]))?;
+        index.add_model(ModelInfo::new("org/second", vec![]))?;
+        manager.set_pinned("org/first", true)?;
+        // Unchanged entries send nothing
+        manager.set_pinned("org/first", true)?;
+        manager.delete_model("org/second")?;
+        assert!(manager.delete_model("org/absent").is_err());
+
+        assert_eq!(
+            drain(&mut receiver),
+            [
+                IndexEvent::ModelAdded("org/first".to_string()),
+                IndexEvent::ModelAdded("org/second".to_string()),
+                IndexEvent::ModelUpdated("org/first".to_string()),
+                IndexEvent::ModelRemoved("org/second".to_string()),
+            ]
+        );
+        assert_eq!(receiver.try_recv(), Err(TryRecvError::Empty));
+        Ok(())
+    }
+
+    #[test]
+    fn test_changes_merge_variants_by_model() {
+        let variant = |id: &str, variant: &str| {
+            let mut model = ModelInfo::new(id, vec![]);
+            model.variant = Some(variant.to_string());
+            model
+        };
+        let before = [variant("org/a", "fp16"), ModelInfo::new("org/b", vec![])];
+        let after = [variant("org/a", "fp16"), variant("org/a", "fp32")];
+        assert_eq!(
+            changes(&before, &after),
+            [
+                IndexEvent::ModelUpdated("org/a".to_string()),
+                IndexEvent::ModelRemoved("org/b".to_string()),
+            ]
+        );
+        assert!(changes(&after, &after).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_external_writes_send_reloaded() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let models_dir = temp_dir.path().join("models");
+        let manager = ModelManagerBuilder::new()
+            .with_models_dir(models_dir.clone())
+            .watch_external(true)
+            .build()?;
+        let mut receiver = manager.subscribe();
+        let wait = Duration::from_secs(5);
+
+        manager
+            .model_index()
+            .add_model(ModelInfo::new("org/own", vec![]))?;
+        assert_eq!(
+            timeout(wait, receiver.recv()).await??,
+            IndexEvent::ModelAdded("org/own".to_string())
+        );
+        // The manager's own save isn't reported as a reload
+        let quiet = timeout(Duration::from_millis(300), receiver.recv()).await;
+        assert!(quiet.is_err(), "{quiet:?}");
+
+        fs::write(
+            models_dir.join(MODEL_INDEX_FILENAME),
+            r#"{"models": [{"model_id": "org/cli", "files": []}]}"#,
+        )?;
+        assert_eq!(timeout(wait, receiver.recv()).await??, IndexEvent::Reloaded);
+        let ids: Vec<_> = manager
+            .list_models()?
+            .into_iter()
+            .map(|m| m.model_id)
+            .collect();
+        assert_eq!(ids, ["org/cli"]);
+        Ok(())
+    }
+}
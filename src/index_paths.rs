@@ -0,0 +1,497 @@
+//! Checks on the file paths recorded in the model index
+//!
+//! The index is a JSON file anyone can edit, so its paths aren't trusted: a
+//! crafted or corrupted entry could point at `/etc/passwd` or climb out of the
+//! cache with `..`. Every path must be absolute, free of `..` and, once symlinks
+//! are resolved, inside the models directory or the Hugging Face cache. Models
+//! imported in place ([`ModelOrigin::External`]) are exempt from the last rule,
+//! as their files are wherever the user keeps them.
+//!
+//! What happens to violations on load is the [`PathPolicy`]. Whatever the
+//! policy, directories are checked again right before si deletes anything.
+//!
+//! Paths are compared through [`normalize_path`]. On Windows
+//! `fs::canonicalize` returns extended-length `\\?\C:\...` paths while the
+//! index and the Hub cache use plain `C:\...` ones, and drive letters and
+//! names differ in case between the two, so neither would match as written.
+
+use std::{
+    fmt, fs, io,
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::{Result, bail};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ModelManager,
+    logging::INDEX_TARGET,
+    models::{ModelIndexData, ModelOrigin},
+};
+
+/// What loading the index does with paths that fail the checks
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PathPolicy {
+    /// Log a warning and keep the entry, as indexes written by older versions
+    /// may hold relative paths
+    #[default]
+    Warn,
+    /// Log a warning and drop the offending files from their model
+    Filter,
+    /// Fail the load
+    Strict,
+}
+
+impl FromStr for PathPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "warn" => Ok(Self::Warn),
+            "filter" => Ok(Self::Filter),
+            "strict" => Ok(Self::Strict),
+            other => bail!("Unknown path policy `{other}`, expected warn, filter or strict"),
+        }
+    }
+}
+
+impl fmt::Display for PathPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Warn => "warn",
+            Self::Filter => "filter",
+            Self::Strict => "strict",
+        })
+    }
+}
+
+/// An indexed file whose path fails the checks
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PathViolation {
+    pub model_id: String,
+    pub path: PathBuf,
+    pub reason: String,
+}
+
+impl fmt::Display for PathViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} {}",
+            self.model_id,
+            self.path.display(),
+            self.reason
+        )
+    }
+}
+
+/// Longest path Windows takes without the extended-length `\\?\` prefix
+#[cfg(windows)]
+const MAX_PATH: usize = 260;
+
+/// `path` without the extended-length `\\?\` prefix, where dropping it keeps
+/// its meaning
+///
+/// The prefix stays on paths of [`MAX_PATH`] or more, as deeply nested
+/// snapshots can be, and on ones with components Windows would read
+/// differently without it. Paths elsewhere are returned as they are.
+#[cfg(windows)]
+pub(crate) fn simplify(path: &Path) -> PathBuf {
+    use std::{ffi::OsString, path::Prefix};
+
+    let mut components = path.components();
+    let Some(Component::Prefix(prefix)) = components.next() else {
+        return path.to_path_buf();
+    };
+    let mut simple = match prefix.kind() {
+        Prefix::VerbatimDisk(drive) => OsString::from(format!("{}:", char::from(drive))),
+        Prefix::VerbatimUNC(server, share) => {
+            let mut unc = OsString::from(r"\\");
+            unc.push(server);
+            unc.push(r"\");
+            unc.push(share);
+            unc
+        }
+        _ => return path.to_path_buf(),
+    };
+    let rest = components.as_path();
+    if !rest.components().all(|c| match c {
+        Component::RootDir => true,
+        Component::Normal(name) => is_plain_name(name),
+        _ => false,
+    }) {
+        return path.to_path_buf();
+    }
+    simple.push(rest);
+    if simple.len() >= MAX_PATH {
+        return path.to_path_buf();
+    }
+    PathBuf::from(simple)
+}
+
+/// Whether `name` means the same without the `\\?\` prefix: no `.` or `..`,
+/// no `/`, no trailing dot or space and no DOS device name
+#[cfg(windows)]
+fn is_plain_name(name: &std::ffi::OsStr) -> bool {
+    const DEVICES: &[&str] = &[
+        "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+        "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+    ];
+    let Some(name) = name.to_str() else {
+        return false;
+    };
+    let stem = name.split('.').next().unwrap_or(name).trim_end();
+    !name.is_empty()
+        && name != "."
+        && name != ".."
+        && !name.contains('/')
+        && !name.ends_with(['.', ' '])
+        && !DEVICES
+            .iter()
+            .any(|device| device.eq_ignore_ascii_case(stem))
+}
+
+/// `path` as it is; only Windows has prefixes to drop
+#[cfg(not(windows))]
+pub(crate) fn simplify(path: &Path) -> PathBuf {
+    path.to_path_buf()
+}
+
+/// `fs::canonicalize`, without the `\\?\` prefix it adds on Windows
+///
+/// Paths that get stored, such as those of models imported in place, are
+/// canonicalized this way so they look like the ones the Hub cache gives.
+pub(crate) fn canonicalize(path: &Path) -> io::Result<PathBuf> {
+    fs::canonicalize(path).map(|canonical| simplify(&canonical))
+}
+
+/// `path` in the form paths are compared in: simplified and, on Windows, where
+/// names are case-insensitive, lowercased
+///
+/// Only for comparing; the result isn't a path to store or show.
+pub(crate) fn normalize_path(path: &Path) -> PathBuf {
+    let simple = simplify(path);
+    if cfg!(windows) {
+        if let Some(text) = simple.to_str() {
+            return PathBuf::from(text.to_lowercase());
+        }
+    }
+    simple
+}
+
+/// Whether `a` and `b` name the same path once normalized
+pub(crate) fn same_path(a: &Path, b: &Path) -> bool {
+    normalize_path(a) == normalize_path(b)
+}
+
+/// Whether `path` is `root` or below it, once both are normalized
+pub(crate) fn is_within(path: &Path, root: &Path) -> bool {
+    normalize_path(path).starts_with(normalize_path(root))
+}
+
+/// Why `path` can't be trusted on its own, before looking at where it points
+pub(crate) fn lexical_violation(path: &Path) -> Option<&'static str> {
+    if !path.is_absolute() {
+        Some("is not absolute")
+    } else if path.components().any(|c| c == Component::ParentDir) {
+        Some("contains `..`")
+    } else {
+        None
+    }
+}
+
+/// The directories indexed paths must stay inside, and the policy for those
+/// that don't
+#[derive(Debug, Clone)]
+pub(crate) struct PathGuard {
+    /// Each root as configured and, when it exists, canonicalized
+    roots: Vec<PathBuf>,
+    policy: PathPolicy,
+}
+
+impl PathGuard {
+    pub fn new(roots: &[&Path], policy: PathPolicy) -> Self {
+        let mut spellings = Vec::new();
+        for root in roots {
+            spellings.push(root.to_path_buf());
+            if let Ok(canonical) = canonicalize(root) {
+                spellings.push(canonical);
+            }
+        }
+        Self {
+            roots: spellings,
+            policy,
+        }
+    }
+
+    /// Why `path` fails the checks, if it does
+    ///
+    /// A path that exists is canonicalized first, so symlinks out of a root are
+    /// caught; one that doesn't is judged as written.
+    pub fn violation(&self, path: &Path) -> Option<&'static str> {
+        if let Some(reason) = lexical_violation(path) {
+            return Some(reason);
+        }
+        let resolved = canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        if self.roots.iter().any(|root| is_within(&resolved, root)) {
+            None
+        } else {
+            Some("is outside the models directory and the Hugging Face cache")
+        }
+    }
+
+    /// Fail unless `path` passes the checks; called before deleting it
+    pub fn ensure_within(&self, path: &Path) -> Result<()> {
+        match self.violation(path) {
+            Some(reason) => bail!("Refusing to touch {}: it {reason}", path.display()),
+            None => Ok(()),
+        }
+    }
+
+    /// Every indexed file of `index` that fails the checks
+    pub fn violations(&self, index: &ModelIndexData) -> Vec<PathViolation> {
+        let mut violations = Vec::new();
+        for model in &index.models {
+            for file in &model.files {
+                let reason = match model.origin {
+                    ModelOrigin::External => lexical_violation(&file.path),
+                    ModelOrigin::HuggingFace => self.violation(&file.path),
+                };
+                if let Some(reason) = reason {
+                    violations.push(PathViolation {
+                        model_id: model.model_id.clone(),
+                        path: file.path.clone(),
+                        reason: reason.to_string(),
+                    });
+                }
+            }
+        }
+        violations
+    }
+
+    /// Apply the policy to a freshly loaded `index`
+    pub fn screen(&self, index: &mut ModelIndexData) -> Result<()> {
+        let violations = self.violations(index);
+        if violations.is_empty() {
+            return Ok(());
+        }
+        if self.policy == PathPolicy::Strict {
+            let list: Vec<_> = violations.iter().map(ToString::to_string).collect();
+            bail!(
+                "The model index has unsafe file paths:\n  {}",
+                list.join("\n  ")
+            );
+        }
+        for violation in &violations {
+            warn!(target: INDEX_TARGET, "Unsafe path in the model index: {violation}");
+        }
+        if self.policy == PathPolicy::Filter {
+            for model in &mut index.models {
+                model.files.retain(|file| {
+                    !violations
+                        .iter()
+                        .any(|v| v.model_id == model.model_id && v.path == file.path)
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ModelManager {
+    /// Indexed files whose paths fail the checks, whatever the [`PathPolicy`]
+    pub fn path_violations(&self) -> Result<Vec<PathViolation>> {
+        let index = self.model_index().unscreened()?;
+        Ok(self.path_guard().violations(&index))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ModelManagerBuilder,
+        models::{ModelFile, ModelInfo},
+        source::MockSource,
+    };
+    use std::fs;
+    use tempfile::tempdir;
+
+    fn file(path: impl Into<PathBuf>) -> ModelFile {
+        ModelFile {
+            size: 4,
+            path: path.into(),
+            sha256: None,
+            rfilename: None,
+            format: None,
+        }
+    }
+
+    /// A manager whose index holds one safe model and one crafted to escape
+    fn manager(root: &Path, policy: PathPolicy) -> Result<ModelManager> {
+        let models_dir = root.join("models");
+        let cache = root.join("cache");
+        fs::create_dir_all(cache.join("models--org--safe/blobs"))?;
+        fs::create_dir_all(&models_dir)?;
+        fs::write(cache.join("models--org--safe/blobs/abc"), "1234")?;
+        fs::write(root.join("secret"), "hunter2")?;
+        // A symlink inside the cache that leads out of it
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(root.join("secret"), cache.join("escape"))?;
+
+        let index = ModelIndexData::new(vec![
+            ModelInfo::new(
+                "org/safe",
+                vec![file(cache.join("models--org--safe/blobs/abc"))],
+            ),
+            ModelInfo::new(
+                "org/evil",
+                vec![
+                    file("/etc/passwd"),
+                    file(cache.join("models--org--evil/../../secret")),
+                    file("relative.bin"),
+                    file(cache.join("escape")),
+                ],
+            ),
+        ]);
+        fs::write(
+            models_dir.join("model_index.json"),
+            serde_json::to_string(&index)?,
+        )?;
+        ModelManagerBuilder::new()
+            .with_models_dir(models_dir)
+            .with_source(Box::new(MockSource::new(root.join("fixtures"), cache)))
+            .with_path_policy(policy)
+            .build()
+    }
+
+    #[test]
+    fn test_crafted_paths_are_reported() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let manager = manager(temp_dir.path(), PathPolicy::Warn)?;
+        let violations = manager.path_violations()?;
+        let reasons: Vec<_> = violations.iter().map(|v| v.reason.as_str()).collect();
+        let expected = [
+            "is outside the models directory and the Hugging Face cache",
+            "contains `..`",
+            "is not absolute",
+        ];
+        assert_eq!(reasons[..3], expected);
+        #[cfg(unix)]
+        assert_eq!(reasons[3], expected[0]);
+        assert!(violations.iter().all(|v| v.model_id == "org/evil"));
+
+        // Warn keeps the entries for compatibility
+        let evil = manager.get_model("org/evil")?.unwrap();
+        assert_eq!(evil.files.len(), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter_drops_crafted_paths() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let manager = manager(temp_dir.path(), PathPolicy::Filter)?;
+        let kept = manager.get_model("org/evil")?.unwrap().files;
+        // Only the escaping symlink survives where symlinks weren't created
+        assert_eq!(kept.len(), if cfg!(unix) { 0 } else { 1 });
+        assert_eq!(manager.get_model("org/safe")?.unwrap().files.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_strict_fails_the_load() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let manager = manager(temp_dir.path(), PathPolicy::Strict)?;
+        let err = manager.list_models().unwrap_err();
+        assert!(format!("{err:#}").contains("/etc/passwd"), "{err:#}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_external_models_may_live_anywhere() {
+        let guard = PathGuard::new(&[Path::new("/srv/models")], PathPolicy::Strict);
+        let mut model = ModelInfo::new("local/sd", vec![file("/home/me/sd.safetensors")]);
+        model.origin = ModelOrigin::External;
+        let mut index = ModelIndexData::new(vec![model]);
+        assert!(guard.violations(&index).is_empty());
+        index.models[0]
+            .files
+            .push(file("/home/me/../../etc/passwd"));
+        assert_eq!(guard.violations(&index).len(), 1);
+    }
+
+    #[test]
+    fn test_is_within_compares_normalized_paths() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let cache = temp_dir.path().join("cache");
+        fs::create_dir_all(cache.join("models--org--repo"))?;
+        let canonical = canonicalize(&cache.join("models--org--repo"))?;
+        assert!(is_within(&canonical, &canonicalize(&cache)?));
+        assert!(same_path(&canonical, &canonicalize(&canonical)?));
+        assert!(!is_within(&temp_dir.path().join("cache-other"), &cache));
+        Ok(())
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_normalize_path_drops_prefixes_and_case() {
+        for (path, simple) in [
+            (r"\\?\C:\Users\me\.cache", r"C:\Users\me\.cache"),
+            (r"\\?\UNC\server\share\hub", r"\\server\share\hub"),
+            (r"C:\Users\me", r"C:\Users\me"),
+            // Components that mean something else without the prefix keep it
+            (r"\\?\C:\cache\NUL", r"\\?\C:\cache\NUL"),
+            (r"\\?\C:\cache\trailing.", r"\\?\C:\cache\trailing."),
+            (r"\\?\C:\cache\..\x", r"\\?\C:\cache\..\x"),
+        ] {
+            assert_eq!(simplify(Path::new(path)), Path::new(simple), "{path}");
+        }
+
+        assert_eq!(
+            normalize_path(Path::new(r"\\?\C:\Users\Me\Models--Org--Repo")),
+            Path::new(r"c:\users\me\models--org--repo")
+        );
+        assert!(same_path(
+            Path::new(r"\\?\C:\hub\models--org--repo"),
+            Path::new(r"c:\HUB\models--org--repo")
+        ));
+        assert!(is_within(
+            Path::new(r"\\?\c:\hub\models--org--repo\blobs\abc"),
+            Path::new(r"C:\Hub")
+        ));
+        assert!(!is_within(Path::new(r"C:\hubs\x"), Path::new(r"C:\hub")));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_simplify_keeps_the_prefix_past_max_path() {
+        let long = format!(
+            r"\\?\C:\hub\snapshots\{}\model.safetensors",
+            "a".repeat(MAX_PATH)
+        );
+        assert_eq!(simplify(Path::new(&long)), Path::new(&long));
+        let canonical = canonicalize(&std::env::temp_dir()).unwrap();
+        assert!(!canonical.to_string_lossy().starts_with(r"\\?\"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_delete_refuses_a_symlinked_repo_dir() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let root = temp_dir.path();
+        let manager = manager(root, PathPolicy::Warn)?;
+        let outside = root.join("precious");
+        fs::create_dir_all(&outside)?;
+        fs::write(outside.join("keep.txt"), "keep")?;
+        std::os::unix::fs::symlink(&outside, root.join("cache/models--org--evil"))?;
+
+        let err = manager.delete_model("org/evil").unwrap_err();
+        assert!(err.to_string().contains("Refusing"), "{err:#}");
+        assert!(outside.join("keep.txt").exists());
+        assert!(manager.get_model("org/evil")?.is_some());
+        Ok(())
+    }
+}
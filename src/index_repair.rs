@@ -0,0 +1,352 @@
+//! Cleaning up duplicate entries in the model index
+//!
+//! Hand edits and bugs in older versions left some indexes holding the same
+//! model twice, under ids that differ only in case or surrounding whitespace,
+//! and entries listing one file twice. Lookups then find whichever entry comes
+//! first. [`ModelManager::repair_index`] trims the ids, drops repeated files and
+//! folds each set of duplicates into its richest entry: the one with the most
+//! files, then the most bytes, then the one indexed first. The index file is
+//! backed up before the cleaned one replaces it.
+
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use log::info;
+use serde::Serialize;
+
+use crate::{
+    ModelManager, index_paths,
+    logging::INDEX_TARGET,
+    models::{ModelIndexData, ModelInfo, lock_index, normalize_lexically},
+};
+
+/// What [`ModelManager::repair_index`] changed, or would change
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct RepairReport {
+    /// Ids that had whitespace around them
+    pub ids_trimmed: usize,
+    /// Duplicate entries with files, folded into a richer one
+    pub entries_merged: usize,
+    /// Duplicate entries without files, dropped
+    pub empty_entries_removed: usize,
+    /// Files listed more than once by the same entry
+    pub duplicate_files_removed: usize,
+    /// One line per change
+    pub changes: Vec<String>,
+    /// Where the index was copied before being rewritten; None on a dry run and
+    /// when there was nothing to repair
+    pub backup: Option<PathBuf>,
+}
+
+impl RepairReport {
+    pub fn is_clean(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// The count of anomalies, e.g. "2 duplicate entries, 1 repeated file"
+    pub fn summary(&self) -> String {
+        let entries = self.entries_merged + self.empty_entries_removed;
+        let mut parts = Vec::new();
+        for (count, singular, plural) in [
+            (entries, "duplicate entry", "duplicate entries"),
+            (
+                self.ids_trimmed,
+                "id with whitespace",
+                "ids with whitespace",
+            ),
+            (
+                self.duplicate_files_removed,
+                "repeated file",
+                "repeated files",
+            ),
+        ] {
+            match count {
+                0 => {}
+                1 => parts.push(format!("1 {singular}")),
+                n => parts.push(format!("{n} {plural}")),
+            }
+        }
+        parts.join(", ")
+    }
+}
+
+impl fmt::Display for RepairReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_clean() {
+            return f.write_str("The model index has no duplicates to repair.");
+        }
+        for (i, change) in self.changes.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            f.write_str(change)?;
+        }
+        Ok(())
+    }
+}
+
+/// Where `path` really is, for telling repeated files apart
+fn canonical(path: &Path) -> PathBuf {
+    let canonical = index_paths::canonicalize(path).unwrap_or_else(|_| normalize_lexically(path));
+    index_paths::normalize_path(&canonical)
+}
+
+/// Clean `index` in place
+pub(crate) fn repair(index: &mut ModelIndexData) -> RepairReport {
+    let mut report = RepairReport::default();
+    for model in &mut index.models {
+        let trimmed = model.model_id.trim();
+        if trimmed != model.model_id {
+            report
+                .changes
+                .push(format!("Trimmed {:?} to `{trimmed}`", model.model_id));
+            report.ids_trimmed += 1;
+            model.model_id = trimmed.to_string();
+        }
+
+        let before = model.files.len();
+        let mut seen = HashSet::new();
+        model
+            .files
+            .retain(|file| seen.insert(canonical(&file.path)));
+        let removed = before - model.files.len();
+        if removed > 0 {
+            report.changes.push(format!(
+                "Removed {removed} repeated file{} from `{}`",
+                if removed == 1 { "" } else { "s" },
+                model.model_id
+            ));
+            report.duplicate_files_removed += removed;
+        }
+    }
+
+    // Entries grouped by case-insensitive id and variant, in the order each
+    // first appears; variants of one model are separate entries, not duplicates
+    let mut groups: Vec<Vec<ModelInfo>> = Vec::new();
+    let mut positions: HashMap<(String, Option<String>), usize> = HashMap::new();
+    for model in index.models.drain(..) {
+        let key = (model.model_id.to_lowercase(), model.variant.clone());
+        match positions.get(&key) {
+            Some(&i) => groups[i].push(model),
+            None => {
+                positions.insert(key, groups.len());
+                groups.push(vec![model]);
+            }
+        }
+    }
+    index.models = groups
+        .into_iter()
+        .map(|group| merge(group, &mut report))
+        .collect();
+    report
+}
+
+/// The richest entry of `group`, keeping any pin, license or kept revisions of
+/// the others
+fn merge(mut group: Vec<ModelInfo>, report: &mut RepairReport) -> ModelInfo {
+    let richest = (0..group.len())
+        .max_by_key(|&i| (group[i].files.len(), group[i].total_size(), Reverse(i)))
+        .unwrap_or_default();
+    let mut kept = group.remove(richest);
+    for duplicate in group {
+        kept.pinned |= duplicate.pinned;
+        if kept.license.is_none() {
+            kept.license = duplicate.license;
+        }
+        if kept.pipeline_tag.is_none() {
+            kept.pipeline_tag = duplicate.pipeline_tag;
+        }
+        if kept.revisions.is_empty() {
+            kept.revisions = duplicate.revisions;
+        }
+        if duplicate.files.is_empty() {
+            report.changes.push(format!(
+                "Removed empty duplicate `{}` of `{}`",
+                duplicate.model_id, kept.model_id
+            ));
+            report.empty_entries_removed += 1;
+        } else {
+            report.changes.push(format!(
+                "Merged duplicate `{}` into `{}`",
+                duplicate.model_id, kept.model_id
+            ));
+            report.entries_merged += 1;
+        }
+    }
+    kept
+}
+
+impl ModelManager {
+    /// Fold duplicate index entries together and drop repeated files, backing up
+    /// the index before saving the result
+    pub fn repair_index(&self) -> Result<RepairReport> {
+        self.repair_index_with(false)
+    }
+
+    /// [`repair_index`](Self::repair_index), or with `dry_run` only report what
+    /// it would change
+    pub fn repair_index_with(&self, dry_run: bool) -> Result<RepairReport> {
+        let model_index = self.model_index();
+        let _lock = lock_index();
+        // Unscreened, so a filtering path policy doesn't drop files for good
+        let mut index_data = model_index.unscreened()?;
+        let mut report = repair(&mut index_data);
+        if dry_run || report.is_clean() {
+            return Ok(report);
+        }
+        report.backup = model_index.backup()?;
+        model_index.save(&index_data)?;
+        info!(target: INDEX_TARGET, "Repaired the model index: {}", report.summary());
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MockSource, ModelManagerBuilder, models::ModelFile};
+    use std::fs;
+    use tempfile::tempdir;
+
+    fn file(path: impl Into<PathBuf>, size: u64) -> ModelFile {
+        ModelFile {
+            size,
+            path: path.into(),
+            sha256: None,
+            rfilename: None,
+            format: None,
+        }
+    }
+
+    fn manager(root: &Path, models: Vec<ModelInfo>) -> Result<ModelManager> {
+        let models_dir = root.join("models");
+        fs::create_dir_all(&models_dir)?;
+        fs::write(
+            models_dir.join("model_index.json"),
+            serde_json::to_string(&ModelIndexData::new(models))?,
+        )?;
+        ModelManagerBuilder::new()
+            .with_models_dir(models_dir)
+            .with_source(Box::new(MockSource::new(
+                root.join("fixtures"),
+                root.join("cache"),
+            )))
+            .build()
+    }
+
+    /// Three spellings of one model, a file listed twice through `.`, and an
+    /// unrelated model that only needs trimming
+    fn messy_index(root: &Path) -> Vec<ModelInfo> {
+        let blobs = root.join("cache/blobs");
+        let mut empty = ModelInfo::new("org/model", vec![]);
+        empty.pinned = true;
+        let mut richer = ModelInfo::new(
+            "Org/Model ",
+            vec![
+                file(blobs.join("a"), 10),
+                file(blobs.join("b"), 20),
+                file(blobs.join("./a"), 10),
+            ],
+        );
+        richer.license = Some("mit".to_string());
+        let poorer = ModelInfo::new("ORG/MODEL", vec![file(blobs.join("a"), 10)]);
+        let other = ModelInfo::new("\torg/other", vec![file(blobs.join("c"), 5)]);
+        vec![empty, richer, poorer, other]
+    }
+
+    #[test]
+    fn test_repair_index() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let root = temp_dir.path();
+        let manager = manager(root, messy_index(root))?;
+        let index_path = manager.models_dir().join("model_index.json");
+        let before = fs::read_to_string(&index_path)?;
+
+        let report = manager.repair_index_with(true)?;
+        assert_eq!(report.backup, None);
+        assert_eq!(fs::read_to_string(&index_path)?, before);
+
+        let report = manager.repair_index()?;
+        assert_eq!(report.ids_trimmed, 2);
+        assert_eq!(report.duplicate_files_removed, 1);
+        assert_eq!(report.entries_merged, 1);
+        assert_eq!(report.empty_entries_removed, 1);
+        assert_eq!(
+            report.changes,
+            [
+                r#"Trimmed "Org/Model " to `Org/Model`"#,
+                "Removed 1 repeated file from `Org/Model`",
+                r#"Trimmed "\torg/other" to `org/other`"#,
+                "Removed empty duplicate `org/model` of `Org/Model`",
+                "Merged duplicate `ORG/MODEL` into `Org/Model`",
+            ]
+        );
+        assert_eq!(
+            report.summary(),
+            "2 duplicate entries, 2 ids with whitespace, 1 repeated file"
+        );
+        let backup = report.backup.expect("backup");
+        assert_eq!(fs::read_to_string(backup)?, before);
+
+        let models = manager.list_models()?;
+        let entries: Vec<_> = models
+            .iter()
+            .map(|m| {
+                let files: Vec<_> = m
+                    .files
+                    .iter()
+                    .map(|f| f.path.strip_prefix(root).unwrap().to_path_buf())
+                    .collect();
+                (m.model_id.as_str(), files, m.pinned, m.license.as_deref())
+            })
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                (
+                    "Org/Model",
+                    vec![
+                        PathBuf::from("cache/blobs/a"),
+                        PathBuf::from("cache/blobs/b")
+                    ],
+                    true,
+                    Some("mit")
+                ),
+                (
+                    "org/other",
+                    vec![PathBuf::from("cache/blobs/c")],
+                    false,
+                    None
+                ),
+            ]
+        );
+
+        // A repaired index has nothing left to repair
+        assert!(manager.repair_index()?.is_clean());
+        Ok(())
+    }
+
+    #[test]
+    fn test_repair_of_a_clean_index_writes_nothing() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let root = temp_dir.path();
+        let manager = manager(
+            root,
+            vec![
+                ModelInfo::new("org/a", vec![file(root.join("a"), 1)]),
+                ModelInfo::new("org/b", vec![]),
+            ],
+        )?;
+        let report = manager.repair_index()?;
+        assert!(report.is_clean());
+        assert_eq!(report.summary(), "");
+        assert_eq!(report.backup, None);
+        assert!(!manager.models_dir().join("model_index.json.bak").exists());
+        Ok(())
+    }
+}
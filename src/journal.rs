@@ -0,0 +1,595 @@
+//! Write-ahead journal for model index updates
+//!
+//! Every change to `model_index.json` is first appended to
+//! `model_index.journal` as one JSON line per [`IndexOp`], then applied and
+//! saved, and only then are its lines removed from the journal; those of other
+//! updates in flight stay. A journal that isn't empty therefore means a process
+//! died mid-update, for example between downloading a model's files and
+//! indexing them. [`ModelManager::recover`] finishes such an update, or rolls
+//! back adds whose files never made it to disk; it runs whenever a
+//! [`ModelManager`] is built.
+//!
+//! Bulk operations, such as downloading a manifest, collect their changes in an
+//! [`IndexTransaction`] instead. Each change is journaled as it's made, but the
+//! index itself is written once, when the transaction commits, rather than once
+//! per model.
+
+use std::{
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::Path,
+    slice,
+    sync::Mutex,
+};
+
+use anyhow::{Context, Result};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ModelManager,
+    logging::INDEX_TARGET,
+    model_id::same_id,
+    models::{ModelIndex, ModelIndexData, ModelInfo, lock_index},
+};
+
+/// One change to the model index
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum IndexOp {
+    /// Add `model`, replacing any entry with the same id and variant
+    Upsert { model: ModelInfo },
+    /// Remove the entries of `model_id`, in every variant, and its cached files
+    Remove { model_id: String },
+}
+
+impl IndexOp {
+    pub fn model_id(&self) -> &str {
+        match self {
+            Self::Upsert { model } => &model.model_id,
+            Self::Remove { model_id } => model_id,
+        }
+    }
+
+    pub(crate) fn apply(&self, index: &mut ModelIndexData) {
+        match self {
+            Self::Upsert { model } => match index.models.iter().position(|m| m.same_entry(model)) {
+                Some(i) => index.models[i] = model.clone(),
+                None => index.models.push(model.clone()),
+            },
+            Self::Remove { model_id } => index.models.retain(|m| !same_id(&m.model_id, model_id)),
+        }
+    }
+}
+
+impl fmt::Display for IndexOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Upsert { model } => write!(f, "add {}", model.display_id()),
+            Self::Remove { model_id } => write!(f, "remove {model_id}"),
+        }
+    }
+}
+
+/// Append `ops` to the journal at `path` and flush them to disk
+pub(crate) fn append(path: &Path, ops: &[IndexOp]) -> Result<()> {
+    let mut lines = Vec::new();
+    for op in ops {
+        serde_json::to_writer(&mut lines, op)?;
+        lines.push(b'\n');
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open index journal {}", path.display()))?;
+    file.write_all(&lines)
+        .and_then(|()| file.sync_data())
+        .with_context(|| format!("Failed to write index journal {}", path.display()))?;
+    debug!(target: INDEX_TARGET, "Journaled {} index operations", ops.len());
+    Ok(())
+}
+
+/// Remove the lines [`append`] wrote for `ops` from the journal at `path`,
+/// once they're saved in the index or dropped
+///
+/// Lines of other updates are kept for their own commit, or for recovery; a
+/// line cut short by a crash goes too. Callers hold
+/// [`lock_index`](crate::models::lock_index), so no append lands in between.
+pub(crate) fn remove(path: &Path, ops: &[IndexOp]) -> Result<()> {
+    let mut done = ops
+        .iter()
+        .map(serde_json::to_string)
+        .collect::<serde_json::Result<Vec<_>>>()?;
+    let journal = match fs::read_to_string(path) {
+        Ok(journal) => journal,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => {
+            return Err(e)
+                .with_context(|| format!("Failed to read index journal {}", path.display()));
+        }
+    };
+    let mut kept = String::new();
+    for line in journal.lines() {
+        if let Some(i) = done.iter().position(|op| op == line) {
+            done.swap_remove(i);
+        } else if serde_json::from_str::<IndexOp>(line).is_ok() {
+            kept.push_str(line);
+            kept.push('\n');
+        }
+    }
+    if kept.is_empty() {
+        return truncate(path);
+    }
+
+    let tmp_path = path.with_extension("journal.tmp");
+    File::create(&tmp_path)
+        .and_then(|mut file| {
+            file.write_all(kept.as_bytes())?;
+            file.sync_data()
+        })
+        .and_then(|()| fs::rename(&tmp_path, path))
+        .with_context(|| format!("Failed to write index journal {}", path.display()))
+}
+
+/// Empty the journal at `path` once all of its operations are saved in the index
+pub(crate) fn truncate(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => {
+            Err(e).with_context(|| format!("Failed to clear index journal {}", path.display()))
+        }
+    }
+}
+
+/// The operations left in the journal at `path`
+///
+/// A line that doesn't parse can only be the last one, cut short by a crash
+/// while appending; its update never reached the index, so it's skipped.
+pub(crate) fn pending(path: &Path) -> Result<Vec<IndexOp>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => {
+            return Err(e)
+                .with_context(|| format!("Failed to open index journal {}", path.display()));
+        }
+    };
+    let mut ops = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line =
+            line.with_context(|| format!("Failed to read index journal {}", path.display()))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(&line) {
+            Ok(op) => ops.push(op),
+            Err(e) => warn!(
+                "Skipping incomplete index journal entry in {}: {e}",
+                path.display()
+            ),
+        }
+    }
+    Ok(ops)
+}
+
+/// Changes to the model index saved together by [`commit`](Self::commit)
+///
+/// Until then the index is unchanged. A transaction dropped without committing
+/// discards its changes. A crash, or a failed save, instead leaves them in the
+/// journal for [`ModelManager::recover`] to finish like any other interrupted
+/// update.
+#[derive(Debug)]
+pub(crate) struct IndexTransaction {
+    index: ModelIndex,
+    ops: Mutex<Vec<IndexOp>>,
+    committed: bool,
+}
+
+impl IndexTransaction {
+    pub fn new(index: ModelIndex) -> Self {
+        Self {
+            index,
+            ops: Mutex::default(),
+            committed: false,
+        }
+    }
+
+    /// Add `model`, replacing any entry with the same id and variant
+    pub fn add(&self, model: ModelInfo) -> Result<()> {
+        debug!(target: INDEX_TARGET, "Adding `{}` to the index transaction.", model.model_id);
+        self.push(IndexOp::Upsert { model })
+    }
+
+    /// Journal `op`, to be applied on commit
+    pub fn push(&self, op: IndexOp) -> Result<()> {
+        let mut ops = self.ops.lock().expect("index transaction lock");
+        self.index.begin(slice::from_ref(&op))?;
+        ops.push(op);
+        Ok(())
+    }
+
+    /// The indexed models as committing would leave them
+    pub fn models(&self) -> Result<Vec<ModelInfo>> {
+        let mut index_data = self.index.model_index_data()?;
+        for op in self.ops.lock().expect("index transaction lock").iter() {
+            op.apply(&mut index_data);
+        }
+        Ok(index_data.models)
+    }
+
+    /// Whether `model_id` is indexed, counting the changes made so far
+    pub fn contains(&self, model_id: &str) -> Result<bool> {
+        Ok(self
+            .models()?
+            .iter()
+            .any(|m| same_id(&m.model_id, model_id)))
+    }
+
+    /// Apply every change to the index in one save
+    ///
+    /// The index is loaded, changed and saved under
+    /// [`lock_index`](crate::models::lock_index), so transactions committing at
+    /// the same time each keep the other's entries.
+    pub fn commit(mut self) -> Result<()> {
+        // From here on a failure leaves the changes for recovery
+        self.committed = true;
+        let ops = std::mem::take(self.ops.get_mut().expect("index transaction lock"));
+        if ops.is_empty() {
+            return Ok(());
+        }
+        debug!(target: INDEX_TARGET, "Committing {} index operations", ops.len());
+        self.index.commit(&ops)
+    }
+}
+
+impl Drop for IndexTransaction {
+    fn drop(&mut self) {
+        let ops = self.ops.get_mut().expect("index transaction lock");
+        if self.committed || ops.is_empty() {
+            return;
+        }
+        debug!(target: INDEX_TARGET, "Discarding {} uncommitted index operations", ops.len());
+        if let Err(e) = self.index.abort(ops) {
+            warn!("Failed to discard uncommitted index changes: {e:#}");
+        }
+    }
+}
+
+/// What [`ModelManager::recover`] did with an interrupted index update
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RecoveryReport {
+    /// Operations applied to the index after the fact
+    pub completed: Vec<IndexOp>,
+    /// Adds dropped because their files are missing
+    pub rolled_back: Vec<IndexOp>,
+}
+
+impl RecoveryReport {
+    pub fn is_empty(&self) -> bool {
+        self.completed.is_empty() && self.rolled_back.is_empty()
+    }
+}
+
+impl fmt::Display for RecoveryReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return f.write_str("No interrupted index updates.");
+        }
+        let lines = self
+            .completed
+            .iter()
+            .map(|op| format!("Completed interrupted {op}"))
+            .chain(
+                self.rolled_back
+                    .iter()
+                    .map(|op| format!("Rolled back interrupted {op}: its files are missing")),
+            );
+        for (i, line) in lines.enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            f.write_str(&line)?;
+        }
+        Ok(())
+    }
+}
+
+impl ModelManager {
+    /// Finish or roll back an index update a crash interrupted
+    ///
+    /// Adds are completed when all of the model's files exist and rolled back
+    /// otherwise; removals are always completed, deleting whatever cached files
+    /// are left. Called when the manager is built, so this is only needed to see
+    /// the report.
+    pub fn recover(&self) -> Result<RecoveryReport> {
+        let model_index = self.model_index();
+        let journal = model_index.journal_path();
+        let _lock = lock_index();
+        let ops = pending(&journal)?;
+        let mut report = RecoveryReport::default();
+        if ops.is_empty() {
+            return Ok(report);
+        }
+
+        let mut index_data = model_index.model_index_data()?;
+        for op in ops {
+            match &op {
+                IndexOp::Upsert { model } if !model.files.iter().all(|f| f.path.exists()) => {
+                    warn!("Rolling back interrupted index update: {op}, its files are missing");
+                    report.rolled_back.push(op);
+                    continue;
+                }
+                IndexOp::Upsert { .. } => {}
+                IndexOp::Remove { model_id } => {
+                    let indexed = index_data
+                        .models
+                        .iter()
+                        .find(|m| same_id(&m.model_id, model_id));
+                    if let Some(model) = indexed {
+                        self.remove_model_files(model)?;
+                    }
+                }
+            }
+            warn!("Completing interrupted index update: {op}");
+            op.apply(&mut index_data);
+            report.completed.push(op);
+        }
+        model_index.save(&index_data)?;
+        truncate(&journal)?;
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MockSource, ModelManagerBuilder, models::ModelFile};
+    use tempfile::tempdir;
+
+    fn manager(root: &Path) -> Result<ModelManager> {
+        ModelManagerBuilder::new()
+            .with_models_dir(root.join("models"))
+            .with_source(Box::new(MockSource::new(
+                root.join("fixtures"),
+                root.join("cache"),
+            )))
+            .build()
+    }
+
+    fn ids(manager: &ModelManager) -> Result<Vec<String>> {
+        Ok(manager
+            .list_models()?
+            .into_iter()
+            .map(|m| m.model_id)
+            .collect())
+    }
+
+    #[test]
+    fn test_remove_matches_ids_as_the_hub_does() {
+        let mut index = ModelIndexData::new(vec![
+            ModelInfo::new("Org/Model", vec![]),
+            ModelInfo::new("org/other", vec![]),
+        ]);
+        IndexOp::Remove {
+            model_id: " org/model ".to_string(),
+        }
+        .apply(&mut index);
+        let ids: Vec<_> = index.models.iter().map(|m| m.model_id.as_str()).collect();
+        assert_eq!(ids, ["org/other"]);
+    }
+
+    #[test]
+    fn test_recover_interrupted_add() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let root = temp_dir.path();
+        let manager = manager(root)?;
+
+        // Crash after the download but before the index was written
+        let file = root.join("cache/model.bin");
+        fs::create_dir_all(root.join("cache"))?;
+        fs::create_dir_all(root.join("models"))?;
+        fs::write(&file, "1234")?;
+        let mut model = ModelInfo::new("org/model", vec![]);
+        model.files.push(ModelFile {
+            size: 4,
+            path: file,
+            sha256: None,
+            rfilename: Some("model.bin".to_string()),
+            format: None,
+        });
+        let journal = manager.model_index().journal_path();
+        append(&journal, &[IndexOp::Upsert { model }])?;
+        assert!(ids(&manager)?.is_empty());
+
+        // Building a manager recovers on its own
+        let manager = self::manager(root)?;
+        assert_eq!(ids(&manager)?, ["org/model"]);
+        assert!(!journal.exists());
+        assert!(manager.recover()?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_recover_interrupted_delete() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join("fixtures/org/model"))?;
+        fs::write(root.join("fixtures/org/model/model.bin"), "1234")?;
+        let manager = manager(root)?;
+        manager.download_model("org/model").await?;
+        let cache_dir = root.join("cache/models--org--model");
+        assert!(cache_dir.exists());
+
+        // Crash after journaling the removal, before touching files or index
+        let journal = manager.model_index().journal_path();
+        append(
+            &journal,
+            &[IndexOp::Remove {
+                model_id: "org/model".to_string(),
+            }],
+        )?;
+        let report = manager.recover()?;
+        assert_eq!(report.to_string(), "Completed interrupted remove org/model");
+        assert!(ids(&manager)?.is_empty());
+        assert!(!cache_dir.exists());
+        assert!(!journal.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_transaction_saves_once_or_not_at_all() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let manager = manager(temp_dir.path())?;
+        let index = manager.model_index();
+        index.add_model(ModelInfo::new("org/first", vec![]))?;
+
+        index.transaction(|txn| {
+            for i in 0..50 {
+                txn.add(ModelInfo::new(format!("org/model-{i}"), vec![]))?;
+            }
+            assert!(txn.contains("org/model-49")?);
+            // Unsaved until the transaction commits
+            assert_eq!(ids(&manager)?, ["org/first"]);
+            Ok(())
+        })?;
+        assert_eq!(manager.index_cache.saves(), 2);
+        assert_eq!(ids(&manager)?.len(), 51);
+
+        let failed: Result<()> = index.transaction(|txn| {
+            txn.add(ModelInfo::new("org/discarded", vec![]))?;
+            anyhow::bail!("import failed")
+        });
+        assert!(failed.is_err());
+        assert_eq!(manager.index_cache.saves(), 2);
+        assert!(!ids(&manager)?.contains(&"org/discarded".to_string()));
+        assert!(!index.journal_path().exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_concurrent_transactions_keep_each_others_entries() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let manager = manager(temp_dir.path())?;
+        let index = manager.model_index();
+        let ready = std::sync::Barrier::new(2);
+        std::thread::scope(|scope| {
+            let commits: Vec<_> = ["first", "second"]
+                .into_iter()
+                .map(|name| {
+                    let (index, ready) = (&index, &ready);
+                    scope.spawn(move || {
+                        let txn = index.start_transaction();
+                        for i in 0..20 {
+                            txn.add(ModelInfo::new(format!("{name}/model-{i}"), vec![]))?;
+                        }
+                        ready.wait();
+                        txn.commit()
+                    })
+                })
+                .collect();
+            commits
+                .into_iter()
+                .try_for_each(|commit| commit.join().expect("commit thread"))
+        })?;
+
+        let ids = ids(&manager)?;
+        assert_eq!(ids.len(), 40);
+        assert!(ids.contains(&"first/model-19".to_string()));
+        assert!(ids.contains(&"second/model-19".to_string()));
+        assert!(!index.journal_path().exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_commit_keeps_other_updates_in_the_journal() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let manager = manager(temp_dir.path())?;
+        let index = manager.model_index();
+        let op = |id: &str| IndexOp::Upsert {
+            model: ModelInfo::new(id, vec![]),
+        };
+        let (first, second, third) = (op("org/first"), op("org/second"), op("org/third"));
+        index.begin(slice::from_ref(&first))?;
+        index.begin(slice::from_ref(&second))?;
+        index.begin(slice::from_ref(&third))?;
+
+        index.commit(slice::from_ref(&second))?;
+        index.abort(slice::from_ref(&first))?;
+        let pending: Vec<_> = pending(&index.journal_path())?
+            .iter()
+            .map(|op| op.model_id().to_string())
+            .collect();
+        assert_eq!(pending, ["org/third"]);
+        assert_eq!(ids(&manager)?, ["org/second"]);
+
+        index.commit(slice::from_ref(&third))?;
+        assert!(!index.journal_path().exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_failed_commit_is_recovered() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let root = temp_dir.path();
+        let manager = manager(root)?;
+        let index = manager.model_index();
+        index.add_model(ModelInfo::new("org/first", vec![]))?;
+        let saved = fs::read(root.join("models/model_index.json"))?;
+
+        // A directory in the way of the temp file makes the save fail, as a
+        // crash while writing it would
+        let blocker = root.join("models/model_index.json.tmp");
+        fs::create_dir(&blocker)?;
+        let txn = index.start_transaction();
+        for i in 0..50 {
+            txn.add(ModelInfo::new(format!("org/model-{i}"), vec![]))?;
+        }
+        assert!(txn.commit().is_err());
+        assert_eq!(fs::read(root.join("models/model_index.json"))?, saved);
+        assert!(index.journal_path().exists());
+
+        fs::remove_dir(&blocker)?;
+        let manager = self::manager(root)?;
+        assert_eq!(ids(&manager)?.len(), 51);
+        assert!(!index.journal_path().exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_recover_rolls_back_add_without_files() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let root = temp_dir.path();
+        let manager = manager(root)?;
+        let mut model = ModelInfo::new("org/partial", vec![]);
+        model.files.push(ModelFile {
+            size: 4,
+            path: root.join("never-written.bin"),
+            sha256: None,
+            rfilename: None,
+            format: None,
+        });
+        let journal = manager.model_index().journal_path();
+        fs::create_dir_all(root.join("models"))?;
+        append(&journal, &[IndexOp::Upsert { model }])?;
+        // A torn final line from a crash mid-append is skipped
+        OpenOptions::new()
+            .append(true)
+            .open(&journal)?
+            .write_all(br#"{"op":"remove","mod"#)?;
+
+        let report = manager.recover()?;
+        assert!(report.completed.is_empty());
+        assert_eq!(report.rolled_back.len(), 1);
+        assert!(
+            report
+                .to_string()
+                .contains("Rolled back interrupted add org/partial")
+        );
+        assert!(ids(&manager)?.is_empty());
+        assert!(!journal.exists());
+        Ok(())
+    }
+}
@@ -2,7 +2,177 @@
 //!
 //! This library provides the core functionality for managing AI models
 //! and generating images locally.
+//!
+//! The model index, cache and download management are always built. The
+//! `image-pipeline` feature adds the try-on pipeline and everything that reads
+//! or writes images, `hub` adds downloads from the Hugging Face Hub, and
+//! `server` the HTTP API; all are on by default. Index-only consumers can build
+//! with `default-features = false`.
 
+#[cfg(feature = "image-pipeline")]
+pub mod analysis;
+pub mod availability;
+#[cfg(feature = "image-pipeline")]
+pub mod batch;
+#[cfg(feature = "image-pipeline")]
+pub mod bench;
+pub mod cancel;
+#[cfg(feature = "image-pipeline")]
+pub mod color_transfer;
+pub mod compat;
+#[cfg(feature = "image-pipeline")]
+pub mod compose;
+pub mod config;
+pub mod config_schema;
+#[cfg(feature = "image-pipeline")]
+pub mod convert;
+pub mod diff;
+pub mod diffusers_config;
+pub mod disk_cache;
+pub mod doctor;
+pub mod download_lock;
+pub mod download_queue;
+pub mod error;
+pub mod eta;
+pub mod eviction;
+pub mod format;
+#[cfg(feature = "image-pipeline")]
+pub mod formats;
+pub mod history;
+pub mod hooks;
+#[cfg(feature = "image-pipeline")]
+pub mod icc;
+pub mod image_source;
+#[cfg(feature = "image-pipeline")]
+pub mod imageio;
+pub mod import;
+mod index_cache;
+pub mod index_events;
+pub mod index_paths;
+pub mod index_repair;
+pub mod journal;
+pub mod logging;
+pub mod manifest;
+pub mod mask;
+#[cfg(feature = "image-pipeline")]
+pub mod mask_cache;
+#[cfg(feature = "image-pipeline")]
+pub mod metadata;
+pub mod metrics;
+pub mod model_id;
 pub mod models;
+pub mod output;
+pub mod paths;
+#[cfg(feature = "image-pipeline")]
+pub mod plan;
+#[cfg(feature = "image-pipeline")]
+pub mod planner;
+pub mod platform;
+#[cfg(feature = "image-pipeline")]
+pub mod preprocess;
+pub mod prompt;
+#[cfg(feature = "image-pipeline")]
+pub mod queue;
+pub mod rate_limit;
+#[cfg(feature = "image-pipeline")]
+pub mod recipe;
+#[cfg(feature = "image-pipeline")]
+pub mod request_file;
+pub mod safetensors_meta;
+pub mod segment;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod source;
+pub mod styles;
+pub mod template;
+#[cfg(all(feature = "image-pipeline", any(test, feature = "test-util")))]
+pub mod test_support;
+#[cfg(feature = "image-pipeline")]
+pub mod texture;
+#[cfg(feature = "image-pipeline")]
+pub mod tryon;
+pub mod usage;
+pub mod variant;
+pub mod version;
+pub mod weights;
 
-pub use models::{ModelFile, ModelInfo, ModelManager, ModelManagerBuilder, SyncResult};
+pub use availability::Availability;
+#[cfg(feature = "image-pipeline")]
+pub use batch::InputSelector;
+#[cfg(feature = "image-pipeline")]
+pub use bench::{Backend, BenchConfig, BenchPipeline, BenchReport};
+pub use cancel::CancelToken;
+#[cfg(feature = "image-pipeline")]
+pub use color_transfer::ColorReference;
+pub use compat::{Compatibility, Task};
+#[cfg(feature = "image-pipeline")]
+pub use compose::ComparisonLayout;
+pub use config::{Config, EffectiveConfig, Profile};
+pub use config_schema::{ConfigKey, ConfigProblem};
+pub use diff::{EntrySummary, IndexDiff, ModelChange, ModelDiff};
+pub use diffusers_config::{DiffusersConfig, PipelineConfig, PipelineIndex, SchedulerConfig};
+pub use disk_cache::{CacheKind, CacheUsage, DiskCache};
+pub use doctor::{Check, CheckStatus};
+pub use download_lock::{DownloadLock, LockWait};
+pub use download_queue::{DownloadQueue, QueueEntry, QueueState};
+pub use error::SiError;
+pub use eta::EtaEstimator;
+pub use eviction::{CachePolicy, EvictedModel, EvictionReason, EvictionReport};
+pub use history::{History, HistoryEntry};
+pub use hooks::{HookContext, HookEvent, HookRunner};
+pub use image_source::{ImageSource, RemoteInput};
+#[cfg(feature = "image-pipeline")]
+pub use imageio::{LoadWarning, LoadedImage, OutputOptions, SaveReport};
+pub use import::{ExternalLayout, ExternalModel, FileHashKey, HashCache, ImportReport};
+pub use index_events::{IndexEvent, IndexEventReceiver};
+pub use index_paths::{PathPolicy, PathViolation};
+pub use index_repair::RepairReport;
+pub use journal::{IndexOp, RecoveryReport};
+pub use manifest::{BulkDownloadOptions, BulkDownloadReport, BulkOutcome, ModelSpec};
+pub use mask::{MaskMode, Polygon, RegionMask};
+#[cfg(feature = "image-pipeline")]
+pub use mask_cache::{MaskCache, MaskKey};
+#[cfg(feature = "image-pipeline")]
+pub use metadata::ReproInfo;
+pub use metrics::{FileSink, MetricEvent, MetricRecord, MetricsSink, MetricsSummary, NoopSink};
+pub use model_id::ModelId;
+pub use models::{
+    DeleteOutcome, DeleteReport, DownloadOptions, DownloadPlan, DownloadProgress,
+    DownloadProgressFn, DownloadReport, FileDownload, FormatMismatch, GcReport, IndexSource,
+    ModelFile, ModelInfo, ModelManager, ModelManagerBuilder, ModelOrigin, ModelQuery,
+    ModelRevision, ModelSelector, PlannedFile, RefreshReport, RevisionChange, SyncResult,
+    VerifyReport,
+};
+pub use output::CommandOutput;
+#[cfg(feature = "image-pipeline")]
+pub use plan::GenerationPlan;
+#[cfg(feature = "image-pipeline")]
+pub use planner::{
+    ColorTransform, KeywordPlanner, StyleAdjustments, TransformPlan, TransformPlanner,
+};
+#[cfg(feature = "image-pipeline")]
+pub use preprocess::{Crop, CropSpec};
+pub use prompt::{ParsedPrompt, PromptTerm};
+#[cfg(feature = "image-pipeline")]
+pub use queue::{JobHandle, JobQueue, JobStatus};
+#[cfg(feature = "image-pipeline")]
+pub use recipe::Recipe;
+#[cfg(feature = "image-pipeline")]
+pub use request_file::RequestOverrides;
+pub use safetensors_meta::{DtypeTotal, SafetensorsSummary, TensorInfo};
+pub use segment::{CoverageBounds, CoverageWarning, Region};
+#[cfg(feature = "image-pipeline")]
+pub use segment::{HeuristicSegmenter, MaskStats, Segmenter};
+#[cfg(feature = "hub")]
+pub use source::HfSource;
+pub use source::{FallbackSource, MockSource, ModelSource, OfflineSource, RepoInfo};
+pub use styles::{StylePreset, StyleRegistry};
+pub use template::{OutputTemplate, TemplateContext};
+#[cfg(feature = "image-pipeline")]
+pub use tryon::{
+    ComparisonRequest, GridRequest, GridResult, InputLimits, TryOnEvent, TryOnRequest, TryOnResult,
+    TryOnStage, VirtualTryOn,
+};
+pub use usage::{ModelUsage, UsageSort};
+pub use version::{VersionInfo, version};
+pub use weights::WeightFormat;
@@ -0,0 +1,209 @@
+//! Log filtering per target, combined from config, `RUST_LOG` and `--debug`
+//!
+//! Levels are merged in increasing precedence: the `log_levels` config table,
+//! then `RUST_LOG`, then each `--debug <target>` flag, which sets its target to
+//! `debug`. A later source replaces an earlier one's level for the same target;
+//! targets it doesn't mention keep theirs. Without any of them only errors are
+//! logged, as with a bare `env_logger::init()`.
+//!
+//! Besides module paths, the library logs to these narrower targets so a single
+//! concern can be turned up without the rest of its module:
+//! [`DOWNLOAD_TARGET`], [`INDEX_TARGET`], [`MASK_TARGET`] and [`TIMING_TARGET`].
+
+use std::{collections::BTreeMap, fmt};
+
+use anyhow::{Context, Result, bail};
+use log::LevelFilter;
+
+/// File transfers and their hashes
+pub const DOWNLOAD_TARGET: &str = "si::models::download";
+/// Reads and writes of the model index
+pub const INDEX_TARGET: &str = "si::models::index";
+/// Clothing mask statistics
+pub const MASK_TARGET: &str = "si::tryon::mask";
+/// How long each pipeline stage took
+pub const TIMING_TARGET: &str = "si::tryon::timing";
+
+/// Environment variable read by [`LogFilter::with_env`]
+pub const LOG_ENV: &str = "RUST_LOG";
+
+/// Parse a level name such as `debug` or `off`, case-insensitively
+pub fn parse_level(level: &str) -> Result<LevelFilter> {
+    level.trim().parse().map_err(|_| {
+        anyhow::anyhow!(
+            "Invalid log level `{level}`, expected off, error, warn, info, debug or trace"
+        )
+    })
+}
+
+/// A default level plus per-target overrides
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogFilter {
+    default: LevelFilter,
+    targets: BTreeMap<String, LevelFilter>,
+    /// `RUST_LOG`'s `/regex` message filter, passed through as is
+    message_filter: Option<String>,
+}
+
+impl Default for LogFilter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LogFilter {
+    /// Errors only
+    pub fn new() -> Self {
+        Self {
+            default: LevelFilter::Error,
+            targets: BTreeMap::new(),
+            message_filter: None,
+        }
+    }
+
+    /// Apply the `log_levels` config table, mapping targets to level names
+    pub fn with_levels(mut self, levels: &BTreeMap<String, String>) -> Result<Self> {
+        for (target, level) in levels {
+            let level =
+                parse_level(level).with_context(|| format!("Invalid log level for {target}"))?;
+            self.targets.insert(target.clone(), level);
+        }
+        Ok(self)
+    }
+
+    /// Apply a `RUST_LOG`-style spec: comma-separated `target=level`, `target`
+    /// (meaning trace) or a bare `level` for everything else, optionally
+    /// followed by `/regex`
+    pub fn with_env(mut self, spec: &str) -> Result<Self> {
+        let (directives, message_filter) = match spec.split_once('/') {
+            Some((directives, filter)) => (directives, Some(filter)),
+            None => (spec, None),
+        };
+        for directive in directives.split(',').map(str::trim) {
+            if directive.is_empty() {
+                continue;
+            }
+            match directive.split_once('=') {
+                Some((target, level)) => {
+                    let level = parse_level(level)
+                        .with_context(|| format!("Invalid {LOG_ENV} directive `{directive}`"))?;
+                    if target.trim().is_empty() {
+                        bail!("Invalid {LOG_ENV} directive `{directive}`: missing target");
+                    }
+                    self.targets.insert(target.trim().to_string(), level);
+                }
+                None => match parse_level(directive) {
+                    Ok(level) => self.default = level,
+                    Err(_) => {
+                        self.targets
+                            .insert(directive.to_string(), LevelFilter::Trace);
+                    }
+                },
+            }
+        }
+        if let Some(filter) = message_filter {
+            self.message_filter = Some(filter.to_string());
+        }
+        Ok(self)
+    }
+
+    /// Turn each target up to `debug`
+    pub fn with_debug_targets(mut self, targets: &[String]) -> Self {
+        for target in targets {
+            self.targets.insert(target.clone(), LevelFilter::Debug);
+        }
+        self
+    }
+
+    /// The level for records logged to `target`: that of the longest configured
+    /// target it starts with (`si::models` covers `si::models::download`), or the
+    /// default, matching how env_logger applies the spec
+    pub fn level_for(&self, target: &str) -> LevelFilter {
+        self.targets
+            .iter()
+            .filter(|(name, _)| target.starts_with(name.as_str()))
+            .max_by_key(|(name, _)| name.len())
+            .map_or(self.default, |(_, level)| *level)
+    }
+
+    /// Install the filter as the global logger
+    pub fn init(&self) -> Result<()> {
+        env_logger::Builder::new()
+            .parse_filters(&self.to_string())
+            .try_init()
+            .context("Failed to set up logging")
+    }
+}
+
+/// The filter as an env_logger spec, e.g. `error,si::models=debug`
+impl fmt::Display for LogFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.default.as_str().to_lowercase())?;
+        for (target, level) in &self.targets {
+            write!(f, ",{target}={}", level.as_str().to_lowercase())?;
+        }
+        if let Some(filter) = &self.message_filter {
+            write!(f, "/{filter}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sources_apply_in_order() -> Result<()> {
+        let config = BTreeMap::from([
+            ("si::models".to_string(), "debug".to_string()),
+            ("si::tryon".to_string(), "warn".to_string()),
+            ("si::server".to_string(), "info".to_string()),
+        ]);
+        let filter = LogFilter::new()
+            .with_levels(&config)?
+            // RUST_LOG overrides the config for si::tryon and sets the default
+            .with_env("info,si::tryon=error,hf_hub")?
+            // --debug overrides both
+            .with_debug_targets(&["si::tryon::mask".to_string(), "si::models".to_string()]);
+
+        assert_eq!(filter.level_for("si::models"), LevelFilter::Debug);
+        assert_eq!(filter.level_for(DOWNLOAD_TARGET), LevelFilter::Debug);
+        assert_eq!(filter.level_for("si::tryon"), LevelFilter::Error);
+        assert_eq!(filter.level_for(TIMING_TARGET), LevelFilter::Error);
+        assert_eq!(filter.level_for(MASK_TARGET), LevelFilter::Debug);
+        assert_eq!(filter.level_for("si::server"), LevelFilter::Info);
+        assert_eq!(filter.level_for("hf_hub::api"), LevelFilter::Trace);
+        assert_eq!(filter.level_for("reqwest"), LevelFilter::Info);
+        assert_eq!(
+            filter.to_string(),
+            "info,hf_hub=trace,si::models=debug,si::server=info,si::tryon=error,\
+             si::tryon::mask=debug"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_defaults_to_errors() -> Result<()> {
+        let filter = LogFilter::new()
+            .with_levels(&BTreeMap::new())?
+            .with_env("")?;
+        assert_eq!(filter, LogFilter::new());
+        assert_eq!(filter.level_for("si::models"), LevelFilter::Error);
+        assert_eq!(filter.to_string(), "error");
+        Ok(())
+    }
+
+    #[test]
+    fn test_env_message_filter_and_errors() -> Result<()> {
+        let filter = LogFilter::new().with_env("si=debug/download")?;
+        assert_eq!(filter.to_string(), "error,si=debug/download");
+
+        assert!(LogFilter::new().with_env("si=loud").is_err());
+        assert!(LogFilter::new().with_env("=debug").is_err());
+        let config = BTreeMap::from([("si".to_string(), "verbose".to_string())]);
+        assert!(LogFilter::new().with_levels(&config).is_err());
+        assert_eq!(parse_level("DEBUG")?, LevelFilter::Debug);
+        Ok(())
+    }
+}
@@ -1,22 +1,121 @@
-use std::{fmt::Debug, path::PathBuf};
+use std::{
+    collections::HashMap,
+    env,
+    fmt::Debug,
+    fs,
+    io::{self, IsTerminal, Read},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 
-use anyhow::{Context, Result};
-use clap::{Parser, Subcommand};
+use anyhow::{Context, Result, bail};
+use chrono::{TimeDelta, Utc};
+use clap::{Args, CommandFactory, Parser, Subcommand, error::ErrorKind};
 
+use image::ImageFormat;
 use log::debug;
-use si::ModelManager;
+use serde_json::json;
+use si::{
+    Backend, BenchConfig, BulkDownloadOptions, BulkDownloadReport, BulkOutcome, CacheKind,
+    CancelToken, CommandOutput, ComparisonLayout, ComparisonRequest, Compatibility, Config,
+    DeleteOutcome, DeleteReport, DownloadOptions, DownloadPlan, DownloadProgress,
+    DownloadProgressFn, DownloadReport, EtaEstimator, GridRequest, HashCache, History, HookContext,
+    HookEvent, HookRunner, InputSelector, JobQueue, JobStatus, LockWait, MaskCache, ModelInfo,
+    ModelManager, ModelManagerBuilder, ModelOrigin, ModelQuery, ModelSelector, ModelSpec,
+    OutputTemplate, Profile, Recipe, RequestOverrides, RevisionChange, SiError, StyleRegistry,
+    Task, TemplateContext, TryOnEvent, TryOnRequest, TryOnStage, UsageSort, VirtualTryOn,
+    bench::{self, TryOnPipeline},
+    config_schema,
+    convert::{self, ConvertOptions, Resize},
+    format::{self, Style, format_duration, format_size, parse_duration, parse_size},
+    formats,
+    image_source::{self, FetchOptions, ImageSource, RemoteInput},
+    imageio,
+    import::is_local_model,
+    logging::{self, LogFilter},
+    mask::{MaskMode, Polygon, RegionMask, SkinProtection},
+    metadata,
+    metrics::{self, FileSink, MetricsSink, MetricsSummary, NoopSink},
+    paths, platform,
+    preprocess::{Crop, CropSpec, DEFAULT_AUTO_CENTER_MARGIN},
+    rate_limit::RateLimit,
+    safetensors_meta,
+    source::{self, HF_HUB_OFFLINE_ENV, OfflineSource},
+    texture::{Texture, TextureBlend, TextureOverlay, TextureSource},
+    tryon::{DEFAULT_MODEL, MAX_VARIATIONS, variation_requests},
+};
 
 #[derive(Parser)]
 #[command(name = "si")]
 #[command(about = "A CLI for the Si (see) AI image generator")]
-#[command(version = "0.1.0")]
+#[command(version = si::version::VERSION)]
 struct Cli {
+    /// Config profile to apply (overrides SI_PROFILE and `active_profile`)
+    #[arg(long, global = true)]
+    profile: Option<String>,
+    /// Log debug messages for a target such as si::models::download
+    /// (repeatable; overrides RUST_LOG and `log_levels`)
+    #[arg(long = "debug", value_name = "TARGET", global = true)]
+    debug: Vec<String>,
+    /// Print sizes in bytes and durations in milliseconds, for scripts
+    #[arg(long, global = true)]
+    raw: bool,
+    /// Print the result as a single JSON document on stdout, and everything else
+    /// on stderr
+    #[arg(long, global = true)]
+    json: bool,
+    #[command(flatten)]
+    storage: StorageArgs,
     #[command(subcommand)]
     command: Commands,
 }
 
+/// Where models live and whether si may use the network, for this invocation
+/// only
+#[derive(Args, Clone, Default)]
+struct StorageArgs {
+    /// Directory holding the model index (overrides SI_DATA_DIR)
+    #[arg(long, global = true, value_name = "PATH")]
+    models_dir: Option<PathBuf>,
+    /// Hugging Face cache to download into and sync from (overrides
+    /// HF_HUB_CACHE and HF_HOME)
+    #[arg(long, alias = "cache-dir", global = true, value_name = "PATH")]
+    hf_cache_dir: Option<PathBuf>,
+    /// Don't use the network: models come from the local cache only and URL
+    /// inputs are refused (also set by HF_HUB_OFFLINE=1)
+    #[arg(long, global = true)]
+    offline: bool,
+}
+
+/// A fabric texture to lay over the garment
+#[derive(Args, Clone, Default)]
+struct TextureArgs {
+    /// Texture to lay over the garment: denim, knit, leather, an image file, or
+    /// auto for the texture of the fabric the description names
+    #[arg(long, value_name = "NAME|PATH|auto")]
+    texture: Option<String>,
+    /// How many times its size to draw the texture, e.g. 2 for a coarser weave
+    #[arg(long, value_parser = parse_texture_scale, requires = "texture")]
+    texture_scale: Option<f32>,
+    /// How strongly the texture shows, 0.0 to 1.0 (default 0.35)
+    #[arg(long, value_parser = parse_texture_opacity, requires = "texture")]
+    texture_opacity: Option<f32>,
+    /// How the texture combines with the garment: multiply or overlay
+    #[arg(long, default_value_t, requires = "texture")]
+    texture_blend: TextureBlend,
+}
+
+impl StorageArgs {
+    fn offline(&self) -> bool {
+        self.offline || source::offline_env(env::var(HF_HUB_OFFLINE_ENV).ok().as_deref())
+    }
+}
+
 #[derive(Subcommand)]
 enum Commands {
+    /// Create the config file and check the setup, for a first run
+    Init(InitArgs),
     /// Model-related operations
     Model {
         #[command(subcommand)]
@@ -32,181 +131,3475 @@ enum Commands {
         #[command(subcommand)]
         action: ImageCommands,
     },
+    /// Summarize your own usage from the local metrics file
+    Stats(StatsArgs),
+    /// Manage the caches of computed files
+    Cache {
+        #[command(subcommand)]
+        action: CacheCommands,
+    },
+    /// Serve the HTTP API, keeping the pipeline loaded between requests
+    #[cfg(feature = "server")]
+    Serve(ServeArgs),
+    /// Show the version, and with --verbose the build details for bug reports
+    Version(VersionArgs),
+}
+
+#[derive(Args, Default)]
+struct InitArgs {
+    /// Model `si image generate` uses when none is named
+    #[arg(long)]
+    default_model: Option<String>,
+    /// Don't ask anything; take the flags and the defaults as they are
+    #[arg(long, short)]
+    yes: bool,
+    /// Replace the settings in an existing config file
+    #[arg(long)]
+    force: bool,
+    /// Download the starter model without asking
+    #[arg(long)]
+    download_starter: bool,
+}
+
+#[derive(Args, Default)]
+struct StatsArgs {
+    /// Only count the last period, e.g. 24h, 7d or 2w
+    #[arg(long, value_parser = metrics::parse_since)]
+    since: Option<TimeDelta>,
+}
+
+#[derive(Args, Default)]
+struct VersionArgs {
+    /// Also show the git revision, cargo features and target of the build
+    #[arg(long, short)]
+    verbose: bool,
+}
+
+#[cfg(feature = "server")]
+#[derive(Args, Default)]
+struct ServeArgs {
+    /// Address to listen on
+    #[arg(long, default_value = "127.0.0.1")]
+    host: String,
+    /// Port to listen on
+    #[arg(long, default_value_t = 7860)]
+    port: u16,
+    /// Maximum number of generations running at once
+    #[arg(long, default_value_t = 2, value_parser = clap::value_parser!(u64).range(1..))]
+    workers: u64,
+    /// Download a requested model that isn't downloaded, rather than failing
+    /// the request
+    #[arg(long)]
+    download_missing: bool,
+}
+
+#[derive(Subcommand)]
+enum ModelCommands {
+    /// List available models
+    List(ListArgs),
+    /// Download a new model
+    Download {
+        /// Name of the model to download, optionally `name@revision`
+        #[arg(required_unless_present = "manifest")]
+        name: Option<ModelSpec>,
+        /// Download every model listed in this file, one `name[@revision]` per line
+        /// or a JSON array
+        #[arg(long, conflicts_with_all = ["name", "dry_run"])]
+        manifest: Option<PathBuf>,
+        /// Downloads to run at once with --manifest
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..), requires = "manifest")]
+        parallel: u64,
+        /// Keep downloading the rest of the manifest after a failure
+        #[arg(long, requires = "manifest")]
+        continue_on_error: bool,
+        /// Only download files matching these glob patterns (repeatable)
+        #[arg(long)]
+        include: Vec<String>,
+        /// Skip README and LICENSE files that aren't matched by --include
+        #[arg(long)]
+        no_docs: bool,
+        /// List what would be fetched and exit without downloading
+        #[arg(long)]
+        dry_run: bool,
+        /// Fail instead of waiting when another download of the model is running
+        #[arg(long)]
+        no_wait: bool,
+        /// When the model is indexed at another revision, keep that revision
+        /// registered next to the new one rather than replacing it
+        #[arg(long)]
+        keep_old: bool,
+        /// Download only the weights of this variant, such as fp16, plus the
+        /// files all variants share
+        #[arg(long)]
+        variant: Option<String>,
+        /// Download no faster than this, e.g. 5MB/s or 500KiB/s, across all
+        /// files fetched at once (overrides the download_rate_limit config key)
+        #[arg(long)]
+        limit_rate: Option<RateLimit>,
+        /// Add the model, or every model in --manifest, to the download queue
+        /// instead of downloading now; `si model queue run` fetches them later
+        #[arg(long, conflicts_with_all = ["dry_run", "include", "no_docs", "variant", "keep_old"])]
+        queue: bool,
+    },
+    /// Manage downloads queued with `model download --queue`
+    Queue {
+        #[command(subcommand)]
+        action: QueueCommands,
+    },
+    /// Index models downloaded by other tools (.safetensors/.ckpt files and
+    /// diffusers folders) without downloading them again
+    Import {
+        /// A model file or folder, or a directory to search for them
+        path: PathBuf,
+        /// Also search subdirectories
+        #[arg(long)]
+        recursive: bool,
+        /// Copy the files into the models directory instead of indexing them in place
+        #[arg(long)]
+        copy: bool,
+        /// Import the single model at PATH under this id instead of `local/<name>`
+        #[arg(long = "as", value_name = "ID", conflicts_with = "recursive")]
+        as_id: Option<String>,
+    },
+    /// Delete a model, or every model matching --all or --filter
+    Delete {
+        /// Name of the model to delete
+        #[arg(required_unless_present_any = ["all", "filter"])]
+        name: Option<String>,
+        /// Delete every model except pinned ones
+        #[arg(long, conflicts_with_all = ["name", "filter"])]
+        all: bool,
+        /// Delete the models whose id matches this glob (`*`/`?`,
+        /// case-insensitive), except pinned ones
+        #[arg(long, conflicts_with = "name")]
+        filter: Option<String>,
+        /// List what --all or --filter would delete and exit
+        #[arg(long, conflicts_with = "name")]
+        dry_run: bool,
+    },
+    /// Show model details
+    Show {
+        /// Name of the model to show
+        name: String,
+        /// Print the model's README to stdout
+        #[arg(long)]
+        readme: bool,
+        /// List the tensors in each .safetensors file, reading only the headers
+        #[arg(long, conflicts_with = "readme")]
+        tensors: bool,
+        /// Show the diffusers pipeline components and scheduler settings
+        #[arg(long, conflicts_with_all = ["readme", "tensors"])]
+        config: bool,
+    },
+    /// Open the directory holding a model's files in the file manager
+    Open {
+        /// Name of the model to open
+        name: String,
+        /// Print the directory instead (the default when stdout isn't a terminal)
+        #[arg(long)]
+        print: bool,
+    },
+    /// Re-check file sizes on disk and fix stale entries in the index
+    Refresh {
+        /// Only refresh this model (defaults to all models)
+        name: Option<String>,
+    },
+    /// Sync local models with the index
+    Sync {
+        /// Perform a dry run without making changes
+        #[arg(long)]
+        dry_run: bool,
+        /// Also show the index changes model by model, with files and sizes
+        #[arg(long)]
+        diff: bool,
+    },
+    /// Show how often each model has been used
+    Stats {
+        /// Sort by generation count, size on disk or last use
+        #[arg(long, default_value_t = UsageSort::Count, value_name = "count|size|recent")]
+        sort: UsageSort,
+    },
+    /// Protect a model from `model gc`
+    Pin {
+        /// Name of the model to pin
+        name: String,
+    },
+    /// Remove a model's pin
+    Unpin {
+        /// Name of the model to unpin
+        name: String,
+    },
+    /// Remove cached revisions and blobs that indexed models no longer use
+    Gc {
+        /// List what would be removed without deleting anything
+        #[arg(long)]
+        dry_run: bool,
+        /// Also collect pinned models
+        #[arg(long)]
+        force: bool,
+    },
+    /// Delete the least recently used downloaded models until the Hub cache
+    /// keeps to `cache_max_bytes` and `cache_max_age_days`
+    Evict {
+        /// List what would be evicted without deleting anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Merge duplicate index entries (ids differing in case or whitespace) and
+    /// drop files listed twice, backing up the index first
+    Repair {
+        /// List what would change without writing the index
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Check that a model's files are present and intact
+    Verify {
+        /// Name of the model to verify
+        name: String,
+        /// Also re-hash every file and compare it with the SHA-256 recorded at download
+        #[arg(long)]
+        deep: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum QueueCommands {
+    /// List queued downloads with their state, attempts and last error
+    List,
+    /// Remove a queued download by its number, or every finished one
+    Remove {
+        /// Number of the entry, as `model queue list` shows it
+        #[arg(required_unless_present = "done")]
+        id: Option<u64>,
+        /// Remove every entry that is done
+        #[arg(long, conflicts_with = "id")]
+        done: bool,
+    },
+    /// Download the queued models that aren't done yet, retrying failed ones
+    Run {
+        /// Download at most this many entries
+        #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+        max: Option<u64>,
+    },
+}
+
+#[derive(Subcommand)]
+enum CacheCommands {
+    /// Delete everything in a cache (masks, hashes)
+    Clear { cache: CacheKind },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommands {
+    /// Show current configuration
+    Show,
+    /// Set a configuration value
+    Set {
+        /// Configuration key
+        key: String,
+        /// Configuration value
+        value: String,
+    },
+    /// Get a configuration value
+    Get {
+        /// Configuration key
+        key: String,
+    },
+    /// Reset configuration to defaults
+    Reset,
+    /// Check the config file strictly: unknown keys, wrong types and
+    /// inconsistent settings, all reported at once
+    Validate,
+    /// List every key the config file understands, with its type, default and
+    /// description
+    Schema,
+    /// Manage named profiles of generation settings
+    Profile {
+        #[command(subcommand)]
+        action: ProfileCommands,
+    },
+}
+
+#[derive(Subcommand)]
+enum ProfileCommands {
+    /// List profiles
+    List,
+    /// Show a profile's settings merged over the top-level config
+    Show {
+        /// Name of the profile
+        name: String,
+    },
+    /// Create a profile
+    Create {
+        /// Name of the profile
+        name: String,
+        /// Model used when --model isn't given
+        #[arg(long)]
+        default_model: Option<String>,
+        /// Transformation strength (0.0 to 1.0)
+        #[arg(long)]
+        strength: Option<f64>,
+        /// Inference steps
+        #[arg(long)]
+        steps: Option<u32>,
+        /// Maximum input edge length in pixels
+        #[arg(long)]
+        max_size: Option<u32>,
+        /// Output file extension for generated paths
+        #[arg(long)]
+        output_format: Option<String>,
+    },
+    /// Delete a profile
+    Delete {
+        /// Name of the profile
+        name: String,
+    },
+}
+
+#[derive(Args, Default)]
+struct ListArgs {
+    /// Only list models whose id matches this glob (`*`/`?`, case-insensitive)
+    #[arg(long)]
+    filter: Option<String>,
+    /// Only list models at least this large (e.g. 500MB, 2GiB)
+    #[arg(long, value_parser = parse_size)]
+    min_size: Option<u64>,
+    /// Only list models at most this large
+    #[arg(long, value_parser = parse_size)]
+    max_size: Option<u64>,
+    /// Maximum number of models to list
+    #[arg(long)]
+    limit: Option<usize>,
+    /// Skip this many matching models
+    #[arg(long, default_value_t = 0)]
+    offset: usize,
+    /// Copy each model's preview thumbnail into this directory
+    #[arg(long, value_name = "DIR")]
+    thumbnails: Option<PathBuf>,
+    /// Add a column saying whether each model can be used by `image generate`
+    /// (yes, no, or ? when nothing says)
+    #[arg(long)]
+    compat: bool,
+}
+
+#[derive(Subcommand)]
+enum ImageCommands {
+    /// Generate an image
+    Generate(Box<GenerateArgs>),
+    /// Apply one prompt to every image in a directory
+    Batch(BatchArgs),
+    /// Convert an image to another format, optionally resizing it
+    Convert(ConvertArgs),
+    /// List the image formats this build can read and write
+    Formats,
+    /// Show an image's format, size and the generation parameters embedded in it
+    Info {
+        /// Image to inspect
+        path: PathBuf,
+    },
+    /// Time the image pipeline on a generated input
+    Bench(BenchArgs),
+    /// Browse and re-run past generations
+    History {
+        #[command(subcommand)]
+        action: HistoryCommands,
+    },
+    /// Fabric style presets, built in or from `styles.toml` in the config
+    /// directory
+    Styles {
+        #[command(subcommand)]
+        action: StylesCommands,
+    },
+}
+
+#[derive(Subcommand)]
+enum StylesCommands {
+    /// List the presets and the keywords that select them
+    List,
+}
+
+#[derive(Args, Default)]
+struct BatchArgs {
+    /// Prompt applied to every image
+    prompt: String,
+    /// Directory of input images; patterns in its `.siignore` file are skipped
+    #[arg(long)]
+    input_dir: PathBuf,
+    /// Where to write the results, mirroring the layout of the input directory
+    #[arg(long)]
+    output_dir: PathBuf,
+    /// Also process images in subdirectories
+    #[arg(long)]
+    recursive: bool,
+    /// Skip paths matching this gitignore-style pattern (repeatable)
+    #[arg(long, value_name = "GLOB")]
+    exclude: Vec<String>,
+    /// Model to use for generation
+    #[arg(short, long)]
+    model: Option<String>,
+    /// Number of images processed at once
+    #[arg(long, default_value_t = 2, value_parser = clap::value_parser!(u64).range(1..))]
+    jobs: u64,
+    /// Fail an image whose clothing mask covers suspiciously little or much of it
+    #[arg(long)]
+    strict_mask: bool,
+    /// Segment every image again instead of reusing cached clothing masks
+    #[arg(long)]
+    no_mask_cache: bool,
+    /// Recolor skin inside the clothing region too
+    #[arg(long)]
+    no_protect_skin: bool,
+    /// How readily a tone counts as skin for protection, 0.0 to 1.0 (default 0.5)
+    #[arg(long, value_parser = parse_skin_sensitivity, conflicts_with = "no_protect_skin")]
+    skin_sensitivity: Option<f32>,
+    #[command(flatten)]
+    texture: TextureArgs,
+    /// Process what can be decoded of truncated or corrupt images instead of
+    /// failing them
+    #[arg(long)]
+    lenient: bool,
+    /// Don't copy the inputs' ICC color profiles into the results
+    #[arg(long)]
+    strip_icc: bool,
+    /// Embed the prompt, model, strength and seed in PNG and JPEG results (the
+    /// default)
+    #[arg(long, overrides_with = "no_embed_metadata")]
+    embed_metadata: bool,
+    /// Don't embed the generation parameters in the results
+    #[arg(long, overrides_with = "embed_metadata")]
+    no_embed_metadata: bool,
+    /// Give up on an image whose processing takes longer than this, e.g. 90,
+    /// 90s or 2m, and go on with the next one
+    #[arg(long, value_parser = parse_duration)]
+    timeout: Option<Duration>,
+    /// Generate with a model that doesn't look like it can make images
+    #[arg(long)]
+    force: bool,
+    /// Download the model first when it isn't downloaded, rather than failing
+    #[arg(long)]
+    download_missing: bool,
+    /// Add a --model given as a local path, such as ./my-finetune, to the
+    /// model index as well
+    #[arg(long)]
+    register: bool,
+    /// Make this many variations of every image, numbered -1, -2, ... after
+    /// its name
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..=MAX_VARIATIONS as u64))]
+    count: u64,
+}
+
+#[derive(Args)]
+struct BenchArgs {
+    /// Width and height of the square input, e.g. 512, 1024 or 4096
+    #[arg(long, default_value_t = bench::DEFAULT_SIZE, value_parser = clap::value_parser!(u32).range(16..=8192))]
+    size: u32,
+    /// Number of timed runs, after one warm-up run
+    #[arg(long, default_value_t = bench::DEFAULT_ITERATIONS, value_parser = clap::value_parser!(u32).range(1..))]
+    iterations: u32,
+    /// Pipeline to measure: heuristic or candle
+    #[arg(long, default_value_t = Backend::Heuristic)]
+    backend: Backend,
+}
+
+#[derive(Args, Default)]
+struct ConvertArgs {
+    /// Image to convert
+    input: PathBuf,
+    /// Where to write the result; its extension picks the format unless --format is given
+    output: PathBuf,
+    /// JPEG, WebP or AVIF quality, 1-100 (100 is lossless WebP)
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..=100))]
+    quality: Option<u8>,
+    /// Resize to WxH, or Wx / xH to keep the aspect ratio
+    #[arg(long)]
+    resize: Option<Resize>,
+    /// Output format (png, jpg, webp, avif, ...), overriding the output extension
+    #[arg(long)]
+    format: Option<String>,
+    /// Drop EXIF and other metadata from the output
+    #[arg(long)]
+    strip_metadata: bool,
+}
+
+#[derive(Args, Default)]
+struct GenerateArgs {
+    /// Prompt for the image generation
+    prompt: Option<String>,
+    /// Additional prompts; each one is run against the same input (requires --grid)
+    #[arg(long = "prompt")]
+    prompts: Vec<String>,
+    /// Terms the result should avoid (also accepted inline as `[neg: ...]`)
+    #[arg(long, conflicts_with = "grid")]
+    negative_prompt: Option<String>,
+    /// Model to use for generation
+    #[arg(short, long)]
+    model: Option<String>,
+    /// Input image file (jpg, png, gif, etc.), an http(s) URL to download it
+    /// from, or `-` to read it from stdin
+    #[arg(short, long, required_unless_present = "request_file")]
+    input: Option<ImageSource>,
+    /// Output image file (defaults to the `output_template` config key), or `-` to
+    /// write it to stdout
+    #[arg(short, long, conflicts_with = "grid")]
+    output: Option<PathBuf>,
+    /// Output format (png, jpeg, webp, avif, ...) when writing to stdout or
+    /// naming the output from the template; see `si image formats`
+    #[arg(long, conflicts_with = "grid", required_if_eq("output", "-"))]
+    format: Option<String>,
+    /// JPEG, WebP or AVIF quality, 1-100 (100 is lossless WebP)
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..=100))]
+    quality: Option<u8>,
+    /// Write image data to stdout even when it is a terminal, and generate with
+    /// a model that doesn't look like it can make images
+    #[arg(long)]
+    force: bool,
+    /// Download the model first when it isn't downloaded, rather than failing
+    #[arg(long)]
+    download_missing: bool,
+    /// Add a --model given as a local path, such as ./my-finetune, to the
+    /// model index as well
+    #[arg(long)]
+    register: bool,
+    /// Fail before processing when the output file already exists
+    #[arg(long)]
+    no_clobber: bool,
+    /// Assemble the result of every prompt into a labeled grid image at this path
+    #[arg(long)]
+    grid: Option<PathBuf>,
+    /// Number of grid columns (defaults to a roughly square layout)
+    #[arg(long, requires = "grid")]
+    columns: Option<usize>,
+    /// Don't draw prompt labels under grid cells
+    #[arg(long, requires = "grid")]
+    no_labels: bool,
+    /// Also save each prompt's result in this directory
+    #[arg(long, requires = "grid")]
+    save_individual: Option<PathBuf>,
+    /// Photo of a garment whose colors replace the ones named in the prompt
+    #[arg(long, conflicts_with = "grid")]
+    reference: Option<PathBuf>,
+    /// Crop the input to x,y,w,h first (pixels, or percentages with a `%` suffix)
+    #[arg(long, conflicts_with_all = ["grid", "auto_center"])]
+    crop: Option<CropSpec>,
+    /// Crop the input to the detected clothing plus a margin first
+    #[arg(long, conflicts_with = "grid")]
+    auto_center: bool,
+    /// Margin around the clothing for --auto-center, as a fraction of its size
+    #[arg(long, requires = "auto_center", default_value_t = DEFAULT_AUTO_CENTER_MARGIN)]
+    auto_center_margin: f64,
+    /// Only re-generate inside this polygon, given as "x1,y1 x2,y2 ..." (whole
+    /// numbers are pixels, decimals fractions of the size)
+    #[arg(long, conflicts_with = "grid")]
+    polygon: Option<Polygon>,
+    /// How --polygon combines with the detected clothing: detect, polygon or
+    /// intersect (the default)
+    #[arg(long, requires = "polygon")]
+    mask_mode: Option<MaskMode>,
+    /// Strength of the transformation, 0.0 to 1.0, or "auto" to pick one from
+    /// the garment's colors (defaults to the profile's strength)
+    #[arg(long, value_parser = parse_strength_arg, conflicts_with_all = ["grid", "strength_ramp"])]
+    strength: Option<StrengthArg>,
+    /// Strength of the color change alone, 0.0 to 1.0 (defaults to the strength)
+    #[arg(long, value_parser = parse_strength)]
+    color_strength: Option<f64>,
+    /// Strength of the fabric style's contrast and brightness alone, 0.0 to 1.0
+    /// (defaults to the strength)
+    #[arg(long, value_parser = parse_strength)]
+    style_strength: Option<f64>,
+    /// Run one pass per strength (e.g. 0.2,0.4,0.6), adding the strength to each
+    /// output file name
+    #[arg(long, value_delimiter = ',', value_parser = parse_strength, conflicts_with = "grid")]
+    strength_ramp: Vec<f64>,
+    /// Make this many variations, numbered -1, -2, ... after the output name;
+    /// each has its own seed and a slightly different strength
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..=MAX_VARIATIONS as u64), conflicts_with_all = ["grid", "strength_ramp"])]
+    count: u64,
+    /// Seed of the --strength-ramp, or of the first --count variation (the
+    /// others count up from it); random by default
+    #[arg(long)]
+    seed: Option<u64>,
+    /// Also write the input and the result side by side to this path
+    #[arg(long, conflicts_with_all = ["grid", "strength_ramp", "count"])]
+    comparison: Option<PathBuf>,
+    /// Put the --comparison halves next to each other (horizontal, the default)
+    /// or one above the other (vertical)
+    #[arg(long, requires = "comparison")]
+    comparison_layout: Option<ComparisonLayout>,
+    /// Label the --comparison halves "before" and "after"
+    #[arg(long, requires = "comparison")]
+    comparison_labels: bool,
+    /// Don't run the configured pre/post generation hooks
+    #[arg(long)]
+    no_hooks: bool,
+    /// Don't show the stage and ETA status on stderr
+    #[arg(long)]
+    no_progress: bool,
+    /// Fail instead of warning when the clothing mask covers suspiciously little
+    /// or much of the image (see the mask_coverage_min/max config keys)
+    #[arg(long)]
+    strict_mask: bool,
+    /// Segment the input again instead of reusing its cached clothing mask
+    #[arg(long)]
+    no_mask_cache: bool,
+    /// Recolor skin inside the clothing region too, instead of keeping faces,
+    /// necks and hands out of the mask
+    #[arg(long)]
+    no_protect_skin: bool,
+    /// How readily a tone counts as skin for protection, 0.0 to 1.0 (default
+    /// 0.5); raise it when skin still gets recolored
+    #[arg(long, value_parser = parse_skin_sensitivity, conflicts_with = "no_protect_skin")]
+    skin_sensitivity: Option<f32>,
+    #[command(flatten)]
+    texture: TextureArgs,
+    /// Also write the clothing mask (red) and the protected skin (blue) over the
+    /// dimmed input to this path
+    #[arg(long, conflicts_with = "grid")]
+    mask_debug: Option<PathBuf>,
+    /// Process what can be decoded of a truncated or corrupt input instead of
+    /// failing; the missing part is filled with gray
+    #[arg(long)]
+    lenient: bool,
+    /// Don't copy the input's ICC color profile into the result (PNG and JPEG
+    /// outputs keep it otherwise)
+    #[arg(long)]
+    strip_icc: bool,
+    /// Embed the prompt, model, strength and seed in a PNG or JPEG result, where
+    /// `si image info` and other tools read them (the default)
+    #[arg(long, overrides_with = "no_embed_metadata")]
+    embed_metadata: bool,
+    /// Don't embed the generation parameters in the result
+    #[arg(long, overrides_with = "embed_metadata")]
+    no_embed_metadata: bool,
+    /// Generate with this weight variant of the model, such as fp16,
+    /// downloading it if needed
+    #[arg(long)]
+    variant: Option<String>,
+    /// Print the resolved model, settings and output path without generating
+    #[arg(long, conflicts_with_all = ["grid", "strength_ramp"])]
+    dry_run: bool,
+    /// Give up when processing takes longer than this, e.g. 90, 90s or 2m;
+    /// nothing is written then
+    #[arg(long, value_parser = parse_duration, conflicts_with_all = ["grid", "strength_ramp"])]
+    timeout: Option<Duration>,
+    /// Read the request from this YAML or JSON file; flags given on the command
+    /// line override its values
+    #[arg(long, conflicts_with = "grid")]
+    request_file: Option<PathBuf>,
+    /// Save the resolved transformation, mask, crop and strengths of this run to
+    /// a recipe file, to apply to other photos with --recipe
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["grid", "strength_ramp", "count", "dry_run"])]
+    save_recipe: Option<PathBuf>,
+    /// Apply a recipe saved with --save-recipe to this input; flags and the
+    /// request file override its values
+    #[arg(long, value_name = "FILE", conflicts_with = "grid")]
+    recipe: Option<PathBuf>,
+    /// Where `input` was downloaded from, once a URL input has been fetched
+    #[arg(skip)]
+    remote_input: Option<RemoteInput>,
+}
+
+impl GenerateArgs {
+    /// Fill in whatever the command line left unset from a request file
+    ///
+    /// The file's strength isn't a flag; it is applied over the profile's by the
+    /// caller.
+    fn apply_request_file(&mut self, file: &RequestOverrides) {
+        self.input = self
+            .input
+            .take()
+            .or(file.input_image_path.clone().map(ImageSource::from));
+        if self.prompt.is_none() && self.prompts.is_empty() {
+            self.prompt = file.clothing_description.clone();
+        }
+        self.negative_prompt = self.negative_prompt.take().or(file.negative_prompt.clone());
+        self.output = self.output.take().or(file.output_path.clone());
+        self.model = self.model.take().or(file.model_name.clone());
+        self.reference = self.reference.take().or(file.reference_image.clone());
+        self.quality = self.quality.or(file.quality);
+        self.seed = self.seed.or(file.seed);
+        if file.overwrite == Some(false) {
+            self.no_clobber = true;
+        }
+        if self.crop.is_none() && !self.auto_center {
+            match &file.crop {
+                Some(Crop::Region(spec)) => self.crop = Some(*spec),
+                Some(Crop::AutoCenter { margin }) => {
+                    self.auto_center = true;
+                    self.auto_center_margin = *margin;
+                }
+                None => {}
+            }
+        }
+        if self.polygon.is_none() {
+            if let Some(mask) = &file.mask {
+                self.polygon = Some(mask.polygon.clone());
+                self.mask_mode = Some(mask.mode);
+            }
+        }
+    }
+
+    /// Fill in whatever the command line and request file left unset from a
+    /// recipe
+    ///
+    /// The recipe's strength and plan aren't flags; the caller applies them.
+    fn apply_recipe(&mut self, recipe: &Recipe) {
+        if self.prompt.is_none() && self.prompts.is_empty() {
+            self.prompt = Some(recipe.prompt.clone());
+        }
+        self.negative_prompt = self
+            .negative_prompt
+            .take()
+            .or(recipe.negative_prompt.clone());
+        self.model = self.model.take().or(Some(recipe.model.clone()));
+        self.color_strength = self.color_strength.or(Some(recipe.color_strength));
+        self.style_strength = self.style_strength.or(Some(recipe.style_strength));
+        if self.crop.is_none() && !self.auto_center {
+            match &recipe.crop {
+                Some(Crop::Region(spec)) => self.crop = Some(*spec),
+                Some(Crop::AutoCenter { margin }) => {
+                    self.auto_center = true;
+                    self.auto_center_margin = *margin;
+                }
+                None => {}
+            }
+        }
+        if self.polygon.is_none() {
+            if let Some(mask) = &recipe.mask {
+                self.polygon = Some(mask.polygon.clone());
+                self.mask_mode = Some(mask.mode);
+            }
+        }
+    }
+
+    /// Flags that contradict each other because of their values, like
+    /// `--output -` with `--strength-ramp`, which clap's `conflicts_with` can't
+    /// tell as it only sees which flags are given
+    fn value_conflict(&self) -> Option<&'static str> {
+        let from_stdin =
+            matches!(&self.input, Some(ImageSource::Path(path)) if path == Path::new(STDIO));
+        let to_stdout = self.output.as_deref() == Some(Path::new(STDIO));
+        if from_stdin && (self.grid.is_some() || !self.strength_ramp.is_empty() || self.count > 1) {
+            return Some(
+                "Reading the input from stdin doesn't work with --grid, --strength-ramp or --count",
+            );
+        }
+        if from_stdin && self.dry_run {
+            return Some("--dry-run needs the input image as a file, not stdin");
+        }
+        if to_stdout && (!self.strength_ramp.is_empty() || self.count > 1) {
+            return Some("Writing to stdout doesn't work with --strength-ramp or --count");
+        }
+        if !to_stdout && self.output.is_some() && self.format.is_some() {
+            return Some(
+                "--format only applies when writing to stdout (--output -) or naming the output \
+                 from the template; otherwise the --output extension picks the format",
+            );
+        }
+        None
+    }
+}
+
+#[derive(Subcommand)]
+enum HistoryCommands {
+    /// List past generations, newest first
+    List {
+        /// Maximum number of entries to show
+        #[arg(long)]
+        limit: Option<usize>,
+    },
+    /// Re-run a past generation
+    Rerun {
+        /// Id of the history entry to re-run
+        id: u64,
+        /// Write the result here instead of the original output path
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Don't run the configured pre/post generation hooks
+        #[arg(long)]
+        no_hooks: bool,
+    },
+}
+
+/// Exit status when an operation is interrupted with Ctrl-C (128 + SIGINT)
+const EXIT_CANCELLED: i32 = 130;
+
+/// A clap error for the subcommand at `path`, so checks clap can't express
+/// print its usage and exit with its status, 2, like the ones it can
+fn usage_error(path: &[&str], kind: ErrorKind, message: &str) -> clap::Error {
+    let mut command = Cli::command();
+    command.build();
+    let mut subcommand = &mut command;
+    for name in path {
+        subcommand = subcommand
+            .find_subcommand_mut(name)
+            .expect("usage_error path names a subcommand");
+    }
+    subcommand.error(kind, message)
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let cli = Cli::parse();
+    if let Commands::Image {
+        action: ImageCommands::Generate(args),
+    } = &cli.command
+    {
+        if let Some(conflict) = args.value_conflict() {
+            usage_error(
+                &["image", "generate"],
+                ErrorKind::ArgumentConflict,
+                conflict,
+            )
+            .exit();
+        }
+    }
+    if cli.raw {
+        format::set_style(Style::Raw);
+    }
+    init_logging(&cli.debug)?;
+
+    let cancel = CancelToken::new();
+    install_ctrl_c_handler(cancel.clone());
+
+    let status = Status {
+        to_stderr: cli.json,
+    };
+    let result = match cli.command {
+        Commands::Init(args) => {
+            handle_init(
+                args,
+                &Config::default_path()?,
+                &cli.storage,
+                status,
+                &cancel,
+            )
+            .await
+        }
+        Commands::Model { action } => {
+            handle_model_command(
+                action,
+                &Config::default_path()?,
+                &cli.storage,
+                status,
+                &cancel,
+            )
+            .await
+        }
+        Commands::Stats(args) => handle_stats(args, &Config::default_path()?),
+        Commands::Version(args) => handle_version(args),
+        Commands::Cache { action } => handle_cache_command(action),
+        Commands::Config { action } => {
+            handle_config_command(action, &Config::default_path()?, status)
+        }
+        Commands::Image { action } => {
+            handle_image_command(
+                action,
+                &Config::default_path()?,
+                cli.profile.as_deref(),
+                &cli.storage,
+                status,
+                &cancel,
+            )
+            .await
+        }
+        #[cfg(feature = "server")]
+        Commands::Serve(args) => {
+            handle_serve(
+                args,
+                &Config::default_path()?,
+                &cli.storage,
+                status,
+                &cancel,
+            )
+            .await
+        }
+    }
+    .and_then(|output| output.print(cli.json))
+    .log_error();
+
+    if let Err(e) = &result {
+        if SiError::Cancelled.matches(e) {
+            eprintln!("Cancelled.");
+            std::process::exit(EXIT_CANCELLED);
+        }
+    }
+    result
+}
+
+/// Set up logging from the config's `log_levels`, then `RUST_LOG`, then `--debug`
+fn init_logging(debug: &[String]) -> Result<()> {
+    // A config that doesn't load is reported by the command that needs it
+    let config = Config::default_path()
+        .and_then(|path| Config::load(&path))
+        .unwrap_or_default();
+    let mut filter = LogFilter::new()
+        .with_levels(&config.log_levels)
+        .context("Invalid `log_levels` in config")?;
+    if let Ok(spec) = std::env::var(logging::LOG_ENV) {
+        // A bad RUST_LOG shouldn't stop every command, as it didn't with env_logger
+        match filter.clone().with_env(&spec) {
+            Ok(with_env) => filter = with_env,
+            Err(e) => eprintln!("Warning: ignoring {}: {e:#}", logging::LOG_ENV),
+        }
+    }
+    filter.with_debug_targets(debug).init()
+}
+
+/// Trip `cancel` on the first Ctrl-C so running operations can clean up; a second
+/// Ctrl-C exits immediately
+fn install_ctrl_c_handler(cancel: CancelToken) {
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_err() {
+            return;
+        }
+        eprintln!("cancelling…");
+        cancel.cancel();
+
+        if tokio::signal::ctrl_c().await.is_ok() {
+            std::process::exit(EXIT_CANCELLED);
+        }
+    });
+}
+
+/// A `--dry-run` download plan as a table with a transfer summary
+fn download_plan_output(plan: &DownloadPlan) -> Result<CommandOutput> {
+    let from = plan
+        .endpoint
+        .as_ref()
+        .map(|endpoint| format!(" from {endpoint}"))
+        .unwrap_or_default();
+    let variant = plan
+        .variant
+        .as_ref()
+        .map(|variant| format!(" ({variant})"))
+        .unwrap_or_default();
+    let mut output = CommandOutput::new(plan)?.line(format_args!(
+        "Plan for {}{variant} at revision {}{from}:",
+        plan.model_id,
+        plan.revision.as_deref().unwrap_or("unknown")
+    ));
+    let width = plan
+        .files
+        .iter()
+        .map(|f| f.rfilename.len())
+        .max()
+        .unwrap_or(0);
+    for file in &plan.files {
+        let size = file.size.map_or_else(|| "?".to_string(), format_size);
+        let status = match (file.selected, file.is_cached()) {
+            (false, _) => "skipped",
+            (true, true) => "cached",
+            (true, false) => "download",
+        };
+        output.push_line(format_args!(
+            "  {:<width$}  {size:>10}  {status}",
+            file.rfilename
+        ));
+    }
+
+    let count = plan.to_transfer().count();
+    let unknown = match plan.unknown_sizes() {
+        0 => String::new(),
+        n => format!(" plus {n} of unknown size"),
+    };
+    Ok(output.line(format_args!(
+        "{count} of {} files to download, {}{unknown} to transfer.",
+        plan.files.len(),
+        format_size(plan.transfer_size())
+    )))
+}
+
+/// Warn about files of `model` without a UTF-8 name, which listings show by
+/// their lossily decoded path
+fn warn_unnamed_files(model: &ModelInfo) {
+    for file in model.files.iter().filter(|file| file.name().is_none()) {
+        eprintln!(
+            "Warning: {} has a file without a UTF-8 name: {}",
+            model.model_id,
+            file.display_name()
+        );
+    }
+}
+
+/// Shows on a redrawn stderr line how far the hash of each file of `plan` has
+/// got, as a large file takes a while to hash after its transfer bar is done
+fn hash_progress(plan: &DownloadPlan) -> DownloadProgressFn {
+    if !io::stderr().is_terminal() {
+        return Arc::new(|_| {});
+    }
+    let sizes: HashMap<String, Option<u64>> = plan
+        .files
+        .iter()
+        .map(|file| (file.rfilename.clone(), file.size))
+        .collect();
+    Arc::new(move |progress| {
+        let DownloadProgress::Hashed { file, bytes } = progress else {
+            return;
+        };
+        match sizes.get(file).copied().flatten() {
+            Some(size) if bytes >= size => eprint!("\r\x1b[2K"),
+            Some(size) => eprint!(
+                "\r\x1b[2KHashing {file}: {} of {}",
+                format_size(bytes),
+                format_size(size)
+            ),
+            None => eprint!("\r\x1b[2KHashing {file}: {}", format_size(bytes)),
+        }
+    })
+}
+
+/// One line on how much was transferred and how fast, naming the slowest file
+fn download_summary(report: &DownloadReport) -> String {
+    let fetched = report.files.iter().filter(|f| !f.cached).count();
+    let cached = report.files.len() - fetched;
+    let mut line = format!(
+        "Fetched {fetched} file{} ({}) in {}",
+        if fetched == 1 { "" } else { "s" },
+        format_size(report.downloaded_bytes),
+        format_duration(report.elapsed),
+    );
+    if fetched > 0 {
+        line.push_str(&format!(
+            " at {}/s",
+            format_size(report.bytes_per_sec as u64)
+        ));
+    }
+    if cached > 0 {
+        line.push_str(&format!(", {cached} from cache"));
+    }
+    if let Some(slowest) = report.slowest().filter(|_| fetched > 1) {
+        line.push_str(&format!(
+            "; slowest {} ({} in {})",
+            slowest.rfilename,
+            format_size(slowest.bytes),
+            format_duration(slowest.elapsed)
+        ));
+    }
+    line.push('.');
+    line
+}
+
+fn warn_revision_change(model_id: &str, change: &RevisionChange) {
+    eprintln!(
+        "Warning: {model_id} was indexed at revision {}, now at {}.",
+        change.previous, change.current
+    );
+    if change.kept_old {
+        eprintln!("Both revisions stay registered; see `si model info {model_id}`.");
+    } else if let Some(snapshot) = &change.superseded_snapshot {
+        eprintln!(
+            "The old snapshot ({}) is still in the cache at {}; `si model gc` removes it.",
+            format_size(change.superseded_bytes),
+            snapshot.display()
+        );
+    }
+}
+
+/// One line per model touched by `model delete --all/--filter` and the total
+/// reclaimed, failing if any model couldn't be deleted
+fn delete_reports_output(reports: &[DeleteReport], dry_run: bool) -> Result<CommandOutput> {
+    let mut output = CommandOutput::new(&reports)?;
+    if reports.is_empty() {
+        return Ok(output.line("No models match."));
+    }
+    let width = reports.iter().map(|r| r.model_id.len()).max().unwrap_or(0);
+    for report in reports {
+        output.push_line(format_args!(
+            "  {:<width$}  {:>10}  {}",
+            report.model_id,
+            format_size(report.bytes),
+            report.outcome
+        ));
+    }
+
+    let count =
+        |wanted: fn(&DeleteOutcome) -> bool| reports.iter().filter(|r| wanted(&r.outcome)).count();
+    let reclaimed = format_size(reports.iter().map(DeleteReport::reclaimed_bytes).sum());
+    if dry_run {
+        let selected = count(|o| *o == DeleteOutcome::WouldDelete);
+        output.push_line(format_args!(
+            "Would delete {selected} models, freeing {reclaimed}."
+        ));
+    } else {
+        let deleted = count(|o| *o == DeleteOutcome::Deleted);
+        output.push_line(format_args!(
+            "Deleted {deleted} models, freeing {reclaimed}."
+        ));
+    }
+    let pinned = count(|o| *o == DeleteOutcome::Pinned);
+    if pinned > 0 {
+        output.push_line(format_args!(
+            "Skipped {pinned} pinned models (unpin them to delete)."
+        ));
+    }
+    let failed = count(|o| matches!(o, DeleteOutcome::Failed(_)));
+    if failed > 0 {
+        output = output.with_failure(format_args!("Failed to delete {failed} models"));
+    }
+    Ok(output)
+}
+
+/// Download every model in a manifest file, with one line per model
+async fn download_manifest(
+    model_manager: &ModelManager,
+    manifest: &Path,
+    options: &BulkDownloadOptions,
+) -> Result<CommandOutput> {
+    let text = fs::read_to_string(manifest)
+        .with_context(|| format!("Failed to read manifest {}", manifest.display()))?;
+    let specs = ModelSpec::parse_manifest(&text)
+        .with_context(|| format!("Invalid manifest {}", manifest.display()))?;
+    if specs.is_empty() {
+        return Ok(CommandOutput::new(&BulkDownloadReport::default())?
+            .line(format_args!("No models listed in {}.", manifest.display())));
+    }
+
+    let report = model_manager.download_many(&specs, options).await?;
+    let mut output = CommandOutput::new(&report)?;
+    push_bulk_outcomes(&mut output, &report);
+    output.push_line(format_args!(
+        "{} downloaded, {} already present, {} failed, {} not attempted.",
+        report.succeeded(),
+        report.already_present(),
+        report.failed(),
+        report.not_attempted()
+    ));
+    if !report.is_ok() {
+        output = output.with_failure(format_args!(
+            "{} of {} models failed to download",
+            report.failed(),
+            specs.len()
+        ));
+    }
+    Ok(output)
+}
+
+/// One line per model of a bulk download saying how it went
+fn push_bulk_outcomes(output: &mut CommandOutput, report: &BulkDownloadReport) {
+    for (spec, outcome) in &report.results {
+        match outcome {
+            BulkOutcome::Downloaded(size) => output.push_line(format_args!(
+                "  {spec}: downloaded ({})",
+                format_size(*size)
+            )),
+            BulkOutcome::AlreadyPresent => {
+                output.push_line(format_args!("  {spec}: already downloaded"))
+            }
+            BulkOutcome::Failed(e) => output.push_line(format_args!("  {spec}: failed: {e}")),
+            BulkOutcome::NotAttempted => output.push_line(format_args!("  {spec}: not attempted")),
+        }
+    }
+}
+
+/// Add `specs` to the download queue, skipping ones already waiting in it
+fn queue_downloads(model_manager: &ModelManager, specs: Vec<ModelSpec>) -> Result<CommandOutput> {
+    let mut queue = model_manager.download_queue()?;
+    let mut lines = Vec::new();
+    for spec in specs {
+        let (entry, added) = queue.push(spec);
+        lines.push(if added {
+            format!("Queued {} as #{}.", entry.spec, entry.id)
+        } else {
+            format!("{} is already queued as #{}.", entry.spec, entry.id)
+        });
+    }
+    queue.save()?;
+    let mut output = CommandOutput::new(&queue.entries())?;
+    for line in lines {
+        output.push_line(line);
+    }
+    output.push_line(format_args!(
+        "{} queued download(s) waiting; run `si model queue run` to fetch them.",
+        queue.pending()
+    ));
+    Ok(output)
+}
+
+async fn handle_queue_command(
+    action: QueueCommands,
+    model_manager: &ModelManager,
+    storage: &StorageArgs,
+) -> Result<CommandOutput> {
+    let mut queue = model_manager.download_queue()?;
+    match action {
+        QueueCommands::List => {
+            let mut output = CommandOutput::new(&queue.entries())?;
+            if queue.entries().is_empty() {
+                return Ok(output.line("The download queue is empty."));
+            }
+            for entry in queue.entries() {
+                output.push_line(format_args!(
+                    "#{:<4} {:<8} {} (queued {}, {} attempt{})",
+                    entry.id,
+                    entry.state,
+                    entry.spec,
+                    entry.queued_at.format("%Y-%m-%d %H:%M"),
+                    entry.attempts,
+                    if entry.attempts == 1 { "" } else { "s" }
+                ));
+                if let Some(error) = &entry.last_error {
+                    output.push_line(format_args!("      last error: {error}"));
+                }
+            }
+            Ok(output)
+        }
+        QueueCommands::Remove { id, done: _ } => {
+            let Some(id) = id else {
+                let removed = queue.remove_done();
+                queue.save()?;
+                return Ok(CommandOutput::new(&json!({ "removed": removed }))?
+                    .line(format_args!("Removed {removed} finished download(s).")));
+            };
+            let entry = queue
+                .remove(id)
+                .with_context(|| format!("No queued download #{id}"))?;
+            queue.save()?;
+            Ok(CommandOutput::new(&entry)?.line(format_args!(
+                "Removed {} (#{id}) from the queue.",
+                entry.spec
+            )))
+        }
+        QueueCommands::Run { max } => {
+            if queue.pending() == 0 {
+                return Ok(CommandOutput::new(&BulkDownloadReport::default())?
+                    .line("No queued downloads are waiting."));
+            }
+            if storage.offline() {
+                return Err(
+                    anyhow::Error::new(SiError::Offline).context("Can't run the download queue")
+                );
+            }
+            let report = queue
+                .run(
+                    model_manager,
+                    max.map(|max| max as usize),
+                    &BulkDownloadOptions::default(),
+                )
+                .await?;
+            let mut output = CommandOutput::new(&report)?;
+            push_bulk_outcomes(&mut output, &report);
+            output.push_line(format_args!(
+                "{} downloaded, {} already present, {} failed; {} still queued.",
+                report.succeeded(),
+                report.already_present(),
+                report.failed(),
+                queue.pending()
+            ));
+            if report.failed() > 0 {
+                output = output.with_failure(format_args!(
+                    "{} of {} queued downloads failed; they stay queued for the next run",
+                    report.failed(),
+                    report.results.len()
+                ));
+            }
+            Ok(output)
+        }
+    }
+}
+
+/// Parse a strength between 0.0 and 1.0
+fn parse_strength(value: &str) -> Result<f64> {
+    let strength: f64 = value
+        .trim()
+        .parse()
+        .with_context(|| format!("Invalid strength `{value}`"))?;
+    if !(0.0..=1.0).contains(&strength) {
+        bail!("Strength must be between 0.0 and 1.0, got {strength}");
+    }
+    Ok(strength)
+}
+
+/// A `--strength`: a fixed one, or one suggested from the input
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum StrengthArg {
+    Value(f64),
+    Auto,
+}
+
+fn parse_strength_arg(value: &str) -> Result<StrengthArg> {
+    if value.trim().eq_ignore_ascii_case("auto") {
+        Ok(StrengthArg::Auto)
+    } else {
+        parse_strength(value).map(StrengthArg::Value)
+    }
+}
+
+fn parse_skin_sensitivity(value: &str) -> Result<f32> {
+    let sensitivity: f32 = value
+        .trim()
+        .parse()
+        .with_context(|| format!("Invalid skin sensitivity `{value}`"))?;
+    if !(0.0..=1.0).contains(&sensitivity) {
+        bail!("Skin sensitivity must be between 0.0 and 1.0, got {sensitivity}");
+    }
+    Ok(sensitivity)
+}
+
+fn parse_texture_scale(value: &str) -> Result<f32> {
+    let scale: f32 = value
+        .trim()
+        .parse()
+        .with_context(|| format!("Invalid texture scale `{value}`"))?;
+    if !(scale.is_finite() && scale > 0.0) {
+        bail!("Texture scale must be a positive number, got {scale}");
+    }
+    Ok(scale)
+}
+
+fn parse_texture_opacity(value: &str) -> Result<f32> {
+    let opacity: f32 = value
+        .trim()
+        .parse()
+        .with_context(|| format!("Invalid texture opacity `{value}`"))?;
+    if !(0.0..=1.0).contains(&opacity) {
+        bail!("Texture opacity must be between 0.0 and 1.0, got {opacity}");
+    }
+    Ok(opacity)
+}
+
+/// Index the local model `model` for `--register`, saying so unless it already
+/// is
+fn register_local_model(manager: &ModelManager, model: &str, status: Status) -> Result<()> {
+    if !is_local_model(model) {
+        bail!("--register only applies to a model given as a local path, such as ./my-model");
+    }
+    let local = manager.open_local(model)?;
+    match manager.register_local(&local)? {
+        Some(info) => status.line(format_args!(
+            "Registered {} as {}.",
+            local.path.display(),
+            info.model_id
+        )),
+        None => status.line(format_args!(
+            "{} is already registered as {}.",
+            local.path.display(),
+            local.info.model_id
+        )),
+    }
+    Ok(())
+}
+
+/// The texture overlay the `--texture` flags ask for, if any
+fn texture_overlay(args: &TextureArgs) -> Result<Option<TextureOverlay>> {
+    let Some(spec) = &args.texture else {
+        return Ok(None);
+    };
+    let source = if spec.trim().eq_ignore_ascii_case("auto") {
+        TextureSource::Material
+    } else {
+        TextureSource::Texture(Texture::resolve(spec)?)
+    };
+    let mut overlay = TextureOverlay::new(source).with_blend(args.texture_blend);
+    if let Some(scale) = args.texture_scale {
+        overlay = overlay.with_scale(scale);
+    }
+    if let Some(opacity) = args.texture_opacity {
+        overlay = overlay.with_opacity(opacity);
+    }
+    Ok(Some(overlay))
+}
+
+/// The skin protection `--no-protect-skin` and `--skin-sensitivity` ask for
+fn skin_protection(off: bool, sensitivity: Option<f32>) -> Option<SkinProtection> {
+    if off {
+        return None;
+    }
+    Some(sensitivity.map_or_else(SkinProtection::default, SkinProtection::with_sensitivity))
+}
+
+fn hook_runner(config: &Config, no_hooks: bool) -> HookRunner {
+    if no_hooks {
+        HookRunner::disabled()
+    } else {
+        HookRunner::new(config.hooks.clone())
+    }
+}
+
+/// The manager every subcommand uses, so `--models-dir` and `--hf-cache-dir`
+/// apply everywhere; the flags win over the environment
+fn model_manager(
+    config: &Config,
+    storage: &StorageArgs,
+    cancel: &CancelToken,
+) -> Result<ModelManager> {
+    let mut builder = ModelManagerBuilder::new()
+        .with_cancel_token(cancel.clone())
+        .with_metrics(metrics_sink(config)?)
+        .with_endpoint_fallbacks(config.hf_endpoint_fallbacks.clone())
+        .with_path_policy(config.index_path_policy.unwrap_or_default())
+        .with_download_lock_ttl(config.download_lock_ttl())
+        .with_cache_policy(config.cache_policy());
+    if config.history_enabled() {
+        if let Ok(path) = History::default_path() {
+            builder = builder.with_usage_history(History::new(path));
+        }
+    }
+    if let Some(limit) = config.download_rate_limit()? {
+        builder = builder.with_download_rate_limit(limit);
+    }
+    if let Some(endpoint) = config.hf_endpoint() {
+        builder = builder.with_endpoint(endpoint);
+    }
+    if let Some(dir) = storage.models_dir.clone().or_else(|| config.models_dir()) {
+        builder = builder.with_models_dir(dir);
+    }
+    if let Some(dir) = &storage.hf_cache_dir {
+        builder = builder.with_hf_cache_dir(dir.clone());
+    }
+    if storage.offline() {
+        let cache_dir = storage
+            .hf_cache_dir
+            .clone()
+            .unwrap_or_else(paths::hf_cache_dir);
+        builder = builder.with_source(Box::new(OfflineSource::offline(cache_dir)));
+    }
+    builder.build()
+}
+
+/// The local metrics file, or nowhere when `metrics_enabled` is off
+fn metrics_sink(config: &Config) -> Result<Arc<dyn MetricsSink>> {
+    Ok(if config.metrics_enabled() {
+        Arc::new(FileSink::new(FileSink::default_path()?))
+    } else {
+        Arc::new(NoopSink)
+    })
+}
+
+async fn handle_model_command(
+    action: ModelCommands,
+    config_path: &Path,
+    storage: &StorageArgs,
+    status: Status,
+    cancel: &CancelToken,
+) -> Result<CommandOutput> {
+    let mut config = Config::load(config_path)?;
+    if let ModelCommands::Download {
+        limit_rate: Some(limit),
+        ..
+    } = &action
+    {
+        config.download_rate_limit = Some(limit.bytes_per_sec().to_string());
+    }
+    let model_manager = model_manager(&config, storage, cancel)?;
+    match action {
+        ModelCommands::List(args) => {
+            let query = ModelQuery {
+                filter: args.filter,
+                min_size: args.min_size,
+                max_size: args.max_size,
+                offset: args.offset,
+                limit: args.limit,
+            };
+            let thumbnails = args.thumbnails;
+            let models = model_manager
+                .query_models_async(&query)
+                .await
+                .context("Failed to list models")?;
+
+            let mut output = CommandOutput::new(&models)?;
+            if models.is_empty() {
+                return Ok(output.line("No models available."));
+            }
+            let models_pinned = models.iter().any(|m| m.pinned);
+            if let Some(dir) = &thumbnails {
+                fs::create_dir_all(dir)
+                    .with_context(|| format!("Failed to create directory {}", dir.display()))?;
+            }
+
+            let models_external = models.iter().any(|m| m.origin == ModelOrigin::External);
+            for model in &models {
+                let marker = if model.pinned { "*" } else { " " };
+                let origin = match model.origin {
+                    ModelOrigin::External => "e",
+                    ModelOrigin::HuggingFace => " ",
+                };
+                let availability = model_manager.model_availability(model);
+                let compat = if args.compat {
+                    let compatibility = model_manager
+                        .check_compatibility(&model.model_id, Task::ImageToImage)
+                        .unwrap_or(Compatibility::Unknown);
+                    format!("{:<4}", compatibility.label())
+                } else {
+                    String::new()
+                };
+                output.push_line(format_args!(
+                    "{marker}{origin} {availability:<11} {compat}{}",
+                    model.summary_line()
+                ));
+                warn_unnamed_files(model);
+                let (Some(dir), Some(thumbnail)) =
+                    (&thumbnails, model_manager.thumbnail_path(&model.model_id))
+                else {
+                    continue;
+                };
+                let target = dir.join(thumbnail.file_name().unwrap_or_default());
+                fs::copy(&thumbnail, &target)
+                    .with_context(|| format!("Failed to copy thumbnail to {}", target.display()))?;
+                output.push_line(format_args!("  thumbnail: {}", target.display()));
+            }
+            let legend: Vec<_> = [
+                (models_pinned, "* pinned"),
+                (models_external, "e external"),
+                (args.compat, "yes/no/? whether `image generate` can use it"),
+            ]
+            .into_iter()
+            .filter_map(|(shown, legend)| shown.then_some(legend))
+            .collect();
+            if !legend.is_empty() {
+                output.push_line(format_args!("({})", legend.join(", ")));
+            }
+            Ok(output)
+        }
+        ModelCommands::Download {
+            name,
+            manifest,
+            parallel,
+            continue_on_error,
+            include,
+            no_docs,
+            dry_run,
+            no_wait,
+            keep_old,
+            variant,
+            limit_rate: _,
+            queue,
+        } => {
+            if queue {
+                let specs = match (name, manifest) {
+                    (Some(spec), _) => vec![spec],
+                    (None, Some(manifest)) => {
+                        let text = fs::read_to_string(&manifest).with_context(|| {
+                            format!("Failed to read manifest {}", manifest.display())
+                        })?;
+                        ModelSpec::parse_manifest(&text)
+                            .with_context(|| format!("Invalid manifest {}", manifest.display()))?
+                    }
+                    (None, None) => bail!("Either a model name or --manifest is required"),
+                };
+                return queue_downloads(&model_manager, specs);
+            }
+            let mut options = DownloadOptions {
+                include,
+                fetch_docs: !no_docs,
+                lock_wait: if no_wait {
+                    LockWait::FailFast
+                } else {
+                    LockWait::Wait
+                },
+                keep_old,
+                variant,
+                ..Default::default()
+            };
+            let Some(spec) = name else {
+                let manifest = manifest.context("Either a model name or --manifest is required")?;
+                let options = BulkDownloadOptions {
+                    parallel: parallel as usize,
+                    continue_on_error,
+                    download: options,
+                };
+                return download_manifest(&model_manager, &manifest, &options).await;
+            };
+            options.revision = spec.revision;
+            let name = spec.id;
+            if let Some(url) = name.pasted_url() {
+                status.line(format_args!("Reading {url} as the model id {name}."));
+            }
+            let plan = model_manager.plan_download(&name, &options).await?;
+            if dry_run {
+                return download_plan_output(&plan);
+            }
+            let report = model_manager
+                .download_planned_with_progress(&plan, options.lock_wait, hash_progress(&plan))
+                .await;
+            if io::stderr().is_terminal() {
+                eprint!("\r\x1b[2K");
+            }
+            let report = report?;
+            debug!("Downloaded model: {:?}", report.model);
+            if let Some(change) = &report.revision_change {
+                warn_revision_change(&name, change);
+            }
+            let output = CommandOutput::new(&report)?;
+            let output = match &plan.endpoint {
+                Some(endpoint) => output.line(format_args!(
+                    "Model {name} downloaded successfully from {endpoint}."
+                )),
+                None => output.line(format_args!("Model {name} downloaded successfully.")),
+            };
+            Ok(output.line(download_summary(&report)))
+        }
+        ModelCommands::Import {
+            path,
+            recursive,
+            copy,
+            as_id,
+        } => {
+            if let Some(model_id) = as_id {
+                let model = model_manager.import_external_as(&path, &model_id, copy)?;
+                let mut output = CommandOutput::new(&model)?;
+                push_imported(&mut output, &model);
+                return Ok(output);
+            }
+            let report = model_manager.import_external(&path, recursive, copy)?;
+            let mut output = CommandOutput::new(&report)?;
+            for model in &report.imported {
+                push_imported(&mut output, model);
+            }
+            for (found, reason) in &report.skipped {
+                output.push_line(format_args!(
+                    "Skipped {} ({}): {reason}",
+                    found.model_id,
+                    found.path.display()
+                ));
+            }
+            if report.imported.is_empty() && report.skipped.is_empty() {
+                output.push_line(format_args!("No models found in {}.", path.display()));
+            }
+            Ok(output)
+        }
+        ModelCommands::Delete {
+            name,
+            all,
+            filter,
+            dry_run,
+        } => {
+            let Some(name) = name else {
+                let selector = match filter {
+                    Some(pattern) => ModelSelector::Filter(pattern),
+                    None if all => ModelSelector::All,
+                    None => unreachable!("clap requires a name, --all or --filter"),
+                };
+                let reports = model_manager.delete_models(&selector, dry_run)?;
+                return delete_reports_output(&reports, dry_run);
+            };
+            status.line(format_args!("Deleting model: {name}"));
+            let model = model_manager.delete_model(&name)?;
+            let report = DeleteReport {
+                model_id: name,
+                bytes: model.total_size(),
+                outcome: DeleteOutcome::Deleted,
+            };
+            Ok(CommandOutput::new(&report)?.line(format_args!(
+                "Model {} deleted ({} freed).",
+                report.model_id,
+                format_size(report.bytes)
+            )))
+        }
+        ModelCommands::Pin { name } => {
+            model_manager.set_pinned(&name, true)?;
+            Ok(
+                CommandOutput::new(&json!({ "model_id": name, "pinned": true }))?
+                    .line(format_args!("Pinned {name}.")),
+            )
+        }
+        ModelCommands::Unpin { name } => {
+            model_manager.set_pinned(&name, false)?;
+            Ok(
+                CommandOutput::new(&json!({ "model_id": name, "pinned": false }))?
+                    .line(format_args!("Unpinned {name}.")),
+            )
+        }
+        ModelCommands::Gc { dry_run, force } => {
+            let report = model_manager.gc(dry_run, force)?;
+            let mut output = CommandOutput::new(&report)?;
+            for path in &report.removed {
+                output.push_line(format_args!("  {}", path.display()));
+            }
+            for model_id in &report.skipped_pinned {
+                output.push_line(format_args!(
+                    "Skipped pinned model {model_id} (use --force to collect it)"
+                ));
+            }
+            let size = format_size(report.reclaimed_bytes);
+            if dry_run {
+                output.push_line(format_args!(
+                    "Would remove {} entries ({size}).",
+                    report.removed.len()
+                ));
+            } else {
+                output.push_line(format_args!(
+                    "Removed {} entries ({size} freed).",
+                    report.removed.len()
+                ));
+            }
+            Ok(output)
+        }
+        ModelCommands::Evict { dry_run } => {
+            if !model_manager.cache_policy().is_set() {
+                bail!(
+                    "No cache limits are set; set `cache_max_bytes` or `cache_max_age_days` first"
+                );
+            }
+            let report = model_manager.enforce_cache_policy(dry_run)?;
+            let mut output = CommandOutput::new(&report)?;
+            for model in &report.evicted {
+                let last_used = model
+                    .last_used
+                    .map_or("never".to_string(), |t| t.format("%Y-%m-%d").to_string());
+                output.push_line(format_args!(
+                    "  {}  {}  (last used {last_used}, {})",
+                    model.model_id,
+                    format_size(model.bytes),
+                    model.reason
+                ));
+            }
+            for model_id in &report.skipped_pinned {
+                output.push_line(format_args!("Skipped pinned model {model_id}"));
+            }
+            let size = format_size(report.reclaimed_bytes());
+            let verb = if dry_run { "Would evict" } else { "Evicted" };
+            output.push_line(format_args!(
+                "{verb} {} models ({size}); the cache {} {}.",
+                report.evicted.len(),
+                if dry_run { "would take" } else { "takes" },
+                format_size(report.remaining_bytes)
+            ));
+            if report.over_budget() {
+                output.push_line(format_args!(
+                    "Still over the {} budget: what is left is pinned or wasn't downloaded by si.",
+                    format_size(report.policy.max_bytes.unwrap_or_default())
+                ));
+            }
+            Ok(output)
+        }
+        ModelCommands::Repair { dry_run } => {
+            let report = model_manager.repair_index_with(dry_run)?;
+            let mut output = CommandOutput::new(&report)?;
+            if report.is_clean() {
+                return Ok(output.line(&report));
+            }
+            for change in &report.changes {
+                output.push_line(format_args!("  {change}"));
+            }
+            if dry_run {
+                output.push_line(format_args!("Would repair {}.", report.summary()));
+            } else {
+                output.push_line(format_args!("Repaired {}.", report.summary()));
+                if let Some(backup) = &report.backup {
+                    output.push_line(format_args!(
+                        "The old index is saved at {}.",
+                        backup.display()
+                    ));
+                }
+            }
+            Ok(output)
+        }
+        ModelCommands::Show {
+            name,
+            readme,
+            tensors,
+            config,
+        } => {
+            if readme {
+                let text = model_manager
+                    .model_readme(&name)?
+                    .with_context(|| format!("No README found for model {name}"))?;
+                let mut output = CommandOutput::new(&json!({ "model_id": name, "readme": text }))?;
+                output.push_str(text);
+                return Ok(output);
+            }
+
+            if tensors {
+                let model = model_manager
+                    .get_model_async(&name)
+                    .await?
+                    .with_context(|| format!("Model {name} is not in the index"))?;
+                let mut summaries = Vec::new();
+                let mut lines = Vec::new();
+                for file in &model.files {
+                    let Some(file_name) = file.name().filter(|n| n.ends_with(".safetensors"))
+                    else {
+                        continue;
+                    };
+                    let summary = safetensors_meta::inspect(&file.path)
+                        .with_context(|| format!("Failed to inspect {file}"))?;
+                    lines.push(format!("{file}: {summary}"));
+                    summaries.push(json!({ "file": file_name, "summary": summary }));
+                }
+                let mut output = CommandOutput::new(&summaries)?;
+                if summaries.is_empty() {
+                    output.push_line(format_args!("Model {name} has no .safetensors files."));
+                }
+                for line in lines {
+                    output.push_line(line);
+                }
+                return Ok(output);
+            }
+
+            if config {
+                let config = model_manager.read_model_config(&name)?;
+                return Ok(CommandOutput::new(&config)?.line(&config));
+            }
+
+            status.line(format_args!("Showing details for model: {name}"));
+            let Some(model) = model_manager.get_model_async(&name).await? else {
+                return Ok(CommandOutput::new(&Option::<ModelInfo>::None)?
+                    .line(format_args!("Model {name} is not in the index.")));
+            };
+            let mut output = CommandOutput::new(&model)?.line(&model);
+            warn_unnamed_files(&model);
+            output.push_str(format_args!("Source: {}", model.source));
+            match model.source.caveat() {
+                Some(caveat) => output.push_line(format_args!(" ({caveat})")),
+                None => output.push_line(""),
+            }
+            if model.is_pickle_only() {
+                output.push_line(PICKLE_ONLY_NOTE);
+            }
+            for kept in &model.revisions {
+                let size: u64 = kept.files.iter().map(|f| f.size).sum();
+                output.push_line(format_args!(
+                    "Kept revision {} ({} files - {})",
+                    kept.revision,
+                    kept.files.len(),
+                    format_size(size)
+                ));
+            }
+            if let Some(version) = model_manager.index_created_by()? {
+                output.push_line(format_args!("Index created by si {version}"));
+            }
+            Ok(output)
+        }
+        ModelCommands::Open { name, print } => {
+            let dirs = model_manager.model_dirs(&name)?;
+            let mut output = CommandOutput::new(&dirs)?;
+            match dirs.as_slice() {
+                [dir] if !print && !status.to_stderr && io::stdout().is_terminal() => {
+                    platform::reveal(dir)?
+                }
+                _ => {
+                    for dir in &dirs {
+                        output.push_line(dir.display());
+                    }
+                }
+            }
+            Ok(output)
+        }
+        ModelCommands::Refresh { name } => {
+            let report = model_manager.refresh_sizes(name.as_deref())?;
+            let mut output = CommandOutput::new(&report)?;
+            for file in &report.resized {
+                output.push_line(format_args!(
+                    "Updated {} {}: {} -> {}",
+                    file.model_id,
+                    file.path.display(),
+                    format_size(file.old_size),
+                    format_size(file.new_size)
+                ));
+            }
+            for file in &report.missing {
+                output.push_line(format_args!(
+                    "Missing {} {}",
+                    file.model_id,
+                    file.path.display()
+                ));
+            }
+            Ok(output.line(format_args!(
+                "Checked {} models: {} sizes updated, {} files missing.",
+                report.models_checked,
+                report.resized.len(),
+                report.missing.len()
+            )))
+        }
+        ModelCommands::Sync { dry_run, diff } => {
+            let spinner = std::io::stderr().is_terminal();
+            let progress = |found: usize| {
+                if spinner {
+                    let frame = ['|', '/', '-', '\\'][found % 4];
+                    eprint!("\r{frame} Scanning cache: {found} models found");
+                }
+            };
+            let sync_result = model_manager
+                .sync_models_with_progress(dry_run, &progress)
+                .await;
+            if spinner {
+                // Clear the spinner line
+                eprint!("\r\x1b[2K");
+            }
+            let sync_result = sync_result?;
+            let mut output = CommandOutput::new(&sync_result)?;
+            if dry_run {
+                output.push_line(format_args!(
+                    "Dry run completed. Found {} discrepancies.",
+                    sync_result.discrepancies_count()
+                ));
+            } else {
+                output.push_line("Sync completed successfully.");
+            }
+
+            output.push_line(&sync_result);
+            if diff {
+                output.push_line(format_args!("\n{}", sync_result.diff()));
+            }
+            Ok(output)
+        }
+        ModelCommands::Stats { sort } => {
+            let history = History::new(History::default_path()?);
+            let mut rows = model_manager.usage_stats(&history)?;
+            sort.sort(&mut rows);
+            let mut output = CommandOutput::new(&rows)?;
+            if rows.is_empty() {
+                return Ok(output.line("No models available."));
+            }
+            output.push_line(format_args!(
+                "{:<48} {:>6} {:>6} {:>10} {:>10}  LAST USED",
+                "MODEL", "RUNS", "FAILED", "TIME", "SIZE"
+            ));
+            for row in &rows {
+                output.push_line(format_args!(
+                    "{:<48} {:>6} {:>6} {:>10} {:>10}  {}",
+                    row.model_id,
+                    row.generations,
+                    row.failures,
+                    format_duration(Duration::from_millis(row.total_processing_ms)),
+                    format_size(row.size_bytes),
+                    row.last_used
+                        .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+                        .unwrap_or_else(|| "never".to_string())
+                ));
+            }
+            Ok(output)
+        }
+        ModelCommands::Queue { action } => {
+            handle_queue_command(action, &model_manager, storage).await
+        }
+        ModelCommands::Verify { name, deep } => {
+            let total = model_manager
+                .get_model_async(&name)
+                .await?
+                .map_or(0, |model| model.total_size());
+            let show_progress = deep && std::io::stderr().is_terminal();
+            let progress = |done: u64| {
+                if show_progress {
+                    eprint!("\rHashing: {} of {}", format_size(done), format_size(total));
+                }
+            };
+            let report = model_manager.verify_model(&name, deep, &progress);
+            if show_progress {
+                eprint!("\r\x1b[2K");
+            }
+            let report = report?;
+            let mut output = CommandOutput::new(&report)?;
+            for path in &report.missing {
+                output.push_line(format_args!("Missing {}", path.display()));
+            }
+            for path in &report.size_mismatches {
+                output.push_line(format_args!("Size mismatch {}", path.display()));
+            }
+            for path in &report.hash_mismatches {
+                output.push_line(format_args!("Checksum mismatch {}", path.display()));
+            }
+            for mismatch in &report.format_mismatches {
+                output.push_line(format_args!(
+                    "Format mismatch {}: indexed as {}, but the contents are {}",
+                    mismatch.path.display(),
+                    mismatch.indexed,
+                    mismatch.found
+                ));
+            }
+            if deep && !report.unhashed.is_empty() {
+                output.push_line(format_args!(
+                    "{} files have no recorded checksum; re-download the model to record them.",
+                    report.unhashed.len()
+                ));
+            }
+            if !report.is_ok() || (deep && !report.unhashed.is_empty()) {
+                if let Some(caveat) = report.source.caveat() {
+                    output.push_line(format_args!("Note: the index entry was {caveat}."));
+                }
+            }
+            if !report.is_ok() {
+                return Ok(output.with_failure(format_args!("Model {name} failed verification")));
+            }
+            Ok(output.line(format_args!(
+                "Model {name} verified: {} files OK.",
+                report.files_checked
+            )))
+        }
+    }
+}
+
+/// What `model show` and `model import` say about a model with only pickle weights
+const PICKLE_ONLY_NOTE: &str =
+    "Only pickle weights: convert them to .safetensors before generating with this model.";
+
+/// Report `model` as imported, noting when it can't be generated with as is
+fn push_imported(output: &mut CommandOutput, model: &ModelInfo) {
+    output.push_line(format_args!("Imported {}", model.summary_line()));
+    if model.is_pickle_only() {
+        output.push_line(format_args!("  {PICKLE_ONLY_NOTE}"));
+    }
+}
+
+/// A small model `si init` offers, so the first generation needs no large download
+const STARTER_MODEL: &str = "hf-internal-testing/tiny-stable-diffusion-pipe";
+
+async fn handle_init(
+    args: InitArgs,
+    config_path: &Path,
+    storage: &StorageArgs,
+    status: Status,
+    cancel: &CancelToken,
+) -> Result<CommandOutput> {
+    let interactive = !args.yes && io::stdin().is_terminal();
+    let starter_question = format!("Download the starter model {STARTER_MODEL} (a few MB)?");
+    let mut download = args.download_starter;
+
+    let config = if config_path.exists() && !args.force {
+        status.line(format_args!(
+            "Keeping the existing config at {} (use --force to replace its settings).",
+            config_path.display()
+        ));
+        if interactive {
+            download = confirm(&starter_question, download, status)?;
+        }
+        Config::load(config_path)?
+    } else {
+        let mut default_model = args.default_model.clone();
+        let mut models_dir = storage.models_dir.clone();
+        if interactive {
+            download = confirm(&starter_question, download, status)?;
+            let suggested = default_model.clone().unwrap_or_else(|| {
+                if download {
+                    STARTER_MODEL
+                } else {
+                    DEFAULT_MODEL
+                }
+                .to_string()
+            });
+            default_model = Some(ask("Default model", &suggested, status)?);
+            let dir = ask(
+                "Models directory (empty for the default)",
+                &models_dir
+                    .as_ref()
+                    .map(|d| d.display().to_string())
+                    .unwrap_or_default(),
+                status,
+            )?;
+            models_dir = (!dir.is_empty()).then(|| PathBuf::from(dir));
+        } else if download && default_model.is_none() {
+            default_model = Some(STARTER_MODEL.to_string());
+        }
+
+        // --force keeps profiles, hooks and the like, replacing only what init sets
+        let mut config = Config::load(config_path)?;
+        config.defaults.default_model =
+            Some(default_model.unwrap_or_else(|| DEFAULT_MODEL.to_string()));
+        if let Some(dir) = models_dir {
+            config.models_dir = Some(
+                std::path::absolute(&dir)
+                    .with_context(|| format!("Invalid models directory {}", dir.display()))?,
+            );
+        }
+        config.save(config_path)?;
+        status.line(format_args!("Wrote config to {}", config_path.display()));
+        config
+    };
+
+    let model_manager = model_manager(&config, storage, cancel)?;
+    let mut checks =
+        si::doctor::run_checks(model_manager.models_dir(), &model_manager.hf_cache_dir());
+    checks.push(si::doctor::check_index(&model_manager));
+    checks.push(si::doctor::check_weights(&model_manager));
+    checks.push(si::doctor::check_download_queue(&model_manager));
+    for check in &checks {
+        status.line(format_args!("{check}"));
+    }
+
+    let default_model = config
+        .effective(None)?
+        .settings
+        .default_model
+        .unwrap_or_else(|| DEFAULT_MODEL.to_string());
+    if download {
+        if model_manager
+            .get_model_async(STARTER_MODEL)
+            .await?
+            .is_some()
+        {
+            status.line(format_args!(
+                "Starter model {STARTER_MODEL} is already downloaded."
+            ));
+        } else {
+            status.line(format_args!("Downloading {STARTER_MODEL}..."));
+            let model = model_manager.download_model(STARTER_MODEL).await?;
+            status.line(format_args!("Downloaded {}", model.summary_line()));
+        }
+    }
+
+    let output = CommandOutput::new(&json!({
+        "config_path": config_path,
+        "models_dir": model_manager.models_dir(),
+        "default_model": default_model,
+        "checks": checks,
+    }))?
+    .line("")
+    .line("si is set up:")
+    .line(format_args!("  Config:        {}", config_path.display()))
+    .line(format_args!(
+        "  Models:        {}",
+        model_manager.models_dir().display()
+    ))
+    .line(format_args!("  Default model: {default_model}"))
+    .line("")
+    .line("Next:")
+    .line("  si model download <name>")
+    .line("  si image generate --input photo.jpg --prompt \"red silk shirt\"")
+    .line("  si config show");
+    if checks.iter().any(|c| c.status == si::CheckStatus::Fail) {
+        return Ok(output.with_failure("The setup has problems, see the failed checks above"));
+    }
+    Ok(output)
+}
+
+/// Ask `question` on the terminal, returning `default` for an empty answer
+fn ask(question: &str, default: &str, status: Status) -> Result<String> {
+    if default.is_empty() {
+        status.prompt(format_args!("{question}: "))?;
+    } else {
+        status.prompt(format_args!("{question} [{default}]: "))?;
+    }
+    let mut answer = String::new();
+    io::stdin()
+        .read_line(&mut answer)
+        .context("Failed to read the answer")?;
+    let answer = answer.trim();
+    Ok(if answer.is_empty() { default } else { answer }.to_string())
+}
+
+/// Ask a yes/no `question` on the terminal
+fn confirm(question: &str, default: bool, status: Status) -> Result<bool> {
+    let hint = if default { "Y/n" } else { "y/N" };
+    loop {
+        let answer = ask(&format!("{question} ({hint})"), "", status)?;
+        match answer.to_lowercase().as_str() {
+            "" => return Ok(default),
+            "y" | "yes" => return Ok(true),
+            "n" | "no" => return Ok(false),
+            _ => status.line(format_args!("Please answer y or n.")),
+        }
+    }
+}
+
+fn handle_config_command(
+    action: ConfigCommands,
+    config_path: &Path,
+    status: Status,
+) -> Result<CommandOutput> {
+    match action {
+        ConfigCommands::Show => {
+            status.line(format_args!("Showing current configuration..."));
+            let config = Config::load(config_path)?;
+            let mut output = CommandOutput::new(&config)?;
+            output.push_str(toml::to_string_pretty(&config).context("Failed to render config")?);
+            Ok(output)
+        }
+        ConfigCommands::Set { key, value } => {
+            status.line(format_args!("Setting config: {key} = {value}"));
+            let mut config = Config::load(config_path)?;
+            config.set(&key, &value)?;
+            config.save(config_path)?;
+            CommandOutput::new(&json!({ "key": key, "value": config.get(&key) }))
+        }
+        ConfigCommands::Get { key } => {
+            status.line(format_args!("Getting config value for: {key}"));
+            let config = Config::load(config_path)?;
+            let value = config.get(&key);
+            let output = CommandOutput::new(&json!({ "key": key, "value": value }))?;
+            Ok(match value {
+                Some(value) => output.line(value),
+                None => output.line(format_args!("{key} is not set")),
+            })
+        }
+        ConfigCommands::Reset => {
+            status.line(format_args!("Resetting configuration to defaults..."));
+            let existed = config_path.exists();
+            if existed {
+                fs::remove_file(config_path).with_context(|| {
+                    format!("Failed to remove config file {}", config_path.display())
+                })?;
+            }
+            CommandOutput::new(&json!({ "config_path": config_path, "removed": existed }))
+        }
+        ConfigCommands::Validate => {
+            status.line(format_args!("Validating {}...", config_path.display()));
+            if !config_path.exists() {
+                let output = CommandOutput::new(
+                    &json!({ "config_path": config_path, "exists": false, "problems": [] }),
+                )?;
+                return Ok(output.line(format_args!(
+                    "No config file at {}; the defaults are in effect",
+                    config_path.display()
+                )));
+            }
+            let text = fs::read_to_string(config_path)
+                .with_context(|| format!("Failed to read config from {}", config_path.display()))?;
+            let problems = config_schema::validate(&text);
+            let mut output = CommandOutput::new(
+                &json!({ "config_path": config_path, "exists": true, "problems": problems }),
+            )?;
+            if problems.is_empty() {
+                return Ok(output.line(format_args!("{} is valid", config_path.display())));
+            }
+            for problem in &problems {
+                output.push_line(problem);
+            }
+            Ok(output.with_failure(format!(
+                "Found {} problem{} in {}",
+                problems.len(),
+                if problems.len() == 1 { "" } else { "s" },
+                config_path.display()
+            )))
+        }
+        ConfigCommands::Schema => {
+            let mut output = CommandOutput::new(&json!({ "keys": config_schema::SCHEMA }))?;
+            for key in config_schema::SCHEMA {
+                let mut details = key.kind.to_string();
+                if let Some(default) = key.default {
+                    details.push_str(&format!(", default {default}"));
+                }
+                if key.profile {
+                    details.push_str(", also per profile");
+                }
+                output.push_line(format_args!("{} ({details})", key.key));
+                output.push_line(format_args!("    {}", key.description));
+            }
+            Ok(output)
+        }
+        ConfigCommands::Profile { action } => handle_profile_command(action, config_path),
+    }
+}
+
+fn handle_profile_command(action: ProfileCommands, config_path: &Path) -> Result<CommandOutput> {
+    let mut config = Config::load(config_path)?;
+    match action {
+        ProfileCommands::List => {
+            let profiles: Vec<_> = config
+                .profiles
+                .keys()
+                .map(|name| {
+                    let active = config.active_profile.as_ref() == Some(name);
+                    json!({ "name": name, "active": active })
+                })
+                .collect();
+            let mut output = CommandOutput::new(&profiles)?;
+            if config.profiles.is_empty() {
+                output.push_line("No profiles defined.");
+            }
+            for name in config.profiles.keys() {
+                let marker = if config.active_profile.as_ref() == Some(name) {
+                    " (active)"
+                } else {
+                    ""
+                };
+                output.push_line(format_args!("{name}{marker}"));
+            }
+            Ok(output)
+        }
+        ProfileCommands::Show { name } => {
+            let effective = config.effective(Some(&name))?;
+            let mut output = CommandOutput::new(&effective.settings)?;
+            output.push_str(
+                toml::to_string_pretty(&effective.settings).context("Failed to render profile")?,
+            );
+            Ok(output)
+        }
+        ProfileCommands::Create {
+            name,
+            default_model,
+            strength,
+            steps,
+            max_size,
+            output_format,
+        } => {
+            let values = [
+                ("default_model", default_model),
+                ("strength", strength.map(|v| v.to_string())),
+                ("steps", steps.map(|v| v.to_string())),
+                ("max_size", max_size.map(|v| v.to_string())),
+                ("output_format", output_format),
+            ];
+            config.create_profile(&name, Profile::default())?;
+            for (key, value) in values {
+                if let Some(value) = value {
+                    config.set(&format!("profiles.{name}.{key}"), &value)?;
+                }
+            }
+            config.save(config_path)?;
+            Ok(CommandOutput::new(&config.profiles.get(&name))?
+                .line(format_args!("Created profile {name}.")))
+        }
+        ProfileCommands::Delete { name } => {
+            config.delete_profile(&name)?;
+            config.save(config_path)?;
+            Ok(
+                CommandOutput::new(&json!({ "name": name, "deleted": true }))?
+                    .line(format_args!("Deleted profile {name}.")),
+            )
+        }
+    }
+}
+
+fn handle_cache_command(action: CacheCommands) -> Result<CommandOutput> {
+    match action {
+        CacheCommands::Clear { cache } => {
+            let removed = match cache {
+                CacheKind::Masks => MaskCache::masks()?.clear()?,
+                CacheKind::Hashes => HashCache::hashes()?.clear()?,
+            };
+            Ok(CommandOutput::new(&removed)?.line(format_args!(
+                "Removed {} cached {cache} ({}).",
+                removed.entries,
+                format_size(removed.bytes)
+            )))
+        }
+    }
+}
+
+fn handle_version(args: VersionArgs) -> Result<CommandOutput> {
+    let info = si::version();
+    let mut output = CommandOutput::new(&info)?;
+    output.push_line(&info);
+    if args.verbose {
+        output.push_line(format_args!("  target:   {}", info.target));
+        output.push_line(format_args!(
+            "  features: {}",
+            match info.features.as_slice() {
+                [] => "none".to_string(),
+                features => features.join(", "),
+            }
+        ));
+    }
+    Ok(output)
+}
+
+fn handle_stats(args: StatsArgs, config_path: &Path) -> Result<CommandOutput> {
+    let config = Config::load(config_path)?;
+    let sink = FileSink::new(FileSink::default_path()?);
+    let since = args.since.map(|window| Utc::now() - window);
+    let summary = MetricsSummary::from_records(&sink.records()?, since);
+    let mut output = CommandOutput::new(&summary)?;
+
+    if !config.metrics_enabled() {
+        output.push_line(
+            "Metrics are disabled (metrics_enabled = false); showing what was recorded before.",
+        );
+    }
+    let size = format_size;
+    output.push_line(format_args!(
+        "Generations:      {} ({} failed)",
+        summary.generations, summary.failed_generations
+    ));
+    match summary.average_generation_ms {
+        Some(ms) => output.push_line(format_args!(
+            "Average time:     {}",
+            format_duration(Duration::from_millis(ms))
+        )),
+        None => output.push_line("Average time:     -"),
+    }
+    output.push_line(format_args!(
+        "Downloads:        {} ({})",
+        summary.downloads,
+        size(summary.downloaded_bytes)
+    ));
+    match (summary.cache_bytes_first, summary.cache_bytes_last) {
+        (Some(first), Some(last)) if first != last => {
+            output.push_line(format_args!(
+                "Cache size:       {} -> {}",
+                size(first),
+                size(last)
+            ));
+        }
+        (_, Some(last)) => output.push_line(format_args!("Cache size:       {}", size(last))),
+        _ => output.push_line("Cache size:       -"),
+    }
+    Ok(output)
+}
+
+#[cfg(feature = "server")]
+async fn handle_serve(
+    args: ServeArgs,
+    config_path: &Path,
+    storage: &StorageArgs,
+    status: Status,
+    cancel: &CancelToken,
+) -> Result<CommandOutput> {
+    let config = Config::load(config_path)?;
+    let tryon = VirtualTryOn::new(model_manager(&config, storage, cancel)?)?
+        .with_cancel_token(cancel.clone())
+        .with_thumbnails(config.thumbnails_enabled())
+        .with_styles(style_registry()?)
+        .with_coverage_bounds(config.mask_coverage_bounds())
+        .with_download_missing(args.download_missing);
+    let router = si::server::router(
+        model_manager(&config, storage, cancel)?,
+        tryon,
+        args.workers as usize,
+    )?;
+
+    let listener = tokio::net::TcpListener::bind((args.host.as_str(), args.port))
+        .await
+        .with_context(|| format!("Failed to listen on {}:{}", args.host, args.port))?;
+    let address = listener.local_addr()?;
+    status.line(format_args!("Listening on http://{address}"));
+    si::server::serve(listener, router, cancel.clone()).await?;
+    CommandOutput::new(&json!({ "address": address }))
+}
+
+async fn handle_image_command(
+    action: ImageCommands,
+    config_path: &Path,
+    profile: Option<&str>,
+    storage: &StorageArgs,
+    status: Status,
+    cancel: &CancelToken,
+) -> Result<CommandOutput> {
+    match action {
+        ImageCommands::Generate(args) => {
+            let config = Config::load(config_path)?;
+            handle_generate(*args, &config, profile, storage, status, cancel).await
+        }
+        ImageCommands::Batch(args) => {
+            let config = Config::load(config_path)?;
+            handle_batch(args, &config, profile, storage, status, cancel).await
+        }
+        ImageCommands::Convert(args) => handle_convert(args),
+        ImageCommands::Formats => handle_formats(),
+        ImageCommands::Info { path } => handle_image_info(&path),
+        ImageCommands::Bench(args) => {
+            handle_bench(args, &Config::load(config_path)?, storage, status, cancel)
+        }
+        ImageCommands::Styles {
+            action: StylesCommands::List,
+        } => handle_styles_list(),
+        ImageCommands::History { action } => {
+            let history = History::new(History::default_path()?);
+            handle_history_command(
+                action,
+                &history,
+                &Config::load(config_path)?,
+                storage,
+                cancel,
+            )
+            .await
+        }
+    }
+}
+
+/// Shown when an output format can't carry the input's color profile
+const ICC_DROPPED: &str = "this format can't carry the input's ICC color profile, so colors may shift \
+     (use PNG or JPEG to keep it, or --strip-icc to drop it)";
+
+async fn handle_batch(
+    args: BatchArgs,
+    config: &Config,
+    profile: Option<&str>,
+    storage: &StorageArgs,
+    status: Status,
+    cancel: &CancelToken,
+) -> Result<CommandOutput> {
+    let settings = config
+        .effective(config.resolve_profile_name(profile).as_deref())?
+        .settings;
+    let model = args
+        .model
+        .or(settings.default_model)
+        .unwrap_or_else(|| DEFAULT_MODEL.to_string());
+
+    let selection = InputSelector::new(&args.input_dir, &args.exclude)?
+        .with_recursive(args.recursive)
+        .select()?;
+    if selection.images.is_empty() {
+        let summary = json!({ "processed": 0, "skipped": selection.skipped, "results": [] });
+        return Ok(CommandOutput::new(&summary)?.line(format_args!(
+            "No images found in {} ({} files skipped).",
+            args.input_dir.display(),
+            selection.skipped
+        )));
+    }
+
+    let coverage_bounds = config.mask_coverage_bounds();
+    let manager = model_manager(config, storage, cancel)?;
+    if args.register {
+        register_local_model(&manager, &model, status)?;
+    }
+    let mut tryon = VirtualTryOn::new(manager)?
+        .with_cancel_token(cancel.clone())
+        .with_thumbnails(config.thumbnails_enabled())
+        .with_styles(style_registry()?)
+        .with_coverage_bounds(coverage_bounds)
+        .with_strict_mask(args.strict_mask)
+        .with_lenient(args.lenient)
+        .with_strip_icc(args.strip_icc)
+        .with_embedded_metadata(args.embed_metadata || !args.no_embed_metadata)
+        .with_skin_protection(skin_protection(args.no_protect_skin, args.skin_sensitivity))
+        .with_texture(texture_overlay(&args.texture)?)
+        .with_timeout(args.timeout)
+        .with_allow_incompatible(args.force)
+        .with_download_missing(args.download_missing);
+    if !args.no_mask_cache {
+        tryon = tryon.with_mask_cache(MaskCache::masks()?);
+    }
+    tryon = tryon.with_hash_cache(HashCache::hashes()?);
+    if config.history_enabled() {
+        tryon = tryon.with_history(History::new(History::default_path()?));
+    }
+    let queue = JobQueue::new(args.jobs as usize, tryon);
+
+    status.line(format_args!(
+        "Processing {} images with model {model}",
+        selection.images.len()
+    ));
+    let mut jobs = Vec::new();
+    for input in &selection.images {
+        let relative = input.strip_prefix(&args.input_dir).unwrap_or(input);
+        let request = TryOnRequest {
+            input_image_path: input.clone(),
+            clothing_description: args.prompt.clone(),
+            negative_prompt: None,
+            output_path: args.output_dir.join(relative),
+            model_name: Some(model.clone()),
+            strength: settings.strength,
+            color_strength: None,
+            style_strength: None,
+            reference_image: None,
+            crop: None,
+            mask: None,
+            transform_plan: None,
+            comparison: None,
+            remote_input: None,
+            seed: None,
+        };
+        if args.count > 1 {
+            // The mask cache spares segmenting the input again for each variation
+            for (_, variation) in variation_requests(&request, args.count as usize, rand::random())?
+            {
+                jobs.push((input, queue.submit(variation)));
+            }
+        } else {
+            jobs.push((input, queue.submit(request)));
+        }
+    }
+
+    let total = jobs.len();
+    let mut failed = 0;
+    let mut timed_out = 0;
+    let mut recovered = 0;
+    let mut results = Vec::new();
+    let mut cancelled = false;
+    for (input, job) in jobs {
+        let mut wait = std::pin::pin!(job.wait());
+        // After a cancel, running jobs stop at their next check; wait for them
+        // anyway so none is cut off mid-write when the process exits
+        let result = if cancelled {
+            wait.await
+        } else {
+            tokio::select! {
+                result = &mut wait => result,
+                _ = cancel.cancelled() => {
+                    cancelled = true;
+                    wait.await
+                }
+            }
+        };
+        if cancelled {
+            continue;
+        }
+        match JobStatus::of(&result) {
+            JobStatus::TimedOut => timed_out += 1,
+            JobStatus::Done => {}
+            _ => failed += 1,
+        }
+        match result {
+            Ok(result) => {
+                status.line(format_args!(
+                    "  {} -> {} ({})",
+                    input.display(),
+                    result.output_path.display(),
+                    format_duration(result.processing_time)
+                ));
+                if let Some(warning) = coverage_bounds.check(result.mask_coverage) {
+                    status.line(format_args!("    Warning: {warning}"));
+                }
+                if !result.load_warnings.is_empty() {
+                    recovered += 1;
+                }
+                for warning in &result.load_warnings {
+                    status.line(format_args!("    Warning: {warning}"));
+                }
+                if result.icc_dropped {
+                    status.line(format_args!("    Warning: {ICC_DROPPED}"));
+                }
+                results.push(json!({ "input": input, "result": result }));
+            }
+            Err(e) => {
+                status.line(format_args!("  {} failed: {e:#}", input.display()));
+                results.push(json!({ "input": input, "error": format!("{e:#}") }));
+            }
+        }
+    }
+
+    if cancelled {
+        return Err(SiError::Cancelled.into());
+    }
+
+    let unfinished = failed + timed_out;
+    let summary = json!({
+        "processed": total,
+        "succeeded": total - unfinished,
+        "recovered": recovered,
+        "failed": failed,
+        "timed_out": timed_out,
+        "skipped": selection.skipped,
+        "results": results,
+    });
+    let succeeded = if recovered > 0 {
+        format!("{} succeeded ({recovered} recovered)", total - unfinished)
+    } else {
+        format!("{} succeeded", total - unfinished)
+    };
+    let timed_out_note = if timed_out > 0 {
+        format!(", {timed_out} timed out")
+    } else {
+        String::new()
+    };
+    let processed = if args.count > 1 {
+        format!("{} images ({total} variations)", selection.images.len())
+    } else {
+        format!("{total} images")
+    };
+    let output = CommandOutput::new(&summary)?.line(format_args!(
+        "Processed {processed}: {succeeded}, {failed} failed{timed_out_note}, {} files skipped.",
+        selection.skipped
+    ));
+    if unfinished > 0 {
+        return Ok(output.with_failure(format_args!("{unfinished} of {total} images failed")));
+    }
+    Ok(output)
+}
+
+/// Path that stands for stdin or stdout
+const STDIO: &str = "-";
+
+/// Where messages besides a command's result go: stdout, or stderr when stdout
+/// carries the `--json` document or image data
+#[derive(Clone, Copy, Default)]
+struct Status {
+    to_stderr: bool,
+}
+
+impl Status {
+    fn line(self, message: std::fmt::Arguments<'_>) {
+        if self.to_stderr {
+            eprintln!("{message}");
+        } else {
+            println!("{message}");
+        }
+    }
+
+    /// Show `question` without a line break, for the answer to follow it
+    fn prompt(self, question: std::fmt::Arguments<'_>) -> Result<()> {
+        if self.to_stderr {
+            eprint!("{question}");
+            io::Write::flush(&mut io::stderr())?;
+        } else {
+            print!("{question}");
+            io::Write::flush(&mut io::stdout())?;
+        }
+        Ok(())
+    }
 }
 
-#[derive(Subcommand)]
-enum ModelCommands {
-    /// List available models
-    List,
-    /// Download a new model
-    Download {
-        /// Name of the model to download
-        name: String,
-    },
-    /// Delete a model
-    Delete {
-        /// Name of the model to delete
-        name: String,
-    },
-    /// Show model details
-    Show {
-        /// Name of the model to show
-        name: String,
-    },
-    /// Sync local models with the index
-    Sync {
-        /// Perform a dry run without making changes
-        #[arg(long)]
-        dry_run: bool,
-    },
+/// Shows [`TryOnEvent`]s on stderr: one status line redrawn in place with an ETA
+/// for the pixel transform on a terminal, or a plain line per stage otherwise
+///
+/// Warnings are shown even when the stages aren't.
+struct ProgressDisplay {
+    redraw: bool,
+    stages: bool,
+    start: Instant,
+    state: Mutex<ProgressState>,
 }
 
-#[derive(Subcommand)]
-enum ConfigCommands {
-    /// Show current configuration
-    Show,
-    /// Set a configuration value
-    Set {
-        /// Configuration key
-        key: String,
-        /// Configuration value
-        value: String,
-    },
-    /// Get a configuration value
-    Get {
-        /// Configuration key
-        key: String,
-    },
-    /// Reset configuration to defaults
-    Reset,
+struct ProgressState {
+    stage: Option<TryOnStage>,
+    stage_start: Instant,
+    eta: Option<EtaEstimator>,
+    /// Whether a redrawn line is showing and must be cleared
+    drawn: bool,
 }
 
-#[derive(Subcommand)]
-enum ImageCommands {
-    /// Generate an image
-    Generate {
-        /// Prompt for the image generation
-        prompt: String,
-        /// Model to use for generation
-        #[arg(short, long)]
-        model: String,
-        /// Input image file (jpg, png, gif, etc.)
-        #[arg(short, long)]
-        input: PathBuf,
-        /// Output image file
-        #[arg(short, long)]
-        output: PathBuf,
-    },
+impl ProgressState {
+    /// Erase the redrawn line, if one is showing
+    fn clear(&mut self) {
+        if self.drawn {
+            eprint!("\r\x1b[2K");
+            self.drawn = false;
+        }
+    }
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    env_logger::init();
+impl ProgressDisplay {
+    fn new(redraw: bool, stages: bool) -> Self {
+        Self {
+            redraw,
+            stages,
+            start: Instant::now(),
+            state: Mutex::new(ProgressState {
+                stage: None,
+                stage_start: Instant::now(),
+                eta: None,
+                drawn: false,
+            }),
+        }
+    }
 
-    let cli = Cli::parse();
+    fn handle(&self, event: &TryOnEvent) {
+        let mut state = self.state.lock().expect("progress lock");
+        let elapsed = format_duration(self.start.elapsed());
+        match *event {
+            TryOnEvent::MaskWarning { warning, .. } => {
+                state.clear();
+                eprintln!("Warning: {warning}");
+            }
+            TryOnEvent::CompatibilityWarning {
+                ref model,
+                ref compatibility,
+            } => {
+                state.clear();
+                match compatibility {
+                    Compatibility::Incompatible(reason) => {
+                        eprintln!("Warning: generating with {model} anyway, though {reason}")
+                    }
+                    _ => eprintln!(
+                        "Warning: can't tell whether {model} can generate images; trying it anyway"
+                    ),
+                }
+            }
+            _ if !self.stages => {}
+            TryOnEvent::Stage { stage } => {
+                state.stage = Some(stage);
+                state.stage_start = Instant::now();
+                state.eta = None;
+                if self.redraw {
+                    eprint!("\r\x1b[2K[{elapsed:5.1}s] {stage}");
+                    state.drawn = true;
+                } else {
+                    eprintln!("[{elapsed:.1}s] {stage}");
+                }
+            }
+            TryOnEvent::Rows { done, total } => {
+                if !self.redraw {
+                    return;
+                }
+                let since = state.stage_start.elapsed();
+                let stage = state.stage.unwrap_or(TryOnStage::Transforming);
+                let eta = state
+                    .eta
+                    .get_or_insert_with(|| EtaEstimator::new(total.into()));
+                eta.update(done.into(), since);
+                let remaining = eta
+                    .eta()
+                    .map(|left| format!(", ETA {:.1}s", left.as_secs_f64()))
+                    .unwrap_or_default();
+                eprint!(
+                    "\r\x1b[2K[{elapsed:5.1}s] {stage} {:3.0}%{remaining}",
+                    eta.fraction() * 100.0
+                );
+                state.drawn = true;
+            }
+        }
+    }
+
+    /// Clear the status line so regular output starts on a clean line
+    fn finish(&self) {
+        self.state.lock().expect("progress lock").clear();
+    }
 
-    match cli.command {
-        Commands::Model { action } => handle_model_command(action).await,
-        Commands::Config { action } => handle_config_command(action),
-        Commands::Image { action } => handle_image_command(action),
+    /// Total wall time, plus the size of `output` when it is a file
+    fn summary(&self, output: Option<&Path>) -> String {
+        let elapsed = format_duration(self.start.elapsed());
+        match output.and_then(|path| fs::metadata(path).ok()) {
+            Some(meta) => format!(
+                "Finished in {elapsed}, output is {}",
+                format_size(meta.len())
+            ),
+            None => format!("Finished in {elapsed}"),
+        }
     }
-    .log_error()
 }
 
-async fn handle_model_command(action: ModelCommands) -> Result<()> {
-    let model_manager = ModelManager::new()?;
-    match action {
-        ModelCommands::List => {
-            let models = model_manager
-                .list_models()
-                .context("Failed to list models")?;
+/// The format to write to stdout, refusing to dump binary data on a terminal
+fn stdout_format(name: &str, force: bool) -> Result<ImageFormat> {
+    let format = formats::parse_format(name)?;
+    formats::ensure_writable(format)?;
+    if io::stdout().is_terminal() && !force {
+        bail!("Refusing to write image data to a terminal; redirect stdout or pass --force");
+    }
+    Ok(format)
+}
 
-            if models.is_empty() {
-                println!("No models available.");
-                return Ok(());
-            }
+fn handle_convert(args: ConvertArgs) -> Result<CommandOutput> {
+    let format = args
+        .format
+        .as_deref()
+        .map(formats::parse_format)
+        .transpose()?;
+    let options = ConvertOptions {
+        quality: args.quality,
+        resize: args.resize,
+        format,
+        strip_metadata: args.strip_metadata,
+    };
+    let report = convert::convert(&args.input, &args.output, &options)?;
 
-            for model in models {
-                let total_size: u64 = model.files.iter().map(|f| f.size).sum();
-                println!(
-                    "{} ({} files - {})",
-                    model.model_id,
-                    model.files.iter().len(),
-                    humansize::format_size(total_size, humansize::DECIMAL)
-                );
+    let (in_w, in_h) = report.input_dimensions;
+    let (out_w, out_h) = report.output_dimensions;
+    Ok(CommandOutput::new(&report)?.line(format_args!(
+        "Converted {} ({:?}, {in_w}x{in_h}, {}) to {} ({:?}, {out_w}x{out_h}, {}).",
+        args.input.display(),
+        report.input_format,
+        format_size(report.input_bytes),
+        args.output.display(),
+        report.output_format,
+        format_size(report.output_bytes)
+    )))
+}
+
+fn handle_formats() -> Result<CommandOutput> {
+    let names =
+        |list: Vec<ImageFormat>| -> Vec<_> { list.into_iter().map(formats::extension).collect() };
+    let (input, output) = (names(formats::decoders()), names(formats::encoders()));
+    let missing: Vec<_> = formats::missing_encoders()
+        .into_iter()
+        .map(|(format, feature)| (formats::extension(format), feature))
+        .collect();
+    let mut result = CommandOutput::new(&json!({
+        "input": input,
+        "output": output,
+        "missing": missing
+            .iter()
+            .map(|(format, feature)| json!({ "format": format, "feature": feature }))
+            .collect::<Vec<_>>(),
+    }))?
+    .line(format_args!("Input:  {}", input.join(", ")))
+    .line(format_args!("Output: {}", output.join(", ")));
+    if !missing.is_empty() {
+        let missing: Vec<_> = missing
+            .iter()
+            .map(|(format, feature)| format!("{format} (feature `{feature}`)"))
+            .collect();
+        result.push_line(format_args!(
+            "Output not in this build: {}",
+            missing.join(", ")
+        ));
+    }
+    Ok(result)
+}
+
+fn handle_image_info(path: &Path) -> Result<CommandOutput> {
+    let loaded = imageio::load(path)?;
+    let parameters = metadata::read(path);
+    let (width, height) = (loaded.image.width(), loaded.image.height());
+    let mut result = CommandOutput::new(&json!({
+        "path": path,
+        "format": formats::extension(loaded.format),
+        "width": width,
+        "height": height,
+        "color_type": format!("{:?}", loaded.color_type),
+        "icc_profile": loaded.icc_profile.is_some(),
+        "parameters": parameters,
+    }))?
+    .line(format_args!(
+        "{}: {}, {width}x{height}, {:?}{}",
+        path.display(),
+        formats::extension(loaded.format),
+        loaded.color_type,
+        if loaded.icc_profile.is_some() {
+            ", ICC profile"
+        } else {
+            ""
+        }
+    ));
+    let Some(parameters) = parameters else {
+        result.push_line("No generation parameters embedded.");
+        return Ok(result);
+    };
+    result.push_line(format_args!("Prompt:    {}", parameters.prompt));
+    if let Some(negative) = &parameters.negative_prompt {
+        result.push_line(format_args!("Negative:  {negative}"));
+    }
+    if let Some(model) = &parameters.model {
+        result.push_line(format_args!("Model:     {model}"));
+    }
+    if let Some(strength) = parameters.strength {
+        result.push_line(format_args!("Strength:  {strength}"));
+    }
+    if let Some(seed) = parameters.seed {
+        result.push_line(format_args!("Seed:      {seed}"));
+    }
+    if let Some(version) = &parameters.si_version {
+        result.push_line(format_args!("Made by:   si {version}"));
+    }
+    for (key, value) in &parameters.extra {
+        result.push_line(format_args!("{key}: {value}"));
+    }
+    Ok(result)
+}
+
+fn handle_bench(
+    args: BenchArgs,
+    config: &Config,
+    storage: &StorageArgs,
+    status: Status,
+    cancel: &CancelToken,
+) -> Result<CommandOutput> {
+    if args.backend == Backend::Candle {
+        bail!("The candle backend isn't part of this build; only heuristic can be benchmarked");
+    }
+    let tryon = VirtualTryOn::new(model_manager(config, storage, cancel)?)?
+        .with_cancel_token(cancel.clone())
+        .with_styles(style_registry()?);
+    status.line(format_args!(
+        "Timing {} runs of the {} pipeline on a {}x{} input...",
+        args.iterations, args.backend, args.size, args.size
+    ));
+    let report = bench::run(
+        BenchConfig::new(Arc::new(TryOnPipeline::new(tryon)))
+            .with_backend(args.backend)
+            .with_size(args.size)
+            .with_iterations(args.iterations),
+    )?;
+    Ok(CommandOutput::new(&report)?.line(&report))
+}
+
+/// The style presets, with those from `styles.toml` in the config directory
+fn style_registry() -> Result<StyleRegistry> {
+    StyleRegistry::load(&paths::config_dir()?)
+}
+
+fn handle_styles_list() -> Result<CommandOutput> {
+    let styles = style_registry()?;
+    let presets: Vec<_> = styles
+        .iter()
+        .map(|(name, preset)| {
+            json!({
+                "name": name,
+                "user_defined": styles.is_user_defined(name),
+                "preset": preset,
+            })
+        })
+        .collect();
+    let mut output = CommandOutput::new(&presets)?.line(format_args!(
+        "  {:<16} {:>8} {:>10}  KEYWORDS",
+        "NAME", "CONTRAST", "BRIGHTNESS"
+    ));
+    for (name, preset) in styles.iter() {
+        let marker = if styles.is_user_defined(name) {
+            "u"
+        } else {
+            " "
+        };
+        let keywords = if preset.keywords.is_empty() {
+            name.to_string()
+        } else {
+            preset.keywords.join(", ")
+        };
+        output.push_line(format_args!(
+            "{marker} {name:<16} {:>8.2} {:>+10.2}  {keywords}",
+            preset.contrast, preset.brightness
+        ));
+    }
+    if styles.iter().any(|(name, _)| styles.is_user_defined(name)) {
+        output.push_line(format_args!("(u from {})", si::styles::STYLES_FILENAME));
+    }
+    Ok(output)
+}
+
+async fn handle_generate(
+    args: GenerateArgs,
+    config: &Config,
+    profile: Option<&str>,
+    storage: &StorageArgs,
+    status: Status,
+    cancel: &CancelToken,
+) -> Result<CommandOutput> {
+    let mut args = args;
+    let request_file = args
+        .request_file
+        .as_deref()
+        .map(RequestOverrides::from_file)
+        .transpose()?;
+    if let Some(file) = &request_file {
+        args.apply_request_file(file);
+    }
+    let recipe = args.recipe.as_deref().map(Recipe::load).transpose()?;
+    if let Some(recipe) = &recipe {
+        args.apply_recipe(recipe);
+    }
+    let source = args
+        .input
+        .clone()
+        .context("An input image is required, with --input or in the request file")?;
+    if let ImageSource::Url(url) = &source {
+        if storage.offline() {
+            return Err(anyhow::Error::new(SiError::Offline)
+                .context(format!("Can't download the input image {url}")));
+        }
+    }
+    let prompts: Vec<String> = args
+        .prompt
+        .take()
+        .into_iter()
+        .chain(args.prompts.drain(..))
+        .collect();
+    if prompts.is_empty() {
+        bail!("At least one prompt is required");
+    }
+    if prompts.len() > 1 && args.grid.is_none() {
+        bail!("Multiple prompts require --grid <path>");
+    }
+    // Parse the template up front so a bad config fails before any work is done
+    let template = config.output_template()?;
+    let mut settings = config
+        .effective(config.resolve_profile_name(profile).as_deref())?
+        .settings;
+    if let Some(recipe) = &recipe {
+        settings.strength = Some(recipe.strength);
+    }
+    if let Some(strength) = request_file.as_ref().and_then(|file| file.strength) {
+        settings.strength = Some(strength);
+    }
+    match args.strength {
+        Some(StrengthArg::Value(strength)) => settings.strength = Some(strength),
+        Some(StrengthArg::Auto) => settings.strength = None,
+        None => {}
+    }
+    let model = args
+        .model
+        .clone()
+        .or(settings.default_model.clone())
+        .unwrap_or_else(|| DEFAULT_MODEL.to_string());
+
+    let from_stdin = source.path() == Some(Path::new(STDIO));
+    let to_stdout = args.output.as_deref() == Some(Path::new(STDIO));
+    // Checked again, as a request file may have brought in the input or output
+    if let Some(conflict) = args.value_conflict() {
+        bail!(conflict);
+    }
+    if from_stdin && args.output.is_none() {
+        bail!("Reading the input from stdin requires --output");
+    }
+    if to_stdout && status.to_stderr {
+        bail!("--json needs stdout for the result; write the image to a file instead of `-`");
+    }
+    let stdout_format = match (&args.format, to_stdout) {
+        (Some(name), true) => Some(stdout_format(name, args.force)?),
+        // Only reachable with the `-` from a request file; clap catches the flag
+        (None, true) => bail!("Writing to stdout requires --format, e.g. png or jpeg"),
+        (Some(name), false) => {
+            formats::ensure_writable(formats::parse_format(name)?)?;
+            None
+        }
+        (None, false) => None,
+    };
+    // Keep stdout clean for the image data or the plan
+    let status = Status {
+        to_stderr: status.to_stderr || to_stdout || args.dry_run,
+    };
+    let input = match source {
+        ImageSource::Path(path) => path,
+        ImageSource::Url(url) => {
+            status.line(format_args!("Downloading input image: {url}"));
+            let dir = image_source::inputs_dir()?;
+            let (path, remote) = image_source::fetch(&url, &dir, &FetchOptions::default()).await?;
+            args.input = Some(ImageSource::Path(path.clone()));
+            args.remote_input = Some(remote);
+            path
+        }
+    };
+
+    let hooks = hook_runner(config, args.no_hooks);
+    let manager = model_manager(config, storage, cancel)?;
+    if args.register {
+        register_local_model(&manager, &model, status)?;
+    }
+    let mut tryon = VirtualTryOn::new(manager)?
+        .with_cancel_token(cancel.clone())
+        .with_thumbnails(config.thumbnails_enabled())
+        .with_styles(style_registry()?)
+        .with_overwrite(!args.no_clobber)
+        .with_coverage_bounds(config.mask_coverage_bounds())
+        .with_strict_mask(args.strict_mask)
+        .with_lenient(args.lenient)
+        .with_strip_icc(args.strip_icc)
+        .with_embedded_metadata(args.embed_metadata || !args.no_embed_metadata)
+        .with_variant(args.variant.clone())
+        .with_skin_protection(skin_protection(args.no_protect_skin, args.skin_sensitivity))
+        .with_texture(texture_overlay(&args.texture)?)
+        .with_timeout(args.timeout)
+        .with_allow_incompatible(args.force)
+        .with_download_missing(args.download_missing);
+    if let Some(quality) = args.quality {
+        tryon = tryon.with_output_quality(quality);
+    }
+    if let Some(path) = &args.mask_debug {
+        tryon = tryon.with_mask_debug(path);
+    }
+    if !args.no_mask_cache {
+        tryon = tryon.with_mask_cache(MaskCache::masks()?);
+    }
+    tryon = tryon.with_hash_cache(HashCache::hashes()?);
+    let progress = Arc::new(ProgressDisplay::new(
+        io::stderr().is_terminal(),
+        !args.no_progress,
+    ));
+    let events = progress.clone();
+    tryon = tryon.with_events(Arc::new(move |event| events.handle(event)));
+    // A dry run reports an invalid input as a warning in the plan instead
+    if !from_stdin && !args.dry_run {
+        tryon.validate_input_image(&input)?;
+    }
+    if config.history_enabled() {
+        tryon = tryon.with_history(History::new(History::default_path()?));
+    }
+
+    status.line(format_args!("Using model: {model}"));
+    status.line(format_args!("Input image: {}", input.display()));
+
+    if let Some(grid_path) = args.grid.clone() {
+        status.line(format_args!(
+            "Generating {} prompts into a grid",
+            prompts.len()
+        ));
+        let request = GridRequest {
+            input_image_path: input,
+            prompts,
+            grid_path,
+            model_name: Some(model.clone()),
+            strength: settings.strength,
+            columns: args.columns,
+            labels: !args.no_labels,
+            individual_dir: args.save_individual.clone(),
+        };
+        check_quality(args.quality, None, &request.grid_path)?;
+        let hook_ctx = HookContext::new(
+            &request.input_image_path,
+            &request.grid_path,
+            &request.prompts.join(" | "),
+            &model,
+        );
+        hooks.run(HookEvent::PreGenerate, &hook_ctx).await?;
+        let result = tryon.try_on_grid(&request).await;
+        progress.finish();
+        let result = result?;
+        let mut output = CommandOutput::new(&result)?;
+        for (prompt, r) in request.prompts.iter().zip(&result.results) {
+            if args.save_individual.is_some() {
+                output.push_line(format_args!(
+                    "  {prompt}: {} ({})",
+                    r.output_path.display(),
+                    format_duration(r.processing_time)
+                ));
+            } else {
+                output.push_line(format_args!(
+                    "  {prompt}: {}",
+                    format_duration(r.processing_time)
+                ));
             }
         }
-        ModelCommands::Download { name } => {
-            let model_info = model_manager.download_model(&name).await?;
-            debug!("Downloaded model: {model_info:?}");
-            println!("Model {name} downloaded successfully.");
+        output.push_line(format_args!("Grid image: {}", result.grid_path.display()));
+        output.push_line(progress.summary(Some(&result.grid_path)));
+        hooks.run(HookEvent::PostGenerate, &hook_ctx).await?;
+        Ok(output)
+    } else {
+        let prompt = prompts.into_iter().next().unwrap_or_default();
+        if !args.dry_run {
+            status.line(format_args!("Generating image with prompt: {prompt}"));
         }
-        ModelCommands::Delete { name } => {
-            println!("Deleting model: {name}");
-            // TODO: Implement model deletion logic
+        let mut request = generate_request(&args, prompt, &model, &settings, &template)?;
+        if let Some(recipe) = &recipe {
+            request = recipe.apply_to(request);
         }
-        ModelCommands::Show { name } => {
-            println!("Showing details for model: {name}");
-            // TODO: Implement model show logic
+        if args.strength == Some(StrengthArg::Auto) {
+            // Left unset, for the suggestion to take its place
+            request.strength = None;
+            tryon = tryon.with_auto_strength(true);
         }
-        ModelCommands::Sync { dry_run } => {
-            let sync_result = model_manager.sync_models(dry_run).await?;
-            if dry_run {
-                println!(
-                    "Dry run completed. Found {} discrepancies.",
-                    sync_result.discrepancies_count()
-                );
+        check_quality(args.quality, stdout_format, &request.output_path)?;
+        if args.dry_run {
+            let plan = tryon.plan(&request)?;
+            return Ok(CommandOutput::new(&plan)?.line(&plan));
+        }
+        let mut hook_ctx = HookContext::new(
+            &request.input_image_path,
+            &request.output_path,
+            &request.clothing_description,
+            &model,
+        );
+        hooks.run(HookEvent::PreGenerate, &hook_ctx).await?;
+        if args.count > 1 {
+            let results = tryon.try_on_variations(&request, args.count as usize).await;
+            progress.finish();
+            let results = results?;
+            let mut output = CommandOutput::new(&results)?;
+            for (i, r) in results.iter().enumerate() {
+                output.push_line(format_args!(
+                    "  variation {} (seed {}, strength {:.2}): {} ({})",
+                    i + 1,
+                    r.seed.unwrap_or_default(),
+                    r.color_strength,
+                    r.output_path.display(),
+                    format_duration(r.processing_time)
+                ));
+                hook_ctx.output = r.output_path.clone();
+                hooks.run(HookEvent::PostGenerate, &hook_ctx).await?;
+            }
+            Ok(output)
+        } else if args.strength_ramp.is_empty() {
+            let recipe_request = args.save_recipe.as_ref().map(|_| request.clone());
+            let result = if from_stdin || to_stdout {
+                let input: Box<dyn Read> = if from_stdin {
+                    Box::new(io::stdin().lock())
+                } else {
+                    Box::new(fs::File::open(&request.input_image_path).with_context(|| {
+                        format!("Failed to open {}", request.input_image_path.display())
+                    })?)
+                };
+                if let Some(format) = stdout_format {
+                    tryon
+                        .try_on_stream(request, input, io::stdout().lock(), format)
+                        .await
+                } else {
+                    // Only write the file once the whole result is encoded
+                    let output_path = request.output_path.clone();
+                    let format = ImageFormat::from_path(&output_path).with_context(|| {
+                        format!(
+                            "Can't tell the output format from {}",
+                            output_path.display()
+                        )
+                    })?;
+                    let mut encoded = Vec::new();
+                    tryon
+                        .try_on_stream(request, input, &mut encoded, format)
+                        .await
+                        .and_then(|result| {
+                            fs::write(&output_path, encoded).with_context(|| {
+                                format!("Failed to write {}", output_path.display())
+                            })?;
+                            Ok(result)
+                        })
+                }
             } else {
-                println!("Sync completed successfully.");
+                tryon.try_on(request).await
+            };
+            progress.finish();
+            let result = result?;
+            for warning in &result.load_warnings {
+                eprintln!("Warning: {}: {warning}", input.display());
+            }
+            if result.icc_dropped {
+                eprintln!("Warning: {}: {ICC_DROPPED}", result.output_path.display());
+            }
+            let mut lines = Vec::new();
+            if let (Some(path), Some(request)) = (&args.save_recipe, &recipe_request) {
+                Recipe::from_result(request, &result).save(path)?;
+                lines.push(format!("Recipe: {}", path.display()));
             }
+            if let Some(comparison_path) = &result.comparison_path {
+                lines.push(format!("Comparison: {}", comparison_path.display()));
+            }
+            if let Some(suggested) = result.suggested_strength {
+                lines.push(format!(
+                    "Strength: auto, suggested {suggested:.2}, applied {:.2} color and {:.2} style",
+                    result.color_strength, result.style_strength
+                ));
+            }
+            if let Some(crop) = result.crop {
+                lines.push(format!(
+                    "Cropped input to {}x{} at {},{}",
+                    crop.width, crop.height, crop.x, crop.y
+                ));
+            }
+            lines.push(format!(
+                "Output image: {} ({})",
+                result.output_path.display(),
+                format_duration(result.processing_time)
+            ));
+            lines.push(progress.summary(Some(&result.output_path)));
+            hooks.run(HookEvent::PostGenerate, &hook_ctx).await?;
 
-            // Display sync results
-            for message in sync_result.messages() {
-                println!("{message}");
+            let mut output = CommandOutput::new(&result)?;
+            for line in lines {
+                // The image data has stdout to itself
+                if to_stdout {
+                    status.line(format_args!("{line}"));
+                } else {
+                    output.push_line(line);
+                }
+            }
+            Ok(output)
+        } else {
+            let results = tryon.try_on_ramp(&request, &args.strength_ramp).await;
+            progress.finish();
+            let results = results?;
+            let mut output = CommandOutput::new(&results)?;
+            for (strength, r) in args.strength_ramp.iter().zip(&results) {
+                output.push_line(format_args!(
+                    "  strength {strength:.2}: {} ({})",
+                    r.output_path.display(),
+                    format_duration(r.processing_time)
+                ));
+                hook_ctx.output = r.output_path.clone();
+                hooks.run(HookEvent::PostGenerate, &hook_ctx).await?;
             }
+            Ok(output)
         }
     }
+}
+
+/// Fail before generating when `quality` is given for an output format without
+/// one: `format` when writing to stdout, else the one `output`'s extension implies
+fn check_quality(quality: Option<u8>, format: Option<ImageFormat>, output: &Path) -> Result<()> {
+    if quality.is_none() {
+        return Ok(());
+    }
+    let format = match format {
+        Some(format) => format,
+        None => ImageFormat::from_path(output)
+            .with_context(|| format!("Can't tell the output format from {}", output.display()))?,
+    };
+    if !formats::supports_quality(format) {
+        bail!("--quality only applies to JPEG, WebP and AVIF output, not {format:?}");
+    }
     Ok(())
 }
 
-fn handle_config_command(action: ConfigCommands) -> Result<()> {
-    match action {
-        ConfigCommands::Show => {
-            println!("Showing current configuration...");
-            // TODO: Implement config show logic
-        }
-        ConfigCommands::Set { key, value } => {
-            println!("Setting config: {key} = {value}");
-            // TODO: Implement config set logic
+/// The single-prompt request `args` ask for, with the model, strength and
+/// output name resolved from the profile `settings` and `template`
+///
+/// Both generating and `--dry-run` go through here, so the plan shows exactly
+/// what would be generated.
+fn generate_request(
+    args: &GenerateArgs,
+    prompt: String,
+    model: &str,
+    settings: &Profile,
+    template: &OutputTemplate,
+) -> Result<TryOnRequest> {
+    let input = args
+        .input
+        .as_ref()
+        .and_then(ImageSource::path)
+        .map(Path::to_path_buf)
+        .context("An input image is required, with --input or in the request file")?;
+    let output = args.output.clone().unwrap_or_else(|| {
+        let mut ctx = TemplateContext::new(&input, &prompt, model);
+        if let Some(strength) = settings.strength {
+            ctx.strength = strength;
         }
-        ConfigCommands::Get { key } => {
-            println!("Getting config value for: {key}");
-            // TODO: Implement config get logic
-        }
-        ConfigCommands::Reset => {
-            println!("Resetting configuration to defaults...");
-            // TODO: Implement config reset logic
+        if let Some(format) = args.format.as_ref().or(settings.output_format.as_ref()) {
+            ctx.extension = format.clone();
         }
-    }
-    Ok(())
+        let base_dir = input.parent().unwrap_or(Path::new(""));
+        template.render_available(base_dir, &ctx)
+    });
+    Ok(TryOnRequest {
+        input_image_path: input,
+        clothing_description: prompt,
+        negative_prompt: args.negative_prompt.clone(),
+        output_path: output,
+        model_name: Some(model.to_string()),
+        strength: settings.strength,
+        color_strength: args.color_strength,
+        style_strength: args.style_strength,
+        reference_image: args.reference.clone(),
+        crop: match args.crop {
+            Some(spec) => Some(Crop::Region(spec)),
+            None if args.auto_center => Some(Crop::AutoCenter {
+                margin: args.auto_center_margin,
+            }),
+            None => None,
+        },
+        mask: args.polygon.clone().map(|polygon| RegionMask {
+            polygon,
+            mode: args.mask_mode.unwrap_or_default(),
+        }),
+        transform_plan: None,
+        remote_input: args.remote_input.clone(),
+        comparison: args.comparison.clone().map(|path| {
+            ComparisonRequest::new(path)
+                .with_layout(args.comparison_layout.unwrap_or_default())
+                .with_labels(args.comparison_labels)
+        }),
+        seed: args.seed,
+    })
 }
 
-fn handle_image_command(action: ImageCommands) -> Result<()> {
+async fn handle_history_command(
+    action: HistoryCommands,
+    history: &History,
+    config: &Config,
+    storage: &StorageArgs,
+    cancel: &CancelToken,
+) -> Result<CommandOutput> {
     match action {
-        ImageCommands::Generate {
-            prompt,
-            model,
-            input,
+        HistoryCommands::List { limit } => {
+            let entries = history.list(limit)?;
+            let mut output = CommandOutput::new(&entries)?;
+            if entries.is_empty() {
+                output.push_line("No history entries.");
+            }
+            for entry in &entries {
+                output.push_line(format_args!(
+                    "{:>4}  {}  {}  \"{}\" -> {} ({}){}",
+                    entry.id,
+                    entry.timestamp.format("%Y-%m-%d %H:%M:%S"),
+                    entry.model,
+                    entry.prompt,
+                    entry.output.display(),
+                    format_duration(Duration::from_millis(entry.duration_ms)),
+                    if entry.success { "" } else { " [failed]" }
+                ));
+            }
+            Ok(output)
+        }
+        HistoryCommands::Rerun {
+            id,
             output,
+            no_hooks,
         } => {
-            println!("Generating image with prompt: {prompt}");
-            println!("Using model: {model}");
-            println!("Input image: {}", input.display());
-            println!("Output image: {}", output.display());
-            // TODO: Implement image generation logic
+            let entry = history
+                .get(id)?
+                .with_context(|| format!("No history entry with id {id}"))?;
+            let request = entry.to_request(output);
+            let hooks = hook_runner(config, no_hooks);
+            let hook_ctx = HookContext::new(
+                &request.input_image_path,
+                &request.output_path,
+                &request.clothing_description,
+                &entry.model,
+            );
+
+            let mut tryon = VirtualTryOn::new(model_manager(config, storage, cancel)?)?
+                .with_cancel_token(cancel.clone())
+                .with_thumbnails(config.thumbnails_enabled())
+                .with_styles(style_registry()?)
+                .with_coverage_bounds(config.mask_coverage_bounds())
+                .with_hash_cache(HashCache::hashes()?);
+            if config.history_enabled() {
+                tryon = tryon.with_history(history.clone());
+            }
+            hooks.run(HookEvent::PreGenerate, &hook_ctx).await?;
+            let result = tryon.try_on(request).await?;
+            hooks.run(HookEvent::PostGenerate, &hook_ctx).await?;
+            Ok(CommandOutput::new(&result)?.line(format_args!(
+                "Re-ran entry {id}: {} ({})",
+                result.output_path.display(),
+                format_duration(result.processing_time)
+            )))
         }
     }
-    Ok(())
 }
 
 trait LogError<T> {
@@ -227,76 +3620,581 @@ mod tests {
 
     #[test]
     fn test_handle_config_show() {
+        let temp_dir = tempdir().unwrap();
         let action = ConfigCommands::Show;
-        let result = handle_config_command(action);
+        let result = handle_config_command(
+            action,
+            &temp_dir.path().join("config.toml"),
+            Status::default(),
+        );
         assert!(result.is_ok());
     }
 
     #[test]
     fn test_handle_config_set() {
+        let temp_dir = tempdir().unwrap();
+        let config_path = temp_dir.path().join("config.toml");
         let action = ConfigCommands::Set {
             key: "test_key".to_string(),
             value: "test_value".to_string(),
         };
-        let result = handle_config_command(action);
+        let result = handle_config_command(action, &config_path, Status::default());
         assert!(result.is_ok());
+        assert_eq!(
+            Config::load(&config_path)
+                .unwrap()
+                .get("test_key")
+                .as_deref(),
+            Some("test_value")
+        );
+    }
+
+    #[test]
+    fn test_handle_config_set_invalid_output_template() {
+        let temp_dir = tempdir().unwrap();
+        let action = ConfigCommands::Set {
+            key: "output_template".to_string(),
+            value: "{bogus}.png".to_string(),
+        };
+        let result = handle_config_command(
+            action,
+            &temp_dir.path().join("config.toml"),
+            Status::default(),
+        );
+        assert!(result.is_err());
     }
 
     #[test]
     fn test_handle_config_get() {
+        let temp_dir = tempdir().unwrap();
         let action = ConfigCommands::Get {
             key: "test_key".to_string(),
         };
-        let result = handle_config_command(action);
+        let result = handle_config_command(
+            action,
+            &temp_dir.path().join("config.toml"),
+            Status::default(),
+        );
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn test_config_get_renders_text_and_json() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let config_path = temp_dir.path().join("config.toml");
+        let get = |key: &str| {
+            let action = ConfigCommands::Get {
+                key: key.to_string(),
+            };
+            handle_config_command(action, &config_path, Status::default())
+        };
+        let set = ConfigCommands::Set {
+            key: "thumbnails_enabled".to_string(),
+            value: "false".to_string(),
+        };
+        handle_config_command(set, &config_path, Status::default())?;
+
+        let output = get("thumbnails_enabled")?;
+        assert_eq!(output.text(), "false\n");
+        assert_eq!(
+            output.json(),
+            &json!({ "key": "thumbnails_enabled", "value": "false" })
+        );
+        let output = get("hf_endpoint")?;
+        assert_eq!(output.text(), "hf_endpoint is not set\n");
+        assert_eq!(
+            output.json(),
+            &json!({ "key": "hf_endpoint", "value": null })
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_handle_config_reset() {
+        let temp_dir = tempdir().unwrap();
+        let config_path = temp_dir.path().join("config.toml");
+        fs::write(&config_path, "output_template = \"{stem}.png\"\n").unwrap();
+
         let action = ConfigCommands::Reset;
-        let result = handle_config_command(action);
+        let result = handle_config_command(action, &config_path, Status::default());
         assert!(result.is_ok());
+        assert!(!config_path.exists());
     }
 
     #[test]
-    fn test_handle_image_generate() {
+    fn test_config_validate_reports_problems_and_fails() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let config_path = temp_dir.path().join("config.toml");
+        let validate =
+            || handle_config_command(ConfigCommands::Validate, &config_path, Status::default());
+
+        let output = validate()?;
+        assert!(output.text().starts_with("No config file at"));
+
+        fs::write(&config_path, "strength = 0.5\n")?;
+        let output = validate()?;
+        assert_eq!(output.json()["problems"], json!([]));
+        output.print(false)?;
+
+        fs::write(&config_path, "strenght = 0.5\nsteps = \"many\"\n")?;
+        let output = validate()?;
+        assert_eq!(output.json()["problems"].as_array().unwrap().len(), 2);
+        assert!(
+            output.text().contains("line 2: `steps`"),
+            "{}",
+            output.text()
+        );
+        let err = output.print(false).unwrap_err();
+        assert!(err.to_string().starts_with("Found 2 problems"), "{err}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_config_schema_lists_every_key() -> Result<()> {
+        let output = handle_config_command(
+            ConfigCommands::Schema,
+            Path::new("unused"),
+            Status::default(),
+        )?;
+        let keys = output.json()["keys"].as_array().unwrap();
+        assert_eq!(keys.len(), config_schema::SCHEMA.len());
+        assert!(
+            keys.iter()
+                .any(|k| k["key"] == "strength" && k["profile"] == true)
+        );
+        assert!(
+            output
+                .text()
+                .contains("mask_coverage_min (number (0.0 to 1.0), default 0.05)")
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_handle_image_generate_missing_input() {
         let temp_dir = tempdir().unwrap();
         let input_path = temp_dir.path().join("input.jpg");
         let output_path = temp_dir.path().join("output.png");
 
-        let action = ImageCommands::Generate {
-            prompt: "A beautiful sunset".to_string(),
-            model: "test-model".to_string(),
-            input: input_path,
-            output: output_path,
+        let action = ImageCommands::Generate(Box::new(GenerateArgs {
+            prompt: Some("A beautiful sunset".to_string()),
+            model: Some("test-model".to_string()),
+            input: Some(ImageSource::from(input_path)),
+            output: Some(output_path.clone()),
+            ..Default::default()
+        }));
+
+        let result = handle_image_command(
+            action,
+            &temp_dir.path().join("config.toml"),
+            None,
+            &StorageArgs::default(),
+            Status::default(),
+            &CancelToken::new(),
+        )
+        .await;
+        assert!(result.is_err());
+        assert!(!output_path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_handle_image_generate_multiple_prompts_require_grid() {
+        let temp_dir = tempdir().unwrap();
+        let action = ImageCommands::Generate(Box::new(GenerateArgs {
+            prompts: vec!["red dress".to_string(), "blue dress".to_string()],
+            input: Some(ImageSource::from(temp_dir.path().join("input.png"))),
+            ..Default::default()
+        }));
+
+        let err = handle_image_command(
+            action,
+            &temp_dir.path().join("config.toml"),
+            None,
+            &StorageArgs::default(),
+            Status::default(),
+            &CancelToken::new(),
+        )
+        .await
+        .unwrap_err();
+        assert!(err.to_string().contains("--grid"));
+    }
+
+    #[tokio::test]
+    async fn test_handle_image_generate_rejects_bad_template() {
+        let temp_dir = tempdir().unwrap();
+        let config_path = temp_dir.path().join("config.toml");
+        fs::write(&config_path, "output_template = \"{nope}.png\"\n").unwrap();
+
+        let action = ImageCommands::Generate(Box::new(GenerateArgs {
+            prompt: Some("A beautiful sunset".to_string()),
+            model: Some("test-model".to_string()),
+            input: Some(ImageSource::from(temp_dir.path().join("input.jpg"))),
+            ..Default::default()
+        }));
+
+        assert!(
+            handle_image_command(
+                action,
+                &config_path,
+                None,
+                &StorageArgs::default(),
+                Status::default(),
+                &CancelToken::new(),
+            )
+            .await
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_request_file_fills_unset_flags() -> Result<()> {
+        let file = RequestOverrides {
+            input_image_path: Some(PathBuf::from("jobs/me.png")),
+            clothing_description: Some("red shirt".to_string()),
+            model_name: Some("org/file".to_string()),
+            crop: Some(Crop::AutoCenter { margin: 0.3 }),
+            quality: Some(80),
+            overwrite: Some(false),
+            seed: Some(42),
+            ..Default::default()
+        };
+        let mut args = GenerateArgs {
+            prompt: Some("blue shirt".to_string()),
+            model: Some("org/flag".to_string()),
+            ..Default::default()
+        };
+        args.apply_request_file(&file);
+
+        assert_eq!(
+            args.input.as_ref().and_then(ImageSource::path),
+            Some(Path::new("jobs/me.png"))
+        );
+        assert_eq!(args.prompt.as_deref(), Some("blue shirt"));
+        assert_eq!(args.model.as_deref(), Some("org/flag"));
+        assert!(args.auto_center && args.auto_center_margin == 0.3);
+        assert_eq!(args.quality, Some(80));
+        assert_eq!(args.seed, Some(42));
+        assert!(args.no_clobber);
+        Ok(())
+    }
+
+    #[test]
+    fn test_recipe_fills_what_flags_and_request_file_leave_unset() -> Result<()> {
+        let recipe = Recipe {
+            schema_version: si::recipe::RECIPE_SCHEMA_VERSION,
+            model: "org/recipe".to_string(),
+            prompt: "red shirt".to_string(),
+            negative_prompt: None,
+            strength: 0.7,
+            color_strength: 0.6,
+            style_strength: 0.5,
+            transform_plan: Default::default(),
+            crop: None,
+            mask: Some(RegionMask {
+                polygon: "0,0 10,0 0,10".parse()?,
+                mode: MaskMode::Polygon,
+            }),
+        };
+        let mut args = GenerateArgs {
+            model: Some("org/flag".to_string()),
+            style_strength: Some(0.2),
+            ..Default::default()
+        };
+        args.apply_recipe(&recipe);
+
+        assert_eq!(args.prompt.as_deref(), Some("red shirt"));
+        assert_eq!(args.model.as_deref(), Some("org/flag"));
+        assert_eq!(args.color_strength, Some(0.6));
+        assert_eq!(args.style_strength, Some(0.2));
+        assert_eq!(args.mask_mode, Some(MaskMode::Polygon));
+        Ok(())
+    }
+
+    #[test]
+    fn test_generate_request_applies_overrides() -> Result<()> {
+        let mut config = Config::default();
+        config.set("output_template", "{stem}_{strength}.{ext}")?;
+        config.set("profiles.quality.strength", "0.8")?;
+        config.set("profiles.quality.output_format", "jpg")?;
+        let settings = config.effective(Some("quality"))?.settings;
+        let args = GenerateArgs {
+            input: Some(ImageSource::from(PathBuf::from("photos/me.png"))),
+            auto_center: true,
+            auto_center_margin: 0.2,
+            ..Default::default()
+        };
+
+        let request = generate_request(
+            &args,
+            "red shirt".to_string(),
+            "org/model",
+            &settings,
+            &config.output_template()?,
+        )?;
+        assert_eq!(request.model(), "org/model");
+        assert_eq!(request.strength, Some(0.8));
+        assert_eq!(request.output_path, Path::new("photos/me_0.80.jpg"));
+        assert!(matches!(request.crop, Some(Crop::AutoCenter { margin }) if margin == 0.2));
+
+        let args = GenerateArgs {
+            output: Some(PathBuf::from("out.png")),
+            ..args
+        };
+        let request = generate_request(
+            &args,
+            "red shirt".to_string(),
+            "org/model",
+            &settings,
+            &config.output_template()?,
+        )?;
+        assert_eq!(request.output_path, Path::new("out.png"));
+        assert!(request.mask.is_none());
+
+        let args = GenerateArgs {
+            polygon: Some("0,0 10,0 0,10".parse()?),
+            ..args
+        };
+        let request = generate_request(
+            &args,
+            "red shirt".to_string(),
+            "org/model",
+            &settings,
+            &config.output_template()?,
+        )?;
+        assert_eq!(request.mask.map(|m| m.mode), Some(MaskMode::Intersect));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_handle_image_generate_dry_run_writes_nothing() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let input_path = temp_dir.path().join("input.png");
+        image::RgbImage::from_pixel(64, 64, image::Rgb([100, 80, 120])).save(&input_path)?;
+        let output_path = temp_dir.path().join("out/output.png");
+
+        let action = ImageCommands::Generate(Box::new(GenerateArgs {
+            prompt: Some("red shirt".to_string()),
+            model: Some("test-model".to_string()),
+            input: Some(ImageSource::from(input_path)),
+            output: Some(output_path.clone()),
+            dry_run: true,
+            ..Default::default()
+        }));
+        let plan = handle_image_command(
+            action,
+            &temp_dir.path().join("config.toml"),
+            None,
+            &StorageArgs {
+                models_dir: Some(temp_dir.path().join("models")),
+                ..Default::default()
+            },
+            Status::default(),
+            &CancelToken::new(),
+        )
+        .await?;
+        assert_eq!(plan.json()["output"], json!(output_path));
+        assert_eq!(plan.json()["model_downloaded"], json!(false));
+        assert!(!output_path.exists());
+        assert!(!temp_dir.path().join("out").exists());
+        assert!(!temp_dir.path().join("models").exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_handle_image_generate_quality_needs_lossy_output() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let input_path = temp_dir.path().join("input.png");
+        image::RgbImage::from_pixel(64, 64, image::Rgb([100, 80, 120])).save(&input_path)?;
+        let output_path = temp_dir.path().join("output.png");
+
+        let action = ImageCommands::Generate(Box::new(GenerateArgs {
+            prompt: Some("red shirt".to_string()),
+            input: Some(ImageSource::from(input_path)),
+            output: Some(output_path.clone()),
+            quality: Some(80),
+            ..Default::default()
+        }));
+        let err = handle_image_command(
+            action,
+            &temp_dir.path().join("config.toml"),
+            None,
+            &StorageArgs::default(),
+            Status::default(),
+            &CancelToken::new(),
+        )
+        .await
+        .unwrap_err();
+        assert!(
+            err.to_string().contains("--quality only applies"),
+            "{err:#}"
+        );
+        assert!(!output_path.exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_handle_image_generate_unknown_profile() {
+        let temp_dir = tempdir().unwrap();
+        let action = ImageCommands::Generate(Box::new(GenerateArgs {
+            prompt: Some("A beautiful sunset".to_string()),
+            input: Some(ImageSource::from(temp_dir.path().join("input.jpg"))),
+            ..Default::default()
+        }));
+
+        let err = handle_image_command(
+            action,
+            &temp_dir.path().join("config.toml"),
+            Some("nope"),
+            &StorageArgs::default(),
+            Status::default(),
+            &CancelToken::new(),
+        )
+        .await
+        .unwrap_err();
+        assert!(err.to_string().contains("Unknown profile"));
+    }
+
+    #[test]
+    fn test_handle_profile_create_and_delete() {
+        let temp_dir = tempdir().unwrap();
+        let config_path = temp_dir.path().join("config.toml");
+
+        let action = ProfileCommands::Create {
+            name: "quality".to_string(),
+            default_model: None,
+            strength: Some(0.8),
+            steps: Some(50),
+            max_size: None,
+            output_format: None,
         };
+        handle_config_command(
+            ConfigCommands::Profile { action },
+            &config_path,
+            Status::default(),
+        )
+        .unwrap();
+        let config = Config::load(&config_path).unwrap();
+        assert_eq!(config.get("profiles.quality.steps").as_deref(), Some("50"));
 
-        let result = handle_image_command(action);
-        assert!(result.is_ok());
+        let action = ProfileCommands::Delete {
+            name: "quality".to_string(),
+        };
+        handle_config_command(
+            ConfigCommands::Profile { action },
+            &config_path,
+            Status::default(),
+        )
+        .unwrap();
+        assert!(Config::load(&config_path).unwrap().profiles.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_handle_history_list() {
+        let temp_dir = tempdir().unwrap();
+        let history = History::new(temp_dir.path().join("history.jsonl"));
+        let action = HistoryCommands::List { limit: Some(5) };
+
+        let result = handle_history_command(
+            action,
+            &history,
+            &Config::default(),
+            &StorageArgs::default(),
+            &CancelToken::new(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(result.json(), &json!([]));
+        assert_eq!(result.text(), "No history entries.\n");
+    }
+
+    #[tokio::test]
+    async fn test_handle_history_rerun_unknown_id() {
+        let temp_dir = tempdir().unwrap();
+        let history = History::new(temp_dir.path().join("history.jsonl"));
+        let action = HistoryCommands::Rerun {
+            id: 42,
+            output: None,
+            no_hooks: false,
+        };
+
+        let result = handle_history_command(
+            action,
+            &history,
+            &Config::default(),
+            &StorageArgs::default(),
+            &CancelToken::new(),
+        )
+        .await;
+        assert!(result.is_err());
     }
 
     #[test]
     fn test_cli_parsing() {
         // Test that the CLI can be parsed (this tests the derive macros)
-        use clap::CommandFactory;
         let _cmd = Cli::command();
     }
 
     #[test]
     fn test_model_commands_variants() {
         // Test all ModelCommands variants can be created
-        let _list = ModelCommands::List;
+        let _list = ModelCommands::List(ListArgs::default());
         let _download = ModelCommands::Download {
-            name: "test".to_string(),
+            name: Some("test".parse().unwrap()),
+            manifest: None,
+            parallel: 1,
+            continue_on_error: false,
+            include: vec![],
+            no_docs: false,
+            dry_run: false,
+            no_wait: false,
+            keep_old: false,
+            variant: None,
+            limit_rate: None,
+            queue: false,
+        };
+        let _import = ModelCommands::Import {
+            path: PathBuf::from("models"),
+            recursive: true,
+            copy: false,
+            as_id: None,
         };
         let _delete = ModelCommands::Delete {
-            name: "test".to_string(),
+            name: Some("test".to_string()),
+            all: false,
+            filter: None,
+            dry_run: false,
         };
         let _show = ModelCommands::Show {
             name: "test".to_string(),
+            readme: false,
+            tensors: false,
+            config: false,
+        };
+        let _open = ModelCommands::Open {
+            name: "test".to_string(),
+            print: true,
+        };
+        let _refresh = ModelCommands::Refresh { name: None };
+        let _pin = ModelCommands::Pin {
+            name: "test".to_string(),
+        };
+        let _gc = ModelCommands::Gc {
+            dry_run: true,
+            force: false,
+        };
+        let _evict = ModelCommands::Evict { dry_run: true };
+        let _repair = ModelCommands::Repair { dry_run: true };
+        let _queue = ModelCommands::Queue {
+            action: QueueCommands::Run { max: Some(2) },
+        };
+        let _sync = ModelCommands::Sync {
+            dry_run: false,
+            diff: false,
+        };
+        let _sync_dry = ModelCommands::Sync {
+            dry_run: true,
+            diff: true,
         };
-        let _sync = ModelCommands::Sync { dry_run: false };
-        let _sync_dry = ModelCommands::Sync { dry_run: true };
     }
 
     #[test]
@@ -316,29 +4214,41 @@ mod tests {
     #[test]
     fn test_image_commands_variants() {
         // Test all ImageCommands variants can be created
-        let _generate = ImageCommands::Generate {
-            prompt: "test".to_string(),
-            model: "model".to_string(),
-            input: PathBuf::from("input.jpg"),
-            output: PathBuf::from("output.png"),
-        };
+        let _generate = ImageCommands::Generate(Box::new(GenerateArgs {
+            prompt: Some("test".to_string()),
+            model: Some("model".to_string()),
+            input: Some(ImageSource::from(PathBuf::from("input.jpg"))),
+            output: Some(PathBuf::from("output.png")),
+            ..Default::default()
+        }));
     }
 
     #[test]
     fn test_commands_variants() {
         // Test all Commands variants can be created
         let _model = Commands::Model {
-            action: ModelCommands::List,
+            action: ModelCommands::List(ListArgs::default()),
         };
         let _config = Commands::Config {
             action: ConfigCommands::Show,
         };
         let _image = Commands::Image {
-            action: ImageCommands::Generate {
-                prompt: "test".to_string(),
-                model: "model".to_string(),
-                input: PathBuf::from("input.jpg"),
-                output: PathBuf::from("output.png"),
+            action: ImageCommands::Generate(Box::new(GenerateArgs {
+                prompt: Some("test".to_string()),
+                model: Some("model".to_string()),
+                input: Some(ImageSource::from(PathBuf::from("input.jpg"))),
+                output: Some(PathBuf::from("output.png")),
+                ..Default::default()
+            })),
+        };
+        let _init = Commands::Init(InitArgs {
+            yes: true,
+            ..Default::default()
+        });
+        let _stats = Commands::Stats(StatsArgs::default());
+        let _cache = Commands::Cache {
+            action: CacheCommands::Clear {
+                cache: CacheKind::Masks,
             },
         };
     }
@@ -0,0 +1,408 @@
+//! Downloading many models at once from a manifest
+//!
+//! A manifest is either plain text with one [`ModelSpec`] per line (`#` starts a
+//! comment) or a JSON array of the same strings:
+//!
+//! ```text
+//! # lab machines
+//! org/base-model
+//! org/finetune@v1.2   # pinned to a tag
+//! ```
+
+use std::{
+    fmt,
+    str::FromStr,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use anyhow::{Context, Result, bail};
+use futures_util::{StreamExt, stream};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    journal::IndexTransaction,
+    model_id::ModelId,
+    models::{DownloadOptions, ModelManager},
+};
+
+/// A model to download, optionally at a specific revision
+///
+/// Parsed from `org/repo` or `org/repo@revision`, where `org/repo` is anything
+/// [`ModelId`] reads, such as a model page URL.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModelSpec {
+    pub id: ModelId,
+    /// Branch, tag or commit; `None` means the default branch
+    pub revision: Option<String>,
+}
+
+impl ModelSpec {
+    pub fn new(id: ModelId) -> Self {
+        Self { id, revision: None }
+    }
+
+    pub fn with_revision(mut self, revision: impl Into<String>) -> Self {
+        self.revision = Some(revision.into());
+        self
+    }
+
+    /// Parse a manifest, either a JSON array of specs or one spec per line
+    ///
+    /// Blank lines and `#` comments are ignored. Errors name the offending line.
+    pub fn parse_manifest(text: &str) -> Result<Vec<ModelSpec>> {
+        if text.trim_start().starts_with('[') {
+            let entries: Vec<String> =
+                serde_json::from_str(text).context("Invalid JSON manifest")?;
+            return entries
+                .iter()
+                .enumerate()
+                .map(|(i, entry)| {
+                    entry
+                        .parse()
+                        .with_context(|| format!("Manifest entry {}", i + 1))
+                })
+                .collect();
+        }
+
+        let mut specs = Vec::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.split_once('#').map_or(line, |(spec, _)| spec).trim();
+            if line.is_empty() {
+                continue;
+            }
+            specs.push(
+                line.parse()
+                    .with_context(|| format!("Manifest line {}", n + 1))?,
+            );
+        }
+        Ok(specs)
+    }
+}
+
+impl FromStr for ModelSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let (id, revision) = match s.split_once('@') {
+            Some((id, revision)) => (id, Some(revision)),
+            None => (s, None),
+        };
+        let id: ModelId = id.parse()?;
+        if let Some(revision) = revision {
+            if revision.is_empty() || revision.contains(['@', ' ', '\t']) {
+                bail!("Invalid revision `{revision}` for {id}");
+            }
+        }
+        Ok(Self {
+            id,
+            revision: revision.map(str::to_string),
+        })
+    }
+}
+
+impl fmt::Display for ModelSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.id)?;
+        if let Some(revision) = &self.revision {
+            write!(f, "@{revision}")?;
+        }
+        Ok(())
+    }
+}
+
+/// How [`ModelManager::download_many`] runs
+#[derive(Debug, Clone)]
+pub struct BulkDownloadOptions {
+    /// Downloads running at once; at least 1
+    pub parallel: usize,
+    /// Keep going after a failure instead of starting no further downloads
+    pub continue_on_error: bool,
+    /// Applied to every model; its `revision` is overridden by each spec's
+    pub download: DownloadOptions,
+}
+
+impl Default for BulkDownloadOptions {
+    fn default() -> Self {
+        Self {
+            parallel: 1,
+            continue_on_error: false,
+            download: DownloadOptions::default(),
+        }
+    }
+}
+
+/// What happened to one model of a bulk download
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "status", content = "detail", rename_all = "snake_case")]
+pub enum BulkOutcome {
+    /// Downloaded, with the number of bytes the model takes
+    Downloaded(u64),
+    /// Already in the index, so nothing was fetched
+    AlreadyPresent,
+    Failed(String),
+    /// Not started because an earlier download failed
+    NotAttempted,
+}
+
+/// Outcome of [`ModelManager::download_many`], in manifest order
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct BulkDownloadReport {
+    pub results: Vec<(ModelSpec, BulkOutcome)>,
+}
+
+impl BulkDownloadReport {
+    fn count(&self, matches: impl Fn(&BulkOutcome) -> bool) -> usize {
+        self.results
+            .iter()
+            .filter(|(_, outcome)| matches(outcome))
+            .count()
+    }
+
+    pub fn succeeded(&self) -> usize {
+        self.count(|o| matches!(o, BulkOutcome::Downloaded(_)))
+    }
+
+    pub fn already_present(&self) -> usize {
+        self.count(|o| *o == BulkOutcome::AlreadyPresent)
+    }
+
+    pub fn failed(&self) -> usize {
+        self.count(|o| matches!(o, BulkOutcome::Failed(_)))
+    }
+
+    pub fn not_attempted(&self) -> usize {
+        self.count(|o| *o == BulkOutcome::NotAttempted)
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.failed() == 0 && self.not_attempted() == 0
+    }
+}
+
+impl ModelManager {
+    /// Download every model in `specs`, up to `options.parallel` at a time
+    ///
+    /// Models already in the index are skipped unless their spec asks for a
+    /// specific revision. Unless `continue_on_error` is set, the first failure
+    /// stops further downloads from starting; the ones already running finish.
+    /// Individual failures are reported, not returned as errors.
+    ///
+    /// The downloaded models are indexed together once all downloads are done,
+    /// in a single write of the index.
+    pub async fn download_many(
+        &self,
+        specs: &[ModelSpec],
+        options: &BulkDownloadOptions,
+    ) -> Result<BulkDownloadReport> {
+        let aborted = AtomicBool::new(false);
+        let txn = self.model_index().start_transaction();
+        let results = stream::iter(specs)
+            .map(|spec| async {
+                let outcome = self.download_spec(spec, options, &aborted, &txn).await;
+                (spec.clone(), outcome)
+            })
+            .buffered(options.parallel.max(1))
+            .collect()
+            .await;
+        txn.commit()
+            .context("Failed to add the downloaded models to the index")?;
+        Ok(BulkDownloadReport { results })
+    }
+
+    async fn download_spec(
+        &self,
+        spec: &ModelSpec,
+        options: &BulkDownloadOptions,
+        aborted: &AtomicBool,
+        txn: &IndexTransaction,
+    ) -> BulkOutcome {
+        if aborted.load(Ordering::Relaxed) {
+            return BulkOutcome::NotAttempted;
+        }
+        if spec.revision.is_none() {
+            match self.get_model_async(&spec.id).await {
+                Ok(Some(_)) => return BulkOutcome::AlreadyPresent,
+                Ok(None) => {}
+                Err(e) => return BulkOutcome::Failed(format!("{e:#}")),
+            }
+        }
+
+        let download = DownloadOptions {
+            revision: spec.revision.clone(),
+            ..options.download.clone()
+        };
+        let downloaded = async {
+            let plan = self.plan_download(&spec.id, &download).await?;
+            self.download_planned_in(&plan, download.lock_wait, Some(txn), Arc::new(|_| {}))
+                .await
+        };
+        match downloaded.await {
+            Ok(report) => BulkOutcome::Downloaded(report.model.total_size()),
+            Err(e) => {
+                warn!("Download of {spec} failed: {e:#}");
+                if !options.continue_on_error {
+                    aborted.store(true, Ordering::Relaxed);
+                }
+                BulkOutcome::Failed(format!("{e:#}"))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MockSource, ModelManagerBuilder};
+    use std::{fs, path::Path, sync::Arc};
+    use tempfile::tempdir;
+
+    fn spec(id: &str) -> ModelSpec {
+        ModelSpec::new(id.parse().expect("valid model id"))
+    }
+
+    fn mock_setup(root: &Path, repos: &[&str]) -> Result<(ModelManager, Arc<MockSource>)> {
+        for repo in repos {
+            let dir = root.join("fixtures").join(repo);
+            fs::create_dir_all(&dir)?;
+            fs::write(dir.join("model.bin"), "weights")?;
+        }
+        let source = Arc::new(MockSource::new(root.join("fixtures"), root.join("cache")));
+        let manager = ModelManagerBuilder::new()
+            .with_models_dir(root.join("models"))
+            .with_source(Box::new(source.clone()))
+            .build()?;
+        Ok((manager, source))
+    }
+
+    #[test]
+    fn test_parse_spec() -> Result<()> {
+        assert_eq!("org/repo".parse::<ModelSpec>()?, spec("org/repo"));
+        assert_eq!(
+            "  org/repo@v1.0 ".parse::<ModelSpec>()?,
+            spec("org/repo").with_revision("v1.0")
+        );
+        assert_eq!("gpt2".parse::<ModelSpec>()?, spec("gpt2"));
+        for invalid in [
+            "",
+            "org/",
+            "/repo",
+            "a/b/c",
+            "org/re po",
+            "org/repo@",
+            "a@b@c",
+        ] {
+            assert!(invalid.parse::<ModelSpec>().is_err(), "{invalid:?}");
+        }
+        let spec = spec("org/repo").with_revision("main");
+        assert_eq!(spec.to_string().parse::<ModelSpec>()?, spec);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_text_manifest() -> Result<()> {
+        let manifest = "# lab machines\n\norg/one\n  org/two@abc123  # pinned\n\t\n";
+        assert_eq!(
+            ModelSpec::parse_manifest(manifest)?,
+            [spec("org/one"), spec("org/two").with_revision("abc123"),]
+        );
+        assert!(ModelSpec::parse_manifest("# nothing\n")?.is_empty());
+
+        let err = ModelSpec::parse_manifest("org/one\n# ok\norg/bad name\n").unwrap_err();
+        assert!(format!("{err:#}").contains("line 3"), "{err:#}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_json_manifest() -> Result<()> {
+        assert_eq!(
+            ModelSpec::parse_manifest(r#"["org/one", "org/two@v2"]"#)?,
+            [spec("org/one"), spec("org/two").with_revision("v2"),]
+        );
+        let err = ModelSpec::parse_manifest(r#"["org/one", "a/b/c"]"#).unwrap_err();
+        assert!(format!("{err:#}").contains("entry 2"), "{err:#}");
+        assert!(ModelSpec::parse_manifest("[1, 2]").is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_download_many_writes_the_index_once() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let repos: Vec<_> = (0..50).map(|i| format!("org/model-{i}")).collect();
+        let repos: Vec<_> = repos.iter().map(String::as_str).collect();
+        let (manager, _) = mock_setup(temp_dir.path(), &repos)?;
+
+        let specs: Vec<_> = repos.iter().map(|id| spec(id)).collect();
+        let options = BulkDownloadOptions {
+            parallel: 4,
+            ..Default::default()
+        };
+        let report = manager.download_many(&specs, &options).await?;
+        assert!(report.is_ok());
+        assert_eq!(manager.index_cache.saves(), 1);
+        assert_eq!(manager.list_models()?.len(), 50);
+        assert!(!manager.model_index().journal_path().exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_download_many_skips_present_and_continues() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let (manager, source) = mock_setup(temp_dir.path(), &["org/one", "org/two"])?;
+        manager.download_model("org/one").await?;
+
+        let specs = ModelSpec::parse_manifest("org/one\norg/missing\norg/two@v2\n")?;
+        let options = BulkDownloadOptions {
+            parallel: 2,
+            continue_on_error: true,
+            ..Default::default()
+        };
+        let report = manager.download_many(&specs, &options).await?;
+
+        assert_eq!(report.results.len(), 3);
+        assert_eq!(report.results[0].1, BulkOutcome::AlreadyPresent);
+        assert!(matches!(report.results[1].1, BulkOutcome::Failed(_)));
+        assert_eq!(report.results[2].1, BulkOutcome::Downloaded(7));
+        assert_eq!(
+            (
+                report.succeeded(),
+                report.already_present(),
+                report.failed()
+            ),
+            (1, 1, 1)
+        );
+        assert!(!report.is_ok());
+        assert_eq!(
+            source.downloads(),
+            ["org/one/model.bin", "org/two/model.bin"]
+        );
+        assert!(
+            temp_dir
+                .path()
+                .join("cache/models--org--two/snapshots/v2/model.bin")
+                .exists()
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_download_many_stops_after_failure() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let (manager, source) = mock_setup(temp_dir.path(), &["org/one", "org/two"])?;
+
+        let specs = ModelSpec::parse_manifest("org/missing\norg/one\norg/two\n")?;
+        let report = manager
+            .download_many(&specs, &BulkDownloadOptions::default())
+            .await?;
+
+        assert_eq!(report.failed(), 1);
+        assert_eq!(report.not_attempted(), 2);
+        assert!(source.downloads().is_empty());
+        assert!(manager.list_models()?.is_empty());
+        Ok(())
+    }
+}
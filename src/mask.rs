@@ -0,0 +1,539 @@
+//! Masks drawn by the user instead of, or on top of, the detected clothing
+//!
+//! A [`Polygon`] such as `"120,80 260,80 300,400 90,400"` marks the part of the
+//! input to re-generate, for shapes a rectangle can't follow like sleeves and
+//! collars. Whole numbers are pixels and numbers with a decimal point are
+//! fractions of the width or height, so `0.5,0.25` is the same point on any
+//! image size. [`MaskMode`] decides how it combines with the detected mask.
+//!
+//! [`SkinProtection`] then takes skin out of whichever mask results, so necks,
+//! faces and hands inside the clothing region keep their color.
+
+use std::{fmt, str::FromStr};
+
+use anyhow::{Context, Result, bail};
+#[cfg(feature = "image-pipeline")]
+use image::{GrayImage, Luma, Rgb, RgbImage};
+#[cfg(feature = "image-pipeline")]
+use imageproc::{distance_transform::Norm, morphology};
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "image-pipeline")]
+use crate::segment::Region;
+
+/// One coordinate of a polygon point
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Coord {
+    Pixels(u32),
+    /// Fraction of the image width (for x) or height (for y), 0.0 to 1.0
+    Normalized(f64),
+}
+
+impl Coord {
+    fn resolve(self, extent: u32) -> f64 {
+        match self {
+            Self::Pixels(px) => f64::from(px),
+            Self::Normalized(fraction) => f64::from(extent) * fraction,
+        }
+    }
+}
+
+impl FromStr for Coord {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s.contains('.') {
+            let fraction: f64 = s
+                .parse()
+                .with_context(|| format!("Invalid coordinate `{s}`"))?;
+            if !(0.0..=1.0).contains(&fraction) {
+                bail!(
+                    "Normalized coordinate `{s}` is outside 0.0 to 1.0; write pixels as \
+                     whole numbers"
+                );
+            }
+            return Ok(Self::Normalized(fraction));
+        }
+        s.parse()
+            .map(Self::Pixels)
+            .with_context(|| format!("Invalid coordinate `{s}`"))
+    }
+}
+
+impl fmt::Display for Coord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pixels(px) => write!(f, "{px}"),
+            // Always with a decimal point, so it parses back as a fraction
+            Self::Normalized(fraction) => write!(f, "{fraction:?}"),
+        }
+    }
+}
+
+/// A closed polygon of at least three points, written as space-separated
+/// `x,y` pairs
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub struct Polygon {
+    pub points: Vec<(Coord, Coord)>,
+}
+
+impl Polygon {
+    /// The points in pixels of a `width`x`height` image
+    pub fn resolve(&self, width: u32, height: u32) -> Vec<(f64, f64)> {
+        self.points
+            .iter()
+            .map(|(x, y)| (x.resolve(width), y.resolve(height)))
+            .collect()
+    }
+}
+
+impl FromStr for Polygon {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let points = s
+            .split_whitespace()
+            .map(|point| {
+                let Some((x, y)) = point.split_once(',') else {
+                    bail!("Invalid polygon point `{point}`, expected x,y");
+                };
+                let coord = |c: &str| {
+                    c.parse::<Coord>()
+                        .with_context(|| format!("Invalid polygon point `{point}`"))
+                };
+                Ok((coord(x)?, coord(y)?))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if points.len() < 3 {
+            bail!(
+                "A polygon needs at least 3 points, got {} in `{s}`",
+                points.len()
+            );
+        }
+        Ok(Self { points })
+    }
+}
+
+impl fmt::Display for Polygon {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (x, y)) in self.points.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            write!(f, "{x},{y}")?;
+        }
+        Ok(())
+    }
+}
+
+impl From<Polygon> for String {
+    fn from(polygon: Polygon) -> Self {
+        polygon.to_string()
+    }
+}
+
+impl TryFrom<String> for Polygon {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+/// Which mask a try-on transforms inside
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MaskMode {
+    /// The detected clothing; a polygon is ignored
+    Detect,
+    /// The polygon alone
+    Polygon,
+    /// The detected clothing within the polygon
+    #[default]
+    Intersect,
+}
+
+impl FromStr for MaskMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "detect" => Ok(Self::Detect),
+            "polygon" => Ok(Self::Polygon),
+            "intersect" => Ok(Self::Intersect),
+            other => bail!("Unknown mask mode `{other}`, expected detect, polygon or intersect"),
+        }
+    }
+}
+
+impl fmt::Display for MaskMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Detect => "detect",
+            Self::Polygon => "polygon",
+            Self::Intersect => "intersect",
+        })
+    }
+}
+
+/// A user-drawn region and how it combines with the detected clothing
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RegionMask {
+    pub polygon: Polygon,
+    #[serde(default)]
+    pub mode: MaskMode,
+}
+
+#[cfg(feature = "image-pipeline")]
+impl RegionMask {
+    /// The polygon rasterized for a `size` image made from a `input_size` input
+    /// cropped to `crop`
+    ///
+    /// Coordinates refer to the uncropped input, so a polygon stays put when a
+    /// crop is added; the result is scaled when `size` differs from the crop.
+    pub fn rasterize(
+        &self,
+        input_size: (u32, u32),
+        crop: Option<Region>,
+        size: (u32, u32),
+    ) -> GrayImage {
+        let region = crop.unwrap_or(Region {
+            x: 0,
+            y: 0,
+            width: input_size.0,
+            height: input_size.1,
+        });
+        let scale_x = f64::from(size.0) / f64::from(region.width.max(1));
+        let scale_y = f64::from(size.1) / f64::from(region.height.max(1));
+        let points: Vec<_> = self
+            .polygon
+            .resolve(input_size.0, input_size.1)
+            .into_iter()
+            .map(|(x, y)| {
+                (
+                    (x - f64::from(region.x)) * scale_x,
+                    (y - f64::from(region.y)) * scale_y,
+                )
+            })
+            .collect();
+        polygon_mask(size.0, size.1, &points)
+    }
+}
+
+#[cfg(feature = "image-pipeline")]
+/// A `width`x`height` mask, 255 inside the polygon through `points` and 0
+/// outside
+///
+/// A pixel is inside when its center is, by the even-odd rule, so a
+/// self-intersecting polygon leaves the overlap of its lobes unmasked.
+pub fn polygon_mask(width: u32, height: u32, points: &[(f64, f64)]) -> GrayImage {
+    let mut mask = GrayImage::new(width, height);
+    if points.len() < 3 {
+        return mask;
+    }
+    let edges: Vec<_> = points
+        .iter()
+        .zip(points.iter().cycle().skip(1))
+        .filter(|((_, y0), (_, y1))| y0 != y1)
+        .collect();
+    let mut crossings = Vec::new();
+    for row in 0..height {
+        let center = f64::from(row) + 0.5;
+        crossings.clear();
+        for &(&(x0, y0), &(x1, y1)) in &edges {
+            // Half-open, so a vertex on the scanline is counted once
+            if (y0 <= center) != (y1 <= center) {
+                crossings.push(x0 + (center - y0) * (x1 - x0) / (y1 - y0));
+            }
+        }
+        crossings.sort_by(f64::total_cmp);
+        for span in crossings.chunks_exact(2) {
+            // Pixels whose centers fall within the span
+            let first = (span[0] - 0.5).ceil().clamp(0.0, f64::from(width)) as u32;
+            let end = (span[1] - 0.5).ceil().clamp(0.0, f64::from(width)) as u32;
+            for column in first..end {
+                mask.put_pixel(column, row, Luma([255]));
+            }
+        }
+    }
+    mask
+}
+
+#[cfg(feature = "image-pipeline")]
+/// The pixelwise minimum of two masks of the same size
+pub fn intersect(a: &GrayImage, b: &GrayImage) -> GrayImage {
+    GrayImage::from_fn(a.width(), a.height(), |x, y| {
+        let b = b.get_pixel_checked(x, y).map_or(0, |p| p.0[0]);
+        Luma([a.get_pixel(x, y).0[0].min(b)])
+    })
+}
+
+#[cfg(feature = "image-pipeline")]
+/// The pixelwise `a` minus `b`: `a` wherever `b` is empty, nothing where `b`
+/// is full
+pub fn subtract(a: &GrayImage, b: &GrayImage) -> GrayImage {
+    GrayImage::from_fn(a.width(), a.height(), |x, y| {
+        let b = b.get_pixel_checked(x, y).map_or(0, |p| p.0[0]);
+        Luma([a.get_pixel(x, y).0[0].min(255 - b)])
+    })
+}
+
+#[cfg(feature = "image-pipeline")]
+/// Whether `pixel` looks like skin, by its chroma in YCbCr
+///
+/// Skin of any complexion falls in a narrow Cb/Cr box, the classic
+/// Cb 77-127 and Cr 133-173 at a `sensitivity` of 0.5; higher sensitivities
+/// widen the box to catch more borderline tones (and more beige fabric), lower
+/// ones narrow it. Pixels too dark to judge are never skin.
+pub fn is_skin_tone(pixel: &Rgb<u8>, sensitivity: f32) -> bool {
+    let [r, g, b] = pixel.0.map(f32::from);
+    let luma = 0.299 * r + 0.587 * g + 0.114 * b;
+    let cb = 128.0 - 0.168_736 * r - 0.331_264 * g + 0.5 * b;
+    let cr = 128.0 + 0.5 * r - 0.418_688 * g - 0.081_312 * b;
+    let margin = (sensitivity.clamp(0.0, 1.0) - 0.5) * 30.0;
+    luma >= 40.0
+        && (77.0 - margin..=127.0 + margin).contains(&cb)
+        && (133.0 - margin..=173.0 + margin).contains(&cr)
+}
+
+#[cfg(feature = "image-pipeline")]
+/// Keeps skin out of the mask a try-on transforms inside
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SkinProtection {
+    /// How readily a tone counts as skin, 0.0 to 1.0; see [`is_skin_tone`]
+    pub sensitivity: f32,
+    /// Pixels the skin mask grows by, covering the soft edges of skin that
+    /// blend into the clothing
+    pub dilation: u8,
+}
+
+#[cfg(feature = "image-pipeline")]
+impl Default for SkinProtection {
+    fn default() -> Self {
+        Self {
+            sensitivity: Self::DEFAULT_SENSITIVITY,
+            dilation: 1,
+        }
+    }
+}
+
+#[cfg(feature = "image-pipeline")]
+impl SkinProtection {
+    pub const DEFAULT_SENSITIVITY: f32 = 0.5;
+
+    pub fn with_sensitivity(sensitivity: f32) -> Self {
+        Self {
+            sensitivity,
+            ..Self::default()
+        }
+    }
+
+    /// 255 where `image` looks like skin or is next to skin, 0 elsewhere
+    pub fn skin_mask(&self, image: &RgbImage) -> GrayImage {
+        let skin = GrayImage::from_fn(image.width(), image.height(), |x, y| {
+            let skin = is_skin_tone(image.get_pixel(x, y), self.sensitivity);
+            Luma([if skin { 255 } else { 0 }])
+        });
+        match self.dilation {
+            0 => skin,
+            radius => morphology::dilate(&skin, Norm::LInf, radius),
+        }
+    }
+
+    /// `mask` without the skin in `image`, and the part of `mask` that was
+    /// taken out
+    pub fn apply(&self, image: &RgbImage, mask: &GrayImage) -> (GrayImage, GrayImage) {
+        let skin = self.skin_mask(image);
+        (subtract(mask, &skin), intersect(mask, &skin))
+    }
+}
+
+#[cfg(feature = "image-pipeline")]
+/// `image` dimmed, with `mask` tinted red and `protected` tinted blue, to see
+/// what a try-on would transform and what skin protection kept out of it
+pub fn debug_overlay(image: &RgbImage, mask: &GrayImage, protected: &GrayImage) -> RgbImage {
+    let weight = |mask: &GrayImage, x, y| {
+        mask.get_pixel_checked(x, y)
+            .map_or(0.0, |p| f32::from(p.0[0]) / 255.0)
+    };
+    RgbImage::from_fn(image.width(), image.height(), |x, y| {
+        // Dimmed gray, so the tints stand out whatever the colors
+        let gray = image.get_pixel(x, y).0.map(f32::from).iter().sum::<f32>() / 3.0 * 0.4;
+        let red = weight(mask, x, y) * 0.7;
+        let blue = weight(protected, x, y) * 0.7;
+        Rgb([
+            gray + (255.0 - gray) * red,
+            gray * (1.0 - red.max(blue)),
+            gray + (255.0 - gray) * blue,
+        ]
+        .map(|channel| channel as u8))
+    })
+}
+
+#[cfg(all(test, feature = "image-pipeline"))]
+mod tests {
+    use super::*;
+
+    fn covered(mask: &GrayImage) -> usize {
+        mask.pixels().filter(|p| p.0[0] == 255).count()
+    }
+
+    #[test]
+    fn test_triangle_mask() -> Result<()> {
+        let triangle: Polygon = "0,0 10,0 0,10".parse()?;
+        let mask = polygon_mask(10, 10, &triangle.resolve(10, 10));
+        // Pixel centers strictly below the diagonal: 9 + 8 + ... + 1
+        assert_eq!(covered(&mask), 45);
+        assert_eq!(mask.get_pixel(0, 0).0[0], 255);
+        assert_eq!(mask.get_pixel(9, 9).0[0], 0);
+
+        let normalized: Polygon = "0.0,0.0 1.0,0.0 0.0,1.0".parse()?;
+        assert_eq!(polygon_mask(10, 10, &normalized.resolve(10, 10)), mask);
+        Ok(())
+    }
+
+    #[test]
+    fn test_concave_mask() -> Result<()> {
+        // A square with a triangular notch cut into its bottom edge
+        let notched: Polygon = "0,0 10,0 10,10 5,5 0,10".parse()?;
+        let mask = polygon_mask(10, 10, &notched.resolve(10, 10));
+        assert_eq!(covered(&mask), 75);
+        assert_eq!(mask.get_pixel(5, 8).0[0], 0);
+        assert_eq!(mask.get_pixel(0, 8).0[0], 255);
+        assert_eq!(mask.get_pixel(9, 8).0[0], 255);
+        Ok(())
+    }
+
+    #[test]
+    fn test_degenerate_polygons_do_not_panic() -> Result<()> {
+        let bowtie: Polygon = "0,0 10,10 10,0 0,10".parse()?;
+        let mask = polygon_mask(10, 10, &bowtie.resolve(10, 10));
+        assert!(covered(&mask) > 0);
+
+        let line: Polygon = "0,0 5,5 10,10".parse()?;
+        assert_eq!(covered(&polygon_mask(10, 10, &line.resolve(10, 10))), 0);
+
+        let outside: Polygon = "50,50 90,50 90,90".parse()?;
+        assert_eq!(covered(&polygon_mask(10, 10, &outside.resolve(10, 10))), 0);
+        assert_eq!(polygon_mask(0, 0, &bowtie.resolve(0, 0)).len(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_polygon_parse_errors() {
+        let err = |s: &str| format!("{:#}", s.parse::<Polygon>().unwrap_err());
+        assert!(err("1,1 2,2").contains("at least 3 points, got 2"));
+        assert!(err("").contains("got 0"));
+        assert!(err("1,1 2;2 3,3").contains("`2;2`, expected x,y"));
+        let non_numeric = err("1,1 2,abc 3,3");
+        assert!(non_numeric.contains("`2,abc`"), "{non_numeric}");
+        assert!(
+            non_numeric.contains("Invalid coordinate `abc`"),
+            "{non_numeric}"
+        );
+        assert!(err("0.5,1.5 0,0 1,1").contains("outside 0.0 to 1.0"));
+        assert!(err("NaN,0 0,0 1,1").contains("Invalid coordinate"));
+    }
+
+    #[test]
+    fn test_polygon_round_trips() -> Result<()> {
+        let polygon: Polygon = "  0.25,0.5   120,80\t0.0,1.0 ".parse()?;
+        assert_eq!(polygon.to_string(), "0.25,0.5 120,80 0.0,1.0");
+        assert_eq!(polygon.to_string().parse::<Polygon>()?, polygon);
+        let json = serde_json::to_string(&RegionMask {
+            polygon: polygon.clone(),
+            mode: MaskMode::Polygon,
+        })?;
+        assert_eq!(
+            json,
+            r#"{"polygon":"0.25,0.5 120,80 0.0,1.0","mode":"polygon"}"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_rasterize_follows_the_crop() -> Result<()> {
+        let mask = RegionMask {
+            polygon: "10,10 20,10 20,20 10,20".parse()?,
+            mode: MaskMode::Polygon,
+        };
+        let crop = Region {
+            x: 10,
+            y: 10,
+            width: 20,
+            height: 20,
+        };
+        let cropped = mask.rasterize((40, 40), Some(crop), (20, 20));
+        assert_eq!(covered(&cropped), 100);
+        assert_eq!(cropped.get_pixel(0, 0).0[0], 255);
+        assert_eq!(cropped.get_pixel(10, 10).0[0], 0);
+        // Half the size, a quarter of the pixels
+        let scaled = mask.rasterize((40, 40), Some(crop), (10, 10));
+        assert_eq!(covered(&scaled), 25);
+        Ok(())
+    }
+
+    #[test]
+    fn test_intersect() {
+        let left = polygon_mask(4, 4, &[(0.0, 0.0), (2.0, 0.0), (2.0, 4.0), (0.0, 4.0)]);
+        let top = polygon_mask(4, 4, &[(0.0, 0.0), (4.0, 0.0), (4.0, 2.0), (0.0, 2.0)]);
+        assert_eq!(covered(&intersect(&left, &top)), 4);
+    }
+
+    #[test]
+    fn test_is_skin_tone() {
+        for skin in [
+            Rgb([224, 172, 145]),
+            Rgb([198, 134, 66]),
+            Rgb([141, 85, 36]),
+            Rgb([255, 219, 172]),
+            Rgb([200, 170, 150]),
+        ] {
+            assert!(is_skin_tone(&skin, 0.5), "{skin:?}");
+        }
+        for other in [
+            Rgb([30, 60, 200]),
+            Rgb([40, 160, 60]),
+            Rgb([100, 80, 120]),
+            Rgb([128, 128, 128]),
+            Rgb([20, 10, 8]),
+        ] {
+            assert!(!is_skin_tone(&other, 0.5), "{other:?}");
+        }
+        // Borderline pink: skin only to a sensitive detector
+        let pink = Rgb([240, 140, 140]);
+        assert!(!is_skin_tone(&pink, 0.5));
+        assert!(is_skin_tone(&pink, 1.0));
+        assert!(!is_skin_tone(&pink, 0.0));
+    }
+
+    #[test]
+    fn test_skin_protection_cuts_dilated_skin_out_of_the_mask() {
+        let mut image = RgbImage::from_pixel(20, 20, Rgb([30, 60, 200]));
+        for x in 8..12 {
+            for y in 8..12 {
+                image.put_pixel(x, y, Rgb([224, 172, 145]));
+            }
+        }
+        let clothing = GrayImage::from_pixel(20, 20, Luma([255]));
+        let (mask, protected) = SkinProtection::default().apply(&image, &clothing);
+        // The 4x4 patch grown by a pixel on every side
+        assert_eq!(covered(&protected), 36);
+        assert_eq!(covered(&mask), 400 - 36);
+        assert_eq!(mask.get_pixel(7, 7).0[0], 0);
+        assert_eq!(mask.get_pixel(6, 6).0[0], 255);
+
+        let tight = SkinProtection {
+            dilation: 0,
+            ..SkinProtection::default()
+        };
+        assert_eq!(covered(&tight.skin_mask(&image)), 16);
+
+        let overlay = debug_overlay(&image, &mask, &protected);
+        let [r, _, b] = overlay.get_pixel(9, 9).0;
+        assert!(b > r, "protected skin shows blue");
+        let [r, _, b] = overlay.get_pixel(0, 0).0;
+        assert!(r > b, "the transformed mask shows red");
+    }
+}
@@ -0,0 +1,87 @@
+//! Clothing masks kept between runs
+//!
+//! Trying different colors on the same photo segments it the same way every
+//! time, so masks are stored as PNGs in the [`CacheKind::Masks`] cache. The key
+//! covers everything the mask depends on: the input pixels, the segmenter and
+//! its version, the crop, the drawn polygon and the size the mask was made at.
+
+use std::io::Cursor;
+
+use anyhow::{Context, Result};
+use image::{DynamicImage, GrayImage, ImageFormat};
+use log::{debug, warn};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    disk_cache::{CacheKind, DiskCache, hex},
+    logging::MASK_TARGET,
+    mask::RegionMask,
+    segment::Region,
+};
+
+/// Size the mask cache is trimmed to
+pub const MASK_CACHE_MAX_BYTES: u64 = 256 * 1024 * 1024;
+
+/// Everything a cached clothing mask was computed from
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MaskKey {
+    /// [`content_hash`] of the uncropped input
+    pub input: String,
+    /// [`Segmenter::id`](crate::Segmenter::id) of the segmenter that made it
+    pub segmenter: String,
+    pub crop: Option<Region>,
+    /// The drawn polygon, unless the mask mode ignores it
+    pub mask: Option<RegionMask>,
+    pub size: (u32, u32),
+}
+
+pub type MaskCache = DiskCache<MaskKey>;
+
+impl DiskCache<MaskKey> {
+    /// The mask cache in the si cache directory
+    pub fn masks() -> Result<Self> {
+        Ok(Self::new(CacheKind::Masks.dir()?, "png").with_max_bytes(MASK_CACHE_MAX_BYTES))
+    }
+
+    /// The mask stored for `key`; an unreadable entry counts as a miss
+    pub fn get_mask(&self, key: &MaskKey) -> Option<GrayImage> {
+        let decoded = self.get(key).and_then(|bytes| {
+            bytes
+                .map(|bytes| image::load_from_memory_with_format(&bytes, ImageFormat::Png))
+                .transpose()
+                .context("Failed to decode the cached mask")
+        });
+        match decoded {
+            Ok(Some(mask)) if mask.width() == key.size.0 && mask.height() == key.size.1 => {
+                debug!(target: MASK_TARGET, "Using the cached clothing mask");
+                Some(mask.to_luma8())
+            }
+            Ok(_) => None,
+            Err(e) => {
+                warn!("Ignoring the cached clothing mask: {e:#}");
+                None
+            }
+        }
+    }
+
+    pub fn put_mask(&self, key: &MaskKey, mask: &GrayImage) -> Result<()> {
+        let mut png = Cursor::new(Vec::new());
+        mask.write_to(&mut png, ImageFormat::Png)
+            .context("Failed to encode the mask")?;
+        self.put(key, png.get_ref())
+    }
+}
+
+/// SHA-256 of `image`'s dimensions, pixel type and pixels, as hex
+///
+/// Two files with the same pixels hash the same, whatever their format or
+/// metadata.
+pub fn content_hash(image: &DynamicImage) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(image.width().to_le_bytes());
+    hasher.update(image.height().to_le_bytes());
+    hasher.update(format!("{:?}", image.color()));
+    hasher.update(image.as_bytes());
+    hex(&hasher.finalize())
+}
@@ -0,0 +1,494 @@
+//! Generation parameters embedded in output images
+//!
+//! Outputs carry the prompt, model, strength, seed and si version that made
+//! them, as the text AUTOMATIC1111's web UI writes and the tools around it
+//! read: the prompt, a `Negative prompt:` line when there is one, then a line
+//! of `Key: value` pairs.
+//!
+//! ```text
+//! red silk shirt
+//! Negative prompt: blurry
+//! Seed: 42, Model: runwayml/stable-diffusion-v1-5, Denoising strength: 0.8, Version: si 0.1.0
+//! ```
+//!
+//! PNGs hold it in a `parameters` text chunk, `tEXt` or, when it isn't
+//! Latin-1, `iTXt`. JPEGs hold it in the EXIF `UserComment`, as UTF-16 after
+//! the `UNICODE` charset marker. Other formats get none. [`read`] understands
+//! what other tools wrote the same way, keeping keys si doesn't use in
+//! [`ReproInfo::extra`].
+
+use std::{fmt, fs, path::Path};
+
+use anyhow::{Context, Result, bail};
+use flate2::Crc;
+use image::ImageFormat;
+use serde::{Deserialize, Serialize};
+
+use crate::imageio::{self, read_u16, read_u32};
+
+/// Keyword of the PNG text chunk, and the name tools look for
+pub const PARAMETERS_KEY: &str = "parameters";
+/// Key of the content hash of a model used from a local path, as
+/// [`LocalModel::content_hash`](crate::import::LocalModel::content_hash) computes it
+pub const MODEL_HASH_KEY: &str = "Model hash";
+/// Start of the line holding the negative prompt
+const NEGATIVE_PREFIX: &str = "Negative prompt:";
+/// What the `Version` value starts with for images si made
+const VERSION_PREFIX: &str = "si ";
+/// EXIF tag pointing at the Exif sub-IFD
+const EXIF_IFD_TAG: u16 = 0x8769;
+/// EXIF tag of the user comment
+const USER_COMMENT_TAG: u16 = 0x9286;
+/// Charset marker of a UTF-16 user comment
+const UNICODE_CHARSET: &[u8; 8] = b"UNICODE\0";
+
+/// What a try-on needs to be run again: the parameters embedded in its output
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ReproInfo {
+    pub prompt: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub negative_prompt: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strength: Option<f64>,
+    /// The seed, for runs that used one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    /// The si version that made the image; `None` for images other tools made
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub si_version: Option<String>,
+    /// Other `Key: value` pairs, such as another tool's sampler and steps
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extra: Vec<(String, String)>,
+}
+
+impl ReproInfo {
+    /// The parameters of a try-on of `prompt` with `model` at `strength`, made
+    /// by this si
+    pub fn new(prompt: &str, model: &str, strength: f64) -> Self {
+        Self {
+            prompt: prompt.to_string(),
+            model: Some(model.to_string()),
+            strength: Some(strength),
+            si_version: Some(crate::version::VERSION.to_string()),
+            ..Self::default()
+        }
+    }
+
+    /// Read the parameters text of `text`, as [`Display`](fmt::Display)
+    /// writes it and other tools do; `None` when it isn't one
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut lines: Vec<&str> = text.trim_end().lines().collect();
+        let pairs = lines.last().and_then(|line| parse_pairs(line))?;
+        lines.pop();
+
+        let mut info = Self::default();
+        let negative_at = lines
+            .iter()
+            .position(|line| line.starts_with(NEGATIVE_PREFIX));
+        if let Some(at) = negative_at {
+            let mut negative = lines.split_off(at);
+            negative[0] = negative[0][NEGATIVE_PREFIX.len()..].trim_start();
+            info.negative_prompt = Some(negative.join("\n"));
+        }
+        info.prompt = lines.join("\n");
+        for (key, value) in pairs {
+            match key.as_str() {
+                "Seed" => info.seed = value.parse().ok(),
+                "Model" => info.model = Some(value),
+                "Denoising strength" => info.strength = value.parse().ok(),
+                "Version" => match value.strip_prefix(VERSION_PREFIX) {
+                    Some(version) => info.si_version = Some(version.to_string()),
+                    None => info.extra.push((key, value)),
+                },
+                _ => info.extra.push((key, value)),
+            }
+        }
+        Some(info)
+    }
+}
+
+impl fmt::Display for ReproInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.prompt)?;
+        if let Some(negative) = &self.negative_prompt {
+            writeln!(f, "{NEGATIVE_PREFIX} {negative}")?;
+        }
+        let mut pairs = Vec::new();
+        if let Some(seed) = self.seed {
+            pairs.push(("Seed".to_string(), seed.to_string()));
+        }
+        if let Some(model) = &self.model {
+            pairs.push(("Model".to_string(), model.clone()));
+        }
+        if let Some(strength) = self.strength {
+            pairs.push(("Denoising strength".to_string(), strength.to_string()));
+        }
+        if let Some(version) = &self.si_version {
+            pairs.push(("Version".to_string(), format!("{VERSION_PREFIX}{version}")));
+        }
+        pairs.extend(self.extra.iter().cloned());
+        let pairs: Vec<_> = pairs
+            .iter()
+            .map(|(key, value)| format!("{key}: {}", quote(value)))
+            .collect();
+        f.write_str(&pairs.join(", "))
+    }
+}
+
+/// `value` as a parameters line holds it: quoted as JSON when it has a
+/// character that would end it early
+fn quote(value: &str) -> String {
+    if value.contains([',', ':', '\n', '"']) {
+        serde_json::Value::from(value).to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+/// The `Key: value` pairs of a parameters line, or `None` when `line` isn't one
+fn parse_pairs(line: &str) -> Option<Vec<(String, String)>> {
+    let mut pairs = Vec::new();
+    let mut rest = line.trim();
+    while !rest.is_empty() {
+        let (key, after) = rest.split_once(':')?;
+        let key = key.trim();
+        if key.is_empty()
+            || !key
+                .chars()
+                .all(|c| c.is_alphanumeric() || matches!(c, ' ' | '-' | '_' | '/'))
+        {
+            return None;
+        }
+        let after = after.trim_start();
+        let (value, after) = match after.strip_prefix('"') {
+            Some(quoted) => {
+                let end = closing_quote(quoted)?;
+                let value: String = serde_json::from_str(&after[..end + 2]).ok()?;
+                (value, &quoted[end + 1..])
+            }
+            None => {
+                let end = after.find(',').unwrap_or(after.len());
+                (after[..end].trim().to_string(), &after[end..])
+            }
+        };
+        pairs.push((key.to_string(), value));
+        rest = after.trim_start();
+        rest = rest.strip_prefix(',').unwrap_or(rest).trim_start();
+    }
+    (!pairs.is_empty()).then_some(pairs)
+}
+
+/// Index of the unescaped quote that ends the JSON string `quoted`
+fn closing_quote(quoted: &str) -> Option<usize> {
+    let mut escaped = false;
+    for (i, c) in quoted.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Whether [`embed`] can put parameters into `format`
+pub fn can_embed(format: ImageFormat) -> bool {
+    matches!(format, ImageFormat::Png | ImageFormat::Jpeg)
+}
+
+/// `encoded`, a PNG or JPEG as the encoders write it, with `info` added
+pub fn embed(encoded: &[u8], format: ImageFormat, info: &ReproInfo) -> Result<Vec<u8>> {
+    match format {
+        ImageFormat::Png => embed_png(encoded, info),
+        ImageFormat::Jpeg => embed_jpeg(encoded, &info.to_string()),
+        other => bail!("{other:?} images can't carry generation parameters"),
+    }
+}
+
+/// The type and data of the PNG text chunk holding `info`, for encoders that
+/// write their own chunks
+pub fn png_chunk(info: &ReproInfo) -> (png::chunk::ChunkType, Vec<u8>) {
+    let text = info.to_string();
+    let mut data = PARAMETERS_KEY.as_bytes().to_vec();
+    data.push(0);
+    // Latin-1 is the first 256 code points, one byte each
+    let latin1: Option<Vec<u8>> = text.chars().map(|c| u8::try_from(c).ok()).collect();
+    match latin1 {
+        Some(latin1) => {
+            data.extend(latin1);
+            (png::chunk::tEXt, data)
+        }
+        None => {
+            // Uncompressed, then empty language tag and translated keyword
+            data.extend([0, 0, 0, 0]);
+            data.extend(text.as_bytes());
+            (png::chunk::iTXt, data)
+        }
+    }
+}
+
+fn embed_png(png: &[u8], info: &ReproInfo) -> Result<Vec<u8>> {
+    // The signature, then IHDR: length, type, 13 bytes of data and the CRC
+    const IHDR_END: usize = 8 + 4 + 4 + 13 + 4;
+    if png.len() < IHDR_END || &png[12..16] != b"IHDR" {
+        bail!("Not a PNG starting with its header chunk");
+    }
+    let (kind, data) = png_chunk(info);
+    let len = u32::try_from(data.len()).context("Parameters are too large for a PNG")?;
+
+    let mut crc = Crc::new();
+    crc.update(&kind.0);
+    crc.update(&data);
+    let mut out = Vec::with_capacity(png.len() + data.len() + 12);
+    out.extend(&png[..IHDR_END]);
+    out.extend(len.to_be_bytes());
+    out.extend(kind.0);
+    out.extend(&data);
+    out.extend(crc.sum().to_be_bytes());
+    out.extend(&png[IHDR_END..]);
+    Ok(out)
+}
+
+fn embed_jpeg(jpeg: &[u8], text: &str) -> Result<Vec<u8>> {
+    if !jpeg.starts_with(&[0xFF, 0xD8]) {
+        bail!("Not a JPEG");
+    }
+    // Readers expect a JFIF APP0 to come first
+    let mut at = 2;
+    if jpeg.get(2..4) == Some(&[0xFF, 0xE0]) {
+        let len = read_u16(jpeg, 4, true).context("Truncated JPEG header")?;
+        at = 4 + usize::from(len);
+    }
+
+    let mut comment = UNICODE_CHARSET.to_vec();
+    comment.extend(text.encode_utf16().flat_map(u16::to_be_bytes));
+    // A big-endian TIFF header, IFD0 pointing at the Exif IFD, and the Exif
+    // IFD holding the comment, which follows it
+    const EXIF_IFD: u32 = 8 + 2 + 12 + 4;
+    const COMMENT: u32 = EXIF_IFD + 2 + 12 + 4;
+    let count = u32::try_from(comment.len()).context("Parameters are too large for a JPEG")?;
+    let mut tiff = b"MM\0\x2a".to_vec();
+    tiff.extend(8u32.to_be_bytes());
+    for (tag, kind, count, value) in [
+        (EXIF_IFD_TAG, 4u16, 1, EXIF_IFD),
+        (USER_COMMENT_TAG, 7u16, count, COMMENT),
+    ] {
+        tiff.extend(1u16.to_be_bytes());
+        tiff.extend(tag.to_be_bytes());
+        tiff.extend(kind.to_be_bytes());
+        tiff.extend(count.to_be_bytes());
+        tiff.extend(value.to_be_bytes());
+        tiff.extend(0u32.to_be_bytes());
+    }
+    tiff.extend(comment);
+
+    let segment_len = u16::try_from(2 + 6 + tiff.len())
+        .ok()
+        .context("Parameters are too large for a JPEG")?;
+    let mut out = Vec::with_capacity(jpeg.len() + tiff.len() + 10);
+    out.extend(&jpeg[..at]);
+    out.extend([0xFF, 0xE1]);
+    out.extend(segment_len.to_be_bytes());
+    out.extend(b"Exif\0\0");
+    out.extend(tiff);
+    out.extend(&jpeg[at..]);
+    Ok(out)
+}
+
+/// The generation parameters embedded in the image at `path`, if it has any
+pub fn read(path: &Path) -> Option<ReproInfo> {
+    let bytes = fs::read(path).ok()?;
+    let text = match imageio::sniff_format(path).ok()? {
+        ImageFormat::Png => png_parameters(&bytes),
+        ImageFormat::Jpeg => jpeg_user_comment(&bytes),
+        _ => None,
+    }?;
+    ReproInfo::parse(&text)
+}
+
+/// The text of the PNG's `parameters` chunk
+fn png_parameters(bytes: &[u8]) -> Option<String> {
+    let reader = png::Decoder::new(bytes).read_info().ok()?;
+    let info = reader.info();
+    let latin1 = info
+        .uncompressed_latin1_text
+        .iter()
+        .find(|chunk| chunk.keyword == PARAMETERS_KEY)
+        .map(|chunk| chunk.text.clone());
+    let compressed = || {
+        info.compressed_latin1_text
+            .iter()
+            .find(|chunk| chunk.keyword == PARAMETERS_KEY)
+            .and_then(|chunk| chunk.get_text().ok())
+    };
+    let utf8 = || {
+        info.utf8_text
+            .iter()
+            .find(|chunk| chunk.keyword == PARAMETERS_KEY)
+            .and_then(|chunk| chunk.get_text().ok())
+    };
+    latin1.or_else(compressed).or_else(utf8)
+}
+
+/// The EXIF user comment of a JPEG
+fn jpeg_user_comment(bytes: &[u8]) -> Option<String> {
+    let tiff = imageio::jpeg_exif(bytes)?;
+    let big_endian = match tiff.get(..2)? {
+        b"MM" => true,
+        b"II" => false,
+        _ => return None,
+    };
+    let entry = |ifd: usize, tag: u16| {
+        let count = read_u16(tiff, ifd, big_endian)? as usize;
+        (0..count)
+            .map(|i| ifd + 2 + i * 12)
+            .find(|&entry| read_u16(tiff, entry, big_endian) == Some(tag))
+    };
+    let ifd0 = read_u32(tiff, 4, big_endian)? as usize;
+    let exif_ifd = read_u32(tiff, entry(ifd0, EXIF_IFD_TAG)? + 8, big_endian)? as usize;
+    let comment = entry(exif_ifd, USER_COMMENT_TAG)?;
+    let len = read_u32(tiff, comment + 4, big_endian)? as usize;
+    let data = if len <= 4 {
+        tiff.get(comment + 8..comment + 8 + len)?
+    } else {
+        let at = read_u32(tiff, comment + 8, big_endian)? as usize;
+        tiff.get(at..at.checked_add(len)?)?
+    };
+
+    let (charset, text) = data.split_at_checked(8)?;
+    if charset == UNICODE_CHARSET {
+        let units: Vec<u16> = text
+            .chunks_exact(2)
+            .map(|pair| read_u16(pair, 0, big_endian))
+            .collect::<Option<_>>()?;
+        return Some(
+            String::from_utf16_lossy(&units)
+                .trim_end_matches('\0')
+                .to_string(),
+        );
+    }
+    // ASCII, or undefined, which tools fill with UTF-8
+    Some(
+        String::from_utf8_lossy(text)
+            .trim_end_matches('\0')
+            .to_string(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::imageio::OutputOptions;
+    use image::{DynamicImage, Rgb, RgbImage};
+    use tempfile::tempdir;
+
+    fn info() -> ReproInfo {
+        ReproInfo {
+            negative_prompt: Some("blurry, low quality".to_string()),
+            seed: Some(1234),
+            ..ReproInfo::new("red silk shirt", "org/model", 0.8)
+        }
+    }
+
+    #[test]
+    fn test_parameters_round_trip_through_png_and_jpeg() -> Result<()> {
+        let dir = tempdir()?;
+        let image = DynamicImage::ImageRgb8(RgbImage::from_pixel(16, 12, Rgb([90, 40, 200])));
+        let unicode = ReproInfo::new("chemise en soie rouge, 赤いシャツ", "org/model", 0.5);
+        for (name, parameters) in [
+            ("out.png", info()),
+            ("out.jpg", info()),
+            ("unicode.png", unicode.clone()),
+            ("unicode.jpg", unicode),
+        ] {
+            let path = dir.path().join(name);
+            let options = OutputOptions {
+                parameters: Some(parameters.clone()),
+                icc_profile: Some((0..=255).collect()),
+                ..Default::default()
+            };
+            imageio::save(&image, &path, &options)?;
+            assert_eq!(read(&path), Some(parameters), "{name}");
+            let loaded = imageio::load(&path)?;
+            assert_eq!(loaded.image.width(), 16);
+            assert!(loaded.icc_profile.is_some(), "{name}");
+        }
+
+        // Spliced into what another encoder wrote
+        for (name, format) in [
+            ("spliced.png", ImageFormat::Png),
+            ("spliced.jpg", ImageFormat::Jpeg),
+        ] {
+            let mut encoded = std::io::Cursor::new(Vec::new());
+            image.write_to(&mut encoded, format)?;
+            let path = dir.path().join(name);
+            fs::write(&path, embed(encoded.get_ref(), format, &info())?)?;
+            assert_eq!(read(&path), Some(info()), "{name}");
+        }
+        assert!(embed(b"BM", ImageFormat::Bmp, &info()).is_err());
+
+        // Formats that can't carry them, and files without them, have none
+        let bmp = dir.path().join("out.bmp");
+        let options = OutputOptions {
+            parameters: Some(info()),
+            ..Default::default()
+        };
+        imageio::save(&image, &bmp, &options)?;
+        assert_eq!(read(&bmp), None);
+        let plain = dir.path().join("plain.png");
+        imageio::save(&image, &plain, &OutputOptions::default())?;
+        assert_eq!(read(&plain), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parameters_text_matches_other_tools() {
+        assert_eq!(
+            info().to_string(),
+            format!(
+                "red silk shirt\nNegative prompt: blurry, low quality\nSeed: 1234, \
+                 Model: org/model, Denoising strength: 0.8, Version: si {}",
+                crate::version::VERSION
+            )
+        );
+
+        let webui = "masterpiece, a knight in armor\nat sunset\n\
+                     Negative prompt: lowres, bad anatomy\n\
+                     Steps: 20, Sampler: DPM++ 2M, CFG scale: 7, Seed: 3141592653, \
+                     Size: 512x768, Model hash: 6ce0161689, Model: v1-5-pruned-emaonly, \
+                     Denoising strength: 0.45, Lora hashes: \"detail: 6b2e1c, style: 0a1f\", \
+                     Version: v1.6.0";
+        let parsed = ReproInfo::parse(webui).unwrap();
+        assert_eq!(parsed.prompt, "masterpiece, a knight in armor\nat sunset");
+        assert_eq!(
+            parsed.negative_prompt.as_deref(),
+            Some("lowres, bad anatomy")
+        );
+        assert_eq!(parsed.seed, Some(3_141_592_653));
+        assert_eq!(parsed.model.as_deref(), Some("v1-5-pruned-emaonly"));
+        assert_eq!(parsed.strength, Some(0.45));
+        assert_eq!(parsed.si_version, None);
+        let extra: Vec<_> = parsed.extra.iter().map(|(k, _)| k.as_str()).collect();
+        assert_eq!(
+            extra,
+            [
+                "Steps",
+                "Sampler",
+                "CFG scale",
+                "Size",
+                "Model hash",
+                "Lora hashes",
+                "Version"
+            ]
+        );
+        assert_eq!(parsed.extra[5].1, "detail: 6b2e1c, style: 0a1f");
+        // Written back, it reads the same
+        assert_eq!(ReproInfo::parse(&parsed.to_string()), Some(parsed));
+
+        assert_eq!(ReproInfo::parse("just a caption"), None);
+        assert_eq!(ReproInfo::parse(""), None);
+    }
+}
@@ -0,0 +1,285 @@
+//! Local-only usage metrics
+//!
+//! Nothing here talks to the network. Generations and downloads report a
+//! [`MetricEvent`] to a [`MetricsSink`]: [`FileSink`] appends them to
+//! `metrics.jsonl` in the data dir, rotated like the history file, and
+//! [`NoopSink`] drops them. Embedders can supply their own sink through
+//! [`ModelManagerBuilder::with_metrics`](crate::ModelManagerBuilder::with_metrics)
+//! and [`VirtualTryOn::with_metrics`](crate::VirtualTryOn::with_metrics).
+
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    history::{append_json_line, read_json_lines},
+    paths,
+};
+
+const METRICS_FILENAME: &str = "metrics.jsonl";
+pub const DEFAULT_MAX_METRICS_BYTES: u64 = 1024 * 1024;
+
+/// Something worth counting
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum MetricEvent {
+    /// One try-on, successful or not
+    Generation {
+        model: String,
+        duration_ms: u64,
+        success: bool,
+    },
+    /// A finished model download
+    Download {
+        model: String,
+        /// Bytes fetched, not counting files that were already cached
+        bytes: u64,
+        /// Size of every indexed model after the download
+        cache_bytes: u64,
+    },
+}
+
+/// A [`MetricEvent`] with the time it was recorded
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MetricRecord {
+    pub timestamp: DateTime<Utc>,
+    #[serde(flatten)]
+    pub event: MetricEvent,
+}
+
+/// Receives metric events; failures are logged by the caller and never fail the
+/// operation being measured
+pub trait MetricsSink: fmt::Debug + Send + Sync {
+    fn record(&self, event: MetricEvent) -> Result<()>;
+}
+
+/// Discards every event
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopSink;
+
+impl MetricsSink for NoopSink {
+    fn record(&self, _event: MetricEvent) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Appends events to a JSON lines file
+#[derive(Debug, Clone)]
+pub struct FileSink {
+    path: PathBuf,
+    max_bytes: u64,
+}
+
+impl FileSink {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            max_bytes: DEFAULT_MAX_METRICS_BYTES,
+        }
+    }
+
+    pub fn default_path() -> Result<PathBuf> {
+        Ok(paths::data_dir()
+            .context("Metrics directory is not set")?
+            .join(METRICS_FILENAME))
+    }
+
+    pub fn with_max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// All records, oldest first
+    pub fn records(&self) -> Result<Vec<MetricRecord>> {
+        read_json_lines(&self.path)
+    }
+}
+
+impl MetricsSink for FileSink {
+    fn record(&self, event: MetricEvent) -> Result<()> {
+        let record = MetricRecord {
+            timestamp: Utc::now(),
+            event,
+        };
+        append_json_line(&self.path, self.max_bytes, &record)
+    }
+}
+
+/// Aggregates over a window of [`MetricRecord`]s
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct MetricsSummary {
+    /// Start of the window, if it was limited
+    pub since: Option<DateTime<Utc>>,
+    /// Successful generations
+    pub generations: u64,
+    pub failed_generations: u64,
+    /// Mean duration of all generations, failed ones included
+    pub average_generation_ms: Option<u64>,
+    pub downloads: u64,
+    pub downloaded_bytes: u64,
+    /// Cache size after the first and the last download in the window
+    pub cache_bytes_first: Option<u64>,
+    pub cache_bytes_last: Option<u64>,
+}
+
+impl MetricsSummary {
+    /// Summarize the records at or after `since`
+    pub fn from_records<'a>(
+        records: impl IntoIterator<Item = &'a MetricRecord>,
+        since: Option<DateTime<Utc>>,
+    ) -> Self {
+        let mut summary = Self {
+            since,
+            ..Self::default()
+        };
+        let mut total_ms = 0;
+        for record in records {
+            if since.is_some_and(|since| record.timestamp < since) {
+                continue;
+            }
+            match &record.event {
+                MetricEvent::Generation {
+                    duration_ms,
+                    success,
+                    ..
+                } => {
+                    if *success {
+                        summary.generations += 1;
+                    } else {
+                        summary.failed_generations += 1;
+                    }
+                    total_ms += duration_ms;
+                }
+                MetricEvent::Download {
+                    bytes, cache_bytes, ..
+                } => {
+                    summary.downloads += 1;
+                    summary.downloaded_bytes += bytes;
+                    summary.cache_bytes_first.get_or_insert(*cache_bytes);
+                    summary.cache_bytes_last = Some(*cache_bytes);
+                }
+            }
+        }
+        let count = summary.generations + summary.failed_generations;
+        summary.average_generation_ms = (count > 0).then(|| total_ms / count);
+        summary
+    }
+}
+
+/// Parse a look-back window such as `30m`, `24h`, `7d` or `2w`
+pub fn parse_since(value: &str) -> Result<TimeDelta> {
+    let value = value.trim();
+    let Some(unit) = value.chars().last().filter(char::is_ascii_alphabetic) else {
+        bail!("Invalid duration `{value}`, expected a number followed by m, h, d or w");
+    };
+    let number: i64 = value[..value.len() - 1]
+        .parse()
+        .with_context(|| format!("Invalid duration `{value}`"))?;
+    if number < 0 {
+        bail!("Invalid duration `{value}`: must not be negative");
+    }
+    let delta = match unit {
+        'm' => TimeDelta::try_minutes(number),
+        'h' => TimeDelta::try_hours(number),
+        'd' => TimeDelta::try_days(number),
+        'w' => TimeDelta::try_weeks(number),
+        other => bail!("Unknown duration unit `{other}` in `{value}`, expected m, h, d or w"),
+    };
+    delta.with_context(|| format!("Duration `{value}` is too long"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_parse_since() -> Result<()> {
+        assert_eq!(parse_since("30m")?, TimeDelta::minutes(30));
+        assert_eq!(parse_since("24h")?, TimeDelta::hours(24));
+        assert_eq!(parse_since("7d")?, TimeDelta::days(7));
+        assert_eq!(parse_since(" 2w ")?, TimeDelta::weeks(2));
+        for invalid in ["", "7", "d", "7y", "-1d", "1.5d", "99999999999999w"] {
+            assert!(parse_since(invalid).is_err(), "{invalid:?}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_sink_round_trip() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let sink = FileSink::new(temp_dir.path().join("metrics.jsonl"));
+        assert!(sink.records()?.is_empty());
+
+        let event = MetricEvent::Download {
+            model: "org/model".to_string(),
+            bytes: 42,
+            cache_bytes: 1000,
+        };
+        sink.record(event.clone())?;
+        let records = sink.records()?;
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].event, event);
+
+        let line = std::fs::read_to_string(sink.path())?;
+        assert!(line.contains(r#""kind":"download""#));
+        Ok(())
+    }
+
+    #[test]
+    fn test_summary_respects_window() -> Result<()> {
+        let at = |timestamp: &str, event| -> Result<MetricRecord> {
+            Ok(MetricRecord {
+                timestamp: timestamp.parse()?,
+                event,
+            })
+        };
+        let generation = |duration_ms, success| MetricEvent::Generation {
+            model: "org/model".to_string(),
+            duration_ms,
+            success,
+        };
+        let download = |bytes, cache_bytes| MetricEvent::Download {
+            model: "org/model".to_string(),
+            bytes,
+            cache_bytes,
+        };
+        let records = [
+            at("2026-01-01T00:00:00Z", generation(1000, true))?,
+            at("2026-01-01T00:00:00Z", download(500, 500))?,
+            at("2026-02-01T00:00:00Z", generation(100, true))?,
+            at("2026-02-02T00:00:00Z", generation(300, false))?,
+            at("2026-02-03T00:00:00Z", download(200, 700))?,
+            at("2026-02-04T00:00:00Z", download(0, 650))?,
+        ];
+
+        let all = MetricsSummary::from_records(&records, None);
+        assert_eq!((all.generations, all.failed_generations), (2, 1));
+        assert_eq!(all.average_generation_ms, Some(466));
+        assert_eq!((all.downloads, all.downloaded_bytes), (3, 700));
+        assert_eq!(
+            (all.cache_bytes_first, all.cache_bytes_last),
+            (Some(500), Some(650))
+        );
+
+        let since = "2026-02-01T00:00:00Z".parse()?;
+        let recent = MetricsSummary::from_records(&records, Some(since));
+        assert_eq!((recent.generations, recent.failed_generations), (1, 1));
+        assert_eq!(recent.average_generation_ms, Some(200));
+        assert_eq!(recent.downloaded_bytes, 200);
+        assert_eq!(recent.cache_bytes_first, Some(700));
+
+        let empty = MetricsSummary::from_records(&[], None);
+        assert_eq!(empty.average_generation_ms, None);
+        Ok(())
+    }
+}
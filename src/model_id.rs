@@ -0,0 +1,296 @@
+//! Hugging Face model ids, checked before they reach the Hub
+//!
+//! A [`ModelId`] is `org/name`, or a bare `name` for the Hub's legacy
+//! canonical models such as `gpt2`. Each part is 1 to [`MAX_PART_LEN`] ASCII
+//! letters, digits, `-`, `_` and `.`, doesn't start or end with `-` or `.` and
+//! has no `--` or `..`, which the Hub reserves. Parsing trims whitespace and a
+//! trailing slash and reads the URL of a model page, such as
+//! `https://huggingface.co/org/name/tree/main`, as the id it shows; the URL is
+//! kept in [`ModelId::pasted_url`] so the CLI can say so.
+//!
+//! Ids keep their case, as the Hub shows it, but the Hub itself doesn't tell
+//! `Org/Name` from `org/name`, so neither do index lookups: compare ids with
+//! [`same_id`] or [`ModelId::matches`].
+
+use std::{
+    fmt,
+    hash::{Hash, Hasher},
+    ops::Deref,
+    str::FromStr,
+};
+
+use anyhow::{Result, bail};
+use serde::{Deserialize, Serialize};
+
+/// Longest organization or model name the Hub accepts
+pub const MAX_PART_LEN: usize = 96;
+
+/// Hosts whose model page URLs [`ModelId`] reads
+const HUB_HOSTS: [&str; 3] = ["huggingface.co/", "www.huggingface.co/", "hf.co/"];
+
+/// A checked Hugging Face model id
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct ModelId {
+    id: String,
+    /// The model page URL the id was read from
+    url: Option<String>,
+}
+
+impl ModelId {
+    pub fn as_str(&self) -> &str {
+        &self.id
+    }
+
+    /// The organization, `None` for a legacy id without one
+    pub fn org(&self) -> Option<&str> {
+        self.id.split_once('/').map(|(org, _)| org)
+    }
+
+    /// The model name, after the organization
+    pub fn name(&self) -> &str {
+        self.id.rsplit('/').next().unwrap_or(&self.id)
+    }
+
+    /// The id in lowercase, the same for every spelling the Hub resolves alike
+    pub fn normalized(&self) -> String {
+        self.id.to_ascii_lowercase()
+    }
+
+    /// Whether `other` names this model, whatever its case
+    pub fn matches(&self, other: &str) -> bool {
+        same_id(&self.id, other)
+    }
+
+    /// The model page URL this id was read from, when one was pasted
+    pub fn pasted_url(&self) -> Option<&str> {
+        self.url.as_deref()
+    }
+}
+
+/// Whether the model ids `a` and `b` name the same model, as the Hub would
+/// resolve them
+pub fn same_id(a: &str, b: &str) -> bool {
+    a.trim().eq_ignore_ascii_case(b.trim())
+}
+
+impl FromStr for ModelId {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let trimmed = s.trim();
+        let (path, url) = match strip_hub_url(trimmed) {
+            Some(path) => {
+                // A page under the model, such as its file tree, names the model
+                // by its first two segments
+                let path = path.split(['?', '#']).next().unwrap_or_default();
+                let end = path
+                    .match_indices('/')
+                    .nth(1)
+                    .map_or(path.len(), |(i, _)| i);
+                (&path[..end], Some(trimmed.to_string()))
+            }
+            None => (trimmed, None),
+        };
+        // A trailing slash after the name is harmless; after a bare
+        // organization it means the name is missing
+        let id = match path.strip_suffix('/') {
+            Some(id) if id.contains('/') => id,
+            _ => path,
+        };
+        if let Err(reason) = check(id) {
+            bail!("Invalid model id `{trimmed}`: {reason}; expected `org/name`");
+        }
+        Ok(Self {
+            id: id.to_string(),
+            url,
+        })
+    }
+}
+
+/// What follows the host of a Hub model page URL, or `None` when `s` isn't one
+fn strip_hub_url(s: &str) -> Option<&str> {
+    let rest = strip_prefix_ignore_case(s, "https://")
+        .or_else(|| strip_prefix_ignore_case(s, "http://"))
+        .unwrap_or(s);
+    HUB_HOSTS
+        .iter()
+        .find_map(|host| strip_prefix_ignore_case(rest, host))
+}
+
+fn strip_prefix_ignore_case<'a>(s: &'a str, prefix: &str) -> Option<&'a str> {
+    s.get(..prefix.len())
+        .filter(|start| start.eq_ignore_ascii_case(prefix))
+        .map(|_| &s[prefix.len()..])
+}
+
+/// Why `id` isn't a valid model id
+fn check(id: &str) -> std::result::Result<(), String> {
+    if id.is_empty() {
+        return Err("it is empty".to_string());
+    }
+    if id.contains("://") {
+        return Err("only Hugging Face model URLs can be used".to_string());
+    }
+    let parts: Vec<&str> = id.split('/').collect();
+    if parts.len() > 2 {
+        return Err("it has more than one `/`".to_string());
+    }
+    for part in parts {
+        if part.is_empty() {
+            return Err("the organization or name is empty".to_string());
+        }
+        if part.len() > MAX_PART_LEN {
+            return Err(format!("`{part}` is longer than {MAX_PART_LEN} characters"));
+        }
+        if let Some(c) = part
+            .chars()
+            .find(|c| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')))
+        {
+            return Err(format!("`{c}` isn't allowed"));
+        }
+        if part.starts_with(['-', '.']) || part.ends_with(['-', '.']) {
+            return Err(format!("`{part}` starts or ends with `-` or `.`"));
+        }
+        if part.contains("--") || part.contains("..") {
+            return Err(format!("`{part}` has `--` or `..`"));
+        }
+    }
+    Ok(())
+}
+
+impl TryFrom<String> for ModelId {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<ModelId> for String {
+    fn from(id: ModelId) -> Self {
+        id.id
+    }
+}
+
+impl fmt::Display for ModelId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.id)
+    }
+}
+
+impl Deref for ModelId {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.id
+    }
+}
+
+impl AsRef<str> for ModelId {
+    fn as_ref(&self) -> &str {
+        &self.id
+    }
+}
+
+/// Ids are equal when spelled alike; where they came from doesn't matter
+impl PartialEq for ModelId {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for ModelId {}
+
+impl Hash for ModelId {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Result<ModelId> {
+        s.parse()
+    }
+
+    #[test]
+    fn test_parse_trims_and_keeps_case() -> Result<()> {
+        let id = parse("  Runwayml/Stable-Diffusion-V1-5 \n")?;
+        assert_eq!(id.as_str(), "Runwayml/Stable-Diffusion-V1-5");
+        assert_eq!(id.org(), Some("Runwayml"));
+        assert_eq!(id.name(), "Stable-Diffusion-V1-5");
+        assert_eq!(id.normalized(), "runwayml/stable-diffusion-v1-5");
+        assert!(id.matches("runwayml/stable-diffusion-v1-5"));
+        assert_ne!(id, parse("runwayml/stable-diffusion-v1-5")?);
+        assert!(id.pasted_url().is_none());
+
+        assert_eq!(parse("org/name/")?.as_str(), "org/name");
+        let legacy = parse("gpt2")?;
+        assert_eq!((legacy.org(), legacy.name()), (None, "gpt2"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_reads_model_page_urls() -> Result<()> {
+        for url in [
+            "https://huggingface.co/org/name",
+            "https://huggingface.co/org/name/",
+            "http://www.huggingface.co/org/name",
+            "HTTPS://HuggingFace.co/org/name",
+            "huggingface.co/org/name",
+            "https://hf.co/org/name",
+            "https://huggingface.co/org/name/tree/main/unet",
+            "https://huggingface.co/org/name/blob/main/model_index.json",
+            "https://huggingface.co/org/name?library=diffusers",
+            "https://huggingface.co/org/name#usage",
+        ] {
+            let id = parse(url)?;
+            assert_eq!(id.as_str(), "org/name", "{url}");
+            assert_eq!(id.pasted_url(), Some(url), "{url}");
+        }
+        assert!(parse("https://example.com/org/name").is_err());
+        assert!(parse("https://huggingface.co/").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_rejects_invalid_ids() {
+        let long = format!("org/{}", "a".repeat(MAX_PART_LEN + 1));
+        for invalid in [
+            "",
+            "   ",
+            "/name",
+            "org/",
+            "org//name",
+            "a/b/c",
+            "org/na me",
+            "org/name!",
+            "org/naïve",
+            "-org/name",
+            "org/name.",
+            "org/na--me",
+            "org/na..me",
+            long.as_str(),
+        ] {
+            let err = parse(invalid).unwrap_err();
+            assert!(
+                err.to_string().starts_with("Invalid model id"),
+                "{invalid:?}: {err}"
+            );
+        }
+        assert!(parse(&format!("org/{}", "a".repeat(MAX_PART_LEN))).is_ok());
+    }
+
+    #[test]
+    fn test_serializes_as_the_id() -> Result<()> {
+        let id = parse("https://huggingface.co/org/name")?;
+        assert_eq!(serde_json::to_string(&id)?, r#""org/name""#);
+        let back: ModelId = serde_json::from_str(r#"" org/name ""#)?;
+        assert_eq!(back, id);
+        assert!(serde_json::from_str::<ModelId>(r#""a/b/c""#).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,135 @@
+//! What a CLI command prints as its result
+//!
+//! Commands don't print their result as they go; they return a
+//! [`CommandOutput`] holding both renderings of it: the text people read and the
+//! JSON document `si --json` prints instead. Either way the result is the only
+//! thing on stdout, so with `--json` it parses as a whole; progress and other
+//! messages go to stderr.
+
+use std::{
+    fmt,
+    io::{self, Write},
+};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use serde_json::Value;
+
+/// The result of a command, rendered as text or as JSON
+#[derive(Debug)]
+pub struct CommandOutput {
+    json: Value,
+    text: String,
+    failure: Option<String>,
+}
+
+impl CommandOutput {
+    /// `value` as the JSON document, with no text yet
+    pub fn new(value: &impl Serialize) -> Result<Self> {
+        Ok(Self {
+            json: serde_json::to_value(value).context("Failed to serialize the result")?,
+            text: String::new(),
+            failure: None,
+        })
+    }
+
+    /// Add a line to the text
+    pub fn line(mut self, line: impl fmt::Display) -> Self {
+        self.push_line(line);
+        self
+    }
+
+    /// Add a line to the text
+    pub fn push_line(&mut self, line: impl fmt::Display) {
+        self.push_str(line);
+        self.text.push('\n');
+    }
+
+    /// Add `text` as it is, without a line break
+    pub fn push_str(&mut self, text: impl fmt::Display) {
+        use fmt::Write as _;
+        // Writing to a String can't fail
+        let _ = write!(self.text, "{text}");
+    }
+
+    /// Mark the command as failed with `message`, after printing the result
+    ///
+    /// For commands whose report is worth reading even when they fail, such as a
+    /// verification listing the files that didn't match.
+    pub fn with_failure(mut self, message: impl fmt::Display) -> Self {
+        self.failure = Some(message.to_string());
+        self
+    }
+
+    /// The JSON document
+    pub fn json(&self) -> &Value {
+        &self.json
+    }
+
+    /// The text for people
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Write the result to `out`: the JSON document when `json` is set, else the
+    /// text
+    pub fn write_to(&self, json: bool, mut out: impl Write) -> io::Result<()> {
+        if json {
+            serde_json::to_writer_pretty(&mut out, &self.json)?;
+            writeln!(out)?;
+        } else {
+            out.write_all(self.text.as_bytes())?;
+        }
+        out.flush()
+    }
+
+    /// Print the result on stdout, then fail if the command did
+    pub fn print(self, json: bool) -> Result<()> {
+        self.write_to(json, io::stdout().lock())
+            .context("Failed to write to stdout")?;
+        match self.failure {
+            Some(message) => Err(anyhow::Error::msg(message)),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_text_and_json_renderings() -> Result<()> {
+        let output = CommandOutput::new(&json!({"model_id": "org/model", "pinned": true}))?
+            .line("Pinned org/model.");
+
+        let mut text = Vec::new();
+        output.write_to(false, &mut text)?;
+        assert_eq!(String::from_utf8(text)?, "Pinned org/model.\n");
+
+        let mut document = Vec::new();
+        output.write_to(true, &mut document)?;
+        let parsed: Value = serde_json::from_slice(&document)?;
+        assert_eq!(parsed, json!({"model_id": "org/model", "pinned": true}));
+        Ok(())
+    }
+
+    #[test]
+    fn test_push_str_keeps_text_as_is() -> Result<()> {
+        let mut output = CommandOutput::new(&Value::Null)?;
+        output.push_str("key = 1\n");
+        output.push_str("other");
+        output.push_line(" = 2");
+        assert_eq!(output.text(), "key = 1\nother = 2\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_failure_is_reported_after_printing() -> Result<()> {
+        let output = CommandOutput::new(&json!([]))?.with_failure("Model x failed verification");
+        let err = output.print(true).unwrap_err();
+        assert_eq!(err.to_string(), "Model x failed verification");
+        Ok(())
+    }
+}
@@ -0,0 +1,138 @@
+//! Where si keeps its files
+//!
+//! Every function here reads the environment when called, so changes to
+//! `SI_DATA_DIR`, `XDG_*`, `HF_HOME` or `HF_HUB_CACHE` take effect immediately.
+//!
+//! - data (model index, history): `$SI_DATA_DIR`, else the platform data dir
+//! - config: the platform config dir
+//! - caches of computed files (masks): `$SI_CACHE_DIR`, else the platform cache
+//!   dir
+//! - Hugging Face cache: `$HF_HUB_CACHE`, else `$HF_HOME/hub`, else
+//!   `~/.cache/huggingface/hub`
+//! - Hugging Face token: `$HF_TOKEN_PATH`, else `token` next to the `hub`
+//!   directory of `$HF_HOME` or `~/.cache/huggingface`
+
+use std::{ffi::OsString, path::PathBuf};
+
+use anyhow::{Context, Result};
+use directories::{BaseDirs, ProjectDirs};
+
+pub const DATA_DIR_ENV: &str = "SI_DATA_DIR";
+pub const CACHE_DIR_ENV: &str = "SI_CACHE_DIR";
+pub const HF_HOME_ENV: &str = "HF_HOME";
+pub const HF_HUB_CACHE_ENV: &str = "HF_HUB_CACHE";
+pub const HF_TOKEN_PATH_ENV: &str = "HF_TOKEN_PATH";
+
+fn project_dirs() -> Option<ProjectDirs> {
+    ProjectDirs::from("", "", "si")
+}
+
+fn non_empty(var: &str) -> Option<OsString> {
+    std::env::var_os(var).filter(|v| !v.is_empty())
+}
+
+/// Directory holding the model index and history
+pub fn data_dir() -> Result<PathBuf> {
+    data_dir_from(non_empty(DATA_DIR_ENV))
+}
+
+fn data_dir_from(si_data_dir: Option<OsString>) -> Result<PathBuf> {
+    match si_data_dir {
+        Some(dir) => Ok(PathBuf::from(dir)),
+        None => project_dirs()
+            .map(|p| p.data_dir().to_path_buf())
+            .context("Data directory is not set"),
+    }
+}
+
+/// Directory holding `config.toml`
+pub fn config_dir() -> Result<PathBuf> {
+    project_dirs()
+        .map(|p| p.config_dir().to_path_buf())
+        .context("Config directory is not set")
+}
+
+/// Directory holding caches that can be deleted at any time
+pub fn cache_dir() -> Result<PathBuf> {
+    match non_empty(CACHE_DIR_ENV) {
+        Some(dir) => Ok(PathBuf::from(dir)),
+        None => project_dirs()
+            .map(|p| p.cache_dir().to_path_buf())
+            .context("Cache directory is not set"),
+    }
+}
+
+/// The Hugging Face hub cache, honoring `HF_HUB_CACHE` and `HF_HOME`
+pub fn hf_cache_dir() -> PathBuf {
+    hf_cache_dir_from(
+        non_empty(HF_HUB_CACHE_ENV),
+        non_empty(HF_HOME_ENV),
+        BaseDirs::new().map(|b| b.home_dir().to_path_buf()),
+    )
+}
+
+fn hf_cache_dir_from(
+    hub_cache: Option<OsString>,
+    hf_home: Option<OsString>,
+    home: Option<PathBuf>,
+) -> PathBuf {
+    if let Some(dir) = hub_cache {
+        return PathBuf::from(dir);
+    }
+    hf_home_from(hf_home, home).join("hub")
+}
+
+/// The file `huggingface-cli login` stores the Hub token in
+pub fn hf_token_path() -> PathBuf {
+    non_empty(HF_TOKEN_PATH_ENV)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| {
+            hf_home_from(
+                non_empty(HF_HOME_ENV),
+                BaseDirs::new().map(|b| b.home_dir().to_path_buf()),
+            )
+            .join("token")
+        })
+}
+
+fn hf_home_from(hf_home: Option<OsString>, home: Option<PathBuf>) -> PathBuf {
+    hf_home
+        .map(PathBuf::from)
+        .unwrap_or_else(|| home.unwrap_or_default().join(".cache").join("huggingface"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_si_data_dir_overrides_platform_dir() -> Result<()> {
+        assert_eq!(
+            data_dir_from(Some("/srv/si".into()))?,
+            PathBuf::from("/srv/si")
+        );
+        // Without the override we fall back to `directories`, which is always
+        // available when a home directory exists
+        if let Some(dirs) = project_dirs() {
+            assert_eq!(data_dir_from(None)?, dirs.data_dir());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_hf_cache_dir_precedence() {
+        let home = Some(PathBuf::from("/home/me"));
+        assert_eq!(
+            hf_cache_dir_from(Some("/cache/hub".into()), Some("/hf".into()), home.clone()),
+            PathBuf::from("/cache/hub")
+        );
+        assert_eq!(
+            hf_cache_dir_from(None, Some("/hf".into()), home.clone()),
+            PathBuf::from("/hf/hub")
+        );
+        assert_eq!(
+            hf_cache_dir_from(None, None, home),
+            PathBuf::from("/home/me/.cache/huggingface/hub")
+        );
+    }
+}
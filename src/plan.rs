@@ -0,0 +1,388 @@
+//! What a try-on would do, resolved without doing it
+//!
+//! [`VirtualTryOn::plan`] resolves a [`TryOnRequest`] the same way
+//! [`VirtualTryOn::try_on`] does: the model and strength it falls back to, the
+//! crop, the output size, and how much of the input the clothing mask covers.
+//! Nothing is downloaded or written; problems that would make the try-on fail or
+//! surprise are collected as warnings instead of errors.
+
+use std::{fmt, path::PathBuf};
+
+use anyhow::Result;
+use image::{ImageFormat, imageops::FilterType};
+use serde::Serialize;
+
+use crate::{
+    icc,
+    planner::TransformPlan,
+    segment::{Region, mask_coverage},
+    tryon::{MaskSource, Strengths, TryOnRequest, VirtualTryOn, drawn_mask},
+};
+
+/// Longest side of the downscaled copy the mask coverage is estimated on
+const COVERAGE_SAMPLE_SIZE: u32 = 256;
+
+/// The fully resolved settings of a try-on, from [`VirtualTryOn::plan`]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GenerationPlan {
+    pub model: String,
+    /// Whether the model is in the index; otherwise it's downloaded first
+    pub model_downloaded: bool,
+    pub prompt: String,
+    pub negative_prompt: Option<String>,
+    pub strength: f64,
+    /// Strength of the color change, `strength` unless given
+    pub color_strength: f64,
+    /// Strength of the style adjustments, `strength` unless given
+    pub style_strength: f64,
+    /// The strength suggested for the garment when the request leaves it to
+    /// [`VirtualTryOn::with_auto_strength`], estimated like `mask_coverage`
+    pub suggested_strength: Option<f64>,
+    /// The generation seed; the built-in pipeline is deterministic and uses none
+    pub seed: Option<u64>,
+    pub input: PathBuf,
+    /// `None` when the input can't be read
+    pub input_size: Option<(u32, u32)>,
+    /// The part of the input that is processed, when it's cropped
+    pub crop: Option<Region>,
+    pub output: PathBuf,
+    pub output_size: Option<(u32, u32)>,
+    /// Estimated fraction of the processed image the clothing mask covers
+    pub mask_coverage: Option<f64>,
+    pub warnings: Vec<String>,
+}
+
+impl VirtualTryOn {
+    /// Resolve `request` into a [`GenerationPlan`] without generating anything
+    ///
+    /// Only fails when the model index can't be read; an unreadable input, bad
+    /// crop or prompt becomes a warning in the plan.
+    pub fn plan(&self, request: &TryOnRequest) -> Result<GenerationPlan> {
+        let model = request.model();
+        let model_downloaded = self.is_downloaded(model)?;
+        let mut plan = GenerationPlan {
+            model: model.to_string(),
+            model_downloaded,
+            prompt: request.clothing_description.clone(),
+            negative_prompt: request.negative_prompt.clone(),
+            strength: request.effective_strength(),
+            color_strength: request.effective_color_strength(),
+            style_strength: request.effective_style_strength(),
+            suggested_strength: None,
+            seed: None,
+            input: request.input_image_path.clone(),
+            input_size: None,
+            crop: None,
+            output: request.output_path.clone(),
+            output_size: None,
+            mask_coverage: None,
+            warnings: Vec::new(),
+        };
+
+        if !model_downloaded {
+            plan.warnings.push(format!(
+                "Model {model} isn't downloaded yet and will be downloaded first"
+            ));
+        }
+        let transform = match self.transform_plan(request) {
+            Ok(transform) => transform,
+            Err(e) => {
+                plan.warnings.push(format!("{e:#}"));
+                TransformPlan::default()
+            }
+        };
+        let missing_reference = request.reference_image.as_ref().filter(|r| !r.exists());
+        if let Some(reference) = missing_reference {
+            plan.warnings.push(format!(
+                "Reference image does not exist: {}",
+                reference.display()
+            ));
+        }
+        if let Err(e) = self.validate_output(&request.output_path) {
+            plan.warnings.push(format!("{e:#}"));
+        } else if request.output_path.exists() {
+            plan.warnings.push(format!(
+                "{} already exists and will be overwritten",
+                request.output_path.display()
+            ));
+        }
+
+        let image = match self.load_image(&request.input_image_path) {
+            Ok((loaded, load_warnings)) => {
+                plan.warnings.extend(load_warnings.iter().map(|w| {
+                    format!(
+                        "{} will be recovered: {w}",
+                        request.input_image_path.display()
+                    )
+                }));
+                let output_format = ImageFormat::from_path(&request.output_path).ok();
+                if self.output_profile(&loaded).is_some()
+                    && output_format.is_some_and(|format| !icc::can_embed(format))
+                {
+                    plan.warnings.push(format!(
+                        "{} can't carry the input's ICC color profile; colors may shift",
+                        request.output_path.display()
+                    ));
+                }
+                loaded.image
+            }
+            Err(e) => {
+                plan.warnings.push(format!("{e:#}"));
+                return Ok(plan);
+            }
+        };
+        let input_size = (image.width(), image.height());
+        plan.input_size = Some(input_size);
+        let (image, crop) = match self.preprocess(image, request) {
+            Ok(preprocessed) => preprocessed,
+            Err(e) => {
+                plan.warnings.push(format!("{e:#}"));
+                return Ok(plan);
+            }
+        };
+        if request.crop.is_some() && crop.is_none() {
+            plan.warnings.push(format!(
+                "No clothing found in {}, it will be processed uncropped",
+                request.input_image_path.display()
+            ));
+        }
+        plan.crop = crop;
+        plan.output_size = Some((image.width(), image.height()));
+
+        // The mask of a downscaled copy is close enough and much cheaper
+        let sample = if image.width().max(image.height()) > COVERAGE_SAMPLE_SIZE {
+            image.resize(
+                COVERAGE_SAMPLE_SIZE,
+                COVERAGE_SAMPLE_SIZE,
+                FilterType::Triangle,
+            )
+        } else {
+            image
+        };
+        // Not from the mask cache, which is keyed on the full-size image
+        let region_mask = request.region_mask(&transform);
+        let source = MaskSource {
+            drawn: drawn_mask(
+                region_mask,
+                input_size,
+                crop,
+                (sample.width(), sample.height()),
+            ),
+            cache_key: None,
+        };
+        let sample = sample.to_rgb8();
+        match self.clothing_mask(&sample, &source) {
+            Ok(mask) => {
+                let suggested = self.suggested_strength(request, &sample, &mask, &transform);
+                if suggested.is_some() {
+                    let strengths = Strengths::of(request, suggested);
+                    plan.strength = strengths.strength;
+                    plan.color_strength = strengths.color;
+                    plan.style_strength = strengths.style;
+                    plan.suggested_strength = suggested;
+                }
+                let coverage = mask_coverage(&mask);
+                if coverage == 0.0 && region_mask.is_some() {
+                    plan.warnings.push(
+                        "The polygon covers no detected clothing, the output will match the input"
+                            .to_string(),
+                    );
+                } else if coverage == 0.0 {
+                    plan.warnings.push(
+                        "No clothing detected in the input, the output will match it".to_string(),
+                    );
+                }
+                plan.mask_coverage = Some(coverage);
+            }
+            Err(e) => plan.warnings.push(format!("{e:#}")),
+        }
+        Ok(plan)
+    }
+}
+
+/// One `Key: value` line per setting, then one line per warning
+impl fmt::Display for GenerationPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let size = |size: Option<(u32, u32)>| match size {
+            Some((width, height)) => format!(" ({width}x{height})"),
+            None => String::new(),
+        };
+        writeln!(
+            f,
+            "Model:         {}{}",
+            self.model,
+            if self.model_downloaded {
+                ""
+            } else {
+                " (not downloaded)"
+            }
+        )?;
+        writeln!(f, "Prompt:        {}", self.prompt)?;
+        if let Some(negative) = &self.negative_prompt {
+            writeln!(f, "Negative:      {negative}")?;
+        }
+        let auto = if self.suggested_strength.is_some() {
+            " (auto)"
+        } else {
+            ""
+        };
+        if (self.color_strength, self.style_strength) == (self.strength, self.strength) {
+            writeln!(f, "Strength:      {:.2}{auto}", self.strength)?;
+        } else {
+            writeln!(
+                f,
+                "Strength:      {:.2} color, {:.2} style{auto}",
+                self.color_strength, self.style_strength
+            )?;
+        }
+        match self.seed {
+            Some(seed) => writeln!(f, "Seed:          {seed}")?,
+            None => writeln!(f, "Seed:          none")?,
+        }
+        writeln!(
+            f,
+            "Input:         {}{}",
+            self.input.display(),
+            size(self.input_size)
+        )?;
+        if let Some(crop) = self.crop {
+            writeln!(
+                f,
+                "Crop:          {}x{} at {},{}",
+                crop.width, crop.height, crop.x, crop.y
+            )?;
+        }
+        write!(
+            f,
+            "Output:        {}{}",
+            self.output.display(),
+            size(self.output_size)
+        )?;
+        if let Some(coverage) = self.mask_coverage {
+            write!(f, "\nMask coverage: {:.1}%", coverage * 100.0)?;
+        }
+        for warning in &self.warnings {
+            write!(f, "\nWarning: {warning}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Crop, CropSpec, ModelManagerBuilder, test_support::person_scene};
+    use std::{fs, path::Path};
+    use tempfile::tempdir;
+
+    fn tryon(root: &Path) -> Result<VirtualTryOn> {
+        let models_dir = root.join("models");
+        fs::create_dir_all(&models_dir)?;
+        fs::write(
+            models_dir.join("model_index.json"),
+            r#"{"models": [{"model_id": "test/model", "files": []}]}"#,
+        )?;
+        VirtualTryOn::new(
+            ModelManagerBuilder::new()
+                .with_models_dir(models_dir)
+                .build()?,
+        )
+    }
+
+    fn request(root: &Path) -> TryOnRequest {
+        TryOnRequest {
+            input_image_path: root.join("person.png"),
+            clothing_description: "red shirt".to_string(),
+            negative_prompt: None,
+            output_path: root.join("out/person_red.png"),
+            model_name: None,
+            strength: None,
+            color_strength: None,
+            style_strength: None,
+            reference_image: None,
+            crop: None,
+            mask: None,
+            transform_plan: None,
+            comparison: None,
+            remote_input: None,
+            seed: None,
+        }
+    }
+
+    #[test]
+    fn test_plan_resolves_defaults_and_overrides() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let root = temp_dir.path();
+        let tryon = tryon(root)?;
+        person_scene(400, 600).save(root.join("person.png"))?;
+
+        let plan = tryon.plan(&request(root))?;
+        assert_eq!(plan.model, crate::tryon::DEFAULT_MODEL);
+        assert!(!plan.model_downloaded);
+        assert_eq!(plan.strength, crate::tryon::DEFAULT_STRENGTH);
+        assert_eq!(plan.input_size, Some((400, 600)));
+        assert_eq!(plan.output_size, Some((400, 600)));
+        let coverage = plan.mask_coverage.unwrap();
+        assert!((0.05..0.5).contains(&coverage), "{coverage}");
+        assert_eq!(plan.warnings.len(), 1, "{:?}", plan.warnings);
+        assert!(plan.warnings[0].contains("will be downloaded"));
+
+        let plan = tryon.plan(&TryOnRequest {
+            model_name: Some("test/model".to_string()),
+            strength: Some(0.8),
+            crop: Some(Crop::Region("0,0,50%,50%".parse::<CropSpec>()?)),
+            ..request(root)
+        })?;
+        assert_eq!(plan.model, "test/model");
+        assert!(plan.model_downloaded);
+        assert_eq!(plan.strength, 0.8);
+        assert_eq!(plan.output_size, Some((200, 300)));
+        assert!(plan.warnings.is_empty(), "{:?}", plan.warnings);
+        let text = plan.to_string();
+        assert!(text.contains("Strength:      0.80"), "{text}");
+        assert!(text.contains("Crop:          200x300 at 0,0"), "{text}");
+
+        let plan = tryon.plan(&TryOnRequest {
+            strength: Some(0.8),
+            style_strength: Some(0.2),
+            ..request(root)
+        })?;
+        assert_eq!((plan.color_strength, plan.style_strength), (0.8, 0.2));
+        let text = plan.to_string();
+        assert!(
+            text.contains("Strength:      0.80 color, 0.20 style"),
+            "{text}"
+        );
+
+        // Planning never writes the output, nor its directory
+        assert!(!root.join("out").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_plan_reports_problems_as_warnings() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let root = temp_dir.path();
+        let tryon = tryon(root)?;
+        fs::write(root.join("existing.png"), "")?;
+
+        let plan = tryon.plan(&TryOnRequest {
+            model_name: Some("test/model".to_string()),
+            output_path: root.join("existing.png"),
+            reference_image: Some(root.join("missing.jpg")),
+            ..request(root)
+        })?;
+        assert_eq!(plan.input_size, None);
+        assert_eq!(plan.mask_coverage, None);
+        assert_eq!(plan.warnings.len(), 3, "{:?}", plan.warnings);
+        assert!(plan.warnings[0].contains("Reference image does not exist"));
+        assert!(plan.warnings[1].contains("will be overwritten"));
+        assert!(plan.warnings[2].contains("does not exist"));
+
+        let json = serde_json::to_value(&plan)?;
+        assert_eq!(json["model"], "test/model");
+        assert_eq!(json["seed"], serde_json::Value::Null);
+        assert_eq!(json["warnings"].as_array().map(Vec::len), Some(3));
+        Ok(())
+    }
+}
@@ -0,0 +1,267 @@
+//! Turning a garment description into the transformation to apply
+//!
+//! A [`TransformPlanner`] reads the description of a [`TryOnRequest`] and
+//! returns a [`TransformPlan`]: the color change, the fabric's contrast and
+//! brightness, and optionally where the garment is. [`KeywordPlanner`], the
+//! default, matches color keywords and [`StyleRegistry`] presets against the
+//! positive prompt terms. Applications that understand descriptions better can
+//! pass their own planner to [`VirtualTryOn::with_planner`], or skip planning
+//! for a single request by setting [`TryOnRequest::transform_plan`].
+//!
+//! [`TryOnRequest`]: crate::TryOnRequest
+//! [`TryOnRequest::transform_plan`]: crate::TryOnRequest::transform_plan
+//! [`VirtualTryOn::with_planner`]: crate::VirtualTryOn::with_planner
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    mask::RegionMask,
+    prompt::ParsedPrompt,
+    styles::{StylePreset, StyleRegistry},
+};
+
+/// `(hue_shift, saturation_mult, lightness_mult)`
+type HslShift = (f32, f32, f32);
+
+/// Color keywords and their [`HslShift`]
+const COLOR_TRANSFORMS: &[(&[&str], HslShift)] = &[
+    (&["red"], (0.0, 1.3, 1.0)),                // Enhance red
+    (&["blue"], (240.0, 1.2, 0.95)),            // Shift towards blue
+    (&["green"], (120.0, 1.2, 1.0)),            // Shift towards green
+    (&["yellow"], (60.0, 1.4, 1.1)),            // Shift towards yellow, brighten
+    (&["purple", "violet"], (280.0, 1.3, 0.9)), // Shift towards purple
+    (&["orange"], (30.0, 1.3, 1.05)),           // Shift towards orange
+    (&["pink"], (320.0, 1.2, 1.1)),             // Shift towards pink, brighten
+    (&["black"], (0.0, 0.8, 0.4)),              // Darken significantly
+    (&["white"], (0.0, 0.5, 1.6)),              // Desaturate and brighten
+    (&["gray", "grey"], (0.0, 0.3, 0.8)),       // Desaturate and slightly darken
+];
+
+/// The color change applied inside the clothing mask, in HSL
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ColorTransform {
+    /// Degrees added to the hue at full color strength
+    pub hue_shift: f32,
+    pub saturation_mult: f32,
+    pub lightness_mult: f32,
+}
+
+impl ColorTransform {
+    /// Leaves colors as they are
+    pub const NEUTRAL: Self = Self::new(0.0, 1.0, 1.0);
+
+    pub const fn new(hue_shift: f32, saturation_mult: f32, lightness_mult: f32) -> Self {
+        Self {
+            hue_shift,
+            saturation_mult,
+            lightness_mult,
+        }
+    }
+}
+
+impl Default for ColorTransform {
+    fn default() -> Self {
+        Self::NEUTRAL
+    }
+}
+
+/// The fabric's effect on the recolored garment, as a [`StylePreset`] has it
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct StyleAdjustments {
+    /// Contrast multiplier, 1 for none
+    pub contrast: f32,
+    /// Brightness offset, 0 for none
+    pub brightness: f32,
+}
+
+impl StyleAdjustments {
+    /// Leaves contrast and brightness as they are
+    pub const NEUTRAL: Self = Self {
+        contrast: 1.0,
+        brightness: 0.0,
+    };
+}
+
+impl Default for StyleAdjustments {
+    fn default() -> Self {
+        Self::NEUTRAL
+    }
+}
+
+impl From<&StylePreset> for StyleAdjustments {
+    fn from(preset: &StylePreset) -> Self {
+        let (contrast, brightness) = preset.adjustments();
+        Self {
+            contrast,
+            brightness,
+        }
+    }
+}
+
+/// Everything the pipeline needs to know about the garment to render it
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TransformPlan {
+    #[serde(default)]
+    pub color: ColorTransform,
+    #[serde(default)]
+    pub style: StyleAdjustments,
+    /// Where the garment is, used like [`TryOnRequest::mask`] when the request
+    /// has no mask of its own; grids share one mask and ignore it
+    ///
+    /// [`TryOnRequest::mask`]: crate::TryOnRequest::mask
+    #[serde(default)]
+    pub mask_hint: Option<RegionMask>,
+    /// Texture of the garment's fabric preset, a built-in texture name or an
+    /// image path, laid over it by `--texture auto`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub texture: Option<String>,
+}
+
+/// Decides how a garment description changes the image
+///
+/// Called once per rendered description, possibly from several threads at once.
+/// An error fails the try-on with it.
+pub trait TransformPlanner: Send + Sync {
+    fn plan(&self, description: &str) -> Result<TransformPlan>;
+}
+
+/// The built-in planner: the heaviest color keyword and fabric preset among the
+/// positive terms of the parsed description
+#[derive(Debug, Clone)]
+pub struct KeywordPlanner {
+    styles: StyleRegistry,
+}
+
+impl KeywordPlanner {
+    /// A planner taking fabric adjustments from `styles`
+    pub fn new(styles: StyleRegistry) -> Self {
+        Self { styles }
+    }
+
+    /// The plan for an already parsed description
+    pub fn plan_prompt(&self, prompt: &ParsedPrompt) -> TransformPlan {
+        let preset = self.styles.match_prompt(prompt);
+        TransformPlan {
+            color: self.color_transform(prompt),
+            style: preset.unwrap_or(&StylePreset::NEUTRAL).into(),
+            mask_hint: None,
+            texture: preset.and_then(|p| p.texture.clone()),
+        }
+    }
+
+    /// The color adjustment for the most heavily weighted color among the
+    /// positive terms; on a tie the earlier color in [`COLOR_TRANSFORMS`] wins
+    pub fn color_transform(&self, prompt: &ParsedPrompt) -> ColorTransform {
+        strongest_match(prompt, COLOR_TRANSFORMS)
+            .map(|(hue_shift, saturation_mult, lightness_mult)| {
+                ColorTransform::new(hue_shift, saturation_mult, lightness_mult)
+            })
+            .unwrap_or_default()
+    }
+
+    /// The adjustments of the style preset for the most heavily weighted fabric
+    /// among the positive terms; neutral when none is known
+    pub fn style_adjustments(&self, prompt: &ParsedPrompt) -> StyleAdjustments {
+        self.styles
+            .match_prompt(prompt)
+            .unwrap_or(&StylePreset::NEUTRAL)
+            .into()
+    }
+}
+
+impl Default for KeywordPlanner {
+    fn default() -> Self {
+        Self::new(StyleRegistry::builtin())
+    }
+}
+
+impl TransformPlanner for KeywordPlanner {
+    fn plan(&self, description: &str) -> Result<TransformPlan> {
+        Ok(self.plan_prompt(&ParsedPrompt::parse(description)?))
+    }
+}
+
+/// The value of the keyword group whose heaviest positive term outweighs the
+/// others, preferring earlier groups on a tie
+fn strongest_match<T: Copy>(prompt: &ParsedPrompt, table: &[(&[&str], T)]) -> Option<T> {
+    let mut best: Option<(f32, T)> = None;
+    for (keywords, value) in table {
+        let weight = keywords
+            .iter()
+            .filter_map(|keyword| prompt.positive_weight(keyword))
+            .reduce(f32::max);
+        if let Some(weight) = weight.filter(|w| *w > 0.0) {
+            if best.is_none_or(|(best_weight, _)| weight > best_weight) {
+                best = Some((weight, *value));
+            }
+        }
+    }
+    best.map(|(_, value)| value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_color_transform() {
+        let planner = KeywordPlanner::default();
+        let transform =
+            |prompt: &str| planner.color_transform(&ParsedPrompt::parse(prompt).unwrap());
+
+        let red_transform = transform("red dress");
+        assert_eq!(red_transform.hue_shift, 0.0);
+        assert!(red_transform.saturation_mult > 1.0);
+
+        let blue_transform = transform("blue shirt");
+        assert_eq!(blue_transform.hue_shift, 240.0);
+
+        // A heavier weight beats the keyword order, and negative terms are ignored
+        assert_eq!(transform("red dress, (blue:1.5) trim").hue_shift, 240.0);
+        assert_eq!(transform("dress [neg: blue]").hue_shift, 0.0);
+        assert_eq!(transform("dress [neg: blue]").saturation_mult, 1.0);
+        assert_eq!(transform("(red:0), green dress").hue_shift, 120.0);
+    }
+
+    #[test]
+    fn test_style_adjustments() {
+        let planner = KeywordPlanner::default();
+        let adjustments =
+            |prompt: &str| planner.style_adjustments(&ParsedPrompt::parse(prompt).unwrap());
+
+        let silk = adjustments("silk dress");
+        assert!(silk.contrast > 1.0);
+        assert!(silk.brightness > 0.0);
+
+        let leather = adjustments("leather jacket");
+        assert!(leather.contrast > 1.0);
+        assert!(leather.brightness < 0.0);
+
+        assert_eq!(
+            adjustments("jacket [neg: leather]"),
+            StyleAdjustments::NEUTRAL
+        );
+    }
+
+    #[test]
+    fn test_plan_parses_the_description() -> Result<()> {
+        let plan = KeywordPlanner::default().plan("(blue:1.2) silk shirt")?;
+        assert_eq!(plan.color.hue_shift, 240.0);
+        assert!(plan.style.contrast > 1.0);
+        assert_eq!(plan.mask_hint, None);
+        assert_eq!(plan.texture, None);
+        let denim = KeywordPlanner::default().plan("blue denim jacket")?;
+        assert_eq!(denim.texture.as_deref(), Some("denim"));
+        assert!(KeywordPlanner::default().plan("(unclosed").is_err());
+
+        // Every field may be left out of a plan given as JSON
+        let plan: TransformPlan = serde_json::from_str(
+            r#"{"color": {"hue_shift": 120, "saturation_mult": 1.2, "lightness_mult": 1}}"#,
+        )?;
+        assert_eq!(plan.color.hue_shift, 120.0);
+        assert_eq!(plan.style, StyleAdjustments::NEUTRAL);
+        assert_eq!(plan.texture, None);
+        Ok(())
+    }
+}
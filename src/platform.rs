@@ -0,0 +1,101 @@
+//! Handing paths over to the desktop
+//!
+//! [`reveal`] opens a directory in Finder on macOS, Explorer on Windows and
+//! whatever `xdg-open` picks elsewhere. [`reveal_with`] takes the function that
+//! runs the opener, so tests can check the command without starting anything.
+
+use std::{
+    io,
+    path::Path,
+    process::{Command, ExitStatus},
+};
+
+use anyhow::{Context, Result, bail};
+use log::debug;
+
+/// The command that opens `path` in the platform's file manager
+pub fn opener_command(path: &Path) -> Command {
+    let program = if cfg!(target_os = "macos") {
+        "open"
+    } else if cfg!(windows) {
+        "explorer"
+    } else {
+        "xdg-open"
+    };
+    let mut cmd = Command::new(program);
+    cmd.arg(path);
+    cmd
+}
+
+/// Open `path` in the platform's file manager
+pub fn reveal(path: &Path) -> Result<()> {
+    reveal_with(path, |cmd| cmd.status())
+}
+
+/// Open `path` by running the [`opener_command`] through `run`
+pub fn reveal_with(
+    path: &Path,
+    run: impl FnOnce(&mut Command) -> io::Result<ExitStatus>,
+) -> Result<()> {
+    let mut cmd = opener_command(path);
+    let program = cmd.get_program().to_string_lossy().into_owned();
+    debug!("Revealing {} with {program}", path.display());
+    let status = run(&mut cmd).with_context(|| format!("Failed to run {program}"))?;
+    // Explorer exits with 1 even when it opened the folder
+    if !status.success() && !cfg!(windows) {
+        bail!("{program} failed ({status}) to open {}", path.display());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::OsStr;
+
+    #[cfg(unix)]
+    fn exit_status(code: i32) -> ExitStatus {
+        use std::os::unix::process::ExitStatusExt;
+        // The wait status of a normal exit keeps the code in the second byte
+        ExitStatus::from_raw(code << 8)
+    }
+
+    #[cfg(windows)]
+    fn exit_status(code: i32) -> ExitStatus {
+        use std::os::windows::process::ExitStatusExt;
+        ExitStatus::from_raw(code as u32)
+    }
+
+    #[test]
+    fn test_reveal_runs_the_opener_on_the_path() -> Result<()> {
+        let mut ran = Vec::new();
+        reveal_with(Path::new("/models/snapshot"), |cmd| {
+            ran.push(cmd.get_program().to_owned());
+            ran.extend(cmd.get_args().map(OsStr::to_owned));
+            Ok(exit_status(0))
+        })?;
+
+        let expected = if cfg!(target_os = "macos") {
+            "open"
+        } else if cfg!(windows) {
+            "explorer"
+        } else {
+            "xdg-open"
+        };
+        assert_eq!(ran, [OsStr::new(expected), OsStr::new("/models/snapshot")]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_reveal_reports_opener_failures() {
+        let err = reveal_with(Path::new("/models"), |_| {
+            Err(io::Error::from(io::ErrorKind::NotFound))
+        })
+        .unwrap_err();
+        assert!(format!("{err:#}").contains("Failed to run"));
+
+        if !cfg!(windows) {
+            assert!(reveal_with(Path::new("/models"), |_| Ok(exit_status(3))).is_err());
+        }
+    }
+}
@@ -0,0 +1,285 @@
+//! Input preprocessing applied before try-on
+//!
+//! Try-on works best when the subject fills the frame, so the input can be
+//! cropped first: either to an explicit [`CropSpec`] or, with
+//! [`Crop::AutoCenter`], to the detected clothing plus a margin.
+
+use std::{fmt, str::FromStr};
+
+use anyhow::{Context, Result, bail};
+use image::DynamicImage;
+use serde::{Deserialize, Serialize};
+
+use crate::segment::Region;
+
+/// Margin added around the clothing by [`Crop::AutoCenter`], as a fraction of the
+/// clothing's width and height
+pub const DEFAULT_AUTO_CENTER_MARGIN: f64 = 0.1;
+
+/// How a try-on input is cropped before processing
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Crop {
+    /// A fixed region
+    Region(CropSpec),
+    /// The bounding box of the clothing mask, grown by `margin` on every side
+    AutoCenter { margin: f64 },
+}
+
+/// One side or offset of a [`CropSpec`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CropValue {
+    Pixels(u32),
+    /// Percentage of the image width (for x and width) or height (for y and height)
+    Percent(f64),
+}
+
+impl CropValue {
+    fn resolve(self, extent: u32) -> u32 {
+        match self {
+            Self::Pixels(px) => px,
+            Self::Percent(pct) => (f64::from(extent) * pct / 100.0).round() as u32,
+        }
+    }
+}
+
+impl FromStr for CropValue {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        match s.strip_suffix('%') {
+            Some(pct) => {
+                let pct: f64 = pct
+                    .trim()
+                    .parse()
+                    .with_context(|| format!("Invalid percentage `{s}`"))?;
+                if !(0.0..=100.0).contains(&pct) {
+                    bail!("Percentage `{s}` is outside 0% to 100%");
+                }
+                Ok(Self::Percent(pct))
+            }
+            None => Ok(Self::Pixels(
+                s.parse()
+                    .with_context(|| format!("Invalid pixel value `{s}`"))?,
+            )),
+        }
+    }
+}
+
+impl fmt::Display for CropValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pixels(px) => write!(f, "{px}"),
+            Self::Percent(pct) => write!(f, "{pct}%"),
+        }
+    }
+}
+
+/// A crop rectangle written as `x,y,w,h`, each in pixels or with a `%` suffix
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub struct CropSpec {
+    pub x: CropValue,
+    pub y: CropValue,
+    pub width: CropValue,
+    pub height: CropValue,
+}
+
+impl CropSpec {
+    /// The pixel region this spec selects in a `width`x`height` image
+    ///
+    /// Fails if the region is empty or extends past the image.
+    pub fn resolve(&self, width: u32, height: u32) -> Result<Region> {
+        let region = Region {
+            x: self.x.resolve(width),
+            y: self.y.resolve(height),
+            width: self.width.resolve(width),
+            height: self.height.resolve(height),
+        };
+        if region.width == 0 || region.height == 0 {
+            bail!("Crop {self} has zero area");
+        }
+        if u64::from(region.x) + u64::from(region.width) > u64::from(width)
+            || u64::from(region.y) + u64::from(region.height) > u64::from(height)
+        {
+            bail!("Crop {self} extends past the {width}x{height} image");
+        }
+        Ok(region)
+    }
+}
+
+impl From<Region> for CropSpec {
+    fn from(region: Region) -> Self {
+        Self {
+            x: CropValue::Pixels(region.x),
+            y: CropValue::Pixels(region.y),
+            width: CropValue::Pixels(region.width),
+            height: CropValue::Pixels(region.height),
+        }
+    }
+}
+
+impl FromStr for CropSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let values = s
+            .split(',')
+            .map(str::parse)
+            .collect::<Result<Vec<CropValue>>>()
+            .with_context(|| format!("Invalid crop `{s}`"))?;
+        let [x, y, width, height] = values[..] else {
+            bail!("Invalid crop `{s}`, expected x,y,w,h");
+        };
+        Ok(Self {
+            x,
+            y,
+            width,
+            height,
+        })
+    }
+}
+
+impl fmt::Display for CropSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{},{},{},{}", self.x, self.y, self.width, self.height)
+    }
+}
+
+impl From<CropSpec> for String {
+    fn from(spec: CropSpec) -> Self {
+        spec.to_string()
+    }
+}
+
+impl TryFrom<String> for CropSpec {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+/// Crop `image` to `spec`, which must lie within the image
+pub fn crop(image: &DynamicImage, spec: &CropSpec) -> Result<DynamicImage> {
+    let region = spec.resolve(image.width(), image.height())?;
+    Ok(image.crop_imm(region.x, region.y, region.width, region.height))
+}
+
+/// Grow `region` by `margin` times its width and height on each side, clamped to
+/// a `width`x`height` image
+pub fn expand(region: Region, margin: f64, width: u32, height: u32) -> Region {
+    let grow = |extent: u32| (f64::from(extent) * margin.max(0.0)).round() as u32;
+    let (dx, dy) = (grow(region.width), grow(region.height));
+
+    let x = region.x.saturating_sub(dx);
+    let y = region.y.saturating_sub(dy);
+    let right = region
+        .x
+        .saturating_add(region.width)
+        .saturating_add(dx)
+        .min(width);
+    let bottom = region
+        .y
+        .saturating_add(region.height)
+        .saturating_add(dy)
+        .min(height);
+    Region {
+        x,
+        y,
+        width: right - x,
+        height: bottom - y,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn test_parse_pixels_and_percentages() -> Result<()> {
+        let spec: CropSpec = "10,20%, 50%,100".parse()?;
+        assert_eq!(spec.x, CropValue::Pixels(10));
+        assert_eq!(spec.y, CropValue::Percent(20.0));
+        assert_eq!(spec.width, CropValue::Percent(50.0));
+        assert_eq!(spec.height, CropValue::Pixels(100));
+        assert_eq!(spec.to_string(), "10,20%,50%,100");
+
+        assert_eq!(
+            spec.resolve(200, 400)?,
+            Region {
+                x: 10,
+                y: 80,
+                width: 100,
+                height: 100,
+            }
+        );
+
+        for bad in [
+            "1,2,3",
+            "1,2,3,4,5",
+            "a,0,10,10",
+            "0,0,150%,10",
+            "0,0,-5%,10",
+        ] {
+            assert!(bad.parse::<CropSpec>().is_err(), "{bad}");
+        }
+
+        // Round-trips through the compact string form
+        let json = serde_json::to_string(&Crop::Region(spec))?;
+        assert_eq!(json, r#"{"region":"10,20%,50%,100"}"#);
+        assert_eq!(serde_json::from_str::<Crop>(&json)?, Crop::Region(spec));
+        Ok(())
+    }
+
+    #[test]
+    fn test_crop_bounds_validation() -> Result<()> {
+        let image = DynamicImage::ImageRgb8(RgbImage::from_pixel(100, 80, Rgb([1, 2, 3])));
+
+        let cropped = crop(&image, &"10,10,50%,50%".parse()?)?;
+        assert_eq!((cropped.width(), cropped.height()), (50, 40));
+        // Exactly reaching the edge is fine
+        let cropped = crop(&image, &"50,40,50,40".parse()?)?;
+        assert_eq!((cropped.width(), cropped.height()), (50, 40));
+
+        let err = crop(&image, &"60,0,50,10".parse()?).unwrap_err();
+        assert!(err.to_string().contains("extends past the 100x80 image"));
+        let err = crop(&image, &"0,0,0,10".parse()?).unwrap_err();
+        assert!(err.to_string().contains("zero area"));
+        // 0.4% of 100px rounds to nothing
+        assert!(crop(&image, &"0,0,0.4%,10".parse()?).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_expand_clamps_to_image() {
+        let bbox = Region {
+            x: 40,
+            y: 30,
+            width: 20,
+            height: 40,
+        };
+        assert_eq!(
+            expand(bbox, 0.25, 100, 100),
+            Region {
+                x: 35,
+                y: 20,
+                width: 30,
+                height: 60,
+            }
+        );
+        assert_eq!(expand(bbox, 0.0, 100, 100), bbox);
+        // A large margin is clamped to every edge
+        assert_eq!(
+            expand(bbox, 5.0, 100, 90),
+            Region {
+                x: 0,
+                y: 0,
+                width: 100,
+                height: 90,
+            }
+        );
+    }
+}
@@ -0,0 +1,393 @@
+//! Prompt parsing
+//!
+//! Prompts use the syntax common to diffusion front ends:
+//!
+//! - terms are separated by commas: `red shirt, silk`
+//! - `(term:1.3)` sets a term's weight; a bare `(term)` multiplies it by
+//!   [`EMPHASIS`], and nested groups multiply
+//! - `[neg: term, ...]` marks terms the result should avoid
+//! - `\(`, `\)`, `\[`, `\]` and `\\` stand for the literal characters
+//!
+//! The heuristic pipeline only looks at the positive terms, so a color named in
+//! the negative prompt is never applied.
+
+use std::{fmt, str::FromStr};
+
+use anyhow::{Result, bail};
+use serde::{Deserialize, Serialize};
+
+/// Weight multiplier of a `(term)` group without an explicit weight
+pub const EMPHASIS: f32 = 1.1;
+
+const NEGATIVE_BLOCK: &str = "neg:";
+
+/// One comma-separated term of a prompt
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PromptTerm {
+    pub text: String,
+    pub weight: f32,
+}
+
+impl PromptTerm {
+    pub fn new(text: impl Into<String>, weight: f32) -> Self {
+        Self {
+            text: text.into(),
+            weight,
+        }
+    }
+}
+
+impl fmt::Display for PromptTerm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = escape(&self.text);
+        if self.weight == 1.0 {
+            f.write_str(&text)
+        } else {
+            write!(f, "({text}:{})", self.weight)
+        }
+    }
+}
+
+/// A prompt split into weighted positive and negative terms
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ParsedPrompt {
+    pub positive: Vec<PromptTerm>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub negative: Vec<PromptTerm>,
+}
+
+impl ParsedPrompt {
+    /// Parse `prompt`, failing on unbalanced groups and malformed weights
+    pub fn parse(prompt: &str) -> Result<Self> {
+        let segments = Parser::new(prompt).parse_sequence(None)?;
+        let mut parsed = Self::default();
+        for segment in segments {
+            let terms = if segment.negative {
+                &mut parsed.negative
+            } else {
+                &mut parsed.positive
+            };
+            terms.extend(
+                segment
+                    .text
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|text| !text.is_empty())
+                    .map(|text| PromptTerm::new(text, segment.weight)),
+            );
+        }
+        Ok(parsed)
+    }
+
+    /// Add every term of a separately supplied negative prompt (such as
+    /// `--negative-prompt`) to the negative terms
+    pub fn with_negative(mut self, negative: &str) -> Result<Self> {
+        let parsed = Self::parse(negative)?;
+        self.negative.extend(parsed.positive);
+        self.negative.extend(parsed.negative);
+        Ok(self)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positive.is_empty() && self.negative.is_empty()
+    }
+
+    /// Weight of the heaviest positive term containing `word`, ignoring case
+    pub fn positive_weight(&self, word: &str) -> Option<f32> {
+        self.positive
+            .iter()
+            .filter(|term| term.text.to_lowercase().contains(word))
+            .map(|term| term.weight)
+            .reduce(f32::max)
+    }
+}
+
+impl FromStr for ParsedPrompt {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::parse(s)
+    }
+}
+
+impl fmt::Display for ParsedPrompt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let join = |terms: &[PromptTerm]| {
+            terms
+                .iter()
+                .map(PromptTerm::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        f.write_str(&join(&self.positive))?;
+        if !self.negative.is_empty() {
+            if !self.positive.is_empty() {
+                f.write_str(" ")?;
+            }
+            write!(f, "[{NEGATIVE_BLOCK} {}]", join(&self.negative))?;
+        }
+        Ok(())
+    }
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '(' | ')' | '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// A run of prompt text sharing one weight
+#[derive(Debug)]
+struct Segment {
+    text: String,
+    weight: f32,
+    negative: bool,
+    /// Written directly in the enclosing group rather than in a nested one
+    direct: bool,
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn new(prompt: &str) -> Self {
+        Self {
+            chars: prompt.chars().collect(),
+            pos: 0,
+        }
+    }
+
+    /// Parse up to and including `close`, or to the end of the prompt
+    fn parse_sequence(&mut self, close: Option<char>) -> Result<Vec<Segment>> {
+        let mut segments = Vec::new();
+        let mut text = String::new();
+        let flush = |text: &mut String, segments: &mut Vec<Segment>| {
+            if !text.is_empty() {
+                segments.push(Segment {
+                    text: std::mem::take(text),
+                    weight: 1.0,
+                    negative: false,
+                    direct: true,
+                });
+            }
+        };
+
+        loop {
+            let Some(c) = self.next() else {
+                match close {
+                    Some(')') => bail!("Unclosed `(` in prompt"),
+                    Some(_) => bail!("Unclosed `[{NEGATIVE_BLOCK}` in prompt"),
+                    None => break,
+                }
+            };
+            match c {
+                '\\' => text.push(self.next().unwrap_or('\\')),
+                '(' => {
+                    flush(&mut text, &mut segments);
+                    let group = self.parse_sequence(Some(')'))?;
+                    segments.extend(weighted(group)?);
+                }
+                '[' if self.enter_negative_block() => {
+                    flush(&mut text, &mut segments);
+                    let block = self.parse_sequence(Some(']'))?;
+                    segments.extend(block.into_iter().map(|segment| Segment {
+                        negative: true,
+                        direct: false,
+                        ..segment
+                    }));
+                }
+                c if Some(c) == close => {
+                    flush(&mut text, &mut segments);
+                    return Ok(segments);
+                }
+                ')' => bail!("Unmatched `)` in prompt"),
+                c => text.push(c),
+            }
+        }
+        flush(&mut text, &mut segments);
+        Ok(segments)
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.get(self.pos).copied();
+        self.pos += 1;
+        c
+    }
+
+    /// Whether the text after an opening `[` starts a negative block, skipping
+    /// past the marker if so
+    fn enter_negative_block(&mut self) -> bool {
+        let start = self.chars[self.pos.min(self.chars.len())..]
+            .iter()
+            .position(|c| !c.is_whitespace())
+            .map_or(self.chars.len(), |skipped| self.pos + skipped);
+        let marker: String = self.chars[start..]
+            .iter()
+            .take(NEGATIVE_BLOCK.len())
+            .collect();
+        let found = marker.eq_ignore_ascii_case(NEGATIVE_BLOCK);
+        if found {
+            self.pos = start + NEGATIVE_BLOCK.len();
+        }
+        found
+    }
+}
+
+/// Apply the weight of a closed `(...)` group to its segments
+///
+/// The weight is a trailing `:number` written directly in the group, or
+/// [`EMPHASIS`] without one. A trailing colon followed by anything that isn't a
+/// number (`(time: noon)`) is left as text.
+fn weighted(mut group: Vec<Segment>) -> Result<Vec<Segment>> {
+    let mut weight = EMPHASIS;
+    if let Some(last) = group.last_mut().filter(|segment| segment.direct) {
+        if let Some((text, raw)) = last.text.rsplit_once(':') {
+            let raw = raw.trim();
+            let numeric = raw.is_empty()
+                || raw.starts_with(|c: char| c.is_ascii_digit() || matches!(c, '.' | '-' | '+'));
+            if numeric {
+                weight = match raw.parse::<f32>() {
+                    Ok(w) if w.is_finite() && w >= 0.0 => w,
+                    _ => bail!("Invalid weight `{raw}` in prompt, expected a number like 1.3"),
+                };
+                last.text = text.to_string();
+            }
+        }
+    }
+    Ok(group
+        .into_iter()
+        .filter(|segment| !segment.text.is_empty())
+        .map(|segment| Segment {
+            weight: segment.weight * weight,
+            direct: false,
+            ..segment
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn terms(parsed: &[PromptTerm]) -> Vec<(&str, f32)> {
+        parsed.iter().map(|t| (t.text.as_str(), t.weight)).collect()
+    }
+
+    #[test]
+    fn test_plain_terms() -> Result<()> {
+        let parsed = ParsedPrompt::parse(" red shirt,silk ,, ")?;
+        assert_eq!(terms(&parsed.positive), [("red shirt", 1.0), ("silk", 1.0)]);
+        assert!(parsed.negative.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_empty_prompts() -> Result<()> {
+        for prompt in ["", "   ", ",,", "()", "[neg: ]"] {
+            assert!(ParsedPrompt::parse(prompt)?.is_empty(), "{prompt:?}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_weights_and_nesting() -> Result<()> {
+        let parsed = ParsedPrompt::parse("(red shirt:1.5), (silk), ((velvet)), a (blue) hat")?;
+        assert_eq!(
+            terms(&parsed.positive),
+            [
+                ("red shirt", 1.5),
+                ("silk", EMPHASIS),
+                ("velvet", EMPHASIS * EMPHASIS),
+                ("a", 1.0),
+                ("blue", EMPHASIS),
+                ("hat", 1.0),
+            ]
+        );
+
+        // Weights multiply through nested groups, commas split inside groups
+        let parsed = ParsedPrompt::parse("(dress, (lace:2):0.5)")?;
+        assert_eq!(terms(&parsed.positive), [("dress", 0.5), ("lace", 1.0)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_malformed_weights() {
+        for prompt in [
+            "(red:)",
+            "(red:abc1)x",
+            "(red:1.2.3)",
+            "(red:-1)",
+            "(red:+)",
+            "(red",
+            "red)",
+            "[neg: blue",
+            "(red [neg: blue)]",
+        ] {
+            // `abc1` isn't numeric-looking, so that one stays text; the rest fail
+            let result = ParsedPrompt::parse(prompt);
+            if prompt == "(red:abc1)x" {
+                assert_eq!(
+                    terms(&result.unwrap().positive),
+                    [("red:abc1", EMPHASIS), ("x", 1.0)]
+                );
+            } else {
+                assert!(result.is_err(), "{prompt:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_negative_terms() -> Result<()> {
+        let parsed = ParsedPrompt::parse("red shirt [neg: blue, (stripes:1.4)], silk")?;
+        assert_eq!(terms(&parsed.positive), [("red shirt", 1.0), ("silk", 1.0)]);
+        assert_eq!(terms(&parsed.negative), [("blue", 1.0), ("stripes", 1.4)]);
+        assert_eq!(parsed.positive_weight("blue"), None);
+
+        let parsed = ParsedPrompt::parse("red shirt")?.with_negative("green, [NEG: grey]")?;
+        assert_eq!(terms(&parsed.negative), [("green", 1.0), ("grey", 1.0)]);
+        // A plain bracket is just text
+        let parsed = ParsedPrompt::parse("[1] shirt")?;
+        assert_eq!(terms(&parsed.positive), [("[1] shirt", 1.0)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_escapes_and_round_trip() -> Result<()> {
+        let parsed = ParsedPrompt::parse(r"shirt \(size M\), (\[logo\]:1.2), back\\slash")?;
+        assert_eq!(
+            terms(&parsed.positive),
+            [
+                ("shirt (size M)", 1.0),
+                ("[logo]", 1.2),
+                (r"back\slash", 1.0)
+            ]
+        );
+
+        let with_negative = parsed.clone().with_negative("blue")?;
+        let reparsed = ParsedPrompt::parse(&with_negative.to_string())?;
+        assert_eq!(reparsed, with_negative);
+        Ok(())
+    }
+
+    #[test]
+    fn test_unicode() -> Result<()> {
+        let parsed = ParsedPrompt::parse("(vestido rojo:1.2), 青いシャツ, 👕 [neg: grün]")?;
+        assert_eq!(
+            terms(&parsed.positive),
+            [("vestido rojo", 1.2), ("青いシャツ", 1.0), ("👕", 1.0)]
+        );
+        assert_eq!(terms(&parsed.negative), [("grün", 1.0)]);
+        assert_eq!(
+            ParsedPrompt::parse("ÉTÉ")?.positive_weight("été"),
+            Some(1.0)
+        );
+        Ok(())
+    }
+}
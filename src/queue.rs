@@ -0,0 +1,439 @@
+//! Bounded concurrent try-on jobs
+//!
+//! [`JobQueue`] runs submitted [`TryOnRequest`]s on a shared [`VirtualTryOn`],
+//! starting them in submission order with at most `max_concurrent` running at
+//! once. Loading a model needs exclusive access to the pipeline; image processing
+//! only needs shared access, so jobs for an already loaded model run in parallel
+//! on the blocking thread pool.
+
+use std::sync::{
+    Arc, Mutex,
+    atomic::{AtomicU64, Ordering},
+};
+
+use anyhow::{Context, Result, anyhow};
+use log::debug;
+use serde::Serialize;
+use tokio::sync::{Semaphore, mpsc, oneshot};
+
+use crate::{
+    cancel::CancelToken,
+    error::SiError,
+    tryon::{TryOnRequest, TryOnResult, VirtualTryOn},
+};
+
+/// Where a submitted job is in its lifecycle
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    /// Waiting for a free worker
+    Queued,
+    Running,
+    Done,
+    Failed,
+    /// Cancelled before it finished; no output is written
+    Cancelled,
+    /// Stopped for running past its time limit; no output is written
+    #[serde(rename = "timed_out")]
+    TimedOut,
+}
+
+impl JobStatus {
+    /// The status of a job that finished with `result`
+    pub fn of<T>(result: &Result<T>) -> Self {
+        match result {
+            Ok(_) => Self::Done,
+            Err(e) if SiError::Cancelled.matches(e) => Self::Cancelled,
+            Err(e)
+                if e.chain()
+                    .any(|cause| matches!(cause.downcast_ref(), Some(SiError::Timeout { .. }))) =>
+            {
+                Self::TimedOut
+            }
+            Err(_) => Self::Failed,
+        }
+    }
+}
+
+/// A job waiting for the dispatcher
+struct Job {
+    request: TryOnRequest,
+    status: Arc<Mutex<JobStatus>>,
+    cancel: CancelToken,
+    result: oneshot::Sender<Result<TryOnResult>>,
+}
+
+/// Runs try-on jobs with bounded concurrency
+///
+/// Must be created inside a tokio runtime. Dropping the queue lets already
+/// submitted jobs finish. Cancelling the [`VirtualTryOn`]'s token cancels every
+/// job, queued or running.
+pub struct JobQueue {
+    jobs: mpsc::UnboundedSender<Job>,
+    next_id: AtomicU64,
+    /// The try-on's token, parent of every job's
+    cancel: CancelToken,
+}
+
+impl JobQueue {
+    pub fn new(max_concurrent: usize, tryon: VirtualTryOn) -> Self {
+        let (jobs, rx) = mpsc::unbounded_channel();
+        let cancel = tryon.cancel_token().clone();
+        let tryon = Arc::new(tryon);
+        let permits = Arc::new(Semaphore::new(max_concurrent.max(1)));
+        tokio::spawn(dispatch(rx, tryon, permits));
+        Self {
+            jobs,
+            next_id: AtomicU64::new(1),
+            cancel,
+        }
+    }
+
+    /// Queue `request`, returning a handle to follow and await it
+    pub fn submit(&self, request: TryOnRequest) -> JobHandle {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let status = Arc::new(Mutex::new(JobStatus::Queued));
+        let cancel = self.cancel.child();
+        let (result_tx, result_rx) = oneshot::channel();
+
+        let job = Job {
+            request,
+            status: status.clone(),
+            cancel: cancel.clone(),
+            result: result_tx,
+        };
+        if let Err(mpsc::error::SendError(job)) = self.jobs.send(job) {
+            set_status(&job.status, JobStatus::Failed);
+            let _ = job.result.send(Err(anyhow!("The job queue has shut down")));
+        }
+
+        JobHandle {
+            id,
+            status,
+            cancel,
+            result: result_rx,
+        }
+    }
+}
+
+/// A submitted job
+#[derive(Debug)]
+pub struct JobHandle {
+    id: u64,
+    status: Arc<Mutex<JobStatus>>,
+    cancel: CancelToken,
+    result: oneshot::Receiver<Result<TryOnResult>>,
+}
+
+impl JobHandle {
+    /// Sequential id, in submission order
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn status(&self) -> JobStatus {
+        *self.status.lock().expect("job status lock")
+    }
+
+    /// Cancel the job: a queued job never starts and a running one stops at its
+    /// next cancellation check
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
+
+    /// Wait for the job to finish
+    pub async fn wait(self) -> Result<TryOnResult> {
+        self.result
+            .await
+            .context("The job was dropped before it finished")?
+    }
+}
+
+fn set_status(status: &Mutex<JobStatus>, value: JobStatus) {
+    *status.lock().expect("job status lock") = value;
+}
+
+/// Start jobs in order as permits become available
+async fn dispatch(
+    mut jobs: mpsc::UnboundedReceiver<Job>,
+    tryon: Arc<VirtualTryOn>,
+    permits: Arc<Semaphore>,
+) {
+    while let Some(job) = jobs.recv().await {
+        if job.cancel.is_cancelled() {
+            finish(job, Err(SiError::Cancelled.into()));
+            continue;
+        }
+        let Ok(permit) = permits.clone().acquire_owned().await else {
+            break;
+        };
+        if job.cancel.is_cancelled() {
+            finish(job, Err(SiError::Cancelled.into()));
+            continue;
+        }
+
+        set_status(&job.status, JobStatus::Running);
+        let tryon = tryon.clone();
+        tokio::spawn(async move {
+            let result = run(&tryon, &job.request, &job.cancel).await;
+            drop(permit);
+            finish(job, result);
+        });
+    }
+    debug!("Job queue closed");
+}
+
+async fn run(
+    tryon: &Arc<VirtualTryOn>,
+    request: &TryOnRequest,
+    cancel: &CancelToken,
+) -> Result<TryOnResult> {
+    if !tryon.is_prepared(request) {
+        tryon.prepare(request).await?;
+    }
+    cancel.check()?;
+
+    let tryon = tryon.clone();
+    let (request, cancel) = (request.clone(), cancel.clone());
+    tokio::task::spawn_blocking(move || tryon.run_prepared(&request, &cancel))
+        .await
+        .context("Try-on worker panicked")?
+}
+
+fn finish(job: Job, result: Result<TryOnResult>) {
+    set_status(&job.status, JobStatus::of(&result));
+    let _ = job.result.send(result);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{HeuristicSegmenter, ModelManagerBuilder, Segmenter};
+    use image::{GrayImage, Rgb, RgbImage};
+    use std::{
+        path::Path,
+        sync::atomic::AtomicUsize,
+        time::{Duration, Instant},
+    };
+    use tempfile::tempdir;
+
+    /// Sleeps while segmenting and records how many segmentations overlap and
+    /// which input widths were processed, in order
+    #[derive(Default)]
+    struct Probe {
+        running: AtomicUsize,
+        max_running: AtomicUsize,
+        widths: Mutex<Vec<u32>>,
+    }
+
+    struct SlowSegmenter(Arc<Probe>);
+
+    impl Segmenter for SlowSegmenter {
+        fn segment(&self, image: &RgbImage) -> Result<GrayImage> {
+            let probe = &self.0;
+            let running = probe.running.fetch_add(1, Ordering::SeqCst) + 1;
+            probe.max_running.fetch_max(running, Ordering::SeqCst);
+            probe.widths.lock().unwrap().push(image.width());
+            std::thread::sleep(Duration::from_millis(60));
+            probe.running.fetch_sub(1, Ordering::SeqCst);
+            HeuristicSegmenter.segment(image)
+        }
+    }
+
+    /// A manager whose index holds `test/model`
+    fn manager(root: &Path) -> Result<crate::ModelManager> {
+        let models_dir = root.join("models");
+        std::fs::create_dir_all(&models_dir)?;
+        std::fs::write(
+            models_dir.join("model_index.json"),
+            r#"{"models": [{"model_id": "test/model", "files": []}]}"#,
+        )?;
+        ModelManagerBuilder::new()
+            .with_models_dir(models_dir)
+            .build()
+    }
+
+    fn queue(root: &Path, max_concurrent: usize) -> Result<(JobQueue, Arc<Probe>)> {
+        let probe = Arc::new(Probe::default());
+        let tryon = VirtualTryOn::new(manager(root)?)?
+            .with_thumbnails(false)
+            .with_segmenter(Box::new(SlowSegmenter(probe.clone())));
+        Ok((JobQueue::new(max_concurrent, tryon), probe))
+    }
+
+    /// A request whose input image is `width` pixels wide
+    fn request(root: &Path, width: u32) -> Result<TryOnRequest> {
+        let input = root.join(format!("in-{width}.png"));
+        RgbImage::from_pixel(width, 64, Rgb([100, 80, 120])).save(&input)?;
+        Ok(TryOnRequest {
+            input_image_path: input,
+            clothing_description: "blue shirt".to_string(),
+            negative_prompt: None,
+            output_path: root.join(format!("out-{width}.png")),
+            model_name: Some("test/model".to_string()),
+            strength: None,
+            color_strength: None,
+            style_strength: None,
+            reference_image: None,
+            crop: None,
+            mask: None,
+            transform_plan: None,
+            comparison: None,
+            remote_input: None,
+            seed: None,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_jobs_run_in_submission_order() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let (queue, probe) = queue(temp_dir.path(), 1)?;
+
+        let handles: Vec<_> = (64..68)
+            .map(|w| request(temp_dir.path(), w).map(|r| queue.submit(r)))
+            .collect::<Result<_>>()?;
+        assert_eq!(
+            handles.iter().map(JobHandle::id).collect::<Vec<_>>(),
+            [1, 2, 3, 4]
+        );
+        assert_eq!(handles[3].status(), JobStatus::Queued);
+
+        for handle in handles {
+            let result = handle.wait().await?;
+            assert!(result.output_path.exists());
+        }
+        assert_eq!(*probe.widths.lock().unwrap(), [64, 65, 66, 67]);
+        assert_eq!(probe.max_running.load(Ordering::SeqCst), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_concurrency_is_bounded() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let (queue, probe) = queue(temp_dir.path(), 2)?;
+
+        let start = Instant::now();
+        let handles: Vec<_> = (64..70)
+            .map(|w| request(temp_dir.path(), w).map(|r| queue.submit(r)))
+            .collect::<Result<_>>()?;
+        for handle in handles {
+            handle.wait().await?;
+        }
+
+        assert_eq!(probe.max_running.load(Ordering::SeqCst), 2);
+        assert_eq!(probe.widths.lock().unwrap().len(), 6);
+        // Six 60ms jobs two at a time can't finish faster than three rounds
+        assert!(start.elapsed() >= Duration::from_millis(180));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cancel_queued_job() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let (queue, probe) = queue(temp_dir.path(), 1)?;
+
+        let first = queue.submit(request(temp_dir.path(), 64)?);
+        let queued_request = request(temp_dir.path(), 65)?;
+        let queued = queue.submit(queued_request.clone());
+        let last = queue.submit(request(temp_dir.path(), 66)?);
+        queued.cancel();
+
+        first.wait().await?;
+        last.wait().await?;
+
+        assert_eq!(queued.status(), JobStatus::Cancelled);
+        let err = queued.wait().await.unwrap_err();
+        assert!(SiError::Cancelled.matches(&err));
+        assert!(!queued_request.output_path.exists());
+        assert_eq!(*probe.widths.lock().unwrap(), [64, 66]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cancelling_the_tryon_cancels_queued_jobs() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let root = temp_dir.path();
+        let cancel = CancelToken::new();
+        let probe = Arc::new(Probe::default());
+        let tryon = VirtualTryOn::new(manager(root)?)?
+            .with_thumbnails(false)
+            .with_segmenter(Box::new(SlowSegmenter(probe.clone())))
+            .with_cancel_token(cancel.clone());
+        let queue = JobQueue::new(1, tryon);
+
+        let requests: Vec<_> = (64..68).map(|w| request(root, w)).collect::<Result<_>>()?;
+        let handles: Vec<_> = requests.iter().map(|r| queue.submit(r.clone())).collect();
+        cancel.cancel();
+
+        let mut statuses = Vec::new();
+        for handle in handles {
+            statuses.push(JobStatus::of(&handle.wait().await));
+        }
+        // The first job may have finished before the cancel reached it
+        assert!(matches!(
+            statuses[0],
+            JobStatus::Done | JobStatus::Cancelled
+        ));
+        assert_eq!(statuses[1..], [JobStatus::Cancelled; 3]);
+        assert!(requests[1..].iter().all(|r| !r.output_path.exists()));
+        Ok(())
+    }
+
+    /// Stalls on images of one width, like a pathological input
+    struct StallingSegmenter(u32);
+
+    impl Segmenter for StallingSegmenter {
+        fn segment(&self, image: &RgbImage) -> Result<GrayImage> {
+            if image.width() == self.0 {
+                std::thread::sleep(Duration::from_millis(400));
+            }
+            HeuristicSegmenter.segment(image)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_timed_out_job_leaves_no_output_and_the_rest_run() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let root = temp_dir.path();
+        let tryon = VirtualTryOn::new(manager(root)?)?
+            .with_thumbnails(false)
+            .with_segmenter(Box::new(StallingSegmenter(65)))
+            .with_timeout(Some(Duration::from_millis(100)));
+        let queue = JobQueue::new(1, tryon);
+
+        let requests: Vec<_> = (64..67).map(|w| request(root, w)).collect::<Result<_>>()?;
+        let handles: Vec<_> = requests.iter().map(|r| queue.submit(r.clone())).collect();
+        let mut statuses = Vec::new();
+        for handle in handles {
+            let result = handle.wait().await;
+            if let Err(e) = &result {
+                assert_eq!(format!("{e:#}"), "Timed out after 100ms");
+            }
+            statuses.push(JobStatus::of(&result));
+        }
+
+        assert_eq!(
+            statuses,
+            [JobStatus::Done, JobStatus::TimedOut, JobStatus::Done]
+        );
+        assert!(requests[0].output_path.exists());
+        assert!(!requests[1].output_path.exists());
+        assert!(requests[2].output_path.exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_failed_job_reports_failure() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let (queue, _probe) = queue(temp_dir.path(), 1)?;
+
+        let mut missing = request(temp_dir.path(), 64)?;
+        missing.input_image_path = temp_dir.path().join("missing.png");
+        let handle = queue.submit(missing);
+        let status = handle.status.clone();
+
+        assert!(handle.wait().await.is_err());
+        assert_eq!(*status.lock().unwrap(), JobStatus::Failed);
+        Ok(())
+    }
+}
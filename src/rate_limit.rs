@@ -0,0 +1,178 @@
+//! Download bandwidth limits
+//!
+//! A [`RateLimit`] such as `5MB/s` caps how fast a [`ModelManager`](crate::ModelManager)
+//! downloads. It is enforced by one [`RateLimiter`], a token bucket every file
+//! transfer of the manager draws from as bytes arrive, so files fetched at the
+//! same time share the cap instead of each getting it.
+
+use std::{
+    fmt,
+    str::FromStr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result, bail};
+
+use crate::format::{Style, format_size_as, parse_size};
+
+/// How much can be downloaded at once after an idle spell, in time at the full
+/// rate; short so transfers settle at the limit right away
+const BURST: Duration = Duration::from_millis(100);
+
+/// A download rate in bytes per second, parsed from `5MB/s`, `500KiB/s` or a
+/// plain byte count like `1048576`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    bytes_per_sec: u64,
+}
+
+impl RateLimit {
+    /// A limit of `bytes_per_sec`, which must not be zero
+    pub fn new(bytes_per_sec: u64) -> Result<Self> {
+        if bytes_per_sec == 0 {
+            bail!("A download rate limit must be above zero");
+        }
+        Ok(Self { bytes_per_sec })
+    }
+
+    pub fn bytes_per_sec(&self) -> u64 {
+        self.bytes_per_sec
+    }
+}
+
+impl FromStr for RateLimit {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let trimmed = value.trim();
+        let size = trimmed
+            .strip_suffix("/s")
+            .or_else(|| trimmed.strip_suffix("/S"))
+            .unwrap_or(trimmed);
+        let bytes_per_sec =
+            parse_size(size).with_context(|| format!("Invalid download rate `{value}`"))?;
+        Self::new(bytes_per_sec).with_context(|| format!("Invalid download rate `{value}`"))
+    }
+}
+
+impl fmt::Display for RateLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/s", format_size_as(self.bytes_per_sec, Style::Human))
+    }
+}
+
+/// A token bucket holding at most [`BURST`] worth of bytes, refilled at the
+/// limit
+///
+/// Taking more than the bucket holds leaves it in debt, which the taker, and
+/// anyone taking after it, sleeps off. Transfers that await
+/// [`acquire`](Self::acquire) before passing on each chunk they receive stop
+/// reading from the connection while they sleep, so together they never
+/// average more than the limit.
+#[derive(Debug)]
+pub struct RateLimiter {
+    limit: RateLimit,
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    /// Bytes that can be taken without waiting; negative when in debt
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl RateLimiter {
+    /// A limiter for `limit` whose bucket starts full
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            bucket: Mutex::new(Bucket {
+                tokens: capacity(limit),
+                refilled: Instant::now(),
+            }),
+        }
+    }
+
+    pub fn limit(&self) -> RateLimit {
+        self.limit
+    }
+
+    fn rate(&self) -> f64 {
+        self.limit.bytes_per_sec as f64
+    }
+
+    /// Take `bytes` from the bucket, waiting until the limit allows them
+    pub async fn acquire(&self, bytes: u64) {
+        let wait = {
+            let mut bucket = self.bucket.lock().expect("rate limit lock");
+            let now = Instant::now();
+            let refill = now.duration_since(bucket.refilled).as_secs_f64() * self.rate();
+            bucket.tokens = (bucket.tokens + refill).min(capacity(self.limit)) - bytes as f64;
+            bucket.refilled = now;
+            Duration::from_secs_f64((-bucket.tokens).max(0.0) / self.rate())
+        };
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// The bytes a bucket for `limit` holds when full
+fn capacity(limit: RateLimit) -> f64 {
+    limit.bytes_per_sec as f64 * BURST.as_secs_f64()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rate_limit() -> Result<()> {
+        assert_eq!("5MB/s".parse::<RateLimit>()?.bytes_per_sec(), 5_000_000);
+        assert_eq!("500KiB/s".parse::<RateLimit>()?.bytes_per_sec(), 500 << 10);
+        assert_eq!("1.5 mb/S".parse::<RateLimit>()?.bytes_per_sec(), 1_500_000);
+        assert_eq!("2GiB".parse::<RateLimit>()?.bytes_per_sec(), 2 << 30);
+        assert_eq!(" 1024 ".parse::<RateLimit>()?.bytes_per_sec(), 1024);
+
+        for invalid in ["", "/s", "0MB/s", "fast", "5 parsecs/s", "5MB/h"] {
+            assert!(invalid.parse::<RateLimit>().is_err(), "{invalid}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_rate_limit_display() -> Result<()> {
+        assert_eq!("5MB/s".parse::<RateLimit>()?.to_string(), "5 MB/s");
+        assert_eq!(RateLimit::new(1_500)?.to_string(), "1.50 kB/s");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_limiter_paces_concurrent_takers_together() -> Result<()> {
+        // 10 kB/s with a 1 kB burst; 3 kB more than the burst takes 0.3s,
+        // whether taken by one or split across tasks
+        let limiter = std::sync::Arc::new(RateLimiter::new(RateLimit::new(10_000)?));
+        let started = Instant::now();
+        let takers: Vec<_> = (0..4)
+            .map(|_| {
+                let limiter = limiter.clone();
+                tokio::spawn(async move {
+                    for _ in 0..4 {
+                        limiter.acquire(250).await;
+                    }
+                })
+            })
+            .collect();
+        for taker in takers {
+            taker.await?;
+        }
+        let elapsed = started.elapsed();
+        assert!(
+            elapsed >= Duration::from_millis(280) && elapsed < Duration::from_millis(600),
+            "{elapsed:?}"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,279 @@
+//! Recipes: the resolved settings of a try-on, saved to apply to other photos
+//!
+//! `si image generate --save-recipe look.json` writes a [`Recipe`] of the
+//! try-on it ran: the transformation that was planned, the mask and crop, and
+//! the strengths, but not the input, output or reference paths, so the same
+//! look can be applied to any photo with `--recipe look.json`. Flags given on
+//! the command line still win over the recipe's values.
+//!
+//! Recipes carry a `schema_version`; one written by a newer si is refused
+//! rather than half understood.
+//!
+//! ```json
+//! {
+//!   "schema_version": 1,
+//!   "model": "runwayml/stable-diffusion-v1-5",
+//!   "prompt": "red silk shirt",
+//!   "strength": 0.8,
+//!   "color_strength": 0.8,
+//!   "style_strength": 0.5,
+//!   "transform_plan": {
+//!     "color": { "hue_shift": 0.0, "saturation_mult": 1.4, "lightness_mult": 0.9 },
+//!     "style": { "contrast": 1.1, "brightness": 0.02 },
+//!     "mask_hint": null
+//!   },
+//!   "mask": { "polygon": "0.1,0.2 0.9,0.2 0.5,0.9", "mode": "intersect" }
+//! }
+//! ```
+
+use std::{fs, path::Path};
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    mask::RegionMask,
+    planner::TransformPlan,
+    preprocess::Crop,
+    tryon::{TryOnRequest, TryOnResult},
+};
+
+/// The recipe format this si writes, and the newest it reads
+pub const RECIPE_SCHEMA_VERSION: u32 = 1;
+
+/// Everything about a try-on except the files it read and wrote
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Recipe {
+    pub schema_version: u32,
+    pub model: String,
+    /// The description the plan was made from
+    pub prompt: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub negative_prompt: Option<String>,
+    pub strength: f64,
+    pub color_strength: f64,
+    pub style_strength: f64,
+    pub transform_plan: TransformPlan,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crop: Option<Crop>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mask: Option<RegionMask>,
+}
+
+impl Recipe {
+    /// The recipe of the try-on that ran `request` and returned `result`
+    pub fn from_result(request: &TryOnRequest, result: &TryOnResult) -> Self {
+        Self {
+            schema_version: RECIPE_SCHEMA_VERSION,
+            model: result.model_used.clone(),
+            prompt: request.clothing_description.clone(),
+            negative_prompt: request.negative_prompt.clone(),
+            strength: result
+                .suggested_strength
+                .unwrap_or_else(|| request.effective_strength()),
+            color_strength: result.color_strength,
+            style_strength: result.style_strength,
+            transform_plan: result.transform_plan.clone(),
+            crop: request.crop.clone(),
+            mask: request.mask.clone(),
+        }
+    }
+
+    /// `request` with every field it leaves unset taken from the recipe
+    ///
+    /// The recipe's plan is only used while the request keeps the recipe's
+    /// description; a request with a description of its own is planned afresh.
+    pub fn apply_to(&self, request: TryOnRequest) -> TryOnRequest {
+        let transform_plan = match request.transform_plan {
+            Some(plan) => Some(plan),
+            None if request.clothing_description == self.prompt => {
+                Some(self.transform_plan.clone())
+            }
+            None => None,
+        };
+        TryOnRequest {
+            negative_prompt: request.negative_prompt.or(self.negative_prompt.clone()),
+            model_name: request.model_name.or(Some(self.model.clone())),
+            strength: request.strength.or(Some(self.strength)),
+            color_strength: request.color_strength.or(Some(self.color_strength)),
+            style_strength: request.style_strength.or(Some(self.style_strength)),
+            crop: request.crop.or(self.crop.clone()),
+            mask: request.mask.or(self.mask.clone()),
+            transform_plan,
+            ..request
+        }
+    }
+
+    /// Read a recipe, refusing ones written for a newer schema
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read recipe {}", path.display()))?;
+        // Check the version first, so a newer recipe isn't reported as invalid
+        let version = serde_json::from_str::<serde_json::Value>(&text)
+            .with_context(|| format!("Invalid recipe {}", path.display()))?
+            .get("schema_version")
+            .and_then(serde_json::Value::as_u64);
+        match version {
+            None => bail!("Invalid recipe {}: no schema_version", path.display()),
+            Some(version) if version > u64::from(RECIPE_SCHEMA_VERSION) => bail!(
+                "Recipe {} is schema version {version}, newer than the {RECIPE_SCHEMA_VERSION} \
+                 this si reads; upgrade si to use it",
+                path.display()
+            ),
+            Some(_) => {}
+        }
+        serde_json::from_str(&text).with_context(|| format!("Invalid recipe {}", path.display()))
+    }
+
+    /// Write the recipe as JSON to `path`
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(path, json + "\n")
+            .with_context(|| format!("Failed to write recipe {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ModelManagerBuilder, VirtualTryOn,
+        mask::{MaskMode, Polygon},
+    };
+    use image::{Rgb, RgbImage};
+    use tempfile::tempdir;
+
+    /// A try-on whose index holds `test/model`
+    fn tryon(root: &Path) -> Result<VirtualTryOn> {
+        let models_dir = root.join("models");
+        fs::create_dir_all(&models_dir)?;
+        fs::write(
+            models_dir.join("model_index.json"),
+            r#"{"models": [{"model_id": "test/model", "files": []}]}"#,
+        )?;
+        let manager = ModelManagerBuilder::new()
+            .with_models_dir(models_dir)
+            .build()?;
+        Ok(VirtualTryOn::new(manager)?.with_thumbnails(false))
+    }
+
+    fn request(input: &Path, output: &Path, prompt: &str) -> TryOnRequest {
+        TryOnRequest {
+            input_image_path: input.to_path_buf(),
+            clothing_description: prompt.to_string(),
+            negative_prompt: None,
+            output_path: output.to_path_buf(),
+            model_name: None,
+            strength: None,
+            color_strength: None,
+            style_strength: None,
+            reference_image: None,
+            crop: None,
+            mask: None,
+            transform_plan: None,
+            comparison: None,
+            remote_input: None,
+            seed: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_recipe_from_one_run_applies_to_another_image() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let root = temp_dir.path();
+        let first = root.join("first.png");
+        RgbImage::from_pixel(96, 72, Rgb([200, 180, 160])).save(&first)?;
+        let second = root.join("second.png");
+        RgbImage::from_fn(64, 80, |x, y| Rgb([(x * 3) as u8, (y * 3) as u8, 90])).save(&second)?;
+
+        let tryon = tryon(root)?;
+        let mut original = request(&first, &root.join("first-out.png"), "red silk shirt");
+        original.model_name = Some("test/model".to_string());
+        original.strength = Some(0.7);
+        original.style_strength = Some(0.4);
+        original.mask = Some(RegionMask {
+            polygon: "0.1,0.1 0.9,0.1 0.5,0.9".parse::<Polygon>()?,
+            mode: MaskMode::Intersect,
+        });
+        let result = tryon.try_on(original.clone()).await?;
+        let recipe_path = root.join("look.json");
+        Recipe::from_result(&original, &result).save(&recipe_path)?;
+
+        let recipe = Recipe::load(&recipe_path)?;
+        assert_eq!(recipe.schema_version, RECIPE_SCHEMA_VERSION);
+        assert!(!fs::read_to_string(&recipe_path)?.contains("first"));
+        let applied = recipe.apply_to(request(
+            &second,
+            &root.join("second-out.png"),
+            "red silk shirt",
+        ));
+        assert_eq!(
+            applied.transform_plan.as_ref(),
+            Some(&result.transform_plan)
+        );
+        assert_eq!(applied.strength, Some(0.7));
+        assert_eq!(applied.style_strength, Some(0.4));
+        assert_eq!(applied.color_strength, Some(0.7));
+        assert_eq!(applied.mask, original.mask);
+        assert_eq!(applied.input_image_path, second);
+
+        let second_result = tryon.try_on(applied).await?;
+        assert_eq!(second_result.transform_plan, result.transform_plan);
+        assert_eq!(second_result.style_strength, 0.4);
+        assert_ne!(second_result.output_path, result.output_path);
+        assert!(second_result.output_path.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_request_fields_win_over_the_recipe() {
+        let recipe = Recipe {
+            schema_version: RECIPE_SCHEMA_VERSION,
+            model: "org/model".to_string(),
+            prompt: "red silk shirt".to_string(),
+            negative_prompt: Some("blurry".to_string()),
+            strength: 0.7,
+            color_strength: 0.6,
+            style_strength: 0.5,
+            transform_plan: TransformPlan::default(),
+            crop: Some(Crop::AutoCenter { margin: 0.2 }),
+            mask: None,
+        };
+        let mut own = request(Path::new("in.png"), Path::new("out.png"), "red silk shirt");
+        own.strength = Some(0.3);
+        own.model_name = Some("org/other".to_string());
+        let applied = recipe.apply_to(own);
+        assert_eq!(applied.strength, Some(0.3));
+        assert_eq!(applied.model_name.as_deref(), Some("org/other"));
+        assert_eq!(applied.negative_prompt.as_deref(), Some("blurry"));
+        assert_eq!(applied.crop, recipe.crop);
+
+        // A different description gets its own plan
+        let applied = recipe.apply_to(request(
+            Path::new("in.png"),
+            Path::new("out.png"),
+            "blue denim jacket",
+        ));
+        assert_eq!(applied.transform_plan, None);
+        assert_eq!(applied.color_strength, Some(0.6));
+    }
+
+    #[test]
+    fn test_load_refuses_newer_and_invalid_recipes() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("look.json");
+        fs::write(
+            &path,
+            r#"{"schema_version": 2, "model": "m", "prompt": "p", "future_field": 1}"#,
+        )?;
+        let err = Recipe::load(&path).unwrap_err();
+        assert!(format!("{err:#}").contains("schema version 2"), "{err:#}");
+
+        fs::write(&path, r#"{"model": "m"}"#)?;
+        assert!(format!("{:#}", Recipe::load(&path).unwrap_err()).contains("no schema_version"));
+        fs::write(&path, r#"{"schema_version": 1, "model": "m"}"#)?;
+        assert!(Recipe::load(&path).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,304 @@
+//! Try-on requests described in YAML or JSON files
+//!
+//! `si image generate --request-file job.yaml` reads a [`RequestOverrides`]
+//! from the file and fills in whatever the command line leaves unset. Fields are
+//! those of [`TryOnRequest`] plus the output options; unknown fields are
+//! rejected so typos don't silently fall back to defaults. Relative paths in a
+//! file are relative to the file's directory, not the working directory.
+//!
+//! ```yaml
+//! input: photos/me.jpg
+//! prompt: red linen shirt
+//! output: out/me-red.png
+//! strength: 0.6
+//! reference: garments/shirt.jpg
+//! quality: 90
+//! seed: 42
+//! ```
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+
+use crate::{
+    mask::RegionMask,
+    preprocess::Crop,
+    tryon::{TryOnRequest, check_strength},
+};
+
+/// Path meaning stdin or stdout, never resolved against the file's directory
+const STDIO: &str = "-";
+
+/// Any subset of a [`TryOnRequest`]'s fields and the output options, as read
+/// from a request file or given on the command line
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RequestOverrides {
+    #[serde(default, alias = "input")]
+    pub input_image_path: Option<PathBuf>,
+    #[serde(default, alias = "prompt")]
+    pub clothing_description: Option<String>,
+    #[serde(default)]
+    pub negative_prompt: Option<String>,
+    #[serde(default, alias = "output")]
+    pub output_path: Option<PathBuf>,
+    #[serde(default, alias = "model")]
+    pub model_name: Option<String>,
+    #[serde(default)]
+    pub strength: Option<f64>,
+    #[serde(default, alias = "reference")]
+    pub reference_image: Option<PathBuf>,
+    #[serde(default)]
+    pub crop: Option<Crop>,
+    #[serde(default)]
+    pub mask: Option<RegionMask>,
+    /// JPEG, WebP or AVIF quality, 1-100
+    #[serde(default)]
+    pub quality: Option<u8>,
+    /// Whether an existing output file may be replaced
+    #[serde(default)]
+    pub overwrite: Option<bool>,
+    /// Seed of a strength ramp or of the first variation
+    #[serde(default)]
+    pub seed: Option<u64>,
+}
+
+impl RequestOverrides {
+    /// Read a request file, as YAML (`.yaml`/`.yml`) or JSON (`.json`)
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read request file {}", path.display()))?;
+        let extension = path
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        let overrides: Self = match extension.as_str() {
+            "yaml" | "yml" => serde_yaml::from_str(&text)
+                .with_context(|| format!("Invalid request file {}", path.display()))?,
+            "json" => serde_json::from_str(&text)
+                .with_context(|| format!("Invalid request file {}", path.display()))?,
+            _ => bail!(
+                "Can't tell the format of request file {}: use a .yaml, .yml or .json extension",
+                path.display()
+            ),
+        };
+        if let Some(quality) = overrides.quality {
+            if !(1..=100).contains(&quality) {
+                bail!(
+                    "Invalid request file {}: quality must be 1-100, got {quality}",
+                    path.display()
+                );
+            }
+        }
+        if let Some(strength) = overrides.strength {
+            check_strength("strength", strength)
+                .with_context(|| format!("Invalid request file {}", path.display()))?;
+        }
+        Ok(overrides.relative_to(path.parent().unwrap_or(Path::new(""))))
+    }
+
+    /// Resolve relative paths against `dir`
+    fn relative_to(mut self, dir: &Path) -> Self {
+        let resolve = |path: &mut Option<PathBuf>| {
+            if let Some(p) = path.as_mut() {
+                if p.is_relative() && *p != Path::new(STDIO) {
+                    *p = dir.join(&*p);
+                }
+            }
+        };
+        resolve(&mut self.input_image_path);
+        resolve(&mut self.output_path);
+        resolve(&mut self.reference_image);
+        self
+    }
+}
+
+impl TryOnRequest {
+    /// The request described by a YAML or JSON file, which must name the input,
+    /// the description and the output
+    ///
+    /// The output options in the file are not part of a [`TryOnRequest`]; read
+    /// them with [`RequestOverrides::from_file`].
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let file = RequestOverrides::from_file(path)?;
+        let missing =
+            |field: &str| format!("Request file {} doesn't set `{field}`", path.display());
+        Ok(Self {
+            input_image_path: file
+                .input_image_path
+                .with_context(|| missing("input_image_path"))?,
+            clothing_description: file
+                .clothing_description
+                .with_context(|| missing("clothing_description"))?,
+            negative_prompt: file.negative_prompt,
+            output_path: file.output_path.with_context(|| missing("output_path"))?,
+            model_name: file.model_name,
+            strength: file.strength,
+            color_strength: None,
+            style_strength: None,
+            reference_image: file.reference_image,
+            crop: file.crop,
+            mask: file.mask,
+            transform_plan: None,
+            comparison: None,
+            remote_input: None,
+            seed: file.seed,
+        })
+    }
+
+    /// `self` with every request field `overrides` sets replaced, so flags given
+    /// on the command line win over the request file
+    pub fn merge_cli_overrides(self, overrides: RequestOverrides) -> Self {
+        Self {
+            input_image_path: overrides.input_image_path.unwrap_or(self.input_image_path),
+            clothing_description: overrides
+                .clothing_description
+                .unwrap_or(self.clothing_description),
+            negative_prompt: overrides.negative_prompt.or(self.negative_prompt),
+            output_path: overrides.output_path.unwrap_or(self.output_path),
+            model_name: overrides.model_name.or(self.model_name),
+            strength: overrides.strength.or(self.strength),
+            color_strength: self.color_strength,
+            style_strength: self.style_strength,
+            reference_image: overrides.reference_image.or(self.reference_image),
+            crop: overrides.crop.or(self.crop),
+            mask: overrides.mask.or(self.mask),
+            transform_plan: self.transform_plan,
+            comparison: self.comparison,
+            remote_input: self.remote_input,
+            seed: overrides.seed.or(self.seed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mask::MaskMode;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_yaml_request_file() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("job.yaml");
+        fs::write(
+            &path,
+            "input: photos/me.jpg\n\
+             prompt: red linen shirt\n\
+             output: /tmp/out.png\n\
+             strength: 0.6\n\
+             mask:\n  polygon: \"0,0 10,0 0,10\"\n  mode: polygon\n\
+             quality: 90\n",
+        )?;
+
+        let overrides = RequestOverrides::from_file(&path)?;
+        assert_eq!(overrides.quality, Some(90));
+        let request = TryOnRequest::from_file(&path)?;
+        assert_eq!(request.clothing_description, "red linen shirt");
+        assert_eq!(request.strength, Some(0.6));
+        assert_eq!(request.mask.map(|m| m.mode), Some(MaskMode::Polygon));
+        // Relative to the file, absolute paths untouched
+        assert_eq!(
+            request.input_image_path,
+            temp_dir.path().join("photos/me.jpg")
+        );
+        assert_eq!(request.output_path, Path::new("/tmp/out.png"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_json_request_file_and_missing_fields() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("job.json");
+        fs::write(
+            &path,
+            r#"{"input_image_path": "-", "clothing_description": "blue coat"}"#,
+        )?;
+
+        let overrides = RequestOverrides::from_file(&path)?;
+        assert_eq!(overrides.input_image_path.as_deref(), Some(Path::new("-")));
+        let err = TryOnRequest::from_file(&path).unwrap_err();
+        assert!(format!("{err:#}").contains("`output_path`"));
+
+        fs::write(temp_dir.path().join("job.toml"), "")?;
+        assert!(RequestOverrides::from_file(&temp_dir.path().join("job.toml")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_unknown_fields_are_rejected() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("job.yml");
+        fs::write(&path, "input: me.jpg\nprompt: red shirt\nstrenght: 0.5\n")?;
+
+        let err = RequestOverrides::from_file(&path).unwrap_err();
+        assert!(format!("{err:#}").contains("strenght"), "{err:#}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_out_of_range_strength_is_rejected() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("job.yml");
+        fs::write(&path, "input: me.jpg\nprompt: red shirt\nstrength: 1.5\n")?;
+
+        let err = RequestOverrides::from_file(&path).unwrap_err();
+        assert_eq!(
+            format!("{err:#}"),
+            format!(
+                "Invalid request file {}: strength must be 0.0-1.0, got 1.5",
+                path.display()
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_cli_overrides_win() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("job.yaml");
+        fs::write(
+            &path,
+            "input: me.jpg\nprompt: red shirt\noutput: out.png\nmodel: org/file\nstrength: 0.3\n",
+        )?;
+
+        let request = TryOnRequest::from_file(&path)?.merge_cli_overrides(RequestOverrides {
+            clothing_description: Some("green shirt".to_string()),
+            strength: Some(0.9),
+            ..Default::default()
+        });
+        assert_eq!(request.clothing_description, "green shirt");
+        assert_eq!(request.strength, Some(0.9));
+        assert_eq!(request.model(), "org/file");
+        assert_eq!(request.output_path, temp_dir.path().join("out.png"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_seed_round_trips_and_the_cli_seed_wins() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("job.yaml");
+        fs::write(
+            &path,
+            "input: me.jpg\nprompt: red shirt\noutput: out.png\nseed: 42\n",
+        )?;
+
+        assert_eq!(RequestOverrides::from_file(&path)?.seed, Some(42));
+        let request = TryOnRequest::from_file(&path)?;
+        assert_eq!(request.seed, Some(42));
+        let kept = request
+            .clone()
+            .merge_cli_overrides(RequestOverrides::default());
+        assert_eq!(kept.seed, Some(42));
+        let overridden = request.merge_cli_overrides(RequestOverrides {
+            seed: Some(7),
+            ..Default::default()
+        });
+        assert_eq!(overridden.seed, Some(7));
+        Ok(())
+    }
+}
@@ -0,0 +1,326 @@
+//! Reading the tensor table of a `.safetensors` file without loading its weights
+//!
+//! The format starts with a little-endian `u64` header length followed by a JSON
+//! object mapping tensor names to their dtype, shape and byte range in the data
+//! section. [`inspect`] reads just those two parts with bounded reads, so a
+//! multi-GB checkpoint costs as much as its header.
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::format::format_size;
+
+/// Largest header accepted, the same limit the reference implementation uses
+pub const MAX_HEADER_LEN: u64 = 100_000_000;
+
+/// Key of the free-form string metadata in the header
+const METADATA_KEY: &str = "__metadata__";
+
+/// One tensor as described by the header
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TensorInfo {
+    pub name: String,
+    /// As spelled in the file, e.g. `F16`, `BF16`, `I64`
+    pub dtype: String,
+    pub shape: Vec<u64>,
+    /// Length of the tensor's data
+    pub byte_size: u64,
+}
+
+impl TensorInfo {
+    /// Number of elements; 1 for a scalar
+    pub fn element_count(&self) -> u64 {
+        self.shape.iter().product()
+    }
+}
+
+/// Tensor count, elements and bytes of one dtype
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct DtypeTotal {
+    pub tensors: usize,
+    pub elements: u64,
+    pub bytes: u64,
+}
+
+/// What [`inspect`] found in a safetensors file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SafetensorsSummary {
+    pub path: PathBuf,
+    /// Length of the JSON header in bytes
+    pub header_len: u64,
+    /// Sorted by name
+    pub tensors: Vec<TensorInfo>,
+    /// The header's `__metadata__` strings, if any
+    pub metadata: BTreeMap<String, String>,
+}
+
+impl SafetensorsSummary {
+    /// Bytes of tensor data in the file
+    pub fn total_bytes(&self) -> u64 {
+        self.tensors.iter().map(|t| t.byte_size).sum()
+    }
+
+    pub fn dtype_totals(&self) -> BTreeMap<String, DtypeTotal> {
+        let mut totals: BTreeMap<String, DtypeTotal> = BTreeMap::new();
+        for tensor in &self.tensors {
+            let total = totals.entry(tensor.dtype.clone()).or_default();
+            total.tensors += 1;
+            total.elements += tensor.element_count();
+            total.bytes += tensor.byte_size;
+        }
+        totals
+    }
+}
+
+/// One line per tensor with its dtype, shape and size, then one per dtype with
+/// its totals
+impl fmt::Display for SafetensorsSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ({} tensors, {})",
+            self.path.display(),
+            self.tensors.len(),
+            format_size(self.total_bytes())
+        )?;
+        let name_width = self.tensors.iter().map(|t| t.name.len()).max().unwrap_or(0);
+        for tensor in &self.tensors {
+            write!(
+                f,
+                "\n  {:name_width$}  {:<4} {:?}  {}",
+                tensor.name,
+                tensor.dtype,
+                tensor.shape,
+                format_size(tensor.byte_size)
+            )?;
+        }
+        for (dtype, total) in self.dtype_totals() {
+            write!(
+                f,
+                "\n  total {dtype}: {} tensors, {} parameters, {}",
+                total.tensors,
+                total.elements,
+                format_size(total.bytes)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+struct RawTensor {
+    dtype: String,
+    shape: Vec<u64>,
+    data_offsets: [u64; 2],
+}
+
+/// Read the header of the safetensors file at `path`
+///
+/// Fails with a description of the problem for files that aren't safetensors
+/// or whose data section is shorter than the header says.
+pub fn inspect(path: &Path) -> Result<SafetensorsSummary> {
+    let mut file =
+        File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let file_len = file
+        .metadata()
+        .with_context(|| format!("Failed to read metadata of {}", path.display()))?
+        .len();
+
+    let mut len_bytes = [0; 8];
+    if file_len < 8 {
+        bail!(
+            "{} is too short to be a safetensors file ({file_len} bytes)",
+            path.display()
+        );
+    }
+    file.read_exact(&mut len_bytes)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let header_len = u64::from_le_bytes(len_bytes);
+    if header_len > MAX_HEADER_LEN {
+        bail!(
+            "{} is not a safetensors file: header length {header_len} is implausible",
+            path.display()
+        );
+    }
+    if header_len > file_len - 8 {
+        bail!(
+            "{} is truncated: the header needs {header_len} bytes but only {} follow",
+            path.display(),
+            file_len - 8
+        );
+    }
+
+    let mut header = Vec::with_capacity(header_len as usize);
+    file.take(header_len)
+        .read_to_end(&mut header)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let entries: BTreeMap<String, serde_json::Value> = serde_json::from_slice(&header)
+        .with_context(|| {
+            format!(
+                "{} is not a safetensors file: invalid JSON header",
+                path.display()
+            )
+        })?;
+
+    let data_len = file_len - 8 - header_len;
+    let mut tensors = Vec::new();
+    let mut metadata = BTreeMap::new();
+    for (name, value) in entries {
+        if name == METADATA_KEY {
+            metadata = serde_json::from_value(value)
+                .with_context(|| format!("Invalid metadata in {}", path.display()))?;
+            continue;
+        }
+        let raw: RawTensor = serde_json::from_value(value)
+            .with_context(|| format!("Invalid entry for tensor {name} in {}", path.display()))?;
+        let [start, end] = raw.data_offsets;
+        if start > end {
+            bail!(
+                "Invalid data offsets {start}..{end} for tensor {name} in {}",
+                path.display()
+            );
+        }
+        if end > data_len {
+            bail!(
+                "{} is truncated: tensor {name} ends at byte {end} of a {data_len} byte data section",
+                path.display()
+            );
+        }
+        tensors.push(TensorInfo {
+            name,
+            dtype: raw.dtype,
+            shape: raw.shape,
+            byte_size: end - start,
+        });
+    }
+
+    Ok(SafetensorsSummary {
+        path: path.to_path_buf(),
+        header_len,
+        tensors,
+        metadata,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use safetensors::{Dtype, tensor::TensorView};
+    use std::{collections::HashMap, fs};
+    use tempfile::tempdir;
+
+    fn write_tiny(path: &Path) -> Result<()> {
+        let weight = vec![0u8; 2 * 3 * 4];
+        let bias = vec![0u8; 3 * 2];
+        let step = vec![0u8; 8];
+        let tensors = [
+            (
+                "layer.weight",
+                TensorView::new(Dtype::F32, vec![2, 3], &weight)?,
+            ),
+            ("layer.bias", TensorView::new(Dtype::F16, vec![3], &bias)?),
+            ("step", TensorView::new(Dtype::I64, vec![], &step)?),
+        ];
+        let metadata = HashMap::from([("format".to_string(), "pt".to_string())]);
+        safetensors::serialize_to_file(tensors, &Some(metadata), path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_inspect_tiny_file() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("model.safetensors");
+        write_tiny(&path)?;
+
+        let summary = inspect(&path)?;
+        let tensors: Vec<_> = summary
+            .tensors
+            .iter()
+            .map(|t| {
+                (
+                    t.name.as_str(),
+                    t.dtype.as_str(),
+                    t.shape.clone(),
+                    t.byte_size,
+                )
+            })
+            .collect();
+        assert_eq!(
+            tensors,
+            [
+                ("layer.bias", "F16", vec![3], 6),
+                ("layer.weight", "F32", vec![2, 3], 24),
+                ("step", "I64", vec![], 8),
+            ]
+        );
+        assert_eq!(summary.metadata["format"], "pt");
+        assert_eq!(summary.total_bytes(), 38);
+        assert_eq!(
+            summary.dtype_totals()["F32"],
+            DtypeTotal {
+                tensors: 1,
+                elements: 6,
+                bytes: 24
+            }
+        );
+        assert_eq!(summary.tensors[2].element_count(), 1);
+        assert!(
+            summary
+                .to_string()
+                .contains("total F16: 1 tensors, 3 parameters, 6 B")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_inspect_rejects_other_files() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("model.bin");
+
+        fs::write(&path, "tiny")?;
+        let err = inspect(&path).unwrap_err();
+        assert!(err.to_string().contains("too short"), "{err:#}");
+
+        // A pickle starts with bytes that make for an absurd header length
+        fs::write(&path, b"\x80\x02}q\x00(X\x05\x00\x00\x00model")?;
+        let err = inspect(&path).unwrap_err();
+        assert!(
+            err.to_string().contains("not a safetensors file"),
+            "{err:#}"
+        );
+
+        let mut bytes = 7u64.to_le_bytes().to_vec();
+        bytes.extend_from_slice(b"garbage");
+        fs::write(&path, bytes)?;
+        let err = inspect(&path).unwrap_err();
+        assert!(err.to_string().contains("invalid JSON header"), "{err:#}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_inspect_truncated_file() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("model.safetensors");
+        write_tiny(&path)?;
+        let bytes = fs::read(&path)?;
+
+        // Cut into the data section
+        fs::write(&path, &bytes[..bytes.len() - 4])?;
+        let err = inspect(&path).unwrap_err();
+        assert!(err.to_string().contains("truncated: tensor"), "{err:#}");
+
+        // Cut into the header
+        fs::write(&path, &bytes[..20])?;
+        let err = inspect(&path).unwrap_err();
+        assert!(err.to_string().contains("truncated: the header"), "{err:#}");
+        Ok(())
+    }
+}
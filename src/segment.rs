@@ -0,0 +1,304 @@
+//! Clothing segmentation
+//!
+//! A [`Segmenter`] turns an input image into a mask where 255 marks clothing and
+//! 0 marks everything else. [`HeuristicSegmenter`] is the built-in MVP
+//! implementation; an ML-based segmenter can replace it via
+//! [`VirtualTryOn::with_segmenter`](crate::VirtualTryOn::with_segmenter).
+//! Only the [`Region`] and coverage types are built without the
+//! `image-pipeline` feature, as the history and config refer to them.
+
+use std::fmt;
+
+#[cfg(feature = "image-pipeline")]
+use anyhow::Result;
+#[cfg(feature = "image-pipeline")]
+use image::{GrayImage, Luma, Rgb, RgbImage};
+#[cfg(feature = "image-pipeline")]
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "image-pipeline")]
+use crate::mask::{self, SkinProtection};
+
+#[cfg(feature = "image-pipeline")]
+pub trait Segmenter: Send + Sync {
+    /// Compute the clothing mask for `image`, with the same dimensions
+    fn segment(&self, image: &RgbImage) -> Result<GrayImage>;
+
+    /// Names this segmenter and its version in mask cache keys; change it
+    /// whenever the masks it produces change. Masks of a segmenter without one
+    /// are never cached.
+    fn id(&self) -> Option<&str> {
+        None
+    }
+
+    /// The smallest region containing all clothing in `image`, if there is any
+    fn bounding_box(&self, image: &RgbImage) -> Result<Option<Region>> {
+        Ok(mask_bounding_box(&self.segment(image)?))
+    }
+}
+
+/// A rectangle in pixel coordinates
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[cfg(feature = "image-pipeline")]
+/// The smallest region containing every mask pixel above 127, or `None` for an
+/// empty mask
+pub fn mask_bounding_box(mask: &GrayImage) -> Option<Region> {
+    let mut bounds: Option<(u32, u32, u32, u32)> = None;
+    for (x, y, pixel) in mask.enumerate_pixels() {
+        if pixel.0[0] <= 127 {
+            continue;
+        }
+        bounds = Some(match bounds {
+            None => (x, y, x, y),
+            Some((x0, y0, x1, y1)) => (x0.min(x), y0.min(y), x1.max(x), y1.max(y)),
+        });
+    }
+    bounds.map(|(x0, y0, x1, y1)| Region {
+        x: x0,
+        y: y0,
+        width: x1 - x0 + 1,
+        height: y1 - y0 + 1,
+    })
+}
+
+#[cfg(feature = "image-pipeline")]
+/// Fraction of `mask` pixels marked as clothing at all, from 0.0 to 1.0
+pub fn mask_coverage(mask: &GrayImage) -> f64 {
+    let pixels = (f64::from(mask.width()) * f64::from(mask.height())).max(1.0);
+    mask.pixels().filter(|p| p.0[0] > 0).count() as f64 / pixels
+}
+
+#[cfg(feature = "image-pipeline")]
+/// How much of an image a clothing mask covers
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct MaskStats {
+    /// Fraction of pixels above 127, from 0.0 to 1.0
+    pub coverage: f32,
+    pub bounding_box: Option<Region>,
+}
+
+#[cfg(feature = "image-pipeline")]
+impl MaskStats {
+    pub fn of(mask: &GrayImage) -> Self {
+        let pixels = (f64::from(mask.width()) * f64::from(mask.height())).max(1.0);
+        let covered = mask.pixels().filter(|p| p.0[0] > 127).count() as f64;
+        Self {
+            coverage: (covered / pixels) as f32,
+            bounding_box: mask_bounding_box(mask),
+        }
+    }
+}
+
+/// Share of the image a clothing mask is expected to cover; outside it the
+/// output is likely unchanged or recolored wholesale
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CoverageBounds {
+    pub min: f32,
+    pub max: f32,
+}
+
+impl Default for CoverageBounds {
+    fn default() -> Self {
+        Self {
+            min: 0.05,
+            max: 0.80,
+        }
+    }
+}
+
+impl CoverageBounds {
+    /// What is wrong with a mask covering `coverage` of the image, if anything
+    pub fn check(&self, coverage: f32) -> Option<CoverageWarning> {
+        if coverage < self.min {
+            Some(CoverageWarning::Low {
+                coverage,
+                min: self.min,
+            })
+        } else if coverage > self.max {
+            Some(CoverageWarning::High {
+                coverage,
+                max: self.max,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// A clothing mask covering suspiciously little or much of the image
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum CoverageWarning {
+    Low { coverage: f32, min: f32 },
+    High { coverage: f32, max: f32 },
+}
+
+impl fmt::Display for CoverageWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Low { coverage, min } => write!(
+                f,
+                "The clothing mask covers only {:.1}% of the image (expected at least {:.0}%), \
+                 so the output will barely change",
+                coverage * 100.0,
+                min * 100.0
+            ),
+            Self::High { coverage, max } => write!(
+                f,
+                "The clothing mask covers {:.1}% of the image (expected at most {:.0}%), \
+                 so most of it will be recolored",
+                coverage * 100.0,
+                max * 100.0
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "image-pipeline")]
+/// Marks fabric-looking pixels in the middle of the frame as clothing
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HeuristicSegmenter;
+
+#[cfg(feature = "image-pipeline")]
+impl Segmenter for HeuristicSegmenter {
+    fn id(&self) -> Option<&str> {
+        Some("heuristic-2")
+    }
+
+    fn segment(&self, image: &RgbImage) -> Result<GrayImage> {
+        // Simplified clothing detection for MVP
+        // In a real implementation, this would use ML models for segmentation
+
+        let (width, height) = image.dimensions();
+        let mut mask = GrayImage::new(width, height);
+
+        // Simple heuristic: assume clothing is in the middle region of the image
+        // and has certain color characteristics
+        for (x, y, pixel) in image.enumerate_pixels() {
+            let is_clothing_region = is_likely_clothing_pixel(pixel, x, y, width, height);
+            let mask_value = if is_clothing_region { 255 } else { 0 };
+            mask.put_pixel(x, y, Luma([mask_value]));
+        }
+
+        debug!("Generated clothing mask");
+        Ok(mask)
+    }
+}
+
+#[cfg(feature = "image-pipeline")]
+fn is_likely_clothing_pixel(pixel: &Rgb<u8>, x: u32, y: u32, width: u32, height: u32) -> bool {
+    // Simple heuristics for detecting clothing regions in MVP
+
+    // Focus on middle region of image (typical clothing area)
+    let x_ratio = x as f32 / width as f32;
+    let y_ratio = y as f32 / height as f32;
+
+    let in_clothing_region = x_ratio > 0.2 && x_ratio < 0.8 && y_ratio > 0.3 && y_ratio < 0.9;
+
+    if !in_clothing_region {
+        return false;
+    }
+
+    // Check if pixel looks like fabric (not skin or background)
+    let [r, g, b] = pixel.0;
+    let brightness = (r as f32 + g as f32 + b as f32) / 3.0;
+
+    let is_skin_tone = mask::is_skin_tone(pixel, SkinProtection::DEFAULT_SENSITIVITY);
+
+    // Avoid very bright or very dark regions (likely background)
+    let reasonable_brightness = brightness > 30.0 && brightness < 240.0;
+
+    !is_skin_tone && reasonable_brightness
+}
+
+#[cfg(all(test, feature = "image-pipeline"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_likely_clothing_pixel() {
+        // Test clothing-like pixel in clothing region
+        let clothing_pixel = Rgb([100, 80, 120]); // Purple-ish
+        assert!(is_likely_clothing_pixel(
+            &clothing_pixel,
+            200,
+            300,
+            400,
+            600
+        ));
+
+        // Test skin-tone pixel
+        let skin_pixel = Rgb([200, 170, 150]); // Skin tone
+        assert!(!is_likely_clothing_pixel(&skin_pixel, 200, 300, 400, 600));
+
+        // Test pixel outside clothing region
+        assert!(!is_likely_clothing_pixel(
+            &clothing_pixel,
+            50,
+            100,
+            400,
+            600
+        ));
+    }
+
+    #[test]
+    fn test_coverage_bounds() {
+        let mut mask = GrayImage::new(10, 10);
+        for x in 0..10 {
+            mask.put_pixel(x, 2, Luma([255]));
+            // Faint pixels don't count
+            mask.put_pixel(x, 3, Luma([100]));
+        }
+        let stats = MaskStats::of(&mask);
+        assert!((stats.coverage - 0.1).abs() < 1e-6);
+        assert_eq!(stats.bounding_box.map(|b| (b.y, b.height)), Some((2, 1)));
+
+        let bounds = CoverageBounds::default();
+        assert_eq!(bounds.check(stats.coverage), None);
+        let low = bounds.check(0.02).unwrap();
+        assert!(matches!(low, CoverageWarning::Low { .. }));
+        assert!(low.to_string().contains("only 2.0%"), "{low}");
+        let high = bounds.check(0.95).unwrap();
+        assert!(high.to_string().contains("95.0%"), "{high}");
+        assert!(high.to_string().contains("at most 80%"), "{high}");
+    }
+
+    #[test]
+    fn test_mask_bounding_box() {
+        let mut mask = GrayImage::new(40, 30);
+        assert_eq!(mask_bounding_box(&mask), None);
+
+        mask.put_pixel(5, 7, Luma([255]));
+        mask.put_pixel(20, 3, Luma([200]));
+        // Faint pixels don't count
+        mask.put_pixel(39, 29, Luma([100]));
+        assert_eq!(
+            mask_bounding_box(&mask),
+            Some(Region {
+                x: 5,
+                y: 3,
+                width: 16,
+                height: 5,
+            })
+        );
+    }
+
+    #[test]
+    fn test_heuristic_mask_matches_image_size() -> Result<()> {
+        let image = RgbImage::from_pixel(40, 30, Rgb([100, 80, 120]));
+        let mask = HeuristicSegmenter.segment(&image)?;
+
+        assert_eq!(mask.dimensions(), (40, 30));
+        assert_eq!(mask.get_pixel(0, 0).0, [0]);
+        assert_eq!(mask.get_pixel(20, 20).0, [255]);
+        Ok(())
+    }
+}
@@ -0,0 +1,378 @@
+//! Local HTTP API for `si serve`
+//!
+//! Keeps one [`VirtualTryOn`] warm between requests instead of paying the model
+//! load on every CLI invocation. Endpoints:
+//!
+//! - `GET /healthz`
+//! - `GET /models`: the model index as JSON
+//! - `POST /models/{org}/{repo}/download`: start a download, returning a job id;
+//!   two run at a time and the rest wait as `queued`
+//! - `GET /jobs/{id}`: status of a download job, kept for an hour after it
+//!   finishes
+//! - `POST /generate`: JSON `{"image": <base64>, "prompt", "negative_prompt"?,
+//!   "strength"?, "model"?}`, answered with the result as a PNG; `strength` is
+//!   between 0 and 1 and `model` is an indexed model id, never a local path
+//!
+//! Only built with the `server` feature.
+
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result, anyhow};
+use axum::{
+    Json, Router,
+    extract::{DefaultBodyLimit, Path, State},
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::{net::TcpListener, sync::Semaphore};
+
+use crate::{
+    cancel::CancelToken,
+    error::SiError,
+    import::is_local_model,
+    models::{ModelInfo, ModelManager},
+    queue::{JobQueue, JobStatus},
+    tryon::{TryOnRequest, VirtualTryOn},
+};
+
+/// Largest accepted request body; base64 inflates images by a third
+const MAX_BODY_BYTES: usize = 64 * 1024 * 1024;
+/// Downloads run at once; later ones wait as `queued`
+const MAX_DOWNLOADS: usize = 2;
+/// How long a finished download job can still be looked up
+const DOWNLOAD_RETENTION: Duration = Duration::from_secs(60 * 60);
+
+struct AppState {
+    models: ModelManager,
+    queue: JobQueue,
+    downloads: Mutex<HashMap<u64, DownloadJob>>,
+    /// Limits the downloads running at once to [`MAX_DOWNLOADS`]
+    download_slots: Semaphore,
+    next_id: AtomicU64,
+    /// Scratch space for request images, removed with the state
+    work_dir: PathBuf,
+}
+
+/// A model download started through the API
+#[derive(Debug, Clone, Serialize)]
+pub struct DownloadJob {
+    pub id: u64,
+    pub model_id: String,
+    pub status: JobStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// When the job stopped running, for dropping it after [`DOWNLOAD_RETENTION`]
+    #[serde(skip)]
+    finished: Option<Instant>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GenerateBody {
+    /// Base64-encoded input image
+    image: String,
+    prompt: String,
+    #[serde(default)]
+    negative_prompt: Option<String>,
+    #[serde(default)]
+    strength: Option<f64>,
+    #[serde(default)]
+    model: Option<String>,
+}
+
+/// The API routes, backed by `models` for the index and downloads and `tryon`
+/// for generation with at most `workers` jobs at once
+///
+/// Must be called inside a tokio runtime.
+pub fn router(models: ModelManager, tryon: VirtualTryOn, workers: usize) -> Result<Router> {
+    let work_dir = std::env::temp_dir().join(format!(
+        "si-serve-{}-{:08x}",
+        std::process::id(),
+        rand::random::<u32>()
+    ));
+    std::fs::create_dir_all(&work_dir)
+        .with_context(|| format!("Failed to create {}", work_dir.display()))?;
+
+    let state = Arc::new(AppState {
+        models,
+        queue: JobQueue::new(workers, tryon),
+        downloads: Mutex::new(HashMap::new()),
+        download_slots: Semaphore::new(MAX_DOWNLOADS),
+        next_id: AtomicU64::new(1),
+        work_dir,
+    });
+    Ok(Router::new()
+        .route("/healthz", get(healthz))
+        .route("/models", get(list_models))
+        .route("/models/{org}/{repo}/download", post(start_download))
+        .route("/jobs/{id}", get(download_job))
+        .route("/generate", post(generate))
+        .layer(DefaultBodyLimit::max(MAX_BODY_BYTES))
+        .with_state(state))
+}
+
+/// Serve `router` on `listener` until `cancel` is tripped
+pub async fn serve(listener: TcpListener, router: Router, cancel: CancelToken) -> Result<()> {
+    info!("Serving on {}", listener.local_addr()?);
+    axum::serve(listener, router)
+        .with_graceful_shutdown(async move { cancel.cancelled().await })
+        .await
+        .context("HTTP server failed")
+}
+
+async fn healthz() -> &'static str {
+    "ok"
+}
+
+async fn list_models(State(state): State<Arc<AppState>>) -> Result<Json<Vec<ModelInfo>>, ApiError> {
+    Ok(Json(state.models.list_models_async().await?))
+}
+
+async fn start_download(
+    State(state): State<Arc<AppState>>,
+    Path((org, repo)): Path<(String, String)>,
+) -> (StatusCode, Json<DownloadJob>) {
+    let id = state.next_id.fetch_add(1, Ordering::Relaxed);
+    let job = DownloadJob {
+        id,
+        model_id: format!("{org}/{repo}"),
+        status: JobStatus::Queued,
+        error: None,
+        finished: None,
+    };
+    state.set_download(job.clone());
+
+    let (state, model_id) = (state.clone(), job.model_id.clone());
+    tokio::spawn(async move {
+        let Ok(_slot) = state.download_slots.acquire().await else {
+            return;
+        };
+        state.set_download(DownloadJob {
+            id,
+            model_id: model_id.clone(),
+            status: JobStatus::Running,
+            error: None,
+            finished: None,
+        });
+        let result = state.models.download_model(&model_id).await;
+        if let Err(e) = &result {
+            warn!("Download of {model_id} failed: {e:#}");
+        }
+        state.set_download(DownloadJob {
+            id,
+            model_id,
+            status: JobStatus::of(&result),
+            error: result.err().map(|e| format!("{e:#}")),
+            finished: Some(Instant::now()),
+        });
+    });
+
+    (StatusCode::ACCEPTED, Json(job))
+}
+
+async fn download_job(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<u64>,
+) -> Result<Json<DownloadJob>, ApiError> {
+    state
+        .downloads
+        .lock()
+        .expect("downloads lock")
+        .get(&id)
+        .cloned()
+        .map(Json)
+        .ok_or_else(|| ApiError::new(StatusCode::NOT_FOUND, anyhow!("No job with id {id}")))
+}
+
+async fn generate(
+    State(state): State<Arc<AppState>>,
+    Json(body): Json<GenerateBody>,
+) -> Result<Response, ApiError> {
+    // Clients don't get to point the server at arbitrary files
+    if let Some(model) = body.model.as_deref().filter(|m| is_local_model(m)) {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!("Model `{model}` is a path; the API only uses indexed models"),
+        ));
+    }
+    if let Some(strength) = body.strength.filter(|s| !(0.0..=1.0).contains(s)) {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!("Strength must be between 0.0 and 1.0, got {strength}"),
+        ));
+    }
+    let image = STANDARD.decode(body.image.as_bytes()).map_err(|e| {
+        ApiError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!("Invalid base64 image: {e}"),
+        )
+    })?;
+
+    let id = state.next_id.fetch_add(1, Ordering::Relaxed);
+    let input = state.work_dir.join(format!("{id}-input"));
+    let output = state.work_dir.join(format!("{id}-output.png"));
+    // Removed however the request ends, including when the client goes away
+    let _scratch = Scratch(vec![input.clone(), output.clone()]);
+    tokio::fs::write(&input, image).await?;
+
+    let request = TryOnRequest {
+        input_image_path: input.clone(),
+        clothing_description: body.prompt,
+        negative_prompt: body.negative_prompt,
+        output_path: output.clone(),
+        model_name: body.model,
+        strength: body.strength,
+        color_strength: None,
+        style_strength: None,
+        reference_image: None,
+        crop: None,
+        mask: None,
+        transform_plan: None,
+        comparison: None,
+        remote_input: None,
+        seed: None,
+    };
+    let result = state.queue.submit(request).wait().await;
+    let png = match result {
+        Ok(_) => tokio::fs::read(&output).await.map_err(anyhow::Error::from),
+        Err(e) => Err(e),
+    };
+
+    match png {
+        Ok(png) => Ok(([(header::CONTENT_TYPE, "image/png")], png).into_response()),
+        Err(e) if is_rejected_input(&e) => Err(ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, e)),
+        Err(e) if is_model_missing(&e) => Err(ApiError::new(StatusCode::CONFLICT, e)),
+        Err(e) => Err(e.into()),
+    }
+}
+
+impl AppState {
+    /// Record `job`, dropping the jobs that finished over
+    /// [`DOWNLOAD_RETENTION`] ago so a long-running server doesn't collect them
+    fn set_download(&self, job: DownloadJob) {
+        let mut downloads = self.downloads.lock().expect("downloads lock");
+        prune_downloads(&mut downloads, Instant::now());
+        downloads.insert(job.id, job);
+    }
+}
+
+/// Drop the jobs of `downloads` that finished more than [`DOWNLOAD_RETENTION`]
+/// before `now`
+fn prune_downloads(downloads: &mut HashMap<u64, DownloadJob>, now: Instant) {
+    downloads.retain(|_, job| {
+        job.finished
+            .is_none_or(|finished| now.saturating_duration_since(finished) < DOWNLOAD_RETENTION)
+    });
+}
+
+impl Drop for AppState {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_dir_all(&self.work_dir) {
+            warn!("Failed to remove {}: {e}", self.work_dir.display());
+        }
+    }
+}
+
+/// Files of one request in the work dir, deleted on drop
+struct Scratch(Vec<PathBuf>);
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        for path in &self.0 {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// Whether `err` is an input image outside the pipeline's limits
+fn is_rejected_input(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        matches!(
+            cause.downcast_ref::<SiError>(),
+            Some(
+                SiError::ImageTooSmall { .. }
+                    | SiError::TooManyPixels { .. }
+                    | SiError::ImageTooLarge { .. }
+            )
+        )
+    })
+}
+
+/// Whether `err` asks for a model that has to be downloaded first
+fn is_model_missing(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        matches!(
+            cause.downcast_ref::<SiError>(),
+            Some(SiError::ModelNotDownloaded { .. })
+        )
+    })
+}
+
+/// An error response with a JSON `{"error": ...}` body
+struct ApiError {
+    status: StatusCode,
+    error: anyhow::Error,
+}
+
+impl ApiError {
+    fn new(status: StatusCode, error: anyhow::Error) -> Self {
+        Self { status, error }
+    }
+}
+
+impl<E: Into<anyhow::Error>> From<E> for ApiError {
+    fn from(error: E) -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, error.into())
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = serde_json::json!({ "error": format!("{:#}", self.error) });
+        (self.status, Json(body)).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_finished_downloads_are_pruned_after_retention() {
+        let start = Instant::now();
+        let job = |id, status, finished| DownloadJob {
+            id,
+            model_id: "org/model".to_string(),
+            status,
+            error: None,
+            finished,
+        };
+        let mut downloads = HashMap::from([
+            (1, job(1, JobStatus::Done, Some(start))),
+            (2, job(2, JobStatus::Running, None)),
+            (
+                3,
+                job(3, JobStatus::Failed, Some(start + DOWNLOAD_RETENTION)),
+            ),
+        ]);
+
+        prune_downloads(&mut downloads, start + DOWNLOAD_RETENTION / 2);
+        assert_eq!(downloads.len(), 3);
+
+        prune_downloads(&mut downloads, start + DOWNLOAD_RETENTION);
+        let mut left: Vec<_> = downloads.into_keys().collect();
+        left.sort_unstable();
+        assert_eq!(left, [2, 3]);
+    }
+}
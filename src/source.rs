@@ -0,0 +1,1072 @@
+//! Where model files come from
+//!
+//! [`ModelManager`](crate::ModelManager) talks to the Hugging Face Hub through the
+//! [`ModelSource`] trait. [`HfSource`] is the real implementation, built with the
+//! `hub` feature; [`MockSource`] serves files from a fixture directory so
+//! downloads and syncs can be exercised offline and deterministically, and
+//! [`OfflineSource`] only has the local cache.
+
+#[cfg(feature = "hub")]
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::{
+    fmt, fs,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{Context, Result, bail};
+use futures_util::future::BoxFuture;
+use hf_hub::Cache;
+#[cfg(feature = "hub")]
+use hf_hub::{
+    Repo, RepoType,
+    api::tokio::{Api, ApiBuilder, ApiRepo, Progress},
+};
+#[cfg(feature = "hub")]
+use indicatif::{ProgressBar, ProgressStyle};
+use log::{debug, warn};
+#[cfg(feature = "hub")]
+use serde::Deserialize;
+use std::collections::HashMap;
+
+#[cfg(feature = "hub")]
+use crate::paths;
+use crate::{error::SiError, rate_limit::RateLimiter};
+
+/// Hub used when neither the builder, `HF_ENDPOINT` nor the config names one
+pub const DEFAULT_HF_ENDPOINT: &str = "https://huggingface.co";
+pub const HF_ENDPOINT_ENV: &str = "HF_ENDPOINT";
+/// Set to `1` to keep si off the network, as with other Hugging Face tools
+pub const HF_HUB_OFFLINE_ENV: &str = "HF_HUB_OFFLINE";
+
+/// Revision that [`MockSource`] writes its snapshots under
+pub const MOCK_REVISION: &str = "0000000000000000000000000000000000000000";
+/// Bytes [`MockSource`] copies at a time when a download is rate limited
+const MOCK_CHUNK: usize = 16 * 1024;
+
+/// Called with the number of bytes of a file received so far
+pub type TransferProgress = Arc<dyn Fn(u64) + Send + Sync>;
+
+/// What a source knows about a model repository before downloading it
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RepoInfo {
+    /// Repository-relative file names, `/`-separated
+    pub files: Vec<String>,
+    /// License identifier declared by the repository, if any
+    pub license: Option<String>,
+    /// The Hub task the repository declares, such as `text-to-image`
+    pub pipeline_tag: Option<String>,
+    /// Commit the file list was read from, if the source reports one
+    pub revision: Option<String>,
+    /// Size in bytes of each file whose size the source reports
+    pub sizes: HashMap<String, u64>,
+    /// Endpoint that answered, if the source talks to one
+    pub endpoint: Option<String>,
+}
+
+/// Check that `url` is an `http://` or `https://` URL with a host
+pub fn validate_endpoint(url: &str) -> Result<()> {
+    let rest = url
+        .strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))
+        .with_context(|| format!("Invalid endpoint `{url}`: expected an http(s) URL"))?;
+    let host = rest.split('/').next().unwrap_or_default();
+    if host.is_empty() || host.contains(char::is_whitespace) {
+        bail!("Invalid endpoint `{url}`: missing host");
+    }
+    Ok(())
+}
+
+/// Pick the primary endpoint: `explicit`, then `env`, then [`DEFAULT_HF_ENDPOINT`]
+pub fn resolve_endpoint(explicit: Option<&str>, env: Option<&str>) -> String {
+    explicit
+        .or(env.filter(|e| !e.is_empty()))
+        .unwrap_or(DEFAULT_HF_ENDPOINT)
+        .trim_end_matches('/')
+        .to_string()
+}
+
+/// Whether a value of [`HF_HUB_OFFLINE_ENV`] turns offline mode on
+pub fn offline_env(value: Option<&str>) -> bool {
+    value.is_some_and(|value| {
+        matches!(
+            value.trim().to_ascii_lowercase().as_str(),
+            "1" | "true" | "yes" | "on"
+        )
+    })
+}
+
+/// Access to remote model repositories and the local cache they download into
+///
+/// `revision` is a branch, tag or commit of the repository; `None` means the
+/// default branch.
+pub trait ModelSource: fmt::Debug + Send + Sync {
+    /// Fetch the list of files and metadata for `model_id`
+    fn repo_info<'a>(
+        &'a self,
+        model_id: &'a str,
+        revision: Option<&'a str>,
+    ) -> BoxFuture<'a, Result<RepoInfo>>;
+
+    /// Download one file of `model_id` into the cache, returning its local path
+    fn download_file<'a>(
+        &'a self,
+        model_id: &'a str,
+        revision: Option<&'a str>,
+        rfilename: &'a str,
+    ) -> BoxFuture<'a, Result<PathBuf>>;
+
+    /// [`download_file`](Self::download_file), taking every chunk received
+    /// from `limiter` before accepting the next
+    ///
+    /// Sources that can't pace the transfer itself take the whole file from
+    /// `limiter` once it's downloaded, which keeps the average within the limit.
+    fn download_file_limited<'a>(
+        &'a self,
+        model_id: &'a str,
+        revision: Option<&'a str>,
+        rfilename: &'a str,
+        limiter: Arc<RateLimiter>,
+    ) -> BoxFuture<'a, Result<PathBuf>> {
+        Box::pin(async move {
+            let path = self.download_file(model_id, revision, rfilename).await?;
+            limiter.acquire(fs::metadata(&path)?.len()).await;
+            Ok(path)
+        })
+    }
+
+    /// [`download_file`](Self::download_file), or
+    /// [`download_file_limited`](Self::download_file_limited) when there is a
+    /// `limiter`, calling `progress` with the bytes received so far
+    ///
+    /// Sources that can't follow the transfer call `progress` once, with the
+    /// file's size, when it's downloaded.
+    fn download_file_with_progress<'a>(
+        &'a self,
+        model_id: &'a str,
+        revision: Option<&'a str>,
+        rfilename: &'a str,
+        limiter: Option<Arc<RateLimiter>>,
+        progress: TransferProgress,
+    ) -> BoxFuture<'a, Result<PathBuf>> {
+        Box::pin(async move {
+            let path = match limiter {
+                Some(limiter) => {
+                    self.download_file_limited(model_id, revision, rfilename, limiter)
+                        .await?
+                }
+                None => self.download_file(model_id, revision, rfilename).await?,
+            };
+            progress(fs::metadata(&path)?.len());
+            Ok(path)
+        })
+    }
+
+    /// The Hugging Face style cache that downloaded files are stored in
+    fn cache(&self) -> Cache;
+}
+
+impl<T: ModelSource + ?Sized> ModelSource for Arc<T> {
+    fn repo_info<'a>(
+        &'a self,
+        model_id: &'a str,
+        revision: Option<&'a str>,
+    ) -> BoxFuture<'a, Result<RepoInfo>> {
+        (**self).repo_info(model_id, revision)
+    }
+
+    fn download_file<'a>(
+        &'a self,
+        model_id: &'a str,
+        revision: Option<&'a str>,
+        rfilename: &'a str,
+    ) -> BoxFuture<'a, Result<PathBuf>> {
+        (**self).download_file(model_id, revision, rfilename)
+    }
+
+    fn download_file_limited<'a>(
+        &'a self,
+        model_id: &'a str,
+        revision: Option<&'a str>,
+        rfilename: &'a str,
+        limiter: Arc<RateLimiter>,
+    ) -> BoxFuture<'a, Result<PathBuf>> {
+        (**self).download_file_limited(model_id, revision, rfilename, limiter)
+    }
+
+    fn download_file_with_progress<'a>(
+        &'a self,
+        model_id: &'a str,
+        revision: Option<&'a str>,
+        rfilename: &'a str,
+        limiter: Option<Arc<RateLimiter>>,
+        progress: TransferProgress,
+    ) -> BoxFuture<'a, Result<PathBuf>> {
+        (**self).download_file_with_progress(model_id, revision, rfilename, limiter, progress)
+    }
+
+    fn cache(&self) -> Cache {
+        (**self).cache()
+    }
+}
+
+#[cfg(feature = "hub")]
+/// Subset of the Hub's model info response that si cares about
+#[derive(Debug, Deserialize)]
+pub(crate) struct HubModelInfo {
+    #[serde(default)]
+    sha: Option<String>,
+    siblings: Vec<HubSibling>,
+    #[serde(default, rename = "cardData")]
+    card_data: Option<HubCardData>,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    pipeline_tag: Option<String>,
+}
+
+#[cfg(feature = "hub")]
+impl HubModelInfo {
+    /// The license declared in the model card, falling back to the `license:` tag
+    fn license(&self) -> Option<String> {
+        self.card_data
+            .as_ref()
+            .and_then(|c| c.license.clone())
+            .or_else(|| {
+                self.tags
+                    .iter()
+                    .find_map(|t| t.strip_prefix("license:").map(str::to_string))
+            })
+    }
+}
+
+#[cfg(feature = "hub")]
+impl From<HubModelInfo> for RepoInfo {
+    fn from(info: HubModelInfo) -> Self {
+        let license = info.license();
+        let sizes = info
+            .siblings
+            .iter()
+            .filter_map(|s| Some((s.rfilename.clone(), s.size?)))
+            .collect();
+        Self {
+            license,
+            pipeline_tag: info.pipeline_tag,
+            revision: info.sha,
+            sizes,
+            endpoint: None,
+            files: info.siblings.into_iter().map(|s| s.rfilename).collect(),
+        }
+    }
+}
+
+#[cfg(feature = "hub")]
+#[derive(Debug, Deserialize)]
+struct HubSibling {
+    rfilename: String,
+    /// Only reported when the info is requested with `blobs=true`
+    #[serde(default)]
+    size: Option<u64>,
+}
+
+#[cfg(feature = "hub")]
+#[derive(Debug, Deserialize)]
+struct HubCardData {
+    #[serde(default)]
+    license: Option<String>,
+}
+
+/// Follows a Hub download: paces it by taking each chunk it receives from a
+/// [`RateLimiter`], when there is one, reports the bytes received and draws
+/// the progress bar the Hub client would
+#[cfg(feature = "hub")]
+#[derive(Clone)]
+struct Transfer {
+    limiter: Option<Arc<RateLimiter>>,
+    /// Shared by the clones following the chunks of one file
+    received: Arc<AtomicU64>,
+    progress: TransferProgress,
+    bar: ProgressBar,
+}
+
+#[cfg(feature = "hub")]
+impl Transfer {
+    fn new(limiter: Option<Arc<RateLimiter>>, progress: TransferProgress) -> Self {
+        Self {
+            limiter,
+            received: Arc::default(),
+            progress,
+            bar: ProgressBar::new(0),
+        }
+    }
+}
+
+#[cfg(feature = "hub")]
+impl Progress for Transfer {
+    async fn init(&mut self, size: usize, filename: &str) {
+        self.bar.set_length(size as u64);
+        self.bar.set_style(
+            ProgressStyle::with_template(
+                "{msg} [{elapsed_precise}] [{wide_bar}] {bytes}/{total_bytes} {bytes_per_sec} ({eta})",
+            )
+            .expect("valid progress template"),
+        );
+        self.bar.set_message(filename.to_string());
+    }
+
+    async fn update(&mut self, size: usize) {
+        if let Some(limiter) = &self.limiter {
+            limiter.acquire(size as u64).await;
+        }
+        self.bar.inc(size as u64);
+        let received = self.received.fetch_add(size as u64, Ordering::Relaxed) + size as u64;
+        (self.progress)(received);
+    }
+
+    async fn finish(&mut self) {
+        self.bar.finish();
+    }
+}
+
+#[cfg(feature = "hub")]
+/// The Hugging Face Hub
+#[derive(Debug, Clone)]
+pub struct HfSource {
+    api: Api,
+    cache: Cache,
+    /// Reported in [`RepoInfo::endpoint`]; unknown for a caller-built [`Api`]
+    endpoint: Option<String>,
+}
+
+#[cfg(feature = "hub")]
+impl HfSource {
+    pub fn new(api: Api) -> Self {
+        Self {
+            api,
+            cache: Cache::new(paths::hf_cache_dir()),
+            endpoint: None,
+        }
+    }
+
+    /// Build an API client configured from `HF_*` environment variables that
+    /// downloads into [`paths::hf_cache_dir`]
+    pub fn from_env() -> Result<Self> {
+        let env = std::env::var(HF_ENDPOINT_ENV).ok();
+        Self::with_endpoint(&resolve_endpoint(None, env.as_deref()))
+    }
+
+    /// Like [`from_env`](Self::from_env), but talking to `endpoint`
+    pub fn with_endpoint(endpoint: &str) -> Result<Self> {
+        Self::with_endpoint_in(endpoint, paths::hf_cache_dir())
+    }
+
+    /// Like [`with_endpoint`](Self::with_endpoint), but downloading into
+    /// `cache_dir`
+    pub fn with_endpoint_in(endpoint: &str, cache_dir: PathBuf) -> Result<Self> {
+        validate_endpoint(endpoint)?;
+        let endpoint = endpoint.trim_end_matches('/').to_string();
+        let api = ApiBuilder::from_env()
+            .with_endpoint(endpoint.clone())
+            .with_cache_dir(cache_dir.clone())
+            .build()
+            .context("Failed to creae HuggingFace API")?;
+        Ok(Self {
+            api,
+            cache: Cache::new(cache_dir),
+            endpoint: Some(endpoint),
+        })
+    }
+
+    fn repo(&self, model_id: &str, revision: Option<&str>) -> ApiRepo {
+        match revision {
+            Some(revision) => self.api.repo(Repo::with_revision(
+                model_id.to_string(),
+                RepoType::Model,
+                revision.to_string(),
+            )),
+            None => self.api.model(model_id.to_string()),
+        }
+    }
+}
+
+#[cfg(feature = "hub")]
+impl ModelSource for HfSource {
+    fn repo_info<'a>(
+        &'a self,
+        model_id: &'a str,
+        revision: Option<&'a str>,
+    ) -> BoxFuture<'a, Result<RepoInfo>> {
+        Box::pin(async move {
+            let info: HubModelInfo = self
+                .repo(model_id, revision)
+                .info_request()
+                .query(&[("blobs", "true")])
+                .send()
+                .await
+                .and_then(|r| r.error_for_status())
+                .with_context(|| format!("Failed to get info for `{model_id}`"))?
+                .json()
+                .await
+                .with_context(|| format!("Failed to parse info for `{model_id}`"))?;
+            debug!("  info: {info:?}");
+            Ok(RepoInfo {
+                endpoint: self.endpoint.clone(),
+                ..info.into()
+            })
+        })
+    }
+
+    fn download_file<'a>(
+        &'a self,
+        model_id: &'a str,
+        revision: Option<&'a str>,
+        rfilename: &'a str,
+    ) -> BoxFuture<'a, Result<PathBuf>> {
+        Box::pin(async move {
+            self.repo(model_id, revision)
+                .download(rfilename)
+                .await
+                .with_context(|| format!("{rfilename} download faild"))
+        })
+    }
+
+    fn download_file_limited<'a>(
+        &'a self,
+        model_id: &'a str,
+        revision: Option<&'a str>,
+        rfilename: &'a str,
+        limiter: Arc<RateLimiter>,
+    ) -> BoxFuture<'a, Result<PathBuf>> {
+        self.download_file_with_progress(
+            model_id,
+            revision,
+            rfilename,
+            Some(limiter),
+            Arc::new(|_| {}),
+        )
+    }
+
+    fn download_file_with_progress<'a>(
+        &'a self,
+        model_id: &'a str,
+        revision: Option<&'a str>,
+        rfilename: &'a str,
+        limiter: Option<Arc<RateLimiter>>,
+        progress: TransferProgress,
+    ) -> BoxFuture<'a, Result<PathBuf>> {
+        Box::pin(async move {
+            self.repo(model_id, revision)
+                .download_with_progress(rfilename, Transfer::new(limiter, progress))
+                .await
+                .with_context(|| format!("{rfilename} download faild"))
+        })
+    }
+
+    fn cache(&self) -> Cache {
+        self.cache.clone()
+    }
+}
+
+/// The Hub cache in `cache_dir` with no Hub behind it
+///
+/// What [`ModelManagerBuilder`](crate::ModelManagerBuilder) uses when si is built
+/// without the `hub` feature and no source is given: everything that only reads
+/// the cache, like `sync` and `gc`, works, while planning and downloading fail
+/// with [`SiError::HubDisabled`], or [`SiError::Offline`] for a source made with
+/// [`offline`](Self::offline).
+#[derive(Debug, Clone)]
+pub struct OfflineSource {
+    cache: Cache,
+    cause: SiError,
+}
+
+impl OfflineSource {
+    pub fn new(cache_dir: PathBuf) -> Self {
+        Self {
+            cache: Cache::new(cache_dir),
+            cause: SiError::HubDisabled,
+        }
+    }
+
+    /// The cache in `cache_dir` for a build that has the Hub but was told to
+    /// stay offline
+    pub fn offline(cache_dir: PathBuf) -> Self {
+        Self {
+            cache: Cache::new(cache_dir),
+            cause: SiError::Offline,
+        }
+    }
+}
+
+impl ModelSource for OfflineSource {
+    fn repo_info<'a>(
+        &'a self,
+        model_id: &'a str,
+        _revision: Option<&'a str>,
+    ) -> BoxFuture<'a, Result<RepoInfo>> {
+        Box::pin(async move {
+            Err(anyhow::Error::new(self.cause.clone())
+                .context(format!("Failed to get info for `{model_id}`")))
+        })
+    }
+
+    fn download_file<'a>(
+        &'a self,
+        _model_id: &'a str,
+        _revision: Option<&'a str>,
+        rfilename: &'a str,
+    ) -> BoxFuture<'a, Result<PathBuf>> {
+        Box::pin(async move {
+            Err(anyhow::Error::new(self.cause.clone())
+                .context(format!("{rfilename} download failed")))
+        })
+    }
+
+    fn cache(&self) -> Cache {
+        self.cache.clone()
+    }
+}
+
+/// Tries several sources in order, moving on to the next when a request fails
+///
+/// Each source is named by its endpoint. Once a source has answered
+/// [`repo_info`](ModelSource::repo_info) for a model, that model's downloads go
+/// to it first. The cache is the first source's; sources are expected to share it.
+#[derive(Debug)]
+pub struct FallbackSource {
+    sources: Vec<(String, Box<dyn ModelSource>)>,
+    /// Index of the source that last answered for each model
+    chosen: Mutex<HashMap<String, usize>>,
+}
+
+impl FallbackSource {
+    pub fn new(sources: Vec<(String, Box<dyn ModelSource>)>) -> Result<Self> {
+        if sources.is_empty() {
+            bail!("A fallback source needs at least one endpoint");
+        }
+        Ok(Self {
+            sources,
+            chosen: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// One [`HfSource`] per endpoint, in order
+    #[cfg(feature = "hub")]
+    pub fn hf(endpoints: &[String]) -> Result<Self> {
+        Self::hf_in(endpoints, paths::hf_cache_dir())
+    }
+
+    /// Like [`hf`](Self::hf), with every endpoint downloading into `cache_dir`
+    #[cfg(feature = "hub")]
+    pub fn hf_in(endpoints: &[String], cache_dir: PathBuf) -> Result<Self> {
+        let sources = endpoints
+            .iter()
+            .map(|endpoint| {
+                let source: Box<dyn ModelSource> =
+                    Box::new(HfSource::with_endpoint_in(endpoint, cache_dir.clone())?);
+                Ok((endpoint.clone(), source))
+            })
+            .collect::<Result<_>>()?;
+        Self::new(sources)
+    }
+
+    /// The endpoint that last answered for `model_id`, if any has
+    pub fn chosen_endpoint(&self, model_id: &str) -> Option<&str> {
+        let index = *self.chosen.lock().expect("chosen lock").get(model_id)?;
+        Some(&self.sources[index].0)
+    }
+
+    /// Source indices to try for `model_id`: the chosen one first, then the rest in order
+    fn order(&self, model_id: &str) -> Vec<usize> {
+        let chosen = self
+            .chosen
+            .lock()
+            .expect("chosen lock")
+            .get(model_id)
+            .copied();
+        chosen
+            .into_iter()
+            .chain((0..self.sources.len()).filter(|&i| Some(i) != chosen))
+            .collect()
+    }
+
+    fn choose(&self, model_id: &str, index: usize) {
+        debug!("Using endpoint {} for {model_id}", self.sources[index].0);
+        self.chosen
+            .lock()
+            .expect("chosen lock")
+            .insert(model_id.to_string(), index);
+    }
+
+    /// Run `request` against each source in turn until one succeeds
+    async fn first_success<'a, T>(
+        &'a self,
+        model_id: &str,
+        what: &str,
+        request: impl Fn(&'a dyn ModelSource) -> BoxFuture<'a, Result<T>>,
+    ) -> Result<T> {
+        let order = self.order(model_id);
+        let mut last_error = None;
+        for (attempt, &index) in order.iter().enumerate() {
+            let (endpoint, source) = &self.sources[index];
+            match request(source.as_ref()).await {
+                Ok(value) => {
+                    self.choose(model_id, index);
+                    return Ok(value);
+                }
+                Err(e) => {
+                    if let Some(&next) = order.get(attempt + 1) {
+                        warn!(
+                            "{what} failed at {endpoint}: {e:#}; retrying at {}",
+                            self.sources[next].0
+                        );
+                    }
+                    last_error = Some(e);
+                }
+            }
+        }
+        let error = last_error.expect("at least one source");
+        Err(error.context(format!("{what} failed at all {} endpoints", order.len())))
+    }
+}
+
+impl ModelSource for FallbackSource {
+    fn repo_info<'a>(
+        &'a self,
+        model_id: &'a str,
+        revision: Option<&'a str>,
+    ) -> BoxFuture<'a, Result<RepoInfo>> {
+        Box::pin(async move {
+            let what = format!("Info request for `{model_id}`");
+            let mut info = self
+                .first_success(model_id, &what, |source| {
+                    source.repo_info(model_id, revision)
+                })
+                .await?;
+            if info.endpoint.is_none() {
+                info.endpoint = self.chosen_endpoint(model_id).map(str::to_string);
+            }
+            Ok(info)
+        })
+    }
+
+    fn download_file<'a>(
+        &'a self,
+        model_id: &'a str,
+        revision: Option<&'a str>,
+        rfilename: &'a str,
+    ) -> BoxFuture<'a, Result<PathBuf>> {
+        Box::pin(async move {
+            let what = format!("Download of {rfilename}");
+            self.first_success(model_id, &what, |source| {
+                source.download_file(model_id, revision, rfilename)
+            })
+            .await
+        })
+    }
+
+    fn download_file_limited<'a>(
+        &'a self,
+        model_id: &'a str,
+        revision: Option<&'a str>,
+        rfilename: &'a str,
+        limiter: Arc<RateLimiter>,
+    ) -> BoxFuture<'a, Result<PathBuf>> {
+        Box::pin(async move {
+            let what = format!("Download of {rfilename}");
+            self.first_success(model_id, &what, |source| {
+                source.download_file_limited(model_id, revision, rfilename, limiter.clone())
+            })
+            .await
+        })
+    }
+
+    fn download_file_with_progress<'a>(
+        &'a self,
+        model_id: &'a str,
+        revision: Option<&'a str>,
+        rfilename: &'a str,
+        limiter: Option<Arc<RateLimiter>>,
+        progress: TransferProgress,
+    ) -> BoxFuture<'a, Result<PathBuf>> {
+        Box::pin(async move {
+            let what = format!("Download of {rfilename}");
+            self.first_success(model_id, &what, |source| {
+                source.download_file_with_progress(
+                    model_id,
+                    revision,
+                    rfilename,
+                    limiter.clone(),
+                    progress.clone(),
+                )
+            })
+            .await
+        })
+    }
+
+    fn cache(&self) -> Cache {
+        self.sources[0].1.cache()
+    }
+}
+
+/// Serves models from a fixture directory laid out as `<fixtures>/<org>/<repo>/<files>`
+///
+/// Every revision serves the same fixtures; a requested revision only changes the
+/// snapshot the files are stored under. The default branch is at
+/// [`MOCK_REVISION`] until [`MockSource::set_default_revision`] moves it.
+///
+/// Downloads are copied into `cache_dir` using the Hub cache layout
+/// (`models--org--repo/{refs/main,snapshots/<rev>/...}`), so everything that reads
+/// the cache behaves as it would after a real download.
+#[derive(Debug)]
+pub struct MockSource {
+    fixtures_dir: PathBuf,
+    cache_dir: PathBuf,
+    licenses: HashMap<String, String>,
+    pipeline_tags: HashMap<String, String>,
+    delay: Option<Duration>,
+    downloads: Mutex<Vec<String>>,
+    default_revision: Mutex<String>,
+}
+
+impl MockSource {
+    pub fn new(fixtures_dir: impl Into<PathBuf>, cache_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            fixtures_dir: fixtures_dir.into(),
+            cache_dir: cache_dir.into(),
+            licenses: HashMap::new(),
+            pipeline_tags: HashMap::new(),
+            delay: None,
+            downloads: Mutex::new(Vec::new()),
+            default_revision: Mutex::new(MOCK_REVISION.to_string()),
+        }
+    }
+
+    /// Report `license` in the repo info of `model_id`
+    pub fn with_license(mut self, model_id: &str, license: &str) -> Self {
+        self.licenses
+            .insert(model_id.to_string(), license.to_string());
+        self
+    }
+
+    /// Report `pipeline_tag` in the repo info of `model_id`
+    pub fn with_pipeline_tag(mut self, model_id: &str, pipeline_tag: &str) -> Self {
+        self.pipeline_tags
+            .insert(model_id.to_string(), pipeline_tag.to_string());
+        self
+    }
+
+    /// Simulate a slow connection: each download sits in an `.incomplete` temp
+    /// file for `delay` before being moved into place
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = Some(delay);
+        self
+    }
+
+    /// Move the default branch of every repository to `revision`, as a push to
+    /// the Hub would
+    pub fn set_default_revision(&self, revision: &str) {
+        *self.default_revision.lock().expect("revision lock") = revision.to_string();
+    }
+
+    fn default_revision(&self) -> String {
+        self.default_revision.lock().expect("revision lock").clone()
+    }
+
+    /// `model_id/rfilename` of every file downloaded so far, in order
+    pub fn downloads(&self) -> Vec<String> {
+        self.downloads.lock().expect("downloads lock").clone()
+    }
+
+    fn repo_dir(&self, model_id: &str) -> PathBuf {
+        self.fixtures_dir.join(model_id)
+    }
+
+    fn repo_cache_dir(&self, model_id: &str) -> PathBuf {
+        self.cache_dir
+            .join(format!("models--{}", model_id.replace('/', "--")))
+    }
+
+    /// Copy a fixture into the cache, in [`MOCK_CHUNK`] pieces taken from
+    /// `limiter` when there is one
+    async fn fetch(
+        &self,
+        model_id: &str,
+        revision: Option<&str>,
+        rfilename: &str,
+        limiter: Option<&RateLimiter>,
+    ) -> Result<PathBuf> {
+        let source = self.repo_dir(model_id).join(rfilename);
+        let repo_cache_dir = self.repo_cache_dir(model_id);
+        let snapshot = revision.map_or_else(|| self.default_revision(), str::to_string);
+        let target = repo_cache_dir
+            .join("snapshots")
+            .join(&snapshot)
+            .join(rfilename);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut tmp_name = target.as_os_str().to_owned();
+        tmp_name.push(".incomplete");
+        let tmp_path = PathBuf::from(tmp_name);
+        match limiter {
+            Some(limiter) => copy_paced(&source, &tmp_path, limiter).await,
+            None => fs::copy(&source, &tmp_path).map(drop).map_err(Into::into),
+        }
+        .with_context(|| format!("{rfilename} download faild"))?;
+        if let Some(delay) = self.delay {
+            tokio::time::sleep(delay).await;
+        }
+        fs::rename(&tmp_path, &target)?;
+
+        let refs_dir = repo_cache_dir.join("refs");
+        fs::create_dir_all(&refs_dir)?;
+        fs::write(refs_dir.join(revision.unwrap_or("main")), snapshot)?;
+
+        self.downloads
+            .lock()
+            .expect("downloads lock")
+            .push(format!("{model_id}/{rfilename}"));
+        Ok(target)
+    }
+}
+
+impl ModelSource for MockSource {
+    fn repo_info<'a>(
+        &'a self,
+        model_id: &'a str,
+        revision: Option<&'a str>,
+    ) -> BoxFuture<'a, Result<RepoInfo>> {
+        Box::pin(async move {
+            let repo_dir = self.repo_dir(model_id);
+            if !repo_dir.is_dir() {
+                bail!("Failed to get info for `{model_id}`: repository not found");
+            }
+
+            let mut files = Vec::new();
+            collect_relative_files(&repo_dir, &repo_dir, &mut files)?;
+            files.sort();
+            let sizes = files
+                .iter()
+                .map(|f| Ok((f.clone(), fs::metadata(repo_dir.join(f))?.len())))
+                .collect::<Result<_>>()?;
+
+            Ok(RepoInfo {
+                files,
+                license: self.licenses.get(model_id).cloned(),
+                pipeline_tag: self.pipeline_tags.get(model_id).cloned(),
+                revision: Some(revision.map_or_else(|| self.default_revision(), str::to_string)),
+                sizes,
+                endpoint: None,
+            })
+        })
+    }
+
+    fn download_file<'a>(
+        &'a self,
+        model_id: &'a str,
+        revision: Option<&'a str>,
+        rfilename: &'a str,
+    ) -> BoxFuture<'a, Result<PathBuf>> {
+        Box::pin(self.fetch(model_id, revision, rfilename, None))
+    }
+
+    fn download_file_limited<'a>(
+        &'a self,
+        model_id: &'a str,
+        revision: Option<&'a str>,
+        rfilename: &'a str,
+        limiter: Arc<RateLimiter>,
+    ) -> BoxFuture<'a, Result<PathBuf>> {
+        Box::pin(async move {
+            self.fetch(model_id, revision, rfilename, Some(&limiter))
+                .await
+        })
+    }
+
+    fn cache(&self) -> Cache {
+        Cache::new(self.cache_dir.clone())
+    }
+}
+
+/// Copy `from` to `to` a [`MOCK_CHUNK`] at a time, taking each from `limiter`
+/// first, as a paced network transfer would arrive
+async fn copy_paced(from: &Path, to: &Path, limiter: &RateLimiter) -> Result<()> {
+    let mut reader = fs::File::open(from)?;
+    let mut writer = fs::File::create(to)?;
+    let mut chunk = vec![0; MOCK_CHUNK];
+    loop {
+        let read = reader.read(&mut chunk)?;
+        if read == 0 {
+            return Ok(());
+        }
+        limiter.acquire(read as u64).await;
+        writer.write_all(&chunk[..read])?;
+    }
+}
+
+fn collect_relative_files(root: &Path, dir: &Path, files: &mut Vec<String>) -> Result<()> {
+    for entry in fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.display()))? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_relative_files(root, &path, files)?;
+        } else {
+            let relative = path.strip_prefix(root)?;
+            let parts: Vec<_> = relative
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy().into_owned())
+                .collect();
+            files.push(parts.join("/"));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    #[cfg(feature = "hub")]
+    fn test_hub_model_info_license() -> Result<()> {
+        let from_card: HubModelInfo = serde_json::from_str(
+            r#"{"siblings": [], "cardData": {"license": "openrail"}, "tags": ["license:mit"]}"#,
+        )?;
+        assert_eq!(from_card.license().as_deref(), Some("openrail"));
+
+        let from_tags: HubModelInfo =
+            serde_json::from_str(r#"{"siblings": [], "tags": ["license:apache-2.0"]}"#)?;
+        assert_eq!(from_tags.license().as_deref(), Some("apache-2.0"));
+
+        let none: HubModelInfo = serde_json::from_str(r#"{"siblings": []}"#)?;
+        assert_eq!(none.license(), None);
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "hub")]
+    fn test_repo_info_from_hub_model_info() -> Result<()> {
+        let info: HubModelInfo = serde_json::from_str(
+            r#"{"sha": "abc123", "siblings": [{"rfilename": "config.json", "size": 2}, {"rfilename": "unet/model.bin"}], "tags": ["license:mit"], "pipeline_tag": "text-to-image"}"#,
+        )?;
+        let repo = RepoInfo::from(info);
+        assert_eq!(repo.files, ["config.json", "unet/model.bin"]);
+        assert_eq!(repo.license.as_deref(), Some("mit"));
+        assert_eq!(repo.pipeline_tag.as_deref(), Some("text-to-image"));
+        assert_eq!(repo.revision.as_deref(), Some("abc123"));
+        assert_eq!(repo.sizes, HashMap::from([("config.json".to_string(), 2)]));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_mock_source_serves_fixtures_into_cache() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let fixtures = temp_dir.path().join("fixtures");
+        fs::create_dir_all(fixtures.join("org/repo/unet"))?;
+        fs::write(fixtures.join("org/repo/config.json"), "{}")?;
+        fs::write(fixtures.join("org/repo/unet/model.bin"), "weights")?;
+
+        let source = MockSource::new(&fixtures, temp_dir.path().join("cache"))
+            .with_license("org/repo", "mit");
+
+        let info = source.repo_info("org/repo", None).await?;
+        assert_eq!(info.files, ["config.json", "unet/model.bin"]);
+        assert_eq!(info.license.as_deref(), Some("mit"));
+        assert!(source.repo_info("org/missing", None).await.is_err());
+
+        let path = source
+            .download_file("org/repo", None, "unet/model.bin")
+            .await?;
+        assert_eq!(fs::read_to_string(&path)?, "weights");
+        assert_eq!(
+            source
+                .cache()
+                .model("org/repo".to_string())
+                .get("unet/model.bin"),
+            Some(path)
+        );
+        assert_eq!(source.downloads(), ["org/repo/unet/model.bin"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_endpoint_validation_and_precedence() {
+        for valid in ["https://huggingface.co", "http://mirror.internal:8080/hf/"] {
+            assert!(validate_endpoint(valid).is_ok(), "{valid}");
+        }
+        for invalid in [
+            "",
+            "huggingface.co",
+            "ftp://mirror",
+            "https://",
+            "https:// x",
+        ] {
+            assert!(validate_endpoint(invalid).is_err(), "{invalid:?}");
+        }
+
+        let mirror = "https://mirror.internal/";
+        assert_eq!(
+            resolve_endpoint(Some("https://explicit"), Some(mirror)),
+            "https://explicit"
+        );
+        assert_eq!(
+            resolve_endpoint(None, Some(mirror)),
+            "https://mirror.internal"
+        );
+        assert_eq!(resolve_endpoint(None, Some("")), DEFAULT_HF_ENDPOINT);
+        assert_eq!(resolve_endpoint(None, None), DEFAULT_HF_ENDPOINT);
+    }
+
+    #[tokio::test]
+    async fn test_offline_source_names_offline_mode() {
+        assert!(offline_env(Some("1")) && offline_env(Some(" TRUE ")));
+        assert!(!offline_env(Some("0")) && !offline_env(Some("")) && !offline_env(None));
+
+        let source = OfflineSource::offline(PathBuf::from("cache"));
+        let err = source
+            .download_file("org/repo", None, "model.bin")
+            .await
+            .unwrap_err();
+        assert!(SiError::Offline.matches(&err), "{err:#}");
+    }
+
+    #[tokio::test]
+    async fn test_fallback_source_moves_to_next_endpoint() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let fixtures = temp_dir.path().join("fixtures");
+        fs::create_dir_all(fixtures.join("org/repo"))?;
+        fs::write(fixtures.join("org/repo/model.bin"), "weights")?;
+
+        // The primary has no fixtures, so every request to it fails
+        let blocked = Arc::new(MockSource::new(
+            temp_dir.path().join("empty"),
+            temp_dir.path().join("cache"),
+        ));
+        let mirror = Arc::new(MockSource::new(&fixtures, temp_dir.path().join("cache")));
+        let sources: Vec<(String, Box<dyn ModelSource>)> = vec![
+            ("https://blocked".to_string(), Box::new(blocked.clone())),
+            ("https://mirror".to_string(), Box::new(mirror.clone())),
+        ];
+        let source = FallbackSource::new(sources)?;
+        assert_eq!(source.chosen_endpoint("org/repo"), None);
+
+        let info = source.repo_info("org/repo", None).await?;
+        assert_eq!(info.files, ["model.bin"]);
+        assert_eq!(info.endpoint.as_deref(), Some("https://mirror"));
+        assert_eq!(source.chosen_endpoint("org/repo"), Some("https://mirror"));
+
+        source.download_file("org/repo", None, "model.bin").await?;
+        assert_eq!(mirror.downloads(), ["org/repo/model.bin"]);
+        assert!(blocked.downloads().is_empty());
+
+        let err = source.repo_info("org/missing", None).await.unwrap_err();
+        assert!(format!("{err:#}").contains("all 2 endpoints"), "{err:#}");
+        assert!(FallbackSource::new(vec![]).is_err());
+        Ok(())
+    }
+}
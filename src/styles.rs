@@ -0,0 +1,369 @@
+//! Fabric style presets
+//!
+//! A [`StylePreset`] describes how a material changes the look of recolored
+//! clothing. The presets in [`StyleRegistry::builtin`] are compiled in;
+//! `styles.toml` in the config directory adds more and replaces built-ins of the
+//! same name:
+//!
+//! ```toml
+//! [styles.faux_leather]
+//! keywords = ["faux leather", "pleather"]
+//! contrast = 1.1
+//! brightness = -0.04
+//!
+//! [styles.silk]
+//! contrast = 1.2
+//! texture = "textures/satin.png"
+//! ```
+//!
+//! A description selects the preset with the longest keyword it contains, so
+//! "faux leather jacket" gets `faux_leather` rather than `leather`. A preset's
+//! `texture` is what `--texture auto` lays over the garment: a built-in texture
+//! name or an image path, relative to the config directory.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::Path,
+};
+
+use anyhow::{Context, Result, bail};
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use crate::prompt::ParsedPrompt;
+
+/// Names of the textures compiled into si, which `texture` may name instead of
+/// a file
+pub const BUILTIN_TEXTURES: [&str; 3] = ["denim", "knit", "leather"];
+
+pub const STYLES_FILENAME: &str = "styles.toml";
+
+/// How a material changes recolored clothing
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StylePreset {
+    /// Words in a description that select the preset, matched ignoring case;
+    /// the preset's name when empty
+    #[serde(default)]
+    pub keywords: Vec<String>,
+    /// Contrast multiplier, 1 for none
+    #[serde(default = "neutral_contrast")]
+    pub contrast: f32,
+    /// Brightness offset, 0 for none
+    #[serde(default)]
+    pub brightness: f32,
+    /// Amount of grain, 0 for none; not applied yet
+    #[serde(default)]
+    pub noise: f32,
+    /// Strength of specular highlights, 0 for none; not applied yet
+    #[serde(default)]
+    pub sheen: f32,
+    /// Texture laid over the garment by `--texture auto`: a built-in texture
+    /// name or an image path
+    #[serde(default)]
+    pub texture: Option<String>,
+}
+
+fn neutral_contrast() -> f32 {
+    1.0
+}
+
+impl StylePreset {
+    /// The preset that changes nothing, used for unknown materials
+    pub const NEUTRAL: Self = Self {
+        keywords: Vec::new(),
+        contrast: 1.0,
+        brightness: 0.0,
+        noise: 0.0,
+        sheen: 0.0,
+        texture: None,
+    };
+
+    fn builtin(keywords: &[&str], contrast: f32, brightness: f32) -> Self {
+        Self {
+            keywords: keywords.iter().map(|k| k.to_string()).collect(),
+            contrast,
+            brightness,
+            ..Self::NEUTRAL
+        }
+    }
+
+    fn with_texture(mut self, texture: &str) -> Self {
+        self.texture = Some(texture.to_string());
+        self
+    }
+
+    /// `(contrast_mult, brightness_offset)` as the pixel transform takes them
+    pub fn adjustments(&self) -> (f32, f32) {
+        (self.contrast, self.brightness)
+    }
+
+    fn validate(&self, name: &str) -> Result<()> {
+        if !(self.contrast.is_finite() && self.contrast > 0.0) {
+            bail!("style `{name}`: contrast must be a positive number");
+        }
+        for (field, value) in [
+            ("brightness", self.brightness),
+            ("noise", self.noise),
+            ("sheen", self.sheen),
+        ] {
+            if !value.is_finite() {
+                bail!("style `{name}`: {field} must be a finite number");
+            }
+        }
+        if self.keywords.iter().any(|k| k.trim().is_empty()) {
+            bail!("style `{name}`: keywords can't be empty");
+        }
+        if self.texture.as_ref().is_some_and(|t| t.trim().is_empty()) {
+            bail!("style `{name}`: texture can't be empty");
+        }
+        Ok(())
+    }
+}
+
+/// Contents of `styles.toml`
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StylesFile {
+    #[serde(default)]
+    styles: BTreeMap<String, StylePreset>,
+}
+
+/// Named style presets, built in or from `styles.toml`
+#[derive(Debug, Clone)]
+pub struct StyleRegistry {
+    presets: BTreeMap<String, StylePreset>,
+    /// Names defined in `styles.toml`, including those replacing a built-in
+    user_defined: BTreeSet<String>,
+}
+
+impl Default for StyleRegistry {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+impl StyleRegistry {
+    /// The presets compiled into si
+    pub fn builtin() -> Self {
+        let presets = [
+            // Higher contrast, slight brightness boost
+            ("silk", StylePreset::builtin(&["silk", "satin"], 1.15, 0.05)),
+            // High contrast, darker
+            (
+                "leather",
+                StylePreset::builtin(&["leather"], 1.25, -0.1).with_texture("leather"),
+            ),
+            // Slight contrast boost, slightly darker
+            (
+                "denim",
+                StylePreset::builtin(&["denim"], 1.1, -0.05).with_texture("denim"),
+            ),
+            // Slight contrast boost, a little darker in the gaps between stitches
+            (
+                "knit",
+                StylePreset::builtin(&["knit"], 1.05, -0.02).with_texture("knit"),
+            ),
+            // Subtle adjustments
+            ("cotton", StylePreset::builtin(&["cotton"], 1.05, 0.02)),
+            // Higher contrast, darker
+            ("velvet", StylePreset::builtin(&["velvet"], 1.2, -0.08)),
+            // Lower contrast, brighter
+            ("linen", StylePreset::builtin(&["linen"], 0.95, 0.08)),
+        ];
+        Self {
+            presets: presets
+                .into_iter()
+                .map(|(name, preset)| (name.to_string(), preset))
+                .collect(),
+            user_defined: BTreeSet::new(),
+        }
+    }
+
+    /// The built-in presets merged with `styles.toml` in `config_dir`, if there
+    /// is one
+    pub fn load(config_dir: &Path) -> Result<Self> {
+        let mut registry = Self::builtin();
+        let path = config_dir.join(STYLES_FILENAME);
+        if !path.exists() {
+            debug!("No styles file at {}", path.display());
+            return Ok(registry);
+        }
+        let text = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read styles from {}", path.display()))?;
+        let file: StylesFile = toml::from_str(&text)
+            .with_context(|| format!("Failed to parse styles from {}", path.display()))?;
+        for (name, mut preset) in file.styles {
+            preset
+                .validate(&name)
+                .with_context(|| format!("Invalid styles file {}", path.display()))?;
+            for keyword in &mut preset.keywords {
+                *keyword = keyword.trim().to_lowercase();
+            }
+            // Texture paths are relative to the config directory, as the
+            // styles file is
+            if let Some(texture) = &mut preset.texture {
+                *texture = texture.trim().to_string();
+                if !BUILTIN_TEXTURES.contains(&texture.as_str()) {
+                    *texture = config_dir.join(&*texture).to_string_lossy().into_owned();
+                }
+            }
+            registry.user_defined.insert(name.clone());
+            registry.presets.insert(name, preset);
+        }
+        Ok(registry)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&StylePreset> {
+        self.presets.get(name)
+    }
+
+    /// Every preset by name, sorted
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &StylePreset)> {
+        self.presets
+            .iter()
+            .map(|(name, preset)| (name.as_str(), preset))
+    }
+
+    /// Whether `name` comes from `styles.toml` rather than the built-ins
+    pub fn is_user_defined(&self, name: &str) -> bool {
+        self.user_defined.contains(name)
+    }
+
+    /// The preset with the longest keyword in `text`, ignoring case
+    pub fn match_description(&self, text: &str) -> Option<&StylePreset> {
+        let text = text.to_lowercase();
+        self.best_match(|keyword| text.contains(keyword).then_some(1.0))
+    }
+
+    /// The preset whose keyword is in the most heavily weighted positive term of
+    /// `prompt`, the longest keyword winning among equally weighted ones
+    pub fn match_prompt(&self, prompt: &ParsedPrompt) -> Option<&StylePreset> {
+        self.best_match(|keyword| prompt.positive_weight(keyword))
+    }
+
+    /// The preset with the heaviest, then longest, keyword that `weight` finds;
+    /// the first by name on a tie
+    fn best_match(&self, weight: impl Fn(&str) -> Option<f32>) -> Option<&StylePreset> {
+        let mut best: Option<(f32, usize, &StylePreset)> = None;
+        for (name, preset) in &self.presets {
+            let keywords = if preset.keywords.is_empty() {
+                std::slice::from_ref(name)
+            } else {
+                preset.keywords.as_slice()
+            };
+            for keyword in keywords {
+                let Some(weight) = weight(keyword).filter(|w| *w > 0.0) else {
+                    continue;
+                };
+                let len = keyword.chars().count();
+                if best.is_none_or(|(w, l, _)| (weight, len) > (w, l)) {
+                    best = Some((weight, len, preset));
+                }
+            }
+        }
+        best.map(|(_, _, preset)| preset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_user_styles_shadow_builtins() -> Result<()> {
+        let temp_dir = tempdir()?;
+        fs::write(
+            temp_dir.path().join(STYLES_FILENAME),
+            "[styles.silk]\ncontrast = 1.3\n\n\
+             [styles.tweed]\nkeywords = [\"Tweed\", \"harris\"]\nbrightness = -0.02\nnoise = 0.4\n",
+        )?;
+
+        let registry = StyleRegistry::load(temp_dir.path())?;
+        let silk = registry.get("silk").expect("silk is defined");
+        assert_eq!(silk.adjustments(), (1.3, 0.0));
+        // Without keywords the name is the keyword, so `satin` no longer matches
+        assert_eq!(registry.match_description("silk scarf"), Some(silk));
+        assert_eq!(registry.match_description("satin scarf"), None);
+        assert!(registry.is_user_defined("silk"));
+        assert!(!registry.is_user_defined("leather"));
+
+        let tweed = registry.match_description("TWEED blazer").expect("tweed");
+        assert_eq!(tweed.noise, 0.4);
+        assert_eq!(tweed.contrast, 1.0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_longest_keyword_wins() -> Result<()> {
+        let temp_dir = tempdir()?;
+        fs::write(
+            temp_dir.path().join(STYLES_FILENAME),
+            "[styles.faux_leather]\nkeywords = [\"faux leather\"]\ncontrast = 1.1\n",
+        )?;
+        let registry = StyleRegistry::load(temp_dir.path())?;
+
+        let faux = registry.get("faux_leather");
+        assert_eq!(registry.match_description("faux leather jacket"), faux);
+        assert_eq!(
+            registry.match_description("leather jacket"),
+            registry.get("leather")
+        );
+        assert_eq!(registry.match_description("wool jacket"), None);
+
+        // Weight comes before length in prompts
+        let prompt = ParsedPrompt::parse("faux leather jacket, (silk:1.4) lining")?;
+        assert_eq!(registry.match_prompt(&prompt), registry.get("silk"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_preset_textures() -> Result<()> {
+        let temp_dir = tempdir()?;
+        fs::write(
+            temp_dir.path().join(STYLES_FILENAME),
+            "[styles.tweed]\ntexture = \"textures/tweed.png\"\n\n\
+             [styles.boucle]\ntexture = \" knit \"\n",
+        )?;
+        let registry = StyleRegistry::load(temp_dir.path())?;
+        let texture = |name: &str| registry.get(name).and_then(|p| p.texture.clone());
+        assert_eq!(texture("denim").as_deref(), Some("denim"));
+        assert_eq!(texture("knit").as_deref(), Some("knit"));
+        assert_eq!(texture("silk"), None);
+        // Files are found next to the styles file; built-in names stay names
+        assert_eq!(
+            texture("tweed").map(PathBuf::from),
+            Some(temp_dir.path().join("textures/tweed.png"))
+        );
+        assert_eq!(texture("boucle").as_deref(), Some("knit"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_malformed_styles_file_is_an_error() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join(STYLES_FILENAME);
+        for (text, expected) in [
+            ("[styles.silk\ncontrast = 1.3\n", "Failed to parse"),
+            ("[styles.silk]\ncontrast = \"high\"\n", "Failed to parse"),
+            ("[styles.silk]\nshine = 0.5\n", "shine"),
+            (
+                "[styles.silk]\ncontrast = -1.0\n",
+                "contrast must be a positive",
+            ),
+            (
+                "[styles.silk]\nkeywords = [\" \"]\n",
+                "keywords can't be empty",
+            ),
+            ("[styles.silk]\ntexture = \"\"\n", "texture can't be empty"),
+        ] {
+            fs::write(&path, text)?;
+            let err = StyleRegistry::load(temp_dir.path()).unwrap_err();
+            assert!(format!("{err:#}").contains(expected), "{text}: {err:#}");
+        }
+        Ok(())
+    }
+}
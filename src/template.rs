@@ -0,0 +1,400 @@
+//! Output path templates for generated images
+//!
+//! A template is a path string containing `{placeholder}` segments, e.g.
+//! `"{stem}/{model}/{date}_{prompt:.20}_{seed}.{ext}"`. Supported placeholders:
+//!
+//! - `stem`: input file name without its extension
+//! - `prompt`: the prompt, slugified (lowercase alphanumerics joined by `_`)
+//! - `model`: short model name (the part after the last `/` of the model id)
+//! - `date`: local date as `YYYY-MM-DD`
+//! - `time`: local time as `HHMMSS`
+//! - `seed`: generation seed, or `noseed` when none was used
+//! - `strength`: strength with two decimals
+//! - `variation`: number of the variation in a `--count` run, from 1 (`1` for
+//!   a single output)
+//! - `ext`: output file extension without the dot
+//!
+//! Any placeholder accepts a `:.N` suffix truncating the value to `N` characters.
+//! Literal braces are written as `{{` and `}}`.
+
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::{Result, bail};
+use chrono::{DateTime, Local};
+
+pub const DEFAULT_OUTPUT_TEMPLATE: &str = "{stem}_{prompt}_tryon.{ext}";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Placeholder {
+    Stem,
+    Prompt,
+    Model,
+    Date,
+    Time,
+    Seed,
+    Strength,
+    Variation,
+    Ext,
+}
+
+impl FromStr for Placeholder {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "stem" => Self::Stem,
+            "prompt" => Self::Prompt,
+            "model" => Self::Model,
+            "date" => Self::Date,
+            "time" => Self::Time,
+            "seed" => Self::Seed,
+            "strength" => Self::Strength,
+            "variation" => Self::Variation,
+            "ext" => Self::Ext,
+            other => bail!("Unknown placeholder `{{{other}}}` in output template"),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Placeholder {
+        placeholder: Placeholder,
+        max_len: Option<usize>,
+    },
+}
+
+/// Values substituted into an [`OutputTemplate`]
+#[derive(Debug, Clone)]
+pub struct TemplateContext {
+    pub input_path: PathBuf,
+    pub prompt: String,
+    pub model_id: String,
+    pub seed: Option<u64>,
+    pub strength: f64,
+    /// Number of the variation, from 1, in a run making several
+    pub variation: Option<usize>,
+    pub extension: String,
+    pub timestamp: DateTime<Local>,
+}
+
+impl TemplateContext {
+    pub fn new(input_path: &Path, prompt: &str, model_id: &str) -> Self {
+        Self {
+            input_path: input_path.to_path_buf(),
+            prompt: prompt.to_string(),
+            model_id: model_id.to_string(),
+            seed: None,
+            strength: 0.5,
+            variation: None,
+            extension: "png".to_string(),
+            timestamp: Local::now(),
+        }
+    }
+
+    fn value(&self, placeholder: Placeholder) -> String {
+        match placeholder {
+            Placeholder::Stem => self
+                .input_path
+                .file_stem()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_else(|| "output".to_string()),
+            Placeholder::Prompt => slugify(&self.prompt),
+            Placeholder::Model => self
+                .model_id
+                .rsplit('/')
+                .next()
+                .unwrap_or(&self.model_id)
+                .to_string(),
+            Placeholder::Date => self.timestamp.format("%Y-%m-%d").to_string(),
+            Placeholder::Time => self.timestamp.format("%H%M%S").to_string(),
+            Placeholder::Seed => self
+                .seed
+                .map(|s| s.to_string())
+                .unwrap_or_else(|| "noseed".to_string()),
+            Placeholder::Strength => format!("{:.2}", self.strength),
+            Placeholder::Variation => self.variation.unwrap_or(1).to_string(),
+            Placeholder::Ext => self.extension.trim_start_matches('.').to_string(),
+        }
+    }
+}
+
+/// A parsed output path template
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputTemplate {
+    source: String,
+    segments: Vec<Segment>,
+}
+
+impl Default for OutputTemplate {
+    fn default() -> Self {
+        DEFAULT_OUTPUT_TEMPLATE
+            .parse()
+            .expect("default output template is valid")
+    }
+}
+
+impl fmt::Display for OutputTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl FromStr for OutputTemplate {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = s.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '}' => bail!("Unmatched `}}` in output template `{s}`"),
+                '{' => {
+                    let mut spec = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => spec.push(c),
+                            None => bail!("Unclosed `{{` in output template `{s}`"),
+                        }
+                    }
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(parse_placeholder(&spec)?);
+                }
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+
+        if segments.is_empty() {
+            bail!("Output template must not be empty");
+        }
+
+        Ok(Self {
+            source: s.to_string(),
+            segments,
+        })
+    }
+}
+
+fn parse_placeholder(spec: &str) -> Result<Segment> {
+    let (name, max_len) = match spec.split_once(':') {
+        Some((name, format)) => {
+            let Some(len) = format.strip_prefix('.') else {
+                bail!("Invalid format `{format}` for placeholder `{name}`, expected `.N`");
+            };
+            let len: usize = len
+                .parse()
+                .map_err(|_| anyhow::anyhow!("Invalid truncation length `{len}` for `{name}`"))?;
+            (name, Some(len))
+        }
+        None => (spec, None),
+    };
+
+    Ok(Segment::Placeholder {
+        placeholder: name.trim().parse()?,
+        max_len,
+    })
+}
+
+impl OutputTemplate {
+    /// Render the template, sanitizing substituted values for use in file names
+    pub fn render(&self, ctx: &TemplateContext) -> PathBuf {
+        let mut rendered = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(text) => rendered.push_str(text),
+                Segment::Placeholder {
+                    placeholder,
+                    max_len,
+                } => {
+                    let value = sanitize(&ctx.value(*placeholder));
+                    match max_len {
+                        Some(len) => rendered.extend(value.chars().take(*len)),
+                        None => rendered.push_str(&value),
+                    }
+                }
+            }
+        }
+        PathBuf::from(rendered)
+    }
+
+    /// Render the template relative to `base_dir`, appending `-1`, `-2`, ... to the
+    /// file stem until the path doesn't collide with an existing file
+    pub fn render_available(&self, base_dir: &Path, ctx: &TemplateContext) -> PathBuf {
+        available_path(&base_dir.join(self.render(ctx)))
+    }
+}
+
+/// Return `path`, or the first `stem-N.ext` sibling of it that doesn't exist yet
+pub fn available_path(path: &Path) -> PathBuf {
+    if !path.exists() {
+        return path.to_path_buf();
+    }
+
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let extension = path.extension().map(|e| e.to_string_lossy().into_owned());
+
+    (1..)
+        .map(|n| {
+            let name = match &extension {
+                Some(ext) => format!("{stem}-{n}.{ext}"),
+                None => format!("{stem}-{n}"),
+            };
+            path.with_file_name(name)
+        })
+        .find(|candidate| !candidate.exists())
+        .expect("unbounded range yields a free path")
+}
+
+fn slugify(text: &str) -> String {
+    text.chars()
+        .filter(|c| c.is_alphanumeric() || *c == ' ')
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join("_")
+        .to_lowercase()
+}
+
+/// Replace characters that are unsafe in file names on common filesystems
+fn sanitize(value: &str) -> String {
+    let sanitized: String = value
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+
+    match sanitized.as_str() {
+        "" | "." | ".." => "_".to_string(),
+        _ => sanitized,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use tempfile::tempdir;
+
+    fn context() -> TemplateContext {
+        TemplateContext {
+            input_path: PathBuf::from("/photos/person.jpg"),
+            prompt: "Red silk dress, long sleeves".to_string(),
+            model_id: "runwayml/stable-diffusion-v1-5".to_string(),
+            seed: Some(42),
+            strength: 0.5,
+            variation: Some(3),
+            extension: "png".to_string(),
+            timestamp: Local.with_ymd_and_hms(2025, 3, 9, 14, 5, 7).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_parse_and_render_all_placeholders() -> Result<()> {
+        let template: OutputTemplate =
+            "{stem}/{model}/{date}_{time}_{prompt}_{seed}_{strength}-{variation}.{ext}".parse()?;
+        let path = template.render(&context());
+
+        assert_eq!(
+            path,
+            PathBuf::from(
+                "person/stable-diffusion-v1-5/2025-03-09_140507_red_silk_dress_long_sleeves_42_0.50-3.png"
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_rejects_unknown_placeholder() {
+        let err = "{stem}_{colour}.png".parse::<OutputTemplate>().unwrap_err();
+        assert!(err.to_string().contains("colour"));
+    }
+
+    #[test]
+    fn test_parse_rejects_malformed_templates() {
+        assert!("{stem".parse::<OutputTemplate>().is_err());
+        assert!("stem}".parse::<OutputTemplate>().is_err());
+        assert!("{prompt:20}".parse::<OutputTemplate>().is_err());
+        assert!("{prompt:.x}".parse::<OutputTemplate>().is_err());
+        assert!("".parse::<OutputTemplate>().is_err());
+    }
+
+    #[test]
+    fn test_truncation() -> Result<()> {
+        let template: OutputTemplate = "{prompt:.8}.{ext}".parse()?;
+        assert_eq!(template.render(&context()), PathBuf::from("red_silk.png"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_escaped_braces() -> Result<()> {
+        let template: OutputTemplate = "{{{stem}}}.png".parse()?;
+        assert_eq!(template.render(&context()), PathBuf::from("{person}.png"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_sanitizes_hostile_characters() -> Result<()> {
+        let mut ctx = context();
+        ctx.input_path = PathBuf::from("/tmp/a:b*c?.jpg");
+        ctx.model_id = "..".to_string();
+
+        let template: OutputTemplate = "{model}/{stem}.png".parse()?;
+        assert_eq!(template.render(&ctx), PathBuf::from("_/a_b_c_.png"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_default_template_matches_legacy_naming() {
+        let path = OutputTemplate::default().render(&context());
+        assert_eq!(
+            path,
+            PathBuf::from("person_red_silk_dress_long_sleeves_tryon.png")
+        );
+    }
+
+    #[test]
+    fn test_collision_handling() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let template: OutputTemplate = "{stem}.{ext}".parse()?;
+        let ctx = context();
+
+        let first = template.render_available(temp_dir.path(), &ctx);
+        assert_eq!(first, temp_dir.path().join("person.png"));
+        std::fs::write(&first, b"")?;
+
+        let second = template.render_available(temp_dir.path(), &ctx);
+        assert_eq!(second, temp_dir.path().join("person-1.png"));
+        std::fs::write(&second, b"")?;
+
+        let third = template.render_available(temp_dir.path(), &ctx);
+        assert_eq!(third, temp_dir.path().join("person-2.png"));
+        Ok(())
+    }
+}
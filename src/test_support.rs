@@ -0,0 +1,282 @@
+//! Deterministic test images and tolerant image comparisons
+//!
+//! Built for si's own tests and, with the `test-util` feature, for downstream
+//! crates testing pipelines of their own. The generators depend only on their
+//! arguments, so the same call always yields the same pixels.
+//!
+//! Golden files are compared with [`assert_matches_golden`]: set
+//! [`UPDATE_GOLDEN_ENV`] to `1` to rewrite them from the current output. A golden
+//! that doesn't exist yet is recorded from the first run.
+
+use std::{fs, path::Path};
+
+use anyhow::{Context, Result, bail};
+use image::{Rgb, RgbImage};
+use log::warn;
+use palette::{FromColor, Lab, Srgb};
+
+/// Set to `1` to overwrite golden images instead of comparing against them
+pub const UPDATE_GOLDEN_ENV: &str = "SI_UPDATE_GOLDEN";
+
+/// Red rising left to right, green rising top to bottom, blue fixed at 128
+pub fn gradient(width: u32, height: u32) -> RgbImage {
+    RgbImage::from_fn(width, height, |x, y| {
+        Rgb([ramp(x, width), ramp(y, height), 128])
+    })
+}
+
+/// Eight vertical bars: white, yellow, cyan, green, magenta, red, blue, black
+pub fn color_bars(width: u32, height: u32) -> RgbImage {
+    const BARS: [[u8; 3]; 8] = [
+        [255, 255, 255],
+        [255, 255, 0],
+        [0, 255, 255],
+        [0, 255, 0],
+        [255, 0, 255],
+        [255, 0, 0],
+        [0, 0, 255],
+        [0, 0, 0],
+    ];
+    RgbImage::from_fn(width, height, |x, _| {
+        let bar = (x as usize * BARS.len()) / width.max(1) as usize;
+        Rgb(BARS[bar.min(BARS.len() - 1)])
+    })
+}
+
+/// A flat stand-in for a photo of a person: a near-white backdrop, a skin-toned
+/// head and arms, and a shaded blue-gray shirt across the middle of the frame
+/// where [`HeuristicSegmenter`](crate::HeuristicSegmenter) looks for clothing
+pub fn person_scene(width: u32, height: u32) -> RgbImage {
+    const BACKDROP: Rgb<u8> = Rgb([245, 245, 240]);
+    const SKIN: Rgb<u8> = Rgb([224, 180, 150]);
+    RgbImage::from_fn(width, height, |x, y| {
+        let (fx, fy) = (
+            (x as f32 + 0.5) / width as f32,
+            (y as f32 + 0.5) / height as f32,
+        );
+        let (head_dx, head_dy) = ((fx - 0.5) / 0.12, (fy - 0.2) / 0.12);
+        if head_dx * head_dx + head_dy * head_dy <= 1.0 {
+            return SKIN;
+        }
+        let in_torso_rows = (0.35..0.95).contains(&fy);
+        if in_torso_rows && (0.3..0.7).contains(&fx) {
+            // Light from above: the shirt darkens towards the hem
+            let shade = 1.0 - (fy - 0.35) * 0.5;
+            return Rgb([
+                (90.0 * shade) as u8,
+                (110.0 * shade) as u8,
+                (140.0 * shade) as u8,
+            ]);
+        }
+        if in_torso_rows && fy < 0.75 && ((0.22..0.3).contains(&fx) || (0.7..0.78).contains(&fx)) {
+            return SKIN;
+        }
+        BACKDROP
+    })
+}
+
+fn ramp(i: u32, len: u32) -> u8 {
+    if len <= 1 {
+        return 0;
+    }
+    (i * 255 / (len - 1)) as u8
+}
+
+/// CIE76 color difference between two sRGB pixels; about 2.3 is the smallest
+/// difference people notice
+pub fn delta_e(a: &Rgb<u8>, b: &Rgb<u8>) -> f32 {
+    let (a, b) = (to_lab(a), to_lab(b));
+    ((a.l - b.l).powi(2) + (a.a - b.a).powi(2) + (a.b - b.b).powi(2)).sqrt()
+}
+
+fn to_lab(pixel: &Rgb<u8>) -> Lab {
+    let [r, g, b] = pixel.0;
+    Lab::from_color(Srgb::new(r, g, b).into_format::<f32>().into_linear())
+}
+
+/// How far two images of the same size are apart
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ImageDiff {
+    pub mean_delta_e: f32,
+    pub max_delta_e: f32,
+    /// Largest difference of any one channel of any pixel
+    pub max_channel_diff: u8,
+    /// Pixels with any channel differing
+    pub differing_pixels: usize,
+}
+
+/// The largest [`ImageDiff`] still considered a match
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tolerance {
+    pub mean_delta_e: f32,
+    pub max_channel_diff: u8,
+}
+
+impl Default for Tolerance {
+    /// Rounding noise from float color conversions, but not a visible shift
+    fn default() -> Self {
+        Self {
+            mean_delta_e: 0.5,
+            max_channel_diff: 4,
+        }
+    }
+}
+
+impl Tolerance {
+    /// Pixel-identical images only
+    pub fn exact() -> Self {
+        Self {
+            mean_delta_e: 0.0,
+            max_channel_diff: 0,
+        }
+    }
+}
+
+impl ImageDiff {
+    pub fn within(&self, tolerance: &Tolerance) -> bool {
+        self.mean_delta_e <= tolerance.mean_delta_e
+            && self.max_channel_diff <= tolerance.max_channel_diff
+    }
+}
+
+/// Compare two images pixel by pixel; images of different sizes are an error
+pub fn compare(expected: &RgbImage, actual: &RgbImage) -> Result<ImageDiff> {
+    if expected.dimensions() != actual.dimensions() {
+        bail!(
+            "Image sizes differ: expected {:?}, got {:?}",
+            expected.dimensions(),
+            actual.dimensions()
+        );
+    }
+    let mut diff = ImageDiff {
+        mean_delta_e: 0.0,
+        max_delta_e: 0.0,
+        max_channel_diff: 0,
+        differing_pixels: 0,
+    };
+    let mut total_delta_e = 0.0f64;
+    for (e, a) in expected.pixels().zip(actual.pixels()) {
+        if e == a {
+            continue;
+        }
+        diff.differing_pixels += 1;
+        let channel_diff = e.0.iter().zip(a.0).map(|(e, a)| e.abs_diff(a)).max();
+        diff.max_channel_diff = diff.max_channel_diff.max(channel_diff.unwrap_or(0));
+        let delta = delta_e(e, a);
+        diff.max_delta_e = diff.max_delta_e.max(delta);
+        total_delta_e += f64::from(delta);
+    }
+    let pixels = (expected.width() as usize * expected.height() as usize).max(1);
+    diff.mean_delta_e = (total_delta_e / pixels as f64) as f32;
+    Ok(diff)
+}
+
+/// Compare `actual` with the PNG at `golden`, failing with the [`ImageDiff`] when
+/// it's outside `tolerance`
+///
+/// On a mismatch `actual` is written next to the golden as `<name>.actual.png`
+/// for inspection. With [`UPDATE_GOLDEN_ENV`] set, or when the golden doesn't
+/// exist yet, `actual` becomes the golden instead.
+pub fn assert_matches_golden(
+    golden: &Path,
+    actual: &RgbImage,
+    tolerance: &Tolerance,
+) -> Result<()> {
+    let update = std::env::var(UPDATE_GOLDEN_ENV).is_ok_and(|v| v == "1");
+    if update || !golden.exists() {
+        if !update {
+            warn!("Recording new golden image {}", golden.display());
+        }
+        if let Some(parent) = golden.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        return actual
+            .save(golden)
+            .with_context(|| format!("Failed to write golden image {}", golden.display()));
+    }
+
+    let expected = image::open(golden)
+        .with_context(|| format!("Failed to read golden image {}", golden.display()))?
+        .to_rgb8();
+    let diff = compare(&expected, actual)
+        .with_context(|| format!("Mismatch with {}", golden.display()))?;
+    if diff.within(tolerance) {
+        return Ok(());
+    }
+    let actual_path = golden.with_extension("actual.png");
+    actual
+        .save(&actual_path)
+        .with_context(|| format!("Failed to write {}", actual_path.display()))?;
+    bail!(
+        "{} differs from the golden image: {diff:?} exceeds {tolerance:?}; the output \
+         was written to {} (set {UPDATE_GOLDEN_ENV}=1 to accept it)",
+        golden.display(),
+        actual_path.display()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_generators_are_deterministic() {
+        assert_eq!(gradient(16, 8), gradient(16, 8));
+        assert_eq!(person_scene(24, 32), person_scene(24, 32));
+
+        let gradient = gradient(16, 8);
+        assert_eq!(gradient.get_pixel(0, 0), &Rgb([0, 0, 128]));
+        assert_eq!(gradient.get_pixel(15, 7), &Rgb([255, 255, 128]));
+        let bars = color_bars(16, 2);
+        assert_eq!(bars.get_pixel(0, 0), &Rgb([255, 255, 255]));
+        assert_eq!(bars.get_pixel(15, 1), &Rgb([0, 0, 0]));
+    }
+
+    #[test]
+    fn test_person_scene_has_clothing_to_segment() -> Result<()> {
+        use crate::segment::{HeuristicSegmenter, Segmenter};
+        let mask = HeuristicSegmenter.segment(&person_scene(40, 60))?;
+        let covered = mask.pixels().filter(|p| p.0[0] > 0).count();
+        assert!(covered > 200, "{covered}");
+        // The head is above the clothing region
+        assert_eq!(mask.get_pixel(20, 12).0[0], 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_compare_and_delta_e() -> Result<()> {
+        let image = color_bars(16, 4);
+        assert_eq!(compare(&image, &image)?.differing_pixels, 0);
+        assert!(compare(&image, &color_bars(8, 4)).is_err());
+
+        let mut nudged = image.clone();
+        nudged.put_pixel(0, 0, Rgb([253, 255, 255]));
+        let diff = compare(&image, &nudged)?;
+        assert_eq!((diff.differing_pixels, diff.max_channel_diff), (1, 2));
+        assert!(diff.within(&Tolerance::default()));
+        assert!(!diff.within(&Tolerance::exact()));
+
+        assert!(delta_e(&Rgb([255, 0, 0]), &Rgb([0, 0, 255])) > 100.0);
+        assert!(delta_e(&Rgb([100, 100, 100]), &Rgb([101, 100, 100])) < 1.0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_golden_round_trip() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let golden = temp_dir.path().join("golden/gradient.png");
+        let image = gradient(8, 8);
+
+        assert_matches_golden(&golden, &image, &Tolerance::exact())?;
+        assert!(golden.exists());
+        assert_matches_golden(&golden, &image, &Tolerance::exact())?;
+
+        let err =
+            assert_matches_golden(&golden, &color_bars(8, 8), &Tolerance::default()).unwrap_err();
+        assert!(err.to_string().contains("differs"), "{err:#}");
+        assert!(temp_dir.path().join("golden/gradient.actual.png").exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,372 @@
+//! Fabric textures laid over the recolored garment
+//!
+//! Color and the style presets change how bright and contrasty a garment is,
+//! not its surface. A [`TextureOverlay`] tiles a texture, such as a denim weave,
+//! across the clothing mask and blends its luminance into the pixels under it,
+//! weighted by the mask so feathered edges fade out. The textures named in
+//! [`BUILTIN_TEXTURES`] are compiled in; any image file works as well, its
+//! colors reduced to luminance.
+//!
+//! Both blend modes are centered on the texture's mean luminance, so a texture
+//! changes the garment's surface but not its overall brightness.
+
+use std::{borrow::Cow, fmt, path::Path, str::FromStr};
+
+use anyhow::{Context, Result, bail};
+use image::{GrayImage, ImageFormat, RgbImage};
+use serde::{Deserialize, Serialize};
+
+use crate::planner::TransformPlan;
+pub use crate::styles::BUILTIN_TEXTURES;
+
+/// How much of the texture shows where the mask is full, unless set otherwise
+pub const DEFAULT_TEXTURE_OPACITY: f32 = 0.35;
+
+fn builtin_png(name: &str) -> Option<&'static [u8]> {
+    match name {
+        "denim" => Some(include_bytes!("../assets/textures/denim.png")),
+        "knit" => Some(include_bytes!("../assets/textures/knit.png")),
+        "leather" => Some(include_bytes!("../assets/textures/leather.png")),
+        _ => None,
+    }
+}
+
+/// How a texture combines with the garment under it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TextureBlend {
+    /// Scale each pixel by the texture, darkening and lightening alike
+    #[default]
+    Multiply,
+    /// A photo editor's overlay: strongest in the midtones, leaving deep
+    /// shadows and highlights mostly alone
+    Overlay,
+}
+
+impl TextureBlend {
+    /// Channel value `c` with texture luminance `t` blended in, both 0 to 1,
+    /// for a texture whose mean luminance is `mean`
+    fn blend(self, c: f32, t: f32, mean: f32) -> f32 {
+        match self {
+            Self::Multiply => (c * t / mean.max(1.0 / 255.0)).min(1.0),
+            Self::Overlay => {
+                let t = (t - mean + 0.5).clamp(0.0, 1.0);
+                if c < 0.5 {
+                    2.0 * c * t
+                } else {
+                    1.0 - 2.0 * (1.0 - c) * (1.0 - t)
+                }
+            }
+        }
+    }
+}
+
+impl FromStr for TextureBlend {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "multiply" => Ok(Self::Multiply),
+            "overlay" => Ok(Self::Overlay),
+            other => bail!("Unknown texture blend `{other}`, expected multiply or overlay"),
+        }
+    }
+}
+
+impl fmt::Display for TextureBlend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Multiply => "multiply",
+            Self::Overlay => "overlay",
+        })
+    }
+}
+
+/// A tileable texture, reduced to its luminance
+#[derive(Debug, Clone, PartialEq)]
+pub struct Texture {
+    luma: GrayImage,
+    /// Mean luminance, 0 to 1
+    mean: f32,
+}
+
+impl Texture {
+    pub fn new(luma: GrayImage) -> Result<Self> {
+        let pixels = u64::from(luma.width()) * u64::from(luma.height());
+        if pixels == 0 {
+            bail!("A texture needs at least one pixel");
+        }
+        let sum: u64 = luma.pixels().map(|p| u64::from(p.0[0])).sum();
+        let mean = sum as f32 / pixels as f32 / 255.0;
+        Ok(Self { luma, mean })
+    }
+
+    /// The built-in texture `name`, one of [`BUILTIN_TEXTURES`]
+    pub fn builtin(name: &str) -> Option<Self> {
+        let png = builtin_png(name)?;
+        let image = image::load_from_memory_with_format(png, ImageFormat::Png)
+            .expect("built-in textures are valid PNGs");
+        Self::new(image.to_luma8()).ok()
+    }
+
+    /// The texture in the image file at `path`
+    pub fn load(path: &Path) -> Result<Self> {
+        let image = image::open(path)
+            .with_context(|| format!("Failed to read texture {}", path.display()))?;
+        Self::new(image.to_luma8()).with_context(|| format!("Invalid texture {}", path.display()))
+    }
+
+    /// The built-in texture `spec` names, or else the one in the file at `spec`
+    pub fn resolve(spec: &str) -> Result<Self> {
+        if let Some(texture) = Self::builtin(spec.trim()) {
+            return Ok(texture);
+        }
+        let path = Path::new(spec);
+        if !path.exists() {
+            bail!(
+                "No texture `{spec}`: it isn't a file or a built-in texture ({})",
+                BUILTIN_TEXTURES.join(", ")
+            );
+        }
+        Self::load(path)
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) {
+        self.luma.dimensions()
+    }
+
+    /// Luminance, 0 to 1, at output pixel `(x, y)` with the texture tiled from
+    /// the origin and drawn `scale` times its size
+    fn sample(&self, x: u32, y: u32, scale: f32) -> f32 {
+        let (width, height) = self.luma.dimensions();
+        let at = |v: u32, len: u32| (f64::from(v) / f64::from(scale)) as u64 % u64::from(len);
+        let pixel = self
+            .luma
+            .get_pixel(at(x, width) as u32, at(y, height) as u32);
+        f32::from(pixel.0[0]) / 255.0
+    }
+}
+
+/// Where a [`TextureOverlay`] gets its texture
+#[derive(Debug, Clone, PartialEq)]
+pub enum TextureSource {
+    /// The texture of the garment's fabric preset, as
+    /// [`TransformPlan::texture`] names it; none when the preset has none
+    Material,
+    Texture(Texture),
+}
+
+/// A texture tiled over the garment
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextureOverlay {
+    pub source: TextureSource,
+    /// How many times its own size the texture is drawn; 2 makes a weave
+    /// twice as coarse
+    pub scale: f32,
+    /// How much of the blended texture shows where the mask is full, 0 to 1
+    pub opacity: f32,
+    pub blend: TextureBlend,
+}
+
+impl TextureOverlay {
+    pub fn new(source: TextureSource) -> Self {
+        Self {
+            source,
+            scale: 1.0,
+            opacity: DEFAULT_TEXTURE_OPACITY,
+            blend: TextureBlend::default(),
+        }
+    }
+
+    /// Draw the texture `scale` times its size; must be positive
+    pub fn with_scale(mut self, scale: f32) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    pub fn with_opacity(mut self, opacity: f32) -> Self {
+        self.opacity = opacity;
+        self
+    }
+
+    pub fn with_blend(mut self, blend: TextureBlend) -> Self {
+        self.blend = blend;
+        self
+    }
+
+    /// The texture to lay over a garment planned as `plan`, if there is one
+    pub fn texture(&self, plan: &TransformPlan) -> Result<Option<Cow<'_, Texture>>> {
+        match &self.source {
+            TextureSource::Texture(texture) => Ok(Some(Cow::Borrowed(texture))),
+            TextureSource::Material => plan
+                .texture
+                .as_deref()
+                .map(|spec| Texture::resolve(spec).map(Cow::Owned))
+                .transpose(),
+        }
+    }
+
+    /// Blend `texture` into `image` where `mask` covers it, in proportion to
+    /// the mask; at opacity 0 nothing changes
+    pub fn apply(&self, texture: &Texture, image: &mut RgbImage, mask: &GrayImage) {
+        let opacity = self.opacity.clamp(0.0, 1.0);
+        if opacity == 0.0 {
+            return;
+        }
+        let scale = if self.scale.is_finite() && self.scale > 0.0 {
+            self.scale
+        } else {
+            1.0
+        };
+        for (x, y, pixel) in image.enumerate_pixels_mut() {
+            let weight = mask
+                .get_pixel_checked(x, y)
+                .map_or(0.0, |m| f32::from(m.0[0]) / 255.0)
+                * opacity;
+            if weight <= 0.0 {
+                continue;
+            }
+            let t = texture.sample(x, y, scale);
+            for channel in &mut pixel.0 {
+                let c = f32::from(*channel) / 255.0;
+                let blended = self.blend.blend(c, t, texture.mean);
+                *channel = ((c + (blended - c) * weight) * 255.0)
+                    .round()
+                    .clamp(0.0, 255.0) as u8;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{DynamicImage, Luma, Rgb};
+    use tempfile::tempdir;
+
+    fn garment(width: u32, height: u32) -> (RgbImage, GrayImage) {
+        let image = RgbImage::from_pixel(width, height, Rgb([150, 90, 60]));
+        let mask = GrayImage::from_pixel(width, height, Luma([255]));
+        (image, mask)
+    }
+
+    #[test]
+    fn test_tiles_repeat_seamlessly() -> Result<()> {
+        let texture = Texture::builtin("denim").expect("built-in");
+        let (tw, th) = texture.dimensions();
+        // A region larger than the texture, at scale 2, so tiles meet inside it
+        let (mut image, mask) = garment(tw * 5 + 3, th * 3 + 1);
+        for blend in [TextureBlend::Multiply, TextureBlend::Overlay] {
+            let overlay = TextureOverlay::new(TextureSource::Texture(texture.clone()))
+                .with_scale(2.0)
+                .with_opacity(1.0)
+                .with_blend(blend);
+            overlay.apply(&texture, &mut image, &mask);
+            let (period_x, period_y) = (tw * 2, th * 2);
+            for y in 0..image.height() - period_y {
+                for x in 0..image.width() - period_x {
+                    assert_eq!(
+                        image.get_pixel(x, y),
+                        image.get_pixel(x + period_x, y),
+                        "{blend} at ({x}, {y})"
+                    );
+                    assert_eq!(image.get_pixel(x, y), image.get_pixel(x, y + period_y));
+                }
+            }
+            // Across the boundary the texture continues as it starts
+            let texture_edge = texture.sample(period_x - 1, 0, 2.0);
+            assert_eq!(
+                texture_edge,
+                f32::from(texture.luma.get_pixel(tw - 1, 0).0[0]) / 255.0
+            );
+            assert_eq!(texture.sample(period_x, 0, 2.0), texture.sample(0, 0, 2.0));
+            image = garment(image.width(), image.height()).0;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_zero_opacity_and_empty_mask_change_nothing() {
+        let texture = Texture::builtin("leather").expect("built-in");
+        let (original, mask) = garment(100, 70);
+        let mut image = original.clone();
+        TextureOverlay::new(TextureSource::Material)
+            .with_opacity(0.0)
+            .apply(&texture, &mut image, &mask);
+        assert_eq!(image.as_raw(), original.as_raw());
+
+        TextureOverlay::new(TextureSource::Material).apply(
+            &texture,
+            &mut image,
+            &GrayImage::new(100, 70),
+        );
+        assert_eq!(image.as_raw(), original.as_raw());
+
+        // A half-strength mask edge gets about half the change
+        let mut full = original.clone();
+        let mut half = original.clone();
+        let overlay = TextureOverlay::new(TextureSource::Material).with_opacity(1.0);
+        overlay.apply(&texture, &mut full, &mask);
+        overlay.apply(
+            &texture,
+            &mut half,
+            &GrayImage::from_pixel(100, 70, Luma([128])),
+        );
+        let change = |image: &RgbImage| -> i64 {
+            image
+                .as_raw()
+                .iter()
+                .zip(original.as_raw())
+                .map(|(a, b)| (i64::from(*a) - i64::from(*b)).abs())
+                .sum()
+        };
+        let (full, half) = (change(&full), change(&half));
+        assert!(
+            full > 0 && (half * 2 - full).abs() < full / 10,
+            "{full} {half}"
+        );
+    }
+
+    #[test]
+    fn test_loads_grayscale_and_color_files() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let gray = temp_dir.path().join("gray.png");
+        GrayImage::from_fn(4, 4, |x, _| Luma([if x % 2 == 0 { 64 } else { 192 }])).save(&gray)?;
+        let texture = Texture::resolve(gray.to_str().unwrap())?;
+        assert_eq!(texture.dimensions(), (4, 4));
+        assert!((texture.mean - 0.5).abs() < 0.01);
+
+        let color = temp_dir.path().join("color.png");
+        DynamicImage::ImageRgb8(RgbImage::from_pixel(3, 2, Rgb([200, 10, 10]))).save(&color)?;
+        assert_eq!(Texture::load(&color)?.dimensions(), (3, 2));
+
+        // A flat texture has nothing to add
+        let flat = Texture::load(&color)?;
+        let (original, mask) = garment(8, 8);
+        let mut image = original.clone();
+        TextureOverlay::new(TextureSource::Material)
+            .with_opacity(1.0)
+            .apply(&flat, &mut image, &mask);
+        assert_eq!(image, original);
+
+        for name in BUILTIN_TEXTURES {
+            assert!(Texture::resolve(name).is_ok(), "{name}");
+        }
+        let err = Texture::resolve("tweed").unwrap_err();
+        assert!(err.to_string().contains("denim, knit, leather"), "{err}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_material_source_follows_the_plan() -> Result<()> {
+        let overlay = TextureOverlay::new(TextureSource::Material);
+        assert!(overlay.texture(&TransformPlan::default())?.is_none());
+        let plan = TransformPlan {
+            texture: Some("knit".to_string()),
+            ..Default::default()
+        };
+        let texture = overlay.texture(&plan)?.expect("the plan's texture");
+        assert_eq!(*texture, Texture::builtin("knit").unwrap());
+        Ok(())
+    }
+}
@@ -1,516 +1,4252 @@
-use std::path::{Path, PathBuf};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, Cursor, Read, Seek, Write},
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex, RwLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
 
-use anyhow::{Context, Result};
-use image::{DynamicImage, ImageBuffer, Rgb, RgbImage};
-use log::{debug, info};
+use anyhow::{Context, Result, bail};
+use chrono::Utc;
+use image::{DynamicImage, GrayImage, ImageFormat, Rgb, RgbImage, io::Reader as ImageReader};
+use log::{debug, info, warn};
 use palette::{FromColor, Hsl, Srgb};
 use serde::{Deserialize, Serialize};
 
-use crate::ModelManager;
+use crate::{
+    DownloadOptions, ModelInfo, ModelManager, analysis,
+    availability::Availability,
+    cancel::{CancelToken, Watchdog},
+    color_transfer::ColorReference,
+    compat::{Compatibility, Task},
+    compose::{self, ComparisonLayout},
+    error::SiError,
+    format::{format_duration, format_size},
+    formats,
+    history::{History, HistoryEntry},
+    image_source::RemoteInput,
+    imageio::{self, LoadWarning, LoadedImage},
+    import::{self, HashCache},
+    logging::{MASK_TARGET, TIMING_TARGET},
+    mask::{self, MaskMode, RegionMask, SkinProtection},
+    mask_cache::{self, MaskCache, MaskKey},
+    metadata::{MODEL_HASH_KEY, ReproInfo},
+    metrics::{MetricEvent, MetricsSink},
+    model_id::same_id,
+    planner::{ColorTransform, KeywordPlanner, StyleAdjustments, TransformPlan, TransformPlanner},
+    preprocess::{self, Crop, CropSpec},
+    prompt::ParsedPrompt,
+    segment::{
+        CoverageBounds, CoverageWarning, HeuristicSegmenter, MaskStats, Region, Segmenter,
+        mask_coverage,
+    },
+    styles::StyleRegistry,
+    template::{OutputTemplate, TemplateContext},
+    texture::TextureOverlay,
+    variant::DEFAULT_VARIANT,
+};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TryOnRequest {
     pub input_image_path: PathBuf,
     pub clothing_description: String,
+    /// Terms the result should avoid, on top of any `[neg: ...]` block in the
+    /// description
+    #[serde(default)]
+    pub negative_prompt: Option<String>,
     pub output_path: PathBuf,
     pub model_name: Option<String>,
     pub strength: Option<f64>, // 0.0-1.0, how much to change the image
+    /// How far the color moves toward the described one, `strength` unless set
+    #[serde(default)]
+    pub color_strength: Option<f64>,
+    /// How much of the fabric style's contrast and brightness is applied,
+    /// `strength` unless set
+    #[serde(default)]
+    pub style_strength: Option<f64>,
+    /// Photo of the garment whose color replaces the one derived from the description
+    #[serde(default)]
+    pub reference_image: Option<PathBuf>,
+    /// Crop the input before processing
+    #[serde(default)]
+    pub crop: Option<Crop>,
+    /// Polygon limiting the region that is re-generated
+    #[serde(default)]
+    pub mask: Option<RegionMask>,
+    /// The transformation to apply, instead of planning one from the description
+    #[serde(default)]
+    pub transform_plan: Option<TransformPlan>,
+    /// Also write the input and the result side by side
+    #[serde(default)]
+    pub comparison: Option<ComparisonRequest>,
+    /// The URL `input_image_path` was downloaded from, when it was
+    #[serde(default)]
+    pub remote_input: Option<RemoteInput>,
+    /// Seed of a strength ramp, or of the first of a set of variations; random
+    /// when unset
+    #[serde(default)]
+    pub seed: Option<u64>,
+}
+
+/// Where and how to write the input and the result of a try-on side by side
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ComparisonRequest {
+    pub path: PathBuf,
+    #[serde(default)]
+    pub layout: ComparisonLayout,
+    /// Label the halves "before" and "after"
+    #[serde(default)]
+    pub labels: bool,
+}
+
+impl ComparisonRequest {
+    /// A horizontal, unlabeled comparison written to `path`
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            layout: ComparisonLayout::default(),
+            labels: false,
+        }
+    }
+
+    pub fn with_layout(mut self, layout: ComparisonLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    pub fn with_labels(mut self, labels: bool) -> Self {
+        self.labels = labels;
+        self
+    }
+
+    /// `before` and `after` composed as requested
+    pub fn compose(&self, before: &DynamicImage, after: &DynamicImage) -> DynamicImage {
+        let labels = self.labels.then_some(["before", "after"]);
+        compose::side_by_side(before, after, self.layout, labels)
+    }
+}
+
+impl TryOnRequest {
+    /// The description parsed into weighted terms, including the negative prompt
+    pub fn parsed_prompt(&self) -> Result<ParsedPrompt> {
+        let parsed = ParsedPrompt::parse(&self.clothing_description)?;
+        match &self.negative_prompt {
+            Some(negative) => parsed.with_negative(negative),
+            None => Ok(parsed),
+        }
+    }
+
+    /// The model to generate with, [`DEFAULT_MODEL`] unless one is named
+    pub fn model(&self) -> &str {
+        self.model_name.as_deref().unwrap_or(DEFAULT_MODEL)
+    }
+
+    /// The strength to apply, [`DEFAULT_STRENGTH`] unless one is set
+    pub fn effective_strength(&self) -> f64 {
+        self.strength.unwrap_or(DEFAULT_STRENGTH)
+    }
+
+    /// The strength of the color change, falling back to
+    /// [`effective_strength`](Self::effective_strength)
+    pub fn effective_color_strength(&self) -> f64 {
+        self.color_strength
+            .unwrap_or_else(|| self.effective_strength())
+    }
+
+    /// The strength of the style adjustments, falling back to
+    /// [`effective_strength`](Self::effective_strength)
+    pub fn effective_style_strength(&self) -> f64 {
+        self.style_strength
+            .unwrap_or_else(|| self.effective_strength())
+    }
+
+    /// Fail unless each strength that is set is within 0.0 to 1.0
+    pub fn check_strengths(&self) -> Result<()> {
+        for (name, strength) in [
+            ("strength", self.strength),
+            ("color_strength", self.color_strength),
+            ("style_strength", self.style_strength),
+        ] {
+            if let Some(strength) = strength {
+                check_strength(name, strength)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The drawn mask for a `size` image made from an `input_size` input cropped
+    /// to `crop`, unless the mask mode leaves it to detection
+    pub fn drawn_mask(
+        &self,
+        input_size: (u32, u32),
+        crop: Option<Region>,
+        size: (u32, u32),
+    ) -> Option<(GrayImage, MaskMode)> {
+        drawn_mask(self.mask.as_ref(), input_size, crop, size)
+    }
+
+    /// The request's own mask, or else the one `plan` suggests
+    pub(crate) fn region_mask<'a>(&'a self, plan: &'a TransformPlan) -> Option<&'a RegionMask> {
+        self.mask.as_ref().or(plan.mask_hint.as_ref())
+    }
+}
+
+/// `mask` rasterized as [`TryOnRequest::drawn_mask`] describes
+pub(crate) fn drawn_mask(
+    mask: Option<&RegionMask>,
+    input_size: (u32, u32),
+    crop: Option<Region>,
+    size: (u32, u32),
+) -> Option<(GrayImage, MaskMode)> {
+    mask.filter(|m| m.mode != MaskMode::Detect)
+        .map(|m| (m.rasterize(input_size, crop, size), m.mode))
+}
+
+/// Where the clothing mask of one image comes from
+#[derive(Debug, Default)]
+pub(crate) struct MaskSource {
+    /// A drawn mask to combine with or use instead of the detected one
+    pub drawn: Option<(GrayImage, MaskMode)>,
+    /// The key to look the mask up and store it under, when masks are cached
+    pub cache_key: Option<MaskKey>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TryOnResult {
     pub output_path: PathBuf,
-    pub processing_time_ms: u64,
+    /// Serialized as whole milliseconds under its original name
+    #[serde(rename = "processing_time_ms", with = "crate::format::millis")]
+    pub processing_time: Duration,
     pub model_used: String,
+    /// SHA-256 of the model's file names and contents, when it was used from a
+    /// local path rather than the index
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model_hash: Option<String>,
+    /// Region of the input that was processed, when it was cropped
+    #[serde(default)]
+    pub crop: Option<Region>,
+    /// Fraction of the processed image the clothing mask covered
+    #[serde(default)]
+    pub mask_coverage: f32,
+    /// Color strength that was applied
+    #[serde(default)]
+    pub color_strength: f64,
+    /// Style strength that was applied
+    #[serde(default)]
+    pub style_strength: f64,
+    /// The strength [`analysis::suggest_strength`] picked, when the request
+    /// left it to [`VirtualTryOn::with_auto_strength`]; the applied strengths
+    /// above follow it unless set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub suggested_strength: Option<f64>,
+    /// The seed of a pass of a ramp or a variation
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    /// The history group shared by the passes of one ramp or the variations of
+    /// one request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    /// What lenient loading worked around to read the input
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub load_warnings: Vec<LoadWarning>,
+    /// The input had an ICC profile the output format couldn't carry
+    #[serde(default)]
+    pub icc_dropped: bool,
+    /// Where the side-by-side comparison was written, when one was requested
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comparison_path: Option<PathBuf>,
+    /// The transformation that was applied, as planned from the description or
+    /// given in the request
+    #[serde(default)]
+    pub transform_plan: TransformPlan,
+}
+
+/// What [`VirtualTryOn::prepare_passes`] loaded and computed for passes over
+/// one input
+struct PreparedPasses {
+    model_name: String,
+    load_warnings: Vec<LoadWarning>,
+    icc_profile: Option<Vec<u8>>,
+    rgb_image: RgbImage,
+    crop: Option<Region>,
+    plan: TransformPlan,
+    clothing_mask: GrayImage,
+    mask_coverage: f32,
+    reference: Option<ColorReference>,
+}
+
+/// What [`VirtualTryOn::render`] made of a request
+struct Rendered {
+    image: DynamicImage,
+    /// Region of the input the image covers, when it was cropped
+    crop: Option<Region>,
+    /// Share of the image the clothing mask covered
+    mask_coverage: f32,
+    plan: TransformPlan,
+    strengths: Strengths,
+}
+
+/// The strengths a render applied
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Strengths {
+    pub strength: f64,
+    pub color: f64,
+    pub style: f64,
+    /// The suggested strength `strength` came from, if it was suggested
+    pub suggested: Option<f64>,
+}
+
+impl Strengths {
+    /// The strengths of `request`, with `suggested` standing in for its
+    /// strength when there is one
+    pub fn of(request: &TryOnRequest, suggested: Option<f64>) -> Self {
+        let strength = suggested.unwrap_or_else(|| request.effective_strength());
+        Self {
+            strength,
+            color: request.color_strength.unwrap_or(strength),
+            style: request.style_strength.unwrap_or(strength),
+            suggested,
+        }
+    }
+
+    /// `info` with the strength that was applied, noting a suggested one
+    fn describe(&self, mut info: ReproInfo) -> ReproInfo {
+        info.strength = Some(self.strength);
+        if self.suggested.is_some() {
+            info.extra
+                .push(("Strength".to_string(), "auto".to_string()));
+        }
+        info
+    }
+}
+
+/// Several prompts applied to the same input, assembled into one grid image
+#[derive(Debug, Clone)]
+pub struct GridRequest {
+    pub input_image_path: PathBuf,
+    pub prompts: Vec<String>,
+    pub grid_path: PathBuf,
+    pub model_name: Option<String>,
+    pub strength: Option<f64>,
+    /// Number of grid columns, defaulting to a roughly square layout
+    pub columns: Option<usize>,
+    /// Draw each prompt under its cell
+    pub labels: bool,
+    /// Also save each prompt's result in this directory
+    pub individual_dir: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GridResult {
+    pub results: Vec<TryOnResult>,
+    pub grid_path: PathBuf,
+}
+
+/// A step of a try-on, reported through [`VirtualTryOn::with_events`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TryOnStage {
+    LoadingModel,
+    Decoding,
+    Segmenting,
+    Transforming,
+    Encoding,
+}
+
+impl fmt::Display for TryOnStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::LoadingModel => "loading model",
+            Self::Decoding => "decoding input",
+            Self::Segmenting => "detecting clothing",
+            Self::Transforming => "transforming pixels",
+            Self::Encoding => "encoding output",
+        })
+    }
+}
+
+/// Progress of a try-on
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum TryOnEvent {
+    /// A new step started
+    Stage { stage: TryOnStage },
+    /// Rows of the image the pixel transform has finished
+    Rows { done: u32, total: u32 },
+    /// The clothing mask covers less or more of the image than the
+    /// [`CoverageBounds`] expect
+    MaskWarning {
+        warning: CoverageWarning,
+        bounding_box: Option<Region>,
+    },
+    /// The model isn't known to generate images, or is known not to but was
+    /// allowed anyway
+    CompatibilityWarning {
+        model: String,
+        compatibility: Compatibility,
+    },
+}
+
+/// Receives the [`TryOnEvent`]s of every try-on, on the thread doing the work
+pub type EventHandler = Arc<dyn Fn(&TryOnEvent) + Send + Sync>;
+
+/// Model used when a request doesn't name one
+pub const DEFAULT_MODEL: &str = "runwayml/stable-diffusion-v1-5";
+
+/// Strength used when a request doesn't set one
+pub const DEFAULT_STRENGTH: f64 = 0.5;
+
+/// How many pixel rows are processed between cancellation checks
+const CANCEL_CHECK_ROWS: u32 = 64;
+
+/// File names for [`VirtualTryOn::try_on_ramp`] passes, relative to the requested output
+const RAMP_OUTPUT_TEMPLATE: &str = "{stem}_s{strength}.{ext}";
+
+/// File names for [`VirtualTryOn::try_on_variations`] outputs, relative to the
+/// requested output
+const VARIATION_OUTPUT_TEMPLATE: &str = "{stem}-{variation}.{ext}";
+
+/// Most variations [`variation_requests`] makes of one request
+pub const MAX_VARIATIONS: usize = 16;
+
+/// How far apart the strengths of a request's variations are
+pub const VARIATION_STRENGTH_STEP: f64 = 0.04;
+
+/// Bounds on the input images the pipeline accepts
+///
+/// Checked from the file header before decoding, so a huge image is rejected
+/// without reading its pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputLimits {
+    pub min_width: u32,
+    pub min_height: u32,
+    pub max_pixels: u64,
+    /// Upper bound for the estimated memory needed to decode and process an image
+    pub max_decoded_bytes: u64,
+}
+
+impl Default for InputLimits {
+    fn default() -> Self {
+        Self {
+            min_width: 64,
+            min_height: 64,
+            max_pixels: 40_000_000,
+            max_decoded_bytes: 1 << 30,
+        }
+    }
+}
+
+impl InputLimits {
+    /// Rough memory needed for an image: the decoded buffer in its native pixel
+    /// type plus the RGB working copy, result and mask the pipeline allocates
+    fn estimate_bytes(format: ImageFormat, width: u32, height: u32) -> u64 {
+        let decoded = match format {
+            ImageFormat::Hdr | ImageFormat::OpenExr => 16,
+            // May hold 16 bits per channel
+            ImageFormat::Png | ImageFormat::Tiff | ImageFormat::Pnm => 8,
+            _ => 4,
+        };
+        let working = 3 + 3 + 1;
+        u64::from(width) * u64::from(height) * (decoded + working)
+    }
+
+    fn check(&self, format: ImageFormat, width: u32, height: u32) -> Result<(), SiError> {
+        if width < self.min_width || height < self.min_height {
+            return Err(SiError::ImageTooSmall {
+                width,
+                height,
+                min_width: self.min_width,
+                min_height: self.min_height,
+            });
+        }
+        if u64::from(width) * u64::from(height) > self.max_pixels {
+            return Err(SiError::TooManyPixels {
+                width,
+                height,
+                max_pixels: self.max_pixels,
+            });
+        }
+        let estimated_bytes = Self::estimate_bytes(format, width, height);
+        if estimated_bytes > self.max_decoded_bytes {
+            return Err(SiError::ImageTooLarge {
+                width,
+                height,
+                estimated_bytes,
+                max_bytes: self.max_decoded_bytes,
+            });
+        }
+        Ok(())
+    }
 }
 
+/// A model [`VirtualTryOn`] loaded from a local path
 #[derive(Debug, Clone)]
-struct ColorTransform {
-    hue_shift: f32,
-    saturation_mult: f32,
-    lightness_mult: f32,
+struct LocalLoad {
+    /// The model's folder or checkpoint, resolved
+    path: PathBuf,
+    /// [`LocalModel::content_hash`](import::LocalModel::content_hash) of its files
+    hash: String,
+}
+
+pub struct VirtualTryOn {
+    model_manager: ModelManager,
+    /// The loaded model, shared by concurrent try-ons
+    current_model: RwLock<Option<String>>,
+    /// Held while a model loads, so concurrent requests download it only once
+    loading: tokio::sync::Mutex<()>,
+    history: Option<History>,
+    metrics: Arc<dyn MetricsSink>,
+    cancel: CancelToken,
+    segmenter: Box<dyn Segmenter>,
+    thumbnails: bool,
+    limits: InputLimits,
+    events: Option<EventHandler>,
+    output_quality: Option<u8>,
+    overwrite: bool,
+    coverage_bounds: CoverageBounds,
+    strict_mask: bool,
+    mask_cache: Option<MaskCache>,
+    hash_cache: Option<HashCache>,
+    planner: Box<dyn TransformPlanner>,
+    lenient: bool,
+    strip_icc: bool,
+    variant: Option<String>,
+    skin_protection: Option<SkinProtection>,
+    texture: Option<TextureOverlay>,
+    mask_debug: Option<PathBuf>,
+    timeout: Option<Duration>,
+    allow_incompatible: bool,
+    download_missing: bool,
+    /// Estimated download sizes of models found missing, so a batch asks the
+    /// source once per model
+    missing_sizes: Mutex<HashMap<String, Option<u64>>>,
+    /// The models loaded from local paths, by path as given
+    local_models: Mutex<HashMap<String, LocalLoad>>,
+    /// Output directories already found writable, so a batch probes each once
+    writable_dirs: Mutex<HashSet<PathBuf>>,
+    embed_metadata: bool,
+    auto_strength: bool,
 }
 
-impl ColorTransform {
-    fn new(hue_shift: f32, saturation_mult: f32, lightness_mult: f32) -> Self {
-        Self {
-            hue_shift,
-            saturation_mult,
-            lightness_mult,
-        }
+impl VirtualTryOn {
+    pub fn new(model_manager: ModelManager) -> Result<Self> {
+        info!("Initialized Virtual Try-On (MVP mode with image processing)");
+
+        Ok(Self {
+            metrics: model_manager.metrics(),
+            model_manager,
+            current_model: RwLock::new(None),
+            loading: tokio::sync::Mutex::new(()),
+            history: None,
+            cancel: CancelToken::new(),
+            segmenter: Box::new(HeuristicSegmenter),
+            thumbnails: true,
+            limits: InputLimits::default(),
+            events: None,
+            output_quality: None,
+            overwrite: true,
+            coverage_bounds: CoverageBounds::default(),
+            strict_mask: false,
+            mask_cache: None,
+            hash_cache: None,
+            planner: Box::new(KeywordPlanner::default()),
+            lenient: false,
+            strip_icc: false,
+            variant: None,
+            skin_protection: Some(SkinProtection::default()),
+            texture: None,
+            mask_debug: None,
+            timeout: None,
+            allow_incompatible: false,
+            download_missing: false,
+            missing_sizes: Mutex::default(),
+            local_models: Mutex::default(),
+            writable_dirs: Mutex::default(),
+            embed_metadata: true,
+            auto_strength: false,
+        })
+    }
+
+    /// Compute clothing masks with `segmenter` instead of the built-in heuristic
+    pub fn with_segmenter(mut self, segmenter: Box<dyn Segmenter>) -> Self {
+        self.segmenter = segmenter;
+        self
+    }
+
+    /// Take fabric adjustments from `styles` instead of the built-in presets,
+    /// replacing the planner with a [`KeywordPlanner`] over them
+    pub fn with_styles(mut self, styles: StyleRegistry) -> Self {
+        self.planner = Box::new(KeywordPlanner::new(styles));
+        self
+    }
+
+    /// Turn descriptions into transformations with `planner` instead of the
+    /// [`KeywordPlanner`]
+    pub fn with_planner(mut self, planner: Box<dyn TransformPlanner>) -> Self {
+        self.planner = planner;
+        self
+    }
+
+    /// Record every try-on, successful or not, in `history`
+    pub fn with_history(mut self, history: History) -> Self {
+        self.history = Some(history);
+        self
+    }
+
+    /// Report every try-on to `metrics` instead of the model manager's sink
+    pub fn with_metrics(mut self, metrics: Arc<dyn MetricsSink>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Whether a model's first output is stored as its preview thumbnail (default: on)
+    pub fn with_thumbnails(mut self, enabled: bool) -> Self {
+        self.thumbnails = enabled;
+        self
+    }
+
+    /// Reject input images outside `limits` instead of the defaults
+    pub fn with_input_limits(mut self, limits: InputLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Encode JPEG, WebP and AVIF outputs at `quality` (1-100) instead of each
+    /// format's default; lossless formats ignore it
+    pub fn with_output_quality(mut self, quality: u8) -> Self {
+        self.output_quality = Some(quality);
+        self
+    }
+
+    /// Whether an existing output file may be replaced (default: true); when not,
+    /// the try-on fails before any processing
+    pub fn with_overwrite(mut self, overwrite: bool) -> Self {
+        self.overwrite = overwrite;
+        self
+    }
+
+    /// Warn when the clothing mask covers a share of the image outside `bounds`
+    /// instead of the defaults
+    pub fn with_coverage_bounds(mut self, bounds: CoverageBounds) -> Self {
+        self.coverage_bounds = bounds;
+        self
+    }
+
+    /// Fail a try-on whose clothing mask is outside the coverage bounds instead
+    /// of warning (default: off), so batch jobs stop before producing garbage
+    pub fn with_strict_mask(mut self, strict: bool) -> Self {
+        self.strict_mask = strict;
+        self
+    }
+
+    /// Recover what can be decoded of truncated or corrupt inputs instead of
+    /// failing (default: off); see [`imageio::load_lenient`]
+    pub fn with_lenient(mut self, lenient: bool) -> Self {
+        self.lenient = lenient;
+        self
+    }
+
+    /// Whether outputs carry the prompt, model, strength and seed that made
+    /// them (default: on), for the formats that can; see [`crate::metadata`]
+    pub fn with_embedded_metadata(mut self, enabled: bool) -> Self {
+        self.embed_metadata = enabled;
+        self
+    }
+
+    /// Pick the strength of requests that don't set one from the garment, with
+    /// [`analysis::suggest_strength`], instead of using [`DEFAULT_STRENGTH`]
+    /// (default: off); grids and strength ramps are unaffected
+    pub fn with_auto_strength(mut self, enabled: bool) -> Self {
+        self.auto_strength = enabled;
+        self
+    }
+
+    /// Write outputs without the input's ICC color profile (default: off, the
+    /// profile is kept for PNG and JPEG outputs)
+    pub fn with_strip_icc(mut self, strip: bool) -> Self {
+        self.strip_icc = strip;
+        self
+    }
+
+    /// Load models in weight `variant`, such as `fp16`, downloading it if it
+    /// isn't indexed; without one, the indexed variant best suited to
+    /// [`preferred_variant`](Self::preferred_variant) is used
+    pub fn with_variant(mut self, variant: Option<String>) -> Self {
+        self.variant = variant;
+        self
+    }
+
+    /// Keep skin out of the transformed region as `protection` says, or not at
+    /// all with `None` (default: on, at the default sensitivity)
+    pub fn with_skin_protection(mut self, protection: Option<SkinProtection>) -> Self {
+        self.skin_protection = protection;
+        self
+    }
+
+    /// Lay a fabric texture over the transformed garment, or none with `None`
+    /// (default: none)
+    pub fn with_texture(mut self, texture: Option<TextureOverlay>) -> Self {
+        self.texture = texture;
+        self
+    }
+
+    /// Write an overlay of the transformed region (red) and the skin protection
+    /// kept out of it (blue) to `path` whenever a mask is computed
+    pub fn with_mask_debug(mut self, path: impl Into<PathBuf>) -> Self {
+        self.mask_debug = Some(path.into());
+        self
+    }
+
+    /// Look clothing masks up in `cache` before segmenting and store new ones
+    /// there; segmenters without an [`id`](Segmenter::id) are never cached
+    pub fn with_mask_cache(mut self, cache: MaskCache) -> Self {
+        self.mask_cache = Some(cache);
+        self
+    }
+
+    /// Keep the hashes of local models' files in `cache`, so a model used
+    /// again is only read when its files changed
+    pub fn with_hash_cache(mut self, cache: HashCache) -> Self {
+        self.hash_cache = Some(cache);
+        self
+    }
+
+    /// Stop processing early when `cancel` is tripped; no output is written for a
+    /// cancelled try-on
+    pub fn with_cancel_token(mut self, cancel: CancelToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// The token set by [`with_cancel_token`](Self::with_cancel_token)
+    pub fn cancel_token(&self) -> &CancelToken {
+        &self.cancel
+    }
+
+    /// Stop a try-on whose processing takes longer than `timeout` and fail it
+    /// with [`SiError::Timeout`], leaving no output behind
+    ///
+    /// Loading the model isn't counted. A stage that never checks for
+    /// cancellation, like a custom [`Segmenter`], runs to its end first.
+    pub fn with_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Generate with models [`ModelManager::check_compatibility`] finds can't
+    /// generate images instead of refusing them with
+    /// [`SiError::IncompatibleModel`] (default: off)
+    pub fn with_allow_incompatible(mut self, allow: bool) -> Self {
+        self.allow_incompatible = allow;
+        self
+    }
+
+    /// Download a model that isn't downloaded when a request needs it instead
+    /// of failing with [`SiError::ModelNotDownloaded`] (default: off)
+    pub fn with_download_missing(mut self, download: bool) -> Self {
+        self.download_missing = download;
+        self
+    }
+
+    /// Report the stages and pixel progress of every try-on to `events`
+    pub fn with_events(mut self, events: EventHandler) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    fn emit(&self, event: TryOnEvent) {
+        if let Some(events) = &self.events {
+            events(&event);
+        }
+    }
+
+    fn emit_stage(&self, stage: TryOnStage) {
+        self.emit(TryOnEvent::Stage { stage });
+    }
+
+    /// Load a model (for MVP, this just tracks which model the user wants to use)
+    pub async fn load_model(&self, model_name: &str) -> Result<()> {
+        info!("Loading model: {} (MVP mode)", model_name);
+
+        // Check if model is already loaded
+        if self.is_loaded(model_name) {
+            debug!("Model {} already loaded", model_name);
+            return Ok(());
+        }
+        let _loading = self.loading.lock().await;
+        if self.is_loaded(model_name) {
+            debug!("Model {} was loaded by a concurrent request", model_name);
+            return Ok(());
+        }
+        if import::is_local_model(model_name) {
+            return self.load_local_model(model_name).await;
+        }
+
+        // An entry whose files are gone needs downloading as much as no entry
+        let variant = self.variant.as_deref();
+        let model = match self
+            .model_manager
+            .resolve_model_async(model_name, variant, self.preferred_variant())
+            .await?
+            .filter(|model| self.has_files(model))
+        {
+            Some(model) => {
+                debug!("Using {}", model.display_id());
+                model
+            }
+            None if self.download_missing => {
+                info!("Model {} not found locally, downloading...", model_name);
+                self.model_manager
+                    .download_model_with_options(model_name, &self.download_options())
+                    .await?
+            }
+            None => return Err(self.not_downloaded(model_name).await.into()),
+        };
+        if model.is_pickle_only() {
+            return Err(SiError::PickleOnly {
+                model_id: model_name.to_string(),
+            }
+            .into());
+        }
+        self.check_compatibility(
+            model_name,
+            self.model_manager
+                .check_compatibility(model_name, Task::ImageToImage),
+        )?;
+
+        info!("Model {} ready (MVP mode)", model_name);
+        *self.current_model.write().expect("current model lock") = Some(model_name.to_string());
+
+        Ok(())
+    }
+
+    /// Load the model at the path `model_name` without indexing it or asking
+    /// the Hub, hashing its files on the blocking thread pool for the outputs'
+    /// provenance
+    async fn load_local_model(&self, model_name: &str) -> Result<()> {
+        let local = self.model_manager.open_local(model_name)?;
+        if local.info.is_pickle_only() {
+            return Err(SiError::PickleOnly {
+                model_id: model_name.to_string(),
+            }
+            .into());
+        }
+        self.check_compatibility(model_name, local.compatibility(Task::ImageToImage))?;
+        let cache = self.hash_cache.clone();
+        let (path, hash) = tokio::task::spawn_blocking(move || {
+            let hash = local.content_hash(cache.as_ref())?;
+            anyhow::Ok((local.path, hash))
+        })
+        .await
+        .context("Model hashing task panicked")??;
+        debug!("{} has content hash {hash}", path.display());
+        self.local_models
+            .lock()
+            .expect("local models lock")
+            .insert(model_name.to_string(), LocalLoad { path, hash });
+
+        info!("Model {} ready (MVP mode)", model_name);
+        *self.current_model.write().expect("current model lock") = Some(model_name.to_string());
+        Ok(())
+    }
+
+    /// The content hash of `model_name` when it was loaded from a local path
+    fn model_hash(&self, model_name: &str) -> Option<String> {
+        self.local_models
+            .lock()
+            .expect("local models lock")
+            .get(model_name)
+            .map(|local| local.hash.clone())
+    }
+
+    /// How results record `model_name`: the resolved absolute path of a model
+    /// loaded from a local path, so it still means something elsewhere, and the
+    /// model id otherwise
+    fn model_used(&self, model_name: &str) -> String {
+        self.local_models
+            .lock()
+            .expect("local models lock")
+            .get(model_name)
+            .map_or_else(
+                || model_name.to_string(),
+                |local| local.path.display().to_string(),
+            )
+    }
+
+    /// Refuse a model that can't generate images unless that is allowed, and
+    /// warn about one that may not
+    fn check_compatibility(
+        &self,
+        model_name: &str,
+        compatibility: Result<Compatibility>,
+    ) -> Result<()> {
+        let compatibility = match compatibility {
+            Ok(compatibility) => compatibility,
+            Err(e) => {
+                warn!("Couldn't check whether {model_name} can generate images: {e:#}");
+                Compatibility::Unknown
+            }
+        };
+        match &compatibility {
+            Compatibility::Compatible => return Ok(()),
+            Compatibility::Incompatible(reason) if !self.allow_incompatible => {
+                return Err(SiError::IncompatibleModel {
+                    model_id: model_name.to_string(),
+                    reason: reason.clone(),
+                }
+                .into());
+            }
+            _ => warn!("Generating with {model_name}, which is {compatibility}"),
+        }
+        self.emit(TryOnEvent::CompatibilityWarning {
+            model: model_name.to_string(),
+            compatibility,
+        });
+        Ok(())
+    }
+
+    fn is_loaded(&self, model_name: &str) -> bool {
+        self.current_model
+            .read()
+            .expect("current model lock")
+            .as_deref()
+            == Some(model_name)
+    }
+
+    /// Whether `model_name` is in the index with all its files, or is a local
+    /// model path, so loading it needs no download
+    pub(crate) fn is_downloaded(&self, model_name: &str) -> Result<bool> {
+        if import::is_local_model(model_name) {
+            return Ok(self.model_manager.open_local(model_name).is_ok());
+        }
+        let models = self.model_manager.list_models()?;
+        Ok(models
+            .iter()
+            .any(|m| same_id(&m.model_id, model_name) && self.has_files(m)))
+    }
+
+    fn has_files(&self, model: &ModelInfo) -> bool {
+        self.model_manager.model_availability(model) == Availability::Available
+    }
+
+    fn download_options(&self) -> DownloadOptions {
+        DownloadOptions {
+            variant: self.variant.clone(),
+            ..DownloadOptions::default()
+        }
+    }
+
+    /// [`SiError::ModelNotDownloaded`] for `model_name`, with the size its
+    /// download is planned at when the source can be reached
+    async fn not_downloaded(&self, model_name: &str) -> SiError {
+        let known = self
+            .missing_sizes
+            .lock()
+            .expect("missing sizes lock")
+            .get(model_name)
+            .copied();
+        let estimated_size = match known {
+            Some(size) => size,
+            None => {
+                let size = match self
+                    .model_manager
+                    .plan_download(model_name, &self.download_options())
+                    .await
+                {
+                    Ok(plan) => Some(plan.transfer_size()).filter(|&size| size > 0),
+                    Err(e) => {
+                        debug!("Couldn't size the download of {model_name}: {e:#}");
+                        None
+                    }
+                };
+                self.missing_sizes
+                    .lock()
+                    .expect("missing sizes lock")
+                    .insert(model_name.to_string(), size);
+                size
+            }
+        };
+        SiError::ModelNotDownloaded {
+            model_id: model_name.to_string(),
+            estimated_size,
+        }
+    }
+
+    /// Perform virtual clothing try-on using image processing techniques
+    pub async fn try_on(&self, request: TryOnRequest) -> Result<TryOnResult> {
+        let start_time = std::time::Instant::now();
+        let result = self.run_try_on(&request).await;
+        self.record_history(&request, &result, start_time.elapsed().as_millis() as u64);
+        result
+    }
+
+    /// Like [`try_on`](Self::try_on), but reads the input image from `input` and
+    /// writes the result to `output` encoded as `format`
+    ///
+    /// The request's paths only label the images in logs and history, so `-` can
+    /// stand for stdin and stdout. The input is buffered in memory and rejected once
+    /// it outgrows the decoded size the [`InputLimits`] allow.
+    pub async fn try_on_stream(
+        &self,
+        request: TryOnRequest,
+        input: impl Read,
+        output: impl Write,
+        format: ImageFormat,
+    ) -> Result<TryOnResult> {
+        let start_time = Instant::now();
+        let result = self
+            .run_try_on_stream(&request, input, output, format)
+            .await;
+        self.record_history(&request, &result, start_time.elapsed().as_millis() as u64);
+        result
+    }
+
+    async fn run_try_on_stream(
+        &self,
+        request: &TryOnRequest,
+        input: impl Read,
+        mut output: impl Write,
+        format: ImageFormat,
+    ) -> Result<TryOnResult> {
+        let start_time = Instant::now();
+        // Read the input before loading the model so bad input fails fast
+        let bytes = self.read_input(input)?;
+        if let Some(comparison) = &request.comparison {
+            self.validate_output(&comparison.path)?;
+        }
+        self.prepare(request).await?;
+
+        let model_name = request.model();
+        self.emit_stage(TryOnStage::Decoding);
+        let input_image = self.decode_image(&bytes)?;
+        let before = request.comparison.as_ref().map(|_| input_image.clone());
+        let rendered = self.within_timeout(&self.cancel, |cancel| {
+            let rendered = self.render(input_image, request, cancel)?;
+            cancel.check()?;
+            Ok(rendered)
+        })?;
+        let result_image = rendered.image;
+
+        self.emit_stage(TryOnStage::Encoding);
+        let mut encoded = Cursor::new(Vec::new());
+        formats::write_image(
+            &result_image,
+            &mut encoded,
+            format,
+            self.quality_for(format),
+        )
+        .context("Failed to encode the result")?;
+        output
+            .write_all(encoded.get_ref())
+            .and_then(|()| output.flush())
+            .with_context(|| format!("Failed to write {}", request.output_path.display()))?;
+        let comparison_path =
+            self.write_comparison(request, before.as_ref(), &result_image, None, &self.cancel)?;
+        self.update_thumbnail(model_name, &result_image);
+
+        Ok(TryOnResult {
+            output_path: request.output_path.clone(),
+            processing_time: start_time.elapsed(),
+            model_used: self.model_used(model_name),
+            model_hash: self.model_hash(model_name),
+            crop: rendered.crop,
+            mask_coverage: rendered.mask_coverage,
+            color_strength: rendered.strengths.color,
+            style_strength: rendered.strengths.style,
+            suggested_strength: rendered.strengths.suggested,
+            seed: None,
+            group: None,
+            load_warnings: Vec::new(),
+            icc_dropped: false,
+            comparison_path,
+            transform_plan: rendered.plan,
+        })
+    }
+
+    /// Run the same request at several strengths, computing the mask only once
+    ///
+    /// Each pass is written next to `request.output_path` with its strength added to
+    /// the file name (`out.png` becomes `out_s0.40.png`). All passes are recorded
+    /// with one shared seed and history group so they can be compared later.
+    pub async fn try_on_ramp(
+        &self,
+        request: &TryOnRequest,
+        passes: &[f64],
+    ) -> Result<Vec<TryOnResult>> {
+        if passes.is_empty() {
+            bail!("A strength ramp needs at least one pass");
+        }
+        if let Some(strength) = passes.iter().find(|s| !(0.0..=1.0).contains(*s)) {
+            bail!("Ramp strength {strength} is outside 0.0 to 1.0");
+        }
+        // Every pass gets a fresh name, so only the directory needs checking
+        self.check_output_dir(request.output_path.parent().unwrap_or(Path::new("")))?;
+
+        let prepared = self.prepare_passes(request).await?;
+        let seed = request.seed.unwrap_or_else(rand::random);
+        let group = format!("ramp-{seed:016x}");
+        let template: OutputTemplate = RAMP_OUTPUT_TEMPLATE.parse()?;
+        let base_dir = request.output_path.parent().unwrap_or(Path::new(""));
+
+        let mut results = Vec::new();
+        for &strength in passes {
+            self.cancel.check()?;
+            let mut ctx = TemplateContext::new(
+                &request.output_path,
+                &request.clothing_description,
+                &prepared.model_name,
+            );
+            ctx.strength = strength;
+            ctx.seed = Some(seed);
+            ctx.extension = output_extension(request);
+            let pass_request = TryOnRequest {
+                output_path: template.render_available(base_dir, &ctx),
+                model_name: Some(prepared.model_name.clone()),
+                strength: Some(strength),
+                ..request.clone()
+            };
+            results.push(self.run_pass(&prepared, &pass_request, seed, &group)?);
+        }
+
+        Ok(results)
+    }
+
+    /// Make `count` variations of `request`, loading the model, decoding the
+    /// input and computing the mask only once
+    ///
+    /// The variations are written next to `request.output_path`, numbered
+    /// (`out.png` becomes `out-1.png`, `out-2.png`, ...), and share a history
+    /// group, which their embedded parameters carry too. See
+    /// [`variation_requests`] for how they differ.
+    pub async fn try_on_variations(
+        &self,
+        request: &TryOnRequest,
+        count: usize,
+    ) -> Result<Vec<TryOnResult>> {
+        let base_seed = request.seed.unwrap_or_else(rand::random);
+        let variations = variation_requests(request, count, base_seed)?;
+        self.check_output_dir(request.output_path.parent().unwrap_or(Path::new("")))?;
+
+        let prepared = self.prepare_passes(request).await?;
+        let group = format!("var-{base_seed:016x}");
+        let mut results = Vec::new();
+        for (seed, variation) in variations {
+            self.cancel.check()?;
+            let variation = TryOnRequest {
+                model_name: Some(prepared.model_name.clone()),
+                ..variation
+            };
+            results.push(self.run_pass(&prepared, &variation, seed, &group)?);
+        }
+        Ok(results)
+    }
+
+    /// Load the model and input of `request` and compute its mask, for passes
+    /// that differ in their strengths and output paths only
+    async fn prepare_passes(&self, request: &TryOnRequest) -> Result<PreparedPasses> {
+        request.check_strengths()?;
+        let model_name = request
+            .model_name
+            .clone()
+            .unwrap_or_else(|| DEFAULT_MODEL.to_string());
+        self.load_model(&model_name).await?;
+        let (loaded, load_warnings) = self.load_image(&request.input_image_path)?;
+        let icc_profile = self.output_profile(&loaded);
+        let image = loaded.image;
+        let input_size = (image.width(), image.height());
+        let input_hash = self.input_hash(&image);
+        let (image, crop) = self.preprocess(image, request)?;
+        let rgb_image = image.to_rgb8();
+        let plan = self.transform_plan(request)?;
+        let source = self.mask_source(
+            request.region_mask(&plan),
+            input_hash,
+            input_size,
+            crop,
+            rgb_image.dimensions(),
+        );
+        let clothing_mask = self.clothing_mask(&rgb_image, &source)?;
+        let mask_coverage = self.check_coverage(&clothing_mask)?;
+        let reference = load_reference(request)?;
+        Ok(PreparedPasses {
+            model_name,
+            load_warnings,
+            icc_profile,
+            rgb_image,
+            crop,
+            plan,
+            clothing_mask,
+            mask_coverage,
+            reference,
+        })
+    }
+
+    /// Transform and save one pass of `prepared` as `request` says, recording
+    /// it under `seed` and `group`
+    fn run_pass(
+        &self,
+        prepared: &PreparedPasses,
+        request: &TryOnRequest,
+        seed: u64,
+        group: &str,
+    ) -> Result<TryOnResult> {
+        let start_time = Instant::now();
+        let repro_info = self.repro_info(request, Some(seed)).map(|mut info| {
+            info.extra.push(("Group".to_string(), group.to_string()));
+            info
+        });
+        let result = self
+            .transform_with_mask(
+                &prepared.rgb_image,
+                &prepared.clothing_mask,
+                &prepared.plan,
+                prepared.reference.as_ref(),
+                request.effective_color_strength(),
+                request.effective_style_strength(),
+                &self.cancel,
+            )
+            .and_then(|image| {
+                let icc_dropped = self.save_image(
+                    &image,
+                    &request.output_path,
+                    prepared.icc_profile.as_deref(),
+                    repro_info,
+                    &self.cancel,
+                )?;
+                self.update_thumbnail(&prepared.model_name, &image);
+                Ok(icc_dropped)
+            })
+            .map(|icc_dropped| TryOnResult {
+                output_path: request.output_path.clone(),
+                processing_time: start_time.elapsed(),
+                model_used: self.model_used(&prepared.model_name),
+                model_hash: self.model_hash(&prepared.model_name),
+                crop: prepared.crop,
+                mask_coverage: prepared.mask_coverage,
+                color_strength: request.effective_color_strength(),
+                style_strength: request.effective_style_strength(),
+                suggested_strength: None,
+                seed: Some(seed),
+                group: Some(group.to_string()),
+                load_warnings: prepared.load_warnings.clone(),
+                icc_dropped,
+                comparison_path: None,
+                transform_plan: prepared.plan.clone(),
+            });
+
+        let mut entry = history_entry(request, &result, start_time.elapsed().as_millis() as u64);
+        entry.seed = Some(seed);
+        entry.group = Some(group.to_string());
+        self.record_entry(entry);
+        result
+    }
+
+    /// Run the pipeline once per prompt and assemble the results into a grid image
+    ///
+    /// The model is loaded, the input image decoded and the clothing mask computed
+    /// only once for all prompts.
+    pub async fn try_on_grid(&self, request: &GridRequest) -> Result<GridResult> {
+        if request.prompts.is_empty() {
+            return Err(anyhow::anyhow!("Grid generation needs at least one prompt"));
+        }
+        if let Some(strength) = request.strength {
+            check_strength("strength", strength)?;
+        }
+        self.validate_output(&request.grid_path)?;
+        if let Some(dir) = &request.individual_dir {
+            self.check_output_dir(dir)?;
+        }
+
+        let model_name = request
+            .model_name
+            .clone()
+            .unwrap_or_else(|| DEFAULT_MODEL.to_string());
+        self.load_model(&model_name).await?;
+        let (loaded, load_warnings) = self.load_image(&request.input_image_path)?;
+        let icc_profile = self.output_profile(&loaded);
+        let image = loaded.image;
+        let source = MaskSource {
+            drawn: None,
+            cache_key: self.input_hash(&image).and_then(|input| {
+                self.mask_key(input, None, None, (image.width(), image.height()))
+            }),
+        };
+        let rgb_image = image.to_rgb8();
+        let clothing_mask = self.clothing_mask(&rgb_image, &source)?;
+        let mask_coverage = self.check_coverage(&clothing_mask)?;
+
+        let mut results = Vec::new();
+        let mut cells = Vec::new();
+        for prompt in &request.prompts {
+            self.cancel.check()?;
+            let start_time = std::time::Instant::now();
+            let output_path = match &request.individual_dir {
+                Some(dir) => {
+                    let ctx = TemplateContext::new(&request.input_image_path, prompt, &model_name);
+                    OutputTemplate::default().render_available(dir, &ctx)
+                }
+                None => request.grid_path.clone(),
+            };
+            let prompt_request = TryOnRequest {
+                input_image_path: request.input_image_path.clone(),
+                clothing_description: prompt.clone(),
+                negative_prompt: None,
+                output_path,
+                model_name: Some(model_name.clone()),
+                strength: request.strength,
+                color_strength: None,
+                style_strength: None,
+                reference_image: None,
+                crop: None,
+                mask: None,
+                transform_plan: None,
+                comparison: None,
+                remote_input: None,
+                seed: None,
+            };
+
+            let result = self
+                .planner
+                .plan(prompt)
+                .and_then(|plan| {
+                    let image = self.transform_with_mask(
+                        &rgb_image,
+                        &clothing_mask,
+                        &plan,
+                        None,
+                        prompt_request.effective_color_strength(),
+                        prompt_request.effective_style_strength(),
+                        &self.cancel,
+                    )?;
+                    Ok((image, plan))
+                })
+                .and_then(|(image, plan)| {
+                    let icc_dropped = match request.individual_dir {
+                        Some(_) => self.save_image(
+                            &image,
+                            &prompt_request.output_path,
+                            icc_profile.as_deref(),
+                            self.repro_info(&prompt_request, None),
+                            &self.cancel,
+                        )?,
+                        None => false,
+                    };
+                    Ok((image, icc_dropped, plan))
+                })
+                .map(|(image, icc_dropped, plan)| {
+                    let result = TryOnResult {
+                        output_path: prompt_request.output_path.clone(),
+                        processing_time: start_time.elapsed(),
+                        model_used: self.model_used(&model_name),
+                        model_hash: self.model_hash(&model_name),
+                        crop: None,
+                        mask_coverage,
+                        color_strength: prompt_request.effective_color_strength(),
+                        style_strength: prompt_request.effective_style_strength(),
+                        suggested_strength: None,
+                        seed: None,
+                        group: None,
+                        load_warnings: load_warnings.clone(),
+                        icc_dropped,
+                        comparison_path: None,
+                        transform_plan: plan,
+                    };
+                    (result, image)
+                });
+
+            let history_result = result
+                .as_ref()
+                .map(|(r, _)| r.clone())
+                .map_err(|e| anyhow::anyhow!("{e:#}"));
+            self.record_history(
+                &prompt_request,
+                &history_result,
+                start_time.elapsed().as_millis() as u64,
+            );
+
+            let (result, image) = result?;
+            self.update_thumbnail(&model_name, &image);
+            results.push(result);
+            cells.push((prompt.clone(), image));
+        }
+
+        let columns = request
+            .columns
+            .unwrap_or_else(|| compose::default_columns(cells.len()));
+        // Each row of cells is drawn and written, and its images dropped, before
+        // the next, so a large grid never sits in memory next to its cells
+        let layout = compose::GridLayout::fit(&cells, columns, request.labels)?;
+        self.cancel.check()?;
+        // A grid of several prompts has no one set of parameters to embed
+        self.save_with(
+            &request.grid_path,
+            icc_profile.as_deref(),
+            None,
+            &self.cancel,
+            |path, options| {
+                let bands = layout.rows(cells.into_iter().map(Ok));
+                imageio::save_bands(layout.dimensions(), bands, path, options)
+            },
+        )?;
+
+        Ok(GridResult {
+            results,
+            grid_path: request.grid_path.clone(),
+        })
+    }
+
+    fn record_history(
+        &self,
+        request: &TryOnRequest,
+        result: &Result<TryOnResult>,
+        duration_ms: u64,
+    ) {
+        self.record_entry(history_entry(request, result, duration_ms));
+    }
+
+    fn record_entry(&self, entry: HistoryEntry) {
+        let event = MetricEvent::Generation {
+            model: entry.model.clone(),
+            duration_ms: entry.duration_ms,
+            success: entry.success,
+        };
+        if let Err(e) = self.metrics.record(event) {
+            warn!("Failed to record metrics: {e:#}");
+        }
+
+        let Some(history) = &self.history else {
+            return;
+        };
+        if let Err(e) = history.record(entry) {
+            warn!("Failed to record history: {e:#}");
+        }
+    }
+
+    async fn run_try_on(&self, request: &TryOnRequest) -> Result<TryOnResult> {
+        self.validate_outputs(request)?;
+        self.prepare(request).await?;
+        self.within_timeout(&self.cancel, |cancel| self.process(request, cancel))
+    }
+
+    /// Run `work` with a token that `cancel`, or the [`timeout`](Self::with_timeout)
+    /// running out, trips
+    fn within_timeout<T>(
+        &self,
+        cancel: &CancelToken,
+        work: impl FnOnce(&CancelToken) -> Result<T>,
+    ) -> Result<T> {
+        let Some(limit) = self.timeout else {
+            return work(cancel);
+        };
+        let cancel = cancel.child();
+        let watchdog = Watchdog::start(cancel.clone(), limit);
+        match work(&cancel) {
+            Err(e) if watchdog.fired() && SiError::Cancelled.matches(&e) => {
+                warn!(
+                    "Try-on stopped after running for {}",
+                    format_duration(limit)
+                );
+                Err(SiError::Timeout { limit }.into())
+            }
+            result => result,
+        }
+    }
+
+    /// Load the model `request` needs
+    pub(crate) async fn prepare(&self, request: &TryOnRequest) -> Result<()> {
+        request.check_strengths()?;
+        // Load default model if none specified
+        let model_name = request.model();
+        self.emit_stage(TryOnStage::LoadingModel);
+        self.load_model(model_name).await
+    }
+
+    /// Whether the model `request` needs is already loaded
+    pub(crate) fn is_prepared(&self, request: &TryOnRequest) -> bool {
+        self.is_loaded(request.model())
+    }
+
+    /// Run a [`prepare`](Self::prepare)d request, stopping early when `cancel` is
+    /// tripped, and record it in the history
+    pub(crate) fn run_prepared(
+        &self,
+        request: &TryOnRequest,
+        cancel: &CancelToken,
+    ) -> Result<TryOnResult> {
+        let start_time = Instant::now();
+        let result = self
+            .validate_outputs(request)
+            .and_then(|()| self.within_timeout(cancel, |cancel| self.process(request, cancel)));
+        self.record_history(request, &result, start_time.elapsed().as_millis() as u64);
+        result
+    }
+
+    fn process(&self, request: &TryOnRequest, cancel: &CancelToken) -> Result<TryOnResult> {
+        let start_time = Instant::now();
+
+        info!(
+            "Starting virtual try-on with prompt: {}",
+            request.clothing_description
+        );
+        let model_name = request.model();
+
+        self.emit_stage(TryOnStage::Decoding);
+        let stage_start = Instant::now();
+        let (loaded, load_warnings) = self.load_image(&request.input_image_path)?;
+        let icc_profile = self.output_profile(&loaded);
+        let input_image = loaded.image;
+        debug!(target: TIMING_TARGET, "Decoding took {:.2?}", stage_start.elapsed());
+        let before = request.comparison.as_ref().map(|_| input_image.clone());
+        let rendered = self.render(input_image, request, cancel)?;
+        let result_image = rendered.image;
+
+        // Save result
+        cancel.check()?;
+        self.emit_stage(TryOnStage::Encoding);
+        let stage_start = Instant::now();
+        let icc_dropped = self.save_image(
+            &result_image,
+            &request.output_path,
+            icc_profile.as_deref(),
+            self.repro_info(request, None)
+                .map(|info| rendered.strengths.describe(info)),
+            cancel,
+        )?;
+        let comparison_path = self
+            .write_comparison(
+                request,
+                before.as_ref(),
+                &result_image,
+                icc_profile.as_deref(),
+                cancel,
+            )
+            .inspect_err(|_| {
+                // Half of a cancelled pair is a partial output too
+                if cancel.is_cancelled() {
+                    let _ = fs::remove_file(&request.output_path);
+                }
+            })?;
+        debug!(target: TIMING_TARGET, "Encoding took {:.2?}", stage_start.elapsed());
+        self.update_thumbnail(model_name, &result_image);
+
+        let processing_time = start_time.elapsed();
+
+        info!(
+            "Virtual try-on completed in {}",
+            format_duration(processing_time)
+        );
+
+        Ok(TryOnResult {
+            output_path: request.output_path.clone(),
+            processing_time,
+            model_used: self.model_used(model_name),
+            model_hash: self.model_hash(model_name),
+            crop: rendered.crop,
+            mask_coverage: rendered.mask_coverage,
+            color_strength: rendered.strengths.color,
+            style_strength: rendered.strengths.style,
+            suggested_strength: rendered.strengths.suggested,
+            seed: None,
+            group: None,
+            load_warnings,
+            icc_dropped,
+            comparison_path,
+            transform_plan: rendered.plan,
+        })
+    }
+
+    /// Write `before` and `after` side by side where `request` asks for a
+    /// comparison, returning its path
+    fn write_comparison(
+        &self,
+        request: &TryOnRequest,
+        before: Option<&DynamicImage>,
+        after: &DynamicImage,
+        icc_profile: Option<&[u8]>,
+        cancel: &CancelToken,
+    ) -> Result<Option<PathBuf>> {
+        let (Some(comparison), Some(before)) = (&request.comparison, before) else {
+            return Ok(None);
+        };
+        let composite = comparison.compose(before, after);
+        self.save_image(&composite, &comparison.path, icc_profile, None, cancel)
+            .with_context(|| format!("Failed to write comparison {}", comparison.path.display()))?;
+        Ok(Some(comparison.path.clone()))
+    }
+
+    /// Crop `input_image` and apply the clothing transformation `request` asks for
+    fn render(
+        &self,
+        input_image: DynamicImage,
+        request: &TryOnRequest,
+        cancel: &CancelToken,
+    ) -> Result<Rendered> {
+        let input_size = (input_image.width(), input_image.height());
+        let input_hash = self.input_hash(&input_image);
+        let (input_image, crop) = self.preprocess(input_image, request)?;
+        let reference = load_reference(request)?;
+        let plan = self.transform_plan(request)?;
+        let source = self.mask_source(
+            request.region_mask(&plan),
+            input_hash,
+            input_size,
+            crop,
+            (input_image.width(), input_image.height()),
+        );
+
+        let (image, mask_coverage, strengths) = self.apply_clothing_transformation(
+            &input_image,
+            &source,
+            &plan,
+            reference.as_ref(),
+            request,
+            cancel,
+        )?;
+        Ok(Rendered {
+            image,
+            crop,
+            mask_coverage,
+            plan,
+            strengths,
+        })
+    }
+
+    /// The transformation `request` asks for: its own plan when it has one,
+    /// otherwise the planner's reading of its description
+    pub(crate) fn transform_plan(&self, request: &TryOnRequest) -> Result<TransformPlan> {
+        if let Some(plan) = &request.transform_plan {
+            return Ok(plan.clone());
+        }
+        // Planners only see the description, but a malformed negative prompt is
+        // still an error
+        if let Some(negative) = &request.negative_prompt {
+            ParsedPrompt::parse(negative)?;
+        }
+        self.planner.plan(&request.clothing_description)
+    }
+
+    /// Apply `request`'s transformation to an image already in memory, without
+    /// reading its input or writing its output
+    pub fn render_image(
+        &self,
+        image: DynamicImage,
+        request: &TryOnRequest,
+    ) -> Result<DynamicImage> {
+        Ok(self.render(image, request, &self.cancel)?.image)
+    }
+
+    /// Apply the crop `request` asks for, returning the image to process and the
+    /// region of the input it covers
+    pub(crate) fn preprocess(
+        &self,
+        image: DynamicImage,
+        request: &TryOnRequest,
+    ) -> Result<(DynamicImage, Option<Region>)> {
+        let region = match &request.crop {
+            None => return Ok((image, None)),
+            Some(Crop::Region(spec)) => spec.resolve(image.width(), image.height())?,
+            Some(Crop::AutoCenter { margin }) => {
+                let Some(bbox) = self.segmenter.bounding_box(&image.to_rgb8())? else {
+                    warn!(
+                        "No clothing found in {}, processing it uncropped",
+                        request.input_image_path.display()
+                    );
+                    return Ok((image, None));
+                };
+                preprocess::expand(bbox, *margin, image.width(), image.height())
+            }
+        };
+        debug!("Cropping input to {region:?}");
+        let cropped = preprocess::crop(&image, &CropSpec::from(region))?;
+        Ok((cropped, Some(region)))
+    }
+
+    /// Store `image` as the thumbnail of `model_name` unless it already has one
+    fn update_thumbnail(&self, model_name: &str, image: &DynamicImage) {
+        if !self.thumbnails || self.model_manager.thumbnail_path(model_name).is_some() {
+            return;
+        }
+        if let Err(e) = self.model_manager.set_thumbnail(model_name, image) {
+            warn!("Failed to store thumbnail for {model_name}: {e:#}");
+        }
+    }
+
+    /// Load an input, along with what was worked around to recover it when
+    /// loading is lenient
+    pub(crate) fn load_image(&self, path: &Path) -> Result<(LoadedImage, Vec<LoadWarning>)> {
+        self.validate_input_image(path)?;
+        if !self.lenient {
+            return Ok((imageio::load(path)?, Vec::new()));
+        }
+        let (loaded, warnings) = imageio::load_lenient(path)?;
+        for warning in &warnings {
+            warn!("Recovered {}: {warning}", path.display());
+        }
+        Ok((loaded, warnings))
+    }
+
+    /// The ICC profile to give outputs made from `loaded`, unless stripping
+    pub(crate) fn output_profile(&self, loaded: &LoadedImage) -> Option<Vec<u8>> {
+        loaded.icc_profile.clone().filter(|_| !self.strip_icc)
+    }
+
+    /// Buffer an input image, refusing inputs that are empty or bigger than any
+    /// image the limits accept could decode to
+    fn read_input(&self, input: impl Read) -> Result<Vec<u8>> {
+        let max_bytes = self.limits.max_decoded_bytes;
+        let mut bytes = Vec::new();
+        input
+            .take(max_bytes.saturating_add(1))
+            .read_to_end(&mut bytes)
+            .context("Failed to read the input image")?;
+        if bytes.len() as u64 > max_bytes {
+            bail!(
+                "Input image is larger than {}; use a smaller photo",
+                format_size(max_bytes)
+            );
+        }
+        Ok(bytes)
+    }
+
+    fn decode_image(&self, bytes: &[u8]) -> Result<DynamicImage> {
+        let format = self.validate_input_bytes(bytes)?;
+        image::load_from_memory_with_format(bytes, format).context("Failed to load input image")
+    }
+
+    /// Write `img` to `path` with `icc_profile` and `parameters`, returning
+    /// whether the format couldn't carry the profile
+    fn save_image(
+        &self,
+        img: &DynamicImage,
+        path: &Path,
+        icc_profile: Option<&[u8]>,
+        parameters: Option<ReproInfo>,
+        cancel: &CancelToken,
+    ) -> Result<bool> {
+        self.save_with(path, icc_profile, parameters, cancel, |path, options| {
+            imageio::save(img, path, options)
+        })
+    }
+
+    /// Write `path` with `save`, given the output options, and whether the
+    /// input's profile had to be left out
+    fn save_with(
+        &self,
+        path: &Path,
+        icc_profile: Option<&[u8]>,
+        parameters: Option<ReproInfo>,
+        cancel: &CancelToken,
+        save: impl FnOnce(&Path, &imageio::OutputOptions) -> Result<imageio::SaveReport>,
+    ) -> Result<bool> {
+        let options = imageio::OutputOptions {
+            format: None,
+            quality: self.output_quality,
+            icc_profile: icc_profile.map(<[u8]>::to_vec),
+            parameters,
+        };
+        let saved = save(path, &options);
+        if cancel.is_cancelled() {
+            // A cancelled run leaves no output behind, even a complete one
+            if saved.is_ok() {
+                let _ = std::fs::remove_file(path);
+            }
+            cancel.check()?;
+        }
+        saved.map(|report| icc_profile.is_some() && !report.icc_embedded)
+    }
+
+    /// The parameters to embed in the output of `request`, unless embedding is
+    /// off
+    fn repro_info(&self, request: &TryOnRequest, seed: Option<u64>) -> Option<ReproInfo> {
+        self.embed_metadata.then(|| ReproInfo {
+            negative_prompt: request.negative_prompt.clone(),
+            seed,
+            extra: self
+                .model_hash(request.model())
+                .map(|hash| (MODEL_HASH_KEY.to_string(), hash))
+                .into_iter()
+                .collect(),
+            ..ReproInfo::new(
+                &request.clothing_description,
+                &self.model_used(request.model()),
+                request.effective_strength(),
+            )
+        })
+    }
+
+    /// The configured output quality, for the formats that take one
+    fn quality_for(&self, format: ImageFormat) -> Option<u8> {
+        self.output_quality
+            .filter(|_| formats::supports_quality(format))
+    }
+
+    /// Apply `plan` to `image` inside the clothing mask, at the strengths
+    /// `request` sets or, with [`with_auto_strength`](Self::with_auto_strength),
+    /// the one suggested for the masked garment
+    fn apply_clothing_transformation(
+        &self,
+        image: &DynamicImage,
+        source: &MaskSource,
+        plan: &TransformPlan,
+        reference: Option<&ColorReference>,
+        request: &TryOnRequest,
+        cancel: &CancelToken,
+    ) -> Result<(DynamicImage, f32, Strengths)> {
+        debug!("Applying clothing transformation: {plan:?}");
+
+        // Convert to RGB for processing
+        let rgb_image = image.to_rgb8();
+
+        // Detect clothing regions (simplified approach for MVP)
+        self.emit_stage(TryOnStage::Segmenting);
+        let clothing_mask = self.clothing_mask(&rgb_image, source)?;
+        let mask_coverage = self.check_coverage(&clothing_mask)?;
+        let strengths = Strengths::of(
+            request,
+            self.suggested_strength(request, &rgb_image, &clothing_mask, plan),
+        );
+        self.emit_stage(TryOnStage::Transforming);
+
+        let started = Instant::now();
+        let result = self.transform_with_mask(
+            &rgb_image,
+            &clothing_mask,
+            plan,
+            reference,
+            strengths.color,
+            strengths.style,
+            cancel,
+        );
+        debug!(target: TIMING_TARGET, "Transforming took {:.2?}", started.elapsed());
+        result.map(|image| (image, mask_coverage, strengths))
+    }
+
+    /// The strength [`analysis::suggest_strength`] picks for the garment
+    /// `clothing_mask` covers in `image`, when `request` leaves it to
+    /// [`with_auto_strength`](Self::with_auto_strength)
+    pub(crate) fn suggested_strength(
+        &self,
+        request: &TryOnRequest,
+        image: &RgbImage,
+        clothing_mask: &GrayImage,
+        plan: &TransformPlan,
+    ) -> Option<f64> {
+        if !self.auto_strength || request.strength.is_some() {
+            return None;
+        }
+        let strength = analysis::suggest_strength(image, clothing_mask, plan);
+        debug!("Suggested strength {strength:.2}");
+        // Rounded so the result, the file's metadata and a recipe agree
+        Some((f64::from(strength) * 100.0).round() / 100.0)
+    }
+
+    /// The share of the image `clothing_mask` covers, warning or, with
+    /// [`with_strict_mask`](Self::with_strict_mask), failing when it is outside
+    /// the coverage bounds
+    fn check_coverage(&self, clothing_mask: &GrayImage) -> Result<f32> {
+        let stats = MaskStats::of(clothing_mask);
+        debug!(
+            target: MASK_TARGET,
+            "Mask coverage {:.1}%, bounding box {:?}",
+            stats.coverage * 100.0,
+            stats.bounding_box
+        );
+        if let Some(warning) = self.coverage_bounds.check(stats.coverage) {
+            if self.strict_mask {
+                bail!("{warning}; stopping as the mask check is strict");
+            }
+            warn!("{warning}");
+            self.emit(TryOnEvent::MaskWarning {
+                warning,
+                bounding_box: stats.bounding_box,
+            });
+        }
+        Ok(stats.coverage)
+    }
+
+    /// Apply `plan` inside `clothing_mask`
+    ///
+    /// A `reference` replaces the color of the plan; its fabric style
+    /// adjustments still apply. `color_strength` scales the color change and
+    /// `style_strength` the style adjustments.
+    #[allow(clippy::too_many_arguments)]
+    fn transform_with_mask(
+        &self,
+        rgb_image: &RgbImage,
+        clothing_mask: &GrayImage,
+        plan: &TransformPlan,
+        reference: Option<&ColorReference>,
+        color_strength: f64,
+        style_strength: f64,
+        cancel: &CancelToken,
+    ) -> Result<DynamicImage> {
+        let (recolored, color_transform) = match reference {
+            Some(reference) => (
+                Some(reference.transfer(
+                    rgb_image,
+                    clothing_mask,
+                    color_strength as f32,
+                    cancel,
+                )?),
+                ColorTransform::NEUTRAL,
+            ),
+            None => (None, plan.color),
+        };
+
+        // Apply transformations
+        let mut transformed_image = self.apply_color_and_style_transformation(
+            recolored.as_ref().unwrap_or(rgb_image),
+            clothing_mask,
+            &color_transform,
+            &plan.style,
+            color_strength as f32,
+            style_strength as f32,
+            cancel,
+        )?;
+        if let Some(overlay) = &self.texture {
+            if let Some(texture) = overlay.texture(plan)? {
+                overlay.apply(&texture, &mut transformed_image, clothing_mask);
+            }
+        }
+
+        Ok(DynamicImage::ImageRgb8(transformed_image))
+    }
+
+    /// The [`mask_cache::content_hash`] of an uncropped input, when its mask
+    /// may be cached
+    fn input_hash(&self, input: &DynamicImage) -> Option<String> {
+        self.mask_cache.as_ref()?;
+        self.segmenter.id()?;
+        Some(mask_cache::content_hash(input))
+    }
+
+    /// The cache key of the mask for a `size` image made from the input hashed
+    /// as `input`, cropped to `crop` and limited to `mask`
+    fn mask_key(
+        &self,
+        input: String,
+        crop: Option<Region>,
+        mask: Option<&RegionMask>,
+        size: (u32, u32),
+    ) -> Option<MaskKey> {
+        Some(MaskKey {
+            input,
+            segmenter: self.segmenter.id()?.to_string(),
+            crop,
+            mask: mask.filter(|m| m.mode != MaskMode::Detect).cloned(),
+            size,
+        })
+    }
+
+    /// Where the mask of a `size` image made from an input of `input_size`,
+    /// cropped to `crop` and limited to `mask`, comes from
+    fn mask_source(
+        &self,
+        mask: Option<&RegionMask>,
+        input_hash: Option<String>,
+        input_size: (u32, u32),
+        crop: Option<Region>,
+        size: (u32, u32),
+    ) -> MaskSource {
+        MaskSource {
+            drawn: drawn_mask(mask, input_size, crop, size),
+            cache_key: input_hash.and_then(|input| self.mask_key(input, crop, mask, size)),
+        }
+    }
+
+    /// The mask to transform inside: the detected clothing, narrowed to or
+    /// replaced by a drawn one, from the mask cache when it has it, without skin
+    /// unless skin protection is off
+    pub(crate) fn clothing_mask(&self, image: &RgbImage, source: &MaskSource) -> Result<GrayImage> {
+        let mask = self.segmented_mask(image, source)?;
+        let (mask, protected) = match &self.skin_protection {
+            Some(protection) => protection.apply(image, &mask),
+            None => (mask, GrayImage::new(image.width(), image.height())),
+        };
+        debug!(
+            target: MASK_TARGET,
+            "Skin protection kept {:.1}% of the image out of the mask",
+            mask_coverage(&protected) * 100.0
+        );
+        if let Some(path) = &self.mask_debug {
+            mask::debug_overlay(image, &mask, &protected)
+                .save(path)
+                .with_context(|| {
+                    format!("Failed to write the mask overlay to {}", path.display())
+                })?;
+        }
+        Ok(mask)
+    }
+
+    /// The clothing mask before skin protection, which is what the mask cache
+    /// holds
+    fn segmented_mask(&self, image: &RgbImage, source: &MaskSource) -> Result<GrayImage> {
+        let cache = self.mask_cache.as_ref().zip(source.cache_key.as_ref());
+        if let Some(mask) = cache.and_then(|(cache, key)| cache.get_mask(key)) {
+            return Ok(mask);
+        }
+        let mask = match &source.drawn {
+            Some((drawn, MaskMode::Polygon)) => drawn.clone(),
+            Some((drawn, MaskMode::Intersect)) => {
+                mask::intersect(&self.detect_clothing_regions(image)?, drawn)
+            }
+            Some((_, MaskMode::Detect)) | None => self.detect_clothing_regions(image)?,
+        };
+        if let Some((cache, key)) = cache {
+            if let Err(e) = cache.put_mask(key, &mask) {
+                warn!("Failed to cache the clothing mask: {e:#}");
+            }
+        }
+        Ok(mask)
+    }
+
+    pub(crate) fn detect_clothing_regions(&self, image: &RgbImage) -> Result<GrayImage> {
+        let started = Instant::now();
+        let mask = self.segmenter.segment(image)?;
+        debug!(target: TIMING_TARGET, "Segmenting took {:.2?}", started.elapsed());
+        if log::log_enabled!(target: MASK_TARGET, log::Level::Debug) {
+            let pixels = (f64::from(mask.width()) * f64::from(mask.height())).max(1.0);
+            let mean = mask.pixels().map(|p| f64::from(p.0[0])).sum::<f64>() / pixels / 255.0;
+            debug!(
+                target: MASK_TARGET,
+                "Mask {}x{}: {:.1}% covered, mean weight {mean:.3}",
+                mask.width(),
+                mask.height(),
+                mask_coverage(&mask) * 100.0
+            );
+        }
+        Ok(mask)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn apply_color_and_style_transformation(
+        &self,
+        image: &RgbImage,
+        mask: &GrayImage,
+        color_transform: &ColorTransform,
+        style_adjustments: &StyleAdjustments,
+        color_strength: f32,
+        style_strength: f32,
+        cancel: &CancelToken,
+    ) -> Result<RgbImage> {
+        let (_width, height) = image.dimensions();
+        let mut result = image.clone();
+        let StyleAdjustments {
+            contrast: contrast_mult,
+            brightness: brightness_offset,
+        } = *style_adjustments;
+
+        for (x, y, pixel) in image.enumerate_pixels() {
+            if x == 0 && y % CANCEL_CHECK_ROWS == 0 {
+                cancel.check()?;
+                self.emit(TryOnEvent::Rows {
+                    done: y,
+                    total: height,
+                });
+            }
+            let mask_weight = mask.get_pixel(x, y).0[0] as f32 / 255.0;
+            let color = mask_weight * color_strength;
+            let style = mask_weight * style_strength;
+
+            if color.max(style) > 0.1 {
+                // Transform this pixel
+                let transformed_pixel = self.transform_pixel(
+                    pixel,
+                    color_transform,
+                    contrast_mult,
+                    brightness_offset,
+                    color,
+                    style,
+                )?;
+                result.put_pixel(x, y, transformed_pixel);
+            }
+        }
+
+        self.emit(TryOnEvent::Rows {
+            done: height,
+            total: height,
+        });
+        debug!("Applied color and style transformation");
+        Ok(result)
+    }
+
+    fn transform_pixel(
+        &self,
+        pixel: &Rgb<u8>,
+        color_transform: &ColorTransform,
+        contrast_mult: f32,
+        brightness_offset: f32,
+        color_strength: f32,
+        style_strength: f32,
+    ) -> Result<Rgb<u8>> {
+        let [r, g, b] = pixel.0;
+
+        // Convert to HSL for color manipulation
+        let rgb = Srgb::new(r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0);
+        let hsl = Hsl::from_color(rgb);
+
+        // Apply color transformations
+        let new_hue = if color_transform.hue_shift != 0.0 {
+            (hsl.hue.into_positive_degrees() + color_transform.hue_shift * color_strength) % 360.0
+        } else {
+            hsl.hue.into_positive_degrees()
+        };
+
+        let new_saturation = (hsl.saturation
+            * (1.0 + (color_transform.saturation_mult - 1.0) * color_strength))
+            .clamp(0.0, 1.0);
+
+        let new_lightness = (hsl.lightness
+            * (1.0 + (color_transform.lightness_mult - 1.0) * color_strength))
+            .clamp(0.0, 1.0);
+
+        // Create new HSL color
+        let new_hsl = Hsl::new(new_hue, new_saturation, new_lightness);
+        let new_rgb = Srgb::from_color(new_hsl);
+
+        // Apply contrast and brightness adjustments. The style part is
+        // `style_strength` of the adjusted color, the color part makes up the
+        // rest of `color_strength`, so equal strengths blend exactly as one would
+        let blend = |new: f32, old: u8| {
+            ((new * contrast_mult + brightness_offset) * style_strength
+                + new * (color_strength - style_strength)
+                + (old as f32 / 255.0) * (1.0 - color_strength))
+                .clamp(0.0, 1.0)
+        };
+        let final_r = blend(new_rgb.red, r);
+        let final_g = blend(new_rgb.green, g);
+        let final_b = blend(new_rgb.blue, b);
+
+        Ok(Rgb([
+            (final_r * 255.0) as u8,
+            (final_g * 255.0) as u8,
+            (final_b * 255.0) as u8,
+        ]))
+    }
+
+    /// Get recommended models for virtual try-on
+    pub fn get_recommended_models() -> Vec<&'static str> {
+        vec![
+            "runwayml/stable-diffusion-v1-5",
+            "stabilityai/stable-diffusion-2-1",
+            "stabilityai/stable-diffusion-xl-base-1.0",
+        ]
+    }
+
+    /// Get current device info for debugging
+    pub fn device_info(&self) -> String {
+        "CPU (Image Processing Mode)".to_string()
+    }
+
+    /// The weight variant matching the dtype generation computes in; the CPU
+    /// pipeline works in full precision
+    pub fn preferred_variant(&self) -> &'static str {
+        DEFAULT_VARIANT
+    }
+}
+
+/// Check that `dir` is a writable directory, or that the closest existing
+/// ancestor is one so `dir` can be created in it
+fn check_output_dir(dir: &Path) -> Result<()> {
+    let mut existing = dir;
+    let metadata = loop {
+        let probe = if existing.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            existing
+        };
+        match fs::metadata(probe) {
+            Ok(metadata) => break metadata,
+            // A missing ancestor, or one below a file; the loop finds which
+            Err(_) => match existing.parent() {
+                Some(parent) => existing = parent,
+                None => bail!("Output directory {} can't be created", dir.display()),
+            },
+        }
+    };
+    let shown = |path: &Path| {
+        if path.as_os_str().is_empty() {
+            ".".to_string()
+        } else {
+            path.display().to_string()
+        }
+    };
+    if !metadata.is_dir() {
+        if existing == dir {
+            bail!("Output directory {} is a file", shown(dir));
+        }
+        bail!(
+            "Can't create output directory {}: {} is a file",
+            shown(dir),
+            shown(existing)
+        );
+    }
+    if let Err(e) = probe_writable(existing) {
+        if existing == dir {
+            bail!("Output directory {} is not writable: {e}", shown(dir));
+        }
+        bail!(
+            "Can't create output directory {}: {} is not writable: {e}",
+            shown(dir),
+            shown(existing)
+        );
+    }
+    Ok(())
+}
+
+/// Create and remove a file in `dir`; permission bits alone don't account for
+/// ACLs, read-only mounts or running as root
+///
+/// The file is named after the process and a counter, so concurrent probes of
+/// one directory, from this process or others, never collide.
+fn probe_writable(dir: &Path) -> io::Result<()> {
+    static PROBES: AtomicU64 = AtomicU64::new(0);
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    let probe = dir.join(format!(
+        ".si-write-check-{}-{}",
+        std::process::id(),
+        PROBES.fetch_add(1, Ordering::Relaxed)
+    ));
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&probe)?;
+    fs::remove_file(&probe)
+}
+
+/// The extension of `request`'s output, `png` when it has none
+fn output_extension(request: &TryOnRequest) -> String {
+    request
+        .output_path
+        .extension()
+        .map(|e| e.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "png".to_string())
+}
+
+/// Fail unless `strength`, the value of the field `name`, is within 0.0 to 1.0
+pub(crate) fn check_strength(name: &str, strength: f64) -> Result<()> {
+    if !(0.0..=1.0).contains(&strength) {
+        bail!("{name} must be 0.0-1.0, got {strength}");
+    }
+    Ok(())
+}
+
+/// The requests for `count` variations of `request`, each with its seed
+///
+/// Variation `i` (from 0) gets the seed `base_seed + i`, which is what a
+/// sampling backend would vary. The built-in pipeline is deterministic, so the
+/// variations also differ in strength: each moves the strength, and the color
+/// and style strengths the request sets, by `k` × [`VARIATION_STRENGTH_STEP`],
+/// with `k` running 0, 1, -1, 2, -2, ... and skipping the steps that would
+/// take the strength outside 0.0 to 1.0. The first variation keeps the
+/// request's strengths.
+///
+/// Each output is named after `request.output_path` with the variation's
+/// number, from 1, added (`out.png` becomes `out-1.png`).
+pub fn variation_requests(
+    request: &TryOnRequest,
+    count: usize,
+    base_seed: u64,
+) -> Result<Vec<(u64, TryOnRequest)>> {
+    if !(1..=MAX_VARIATIONS).contains(&count) {
+        bail!("The number of variations must be between 1 and {MAX_VARIATIONS}, got {count}");
+    }
+    let strength = request.effective_strength();
+    let steps = (0..).map(|k: i32| {
+        let step = f64::from((k + 1) / 2) * VARIATION_STRENGTH_STEP;
+        if k % 2 == 1 { step } else { -step }
+    });
+    let nudge = |value: f64, by: f64| ((value + by).clamp(0.0, 1.0) * 100.0).round() / 100.0;
+    let template: OutputTemplate = VARIATION_OUTPUT_TEMPLATE.parse()?;
+    let base_dir = request.output_path.parent().unwrap_or(Path::new(""));
+
+    Ok(steps
+        .filter(|step| (-1e-9..=1.0 + 1e-9).contains(&(strength + step)))
+        .take(count)
+        .enumerate()
+        .map(|(i, step)| {
+            let seed = base_seed.wrapping_add(i as u64);
+            let mut ctx = TemplateContext::new(
+                &request.output_path,
+                &request.clothing_description,
+                request.model(),
+            );
+            ctx.seed = Some(seed);
+            ctx.variation = Some(i + 1);
+            ctx.extension = output_extension(request);
+            let variation = TryOnRequest {
+                output_path: template.render_available(base_dir, &ctx),
+                strength: Some(nudge(strength, step)),
+                color_strength: request.color_strength.map(|c| nudge(c, step)),
+                style_strength: request.style_strength.map(|s| nudge(s, step)),
+                ..request.clone()
+            };
+            (seed, variation)
+        })
+        .collect())
+}
+
+fn load_reference(request: &TryOnRequest) -> Result<Option<ColorReference>> {
+    request
+        .reference_image
+        .as_deref()
+        .map(ColorReference::load)
+        .transpose()
+}
+
+fn history_entry(
+    request: &TryOnRequest,
+    result: &Result<TryOnResult>,
+    duration_ms: u64,
+) -> HistoryEntry {
+    HistoryEntry {
+        id: 0,
+        timestamp: Utc::now(),
+        prompt: request.clothing_description.clone(),
+        negative_prompt: request.negative_prompt.clone(),
+        parsed_prompt: request.parsed_prompt().ok(),
+        model: request.model().to_string(),
+        model_hash: result.as_ref().ok().and_then(|r| r.model_hash.clone()),
+        input: request.input_image_path.clone(),
+        output: request.output_path.clone(),
+        strength: request.strength,
+        color_strength: Some(request.effective_color_strength()),
+        style_strength: Some(request.effective_style_strength()),
+        reference: request.reference_image.clone(),
+        remote_input: request.remote_input.clone(),
+        crop: result.as_ref().ok().and_then(|r| r.crop),
+        mask: request.mask.clone(),
+        seed: None,
+        group: None,
+        duration_ms,
+        success: result.is_ok(),
+        error: result.as_ref().err().map(|e| format!("{e:#}")),
+    }
+}
+
+// Helper functions for the CLI integration
+impl VirtualTryOn {
+    /// Check that `path` is an image within the configured [`InputLimits`],
+    /// returning its format
+    ///
+    /// Only the header is read: the format is detected from the leading bytes
+    /// regardless of the file extension, and the dimensions come from the decoder
+    /// without decoding any pixels.
+    pub fn validate_input_image(&self, path: &Path) -> Result<ImageFormat> {
+        if !path.exists() {
+            return Err(anyhow::anyhow!(
+                "Input image does not exist: {}",
+                path.display()
+            ));
+        }
+
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open input image {}", path.display()))?;
+        self.validate_input(BufReader::new(file), &path.display().to_string())
+    }
+
+    /// Like [`validate_input_image`](Self::validate_input_image), for an image held
+    /// in memory
+    pub fn validate_input_bytes(&self, bytes: &[u8]) -> Result<ImageFormat> {
+        if bytes.is_empty() {
+            bail!("Input image is empty");
+        }
+        self.validate_input(Cursor::new(bytes), "input")
+    }
+
+    fn validate_input(&self, mut reader: impl BufRead + Seek, name: &str) -> Result<ImageFormat> {
+        let mut header = Vec::with_capacity(64);
+        (&mut reader).take(64).read_to_end(&mut header)?;
+        reader.rewind()?;
+        let format = image::guess_format(&header)
+            .map_err(|e| anyhow::anyhow!("Invalid image format in {name}: {e}"))?;
+
+        let mut reader = ImageReader::new(reader);
+        reader.set_format(format);
+        let (width, height) = reader
+            .into_dimensions()
+            .map_err(|e| anyhow::anyhow!("Invalid image format in {name}: {e}"))?;
+
+        self.limits.check(format, width, height)?;
+        Ok(format)
+    }
+
+    /// [`validate_output`](Self::validate_output) for every file `request` writes
+    pub fn validate_outputs(&self, request: &TryOnRequest) -> Result<()> {
+        self.validate_output(&request.output_path)?;
+        if let Some(comparison) = &request.comparison {
+            self.validate_output(&comparison.path)?;
+        }
+        Ok(())
+    }
+
+    /// Check that an image can be written to `path` before any work is done
+    ///
+    /// Its format must be known from the extension and compiled in, it must not
+    /// be a directory or, unless overwriting is allowed, an existing file, and
+    /// its parent must be a writable directory or creatable as one.
+    pub fn validate_output(&self, path: &Path) -> Result<()> {
+        let format = ImageFormat::from_path(path)
+            .with_context(|| format!("Can't tell the output format from {}", path.display()))?;
+        formats::ensure_writable(format)?;
+        match fs::symlink_metadata(path) {
+            Ok(metadata) if metadata.is_dir() => {
+                bail!("Output path {} is a directory", path.display())
+            }
+            Ok(_) if !self.overwrite => bail!(
+                "Output file {} already exists and overwriting is disabled",
+                path.display()
+            ),
+            _ => {}
+        }
+        self.check_output_dir(path.parent().unwrap_or(Path::new("")))
+    }
+
+    /// [`check_output_dir`] once per directory: the jobs of a batch mostly
+    /// share one, and probing it for each would only add file churn
+    fn check_output_dir(&self, dir: &Path) -> Result<()> {
+        if self
+            .writable_dirs
+            .lock()
+            .expect("writable dirs lock")
+            .contains(dir)
+        {
+            return Ok(());
+        }
+        check_output_dir(dir)?;
+        self.writable_dirs
+            .lock()
+            .expect("writable dirs lock")
+            .insert(dir.to_path_buf());
+        Ok(())
+    }
+
+    pub fn suggest_output_path(input_path: &Path, clothing_description: &str) -> PathBuf {
+        let ctx = TemplateContext::new(input_path, clothing_description, "");
+        let base_dir = input_path.parent().unwrap_or(Path::new(""));
+        base_dir.join(OutputTemplate::default().render(&ctx))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        collections::HashSet,
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+    };
+    use tempfile::tempdir;
+
+    /// The single pixel of a solid `color` image transformed by `prompt` with a
+    /// full mask
+    fn transform_solid(
+        tryon: &VirtualTryOn,
+        color: [u8; 3],
+        prompt: &str,
+        color_strength: f64,
+        style_strength: f64,
+    ) -> Result<Rgb<u8>> {
+        let image = RgbImage::from_pixel(4, 4, Rgb(color));
+        let mask = GrayImage::from_pixel(4, 4, image::Luma([255]));
+        let result = tryon.transform_with_mask(
+            &image,
+            &mask,
+            &tryon.planner.plan(prompt)?,
+            None,
+            color_strength,
+            style_strength,
+            &CancelToken::new(),
+        )?;
+        Ok(*result.to_rgb8().get_pixel(0, 0))
+    }
+
+    #[test]
+    fn test_zero_style_strength_keeps_contrast() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let model_manager = crate::ModelManagerBuilder::new()
+            .with_models_dir(temp_dir.path().to_path_buf())
+            .build()?;
+        let tryon = VirtualTryOn::new(model_manager)?;
+        let gray = [120, 120, 120];
+
+        // Leather darkens and adds contrast; without style strength it makes no
+        // difference to a jacket
+        assert_ne!(
+            transform_solid(&tryon, gray, "leather jacket", 1.0, 1.0)?,
+            transform_solid(&tryon, gray, "jacket", 1.0, 1.0)?
+        );
+        assert_eq!(
+            transform_solid(&tryon, gray, "leather jacket", 1.0, 0.0)?,
+            transform_solid(&tryon, gray, "jacket", 1.0, 0.0)?
+        );
+        assert_eq!(
+            transform_solid(&tryon, [60, 90, 200], "red leather jacket", 0.8, 0.0)?,
+            transform_solid(&tryon, [60, 90, 200], "red jacket", 0.8, 0.0)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_zero_color_strength_keeps_hue() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let model_manager = crate::ModelManagerBuilder::new()
+            .with_models_dir(temp_dir.path().to_path_buf())
+            .build()?;
+        let tryon = VirtualTryOn::new(model_manager)?;
+        let hue = |pixel: Rgb<u8>| {
+            let [r, g, b] = pixel.0.map(|c| c as f32 / 255.0);
+            Hsl::from_color(Srgb::new(r, g, b))
+                .hue
+                .into_positive_degrees()
+        };
+        let red = [200, 40, 40];
+
+        let recolored = transform_solid(&tryon, red, "blue leather jacket", 1.0, 1.0)?;
+        assert!((hue(recolored) - 240.0).abs() < 2.0, "{recolored:?}");
+
+        // The leather adjustments still apply, but the red stays red
+        let styled = transform_solid(&tryon, red, "blue leather jacket", 0.0, 1.0)?;
+        assert_ne!(styled, Rgb(red));
+        let shift = (hue(styled) - hue(Rgb(red))).abs();
+        assert!(shift.min(360.0 - shift) < 2.0, "{styled:?}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_skin_inside_the_mask_keeps_its_color() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let model_manager = crate::ModelManagerBuilder::new()
+            .with_models_dir(temp_dir.path().to_path_buf())
+            .build()?;
+        let tryon =
+            VirtualTryOn::new(model_manager)?.with_mask_debug(temp_dir.path().join("mask.png"));
+        let fabric = Rgb([90, 110, 140]);
+        let skin = [Rgb([224, 172, 145]), Rgb([141, 85, 36])];
+        let mut image = RgbImage::from_pixel(24, 24, fabric);
+        for (x, y) in (6..10).flat_map(|x| (6..10).map(move |y| (x, y))) {
+            image.put_pixel(x, y, skin[0]);
+            image.put_pixel(x + 8, y + 8, skin[1]);
+        }
+        // A drawn mask over everything, skin included
+        let source = MaskSource {
+            drawn: Some((
+                GrayImage::from_pixel(24, 24, image::Luma([255])),
+                MaskMode::Polygon,
+            )),
+            cache_key: None,
+        };
+        let transform = |tryon: &VirtualTryOn| -> Result<RgbImage> {
+            let plan = tryon.planner.plan("red shirt")?;
+            let result = tryon.apply_clothing_transformation(
+                &DynamicImage::ImageRgb8(image.clone()),
+                &source,
+                &plan,
+                None,
+                &at_strength(Some(1.0)),
+                &CancelToken::new(),
+            )?;
+            Ok(result.0.to_rgb8())
+        };
+
+        let result = transform(&tryon)?;
+        for (x, y) in (6..10).flat_map(|x| (6..10).map(move |y| (x, y))) {
+            assert_eq!(result.get_pixel(x, y), &skin[0], "({x}, {y})");
+            assert_eq!(result.get_pixel(x + 8, y + 8), &skin[1], "({x}, {y})");
+        }
+        assert_ne!(result.get_pixel(0, 0), &fabric);
+        let overlay = image::open(temp_dir.path().join("mask.png"))?.to_rgb8();
+        let [r, _, b] = overlay.get_pixel(7, 7).0;
+        assert!(b > r, "protected skin shows blue in the mask overlay");
+
+        let unprotected = transform(&tryon.with_skin_protection(None))?;
+        assert_ne!(unprotected.get_pixel(7, 7), &skin[0]);
+        Ok(())
+    }
+
+    /// A request for a "blue shirt" at `strength`, for calling the transformation
+    /// directly
+    fn at_strength(strength: Option<f64>) -> TryOnRequest {
+        TryOnRequest {
+            input_image_path: PathBuf::from("in.png"),
+            clothing_description: "blue shirt".to_string(),
+            negative_prompt: None,
+            output_path: PathBuf::from("out.png"),
+            model_name: None,
+            strength,
+            color_strength: None,
+            style_strength: None,
+            reference_image: None,
+            crop: None,
+            mask: None,
+            transform_plan: None,
+            comparison: None,
+            remote_input: None,
+            seed: None,
+        }
+    }
+
+    #[test]
+    fn test_auto_strength_follows_the_garment_unless_set() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let model_manager = crate::ModelManagerBuilder::new()
+            .with_models_dir(temp_dir.path().to_path_buf())
+            .build()?;
+        let tryon = VirtualTryOn::new(model_manager)?.with_auto_strength(true);
+        let plan = tryon.planner.plan("blue shirt")?;
+        let strengths = |color: [u8; 3], request: &TryOnRequest| -> Result<Strengths> {
+            let image = DynamicImage::ImageRgb8(RgbImage::from_pixel(32, 32, Rgb(color)));
+            let drawn = GrayImage::from_pixel(32, 32, image::Luma([255]));
+            let source = MaskSource {
+                drawn: Some((drawn, MaskMode::Polygon)),
+                cache_key: None,
+            };
+            let (_, _, strengths) = tryon.apply_clothing_transformation(
+                &image,
+                &source,
+                &plan,
+                None,
+                request,
+                &CancelToken::new(),
+            )?;
+            Ok(strengths)
+        };
+
+        let dark = strengths([40, 30, 25], &at_strength(None))?;
+        let vivid = strengths([230, 30, 30], &at_strength(None))?;
+        let suggested = dark.suggested.context("no suggestion")?;
+        assert!(suggested > vivid.suggested.context("no suggestion")?);
+        assert_eq!(
+            (dark.strength, dark.color, dark.style),
+            (suggested, suggested, suggested)
+        );
+        // A strength the request sets wins
+        let fixed = strengths([40, 30, 25], &at_strength(Some(0.4)))?;
+        assert_eq!((fixed.strength, fixed.suggested), (0.4, None));
+        Ok(())
+    }
+
+    /// Descriptions and strengths covered by the golden images in tests/golden
+    const GOLDEN_CASES: [(&str, f64); 10] = [
+        ("red dress", 0.3),
+        ("red dress", 0.8),
+        ("blue shirt", 0.3),
+        ("blue shirt", 0.8),
+        ("black jacket", 0.5),
+        ("black jacket", 1.0),
+        ("silk blouse", 0.5),
+        ("silk blouse", 1.0),
+        ("leather jacket", 0.5),
+        ("(red:1.5) leather jacket", 0.8),
+    ];
+
+    #[test]
+    fn test_transformation_golden_images() -> Result<()> {
+        use crate::test_support::{Tolerance, assert_matches_golden, person_scene};
+
+        let temp_dir = tempdir()?;
+        let model_manager = crate::ModelManagerBuilder::new()
+            .with_models_dir(temp_dir.path().to_path_buf())
+            .build()?;
+        let tryon = VirtualTryOn::new(model_manager)?;
+        let scene = DynamicImage::ImageRgb8(person_scene(48, 64));
+        let golden_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+
+        let mut failures = Vec::new();
+        for (description, strength) in GOLDEN_CASES {
+            let plan = tryon.planner.plan(description)?;
+            let result = tryon
+                .apply_clothing_transformation(
+                    &scene,
+                    &MaskSource::default(),
+                    &plan,
+                    None,
+                    &at_strength(Some(strength)),
+                    &CancelToken::new(),
+                )?
+                .0;
+            let name: String = description
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                .collect();
+            let golden = golden_dir.join(format!("person_{name}_s{strength:.2}.png"));
+            if let Err(e) = assert_matches_golden(&golden, &result.to_rgb8(), &Tolerance::default())
+            {
+                failures.push(format!("{description} at {strength}: {e:#}"));
+            }
+        }
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
+        Ok(())
+    }
+
+    fn validating_tryon(root: &Path, limits: InputLimits) -> Result<VirtualTryOn> {
+        let models_dir = root.join("models");
+        std::fs::create_dir_all(&models_dir)?;
+        std::fs::write(
+            models_dir.join("model_index.json"),
+            r#"{"models": [{"model_id": "test/model", "files": []}]}"#,
+        )?;
+        let model_manager = crate::ModelManagerBuilder::new()
+            .with_models_dir(models_dir)
+            .build()?;
+        Ok(VirtualTryOn::new(model_manager)?.with_input_limits(limits))
+    }
+
+    fn rejection(err: &anyhow::Error) -> Option<&SiError> {
+        err.chain()
+            .find_map(|cause| cause.downcast_ref::<SiError>())
+    }
+
+    #[test]
+    fn test_validate_input_image() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let tryon = validating_tryon(temp_dir.path(), InputLimits::default())?;
+        assert!(
+            tryon
+                .validate_input_image(&temp_dir.path().join("nonexistent.jpg"))
+                .is_err()
+        );
+
+        // The format comes from the header, not the extension
+        let disguised = temp_dir.path().join("photo.jpg");
+        RgbImage::from_pixel(64, 64, Rgb([100, 80, 120]))
+            .save_with_format(&disguised, ImageFormat::Png)?;
+        assert_eq!(tryon.validate_input_image(&disguised)?, ImageFormat::Png);
+
+        let garbage = temp_dir.path().join("notes.png");
+        std::fs::write(&garbage, "not an image")?;
+        let err = tryon.validate_input_image(&garbage).unwrap_err();
+        assert!(err.to_string().contains("Invalid image format"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_output_catches_unwritable_paths() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let root = temp_dir.path();
+        let tryon = validating_tryon(root, InputLimits::default())?;
+
+        // Missing directories are created on save
+        tryon.validate_output(&root.join("new/dir/out.png"))?;
+
+        fs::write(root.join("results"), "")?;
+        let err = tryon
+            .validate_output(&root.join("results/out.png"))
+            .unwrap_err();
+        assert!(err.to_string().contains("results is a file"), "{err:#}");
+        let err = tryon
+            .validate_output(&root.join("results/sub/out.png"))
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("Can't create output directory"),
+            "{err:#}"
+        );
+
+        fs::create_dir(root.join("taken.png"))?;
+        let err = tryon.validate_output(&root.join("taken.png")).unwrap_err();
+        assert!(err.to_string().contains("is a directory"), "{err:#}");
+
+        fs::write(root.join("old.png"), "")?;
+        tryon.validate_output(&root.join("old.png"))?;
+        let tryon = tryon.with_overwrite(false);
+        let err = tryon.validate_output(&root.join("old.png")).unwrap_err();
+        assert!(err.to_string().contains("already exists"), "{err:#}");
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_validate_output_read_only_dir() -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        let temp_dir = tempdir()?;
+        let tryon = validating_tryon(temp_dir.path(), InputLimits::default())?;
+        let locked = temp_dir.path().join("locked");
+        fs::create_dir(&locked)?;
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o555))?;
+        // Root ignores the permission bits, leaving nothing to test
+        let writable = probe_writable(&locked).is_ok();
+        let direct = tryon.validate_output(&locked.join("out.png"));
+        let nested = tryon.validate_output(&locked.join("sub/out.png"));
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755))?;
+        if writable {
+            return Ok(());
+        }
+
+        let err = direct.unwrap_err();
+        assert!(err.to_string().contains("is not writable"), "{err:#}");
+        let err = nested.unwrap_err();
+        assert!(
+            err.to_string().contains("Can't create output directory"),
+            "{err:#}"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_concurrent_probes_do_not_collide() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let dir = temp_dir.path();
+        std::thread::scope(|scope| {
+            let probes: Vec<_> = (0..8)
+                .map(|_| scope.spawn(|| (0..50).try_for_each(|_| probe_writable(dir))))
+                .collect();
+            for probe in probes {
+                probe.join().expect("probe thread")?;
+            }
+            io::Result::Ok(())
+        })?;
+        assert_eq!(fs::read_dir(dir)?.count(), 0, "probe files left behind");
+        Ok(())
+    }
+
+    #[test]
+    fn test_output_dir_is_checked_once() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let root = temp_dir.path();
+        let tryon = validating_tryon(root, InputLimits::default())?;
+        let out = root.join("out");
+        tryon.validate_output(&out.join("a.png"))?;
+        tryon.validate_output(&out.join("b.png"))?;
+        let checked = tryon.writable_dirs.lock().unwrap();
+        assert_eq!(checked.iter().collect::<Vec<_>>(), vec![&out]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_try_on_checks_output_before_reading_input() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let root = temp_dir.path();
+        let tryon = validating_tryon(root, InputLimits::default())?;
+        fs::write(root.join("results"), "")?;
+        let err = tryon
+            .try_on(TryOnRequest {
+                input_image_path: root.join("missing.png"),
+                clothing_description: "red shirt".to_string(),
+                negative_prompt: None,
+                output_path: root.join("results/out.png"),
+                model_name: Some("test/model".to_string()),
+                strength: None,
+                color_strength: None,
+                style_strength: None,
+                reference_image: None,
+                crop: None,
+                mask: None,
+                transform_plan: None,
+                comparison: None,
+                remote_input: None,
+                seed: None,
+            })
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("results is a file"), "{err:#}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_rejects_tiny_image() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let tryon = validating_tryon(temp_dir.path(), InputLimits::default())?;
+        let pixel = temp_dir.path().join("pixel.png");
+        RgbImage::from_pixel(1, 1, Rgb([0, 0, 0])).save(&pixel)?;
+
+        let err = tryon.validate_input_image(&pixel).unwrap_err();
+        assert_eq!(
+            rejection(&err),
+            Some(&SiError::ImageTooSmall {
+                width: 1,
+                height: 1,
+                min_width: 64,
+                min_height: 64,
+            })
+        );
+        assert!(err.to_string().contains("1x1"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_rejects_too_many_pixels() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let limits = InputLimits {
+            max_pixels: 100 * 100,
+            ..Default::default()
+        };
+        let tryon = validating_tryon(temp_dir.path(), limits)?;
+        let wide = temp_dir.path().join("wide.png");
+        RgbImage::from_pixel(200, 64, Rgb([0, 0, 0])).save(&wide)?;
+
+        let err = tryon.validate_input_image(&wide).unwrap_err();
+        assert_eq!(
+            rejection(&err),
+            Some(&SiError::TooManyPixels {
+                width: 200,
+                height: 64,
+                max_pixels: 10_000,
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_rejects_excessive_memory_estimate() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let limits = InputLimits {
+            max_decoded_bytes: 64 * 64 * 12,
+            ..Default::default()
+        };
+        let tryon = validating_tryon(temp_dir.path(), limits)?;
+
+        // 8-bit JPEG fits, while PNG is budgeted for 16-bit channels
+        let jpeg = temp_dir.path().join("photo.jpg");
+        RgbImage::from_pixel(64, 64, Rgb([0, 0, 0])).save(&jpeg)?;
+        assert_eq!(tryon.validate_input_image(&jpeg)?, ImageFormat::Jpeg);
+
+        let png = temp_dir.path().join("photo.png");
+        RgbImage::from_pixel(64, 64, Rgb([0, 0, 0])).save(&png)?;
+        let err = tryon.validate_input_image(&png).unwrap_err();
+        assert_eq!(
+            rejection(&err),
+            Some(&SiError::ImageTooLarge {
+                width: 64,
+                height: 64,
+                estimated_bytes: 64 * 64 * 15,
+                max_bytes: 64 * 64 * 12,
+            })
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_try_on_validates_input() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let tryon = validating_tryon(temp_dir.path(), InputLimits::default())?;
+        let input_path = temp_dir.path().join("pixel.png");
+        RgbImage::from_pixel(8, 8, Rgb([100, 80, 120])).save(&input_path)?;
+        let request = TryOnRequest {
+            input_image_path: input_path,
+            clothing_description: "red shirt".to_string(),
+            negative_prompt: None,
+            output_path: temp_dir.path().join("out.png"),
+            model_name: Some("test/model".to_string()),
+            strength: None,
+            color_strength: None,
+            style_strength: None,
+            reference_image: None,
+            crop: None,
+            mask: None,
+            transform_plan: None,
+            comparison: None,
+            remote_input: None,
+            seed: None,
+        };
+
+        let err = tryon.try_on(request.clone()).await.unwrap_err();
+        assert!(matches!(
+            rejection(&err),
+            Some(SiError::ImageTooSmall { width: 8, .. })
+        ));
+        assert!(!request.output_path.exists());
+
+        // Strengths are checked whichever way the request came in
+        let err = tryon
+            .try_on(TryOnRequest {
+                color_strength: Some(1.5),
+                ..request.clone()
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "color_strength must be 0.0-1.0, got 1.5");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_try_on_stream() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let limits = InputLimits {
+            max_decoded_bytes: 1 << 20,
+            ..InputLimits::default()
+        };
+        let tryon = validating_tryon(temp_dir.path(), limits)?;
+        let request = TryOnRequest {
+            input_image_path: PathBuf::from("-"),
+            clothing_description: "red shirt".to_string(),
+            negative_prompt: None,
+            output_path: PathBuf::from("-"),
+            model_name: Some("test/model".to_string()),
+            strength: None,
+            color_strength: None,
+            style_strength: None,
+            reference_image: None,
+            crop: None,
+            mask: None,
+            transform_plan: None,
+            comparison: None,
+            remote_input: None,
+            seed: None,
+        };
+
+        let mut png = Cursor::new(Vec::new());
+        RgbImage::from_pixel(96, 64, Rgb([100, 80, 120])).write_to(&mut png, ImageFormat::Png)?;
+        let mut output = Vec::new();
+        let result = tryon
+            .try_on_stream(
+                request.clone(),
+                png.get_ref().as_slice(),
+                &mut output,
+                ImageFormat::Jpeg,
+            )
+            .await?;
+        assert_eq!(result.output_path, PathBuf::from("-"));
+        let decoded = image::load_from_memory_with_format(&output, ImageFormat::Jpeg)?;
+        assert_eq!((decoded.width(), decoded.height()), (96, 64));
+
+        let err = tryon
+            .try_on_stream(
+                request.clone(),
+                &b""[..],
+                Vec::<u8>::new(),
+                ImageFormat::Png,
+            )
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("empty"));
+
+        // Anything past the decoded-size budget is refused before it is buffered
+        let huge = std::io::repeat(0).take(2 << 20);
+        let err = tryon
+            .try_on_stream(request, huge, Vec::<u8>::new(), ImageFormat::Png)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("larger than"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_suggest_output_path() {
+        let input_path = PathBuf::from("/path/to/person.jpg");
+        let output_path = VirtualTryOn::suggest_output_path(&input_path, "red silk dress");
+
+        assert!(output_path.to_string_lossy().contains("person"));
+        assert!(output_path.to_string_lossy().contains("red_silk_dress"));
+        assert!(output_path.to_string_lossy().ends_with("_tryon.png"));
+    }
+
+    #[test]
+    fn test_get_recommended_models() {
+        let models = VirtualTryOn::get_recommended_models();
+        assert!(!models.is_empty());
+        assert!(models.contains(&"runwayml/stable-diffusion-v1-5"));
+    }
+
+    #[tokio::test]
+    async fn test_try_on_records_history_and_rerun() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let models_dir = temp_dir.path().join("models");
+        std::fs::create_dir_all(&models_dir)?;
+        std::fs::write(
+            models_dir.join("model_index.json"),
+            r#"{"models": [{"model_id": "test/model", "files": []}]}"#,
+        )?;
+        let model_manager = crate::ModelManagerBuilder::new()
+            .with_models_dir(models_dir)
+            .build()?;
+
+        let input_path = temp_dir.path().join("person.png");
+        RgbImage::from_pixel(64, 64, Rgb([100, 80, 120])).save(&input_path)?;
+
+        let history = History::new(temp_dir.path().join("history.jsonl"));
+        let tryon = VirtualTryOn::new(model_manager)?.with_history(history.clone());
+
+        let request = TryOnRequest {
+            input_image_path: input_path,
+            clothing_description: "blue shirt".to_string(),
+            negative_prompt: None,
+            output_path: temp_dir.path().join("out.png"),
+            model_name: Some("test/model".to_string()),
+            strength: Some(0.7),
+            color_strength: None,
+            style_strength: None,
+            reference_image: None,
+            crop: None,
+            mask: None,
+            transform_plan: None,
+            comparison: None,
+            remote_input: None,
+            seed: None,
+        };
+        tryon.try_on(request).await?;
+
+        let entry = history.get(1)?.expect("try-on should be recorded");
+        assert!(entry.success);
+        assert_eq!(entry.prompt, "blue shirt");
+        assert_eq!(entry.strength, Some(0.7));
+
+        let rerun_output = temp_dir.path().join("rerun.png");
+        let result = tryon
+            .try_on(entry.to_request(Some(rerun_output.clone())))
+            .await?;
+        assert_eq!(result.output_path, rerun_output);
+        assert!(rerun_output.exists());
+        assert_eq!(history.list(None)?.len(), 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_load_model_refuses_pickle_only_weights() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let models_dir = temp_dir.path().join("models");
+        std::fs::create_dir_all(&models_dir)?;
+        let model = |id: &str, file: &str| {
+            let path = models_dir.join(file);
+            std::fs::write(&path, "weights")?;
+            let files = vec![crate::ModelFile::from_cached(&path, file)?];
+            anyhow::Ok(crate::ModelInfo::new(id, files))
+        };
+        let pickled = model("old/checkpoint", "v1-5-pruned.ckpt")?;
+        let safe = model("new/checkpoint", "v1-5-pruned.safetensors")?;
+        let model_manager = crate::ModelManagerBuilder::new()
+            .with_models_dir(models_dir.clone())
+            .build()?;
+        let index = model_manager.model_index();
+        index.add_model(pickled)?;
+        index.add_model(safe)?;
+        let tryon = VirtualTryOn::new(model_manager)?;
+
+        let err = tryon.load_model("old/checkpoint").await.unwrap_err();
+        let expected = SiError::PickleOnly {
+            model_id: "old/checkpoint".to_string(),
+        };
+        assert!(expected.matches(&err), "{err:#}");
+        assert!(format!("{err:#}").contains(".safetensors"));
+        tryon.load_model("new/checkpoint").await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_load_model_needs_the_model_downloaded() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let root = temp_dir.path();
+        let fixture = root.join("fixtures/org/tiny");
+        std::fs::create_dir_all(&fixture)?;
+        std::fs::write(fixture.join("model.safetensors"), "weights")?;
+        let model_manager = || {
+            let source = crate::MockSource::new(root.join("fixtures"), root.join("cache"));
+            crate::ModelManagerBuilder::new()
+                .with_models_dir(root.join("models"))
+                .with_source(Box::new(source))
+                .build()
+        };
+        let not_downloaded = SiError::ModelNotDownloaded {
+            model_id: "org/tiny".to_string(),
+            estimated_size: Some(7),
+        };
+
+        let tryon = VirtualTryOn::new(model_manager()?)?;
+        let err = tryon.load_model("org/tiny").await.unwrap_err();
+        assert!(not_downloaded.matches(&err), "{err:#}");
+        assert!(err.to_string().contains("run `si model download org/tiny`"));
+        assert!(!tryon.is_downloaded("org/tiny")?);
+        assert!(model_manager()?.list_models()?.is_empty());
+
+        let tryon = VirtualTryOn::new(model_manager()?)?.with_download_missing(true);
+        tryon.load_model("org/tiny").await?;
+        assert!(tryon.is_downloaded("org/tiny")?);
+
+        // An entry whose files are gone is as good as none
+        let model = model_manager()?.get_model("org/tiny")?.expect("indexed");
+        std::fs::remove_file(&model.files[0].path)?;
+        let tryon = VirtualTryOn::new(model_manager()?)?;
+        assert!(!tryon.is_downloaded("org/tiny")?);
+        let err = tryon.load_model("org/tiny").await.unwrap_err();
+        assert!(not_downloaded.matches(&err), "{err:#}");
+        Ok(())
     }
-}
 
-pub struct VirtualTryOn {
-    model_manager: ModelManager,
-    current_model: Option<String>,
-}
+    #[tokio::test]
+    async fn test_load_model_refuses_models_that_cant_generate_images() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let models_dir = temp_dir.path().join("models");
+        let model = |id: &str, files: &[(&str, &str)]| {
+            let dir = models_dir.join(id);
+            std::fs::create_dir_all(&dir)?;
+            let files = files
+                .iter()
+                .map(|(name, contents)| {
+                    std::fs::write(dir.join(name), contents)?;
+                    crate::ModelFile::from_cached(&dir.join(name), *name)
+                })
+                .collect::<io::Result<_>>()?;
+            anyhow::Ok(crate::ModelInfo::new(id, files))
+        };
+        let clip = model(
+            "openai/clip",
+            &[
+                ("config.json", r#"{"architectures": ["CLIPModel"]}"#),
+                ("model.safetensors", "weights"),
+            ],
+        )?;
+        let mystery = model("org/mystery", &[("model.safetensors", "weights")])?;
+        let model_manager = || {
+            crate::ModelManagerBuilder::new()
+                .with_models_dir(models_dir.clone())
+                .build()
+        };
+        let index = model_manager()?.model_index();
+        index.add_model(clip)?;
+        index.add_model(mystery)?;
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = events.clone();
+        let tryon = |allow| -> Result<VirtualTryOn> {
+            let recorded = recorded.clone();
+            Ok(VirtualTryOn::new(model_manager()?)?
+                .with_allow_incompatible(allow)
+                .with_events(Arc::new(move |event: &TryOnEvent| {
+                    recorded.lock().unwrap().push(event.clone());
+                })))
+        };
 
-impl VirtualTryOn {
-    pub fn new(model_manager: ModelManager) -> Result<Self> {
-        info!("Initialized Virtual Try-On (MVP mode with image processing)");
+        let err = tryon(false)?.load_model("openai/clip").await.unwrap_err();
+        let Some(SiError::IncompatibleModel { model_id, reason }) = rejection(&err) else {
+            panic!("unexpected error: {err:#}");
+        };
+        assert_eq!(model_id, "openai/clip");
+        assert!(reason.contains("CLIPModel"), "{reason}");
+        assert!(events.lock().unwrap().is_empty());
 
-        Ok(Self {
-            model_manager,
-            current_model: None,
-        })
+        tryon(true)?.load_model("openai/clip").await?;
+        tryon(false)?.load_model("org/mystery").await?;
+        let warned: Vec<_> = events
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|event| match event {
+                TryOnEvent::CompatibilityWarning {
+                    model,
+                    compatibility,
+                } => Some((model.clone(), compatibility.label())),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            warned,
+            [
+                ("openai/clip".to_string(), "no"),
+                ("org/mystery".to_string(), "?")
+            ]
+        );
+        Ok(())
     }
 
-    /// Load a model (for MVP, this just tracks which model the user wants to use)
-    pub async fn load_model(&mut self, model_name: &str) -> Result<()> {
-        info!("Loading model: {} (MVP mode)", model_name);
+    struct CountingSegmenter(Arc<AtomicUsize>);
 
-        // Check if model is already loaded
-        if let Some(ref current) = self.current_model {
-            if current == model_name {
-                debug!("Model {} already loaded", model_name);
-                return Ok(());
-            }
+    impl Segmenter for CountingSegmenter {
+        fn segment(&self, image: &RgbImage) -> Result<GrayImage> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            HeuristicSegmenter.segment(image)
         }
 
-        // Ensure model is downloaded (for future use)
-        let models = self.model_manager.list_models()?;
-        let model_exists = models.iter().any(|m| m.model_id == model_name);
+        fn id(&self) -> Option<&str> {
+            Some("counting-1")
+        }
+    }
 
-        if !model_exists {
-            info!("Model {} not found locally, downloading...", model_name);
-            self.model_manager.download_model(model_name).await?;
+    struct SlowSegmenter(Duration);
+
+    impl Segmenter for SlowSegmenter {
+        fn segment(&self, image: &RgbImage) -> Result<GrayImage> {
+            std::thread::sleep(self.0);
+            HeuristicSegmenter.segment(image)
         }
+    }
 
-        info!("Model {} ready (MVP mode)", model_name);
-        self.current_model = Some(model_name.to_string());
+    #[tokio::test]
+    async fn test_try_on_past_its_timeout_fails_without_output() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let input_path = temp_dir.path().join("person.png");
+        RgbImage::from_pixel(64, 64, Rgb([100, 80, 120])).save(&input_path)?;
+        let request = TryOnRequest {
+            input_image_path: input_path,
+            clothing_description: "red shirt".to_string(),
+            negative_prompt: None,
+            output_path: temp_dir.path().join("out.png"),
+            model_name: Some("test/model".to_string()),
+            strength: None,
+            color_strength: None,
+            style_strength: None,
+            reference_image: None,
+            crop: None,
+            mask: None,
+            transform_plan: None,
+            comparison: Some(ComparisonRequest::new(temp_dir.path().join("compare.png"))),
+            remote_input: None,
+            seed: None,
+        };
+        let tryon = |segmenting: Duration| {
+            validating_tryon(temp_dir.path(), InputLimits::default()).map(|tryon| {
+                tryon
+                    .with_segmenter(Box::new(SlowSegmenter(segmenting)))
+                    .with_timeout(Some(Duration::from_millis(100)))
+            })
+        };
+
+        let err = tryon(Duration::from_millis(400))?
+            .try_on(request.clone())
+            .await
+            .unwrap_err();
+        let expected = SiError::Timeout {
+            limit: Duration::from_millis(100),
+        };
+        assert!(expected.matches(&err), "{err:#}");
+        assert!(!request.output_path.exists());
+        assert!(!temp_dir.path().join("compare.png").exists());
 
+        tryon(Duration::ZERO)?.try_on(request.clone()).await?;
+        assert!(request.output_path.exists());
         Ok(())
     }
 
-    /// Perform virtual clothing try-on using image processing techniques
-    pub async fn try_on(&mut self, request: TryOnRequest) -> Result<TryOnResult> {
-        let start_time = std::time::Instant::now();
+    #[tokio::test]
+    async fn test_mask_cache_skips_segmentation() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let calls = Arc::new(AtomicUsize::new(0));
+        let cache = MaskCache::new(temp_dir.path().join("masks"), "png");
+        let tryon = validating_tryon(temp_dir.path(), InputLimits::default())?
+            .with_segmenter(Box::new(CountingSegmenter(calls.clone())))
+            .with_mask_cache(cache.clone());
+        let input_path = temp_dir.path().join("person.png");
+        RgbImage::from_pixel(64, 64, Rgb([100, 80, 120])).save(&input_path)?;
+        let request = TryOnRequest {
+            input_image_path: input_path.clone(),
+            clothing_description: "red shirt".to_string(),
+            negative_prompt: None,
+            output_path: temp_dir.path().join("out.png"),
+            model_name: Some("test/model".to_string()),
+            strength: None,
+            color_strength: None,
+            style_strength: None,
+            reference_image: None,
+            crop: None,
+            mask: None,
+            transform_plan: None,
+            comparison: None,
+            remote_input: None,
+            seed: None,
+        };
+        let segmented = || calls.load(Ordering::SeqCst);
 
-        info!(
-            "Starting virtual try-on with prompt: {}",
-            request.clothing_description
-        );
+        let first = tryon.try_on(request.clone()).await?;
+        assert_eq!(segmented(), 1);
+        assert_eq!(cache.usage()?.entries, 1);
+        // Another color on the same photo reuses the mask
+        let second = tryon
+            .try_on(TryOnRequest {
+                clothing_description: "blue shirt".to_string(),
+                ..request.clone()
+            })
+            .await?;
+        assert_eq!(segmented(), 1);
+        assert_eq!(second.mask_coverage, first.mask_coverage);
 
-        // Load default model if none specified
-        let model_name = request
-            .model_name
-            .as_deref()
-            .unwrap_or("runwayml/stable-diffusion-v1-5");
+        // Different mask options are different masks
+        tryon
+            .try_on(TryOnRequest {
+                crop: Some(Crop::Region("0,0,50%,100%".parse()?)),
+                ..request.clone()
+            })
+            .await?;
+        assert_eq!(segmented(), 2);
+        let polygon = TryOnRequest {
+            mask: Some(RegionMask {
+                polygon: "0,0 0.5,0.0 0.5,1.0".parse()?,
+                mode: MaskMode::Intersect,
+            }),
+            ..request.clone()
+        };
+        tryon.try_on(polygon.clone()).await?;
+        tryon.try_on(polygon).await?;
+        assert_eq!(segmented(), 3);
 
-        self.load_model(model_name).await?;
+        // So is a changed input at the same path
+        RgbImage::from_pixel(64, 64, Rgb([90, 80, 130])).save(&input_path)?;
+        tryon.try_on(request.clone()).await?;
+        assert_eq!(segmented(), 4);
+        assert_eq!(cache.usage()?.entries, 4);
 
-        // Load input image
-        let input_image = self.load_image(&request.input_image_path)?;
+        // A segmenter without an id is never cached
+        struct Anonymous;
+        impl Segmenter for Anonymous {
+            fn segment(&self, image: &RgbImage) -> Result<GrayImage> {
+                HeuristicSegmenter.segment(image)
+            }
+        }
+        let tryon = tryon.with_segmenter(Box::new(Anonymous));
+        cache.clear()?;
+        tryon.try_on(request).await?;
+        assert_eq!(cache.usage()?.entries, 0);
+        Ok(())
+    }
 
-        // Apply clothing transformations
-        let result_image = self.apply_clothing_transformation(
-            &input_image,
-            &request.clothing_description,
-            request.strength.unwrap_or(0.5),
+    #[tokio::test]
+    async fn test_try_on_ramp_segments_once() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let models_dir = temp_dir.path().join("models");
+        std::fs::create_dir_all(&models_dir)?;
+        std::fs::write(
+            models_dir.join("model_index.json"),
+            r#"{"models": [{"model_id": "test/model", "files": []}]}"#,
         )?;
+        let model_manager = crate::ModelManagerBuilder::new()
+            .with_models_dir(models_dir)
+            .build()?;
 
-        // Save result
-        self.save_image(&result_image, &request.output_path)?;
+        let input_path = temp_dir.path().join("person.png");
+        RgbImage::from_pixel(64, 64, Rgb([100, 80, 120])).save(&input_path)?;
 
-        let processing_time = start_time.elapsed().as_millis() as u64;
+        let calls = Arc::new(AtomicUsize::new(0));
+        let history = History::new(temp_dir.path().join("history.jsonl"));
+        let tryon = VirtualTryOn::new(model_manager)?
+            .with_segmenter(Box::new(CountingSegmenter(calls.clone())))
+            .with_history(history.clone());
 
-        info!("Virtual try-on completed in {}ms", processing_time);
+        let request = TryOnRequest {
+            input_image_path: input_path,
+            clothing_description: "red dress".to_string(),
+            negative_prompt: None,
+            output_path: temp_dir.path().join("out.png"),
+            model_name: Some("test/model".to_string()),
+            strength: None,
+            color_strength: None,
+            style_strength: None,
+            reference_image: None,
+            crop: None,
+            mask: None,
+            transform_plan: None,
+            comparison: None,
+            remote_input: None,
+            seed: None,
+        };
+        let results = tryon.try_on_ramp(&request, &[0.2, 0.4, 0.6, 0.8]).await?;
 
-        Ok(TryOnResult {
-            output_path: request.output_path,
-            processing_time_ms: processing_time,
-            model_used: model_name.to_string(),
-        })
-    }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(results.len(), 4);
+        let paths: HashSet<_> = results.iter().map(|r| r.output_path.clone()).collect();
+        assert_eq!(paths.len(), 4);
+        assert!(results[1].output_path.ends_with("out_s0.40.png"));
+        assert!(results.iter().all(|r| r.output_path.exists()));
+
+        let entries = history.entries()?;
+        assert_eq!(entries.len(), 4);
+        assert!(entries[0].seed.is_some() && entries[0].group.is_some());
+        assert!(
+            entries
+                .iter()
+                .all(|e| e.seed == entries[0].seed && e.group == entries[0].group)
+        );
+        // Each pass carries its own strength and the shared seed
+        let embedded = crate::metadata::read(&results[1].output_path).unwrap();
+        assert_eq!(embedded.seed, entries[0].seed);
+        assert_eq!(embedded.strength, Some(0.4));
+        assert_eq!(embedded.prompt, "red dress");
 
-    fn load_image(&self, path: &Path) -> Result<DynamicImage> {
-        debug!("Loading image from: {}", path.display());
-        image::open(path).with_context(|| format!("Failed to load image from {}", path.display()))
+        assert!(tryon.try_on_ramp(&request, &[]).await.is_err());
+        assert!(tryon.try_on_ramp(&request, &[1.5]).await.is_err());
+        Ok(())
     }
 
-    fn save_image(&self, img: &DynamicImage, path: &Path) -> Result<()> {
-        debug!("Saving image to: {}", path.display());
+    #[tokio::test]
+    async fn test_try_on_variations_share_one_setup_and_group() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let models_dir = temp_dir.path().join("models");
+        std::fs::create_dir_all(&models_dir)?;
+        std::fs::write(
+            models_dir.join("model_index.json"),
+            r#"{"models": [{"model_id": "test/model", "files": []}]}"#,
+        )?;
+        let model_manager = crate::ModelManagerBuilder::new()
+            .with_models_dir(models_dir)
+            .build()?;
+        let input_path = temp_dir.path().join("person.png");
+        RgbImage::from_fn(64, 64, |x, y| Rgb([150 + (x as u8), 80, 60 + (y as u8)]))
+            .save(&input_path)?;
 
-        // Create parent directory if it doesn't exist
-        if let Some(parent) = path.parent() {
-            std::fs::create_dir_all(parent)
-                .with_context(|| format!("Failed to create directory {}", parent.display()))?;
-        }
+        let calls = Arc::new(AtomicUsize::new(0));
+        let history = History::new(temp_dir.path().join("history.jsonl"));
+        let tryon = VirtualTryOn::new(model_manager)?
+            .with_segmenter(Box::new(CountingSegmenter(calls.clone())))
+            .with_history(history.clone());
+        let request = TryOnRequest {
+            input_image_path: input_path,
+            output_path: temp_dir.path().join("out.png"),
+            model_name: Some("test/model".to_string()),
+            ..at_strength(None)
+        };
+        let results = tryon.try_on_variations(&request, 4).await?;
+
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(results.len(), 4);
+        assert!(results[0].output_path.ends_with("out-1.png"));
+        assert!(results[3].output_path.ends_with("out-4.png"));
+        let pixels: HashSet<_> = results
+            .iter()
+            .map(|r| Ok(image::open(&r.output_path)?.into_bytes()))
+            .collect::<Result<_>>()?;
+        assert_eq!(pixels.len(), 4, "every variation comes out different");
 
-        img.save(path)
-            .with_context(|| format!("Failed to save image to {}", path.display()))
+        let seeds: Vec<_> = results.iter().filter_map(|r| r.seed).collect();
+        assert_eq!(seeds, (0..4).map(|i| seeds[0] + i).collect::<Vec<_>>());
+        let group = results[0].group.clone().context("no group")?;
+        assert!(results.iter().all(|r| r.group.as_ref() == Some(&group)));
+        for (result, seed) in results.iter().zip(&seeds) {
+            let embedded = crate::metadata::read(&result.output_path).context("no metadata")?;
+            assert_eq!(embedded.seed, Some(*seed));
+            assert!(
+                embedded
+                    .extra
+                    .contains(&("Group".to_string(), group.clone()))
+            );
+        }
+        let entries = history.entries()?;
+        assert_eq!(entries.len(), 4);
+        assert!(entries.iter().all(|e| e.group.as_ref() == Some(&group)));
+        Ok(())
     }
 
-    fn apply_clothing_transformation(
-        &self,
-        image: &DynamicImage,
-        clothing_description: &str,
-        strength: f64,
-    ) -> Result<DynamicImage> {
-        debug!("Applying clothing transformation: {}", clothing_description);
+    #[test]
+    fn test_variation_requests_stay_within_strength_bounds() -> Result<()> {
+        let strengths = |request: &TryOnRequest, count| -> Result<Vec<f64>> {
+            Ok(variation_requests(request, count, 7)?
+                .iter()
+                .map(|(_, r)| r.effective_strength())
+                .collect())
+        };
+        assert_eq!(strengths(&at_strength(None), 3)?, [0.5, 0.54, 0.46]);
+        // At the top of the range every step goes down
+        assert_eq!(strengths(&at_strength(Some(1.0)), 3)?, [1.0, 0.96, 0.92]);
 
-        // Convert to RGB for processing
-        let rgb_image = image.to_rgb8();
+        let request = TryOnRequest {
+            style_strength: Some(0.2),
+            ..at_strength(Some(0.9))
+        };
+        let variations = variation_requests(&request, 2, u64::MAX)?;
+        assert_eq!(variations[0].0, u64::MAX);
+        assert_eq!(variations[1].0, 0);
+        assert_eq!(variations[1].1.style_strength, Some(0.24));
+        assert!(variations[1].1.output_path.ends_with("out-2.png"));
 
-        // Detect clothing regions (simplified approach for MVP)
-        let clothing_mask = self.detect_clothing_regions(&rgb_image)?;
+        assert!(variation_requests(&request, 0, 7).is_err());
+        assert!(variation_requests(&request, MAX_VARIATIONS + 1, 7).is_err());
+        Ok(())
+    }
 
-        // Extract clothing attributes from description
-        let color_transform = self.extract_color_transform(clothing_description)?;
-        let style_adjustments = self.extract_style_adjustments(clothing_description);
+    #[tokio::test]
+    async fn test_try_on_keeps_the_icc_profile() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let models_dir = temp_dir.path().join("models");
+        std::fs::create_dir_all(&models_dir)?;
+        std::fs::write(
+            models_dir.join("model_index.json"),
+            r#"{"models": [{"model_id": "test/model", "files": []}]}"#,
+        )?;
+        let tryon = |strip| -> Result<VirtualTryOn> {
+            let model_manager = crate::ModelManagerBuilder::new()
+                .with_models_dir(models_dir.clone())
+                .build()?;
+            Ok(VirtualTryOn::new(model_manager)?.with_strip_icc(strip))
+        };
 
-        // Apply transformations
-        let transformed_image = self.apply_color_and_style_transformation(
-            &rgb_image,
-            &clothing_mask,
-            &color_transform,
-            &style_adjustments,
-            strength as f32,
+        // Stands in for a Display P3 profile; nothing looks inside it
+        let profile: Vec<u8> = (0..600).map(|i| (i * 7 % 256) as u8).collect();
+        let input_path = temp_dir.path().join("p3.png");
+        imageio::save(
+            &DynamicImage::ImageRgb8(RgbImage::from_pixel(64, 64, Rgb([100, 80, 120]))),
+            &input_path,
+            &imageio::OutputOptions {
+                icc_profile: Some(profile.clone()),
+                ..Default::default()
+            },
         )?;
+        let request = |output: &str| TryOnRequest {
+            input_image_path: input_path.clone(),
+            clothing_description: "red shirt".to_string(),
+            negative_prompt: None,
+            output_path: temp_dir.path().join(output),
+            model_name: Some("test/model".to_string()),
+            strength: Some(0.0),
+            color_strength: None,
+            style_strength: None,
+            reference_image: None,
+            crop: None,
+            mask: None,
+            transform_plan: None,
+            comparison: None,
+            remote_input: None,
+            seed: None,
+        };
 
-        Ok(DynamicImage::ImageRgb8(transformed_image))
-    }
+        let result = tryon(false)?.try_on(request("out.png")).await?;
+        assert!(!result.icc_dropped);
+        assert_eq!(
+            imageio::load(&result.output_path)?.icc_profile,
+            Some(profile)
+        );
 
-    fn detect_clothing_regions(
-        &self,
-        image: &RgbImage,
-    ) -> Result<ImageBuffer<image::Luma<u8>, Vec<u8>>> {
-        // Simplified clothing detection for MVP
-        // In a real implementation, this would use ML models for segmentation
+        let result = tryon(false)?.try_on(request("out.bmp")).await?;
+        assert!(result.icc_dropped);
 
-        let (width, height) = image.dimensions();
-        let mut mask = image::ImageBuffer::new(width, height);
+        let result = tryon(true)?.try_on(request("stripped.png")).await?;
+        assert!(!result.icc_dropped);
+        assert_eq!(imageio::load(&result.output_path)?.icc_profile, None);
+        Ok(())
+    }
 
-        // Simple heuristic: assume clothing is in the middle region of the image
-        // and has certain color characteristics
-        for (x, y, pixel) in image.enumerate_pixels() {
-            let is_clothing_region = self.is_likely_clothing_pixel(pixel, x, y, width, height);
-            let mask_value = if is_clothing_region { 255 } else { 0 };
-            mask.put_pixel(x, y, image::Luma([mask_value]));
-        }
+    #[tokio::test]
+    async fn test_comparison_is_written_only_when_requested() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let root = temp_dir.path();
+        let tryon = validating_tryon(root, InputLimits::default())?;
+        let input_path = root.join("person.png");
+        RgbImage::from_pixel(96, 64, Rgb([100, 80, 120])).save(&input_path)?;
+        let request = TryOnRequest {
+            input_image_path: input_path,
+            clothing_description: "red shirt".to_string(),
+            negative_prompt: None,
+            output_path: root.join("out.png"),
+            model_name: Some("test/model".to_string()),
+            strength: Some(0.5),
+            color_strength: None,
+            style_strength: None,
+            reference_image: None,
+            crop: None,
+            mask: None,
+            transform_plan: None,
+            comparison: None,
+            remote_input: None,
+            seed: None,
+        };
 
-        debug!("Generated clothing mask");
-        Ok(mask)
+        let result = tryon.try_on(request.clone()).await?;
+        assert_eq!(result.comparison_path, None);
+        assert_eq!(std::fs::read_dir(root)?.count(), 3);
+
+        let comparison_path = root.join("compare.png");
+        let result = tryon
+            .try_on(TryOnRequest {
+                output_path: root.join("out2.png"),
+                comparison: Some(
+                    ComparisonRequest::new(&comparison_path)
+                        .with_layout(ComparisonLayout::Vertical),
+                ),
+                ..request
+            })
+            .await?;
+        assert!(result.output_path.exists());
+        assert_eq!(result.comparison_path.as_ref(), Some(&comparison_path));
+        let comparison = image::open(&comparison_path)?;
+        assert_eq!(
+            (comparison.width(), comparison.height()),
+            (96, 2 * 64 + compose::DIVIDER_WIDTH)
+        );
+        Ok(())
     }
 
-    fn is_likely_clothing_pixel(
-        &self,
-        pixel: &Rgb<u8>,
-        x: u32,
-        y: u32,
-        width: u32,
-        height: u32,
-    ) -> bool {
-        // Simple heuristics for detecting clothing regions in MVP
+    #[tokio::test]
+    async fn test_cancelled_try_on_writes_no_output() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let models_dir = temp_dir.path().join("models");
+        std::fs::create_dir_all(&models_dir)?;
+        std::fs::write(
+            models_dir.join("model_index.json"),
+            r#"{"models": [{"model_id": "test/model", "files": []}]}"#,
+        )?;
+        let model_manager = crate::ModelManagerBuilder::new()
+            .with_models_dir(models_dir)
+            .build()?;
 
-        // Focus on middle region of image (typical clothing area)
-        let x_ratio = x as f32 / width as f32;
-        let y_ratio = y as f32 / height as f32;
+        let input_path = temp_dir.path().join("person.png");
+        RgbImage::from_pixel(64, 64, Rgb([100, 80, 120])).save(&input_path)?;
 
-        let in_clothing_region = x_ratio > 0.2 && x_ratio < 0.8 && y_ratio > 0.3 && y_ratio < 0.9;
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        let tryon = VirtualTryOn::new(model_manager)?.with_cancel_token(cancel);
 
-        if !in_clothing_region {
-            return false;
-        }
+        let output_path = temp_dir.path().join("out.png");
+        let err = tryon
+            .try_on(TryOnRequest {
+                input_image_path: input_path,
+                clothing_description: "blue shirt".to_string(),
+                negative_prompt: None,
+                output_path: output_path.clone(),
+                model_name: Some("test/model".to_string()),
+                strength: None,
+                color_strength: None,
+                style_strength: None,
+                reference_image: None,
+                crop: None,
+                mask: None,
+                transform_plan: None,
+                comparison: None,
+                remote_input: None,
+                seed: None,
+            })
+            .await
+            .unwrap_err();
 
-        // Check if pixel looks like fabric (not skin or background)
-        let [r, g, b] = pixel.0;
-        let brightness = (r as f32 + g as f32 + b as f32) / 3.0;
-
-        // Avoid skin tones (simplified)
-        let is_skin_tone = r > 150
-            && g > 100
-            && b > 80
-            && (r as i32 - g as i32).abs() < 50
-            && (r as i32 - b as i32) < 80;
-
-        // Avoid very bright or very dark regions (likely background)
-        let reasonable_brightness = brightness > 30.0 && brightness < 240.0;
-
-        !is_skin_tone && reasonable_brightness
-    }
-
-    fn extract_color_transform(&self, description: &str) -> Result<ColorTransform> {
-        let desc_lower = description.to_lowercase();
-
-        // Extract color information from description
-        let (hue_shift, saturation_mult, lightness_mult) = if desc_lower.contains("red") {
-            (0.0, 1.3, 1.0) // Enhance red
-        } else if desc_lower.contains("blue") {
-            (240.0, 1.2, 0.95) // Shift towards blue
-        } else if desc_lower.contains("green") {
-            (120.0, 1.2, 1.0) // Shift towards green
-        } else if desc_lower.contains("yellow") {
-            (60.0, 1.4, 1.1) // Shift towards yellow, brighten
-        } else if desc_lower.contains("purple") || desc_lower.contains("violet") {
-            (280.0, 1.3, 0.9) // Shift towards purple
-        } else if desc_lower.contains("orange") {
-            (30.0, 1.3, 1.05) // Shift towards orange
-        } else if desc_lower.contains("pink") {
-            (320.0, 1.2, 1.1) // Shift towards pink, brighten
-        } else if desc_lower.contains("black") {
-            (0.0, 0.8, 0.4) // Darken significantly
-        } else if desc_lower.contains("white") {
-            (0.0, 0.5, 1.6) // Desaturate and brighten
-        } else if desc_lower.contains("gray") || desc_lower.contains("grey") {
-            (0.0, 0.3, 0.8) // Desaturate and slightly darken
-        } else {
-            (0.0, 1.0, 1.0) // No change
-        };
-
-        Ok(ColorTransform::new(
-            hue_shift,
-            saturation_mult,
-            lightness_mult,
-        ))
-    }
-
-    fn extract_style_adjustments(&self, description: &str) -> (f32, f32) {
-        let desc_lower = description.to_lowercase();
-
-        // (contrast_mult, brightness_offset)
-        if desc_lower.contains("silk") || desc_lower.contains("satin") {
-            (1.15, 0.05) // Higher contrast, slight brightness boost
-        } else if desc_lower.contains("leather") {
-            (1.25, -0.1) // High contrast, darker
-        } else if desc_lower.contains("denim") {
-            (1.1, -0.05) // Slight contrast boost, slightly darker
-        } else if desc_lower.contains("cotton") {
-            (1.05, 0.02) // Subtle adjustments
-        } else if desc_lower.contains("velvet") {
-            (1.2, -0.08) // Higher contrast, darker
-        } else if desc_lower.contains("linen") {
-            (0.95, 0.08) // Lower contrast, brighter
-        } else {
-            (1.0, 0.0) // No change
-        }
+        assert!(crate::SiError::Cancelled.matches(&err));
+        assert!(!output_path.exists());
+        Ok(())
     }
 
-    fn apply_color_and_style_transformation(
-        &self,
-        image: &RgbImage,
-        mask: &ImageBuffer<image::Luma<u8>, Vec<u8>>,
-        color_transform: &ColorTransform,
-        style_adjustments: &(f32, f32),
-        strength: f32,
-    ) -> Result<RgbImage> {
-        let (_width, _height) = image.dimensions();
-        let mut result = image.clone();
-        let (contrast_mult, brightness_offset) = *style_adjustments;
+    #[tokio::test]
+    async fn test_crop_is_applied_and_recorded() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let tryon = validating_tryon(temp_dir.path(), InputLimits::default())?;
+        let input_path = temp_dir.path().join("person.png");
+        let input = RgbImage::from_pixel(128, 96, Rgb([100, 80, 120]));
+        input.save(&input_path)?;
+        let request = TryOnRequest {
+            input_image_path: input_path,
+            clothing_description: "red shirt".to_string(),
+            negative_prompt: None,
+            output_path: temp_dir.path().join("out.png"),
+            model_name: Some("test/model".to_string()),
+            strength: None,
+            color_strength: None,
+            style_strength: None,
+            reference_image: None,
+            crop: Some(Crop::Region("0,0,50%,100%".parse()?)),
+            mask: None,
+            transform_plan: None,
+            comparison: None,
+            remote_input: None,
+            seed: None,
+        };
 
-        for (x, y, pixel) in image.enumerate_pixels() {
-            let mask_pixel = mask.get_pixel(x, y);
-            let mask_strength = (mask_pixel.0[0] as f32 / 255.0) * strength;
+        let result = tryon.try_on(request.clone()).await?;
+        let region = Region {
+            x: 0,
+            y: 0,
+            width: 64,
+            height: 96,
+        };
+        assert_eq!(result.crop, Some(region));
+        assert_eq!(image::image_dimensions(&result.output_path)?, (64, 96));
 
-            if mask_strength > 0.1 {
-                // Transform this pixel
-                let transformed_pixel = self.transform_pixel(
-                    pixel,
-                    color_transform,
-                    contrast_mult,
-                    brightness_offset,
-                    mask_strength,
-                )?;
-                result.put_pixel(x, y, transformed_pixel);
-            }
-        }
+        let bbox = HeuristicSegmenter.bounding_box(&input)?.unwrap();
+        let result = tryon
+            .try_on(TryOnRequest {
+                crop: Some(Crop::AutoCenter { margin: 0.0 }),
+                ..request.clone()
+            })
+            .await?;
+        assert_eq!(result.crop, Some(bbox));
+        assert_eq!(
+            image::image_dimensions(&result.output_path)?,
+            (bbox.width, bbox.height)
+        );
 
-        debug!("Applied color and style transformation");
-        Ok(result)
+        let err = tryon
+            .try_on(TryOnRequest {
+                crop: Some(Crop::Region("100,0,50,50".parse()?)),
+                ..request
+            })
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("extends past the 128x96 image"));
+        Ok(())
     }
 
-    fn transform_pixel(
-        &self,
-        pixel: &Rgb<u8>,
-        color_transform: &ColorTransform,
-        contrast_mult: f32,
-        brightness_offset: f32,
-        strength: f32,
-    ) -> Result<Rgb<u8>> {
-        let [r, g, b] = pixel.0;
+    #[tokio::test]
+    async fn test_polygon_limits_the_transformed_region() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let tryon = validating_tryon(temp_dir.path(), InputLimits::default())?;
+        let input_path = temp_dir.path().join("person.png");
+        let input = RgbImage::from_pixel(128, 96, Rgb([100, 80, 120]));
+        input.save(&input_path)?;
+        let output_path = temp_dir.path().join("out.png");
+        tryon
+            .try_on(TryOnRequest {
+                input_image_path: input_path,
+                clothing_description: "red shirt".to_string(),
+                negative_prompt: None,
+                output_path: output_path.clone(),
+                model_name: Some("test/model".to_string()),
+                strength: Some(1.0),
+                color_strength: None,
+                style_strength: None,
+                reference_image: None,
+                crop: None,
+                mask: Some(RegionMask {
+                    polygon: "0,0 0.5,0.0 0.5,1.0 0,96".parse()?,
+                    mode: MaskMode::Polygon,
+                }),
+                transform_plan: None,
+                comparison: None,
+                remote_input: None,
+                seed: None,
+            })
+            .await?;
 
-        // Convert to HSL for color manipulation
-        let rgb = Srgb::new(r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0);
-        let hsl = Hsl::from_color(rgb);
+        let output = image::open(&output_path)?.to_rgb8();
+        assert_ne!(output.get_pixel(10, 48), input.get_pixel(10, 48));
+        for (x, y, pixel) in output.enumerate_pixels().filter(|(x, _, _)| *x >= 64) {
+            assert_eq!(pixel, input.get_pixel(x, y), "changed at {x},{y}");
+        }
+        Ok(())
+    }
 
-        // Apply color transformations
-        let new_hue = if color_transform.hue_shift != 0.0 {
-            (hsl.hue.into_positive_degrees() + color_transform.hue_shift * strength) % 360.0
-        } else {
-            hsl.hue.into_positive_degrees()
-        };
+    #[tokio::test]
+    async fn test_try_on_reports_stages_and_rows() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = events.clone();
+        let tryon = validating_tryon(temp_dir.path(), InputLimits::default())?.with_events(
+            Arc::new(move |event: &TryOnEvent| {
+                sink.lock().unwrap().push(event.clone());
+            }),
+        );
+        let input_path = temp_dir.path().join("person.png");
+        RgbImage::from_pixel(64, 200, Rgb([100, 80, 120])).save(&input_path)?;
 
-        let new_saturation = (hsl.saturation
-            * (1.0 + (color_transform.saturation_mult - 1.0) * strength))
-            .clamp(0.0, 1.0);
+        tryon
+            .try_on(TryOnRequest {
+                input_image_path: input_path,
+                clothing_description: "red shirt".to_string(),
+                negative_prompt: None,
+                output_path: temp_dir.path().join("out.png"),
+                model_name: Some("test/model".to_string()),
+                strength: None,
+                color_strength: None,
+                style_strength: None,
+                reference_image: None,
+                crop: None,
+                mask: None,
+                transform_plan: None,
+                comparison: None,
+                remote_input: None,
+                seed: None,
+            })
+            .await?;
 
-        let new_lightness = (hsl.lightness
-            * (1.0 + (color_transform.lightness_mult - 1.0) * strength))
-            .clamp(0.0, 1.0);
+        let events = events.lock().unwrap();
+        let stages: Vec<_> = events
+            .iter()
+            .filter_map(|e| match e {
+                TryOnEvent::Stage { stage } => Some(*stage),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            stages,
+            [
+                TryOnStage::LoadingModel,
+                TryOnStage::Decoding,
+                TryOnStage::Segmenting,
+                TryOnStage::Transforming,
+                TryOnStage::Encoding,
+            ]
+        );
+        let rows: Vec<_> = events
+            .iter()
+            .filter_map(|e| match e {
+                TryOnEvent::Rows { done, total } => Some((*done, *total)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            rows,
+            [(0, 200), (64, 200), (128, 200), (192, 200), (200, 200)]
+        );
+        Ok(())
+    }
 
-        // Create new HSL color
-        let new_hsl = Hsl::new(new_hue, new_saturation, new_lightness);
-        let new_rgb = Srgb::from_color(new_hsl);
+    #[tokio::test]
+    async fn test_mask_coverage_outside_bounds() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = events.clone();
+        let tryon = validating_tryon(temp_dir.path(), InputLimits::default())?.with_events(
+            Arc::new(move |event: &TryOnEvent| {
+                sink.lock().unwrap().push(event.clone());
+            }),
+        );
+        let warnings = || -> Vec<CoverageWarning> {
+            events
+                .lock()
+                .unwrap()
+                .drain(..)
+                .filter_map(|e| match e {
+                    TryOnEvent::MaskWarning { warning, .. } => Some(warning),
+                    _ => None,
+                })
+                .collect()
+        };
+        // Skin tones only, so nothing is detected as clothing
+        let backlit = temp_dir.path().join("backlit.png");
+        RgbImage::from_pixel(100, 100, Rgb([200, 170, 150])).save(&backlit)?;
+        let request = TryOnRequest {
+            input_image_path: backlit,
+            clothing_description: "red shirt".to_string(),
+            negative_prompt: None,
+            output_path: temp_dir.path().join("low.png"),
+            model_name: Some("test/model".to_string()),
+            strength: None,
+            color_strength: None,
+            style_strength: None,
+            reference_image: None,
+            crop: None,
+            mask: None,
+            transform_plan: None,
+            comparison: None,
+            remote_input: None,
+            seed: None,
+        };
 
-        // Apply contrast and brightness adjustments
-        let final_r = ((new_rgb.red * contrast_mult + brightness_offset) * strength
-            + (r as f32 / 255.0) * (1.0 - strength))
-            .clamp(0.0, 1.0);
-        let final_g = ((new_rgb.green * contrast_mult + brightness_offset) * strength
-            + (g as f32 / 255.0) * (1.0 - strength))
-            .clamp(0.0, 1.0);
-        let final_b = ((new_rgb.blue * contrast_mult + brightness_offset) * strength
-            + (b as f32 / 255.0) * (1.0 - strength))
-            .clamp(0.0, 1.0);
+        let result = tryon.try_on(request.clone()).await?;
+        assert_eq!(result.mask_coverage, 0.0);
+        assert!(matches!(warnings()[..], [CoverageWarning::Low { .. }]));
 
-        Ok(Rgb([
-            (final_r * 255.0) as u8,
-            (final_g * 255.0) as u8,
-            (final_b * 255.0) as u8,
-        ]))
-    }
+        // A polygon around the whole image covers everything
+        let purple = temp_dir.path().join("purple.png");
+        RgbImage::from_pixel(100, 100, Rgb([100, 80, 120])).save(&purple)?;
+        let everything = TryOnRequest {
+            input_image_path: purple,
+            output_path: temp_dir.path().join("high.png"),
+            mask: Some(RegionMask {
+                polygon: "0,0 100,0 100,100 0,100".parse()?,
+                mode: MaskMode::Polygon,
+            }),
+            ..request.clone()
+        };
+        let result = tryon.try_on(everything.clone()).await?;
+        assert_eq!(result.mask_coverage, 1.0);
+        assert!(matches!(warnings()[..], [CoverageWarning::High { .. }]));
 
-    /// Get recommended models for virtual try-on
-    pub fn get_recommended_models() -> Vec<&'static str> {
-        vec![
-            "runwayml/stable-diffusion-v1-5",
-            "stabilityai/stable-diffusion-2-1",
-            "stabilityai/stable-diffusion-xl-base-1.0",
-        ]
-    }
+        // Within custom bounds neither warns
+        let tryon = tryon.with_coverage_bounds(CoverageBounds { min: 0.0, max: 1.0 });
+        tryon.try_on(everything).await?;
+        assert!(warnings().is_empty());
 
-    /// Get current device info for debugging
-    pub fn device_info(&self) -> String {
-        "CPU (Image Processing Mode)".to_string()
+        // Strict checking fails before anything is written
+        let tryon = tryon
+            .with_coverage_bounds(CoverageBounds::default())
+            .with_strict_mask(true);
+        let strict = TryOnRequest {
+            output_path: temp_dir.path().join("strict.png"),
+            ..request
+        };
+        let err = tryon.try_on(strict.clone()).await.unwrap_err();
+        assert!(err.to_string().contains("covers only 0.0%"), "{err:#}");
+        assert!(!strict.output_path.exists());
+        Ok(())
     }
-}
 
-// Helper functions for the CLI integration
-impl VirtualTryOn {
-    pub fn validate_input_image(path: &Path) -> Result<()> {
-        if !path.exists() {
-            return Err(anyhow::anyhow!(
-                "Input image does not exist: {}",
-                path.display()
-            ));
+    #[tokio::test]
+    async fn test_custom_planner_and_supplied_plan() -> Result<()> {
+        /// Darkens whatever it is asked for, remembering the descriptions
+        #[derive(Default)]
+        struct Darkening {
+            descriptions: std::sync::Mutex<Vec<String>>,
         }
-
-        // Check if it's a valid image format
-        match image::open(path) {
-            Ok(_) => Ok(()),
-            Err(e) => Err(anyhow::anyhow!("Invalid image format: {}", e)),
+        impl TransformPlanner for Darkening {
+            fn plan(&self, description: &str) -> Result<TransformPlan> {
+                self.descriptions
+                    .lock()
+                    .unwrap()
+                    .push(description.to_string());
+                Ok(TransformPlan {
+                    color: ColorTransform::new(0.0, 1.0, 0.0),
+                    ..TransformPlan::default()
+                })
+            }
         }
-    }
-
-    pub fn suggest_output_path(input_path: &Path, clothing_description: &str) -> PathBuf {
-        let input_stem = input_path
-            .file_stem()
-            .and_then(|s| s.to_str())
-            .unwrap_or("output");
 
-        let safe_description = clothing_description
-            .chars()
-            .filter(|c| c.is_alphanumeric() || *c == ' ')
-            .collect::<String>()
-            .replace(' ', "_")
-            .to_lowercase();
+        let temp_dir = tempdir()?;
+        let tryon = validating_tryon(temp_dir.path(), InputLimits::default())?;
+        let planner = Arc::new(Darkening::default());
+        struct Shared(Arc<Darkening>);
+        impl TransformPlanner for Shared {
+            fn plan(&self, description: &str) -> Result<TransformPlan> {
+                self.0.plan(description)
+            }
+        }
+        let tryon = tryon.with_planner(Box::new(Shared(planner.clone())));
 
-        let output_name = format!("{}_{}_tryon.png", input_stem, safe_description);
+        let input_path = temp_dir.path().join("person.png");
+        RgbImage::from_pixel(64, 64, Rgb([100, 80, 120])).save(&input_path)?;
+        let request = TryOnRequest {
+            input_image_path: input_path,
+            clothing_description: "my app's own garment notation".to_string(),
+            negative_prompt: None,
+            output_path: temp_dir.path().join("out.png"),
+            model_name: Some("test/model".to_string()),
+            strength: Some(1.0),
+            color_strength: None,
+            style_strength: None,
+            reference_image: None,
+            crop: None,
+            mask: None,
+            transform_plan: None,
+            comparison: None,
+            remote_input: None,
+            seed: None,
+        };
+        let result = tryon.try_on(request.clone()).await?;
+        assert_eq!(
+            *planner.descriptions.lock().unwrap(),
+            ["my app's own garment notation"]
+        );
+        let output = image::open(&result.output_path)?.to_rgb8();
+        assert_eq!(output.get_pixel(32, 32).0, [0, 0, 0]);
 
-        input_path
-            .parent()
-            .map(|p| p.join(output_name))
-            .unwrap_or_else(|| PathBuf::from(output_name))
+        // A plan in the request is used as is: the description isn't parsed,
+        // so its unbalanced group is no error, and the planner isn't asked
+        let result = tryon
+            .try_on(TryOnRequest {
+                clothing_description: "(unbalanced".to_string(),
+                transform_plan: Some(TransformPlan {
+                    color: ColorTransform::new(0.0, 1.0, 1.5),
+                    ..TransformPlan::default()
+                }),
+                ..request
+            })
+            .await?;
+        assert_eq!(planner.descriptions.lock().unwrap().len(), 1);
+        let output = image::open(&result.output_path)?.to_rgb8();
+        let [r, g, b] = output.get_pixel(32, 32).0;
+        assert!(
+            r > 100 && g > 80 && b > 120,
+            "expected a lighter garment, got {r},{g},{b}"
+        );
+        Ok(())
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use tempfile::tempdir;
 
-    #[test]
-    fn test_extract_color_transform() {
-        let temp_dir = tempdir().unwrap();
+    #[tokio::test]
+    async fn test_reference_image_overrides_description_color() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let models_dir = temp_dir.path().join("models");
+        std::fs::create_dir_all(&models_dir)?;
+        std::fs::write(
+            models_dir.join("model_index.json"),
+            r#"{"models": [{"model_id": "test/model", "files": []}]}"#,
+        )?;
         let model_manager = crate::ModelManagerBuilder::new()
-            .with_models_dir(temp_dir.path().to_path_buf())
-            .build()
-            .unwrap();
+            .with_models_dir(models_dir)
+            .build()?;
+
+        let input_path = temp_dir.path().join("person.png");
+        RgbImage::from_pixel(64, 64, Rgb([100, 80, 120])).save(&input_path)?;
+        let reference_path = temp_dir.path().join("jacket.png");
+        RgbImage::from_pixel(40, 40, Rgb([30, 160, 60])).save(&reference_path)?;
+        let tiny_path = temp_dir.path().join("tiny.png");
+        RgbImage::from_pixel(8, 8, Rgb([30, 160, 60])).save(&tiny_path)?;
 
-        let tryon = VirtualTryOn::new(model_manager).unwrap();
+        let tryon = VirtualTryOn::new(model_manager)?;
+        let request = TryOnRequest {
+            input_image_path: input_path,
+            clothing_description: "red dress".to_string(),
+            negative_prompt: None,
+            output_path: temp_dir.path().join("out.png"),
+            model_name: Some("test/model".to_string()),
+            strength: Some(1.0),
+            color_strength: None,
+            style_strength: None,
+            reference_image: Some(reference_path),
+            crop: None,
+            mask: None,
+            transform_plan: None,
+            comparison: None,
+            remote_input: None,
+            seed: None,
+        };
+        let result = tryon.try_on(request.clone()).await?;
 
-        let red_transform = tryon.extract_color_transform("red dress").unwrap();
-        assert_eq!(red_transform.hue_shift, 0.0);
-        assert!(red_transform.saturation_mult > 1.0);
+        let output = image::open(&result.output_path)?.to_rgb8();
+        let [r, g, b] = output.get_pixel(32, 32).0;
+        assert!(g > r && g > b, "expected a green garment, got {r},{g},{b}");
 
-        let blue_transform = tryon.extract_color_transform("blue shirt").unwrap();
-        assert_eq!(blue_transform.hue_shift, 240.0);
+        let err = tryon
+            .try_on(TryOnRequest {
+                reference_image: Some(tiny_path),
+                ..request
+            })
+            .await
+            .unwrap_err();
+        assert!(format!("{err:#}").contains("at least 32x32"));
+        Ok(())
     }
 
-    #[test]
-    fn test_extract_style_adjustments() {
-        let temp_dir = tempdir().unwrap();
+    #[tokio::test]
+    async fn test_first_try_on_stores_model_thumbnail() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let models_dir = temp_dir.path().join("models");
+        std::fs::create_dir_all(&models_dir)?;
+        std::fs::write(
+            models_dir.join("model_index.json"),
+            r#"{"models": [{"model_id": "test/model", "files": []}]}"#,
+        )?;
         let model_manager = crate::ModelManagerBuilder::new()
-            .with_models_dir(temp_dir.path().to_path_buf())
-            .build()
-            .unwrap();
+            .with_models_dir(models_dir)
+            .build()?;
 
-        let tryon = VirtualTryOn::new(model_manager).unwrap();
+        let input_path = temp_dir.path().join("person.png");
+        RgbImage::from_pixel(400, 300, Rgb([100, 80, 120])).save(&input_path)?;
 
-        let (contrast, brightness) = tryon.extract_style_adjustments("silk dress");
-        assert!(contrast > 1.0);
-        assert!(brightness > 0.0);
+        let mut tryon = VirtualTryOn::new(model_manager)?;
+        let request = TryOnRequest {
+            input_image_path: input_path,
+            clothing_description: "blue shirt".to_string(),
+            negative_prompt: None,
+            output_path: temp_dir.path().join("out.png"),
+            model_name: Some("test/model".to_string()),
+            strength: None,
+            color_strength: None,
+            style_strength: None,
+            reference_image: None,
+            crop: None,
+            mask: None,
+            transform_plan: None,
+            comparison: None,
+            remote_input: None,
+            seed: None,
+        };
+        tryon.try_on(request.clone()).await?;
 
-        let (contrast2, brightness2) = tryon.extract_style_adjustments("leather jacket");
-        assert!(contrast2 > 1.0);
-        assert!(brightness2 < 0.0);
-    }
+        let thumbnail = tryon
+            .model_manager
+            .thumbnail_path("test/model")
+            .expect("thumbnail stored");
+        let (width, height) = image::image_dimensions(&thumbnail)?;
+        assert!(width.max(height) <= crate::models::THUMBNAIL_SIZE);
 
-    #[test]
-    fn test_validate_input_image() {
-        let temp_dir = tempdir().unwrap();
-        let nonexistent_path = temp_dir.path().join("nonexistent.jpg");
+        // Regenerated when missing, but not when thumbnails are disabled
+        std::fs::remove_file(&thumbnail)?;
+        tryon.thumbnails = false;
+        tryon.try_on(request.clone()).await?;
+        assert!(tryon.model_manager.thumbnail_path("test/model").is_none());
+        tryon.thumbnails = true;
+        tryon.try_on(request).await?;
+        assert!(thumbnail.exists());
 
-        assert!(VirtualTryOn::validate_input_image(&nonexistent_path).is_err());
+        tryon.model_manager.delete_model("test/model")?;
+        assert!(!thumbnail.exists());
+        Ok(())
     }
 
-    #[test]
-    fn test_suggest_output_path() {
-        let input_path = PathBuf::from("/path/to/person.jpg");
-        let output_path = VirtualTryOn::suggest_output_path(&input_path, "red silk dress");
+    #[tokio::test]
+    async fn test_try_on_grid_saves_grid_and_individuals() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let models_dir = temp_dir.path().join("models");
+        std::fs::create_dir_all(&models_dir)?;
+        std::fs::write(
+            models_dir.join("model_index.json"),
+            r#"{"models": [{"model_id": "test/model", "files": []}]}"#,
+        )?;
+        let model_manager = crate::ModelManagerBuilder::new()
+            .with_models_dir(models_dir)
+            .build()?;
 
-        assert!(output_path.to_string_lossy().contains("person"));
-        assert!(output_path.to_string_lossy().contains("red_silk_dress"));
-        assert!(output_path.to_string_lossy().ends_with("_tryon.png"));
+        let input_path = temp_dir.path().join("person.png");
+        RgbImage::from_pixel(64, 64, Rgb([100, 80, 120])).save(&input_path)?;
+
+        let tryon = VirtualTryOn::new(model_manager)?;
+        let request = GridRequest {
+            input_image_path: input_path,
+            prompts: vec![
+                "red dress".into(),
+                "blue dress".into(),
+                "green dress".into(),
+            ],
+            grid_path: temp_dir.path().join("grid.png"),
+            model_name: Some("test/model".to_string()),
+            strength: None,
+            columns: Some(2),
+            labels: false,
+            individual_dir: Some(temp_dir.path().join("individual")),
+        };
+        let result = tryon.try_on_grid(&request).await?;
+
+        assert_eq!(result.results.len(), 3);
+        for r in &result.results {
+            assert!(r.output_path.exists());
+        }
+        let grid = image::open(&result.grid_path)?;
+        assert_eq!(
+            (grid.width(), grid.height()),
+            compose::grid_dimensions(2, 2, 64, 64)
+        );
+
+        Ok(())
     }
 
     #[test]
-    fn test_get_recommended_models() {
-        let models = VirtualTryOn::get_recommended_models();
-        assert!(!models.is_empty());
-        assert!(models.contains(&"runwayml/stable-diffusion-v1-5"));
+    fn test_virtual_try_on_is_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<VirtualTryOn>();
     }
 
-    #[test]
-    fn test_is_likely_clothing_pixel() {
-        let temp_dir = tempdir().unwrap();
+    #[tokio::test]
+    async fn test_concurrent_try_ons_share_one_instance() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let models_dir = temp_dir.path().join("models");
+        std::fs::create_dir_all(&models_dir)?;
+        std::fs::write(
+            models_dir.join("model_index.json"),
+            r#"{"models": [{"model_id": "test/model", "files": []}]}"#,
+        )?;
         let model_manager = crate::ModelManagerBuilder::new()
-            .with_models_dir(temp_dir.path().to_path_buf())
-            .build()
-            .unwrap();
+            .with_models_dir(models_dir)
+            .build()?;
 
-        let tryon = VirtualTryOn::new(model_manager).unwrap();
+        let input_path = temp_dir.path().join("person.png");
+        RgbImage::from_pixel(64, 64, Rgb([100, 80, 120])).save(&input_path)?;
 
-        // Test clothing-like pixel in clothing region
-        let clothing_pixel = Rgb([100, 80, 120]); // Purple-ish
-        assert!(tryon.is_likely_clothing_pixel(&clothing_pixel, 200, 300, 400, 600));
-
-        // Test skin-tone pixel
-        let skin_pixel = Rgb([200, 170, 150]); // Skin tone
-        assert!(!tryon.is_likely_clothing_pixel(&skin_pixel, 200, 300, 400, 600));
+        let tryon = Arc::new(VirtualTryOn::new(model_manager)?);
+        let request = |prompt: &str, output: &str| TryOnRequest {
+            input_image_path: input_path.clone(),
+            clothing_description: prompt.to_string(),
+            negative_prompt: None,
+            output_path: temp_dir.path().join(output),
+            model_name: Some("test/model".to_string()),
+            strength: None,
+            color_strength: None,
+            style_strength: None,
+            reference_image: None,
+            crop: None,
+            mask: None,
+            transform_plan: None,
+            comparison: None,
+            remote_input: None,
+            seed: None,
+        };
+        let red = tokio::spawn({
+            let tryon = tryon.clone();
+            let request = request("red dress", "red.png");
+            async move { tryon.try_on(request).await }
+        });
+        let blue = tokio::spawn({
+            let tryon = tryon.clone();
+            let request = request("blue dress", "blue.png");
+            async move { tryon.try_on(request).await }
+        });
+        let (red, blue) = (red.await??, blue.await??);
 
-        // Test pixel outside clothing region
-        assert!(!tryon.is_likely_clothing_pixel(&clothing_pixel, 50, 100, 400, 600));
+        assert!(red.output_path.exists() && blue.output_path.exists());
+        assert_ne!(
+            image::open(&red.output_path)?.to_rgb8(),
+            image::open(&blue.output_path)?.to_rgb8()
+        );
+        assert!(tryon.is_loaded("test/model"));
+        Ok(())
     }
 }
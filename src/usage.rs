@@ -0,0 +1,243 @@
+//! Per-model usage statistics, combining the model index with the generation
+//! history
+
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+use anyhow::{Result, bail};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::{
+    history::{History, HistoryEntry},
+    models::ModelManager,
+};
+
+/// Row that collects history entries of models no longer in the index
+pub const DELETED_BUCKET: &str = "(deleted)";
+
+/// How one model has been used
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ModelUsage {
+    pub model_id: String,
+    /// Successful generations
+    pub generations: u64,
+    pub failures: u64,
+    /// Processing time of all generations, including failed ones
+    pub total_processing_ms: u64,
+    pub last_used: Option<DateTime<Utc>>,
+    /// Size of the model's files according to the index
+    pub size_bytes: u64,
+}
+
+impl ModelUsage {
+    fn new(model_id: impl Into<String>, size_bytes: u64) -> Self {
+        Self {
+            model_id: model_id.into(),
+            generations: 0,
+            failures: 0,
+            total_processing_ms: 0,
+            last_used: None,
+            size_bytes,
+        }
+    }
+
+    fn record(&mut self, entry: &HistoryEntry) {
+        if entry.success {
+            self.generations += 1;
+        } else {
+            self.failures += 1;
+        }
+        self.total_processing_ms += entry.duration_ms;
+        self.last_used = self.last_used.max(Some(entry.timestamp));
+    }
+}
+
+/// Order of [`ModelUsage`] rows
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UsageSort {
+    /// Most generations first
+    #[default]
+    Count,
+    /// Largest on disk first
+    Size,
+    /// Most recently used first, never-used models last
+    Recent,
+}
+
+impl UsageSort {
+    /// Sort `rows`, breaking ties by model id
+    pub fn sort(self, rows: &mut [ModelUsage]) {
+        rows.sort_by(|a, b| {
+            let order = match self {
+                Self::Count => b.generations.cmp(&a.generations),
+                Self::Size => b.size_bytes.cmp(&a.size_bytes),
+                Self::Recent => b.last_used.cmp(&a.last_used),
+            };
+            order.then_with(|| a.model_id.cmp(&b.model_id))
+        });
+    }
+}
+
+impl FromStr for UsageSort {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "count" => Ok(Self::Count),
+            "size" => Ok(Self::Size),
+            "recent" => Ok(Self::Recent),
+            _ => bail!("Invalid sort `{s}`, expected count, size or recent"),
+        }
+    }
+}
+
+impl fmt::Display for UsageSort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Count => "count",
+            Self::Size => "size",
+            Self::Recent => "recent",
+        })
+    }
+}
+
+impl ModelManager {
+    /// Usage of every indexed model according to `history`, sorted by model id
+    ///
+    /// Models never seen in the history have zero counts. Entries for models that
+    /// are no longer indexed are summed into one [`DELETED_BUCKET`] row at the end.
+    pub fn usage_stats(&self, history: &History) -> Result<Vec<ModelUsage>> {
+        let mut usage: BTreeMap<String, ModelUsage> = self
+            .list_models()?
+            .into_iter()
+            .map(|model| {
+                let size = model.total_size();
+                (
+                    model.model_id.clone(),
+                    ModelUsage::new(model.model_id, size),
+                )
+            })
+            .collect();
+        let mut deleted = ModelUsage::new(DELETED_BUCKET, 0);
+
+        for entry in history.entries()? {
+            match usage.get_mut(&entry.model) {
+                Some(row) => row.record(&entry),
+                None => deleted.record(&entry),
+            }
+        }
+
+        let mut rows: Vec<_> = usage.into_values().collect();
+        if deleted.generations + deleted.failures > 0 {
+            rows.push(deleted);
+        }
+        Ok(rows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ModelManagerBuilder;
+    use std::fs;
+    use tempfile::tempdir;
+
+    fn history_line(model: &str, timestamp: &str, duration_ms: u64, success: bool) -> String {
+        serde_json::json!({
+            "timestamp": timestamp,
+            "prompt": "red shirt",
+            "model": model,
+            "input": "in.png",
+            "output": "out.png",
+            "strength": null,
+            "duration_ms": duration_ms,
+            "success": success,
+        })
+        .to_string()
+    }
+
+    fn seeded(root: &std::path::Path) -> Result<(ModelManager, History)> {
+        let models_dir = root.join("models");
+        fs::create_dir_all(&models_dir)?;
+        fs::write(
+            models_dir.join("model_index.json"),
+            r#"{"models": [
+                {"model_id": "org/busy", "files": [{"size": 100, "path": "a.bin"}]},
+                {"model_id": "org/big", "files": [{"size": 5000, "path": "b.bin"}]},
+                {"model_id": "org/unused", "files": [{"size": 10, "path": "c.bin"}]}
+            ]}"#,
+        )?;
+        let history_path = root.join("history.jsonl");
+        let lines = [
+            history_line("org/busy", "2026-01-01T10:00:00Z", 100, true),
+            history_line("org/busy", "2026-01-03T10:00:00Z", 200, true),
+            history_line("org/busy", "2026-01-02T10:00:00Z", 50, false),
+            history_line("org/big", "2026-02-01T10:00:00Z", 1000, true),
+            history_line("org/gone", "2026-01-05T10:00:00Z", 30, true),
+            history_line("org/also-gone", "2026-01-06T10:00:00Z", 40, false),
+        ];
+        fs::write(&history_path, lines.join("\n"))?;
+
+        let manager = ModelManagerBuilder::new()
+            .with_models_dir(models_dir)
+            .build()?;
+        Ok((manager, History::new(history_path)))
+    }
+
+    #[test]
+    fn test_usage_stats_aggregation() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let (manager, history) = seeded(temp_dir.path())?;
+        let rows = manager.usage_stats(&history)?;
+
+        let ids: Vec<_> = rows.iter().map(|r| r.model_id.as_str()).collect();
+        assert_eq!(ids, ["org/big", "org/busy", "org/unused", DELETED_BUCKET]);
+
+        let busy = &rows[1];
+        assert_eq!((busy.generations, busy.failures), (2, 1));
+        assert_eq!(busy.total_processing_ms, 350);
+        assert_eq!(busy.size_bytes, 100);
+        assert_eq!(
+            busy.last_used,
+            Some("2026-01-03T10:00:00Z".parse::<DateTime<Utc>>()?)
+        );
+
+        let unused = &rows[2];
+        assert_eq!((unused.generations, unused.failures), (0, 0));
+        assert_eq!(unused.last_used, None);
+        assert_eq!(unused.size_bytes, 10);
+
+        let deleted = &rows[3];
+        assert_eq!((deleted.generations, deleted.failures), (1, 1));
+        assert_eq!(deleted.total_processing_ms, 70);
+        assert_eq!(deleted.size_bytes, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_usage_sort_orders() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let (manager, history) = seeded(temp_dir.path())?;
+        let rows = manager.usage_stats(&history)?;
+        let sorted = |by: UsageSort| {
+            let mut rows = rows.clone();
+            by.sort(&mut rows);
+            rows.into_iter().map(|r| r.model_id).collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            sorted("count".parse()?),
+            ["org/busy", DELETED_BUCKET, "org/big", "org/unused"]
+        );
+        assert_eq!(
+            sorted(UsageSort::Size),
+            ["org/big", "org/busy", "org/unused", DELETED_BUCKET]
+        );
+        assert_eq!(
+            sorted(UsageSort::Recent),
+            ["org/big", DELETED_BUCKET, "org/busy", "org/unused"]
+        );
+        assert!("popular".parse::<UsageSort>().is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,225 @@
+//! Weight variants such as `fp16`, following the diffusers naming convention
+//!
+//! Diffusers repos ship every weight file in its default precision, fp32, and
+//! optionally variants of it with the variant before the extension:
+//! `unet/diffusion_pytorch_model.fp16.safetensors` next to
+//! `unet/diffusion_pytorch_model.safetensors`. Sharded weights tag their index
+//! the same way, as in `model.safetensors.index.fp16.json`. Configs, tokenizers
+//! and docs are shared by all variants.
+//!
+//! Downloading one variant fetches the weights tagged with it, the default
+//! weights of any component (directory) that has no such variant, and every
+//! other file.
+
+use std::collections::{BTreeSet, HashSet};
+
+/// The variant of untagged weights
+pub const DEFAULT_VARIANT: &str = "fp32";
+
+/// Extensions of the files that hold weights
+const WEIGHT_EXTENSIONS: &[&str] = &[
+    "safetensors",
+    "bin",
+    "ckpt",
+    "pt",
+    "pth",
+    "msgpack",
+    "onnx",
+    "h5",
+];
+
+/// The variant a weight file is tagged with, e.g. `fp16` for
+/// `unet/diffusion_pytorch_model.fp16.safetensors`; None for default weights
+/// and for anything that isn't a weight file
+pub fn variant_of(rfilename: &str) -> Option<&str> {
+    let name = rfilename.rsplit('/').next().unwrap_or(rfilename);
+    let parts: Vec<&str> = name.split('.').collect();
+    let variant = match parts.as_slice() {
+        // model.safetensors.index.fp16.json
+        [_, .., extension, "index", variant, "json"] if is_weight_extension(extension) => variant,
+        // model.fp16.safetensors, model-00001-of-00002.fp16.safetensors
+        [_, .., variant, extension] if parts.len() > 2 && is_weight_extension(extension) => variant,
+        _ => return None,
+    };
+    // Leaves dotted version numbers like `sd_xl_base_1.0.safetensors` alone
+    let is_tag = variant.starts_with(|c: char| c.is_ascii_lowercase())
+        && variant
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+    is_tag.then_some(*variant)
+}
+
+fn is_weight_extension(extension: &str) -> bool {
+    WEIGHT_EXTENSIONS.contains(&extension)
+}
+
+/// Whether `rfilename` holds weights or indexes sharded weights
+fn is_weight_file(rfilename: &str) -> bool {
+    let name = rfilename.rsplit('/').next().unwrap_or(rfilename);
+    variant_of(rfilename).is_some()
+        || name.rsplit_once('.').is_some_and(|(stem, extension)| {
+            is_weight_extension(extension)
+                || (extension == "json"
+                    && stem
+                        .strip_suffix(".index")
+                        .and_then(|s| s.rsplit_once('.'))
+                        .is_some_and(|(_, ext)| is_weight_extension(ext)))
+        })
+}
+
+/// The variants a repository with these files can be downloaded as, sorted;
+/// [`DEFAULT_VARIANT`] when it has untagged weights
+pub fn available(files: &[String]) -> BTreeSet<&str> {
+    files
+        .iter()
+        .filter(|f| is_weight_file(f))
+        .map(|f| variant_of(f).unwrap_or(DEFAULT_VARIANT))
+        .collect()
+}
+
+/// The files of a repository a download of `variant` fetches
+pub fn select<'a>(files: &'a [String], variant: &str) -> HashSet<&'a str> {
+    // Components, i.e. directories, whose weights come in `variant`
+    let tagged: HashSet<&str> = files
+        .iter()
+        .filter(|f| variant_of(f) == Some(variant))
+        .map(|f| component(f))
+        .collect();
+    files
+        .iter()
+        .map(String::as_str)
+        .filter(|f| match variant_of(f) {
+            Some(tag) => tag == variant,
+            None => !(is_weight_file(f) && tagged.contains(component(f))),
+        })
+        .collect()
+}
+
+/// The directory of `rfilename`, empty at the top of the repository
+fn component(rfilename: &str) -> &str {
+    rfilename.rsplit_once('/').map_or("", |(dir, _)| dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn repo(files: &[&str]) -> Vec<String> {
+        files.iter().map(|f| f.to_string()).collect()
+    }
+
+    fn sorted(selected: HashSet<&str>) -> Vec<&str> {
+        let mut selected: Vec<_> = selected.into_iter().collect();
+        selected.sort();
+        selected
+    }
+
+    #[test]
+    fn test_variant_of() {
+        assert_eq!(
+            variant_of("unet/diffusion_pytorch_model.fp16.safetensors"),
+            Some("fp16")
+        );
+        assert_eq!(variant_of("text_encoder/model.fp16.bin"), Some("fp16"));
+        assert_eq!(
+            variant_of("unet/diffusion_pytorch_model-00001-of-00002.bf16.safetensors"),
+            Some("bf16")
+        );
+        assert_eq!(
+            variant_of("unet/diffusion_pytorch_model.safetensors.index.fp16.json"),
+            Some("fp16")
+        );
+        assert_eq!(variant_of("v1-5-pruned.non_ema.ckpt"), Some("non_ema"));
+
+        assert_eq!(variant_of("unet/diffusion_pytorch_model.safetensors"), None);
+        assert_eq!(
+            variant_of("unet/diffusion_pytorch_model.safetensors.index.json"),
+            None
+        );
+        assert_eq!(variant_of("sd_xl_base_1.0.safetensors"), None);
+        assert_eq!(variant_of("unet/config.json"), None);
+        assert_eq!(variant_of("tokenizer/vocab.fp16.txt"), None);
+        assert_eq!(variant_of("README.md"), None);
+    }
+
+    #[test]
+    fn test_select_variant_keeps_shared_files() {
+        let files = repo(&[
+            "model_index.json",
+            "README.md",
+            "unet/config.json",
+            "unet/diffusion_pytorch_model.safetensors",
+            "unet/diffusion_pytorch_model.fp16.safetensors",
+            "text_encoder/config.json",
+            "text_encoder/model.safetensors",
+            "text_encoder/model.fp16.safetensors",
+            "safety_checker/model.safetensors",
+            "tokenizer/vocab.json",
+        ]);
+        assert_eq!(
+            sorted(select(&files, "fp16")),
+            [
+                "README.md",
+                "model_index.json",
+                // No fp16 variant, so the default weights stand in
+                "safety_checker/model.safetensors",
+                "text_encoder/config.json",
+                "text_encoder/model.fp16.safetensors",
+                "tokenizer/vocab.json",
+                "unet/config.json",
+                "unet/diffusion_pytorch_model.fp16.safetensors",
+            ]
+        );
+        assert_eq!(
+            sorted(select(&files, DEFAULT_VARIANT)),
+            [
+                "README.md",
+                "model_index.json",
+                "safety_checker/model.safetensors",
+                "text_encoder/config.json",
+                "text_encoder/model.safetensors",
+                "tokenizer/vocab.json",
+                "unet/config.json",
+                "unet/diffusion_pytorch_model.safetensors",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_select_variant_of_sharded_weights() {
+        let files = repo(&[
+            "unet/diffusion_pytorch_model.safetensors.index.json",
+            "unet/diffusion_pytorch_model-00001-of-00002.safetensors",
+            "unet/diffusion_pytorch_model-00002-of-00002.safetensors",
+            "unet/diffusion_pytorch_model.safetensors.index.fp16.json",
+            "unet/diffusion_pytorch_model-00001-of-00001.fp16.safetensors",
+        ]);
+        assert_eq!(
+            sorted(select(&files, "fp16")),
+            [
+                "unet/diffusion_pytorch_model-00001-of-00001.fp16.safetensors",
+                "unet/diffusion_pytorch_model.safetensors.index.fp16.json",
+            ]
+        );
+        assert_eq!(select(&files, DEFAULT_VARIANT).len(), 3);
+        assert!(
+            select(&files, DEFAULT_VARIANT)
+                .contains("unet/diffusion_pytorch_model.safetensors.index.json")
+        );
+    }
+
+    #[test]
+    fn test_available_variants() {
+        let files = repo(&[
+            "unet/config.json",
+            "unet/diffusion_pytorch_model.safetensors",
+            "unet/diffusion_pytorch_model.fp16.safetensors",
+            "vae/diffusion_pytorch_model.bf16.bin",
+        ]);
+        assert_eq!(
+            available(&files).into_iter().collect::<Vec<_>>(),
+            ["bf16", "fp16", "fp32"]
+        );
+        assert!(available(&repo(&["README.md", "config.json"])).is_empty());
+    }
+}
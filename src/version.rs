@@ -0,0 +1,98 @@
+//! What build of si is running
+//!
+//! [`version`] gathers the crate version, the `git describe` of the sources it
+//! was built from (when built from a git checkout), the cargo features enabled
+//! and the target triple, for bug reports and for records that should name the
+//! exact build. `si --version` and `si version` both print [`VERSION`].
+
+use std::fmt;
+
+use serde::Serialize;
+
+/// The crate version, as in Cargo.toml
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// The optional cargo features of this build, in the order Cargo.toml lists them
+const FEATURES: &[(&str, bool)] = &[
+    ("server", cfg!(feature = "server")),
+    ("image-pipeline", cfg!(feature = "image-pipeline")),
+    ("hub", cfg!(feature = "hub")),
+    ("candle-backend", cfg!(feature = "candle-backend")),
+    ("test-util", cfg!(feature = "test-util")),
+    ("webp", cfg!(feature = "webp")),
+    ("avif", cfg!(feature = "avif")),
+];
+
+/// The version and build details [`version`] returns
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct VersionInfo {
+    pub version: &'static str,
+    /// `git describe --always --dirty --tags` at build time; None outside a git
+    /// checkout or without git
+    pub git: Option<&'static str>,
+    /// Enabled cargo features
+    pub features: Vec<&'static str>,
+    /// Target triple, e.g. `x86_64-unknown-linux-gnu`
+    pub target: &'static str,
+    /// Whether this is a debug build
+    pub debug: bool,
+}
+
+/// This build's version and details
+pub fn version() -> VersionInfo {
+    VersionInfo {
+        version: VERSION,
+        git: option_env!("SI_GIT_DESCRIBE").filter(|describe| !describe.is_empty()),
+        features: FEATURES
+            .iter()
+            .filter(|(_, enabled)| *enabled)
+            .map(|(name, _)| *name)
+            .collect(),
+        target: env!("SI_TARGET"),
+        debug: cfg!(debug_assertions),
+    }
+}
+
+impl fmt::Display for VersionInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "si {}", self.version)?;
+        if let Some(git) = self.git {
+            write!(f, " ({git})")?;
+        }
+        if self.debug {
+            f.write_str(" [debug]")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_version_matches_the_crate() -> anyhow::Result<()> {
+        let info = version();
+        assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+        assert!(!info.target.is_empty());
+        assert!(info.to_string().starts_with(&format!("si {VERSION}")));
+
+        let json = serde_json::to_value(&info)?;
+        assert_eq!(json["version"], env!("CARGO_PKG_VERSION"));
+        assert!(json["features"].is_array());
+        Ok(())
+    }
+
+    #[test]
+    fn test_features_follow_the_build() {
+        let features = version().features;
+        assert_eq!(
+            features.contains(&"image-pipeline"),
+            cfg!(feature = "image-pipeline")
+        );
+        assert_eq!(features.contains(&"hub"), cfg!(feature = "hub"));
+        if cfg!(feature = "server") {
+            assert!(features.contains(&"server") && features.contains(&"image-pipeline"));
+        }
+    }
+}
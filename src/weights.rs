@@ -0,0 +1,193 @@
+//! Weight file formats
+//!
+//! Checkpoints come as safetensors, GGUF or Python pickles (`.ckpt`, `.pt`,
+//! `.bin`). Pickles can run code when loaded, so si never loads them; a model
+//! whose weights are all pickles has to be converted to safetensors first. See
+//! [`ModelInfo::is_pickle_only`](crate::ModelInfo::is_pickle_only) and
+//! [`ModelManager::conversion_candidates`](crate::ModelManager::conversion_candidates).
+//!
+//! [`WeightFormat::from_name`] goes by the extension, which is all the index
+//! has for files it didn't look at; [`WeightFormat::detect`] also reads the
+//! first bytes of a file so a pickle named `.safetensors` isn't taken for one.
+
+use std::{
+    fmt,
+    fs::File,
+    io::{self, Read},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+/// Extensions of pickled PyTorch checkpoints
+const PICKLE_EXTENSIONS: &[&str] = &["ckpt", "pt", "pth", "bin", "pkl", "pickle"];
+/// Bytes [`WeightFormat::sniff`] reads
+const SNIFF_LEN: usize = 9;
+/// Largest safetensors header [`WeightFormat::sniff`] believes in
+const MAX_SAFETENSORS_HEADER: u64 = 100 << 20;
+
+/// How a file stores weights
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WeightFormat {
+    Safetensors,
+    /// A Python pickle, possibly inside PyTorch's zip container
+    Pickle,
+    Gguf,
+    /// Not weights, or weights in a format si doesn't know
+    #[default]
+    Unknown,
+}
+
+impl WeightFormat {
+    /// The format the extension of `name` promises
+    pub fn from_name(name: &str) -> Self {
+        let Some((_, extension)) = name.rsplit_once('.') else {
+            return Self::Unknown;
+        };
+        let extension = extension.to_ascii_lowercase();
+        match extension.as_str() {
+            "safetensors" => Self::Safetensors,
+            "gguf" => Self::Gguf,
+            ext if PICKLE_EXTENSIONS.contains(&ext) => Self::Pickle,
+            _ => Self::Unknown,
+        }
+    }
+
+    /// The format the first bytes of `path` show, or [`Unknown`](Self::Unknown)
+    /// when they match none
+    pub fn sniff(path: &Path) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        let len = file.metadata()?.len();
+        let mut head = Vec::with_capacity(SNIFF_LEN);
+        (&mut file).take(SNIFF_LEN as u64).read_to_end(&mut head)?;
+        Ok(Self::from_head(&head, len))
+    }
+
+    fn from_head(head: &[u8], len: u64) -> Self {
+        if head.starts_with(b"GGUF") {
+            return Self::Gguf;
+        }
+        // PyTorch's zip container, or a bare pickle's protocol 2+ opcode
+        if head.starts_with(b"PK\x03\x04") || (head.first() == Some(&0x80) && head.len() > 1) {
+            return Self::Pickle;
+        }
+        // A little-endian header length, then the JSON header itself
+        if let (Some(size), Some(b'{')) = (head.get(..8), head.get(8)) {
+            let size = u64::from_le_bytes(size.try_into().expect("8 bytes"));
+            if size <= MAX_SAFETENSORS_HEADER && size + 8 <= len {
+                return Self::Safetensors;
+            }
+        }
+        Self::Unknown
+    }
+
+    /// The format of the file at `path` named `name`: what its contents show
+    /// when the name promises weights, else what the name says
+    pub fn detect(path: &Path, name: &str) -> io::Result<Self> {
+        let named = Self::from_name(name);
+        if named == Self::Unknown {
+            return Ok(named);
+        }
+        Ok(match Self::sniff(path)? {
+            Self::Unknown => named,
+            sniffed => sniffed,
+        })
+    }
+
+    /// Whether files of this format hold weights
+    pub fn is_weights(self) -> bool {
+        self != Self::Unknown
+    }
+}
+
+impl fmt::Display for WeightFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Safetensors => "safetensors",
+            Self::Pickle => "pickle",
+            Self::Gguf => "gguf",
+            Self::Unknown => "unknown",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_format_from_name() {
+        for (name, format) in [
+            (
+                "unet/diffusion_pytorch_model.safetensors",
+                WeightFormat::Safetensors,
+            ),
+            ("v1-5-pruned-emaonly.ckpt", WeightFormat::Pickle),
+            ("pytorch_model.bin", WeightFormat::Pickle),
+            ("text_encoder/model.fp16.bin", WeightFormat::Pickle),
+            ("lora.PT", WeightFormat::Pickle),
+            ("weights.pth", WeightFormat::Pickle),
+            ("llama-7b.Q4_K_M.gguf", WeightFormat::Gguf),
+            ("model_index.json", WeightFormat::Unknown),
+            (
+                "unet/diffusion_pytorch_model.safetensors.index.json",
+                WeightFormat::Unknown,
+            ),
+            ("model.onnx", WeightFormat::Unknown),
+            ("README", WeightFormat::Unknown),
+        ] {
+            assert_eq!(WeightFormat::from_name(name), format, "{name}");
+        }
+    }
+
+    #[test]
+    fn test_detect_trusts_contents_over_names() -> io::Result<()> {
+        let dir = tempdir()?;
+        let write = |name: &str, bytes: &[u8]| -> io::Result<std::path::PathBuf> {
+            let path = dir.path().join(name);
+            fs::write(&path, bytes)?;
+            Ok(path)
+        };
+        let header = b"{\"a\":{}}";
+        let mut safetensors = (header.len() as u64).to_le_bytes().to_vec();
+        safetensors.extend(header);
+
+        let real = write("real.safetensors", &safetensors)?;
+        assert_eq!(WeightFormat::sniff(&real)?, WeightFormat::Safetensors);
+        let disguised = write("fake.safetensors", b"PK\x03\x04archive/data.pkl")?;
+        assert_eq!(
+            WeightFormat::detect(&disguised, "fake.safetensors")?,
+            WeightFormat::Pickle
+        );
+        let bare = write("old.ckpt", &[0x80, 0x02, b'}', b'q'])?;
+        assert_eq!(
+            WeightFormat::detect(&bare, "old.ckpt")?,
+            WeightFormat::Pickle
+        );
+        let gguf = write("model.bin", b"GGUF\x03\0\0\0")?;
+        assert_eq!(
+            WeightFormat::detect(&gguf, "model.bin")?,
+            WeightFormat::Gguf
+        );
+
+        // Unrecognizable contents keep the name's word, and non-weights aren't read
+        let empty = write("empty.ckpt", b"")?;
+        assert_eq!(
+            WeightFormat::detect(&empty, "empty.ckpt")?,
+            WeightFormat::Pickle
+        );
+        let config = dir.path().join("missing.json");
+        assert_eq!(
+            WeightFormat::detect(&config, "config.json")?,
+            WeightFormat::Unknown
+        );
+
+        // A header length past the end of the file isn't safetensors
+        let truncated = write("cut.safetensors", &safetensors[..12])?;
+        assert_eq!(WeightFormat::sniff(&truncated)?, WeightFormat::Unknown);
+        Ok(())
+    }
+}
@@ -0,0 +1,66 @@
+//! The model index as index-only consumers use it. These tests don't depend on
+//! the image pipeline and pass with `cargo test --no-default-features --test
+//! index_only_tests` as well as with the default features.
+
+use anyhow::Result;
+use si::{ModelManager, ModelManagerBuilder, ModelQuery};
+use std::fs;
+use std::path::Path;
+use tempfile::tempdir;
+
+const MODEL_ID: &str = "org/model";
+const REVISION: &str = "0123456789abcdef0123456789abcdef01234567";
+
+/// A manager without a source over a Hub cache holding one snapshot of
+/// [`MODEL_ID`], as another tool would have downloaded it
+fn cached_manager(root: &Path) -> Result<ModelManager> {
+    let repo_dir = root.join("cache/models--org--model");
+    let snapshot = repo_dir.join("snapshots").join(REVISION);
+    fs::create_dir_all(&snapshot)?;
+    fs::write(snapshot.join("config.json"), "{}")?;
+    fs::write(snapshot.join("model.safetensors"), "weights")?;
+    fs::create_dir_all(repo_dir.join("refs"))?;
+    fs::write(repo_dir.join("refs/main"), REVISION)?;
+
+    ModelManagerBuilder::new()
+        .with_models_dir(root.join("models"))
+        .with_hf_cache_dir(root.join("cache"))
+        .build()
+}
+
+#[tokio::test]
+async fn test_index_apis_work_without_a_hub() -> Result<()> {
+    let temp_dir = tempdir()?;
+    let manager = cached_manager(temp_dir.path())?;
+    assert!(manager.list_models()?.is_empty());
+
+    let synced = manager.sync_models(false).await?;
+    assert_eq!(synced.discrepancies_count(), 1);
+    let model = manager.get_model(MODEL_ID)?.expect("synced model");
+    assert_eq!(model.file_count(), 2);
+    assert_eq!(model.total_size(), 9);
+
+    manager.set_pinned(MODEL_ID, true)?;
+    let query = ModelQuery {
+        filter: Some("org/*".to_string()),
+        ..Default::default()
+    };
+    assert_eq!(manager.query_models(&query)?.len(), 1);
+    assert!(manager.repair_index()?.is_clean());
+    assert_eq!(manager.gc(true, false)?.skipped_pinned, [MODEL_ID]);
+
+    manager.delete_model(MODEL_ID)?;
+    assert!(manager.list_models()?.is_empty());
+    Ok(())
+}
+
+#[cfg(not(feature = "hub"))]
+#[tokio::test]
+async fn test_downloads_fail_cleanly_without_the_hub_feature() -> Result<()> {
+    let temp_dir = tempdir()?;
+    let manager = cached_manager(temp_dir.path())?;
+    let err = manager.download_model(MODEL_ID).await.unwrap_err();
+    assert!(si::SiError::HubDisabled.matches(&err), "{err:#}");
+    assert!(manager.list_models()?.is_empty());
+    Ok(())
+}
@@ -1,3 +1,5 @@
+#![cfg(all(feature = "image-pipeline", feature = "hub"))]
+
 use assert_fs::prelude::*;
 
 use std::process::Command;
@@ -44,6 +46,39 @@ fn test_cli_help() {
     assert!(stdout.contains("image"));
 }
 
+#[test]
+fn test_version_subcommand_reports_the_build() {
+    let mut cmd = Command::new(get_binary_path());
+    cmd.args(["version", "--json"]);
+    let output = cmd.output().expect("Failed to execute command");
+    assert!(output.status.success());
+
+    let info: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(info["version"], env!("CARGO_PKG_VERSION"));
+    let features = info["features"].as_array().unwrap();
+    assert!(features.iter().any(|f| f == "image-pipeline"));
+    assert!(info["target"].as_str().is_some_and(|t| !t.is_empty()));
+
+    let mut cmd = Command::new(get_binary_path());
+    cmd.args(["version", "--verbose"]);
+    let output = cmd.output().expect("Failed to execute command");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with(&format!("si {}", env!("CARGO_PKG_VERSION"))));
+    assert!(stdout.contains("features: "));
+}
+
+#[test]
+fn test_malformed_rust_log_is_ignored_with_a_warning() {
+    let mut cmd = Command::new(get_binary_path());
+    cmd.args(["version"]).env("RUST_LOG", "si=loud");
+    let output = cmd.output().expect("Failed to execute command");
+
+    assert!(output.status.success(), "{output:?}");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Warning: ignoring RUST_LOG"), "{stderr}");
+    assert!(stderr.contains("si=loud"), "{stderr}");
+}
+
 #[test]
 fn test_cli_version() {
     let mut cmd = Command::new(get_binary_path());
@@ -105,6 +140,32 @@ fn test_image_help() {
     assert!(stdout.contains("generate"));
 }
 
+#[test]
+fn test_image_convert() {
+    let temp_dir = assert_fs::TempDir::new().unwrap();
+    let input_file = temp_dir.child("input.png");
+    let output_file = temp_dir.child("output.jpg");
+    image::RgbImage::from_pixel(200, 100, image::Rgb([100, 80, 120]))
+        .save(input_file.path())
+        .unwrap();
+
+    let mut cmd = Command::new(get_binary_path());
+    cmd.args(["image", "convert"])
+        .arg(input_file.path())
+        .arg(output_file.path())
+        .args(["--quality", "80", "--resize", "100x"]);
+    let output = cmd.output().expect("Failed to execute command");
+    assert!(output.status.success(), "{output:?}");
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("200x100"));
+    assert!(stdout.contains("100x50"));
+    assert_eq!(
+        image::image_dimensions(output_file.path()).unwrap(),
+        (100, 50)
+    );
+}
+
 #[test]
 fn test_model_list_no_models() {
     let mut cmd = Command::new(get_binary_path());
@@ -128,6 +189,11 @@ fn test_config_show() {
     let mut cmd = Command::new(get_binary_path());
     cmd.args(["config", "show"]);
 
+    // Keep the user's real config untouched
+    let temp_dir = tempdir().unwrap();
+    cmd.env("HOME", temp_dir.path());
+    cmd.env("XDG_CONFIG_HOME", temp_dir.path());
+
     let output = cmd.output().expect("Failed to execute command");
 
     assert!(output.status.success());
@@ -141,6 +207,11 @@ fn test_config_set() {
     let mut cmd = Command::new(get_binary_path());
     cmd.args(["config", "set", "test_key", "test_value"]);
 
+    // Keep the user's real config untouched
+    let temp_dir = tempdir().unwrap();
+    cmd.env("HOME", temp_dir.path());
+    cmd.env("XDG_CONFIG_HOME", temp_dir.path());
+
     let output = cmd.output().expect("Failed to execute command");
 
     assert!(output.status.success());
@@ -154,6 +225,11 @@ fn test_config_get() {
     let mut cmd = Command::new(get_binary_path());
     cmd.args(["config", "get", "test_key"]);
 
+    // Keep the user's real config untouched
+    let temp_dir = tempdir().unwrap();
+    cmd.env("HOME", temp_dir.path());
+    cmd.env("XDG_CONFIG_HOME", temp_dir.path());
+
     let output = cmd.output().expect("Failed to execute command");
 
     assert!(output.status.success());
@@ -167,6 +243,11 @@ fn test_config_reset() {
     let mut cmd = Command::new(get_binary_path());
     cmd.args(["config", "reset"]);
 
+    // Keep the user's real config untouched
+    let temp_dir = tempdir().unwrap();
+    cmd.env("HOME", temp_dir.path());
+    cmd.env("XDG_CONFIG_HOME", temp_dir.path());
+
     let output = cmd.output().expect("Failed to execute command");
 
     assert!(output.status.success());
@@ -177,8 +258,15 @@ fn test_config_reset() {
 
 #[test]
 fn test_model_delete() {
+    let temp_dir = tempdir().unwrap();
     let mut cmd = Command::new(get_binary_path());
     cmd.args(["model", "delete", "test-model"]);
+    isolate_home_with_model(&mut cmd, temp_dir.path(), "test-model");
+    let thumbnail = temp_dir
+        .path()
+        .join("data/models/thumbnails/test-model.png");
+    std::fs::create_dir_all(thumbnail.parent().unwrap()).unwrap();
+    std::fs::write(&thumbnail, "png").unwrap();
 
     let output = cmd.output().expect("Failed to execute command");
 
@@ -186,6 +274,37 @@ fn test_model_delete() {
 
     let stdout = String::from_utf8(output.stdout).unwrap();
     assert!(stdout.contains("Deleting model: test-model"));
+    assert!(stdout.contains("Model test-model deleted"));
+    assert!(!thumbnail.exists());
+
+    // Deleting it again fails because it's no longer indexed
+    let mut cmd = Command::new(get_binary_path());
+    cmd.args(["model", "delete", "test-model"]);
+    cmd.env("HOME", temp_dir.path());
+    cmd.env("SI_DATA_DIR", temp_dir.path().join("data"));
+    cmd.env("HF_HUB_CACHE", temp_dir.path().join("hf-cache"));
+    let output = cmd.output().expect("Failed to execute command");
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_model_list_copies_thumbnails() {
+    let temp_dir = tempdir().unwrap();
+    let mut cmd = Command::new(get_binary_path());
+    let export_dir = temp_dir.path().join("previews");
+    cmd.args(["model", "list", "--thumbnails"]);
+    cmd.arg(&export_dir);
+    isolate_home_with_model(&mut cmd, temp_dir.path(), "org/model");
+    let thumbnail = temp_dir
+        .path()
+        .join("data/models/thumbnails/org--model.png");
+    std::fs::create_dir_all(thumbnail.parent().unwrap()).unwrap();
+    std::fs::write(&thumbnail, "png").unwrap();
+
+    let output = cmd.output().expect("Failed to execute command");
+
+    assert!(output.status.success());
+    assert!(export_dir.join("org--model.png").exists());
 }
 
 #[test]
@@ -201,14 +320,64 @@ fn test_model_show() {
     assert!(stdout.contains("Showing details for model: test-model"));
 }
 
+/// Point si's data and config directories at `home` and register `model_id` in its index
+fn isolate_home_with_model(cmd: &mut Command, home: &std::path::Path, model_id: &str) {
+    let data_dir = home.join("data");
+    cmd.env("HOME", home);
+    cmd.env("SI_DATA_DIR", &data_dir);
+    cmd.env("XDG_CONFIG_HOME", home.join("config"));
+    cmd.env("HF_HUB_CACHE", home.join("hf-cache"));
+    cmd.env("SI_CACHE_DIR", home.join("cache"));
+
+    let index = format!(r#"{{"models": [{{"model_id": "{model_id}", "files": []}}]}}"#);
+    let models_dir = data_dir.join("models");
+    std::fs::create_dir_all(&models_dir).unwrap();
+    std::fs::write(models_dir.join("model_index.json"), index).unwrap();
+}
+
+#[test]
+fn test_model_list_honors_si_data_dir() {
+    let temp_dir = tempdir().unwrap();
+    let mut cmd = Command::new(get_binary_path());
+    cmd.args(["model", "list"]);
+    isolate_home_with_model(&mut cmd, temp_dir.path(), "org/relocated-model");
+    // XDG points elsewhere; SI_DATA_DIR must win on every platform
+    cmd.env("XDG_DATA_HOME", temp_dir.path().join("xdg"));
+
+    let output = cmd.output().expect("Failed to execute command");
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("org/relocated-model"));
+}
+
+#[test]
+fn test_model_list_empty_si_data_dir() {
+    let temp_dir = tempdir().unwrap();
+    let data_dir = temp_dir.path().join("si-data");
+    let mut cmd = Command::new(get_binary_path());
+    cmd.args(["model", "list"]);
+    cmd.env("HOME", temp_dir.path());
+    cmd.env("SI_DATA_DIR", &data_dir);
+
+    let output = cmd.output().expect("Failed to execute command");
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("No models"));
+    // Listing doesn't create the models directory; the first write does
+    assert!(!data_dir.join("models").exists());
+}
+
 #[test]
 fn test_image_generate() {
     let temp_dir = assert_fs::TempDir::new().unwrap();
-    let input_file = temp_dir.child("input.jpg");
+    let input_file = temp_dir.child("input.png");
     let output_file = temp_dir.child("output.png");
 
-    // Create a dummy input file
-    input_file.write_binary(b"fake image data").unwrap();
+    image::RgbImage::from_pixel(64, 64, image::Rgb([100, 80, 120]))
+        .save(input_file.path())
+        .unwrap();
 
     let mut cmd = Command::new(get_binary_path());
     cmd.args([
@@ -222,6 +391,7 @@ fn test_image_generate() {
         "--output",
         output_file.path().to_str().unwrap(),
     ]);
+    isolate_home_with_model(&mut cmd, temp_dir.path(), "test-model");
 
     let output = cmd.output().expect("Failed to execute command");
 
@@ -230,6 +400,805 @@ fn test_image_generate() {
     let stdout = String::from_utf8(output.stdout).unwrap();
     assert!(stdout.contains("Generating image with prompt: A beautiful sunset"));
     assert!(stdout.contains("Using model: test-model"));
+    output_file.assert(predicates::path::exists());
+}
+
+#[test]
+fn test_image_generate_with_a_local_model_folder() {
+    let temp_dir = assert_fs::TempDir::new().unwrap();
+    // A diffusers folder as a training run leaves it
+    let model_dir = temp_dir.child("my-finetune");
+    for (file, contents) in [
+        (
+            "model_index.json",
+            r#"{"_class_name": "StableDiffusionImg2ImgPipeline",
+                "unet": ["diffusers", "UNet2DConditionModel"],
+                "vae": ["diffusers", "AutoencoderKL"]}"#,
+        ),
+        ("unet/config.json", "{}"),
+        ("unet/diffusion_pytorch_model.safetensors", "unet"),
+        ("vae/config.json", "{}"),
+        ("vae/diffusion_pytorch_model.safetensors", "vae"),
+    ] {
+        model_dir.child(file).write_str(contents).unwrap();
+    }
+    image::RgbImage::from_pixel(64, 64, image::Rgb([100, 80, 120]))
+        .save(temp_dir.child("input.png").path())
+        .unwrap();
+    let si = |args: &[&str]| {
+        let mut cmd = Command::new(get_binary_path());
+        cmd.current_dir(temp_dir.path()).args(args);
+        isolate_home_with_model(&mut cmd, temp_dir.path(), "test-model");
+        // Any attempt to reach a Hub would fail
+        cmd.env("HF_ENDPOINT", "http://127.0.0.1:9");
+        cmd.output().expect("Failed to execute command")
+    };
+    let generate = |extra: &[&str]| {
+        let mut args = vec![
+            "--offline",
+            "image",
+            "generate",
+            "red shirt",
+            "--model",
+            "./my-finetune",
+            "--input",
+            "input.png",
+            "--output",
+            "out.png",
+        ];
+        args.extend(extra);
+        si(&args)
+    };
+    let indexed =
+        || std::fs::read_to_string(temp_dir.child("data/models/model_index.json").path()).unwrap();
+
+    let output = generate(&["--json"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{stderr}");
+    let result: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let resolved = std::fs::canonicalize(temp_dir.child("my-finetune").path()).unwrap();
+    assert_eq!(result["model_used"], resolved.to_str().unwrap());
+    let hash = result["model_hash"].as_str().unwrap().to_string();
+    assert_eq!(hash.len(), 64);
+    temp_dir.child("out.png").assert(predicates::path::exists());
+    assert!(!indexed().contains("my-finetune"));
+
+    let info = si(&["image", "info", "out.png"]);
+    let stdout = String::from_utf8_lossy(&info.stdout);
+    assert!(stdout.contains(&format!("Model hash: {hash}")), "{stdout}");
+
+    // The files' hashes are cached, and reused while they're unchanged
+    let cached = temp_dir.child("cache/hashes").path().read_dir().unwrap();
+    assert_ne!(cached.count(), 0);
+    let output = generate(&["--json"]);
+    let result: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(result["model_hash"], hash.as_str());
+
+    // Without the leading `./` it is a model id, and not a downloaded one
+    let output = si(&[
+        "--offline",
+        "image",
+        "generate",
+        "red shirt",
+        "--model",
+        "my-finetune",
+        "--input",
+        "input.png",
+    ]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("isn't downloaded"), "{stderr}");
+
+    let output = generate(&["--register"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    assert!(stdout.contains("as local/my-finetune."), "{stdout}");
+    assert!(indexed().contains("local/my-finetune"));
+}
+
+#[test]
+fn test_image_generate_lays_a_texture_over_the_garment() {
+    let temp_dir = assert_fs::TempDir::new().unwrap();
+    let input_file = temp_dir.child("input.png");
+    image::RgbImage::from_pixel(64, 64, image::Rgb([100, 80, 120]))
+        .save(input_file.path())
+        .unwrap();
+    let generate = |name: &str, flags: &[&str]| {
+        let output_file = temp_dir.child(name);
+        let mut cmd = Command::new(get_binary_path());
+        cmd.args(["image", "generate", "red shirt", "--model", "test-model"])
+            .arg("--input")
+            .arg(input_file.path())
+            .arg("--output")
+            .arg(output_file.path())
+            .args(flags);
+        isolate_home_with_model(&mut cmd, temp_dir.path(), "test-model");
+        let output = cmd.output().expect("Failed to execute command");
+        assert!(
+            output.status.success(),
+            "{flags:?}: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        image::open(output_file.path()).unwrap().to_rgb8()
+    };
+
+    let plain = generate("plain.png", &[]);
+    let textured = generate(
+        "denim.png",
+        &["--texture", "denim", "--texture-opacity", "1"],
+    );
+    assert_ne!(plain, textured);
+    // Nothing in a red shirt calls for a texture, and opacity 0 adds none
+    assert_eq!(plain, generate("auto.png", &["--texture", "auto"]));
+    assert_eq!(
+        plain,
+        generate(
+            "clear.png",
+            &["--texture", "knit", "--texture-opacity", "0"]
+        )
+    );
+
+    let mut cmd = Command::new(get_binary_path());
+    cmd.args(["image", "generate", "red shirt", "--model", "test-model"])
+        .arg("--input")
+        .arg(input_file.path())
+        .args(["--texture", "tweed"]);
+    isolate_home_with_model(&mut cmd, temp_dir.path(), "test-model");
+    let output = cmd.output().expect("Failed to execute command");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("No texture `tweed`"), "{stderr}");
+}
+
+#[test]
+fn test_image_generate_saves_and_applies_a_recipe() {
+    let temp_dir = assert_fs::TempDir::new().unwrap();
+    let first = temp_dir.child("first.png");
+    let second = temp_dir.child("second.png");
+    let recipe = temp_dir.child("look.json");
+    image::RgbImage::from_pixel(64, 64, image::Rgb([100, 80, 120]))
+        .save(first.path())
+        .unwrap();
+    image::RgbImage::from_pixel(80, 96, image::Rgb([180, 170, 160]))
+        .save(second.path())
+        .unwrap();
+    let run = |args: &[&str]| {
+        let mut cmd = Command::new(get_binary_path());
+        cmd.args(["image", "generate"]).args(args);
+        isolate_home_with_model(&mut cmd, temp_dir.path(), "test-model");
+        cmd.output().expect("Failed to execute command")
+    };
+
+    let output = run(&[
+        "red silk shirt",
+        "--model",
+        "test-model",
+        "--input",
+        first.path().to_str().unwrap(),
+        "--output",
+        temp_dir.child("first-out.png").path().to_str().unwrap(),
+        "--polygon",
+        "0.1,0.1 0.9,0.1 0.5,0.9",
+        "--style-strength",
+        "0.3",
+        "--save-recipe",
+        recipe.path().to_str().unwrap(),
+    ]);
+    assert!(output.status.success());
+    let saved: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(recipe.path()).unwrap()).unwrap();
+    assert_eq!(saved["prompt"], "red silk shirt");
+    assert_eq!(saved["style_strength"], 0.3);
+    assert!(saved.get("input_image_path").is_none() && saved.get("output_path").is_none());
+
+    // No prompt or model: both come from the recipe
+    let second_out = temp_dir.child("second-out.png");
+    let output = run(&[
+        "--input",
+        second.path().to_str().unwrap(),
+        "--output",
+        second_out.path().to_str().unwrap(),
+        "--recipe",
+        recipe.path().to_str().unwrap(),
+        "--json",
+    ]);
+    assert!(output.status.success());
+    let result: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(result["model_used"], "test-model");
+    // The plans went through f64 in the JSON output, so compare them as f32
+    let plan = |value: &serde_json::Value| -> si::TransformPlan {
+        serde_json::from_value(value["transform_plan"].clone()).unwrap()
+    };
+    assert_eq!(plan(&result), plan(&saved));
+    assert_eq!(result["style_strength"], 0.3);
+    second_out.assert(predicates::path::exists());
+}
+
+#[test]
+fn test_image_generate_suggests_a_strength() {
+    let temp_dir = assert_fs::TempDir::new().unwrap();
+    let input_file = temp_dir.child("input.png");
+    image::RgbImage::from_pixel(64, 64, image::Rgb([40, 30, 25]))
+        .save(input_file.path())
+        .unwrap();
+
+    let mut cmd = Command::new(get_binary_path());
+    cmd.args([
+        "image",
+        "generate",
+        "blue shirt",
+        "--model",
+        "test-model",
+        "--input",
+        input_file.path().to_str().unwrap(),
+        "--output",
+        temp_dir.child("output.png").path().to_str().unwrap(),
+        "--strength",
+        "auto",
+        "--json",
+    ]);
+    isolate_home_with_model(&mut cmd, temp_dir.path(), "test-model");
+    let output = cmd.output().expect("Failed to execute command");
+    assert!(output.status.success(), "{output:?}");
+
+    let result: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let suggested = result["suggested_strength"].as_f64().unwrap();
+    assert!((0.2..=0.85).contains(&suggested), "{suggested}");
+    assert_eq!(result["color_strength"].as_f64(), Some(suggested));
+    assert_eq!(result["style_strength"].as_f64(), Some(suggested));
+}
+
+#[test]
+fn test_image_generate_and_batch_make_variations() {
+    let temp_dir = assert_fs::TempDir::new().unwrap();
+    let input_dir = temp_dir.child("in");
+    input_dir.create_dir_all().unwrap();
+    for name in ["a.png", "b.png"] {
+        image::RgbImage::from_pixel(64, 64, image::Rgb([180, 60, 60]))
+            .save(input_dir.child(name).path())
+            .unwrap();
+    }
+    let si = |args: &[&str]| {
+        let mut cmd = Command::new(get_binary_path());
+        cmd.args(args);
+        isolate_home_with_model(&mut cmd, temp_dir.path(), "test-model");
+        cmd.output().expect("Failed to execute command")
+    };
+
+    let output = si(&[
+        "image",
+        "generate",
+        "blue shirt",
+        "--model",
+        "test-model",
+        "--input",
+        input_dir.child("a.png").path().to_str().unwrap(),
+        "--output",
+        temp_dir.child("out.png").path().to_str().unwrap(),
+        "--count",
+        "3",
+        "--json",
+    ]);
+    assert!(output.status.success(), "{output:?}");
+    let results: Vec<serde_json::Value> = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(results.len(), 3);
+    assert!(results.iter().all(|r| r["group"] == results[0]["group"]));
+    for n in 1..=3 {
+        temp_dir
+            .child(format!("out-{n}.png"))
+            .assert(predicates::path::exists());
+    }
+
+    let output_dir = temp_dir.child("out");
+    let output = si(&[
+        "image",
+        "batch",
+        "blue shirt",
+        "--model",
+        "test-model",
+        "--input-dir",
+        input_dir.path().to_str().unwrap(),
+        "--output-dir",
+        output_dir.path().to_str().unwrap(),
+        "--count",
+        "2",
+    ]);
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.contains("Processed 2 images (4 variations)"),
+        "{stdout}"
+    );
+    for name in ["a-1.png", "a-2.png", "b-1.png", "b-2.png"] {
+        output_dir.child(name).assert(predicates::path::exists());
+    }
+}
+
+#[test]
+fn test_image_info_reads_embedded_parameters() {
+    let temp_dir = assert_fs::TempDir::new().unwrap();
+    let input_file = temp_dir.child("input.png");
+    image::RgbImage::from_pixel(64, 64, image::Rgb([100, 80, 120]))
+        .save(input_file.path())
+        .unwrap();
+    let si = |args: &[&str]| {
+        let mut cmd = Command::new(get_binary_path());
+        cmd.args(args);
+        isolate_home_with_model(&mut cmd, temp_dir.path(), "test-model");
+        cmd.output().expect("Failed to execute command")
+    };
+    for (name, extra) in [
+        ("out.jpg", None),
+        ("plain.png", Some("--no-embed-metadata")),
+    ] {
+        let output_file = temp_dir.child(name);
+        let mut args = vec![
+            "image",
+            "generate",
+            "red silk shirt",
+            "--model",
+            "test-model",
+            "--input",
+            input_file.path().to_str().unwrap(),
+            "--output",
+            output_file.path().to_str().unwrap(),
+        ];
+        args.extend(extra);
+        assert!(si(&args).status.success());
+
+        let output = si(&["image", "info", output_file.path().to_str().unwrap()]);
+        assert!(output.status.success());
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        if extra.is_some() {
+            assert!(
+                stdout.contains("No generation parameters embedded"),
+                "{stdout}"
+            );
+            continue;
+        }
+        assert!(stdout.contains("Prompt:    red silk shirt"), "{stdout}");
+        assert!(stdout.contains("Model:     test-model"), "{stdout}");
+        let output = si(&[
+            "image",
+            "info",
+            output_file.path().to_str().unwrap(),
+            "--json",
+        ]);
+        let info: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        assert_eq!(info["format"], "jpg");
+        assert!(info["parameters"]["strength"].is_number());
+        assert_eq!(info["parameters"]["model"], "test-model");
+    }
+}
+
+#[test]
+fn test_generate_caches_the_mask_until_cleared() {
+    let temp_dir = assert_fs::TempDir::new().unwrap();
+    let input_file = temp_dir.child("input.png");
+    image::RgbImage::from_pixel(64, 64, image::Rgb([100, 80, 120]))
+        .save(input_file.path())
+        .unwrap();
+    let masks = temp_dir.path().join("cache/masks");
+    let generate = |output: &str, extra: &[&str]| {
+        let mut cmd = Command::new(get_binary_path());
+        cmd.args(["image", "generate", "red shirt", "--model", "test-model"])
+            .arg("--input")
+            .arg(input_file.path())
+            .arg("--output")
+            .arg(temp_dir.path().join(output))
+            .args(extra);
+        isolate_home_with_model(&mut cmd, temp_dir.path(), "test-model");
+        let output = cmd.output().expect("Failed to execute command");
+        assert!(output.status.success(), "{output:?}");
+    };
+    let cached = || std::fs::read_dir(&masks).map_or(0, |dir| dir.count());
+
+    generate("a.png", &["--no-mask-cache"]);
+    assert_eq!(cached(), 0);
+    generate("b.png", &[]);
+    generate("c.png", &[]);
+    assert_eq!(cached(), 1);
+
+    let mut cmd = Command::new(get_binary_path());
+    cmd.args(["cache", "clear", "masks"]);
+    isolate_home_with_model(&mut cmd, temp_dir.path(), "test-model");
+    let output = cmd.output().expect("Failed to execute command");
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("Removed 1 cached masks"), "{stdout}");
+    assert_eq!(cached(), 0);
+}
+
+#[test]
+fn test_stats_counts_local_generations() {
+    let temp_dir = assert_fs::TempDir::new().unwrap();
+    let input_file = temp_dir.child("input.png");
+    image::RgbImage::from_pixel(64, 64, image::Rgb([100, 80, 120]))
+        .save(input_file.path())
+        .unwrap();
+
+    let si = |args: &[&str]| {
+        let mut cmd = Command::new(get_binary_path());
+        cmd.args(args);
+        isolate_home_with_model(&mut cmd, temp_dir.path(), "test-model");
+        cmd.output().expect("Failed to execute command")
+    };
+    let generate = |output: &str| {
+        si(&[
+            "image",
+            "generate",
+            "red shirt",
+            "--model",
+            "test-model",
+            "--input",
+            input_file.path().to_str().unwrap(),
+            "--output",
+            temp_dir.child(output).path().to_str().unwrap(),
+        ])
+    };
+    let generations = || {
+        let output = si(&["stats", "--since", "1h", "--json"]);
+        assert!(output.status.success(), "{output:?}");
+        let summary: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        summary["generations"].as_u64().unwrap()
+    };
+
+    assert_eq!(generations(), 0);
+    assert!(generate("one.png").status.success());
+    assert_eq!(generations(), 1);
+
+    assert!(
+        si(&["config", "set", "metrics_enabled", "false"])
+            .status
+            .success()
+    );
+    assert!(generate("two.png").status.success());
+    assert_eq!(generations(), 1);
+
+    let output = si(&["stats", "--since", "3y"]);
+    assert!(!output.status.success());
+}
+
+/// Run `si image generate` with `stdin` piped in, returning the finished process
+fn generate_piped(home: &std::path::Path, args: &[&str], stdin: &[u8]) -> std::process::Output {
+    use std::io::Write;
+    use std::process::Stdio;
+
+    let mut cmd = Command::new(get_binary_path());
+    cmd.args(["image", "generate", "red shirt", "--model", "test-model"])
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    isolate_home_with_model(&mut cmd, home, "test-model");
+
+    let mut child = cmd.spawn().expect("Failed to execute command");
+    child.stdin.take().unwrap().write_all(stdin).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn test_image_generate_stdin_to_stdout() {
+    let temp_dir = tempdir().unwrap();
+    let mut png = std::io::Cursor::new(Vec::new());
+    image::RgbImage::from_pixel(64, 64, image::Rgb([100, 80, 120]))
+        .write_to(&mut png, image::ImageFormat::Png)
+        .unwrap();
+
+    let args = ["--input", "-", "--output", "-", "--format", "png"];
+    let output = generate_piped(temp_dir.path(), &args, png.get_ref());
+    assert!(output.status.success(), "{output:?}");
+    // Status messages go to stderr, so stdout is exactly the image
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Using model: test-model"));
+    let result =
+        image::load_from_memory_with_format(&output.stdout, image::ImageFormat::Png).unwrap();
+    assert_eq!((result.width(), result.height()), (64, 64));
+
+    // Without a format there is no extension to go by
+    let output = generate_piped(
+        temp_dir.path(),
+        &["--input", "-", "--output", "-"],
+        png.get_ref(),
+    );
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--format"));
+
+    let output = generate_piped(temp_dir.path(), &args, b"");
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("empty"));
+}
+
+#[test]
+fn test_image_batch_respects_siignore() {
+    let temp_dir = assert_fs::TempDir::new().unwrap();
+    let input_dir = temp_dir.child("in");
+    let output_dir = temp_dir.child("out");
+    for name in ["a.png", "draft.png", "skip-me.png", "sub/b.png"] {
+        let path = input_dir.child(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        image::RgbImage::from_pixel(64, 64, image::Rgb([100, 80, 120]))
+            .save(path.path())
+            .unwrap();
+    }
+    input_dir
+        .child("notes.txt")
+        .write_str("not an image")
+        .unwrap();
+    input_dir.child(".siignore").write_str("draft*\n").unwrap();
+
+    let mut cmd = Command::new(get_binary_path());
+    cmd.args(["image", "batch", "red shirt", "--model", "test-model"])
+        .arg("--input-dir")
+        .arg(input_dir.path())
+        .arg("--output-dir")
+        .arg(output_dir.path())
+        .args(["--recursive", "--exclude", "skip-*"]);
+    isolate_home_with_model(&mut cmd, temp_dir.path(), "test-model");
+
+    let output = cmd.output().expect("Failed to execute command");
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("2 succeeded, 0 failed, 3 files skipped"));
+
+    output_dir.child("a.png").assert(predicates::path::exists());
+    output_dir
+        .child("sub/b.png")
+        .assert(predicates::path::exists());
+    output_dir
+        .child("draft.png")
+        .assert(predicates::path::missing());
+    output_dir
+        .child("skip-me.png")
+        .assert(predicates::path::missing());
+}
+
+#[test]
+fn test_image_batch_reports_a_missing_model_per_image() {
+    let temp_dir = assert_fs::TempDir::new().unwrap();
+    let input_dir = temp_dir.child("in");
+    for name in ["a.png", "b.png"] {
+        let path = input_dir.child(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        image::RgbImage::from_pixel(64, 64, image::Rgb([100, 80, 120]))
+            .save(path.path())
+            .unwrap();
+    }
+
+    let mut cmd = Command::new(get_binary_path());
+    cmd.args([
+        "--offline",
+        "image",
+        "batch",
+        "red shirt",
+        "--model",
+        "org/absent",
+    ])
+    .arg("--input-dir")
+    .arg(input_dir.path())
+    .arg("--output-dir")
+    .arg(temp_dir.child("out").path());
+    isolate_home_with_model(&mut cmd, temp_dir.path(), "test-model");
+
+    let output = cmd.output().expect("Failed to execute command");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let hint = "Model org/absent isn't downloaded; run `si model download org/absent` first";
+    assert_eq!(stdout.matches(hint).count(), 2, "{stdout}");
+    assert!(stdout.contains("0 succeeded, 2 failed"), "{stdout}");
+}
+
+#[test]
+fn test_image_batch_lenient_recovers_truncated_images() {
+    let temp_dir = assert_fs::TempDir::new().unwrap();
+    let input_dir = temp_dir.child("in");
+    let output_dir = temp_dir.child("out");
+    input_dir.create_dir_all().unwrap();
+    let mut png = std::io::Cursor::new(Vec::new());
+    image::RgbImage::from_fn(64, 64, |x, y| {
+        image::Rgb([(x * 4) as u8, (y * 4) as u8, 90])
+    })
+    .write_to(&mut png, image::ImageFormat::Png)
+    .unwrap();
+    let png = png.into_inner();
+    input_dir.child("whole.png").write_binary(&png).unwrap();
+    input_dir
+        .child("cut.png")
+        .write_binary(&png[..png.len() * 3 / 4])
+        .unwrap();
+
+    let batch = |lenient: bool| {
+        let mut cmd = Command::new(get_binary_path());
+        cmd.args(["image", "batch", "red shirt", "--model", "test-model"])
+            .arg("--input-dir")
+            .arg(input_dir.path())
+            .arg("--output-dir")
+            .arg(output_dir.path());
+        if lenient {
+            cmd.arg("--lenient");
+        }
+        isolate_home_with_model(&mut cmd, temp_dir.path(), "test-model");
+        cmd.output().expect("Failed to execute command")
+    };
+
+    let output = batch(false);
+    assert!(!output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("1 succeeded, 1 failed"), "{stdout}");
+
+    let output = batch(true);
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.contains("2 succeeded (1 recovered), 0 failed"),
+        "{stdout}"
+    );
+    assert!(stdout.contains("Warning:"), "{stdout}");
+    output_dir
+        .child("cut.png")
+        .assert(predicates::path::exists());
+}
+
+#[test]
+fn test_image_generate_refuses_models_that_cant_generate_images() {
+    let temp_dir = assert_fs::TempDir::new().unwrap();
+    let clip_dir = temp_dir.child("clip");
+    clip_dir
+        .child("config.json")
+        .write_str(r#"{"architectures": ["CLIPModel"]}"#)
+        .unwrap();
+    clip_dir
+        .child("model.safetensors")
+        .write_str("weights")
+        .unwrap();
+    let file = |name: &str| {
+        serde_json::json!({
+            "size": 7,
+            "path": clip_dir.child(name).path(),
+            "rfilename": name,
+        })
+    };
+    let index = serde_json::json!({ "models": [
+        { "model_id": "openai/clip-vit-base-patch32", "files": [file("config.json"), file("model.safetensors")] },
+        { "model_id": "test-model", "files": [] },
+    ]});
+    let input_file = temp_dir.child("input.png");
+    image::RgbImage::from_pixel(64, 64, image::Rgb([100, 80, 120]))
+        .save(input_file.path())
+        .unwrap();
+
+    let si = |args: &[&str]| {
+        let mut cmd = Command::new(get_binary_path());
+        cmd.args(args);
+        isolate_home_with_model(&mut cmd, temp_dir.path(), "test-model");
+        std::fs::write(
+            temp_dir.path().join("data/models/model_index.json"),
+            index.to_string(),
+        )
+        .unwrap();
+        cmd.output().expect("Failed to execute command")
+    };
+    let generate = |extra: &[&str]| {
+        let input = input_file.path().to_str().unwrap();
+        let output = temp_dir.child("out.png");
+        let output = output.path().to_str().unwrap();
+        let mut args = vec!["image", "generate", "red shirt", "--input", input];
+        args.extend(["--output", output, "--no-progress"]);
+        args.extend(["--model", "openai/clip-vit-base-patch32"]);
+        args.extend(extra);
+        si(&args)
+    };
+
+    let output = si(&["model", "list", "--compat"]);
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.contains("no  openai/clip-vit-base-patch32"),
+        "{stdout}"
+    );
+    assert!(stdout.contains("?   test-model"), "{stdout}");
+
+    let output = generate(&[]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("can't be used to generate images"),
+        "{stderr}"
+    );
+    assert!(
+        stderr.contains("CLIPModel") && stderr.contains("--force"),
+        "{stderr}"
+    );
+    temp_dir
+        .child("out.png")
+        .assert(predicates::path::missing());
+
+    let output = generate(&["--force"]);
+    assert!(output.status.success(), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Warning: generating with openai/clip-vit-base-patch32 anyway"));
+    temp_dir.child("out.png").assert(predicates::path::exists());
+}
+
+#[test]
+fn test_image_batch_timeout() {
+    let temp_dir = assert_fs::TempDir::new().unwrap();
+    let input_dir = temp_dir.child("in");
+    let output_dir = temp_dir.child("out");
+    input_dir.create_dir_all().unwrap();
+    image::RgbImage::from_pixel(64, 64, image::Rgb([100, 80, 120]))
+        .save(input_dir.child("a.png").path())
+        .unwrap();
+
+    let batch = |timeout: &str| {
+        let mut cmd = Command::new(get_binary_path());
+        cmd.args([
+            "--json",
+            "image",
+            "batch",
+            "red shirt",
+            "--model",
+            "test-model",
+        ])
+        .arg("--input-dir")
+        .arg(input_dir.path())
+        .arg("--output-dir")
+        .arg(output_dir.path())
+        .args(["--timeout", timeout]);
+        isolate_home_with_model(&mut cmd, temp_dir.path(), "test-model");
+        cmd.output().expect("Failed to execute command")
+    };
+
+    let output = batch("2m");
+    assert!(output.status.success(), "{output:?}");
+    let summary: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(summary["succeeded"], 1);
+    assert_eq!(summary["timed_out"], 0);
+
+    for invalid in ["0", "5d", "soon"] {
+        let output = batch(invalid);
+        assert_eq!(output.status.code(), Some(2), "{invalid}: {output:?}");
+        assert!(String::from_utf8_lossy(&output.stderr).contains("--timeout"));
+    }
+}
+
+#[test]
+fn test_image_generate_grid() {
+    let temp_dir = assert_fs::TempDir::new().unwrap();
+    let input_file = temp_dir.child("input.png");
+    let grid_file = temp_dir.child("grid.png");
+
+    image::RgbImage::from_pixel(64, 64, image::Rgb([100, 80, 120]))
+        .save(input_file.path())
+        .unwrap();
+
+    let mut cmd = Command::new(get_binary_path());
+    cmd.args([
+        "image",
+        "generate",
+        "--model",
+        "test-model",
+        "--input",
+        input_file.path().to_str().unwrap(),
+        "--prompt",
+        "red dress",
+        "--prompt",
+        "blue dress",
+        "--prompt",
+        "green dress",
+        "--grid",
+        grid_file.path().to_str().unwrap(),
+        "--columns",
+        "2",
+    ]);
+    isolate_home_with_model(&mut cmd, temp_dir.path(), "test-model");
+
+    let output = cmd.output().expect("Failed to execute command");
+
+    assert!(output.status.success());
+    grid_file.assert(predicates::path::exists());
+    let grid = image::open(grid_file.path()).unwrap();
+    assert!(grid.width() > 2 * 32 && grid.height() > 2 * 32);
 }
 
 #[test]
@@ -255,6 +1224,30 @@ fn test_model_download_missing_name() {
     assert!(stderr.contains("required") || stderr.contains("missing"));
 }
 
+#[test]
+fn test_model_download_checks_the_model_id() {
+    let temp_dir = tempdir().unwrap();
+    let home = temp_dir.path();
+    let run = |args: &[&str]| {
+        let mut cmd = Command::new(get_binary_path());
+        cmd.args(args);
+        isolate_home_with_model(&mut cmd, home, "org/model");
+        cmd.output().expect("Failed to execute command")
+    };
+
+    let output = run(&["model", "download", "org/na me"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Invalid model id `org/na me`"), "{stderr}");
+
+    let url = "https://huggingface.co/Org/Other/tree/main";
+    let output = run(&["model", "download", url, "--queue"]);
+    assert!(output.status.success());
+    let output = run(&["model", "queue", "list"]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("pending  Org/Other"), "{stdout}");
+}
+
 #[test]
 fn test_image_generate_missing_arguments() {
     let mut cmd = Command::new(get_binary_path());
@@ -293,3 +1286,628 @@ fn test_config_get_missing_key() {
     let stderr = String::from_utf8(output.stderr).unwrap();
     assert!(stderr.contains("required") || stderr.contains("missing"));
 }
+
+// Other platforms don't read the config from XDG_CONFIG_HOME
+#[cfg(target_os = "linux")]
+#[test]
+fn test_image_generate_runs_post_hook_unless_disabled() {
+    let temp_dir = assert_fs::TempDir::new().unwrap();
+    let input_file = temp_dir.child("input.png");
+    let output_file = temp_dir.child("output.png");
+    let marker = temp_dir.child("hook.marker");
+    image::RgbImage::from_pixel(64, 64, image::Rgb([100, 80, 120]))
+        .save(input_file.path())
+        .unwrap();
+
+    let config_dir = temp_dir.path().join("config").join("si");
+    std::fs::create_dir_all(&config_dir).unwrap();
+    std::fs::write(
+        config_dir.join("config.toml"),
+        format!(
+            "[hooks.post_generate]\ncommand = \"echo {{output}} > '{}'\"\n",
+            marker.path().display()
+        ),
+    )
+    .unwrap();
+
+    let generate = |extra: &[&str]| {
+        let mut cmd = Command::new(get_binary_path());
+        cmd.args([
+            "image",
+            "generate",
+            "red dress",
+            "--model",
+            "test-model",
+            "--input",
+            input_file.path().to_str().unwrap(),
+            "--output",
+            output_file.path().to_str().unwrap(),
+        ]);
+        cmd.args(extra);
+        isolate_home_with_model(&mut cmd, temp_dir.path(), "test-model");
+        cmd.output().expect("Failed to execute command")
+    };
+
+    assert!(generate(&["--no-hooks"]).status.success());
+    marker.assert(predicates::path::missing());
+
+    assert!(generate(&[]).status.success());
+    marker.assert(predicates::str::contains("output.png"));
+}
+
+#[test]
+fn test_model_list_filter_and_pagination() {
+    let temp_dir = tempdir().unwrap();
+    let mut cmd = Command::new(get_binary_path());
+    cmd.args([
+        "model", "list", "--filter", "ORG/*", "--offset", "1", "--limit", "1",
+    ]);
+    isolate_home_with_model(&mut cmd, temp_dir.path(), "other/model");
+    let models: Vec<String> = ["org/a", "org/b", "org/c", "other/model"]
+        .iter()
+        .map(|id| format!(r#"{{"model_id": "{id}", "files": []}}"#))
+        .collect();
+    std::fs::write(
+        temp_dir.path().join("data/models/model_index.json"),
+        format!(r#"{{"models": [{}]}}"#, models.join(",")),
+    )
+    .unwrap();
+
+    let output = cmd.output().expect("Failed to execute command");
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("org/b"));
+    assert!(!stdout.contains("org/a"));
+    assert!(!stdout.contains("org/c"));
+    assert!(!stdout.contains("other/model"));
+}
+
+#[test]
+fn test_models_dir_flag_overrides_si_data_dir() {
+    let temp_dir = tempdir().unwrap();
+    let mut cmd = Command::new(get_binary_path());
+    isolate_home_with_model(&mut cmd, temp_dir.path(), "org/default-location");
+    let elsewhere = temp_dir.path().join("elsewhere");
+    std::fs::create_dir_all(&elsewhere).unwrap();
+    std::fs::write(
+        elsewhere.join("model_index.json"),
+        r#"{"models": [{"model_id": "org/flagged", "files": []}]}"#,
+    )
+    .unwrap();
+    // Global, so it is accepted after the subcommand too
+    cmd.args(["model", "list", "--models-dir"]).arg(&elsewhere);
+
+    let output = cmd.output().expect("Failed to execute command");
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("org/flagged"));
+    assert!(!stdout.contains("org/default-location"));
+}
+
+#[test]
+fn test_model_delete_filter_dry_run_then_delete() {
+    let temp_dir = tempdir().unwrap();
+    let home = temp_dir.path();
+    let run = |args: &[&str]| {
+        let mut cmd = Command::new(get_binary_path());
+        cmd.args(args);
+        cmd.env("HOME", home);
+        cmd.env("SI_DATA_DIR", home.join("data"));
+        cmd.env("XDG_CONFIG_HOME", home.join("config"));
+        cmd.env("HF_HUB_CACHE", home.join("hf-cache"));
+        cmd.env("SI_CACHE_DIR", home.join("cache"));
+        cmd.output().expect("Failed to execute command")
+    };
+    let ids = ["testing/a", "testing/b", "testing/pinned", "keep/model"];
+    let models: Vec<String> = ids
+        .iter()
+        .map(|id| {
+            let repo = home
+                .join("hf-cache")
+                .join(format!("models--{}", id.replace('/', "--")));
+            let file = repo.join("snapshots/main/model.safetensors");
+            std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+            std::fs::write(&file, "weights").unwrap();
+            let pinned = id.ends_with("pinned");
+            format!(
+                r#"{{"model_id": "{id}", "pinned": {pinned}, "files": [{{"size": 7, "path": "{}", "rfilename": "model.safetensors"}}]}}"#,
+                file.display()
+            )
+        })
+        .collect();
+    let index = home.join("data/models/model_index.json");
+    std::fs::create_dir_all(index.parent().unwrap()).unwrap();
+    std::fs::write(&index, format!(r#"{{"models": [{}]}}"#, models.join(","))).unwrap();
+    let repo = |id: &str| {
+        home.join("hf-cache")
+            .join(format!("models--{}", id.replace('/', "--")))
+    };
+
+    let output = run(&["model", "delete", "--filter", "testing/*", "--dry-run"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("testing/a"));
+    assert!(stdout.contains("testing/b"));
+    assert!(stdout.contains("skipped (pinned)"));
+    assert!(!stdout.contains("keep/model"));
+    assert!(stdout.contains("Would delete 2 models"));
+    assert!(ids.iter().all(|id| repo(id).exists()));
+
+    let output = run(&["model", "delete", "--filter", "testing/*"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("Deleted 2 models"));
+    assert!(!repo("testing/a").exists());
+    assert!(!repo("testing/b").exists());
+    assert!(repo("testing/pinned").exists());
+    assert!(repo("keep/model").exists());
+    let index = std::fs::read_to_string(&index).unwrap();
+    assert!(!index.contains("testing/a") && !index.contains("testing/b"));
+    assert!(index.contains("testing/pinned") && index.contains("keep/model"));
+
+    // A name can't be combined with a bulk selection
+    let output = run(&["model", "delete", "keep/model", "--all"]);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_model_list_and_show_file_without_a_name() {
+    let temp_dir = tempdir().unwrap();
+    let home = temp_dir.path();
+    let run = |args: &[&str]| {
+        let mut cmd = Command::new(get_binary_path());
+        cmd.args(args);
+        isolate_home_with_model(&mut cmd, home, "org/placeholder");
+        // An entry whose path has no final component
+        std::fs::write(
+            home.join("data/models/model_index.json"),
+            r#"{"models": [{"model_id": "org/rooted", "files": [{"size": 0, "path": "/"}]}]}"#,
+        )
+        .unwrap();
+        cmd.output().expect("Failed to execute command")
+    };
+
+    let output = run(&["model", "list"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("org/rooted"));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("without a UTF-8 name: /"));
+
+    let output = run(&["model", "show", "org/rooted"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("\n  / (0 B)"));
+}
+
+#[test]
+fn test_model_repair_merges_duplicate_entries() {
+    let temp_dir = tempdir().unwrap();
+    let home = temp_dir.path();
+    let run = |args: &[&str]| {
+        let mut cmd = Command::new(get_binary_path());
+        cmd.args(args);
+        cmd.env("HOME", home);
+        cmd.env("SI_DATA_DIR", home.join("data"));
+        cmd.env("XDG_CONFIG_HOME", home.join("config"));
+        cmd.env("HF_HUB_CACHE", home.join("hf-cache"));
+        cmd.env("SI_CACHE_DIR", home.join("cache"));
+        cmd.output().expect("Failed to execute command")
+    };
+    let blob = home.join("hf-cache/models--org--model/blobs/0123abcd");
+    std::fs::create_dir_all(blob.parent().unwrap()).unwrap();
+    std::fs::write(&blob, "weights").unwrap();
+    let file = format!(r#"{{"size": 7, "path": "{}"}}"#, blob.display());
+    let index = home.join("data/models/model_index.json");
+    std::fs::create_dir_all(index.parent().unwrap()).unwrap();
+    let messy = format!(
+        r#"{{"models": [{{"model_id": "org/model", "files": []}}, {{"model_id": "Org/Model ", "files": [{file}, {file}]}}]}}"#
+    );
+    std::fs::write(&index, &messy).unwrap();
+
+    let output = run(&["model", "repair", "--dry-run"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("Removed empty duplicate `org/model` of `Org/Model`"));
+    assert!(
+        stdout.contains("Would repair 1 duplicate entry, 1 id with whitespace, 1 repeated file.")
+    );
+    assert_eq!(std::fs::read_to_string(&index).unwrap(), messy);
+
+    let output = run(&["model", "repair"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("Repaired 1 duplicate entry"));
+    assert_eq!(
+        std::fs::read_to_string(home.join("data/models/model_index.json.bak")).unwrap(),
+        messy
+    );
+    let repaired: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&index).unwrap()).unwrap();
+    let models = repaired["models"].as_array().unwrap();
+    assert_eq!(models.len(), 1);
+    assert_eq!(models[0]["model_id"], "Org/Model");
+    assert_eq!(models[0]["files"].as_array().unwrap().len(), 1);
+
+    let output = run(&["model", "repair"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("no duplicates to repair"));
+}
+
+#[test]
+fn test_model_download_queue() {
+    let temp_dir = tempdir().unwrap();
+    let home = temp_dir.path();
+    let run = |args: &[&str]| {
+        let mut cmd = Command::new(get_binary_path());
+        cmd.args(args);
+        isolate_home_with_model(&mut cmd, home, "org/model");
+        cmd.output().expect("Failed to execute command")
+    };
+
+    for name in ["org/model", "org/other@v2", "org/model"] {
+        let output = run(&["model", "download", name, "--queue"]);
+        assert!(output.status.success());
+    }
+    let output = run(&["model", "queue", "list"]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("#1    pending  org/model"), "{stdout}");
+    assert!(stdout.contains("#2    pending  org/other@v2"), "{stdout}");
+    assert_eq!(stdout.matches("org/model").count(), 1, "{stdout}");
+
+    // Offline, the queue is left as it is
+    let output = run(&["--offline", "model", "queue", "run"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("offline"));
+
+    let output = run(&["model", "queue", "remove", "2"]);
+    assert!(output.status.success());
+    // The one model left is already indexed, so nothing is fetched
+    let output = run(&["model", "queue", "run"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("org/model: already downloaded"), "{stdout}");
+    assert!(stdout.contains("0 still queued"), "{stdout}");
+
+    let output = run(&["model", "queue", "remove", "--done"]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Removed 1 finished download"));
+    let output = run(&["model", "queue", "list"]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("The download queue is empty."));
+}
+
+#[test]
+fn test_model_open_prints_the_snapshot_when_piped() {
+    let temp_dir = tempdir().unwrap();
+    let home = temp_dir.path();
+    let repo = home.join("hf-cache/models--org--model");
+    let blob = repo.join("blobs/0123abcd");
+    let snapshot = repo.join("snapshots/abc");
+    std::fs::create_dir_all(blob.parent().unwrap()).unwrap();
+    std::fs::create_dir_all(&snapshot).unwrap();
+    std::fs::create_dir_all(repo.join("refs")).unwrap();
+    std::fs::write(&blob, "weights").unwrap();
+    std::fs::write(repo.join("refs/main"), "abc").unwrap();
+    let index = home.join("data/models/model_index.json");
+    std::fs::create_dir_all(index.parent().unwrap()).unwrap();
+    std::fs::write(
+        &index,
+        format!(
+            r#"{{"models": [{{"model_id": "org/model", "files": [{{"size": 7, "path": "{}", "rfilename": "model.safetensors"}}]}}]}}"#,
+            blob.display()
+        ),
+    )
+    .unwrap();
+
+    for args in [
+        &["model", "open", "org/model"][..],
+        &["model", "open", "org/model", "--print"],
+    ] {
+        let mut cmd = Command::new(get_binary_path());
+        cmd.args(args);
+        cmd.env("HOME", home);
+        cmd.env("SI_DATA_DIR", home.join("data"));
+        cmd.env("XDG_CONFIG_HOME", home.join("config"));
+        cmd.env("HF_HUB_CACHE", home.join("hf-cache"));
+        cmd.env("SI_CACHE_DIR", home.join("cache"));
+        let output = cmd.output().expect("Failed to execute command");
+
+        assert!(output.status.success());
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert_eq!(stdout.trim_end(), snapshot.display().to_string());
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_image_styles_list_includes_user_styles() {
+    let temp_dir = tempdir().unwrap();
+    let config_dir = temp_dir.path().join("config").join("si");
+    std::fs::create_dir_all(&config_dir).unwrap();
+    std::fs::write(
+        config_dir.join("styles.toml"),
+        "[styles.tweed]\nkeywords = [\"tweed\", \"harris\"]\ncontrast = 1.1\n",
+    )
+    .unwrap();
+    let run = || {
+        let mut cmd = Command::new(get_binary_path());
+        cmd.args(["image", "styles", "list"]);
+        isolate_home_with_model(&mut cmd, temp_dir.path(), "test-model");
+        cmd.output().expect("Failed to execute command")
+    };
+
+    let output = run();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("silk"));
+    assert!(stdout.contains("satin"));
+    assert!(stdout.lines().any(|line| line.starts_with("u tweed")));
+    assert!(stdout.contains("tweed, harris"));
+
+    std::fs::write(
+        config_dir.join("styles.toml"),
+        "[styles.tweed]\nsheen = \"high\"\n",
+    )
+    .unwrap();
+    let output = run();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("styles.toml"));
+}
+
+#[test]
+fn test_image_bench_smoke() {
+    let temp_dir = tempdir().unwrap();
+    let mut cmd = Command::new(get_binary_path());
+    cmd.args([
+        "image",
+        "bench",
+        "--size",
+        "64",
+        "--iterations",
+        "1",
+        "--json",
+    ]);
+    isolate_home_with_model(&mut cmd, temp_dir.path(), "test-model");
+
+    let output = cmd.output().expect("Failed to execute command");
+    assert!(output.status.success(), "{output:?}");
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["backend"], "heuristic");
+    assert_eq!(report["size"], 64);
+    assert_eq!(report["iterations"], 1);
+    assert!(!report["device"].as_str().unwrap().is_empty());
+    let (min, median, max) = (
+        report["min_ms"].as_f64().unwrap(),
+        report["median_ms"].as_f64().unwrap(),
+        report["max_ms"].as_f64().unwrap(),
+    );
+    assert!(min <= median && median <= max);
+    assert!(report["pixels_per_sec"].as_f64().unwrap() > 0.0);
+
+    let mut cmd = Command::new(get_binary_path());
+    cmd.args(["image", "bench", "--backend", "candle"]);
+    isolate_home_with_model(&mut cmd, temp_dir.path(), "test-model");
+    let output = cmd.output().expect("Failed to execute command");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("candle"));
+}
+
+#[test]
+fn test_init_writes_config_once() {
+    let temp_dir = tempdir().unwrap();
+    let models_dir = temp_dir.path().join("models");
+    let config_path = temp_dir
+        .path()
+        .join("config")
+        .join("si")
+        .join("config.toml");
+    let run = |extra: &[&str]| {
+        let mut cmd = Command::new(get_binary_path());
+        cmd.args(["init", "--yes", "--models-dir"])
+            .arg(&models_dir)
+            .args(extra);
+        isolate_home_with_model(&mut cmd, temp_dir.path(), "test-model");
+        cmd.output().expect("Failed to execute command")
+    };
+
+    let output = run(&[]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("Wrote config"), "{stdout}");
+    assert!(stdout.contains("Hugging Face token"), "{stdout}");
+    let config = std::fs::read_to_string(&config_path).unwrap();
+    assert!(
+        config.contains(r#"default_model = "runwayml/stable-diffusion-v1-5""#),
+        "{config}"
+    );
+    assert!(
+        config.contains(&format!(
+            "models_dir = {:?}",
+            models_dir.display().to_string()
+        )),
+        "{config}"
+    );
+
+    // A second run leaves the file alone, whatever it is given
+    let output = run(&["--default-model", "org/other"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Keeping the existing config"));
+    assert_eq!(std::fs::read_to_string(&config_path).unwrap(), config);
+
+    let output = run(&["--default-model", "org/other", "--force"]);
+    assert!(output.status.success());
+    let forced = std::fs::read_to_string(&config_path).unwrap();
+    assert!(
+        forced.contains(r#"default_model = "org/other""#),
+        "{forced}"
+    );
+}
+
+#[test]
+fn test_json_flag_prints_one_document_per_command() {
+    let temp_dir = assert_fs::TempDir::new().unwrap();
+    let input_file = temp_dir.child("input.png");
+    image::RgbImage::from_pixel(64, 64, image::Rgb([100, 80, 120]))
+        .save(input_file.path())
+        .unwrap();
+    let output_file = temp_dir.child("output.png");
+
+    // Parses stdout as a whole, so any stray line there fails the test
+    let si_json = |args: &[&str]| -> serde_json::Value {
+        let mut cmd = Command::new(get_binary_path());
+        cmd.arg("--json").args(args);
+        isolate_home_with_model(&mut cmd, temp_dir.path(), "test-model");
+        let output = cmd.output().expect("Failed to execute command");
+        assert!(output.status.success(), "{output:?}");
+        serde_json::from_slice(&output.stdout).unwrap_or_else(|e| {
+            panic!(
+                "stdout of {args:?} isn't one JSON document ({e}):\n{}",
+                String::from_utf8_lossy(&output.stdout)
+            )
+        })
+    };
+
+    let models = si_json(&["model", "list"]);
+    assert_eq!(models.as_array().unwrap().len(), 1);
+    assert_eq!(models[0]["model_id"], "test-model");
+
+    let setup = si_json(&["init", "--yes"]);
+    assert!(setup["config_path"].is_string());
+    assert!(setup["checks"].as_array().is_some_and(|c| !c.is_empty()));
+
+    let config = si_json(&["config", "show"]);
+    assert_eq!(config["default_model"], setup["default_model"]);
+
+    let result = si_json(&[
+        "image",
+        "generate",
+        "red shirt",
+        "--model",
+        "test-model",
+        "--input",
+        input_file.path().to_str().unwrap(),
+        "--output",
+        output_file.path().to_str().unwrap(),
+    ]);
+    assert_eq!(result["model_used"], "test-model");
+    assert_eq!(result["output_path"], output_file.path().to_str().unwrap());
+    assert!(result["mask_coverage"].as_f64().is_some());
+    output_file.assert(predicates::path::exists());
+
+    let deleted = si_json(&["model", "delete", "--all", "--dry-run"]);
+    assert_eq!(deleted[0]["model_id"], "test-model");
+    assert_eq!(deleted[0]["outcome"], "would_delete");
+
+    // The flag is global, so it also goes after the subcommand
+    let mut cmd = Command::new(get_binary_path());
+    cmd.args(["model", "list", "--json"]);
+    isolate_home_with_model(&mut cmd, temp_dir.path(), "test-model");
+    let output = cmd.output().expect("Failed to execute command");
+    let models: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(models[0]["model_id"], "test-model");
+}
+
+#[test]
+fn test_image_generate_rejects_contradictory_flags_with_usage() {
+    let cases: &[(&[&str], &str)] = &[
+        (
+            &["--strength-ramp", "0.2,1.5"],
+            "invalid value '1.5' for '--strength-ramp <STRENGTH_RAMP>'",
+        ),
+        (
+            &["--strength-ramp", "0.2,0.4", "--comparison", "cmp.png"],
+            "'--strength-ramp <STRENGTH_RAMP>' cannot be used with '--comparison <COMPARISON>'",
+        ),
+        (&["--output", "-"], "--format <FORMAT>"),
+        (
+            &["--crop", "0,0,10,10", "--auto-center"],
+            "'--crop <CROP>' cannot be used with '--auto-center'",
+        ),
+        (
+            &["--polygon", "10,10 20,20"],
+            "invalid value '10,10 20,20' for '--polygon <POLYGON>'",
+        ),
+        (&["--mask-mode", "polygon"], "--polygon <POLYGON>"),
+        (
+            &["--output", "-", "--format", "png", "--strength-ramp", "0.5"],
+            "Writing to stdout doesn't work with --strength-ramp",
+        ),
+        (
+            &["--output", "out.png", "--format", "jpeg"],
+            "--format only applies when writing to stdout",
+        ),
+        (&["--texture-opacity", "0.5"], "--texture <NAME|PATH|auto>"),
+        (
+            &["--texture", "denim", "--texture-scale", "0"],
+            "Texture scale must be a positive number",
+        ),
+        (
+            &["--texture", "denim", "--texture-blend", "screen"],
+            "Unknown texture blend `screen`",
+        ),
+    ];
+    for (flags, message) in cases {
+        let mut cmd = Command::new(get_binary_path());
+        cmd.args(["image", "generate", "red shirt", "--input", "in.png"])
+            .args(*flags);
+        let output = cmd.output().expect("Failed to execute command");
+
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert_eq!(output.status.code(), Some(2), "{flags:?}: {stderr}");
+        assert!(stderr.contains(message), "{flags:?}: {stderr}");
+        assert!(stderr.starts_with("error: "), "{flags:?}: {stderr}");
+        assert!(stderr.contains("try '--help'"), "{flags:?}: {stderr}");
+    }
+
+    // Stdin only turns up in the values, so clap alone can't catch these
+    let mut cmd = Command::new(get_binary_path());
+    cmd.args([
+        "image",
+        "generate",
+        "red shirt",
+        "--input",
+        "-",
+        "--output",
+        "x.png",
+        "--dry-run",
+    ]);
+    let output = cmd.output().expect("Failed to execute command");
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("--dry-run needs the input image as a file"));
+    assert!(stderr.contains("Usage: si image generate"), "{stderr}");
+}
+
+#[test]
+fn test_model_evict_dry_run_lists_models_over_budget() {
+    let temp_dir = tempdir().unwrap();
+    let si = |args: &[&str]| {
+        let mut cmd = Command::new(get_binary_path());
+        cmd.args(args);
+        isolate_home_with_model(&mut cmd, temp_dir.path(), "org/cached");
+        cmd.output().expect("Failed to execute command")
+    };
+    let blobs = temp_dir.path().join("hf-cache/models--org--cached/blobs");
+    std::fs::create_dir_all(&blobs).unwrap();
+    std::fs::write(blobs.join("weights"), [0; 100]).unwrap();
+
+    let output = si(&["model", "evict"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("cache_max_bytes"));
+
+    assert!(
+        si(&["config", "set", "cache_max_bytes", "10"])
+            .status
+            .success()
+    );
+    let output = si(&["model", "evict", "--dry-run", "--json"]);
+    assert!(output.status.success());
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["cache_bytes"], 100);
+    assert_eq!(report["remaining_bytes"], 0);
+    assert_eq!(report["evicted"][0]["model_id"], "org/cached");
+    assert_eq!(report["evicted"][0]["reason"], "size");
+    assert!(blobs.join("weights").exists());
+}
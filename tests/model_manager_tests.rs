@@ -1,8 +1,27 @@
 use anyhow::Result;
-use si::models::{ModelFile, ModelInfo, ModelManagerBuilder};
+use si::diffusers_config::DiffusersConfig;
+use si::models::{DownloadOptions, ModelFile, ModelInfo, ModelManager, ModelManagerBuilder};
+use si::source::{MOCK_REVISION, MockSource};
 use std::fs;
+use std::path::{Path, PathBuf};
 use tempfile::tempdir;
 
+const TEST_MODEL_ID: &str = "hf-internal-testing/tiny-stable-diffusion-torch";
+
+fn fixtures_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/hub")
+}
+
+/// A manager that serves `tests/fixtures/hub` instead of the Hugging Face Hub
+fn mock_manager(models_dir: &Path, cache_dir: &Path) -> Result<ModelManager> {
+    let source =
+        MockSource::new(fixtures_dir(), cache_dir).with_license(TEST_MODEL_ID, "apache-2.0");
+    ModelManagerBuilder::new()
+        .with_models_dir(models_dir.to_path_buf())
+        .with_source(Box::new(source))
+        .build()
+}
+
 #[tokio::test]
 async fn test_model_manager_creation() -> Result<()> {
     let temp_dir = tempdir()?;
@@ -151,10 +170,16 @@ fn test_model_index_persistence() -> Result<()> {
             ModelFile {
                 size: 1024,
                 path: temp_dir.path().join("model1.bin"),
+                sha256: None,
+                rfilename: None,
+                format: None,
             },
             ModelFile {
                 size: 256,
                 path: temp_dir.path().join("config1.json"),
+                sha256: None,
+                rfilename: None,
+                format: None,
             },
         ],
     );
@@ -164,6 +189,9 @@ fn test_model_index_persistence() -> Result<()> {
         vec![ModelFile {
             size: 2048,
             path: temp_dir.path().join("model2.bin"),
+            sha256: None,
+            rfilename: None,
+            format: None,
         }],
     );
 
@@ -351,6 +379,9 @@ fn test_model_file_edge_cases() -> Result<()> {
     let model_file = ModelFile {
         size: 0,
         path: std::path::PathBuf::new(),
+        sha256: None,
+        rfilename: None,
+        format: None,
     };
 
     let json = serde_json::to_string(&model_file)?;
@@ -363,6 +394,9 @@ fn test_model_file_edge_cases() -> Result<()> {
     let large_model_file = ModelFile {
         size: u64::MAX,
         path: std::path::PathBuf::from("/very/long/path/to/a/model/file.bin"),
+        sha256: None,
+        rfilename: None,
+        format: None,
     };
 
     let json = serde_json::to_string(&large_model_file)?;
@@ -381,6 +415,9 @@ fn test_model_info_with_special_characters() -> Result<()> {
         vec![ModelFile {
             size: 1024,
             path: std::path::PathBuf::from("/path/with spaces/and-special-chars!.bin"),
+            sha256: None,
+            rfilename: None,
+            format: None,
         }],
     );
 
@@ -398,6 +435,7 @@ fn test_model_info_with_special_characters() -> Result<()> {
 async fn test_model_download_and_index_persistence() -> Result<()> {
     let temp_dir = tempdir()?;
     let models_dir = temp_dir.path().join("models");
+    let cache_dir = temp_dir.path().join("cache");
     fs::create_dir_all(&models_dir)?;
 
     // Create an initially empty model index
@@ -405,64 +443,143 @@ async fn test_model_download_and_index_persistence() -> Result<()> {
     let empty_index_data = r#"{"models": []}"#;
     fs::write(&index_path, empty_index_data)?;
 
-    if let Ok(manager) = ModelManagerBuilder::new()
-        .with_models_dir(models_dir.clone())
-        .build()
-    {
-        // Verify index is initially empty
-        let initial_models = manager.list_models()?;
-        assert_eq!(initial_models.len(), 0);
-
-        // Download a small test model (this will fail in CI/test environments without network)
-        // We use a very small model to minimize test time and bandwidth
-        let test_model_id = "hf-internal-testing/tiny-stable-diffusion-torch";
-
-        match manager.download_model(test_model_id).await {
-            Ok(downloaded_model) => {
-                // Verify the ModelInfo was created correctly
-                assert_eq!(downloaded_model.model_id, test_model_id);
-                assert!(
-                    !downloaded_model.files.is_empty(),
-                    "Downloaded model should have files"
-                );
-
-                // Verify files have valid sizes and paths
-                for file in &downloaded_model.files {
-                    assert!(file.size > 0, "File size should be greater than 0");
-                    assert!(file.path.exists(), "Downloaded file should exist on disk");
-                }
+    let manager = mock_manager(&models_dir, &cache_dir)?;
 
-                // Create a new manager instance to verify automatic persistence
-                let new_manager = ModelManagerBuilder::new()
-                    .with_models_dir(models_dir)
-                    .build()?;
-
-                let persisted_models = new_manager.list_models()?;
-                assert_eq!(persisted_models.len(), 1);
-                assert_eq!(persisted_models[0].model_id, test_model_id);
-                assert_eq!(
-                    persisted_models[0].files.len(),
-                    downloaded_model.files.len(),
-                    "Persisted model should have same number of files as downloaded model"
-                );
-
-                // Verify file information was preserved
-                for (original, persisted) in downloaded_model
-                    .files
-                    .iter()
-                    .zip(persisted_models[0].files.iter())
-                {
-                    assert_eq!(original.size, persisted.size);
-                    assert_eq!(original.path, persisted.path);
-                }
-            }
-            Err(_) => {
-                // Skip test if network is unavailable or model download fails
-                // This is acceptable for CI environments
-                println!("Skipping model download test - network unavailable or download failed");
-            }
-        }
+    // Verify index is initially empty
+    let initial_models = manager.list_models()?;
+    assert_eq!(initial_models.len(), 0);
+
+    let downloaded_model = manager.download_model(TEST_MODEL_ID).await?;
+
+    // Verify the ModelInfo was created correctly
+    assert_eq!(downloaded_model.model_id, TEST_MODEL_ID);
+    assert_eq!(downloaded_model.license.as_deref(), Some("apache-2.0"));
+    let names: Vec<_> = downloaded_model
+        .files
+        .iter()
+        .map(|f| f.path.strip_prefix(&cache_dir).unwrap().to_path_buf())
+        .collect();
+    let snapshot = PathBuf::from("models--hf-internal-testing--tiny-stable-diffusion-torch")
+        .join("snapshots")
+        .join(MOCK_REVISION);
+    assert_eq!(
+        names,
+        [
+            snapshot.join("README.md"),
+            snapshot.join("model_index.json"),
+            snapshot.join("unet/config.json"),
+            snapshot.join("unet/diffusion_pytorch_model.bin"),
+        ]
+    );
+
+    // Verify files have valid sizes and paths
+    for file in &downloaded_model.files {
+        assert!(file.size > 0, "File size should be greater than 0");
+        assert_eq!(file.size, fs::metadata(&file.path)?.len());
+    }
+
+    // Create a new manager instance to verify automatic persistence
+    let new_manager = mock_manager(&models_dir, &cache_dir)?;
+    let persisted_models = new_manager.list_models()?;
+    assert_eq!(persisted_models.len(), 1);
+    assert_eq!(persisted_models[0].model_id, TEST_MODEL_ID);
+    assert_eq!(
+        persisted_models[0].files.len(),
+        downloaded_model.files.len(),
+        "Persisted model should have same number of files as downloaded model"
+    );
+
+    // Verify file information was preserved
+    for (original, persisted) in downloaded_model
+        .files
+        .iter()
+        .zip(persisted_models[0].files.iter())
+    {
+        assert_eq!(original.size, persisted.size);
+        assert_eq!(original.path, persisted.path);
     }
 
     Ok(())
 }
+
+#[tokio::test]
+async fn test_model_download_with_include_filter() -> Result<()> {
+    let temp_dir = tempdir()?;
+    let manager = mock_manager(
+        &temp_dir.path().join("models"),
+        &temp_dir.path().join("cache"),
+    )?;
+
+    let options = DownloadOptions {
+        include: vec!["unet/*.json".to_string()],
+        fetch_docs: false,
+        ..Default::default()
+    };
+    let model = manager
+        .download_model_with_options(TEST_MODEL_ID, &options)
+        .await?;
+
+    assert_eq!(model.files.len(), 1);
+    assert!(model.files[0].path.ends_with("unet/config.json"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_read_model_config_of_downloaded_model() -> Result<()> {
+    let temp_dir = tempdir()?;
+    let manager = mock_manager(
+        &temp_dir.path().join("models"),
+        &temp_dir.path().join("cache"),
+    )?;
+    manager.download_model(TEST_MODEL_ID).await?;
+
+    let DiffusersConfig::Pipeline(config) = manager.read_model_config(TEST_MODEL_ID)? else {
+        panic!("expected a diffusers layout");
+    };
+    assert_eq!(config.index.class_name, "StableDiffusionPipeline");
+    assert_eq!(config.index.diffusers_version.as_deref(), Some("0.8.0"));
+    assert!(config.root.join("model_index.json").is_file());
+    assert_eq!(config.scheduler, None);
+
+    assert!(manager.read_model_config("org/not-downloaded").is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_model_download_unknown_repo_leaves_index_untouched() -> Result<()> {
+    let temp_dir = tempdir()?;
+    let manager = mock_manager(
+        &temp_dir.path().join("models"),
+        &temp_dir.path().join("cache"),
+    )?;
+
+    assert!(manager.download_model("org/does-not-exist").await.is_err());
+    assert!(manager.list_models()?.is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_sync_indexes_mock_downloads() -> Result<()> {
+    let temp_dir = tempdir()?;
+    let cache_dir = temp_dir.path().join("cache");
+    let manager = mock_manager(&temp_dir.path().join("models"), &cache_dir)?;
+    manager.download_model(TEST_MODEL_ID).await?;
+
+    // A second manager with its own empty index finds the cached model
+    let other_models_dir = temp_dir.path().join("other_models");
+    let other = mock_manager(&other_models_dir, &cache_dir)?;
+
+    let dry_run = other.sync_models(true).await?;
+    assert_eq!(dry_run.discrepancies_count(), 1);
+    assert!(other.list_models()?.is_empty());
+
+    let synced = other.sync_models(false).await?;
+    assert_eq!(synced.discrepancies_count(), 1);
+    let models = other.list_models()?;
+    assert_eq!(models.len(), 1);
+    assert_eq!(models[0].model_id, TEST_MODEL_ID);
+
+    let again = other.sync_models(false).await?;
+    assert_eq!(again.discrepancies_count(), 0);
+    Ok(())
+}
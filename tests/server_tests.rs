@@ -0,0 +1,220 @@
+#![cfg(feature = "server")]
+
+use std::{
+    fs,
+    io::Cursor,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::Result;
+use base64::{Engine, engine::general_purpose::STANDARD};
+use image::{ImageFormat, Rgb, RgbImage};
+use serde_json::{Value, json};
+use si::{
+    CancelToken, ModelManager, ModelManagerBuilder, VirtualTryOn, server, source::MockSource,
+};
+use tempfile::tempdir;
+use tokio::net::TcpListener;
+
+const TEST_MODEL_ID: &str = "hf-internal-testing/tiny-stable-diffusion-torch";
+
+fn mock_manager(root: &Path) -> Result<ModelManager> {
+    let fixtures = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/hub");
+    let models_dir = root.join("models");
+    fs::create_dir_all(&models_dir)?;
+    fs::write(
+        models_dir.join("model_index.json"),
+        r#"{"models": [{"model_id": "test/model", "files": []}]}"#,
+    )?;
+    ModelManagerBuilder::new()
+        .with_models_dir(models_dir)
+        .with_source(Box::new(MockSource::new(fixtures, root.join("cache"))))
+        .build()
+}
+
+/// Start a server on an ephemeral port, returning its base URL
+async fn start_server(root: &Path) -> Result<String> {
+    let tryon = VirtualTryOn::new(mock_manager(root)?)?;
+    let router = server::router(mock_manager(root)?, tryon, 2)?;
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let url = format!("http://{}", listener.local_addr()?);
+    tokio::spawn(server::serve(listener, router, CancelToken::new()));
+    Ok(url)
+}
+
+fn png_base64(width: u32, height: u32) -> Result<String> {
+    let mut png = Cursor::new(Vec::new());
+    RgbImage::from_pixel(width, height, Rgb([180, 40, 40])).write_to(&mut png, ImageFormat::Png)?;
+    Ok(STANDARD.encode(png.into_inner()))
+}
+
+#[tokio::test]
+async fn test_health_and_models() -> Result<()> {
+    let temp_dir = tempdir()?;
+    let url = start_server(temp_dir.path()).await?;
+    let client = reqwest::Client::new();
+
+    let health = client.get(format!("{url}/healthz")).send().await?;
+    assert_eq!(health.status(), 200);
+
+    let models: Value = client
+        .get(format!("{url}/models"))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    assert_eq!(models[0]["model_id"], "test/model");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_generate_returns_png() -> Result<()> {
+    let temp_dir = tempdir()?;
+    let url = start_server(temp_dir.path()).await?;
+
+    let response = reqwest::Client::new()
+        .post(format!("{url}/generate"))
+        .json(&json!({
+            "image": png_base64(64, 64)?,
+            "prompt": "blue shirt",
+            "strength": 0.5,
+            "model": "test/model",
+        }))
+        .send()
+        .await?;
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["content-type"], "image/png");
+
+    let bytes = response.bytes().await?;
+    let output = image::load_from_memory_with_format(&bytes, ImageFormat::Png)?;
+    assert_eq!((output.width(), output.height()), (64, 64));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_generate_rejects_bad_input() -> Result<()> {
+    let temp_dir = tempdir()?;
+    let url = start_server(temp_dir.path()).await?;
+    let client = reqwest::Client::new();
+
+    let tiny = client
+        .post(format!("{url}/generate"))
+        .json(&json!({
+            "image": png_base64(8, 8)?,
+            "prompt": "blue shirt",
+            "model": "test/model",
+        }))
+        .send()
+        .await?;
+    assert_eq!(tiny.status(), 422);
+    let body: Value = tiny.json().await?;
+    assert!(body["error"].as_str().unwrap_or_default().contains("8x8"));
+
+    let garbled = client
+        .post(format!("{url}/generate"))
+        .json(&json!({ "image": "not base64!", "prompt": "blue shirt" }))
+        .send()
+        .await?;
+    assert_eq!(garbled.status(), 400);
+
+    let too_strong = client
+        .post(format!("{url}/generate"))
+        .json(&json!({
+            "image": png_base64(64, 64)?,
+            "prompt": "blue shirt",
+            "strength": 1.5,
+            "model": "test/model",
+        }))
+        .send()
+        .await?;
+    assert_eq!(too_strong.status(), 400);
+    let body: Value = too_strong.json().await?;
+    assert!(body["error"].as_str().unwrap_or_default().contains("1.5"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_generate_with_a_model_not_downloaded_is_a_conflict() -> Result<()> {
+    let temp_dir = tempdir()?;
+    let url = start_server(temp_dir.path()).await?;
+    let client = reqwest::Client::new();
+    let generate = |model: &str| {
+        client.post(format!("{url}/generate")).json(&json!({
+            "image": png_base64(64, 64).unwrap(),
+            "prompt": "blue shirt",
+            "model": model,
+        }))
+    };
+
+    let missing = generate(TEST_MODEL_ID).send().await?;
+    assert_eq!(missing.status(), 409);
+    let body: Value = missing.json().await?;
+    let error = body["error"].as_str().unwrap_or_default();
+    assert!(
+        error.contains(&format!("si model download {TEST_MODEL_ID}")),
+        "{error}"
+    );
+    // Nothing was downloaded, and other requests go on as before
+    assert!(!temp_dir.path().join("cache").exists());
+    assert_eq!(generate("test/model").send().await?.status(), 200);
+
+    // Local model paths are for the CLI only
+    let path = generate("./my-finetune").send().await?;
+    assert_eq!(path.status(), 400);
+    let body: Value = path.json().await?;
+    let error = body["error"].as_str().unwrap_or_default();
+    assert!(error.contains("is a path"), "{error}");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_download_job_is_pollable() -> Result<()> {
+    let temp_dir = tempdir()?;
+    let url = start_server(temp_dir.path()).await?;
+    let client = reqwest::Client::new();
+
+    let response = client
+        .post(format!("{url}/models/{TEST_MODEL_ID}/download"))
+        .send()
+        .await?;
+    assert_eq!(response.status(), 202);
+    let job: Value = response.json().await?;
+    assert_eq!(job["model_id"], TEST_MODEL_ID);
+    let id = job["id"].as_u64().expect("job id");
+
+    let mut status = Value::Null;
+    for _ in 0..100 {
+        let job: Value = client
+            .get(format!("{url}/jobs/{id}"))
+            .send()
+            .await?
+            .json()
+            .await?;
+        status = job["status"].clone();
+        if status != "queued" && status != "running" {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(status, "done");
+
+    let models: Value = client
+        .get(format!("{url}/models"))
+        .send()
+        .await?
+        .json()
+        .await?;
+    let ids: Vec<&str> = models
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|m| m["model_id"].as_str())
+        .collect();
+    assert!(ids.contains(&TEST_MODEL_ID));
+
+    let missing = client.get(format!("{url}/jobs/9999")).send().await?;
+    assert_eq!(missing.status(), 404);
+    Ok(())
+}